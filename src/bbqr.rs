@@ -0,0 +1,295 @@
+//! Encoding of data as a sequence of QR codes, following the BBQr specification
+//! (https://github.com/coinkite/BBQr/blob/master/BBQr.md).
+//!
+//! This lets airgapped signing devices with a camera exchange PSBTs too large to fit in a single
+//! QR code. Each part starts with an 8 characters header: `B$`, the encoding, the type of the
+//! data, the total number of parts and the index of this part (both as 2 base36 digits). We
+//! produce Base32 encoded parts and accept both the Base32 and hex encodings. The zlib compressed
+//! encoding isn't supported.
+
+use std::{collections::BTreeMap, error, fmt};
+
+/// The type of data for a PSBT.
+pub const FILE_TYPE_PSBT: char = 'P';
+
+const HEADER_LEN: usize = 8;
+const MAX_PARTS: usize = 36 * 36 - 1;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BbqrError {
+    /// Parts can't be this short.
+    PartTooShort(usize),
+    /// The data would need more parts than can be numbered.
+    TooManyParts(usize),
+    /// No part was given.
+    Empty,
+    /// This part doesn't start with a valid header.
+    InvalidHeader(String),
+    /// This encoding isn't supported.
+    UnsupportedEncoding(char),
+    /// The parts don't all have the same encoding, data type or total number of parts.
+    InconsistentParts,
+    /// Two different parts were given for this index.
+    ConflictingParts(usize),
+    /// The parts at these indexes are missing.
+    MissingParts(Vec<usize>),
+    /// The data isn't correctly encoded.
+    InvalidData,
+}
+
+impl fmt::Display for BbqrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::PartTooShort(len) => write!(
+                f,
+                "Parts must be at least {} characters long, not {}.",
+                HEADER_LEN + 8,
+                len
+            ),
+            Self::TooManyParts(n) => write!(
+                f,
+                "The data would need {} parts, more than the maximum of {}.",
+                n, MAX_PARTS
+            ),
+            Self::Empty => write!(f, "No part was given."),
+            Self::InvalidHeader(part) => write!(f, "Invalid header for part '{}'.", part),
+            Self::UnsupportedEncoding(enc) => write!(f, "Unsupported encoding '{}'.", enc),
+            Self::InconsistentParts => write!(
+                f,
+                "Parts have different encodings, data types or numbers of parts."
+            ),
+            Self::ConflictingParts(i) => write!(f, "Two different parts have index {}.", i),
+            Self::MissingParts(indexes) => write!(f, "Missing parts at indexes {:?}.", indexes),
+            Self::InvalidData => write!(f, "Invalid data encoding."),
+        }
+    }
+}
+
+impl error::Error for BbqrError {}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut res = String::with_capacity(data.len() * 8 / 5 + 1);
+    let (mut buf, mut bits) = (0u16, 0);
+    for byte in data {
+        buf = (buf << 8) | u16::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            res.push(BASE32_ALPHABET[usize::from((buf >> bits) & 0x1f)].into());
+        }
+    }
+    if bits > 0 {
+        res.push(BASE32_ALPHABET[usize::from((buf << (5 - bits)) & 0x1f)].into());
+    }
+    res
+}
+
+fn base32_decode(data: &str) -> Option<Vec<u8>> {
+    let mut res = Vec::with_capacity(data.len() * 5 / 8);
+    let (mut buf, mut bits) = (0u16, 0);
+    for c in data.bytes() {
+        let value = BASE32_ALPHABET.iter().position(|a| *a == c)? as u16;
+        buf = ((buf << 5) | value) & 0xfff;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            res.push((buf >> bits) as u8);
+        }
+    }
+    Some(res)
+}
+
+fn hex_decode(data: &str) -> Option<Vec<u8>> {
+    data.as_bytes()
+        .chunks(2)
+        .map(|c| {
+            let digits = std::str::from_utf8(c).ok().filter(|d| d.len() == 2)?;
+            u8::from_str_radix(digits, 16).ok()
+        })
+        .collect()
+}
+
+fn base36(n: usize) -> String {
+    let digit = |d: usize| std::char::from_digit(d as u32, 36).unwrap();
+    [digit(n / 36), digit(n % 36)]
+        .iter()
+        .collect::<String>()
+        .to_uppercase()
+}
+
+/// Encode this data of the given type as a sequence of parts of at most `max_part_len`
+/// characters, header included. All parts but the last have the same length.
+pub fn encode(data: &[u8], file_type: char, max_part_len: usize) -> Result<Vec<String>, BbqrError> {
+    // A part must contain a whole number of 8 characters groups (5 bytes) to be decoded on its own.
+    let chars_per_part = max_part_len.saturating_sub(HEADER_LEN) / 8 * 8;
+    if chars_per_part == 0 {
+        return Err(BbqrError::PartTooShort(max_part_len));
+    }
+    let encoded = base32_encode(data);
+    // Always produce at least one part, even if there is no data.
+    let chunks: Vec<&[u8]> = if encoded.is_empty() {
+        vec![&[]]
+    } else {
+        encoded.as_bytes().chunks(chars_per_part).collect()
+    };
+    if chunks.len() > MAX_PARTS {
+        return Err(BbqrError::TooManyParts(chunks.len()));
+    }
+    Ok(chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            format!(
+                "B$2{}{}{}{}",
+                file_type,
+                base36(chunks.len()),
+                base36(i),
+                std::str::from_utf8(chunk).expect("Base32 is ASCII")
+            )
+        })
+        .collect())
+}
+
+/// Reassemble the data from a sequence of parts, which may be given in any order and more than
+/// once. Returns the type of the data along with it.
+pub fn decode<S: AsRef<str>>(parts: &[S]) -> Result<(char, Vec<u8>), BbqrError> {
+    let mut header = None;
+    let mut contents: BTreeMap<usize, &str> = BTreeMap::new();
+    for part in parts {
+        let part = part.as_ref().trim();
+        let invalid_header = || BbqrError::InvalidHeader(part.to_string());
+        let head = match part.get(..HEADER_LEN) {
+            Some(head) if head.is_ascii() && head.starts_with("B$") => head.as_bytes(),
+            _ => return Err(invalid_header()),
+        };
+        let (encoding, file_type) = (char::from(head[2]), char::from(head[3]));
+        let count = usize::from_str_radix(&part[4..6], 36).map_err(|_| invalid_header())?;
+        let index = usize::from_str_radix(&part[6..8], 36).map_err(|_| invalid_header())?;
+        if index >= count {
+            return Err(invalid_header());
+        }
+        if encoding != '2' && encoding != 'H' {
+            return Err(BbqrError::UnsupportedEncoding(encoding));
+        }
+        if *header.get_or_insert((encoding, file_type, count)) != (encoding, file_type, count) {
+            return Err(BbqrError::InconsistentParts);
+        }
+        let content = &part[HEADER_LEN..];
+        if let Some(prev) = contents.insert(index, content) {
+            if prev != content {
+                return Err(BbqrError::ConflictingParts(index));
+            }
+        }
+    }
+
+    let (encoding, file_type, count) = header.ok_or(BbqrError::Empty)?;
+    let missing: Vec<usize> = (0..count).filter(|i| !contents.contains_key(i)).collect();
+    if !missing.is_empty() {
+        return Err(BbqrError::MissingParts(missing));
+    }
+    let encoded: String = contents.values().copied().collect();
+    let data = if encoding == '2' {
+        base32_decode(&encoded)
+    } else {
+        hex_decode(&encoded)
+    };
+    data.map(|data| (file_type, data))
+        .ok_or(BbqrError::InvalidData)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bbqr_roundtrip() {
+        // Test vectors from RFC 4648.
+        for (data, encoded) in [
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ]
+        .iter()
+        {
+            assert_eq!(base32_encode(data.as_bytes()), *encoded);
+            assert_eq!(base32_decode(encoded).unwrap(), data.as_bytes());
+        }
+
+        // The data is split into parts of the requested size.
+        let data: Vec<u8> = (0..=255).collect();
+        let parts = encode(&data, FILE_TYPE_PSBT, 100).unwrap();
+        assert_eq!(parts.len(), 5);
+        assert!(parts.iter().all(|p| p.len() <= 100));
+        assert!(parts[..4].iter().all(|p| p.len() == 96));
+        assert!(parts[0].starts_with("B$2P0500"));
+        assert!(parts[4].starts_with("B$2P0504"));
+        assert_eq!(decode(&parts).unwrap(), (FILE_TYPE_PSBT, data.clone()));
+        let parts = encode(&data, FILE_TYPE_PSBT, 1_000).unwrap();
+        assert_eq!(parts.len(), 1);
+        assert!(parts[0].starts_with("B$2P0100"));
+        assert_eq!(decode(&parts).unwrap(), (FILE_TYPE_PSBT, data.clone()));
+        assert_eq!(
+            encode(&data, FILE_TYPE_PSBT, 15),
+            Err(BbqrError::PartTooShort(15))
+        );
+        assert_eq!(
+            encode(&[0; 10_000], FILE_TYPE_PSBT, 16),
+            Err(BbqrError::TooManyParts(2_000))
+        );
+
+        // Parts may be scanned in any order, and more than once.
+        let parts = encode(&data, FILE_TYPE_PSBT, 100).unwrap();
+        let scanned = vec![
+            parts[3].clone(),
+            parts[1].clone(),
+            parts[3].clone(),
+            parts[0].clone(),
+            parts[4].clone(),
+            parts[2].clone(),
+            parts[0].clone(),
+        ];
+        assert_eq!(decode(&scanned).unwrap(), (FILE_TYPE_PSBT, data.clone()));
+
+        // But all of them are needed.
+        assert_eq!(
+            decode(&parts[1..4]),
+            Err(BbqrError::MissingParts(vec![0, 4]))
+        );
+        assert_eq!(decode::<String>(&[]), Err(BbqrError::Empty));
+
+        // The hex encoding is also supported.
+        let hex_parts = ["B$HP0200deadbe", "B$HP0201ef"];
+        assert_eq!(
+            decode(&hex_parts).unwrap(),
+            (FILE_TYPE_PSBT, vec![0xde, 0xad, 0xbe, 0xef])
+        );
+        assert_eq!(
+            decode(&["B$ZP0100abcdef"]),
+            Err(BbqrError::UnsupportedEncoding('Z'))
+        );
+
+        // Invalid or inconsistent parts are rejected.
+        assert_eq!(
+            decode(&["B$2P01"]),
+            Err(BbqrError::InvalidHeader("B$2P01".to_string()))
+        );
+        assert_eq!(
+            decode(&["B$2P0101MZXW6YTB"]),
+            Err(BbqrError::InvalidHeader("B$2P0101MZXW6YTB".to_string()))
+        );
+        assert_eq!(
+            decode(&["B$2P0200MZXW6YTB", "B$2T0201MZXW6YTB"]),
+            Err(BbqrError::InconsistentParts)
+        );
+        assert_eq!(
+            decode(&["B$2P0200MZXW6YTB", "B$2P0200MZXW6YTC"]),
+            Err(BbqrError::ConflictingParts(0))
+        );
+        assert_eq!(decode(&["B$2P0100MZ1W"]), Err(BbqrError::InvalidData));
+    }
+}
@@ -1,7 +1,7 @@
 use std::{
     env,
     io::{self, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     process, thread, time,
 };
 
@@ -41,7 +41,10 @@ fn parse_args(args: Vec<String>) -> Option<PathBuf> {
     Some(PathBuf::from(args[2].to_owned()))
 }
 
-fn setup_logger(log_level: log::LevelFilter) -> Result<(), fern::InitError> {
+fn setup_logger(
+    log_level: log::LevelFilter,
+    log_file: Option<&Path>,
+) -> Result<(), fern::InitError> {
     let dispatcher = fern::Dispatch::new()
         .format(|out, message, record| {
             out.finish(format_args!(
@@ -59,9 +62,15 @@ fn setup_logger(log_level: log::LevelFilter) -> Result<(), fern::InitError> {
                 message
             ))
         })
-        .level(log_level);
+        // Don't filter at the dispatcher level. The level is enforced through the global maximum
+        // level instead, as it can be changed at runtime.
+        .level(log::LevelFilter::Trace);
 
-    dispatcher.chain(std::io::stdout()).apply()?;
+    match log_file {
+        Some(path) => dispatcher.chain(fern::log_file(path)?).apply()?,
+        None => dispatcher.chain(std::io::stdout()).apply()?,
+    }
+    log::set_max_level(log_level);
 
     Ok(())
 }
@@ -75,7 +84,7 @@ fn main() {
         print_help_exit(1);
         unreachable!();
     });
-    setup_logger(config.log_level).unwrap_or_else(|e| {
+    setup_logger(config.log_level, config.log_file.as_deref()).unwrap_or_else(|e| {
         eprintln!("Error setting up logger: {}", e);
         process::exit(1);
     });
@@ -219,6 +219,8 @@ pub struct BitcoinD {
     watchonly_wallet_path: String,
     /// How many times we'll retry upon failure to send a request.
     retries: usize,
+    /// The configuration used to connect, kept to be able to reconnect.
+    config: config::BitcoindConfig,
 }
 
 macro_rules! params {
@@ -284,6 +286,7 @@ impl BitcoinD {
             watchonly_client: dummy_wo_client,
             watchonly_wallet_path: watchonly_wallet_path.clone(),
             retries: 0,
+            config: config.clone(),
         };
         log::info!("Checking the connection to bitcoind.");
         dummy_bitcoind.check_connection()?;
@@ -319,9 +322,20 @@ impl BitcoinD {
             watchonly_client,
             watchonly_wallet_path,
             retries: BITCOIND_RETRY_LIMIT,
+            config: config.clone(),
         })
     }
 
+    /// Tear down the connection to bitcoind and establish a new one, for instance after bitcoind
+    /// was restarted. This re-reads the cookie file and makes sure our watchonly wallet is loaded.
+    /// The existing connection is kept if a new one can't be established.
+    pub fn reconnect(&mut self) -> Result<(), BitcoindError> {
+        let bitcoind = BitcoinD::new(&self.config, self.watchonly_wallet_path.clone())?;
+        bitcoind.maybe_load_watchonly_wallet()?;
+        *self = bitcoind;
+        Ok(())
+    }
+
     fn check_client(&self, client: &Client) -> Result<(), BitcoindError> {
         if let Err(e) = self.make_request(client, "echo", None) {
             if e.is_warming_up() {
@@ -480,7 +494,16 @@ impl BitcoinD {
             .expect("Missing or invalid 'version' in 'getnetworkinfo' result?")
     }
 
-    fn get_network_bip70(&self) -> String {
+    /// Get the user agent of the bitcoind we are connected to, such as "/Satoshi:26.0.0/".
+    pub fn get_bitcoind_subversion(&self) -> String {
+        self.make_node_request("getnetworkinfo", None)
+            .get("subversion")
+            .and_then(Json::as_str)
+            .expect("Missing or invalid 'subversion' in 'getnetworkinfo' result?")
+            .to_string()
+    }
+
+    pub fn get_network_bip70(&self) -> String {
         self.make_node_request("getblockchaininfo", None)
             .get("chain")
             .and_then(Json::as_str)
@@ -1054,6 +1077,24 @@ impl BitcoinD {
         Ok(())
     }
 
+    /// Check whether this transaction would be accepted to the mempool, without submitting it.
+    pub fn test_mempool_accept(
+        &self,
+        tx: &bitcoin::Transaction,
+    ) -> Result<MempoolAcceptResult, BitcoindError> {
+        let res = self.make_fallible_node_request(
+            "testmempoolaccept",
+            params!(Json::Array(vec![Json::String(
+                bitcoin::consensus::encode::serialize_hex(tx)
+            )])),
+        )?;
+        let entry = res
+            .as_array()
+            .and_then(|entries| entries.first())
+            .expect("One result per transaction tested");
+        Ok(MempoolAcceptResult::from(entry))
+    }
+
     // For the given descriptor strings check if they are imported at this timestamp in the
     // watchonly wallet.
     fn check_descs_timestamp(&self, descs: &[String], timestamp: u32) -> bool {
@@ -1165,6 +1206,29 @@ impl BitcoinD {
         }
     }
 
+    /// Rescan the block chain between these two heights (inclusive) for transactions involving
+    /// the descriptors already imported in the watchonly wallet.
+    pub fn rescan_range(&self, start_height: i32, end_height: i32) -> Result<(), BitcoindError> {
+        // Have we pruned the blocks necessary to rescan from this height?
+        if let Some(prune_height) = self.block_chain_info().get("pruneheight") {
+            let prune_height = prune_height.as_i64().expect("Height must be an integer");
+            if i64::from(start_height) < prune_height {
+                return Err(BitcoindError::RescanPastPruneHeight);
+            }
+        }
+
+        // As for the descriptors import in `start_rescan`, we don't wait for the rescan to
+        // complete. The progress reported by bitcoind for this rescan is relative to the scanned
+        // range, not to the whole chain.
+        self.make_noreply_request(
+            "rescanblockchain",
+            params!(
+                Json::Number(start_height.into()),
+                Json::Number(end_height.into()),
+            ),
+        )
+    }
+
     /// Get the progress of the ongoing rescan, if there is any.
     pub fn rescan_progress(&self) -> Option<f64> {
         self.make_wallet_request("getwalletinfo", None)
@@ -1230,6 +1294,19 @@ impl BitcoinD {
             .collect()
     }
 
+    /// Get bitcoind's feerate estimate, in sats/vb, for confirmation within this number of blocks.
+    ///
+    /// Returns `None` if bitcoind does not have enough data to provide an estimate.
+    pub fn estimate_feerate(&self, conf_target: u16) -> Option<u64> {
+        let res = self.make_node_request(
+            "estimatesmartfee",
+            params!(Json::Number(conf_target.into())),
+        );
+        // The feerate is in BTC/kvb. Round up the conversion to sats/vb.
+        let btc_per_kvb = res.get("feerate").and_then(Json::as_f64)?;
+        Some((btc_per_kvb * 100_000.0).ceil() as u64)
+    }
+
     /// Stop bitcoind.
     pub fn stop(&self) {
         self.make_node_request("stop", None);
@@ -1495,6 +1572,8 @@ impl<'a> CachedTxGetter<'a> {
 pub struct MempoolEntry {
     pub vsize: u64,
     pub ancestor_vsize: u64,
+    pub ancestor_count: u64,
+    pub descendant_count: u64,
     pub fees: MempoolEntryFees,
 }
 
@@ -1508,6 +1587,14 @@ impl From<Json> for MempoolEntry {
             .get("ancestorsize")
             .and_then(Json::as_u64)
             .expect("Must be present in bitcoind response");
+        let ancestor_count = json
+            .get("ancestorcount")
+            .and_then(Json::as_u64)
+            .expect("Must be present in bitcoind response");
+        let descendant_count = json
+            .get("descendantcount")
+            .and_then(Json::as_u64)
+            .expect("Must be present in bitcoind response");
         let fees = json
             .get("fees")
             .as_ref()
@@ -1517,11 +1604,53 @@ impl From<Json> for MempoolEntry {
         MempoolEntry {
             vsize,
             ancestor_vsize,
+            ancestor_count,
+            descendant_count,
             fees,
         }
     }
 }
 
+/// The result of testing whether a transaction would be accepted to the mempool.
+#[derive(Debug, Clone)]
+pub struct MempoolAcceptResult {
+    pub allowed: bool,
+    /// Why the transaction would be rejected, if it would.
+    pub reject_reason: Option<String>,
+    /// The virtual size of the transaction, if it would be accepted.
+    pub vsize: Option<u64>,
+    /// The fee of the transaction, if it would be accepted.
+    pub fee: Option<bitcoin::Amount>,
+}
+
+impl From<&Json> for MempoolAcceptResult {
+    fn from(json: &Json) -> MempoolAcceptResult {
+        let allowed = json
+            .get("allowed")
+            .and_then(Json::as_bool)
+            .expect("Must be present in bitcoind response");
+        // Some rejections (for instance a transaction already in the mempool) are only reported
+        // through the package error.
+        let reject_reason = json
+            .get("reject-reason")
+            .or_else(|| json.get("package-error"))
+            .and_then(Json::as_str)
+            .map(|s| s.to_string());
+        let vsize = json.get("vsize").and_then(Json::as_u64);
+        let fee = json
+            .get("fees")
+            .and_then(|fees| fees.get("base"))
+            .and_then(Json::as_f64)
+            .and_then(|a| bitcoin::Amount::from_btc(a).ok());
+        MempoolAcceptResult {
+            allowed,
+            reject_reason,
+            vsize,
+            fee,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MempoolEntryFees {
     pub base: bitcoin::Amount,
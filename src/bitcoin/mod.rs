@@ -9,11 +9,12 @@ use crate::{
     bitcoin::d::{BitcoindError, CachedTxGetter, LSBlockEntry},
     descriptors,
 };
-pub use d::{MempoolEntry, SyncProgress};
+pub use d::{MempoolAcceptResult, MempoolEntry, SyncProgress};
 
 use std::{fmt, sync};
 
 use miniscript::bitcoin::{self, address};
+use serde::{Deserialize, Serialize};
 
 const COINBASE_MATURITY: i32 = 100;
 
@@ -38,6 +39,25 @@ impl fmt::Display for BlockChainTip {
     }
 }
 
+/// The kind of Bitcoin backend we are connected to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    Bitcoind,
+}
+
+/// Information about the Bitcoin backend we are connected to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackendInfo {
+    pub kind: BackendKind,
+    /// The version of the backend, as reported by itself.
+    pub version: String,
+    /// The network the backend is running on.
+    pub network: bitcoin::Network,
+    /// The hash of the backend's best block.
+    pub chain_tip_hash: bitcoin::BlockHash,
+}
+
 /// Our Bitcoin backend.
 pub trait BitcoinInterface: Send {
     fn genesis_block_timestamp(&self) -> u32;
@@ -58,6 +78,12 @@ pub trait BitcoinInterface: Send {
     /// Check whether this former tip is part of the current best chain.
     fn is_in_chain(&self, tip: &BlockChainTip) -> bool;
 
+    /// Get the block with this hash, if it is part of the best chain.
+    fn block_in_chain(&self, hash: &bitcoin::BlockHash) -> Option<Block>;
+
+    /// Get the block at this height in the best chain, if there is one.
+    fn block_at_height(&self, height: i32) -> Option<Block>;
+
     /// Get coins received since the specified tip.
     fn received_coins(
         &self,
@@ -89,12 +115,20 @@ pub trait BitcoinInterface: Send {
         Vec<bitcoin::OutPoint>,
     );
 
+    /// Get the coins, among those given, whose unconfirmed deposit transaction conflicts with a
+    /// confirmed transaction. That is, the coins which were double spent before being confirmed.
+    fn double_spent_coins(&self, outpoints: &[bitcoin::OutPoint]) -> Vec<bitcoin::OutPoint>;
+
     /// Get the common ancestor between the Bitcoin backend's tip and the given tip.
     fn common_ancestor(&self, tip: &BlockChainTip) -> Option<BlockChainTip>;
 
     /// Broadcast this transaction to the Bitcoin P2P network
     fn broadcast_tx(&self, tx: &bitcoin::Transaction) -> Result<(), String>;
 
+    /// Check whether this transaction would be accepted to the mempool, without broadcasting it.
+    fn test_mempool_accept(&self, tx: &bitcoin::Transaction)
+        -> Result<MempoolAcceptResult, String>;
+
     /// Trigger a rescan of the block chain for transactions related to this descriptor since
     /// the given date.
     fn start_rescan(
@@ -103,6 +137,10 @@ pub trait BitcoinInterface: Send {
         timestamp: u32,
     ) -> Result<(), String>;
 
+    /// Trigger a rescan of the block chain between these two heights (inclusive) for transactions
+    /// related to our descriptors.
+    fn rescan_range(&self, start_height: i32, end_height: i32) -> Result<(), String>;
+
     /// Rescan progress percentage. Between 0 and 1.
     fn rescan_progress(&self) -> Option<f64>;
 
@@ -123,6 +161,18 @@ pub trait BitcoinInterface: Send {
     ///
     /// Returns `None` if the transaction is not in the mempool.
     fn mempool_entry(&self, txid: &bitcoin::Txid) -> Option<MempoolEntry>;
+
+    /// Get information about the backend itself.
+    fn backend_info(&self) -> BackendInfo;
+
+    /// Get an estimate of the feerate, in sats/vb, for a transaction to be confirmed within
+    /// this number of blocks.
+    ///
+    /// Returns `None` if the backend could not provide an estimate.
+    fn feerate_estimate(&self, conf_target: u16) -> Option<u64>;
+
+    /// Tear down the connection to the backend and establish a new one.
+    fn reconnect(&mut self) -> Result<(), String>;
 }
 
 impl BitcoinInterface for d::BitcoinD {
@@ -157,6 +207,22 @@ impl BitcoinInterface for d::BitcoinD {
             .unwrap_or(false)
     }
 
+    fn block_in_chain(&self, hash: &bitcoin::BlockHash) -> Option<Block> {
+        // A block which isn't part of the best chain has -1 confirmations.
+        self.get_block_stats(*hash)
+            .filter(|stats| stats.confirmations > 0)
+            .map(|stats| Block {
+                hash: stats.blockhash,
+                height: stats.height,
+                time: stats.time,
+            })
+    }
+
+    fn block_at_height(&self, height: i32) -> Option<Block> {
+        let hash = self.get_block_hash(height)?;
+        self.block_in_chain(&hash)
+    }
+
     fn received_coins(
         &self,
         tip: &BlockChainTip,
@@ -319,6 +385,29 @@ impl BitcoinInterface for d::BitcoinD {
         (spent, expired)
     }
 
+    fn double_spent_coins(&self, outpoints: &[bitcoin::OutPoint]) -> Vec<bitcoin::OutPoint> {
+        let mut double_spent = Vec::new();
+        // Cached calls to `gettransaction`.
+        let mut tx_getter = CachedTxGetter::new(self);
+
+        for op in outpoints {
+            let res = if let Some(res) = tx_getter.get_transaction(&op.txid) {
+                res
+            } else {
+                log::error!("Transaction not in wallet for coin '{}'.", op);
+                continue;
+            };
+
+            // The wallet reports a negative number of confirmations for a transaction conflicting
+            // with a transaction in the block chain: it's the depth of the conflicting one.
+            if res.block.is_none() && res.confirmations < 0 {
+                double_spent.push(*op);
+            }
+        }
+
+        double_spent
+    }
+
     fn common_ancestor(&self, tip: &BlockChainTip) -> Option<BlockChainTip> {
         let mut stats = self.get_block_stats(tip.hash)?;
         let mut ancestor = *tip;
@@ -346,6 +435,21 @@ impl BitcoinInterface for d::BitcoinD {
         }
     }
 
+    fn test_mempool_accept(
+        &self,
+        tx: &bitcoin::Transaction,
+    ) -> Result<MempoolAcceptResult, String> {
+        match self.test_mempool_accept(tx) {
+            Ok(res) => Ok(res),
+            Err(BitcoindError::Server(e)) => Err(e.to_string()),
+            // We assume the Bitcoin backend doesn't fail, so it must be a JSONRPC error.
+            Err(e) => panic!(
+                "Unexpected Bitcoin error when testing transaction acceptance: '{}'.",
+                e
+            ),
+        }
+    }
+
     fn start_rescan(
         &self,
         desc: &descriptors::LianaDescriptor,
@@ -356,6 +460,11 @@ impl BitcoinInterface for d::BitcoinD {
             .map_err(|e| e.to_string())
     }
 
+    fn rescan_range(&self, start_height: i32, end_height: i32) -> Result<(), String> {
+        self.rescan_range(start_height, end_height)
+            .map_err(|e| e.to_string())
+    }
+
     fn rescan_progress(&self) -> Option<f64> {
         self.rescan_progress()
     }
@@ -386,6 +495,26 @@ impl BitcoinInterface for d::BitcoinD {
     fn mempool_entry(&self, txid: &bitcoin::Txid) -> Option<MempoolEntry> {
         self.mempool_entry(txid)
     }
+
+    fn backend_info(&self) -> BackendInfo {
+        // The node sanity checks at startup make sure bitcoind is running on a network we know.
+        let network =
+            bitcoin::Network::from_core_arg(&self.get_network_bip70()).expect("Checked at startup");
+        BackendInfo {
+            kind: BackendKind::Bitcoind,
+            version: self.get_bitcoind_subversion(),
+            network,
+            chain_tip_hash: self.chain_tip().hash,
+        }
+    }
+
+    fn feerate_estimate(&self, conf_target: u16) -> Option<u64> {
+        self.estimate_feerate(conf_target)
+    }
+
+    fn reconnect(&mut self) -> Result<(), String> {
+        self.reconnect().map_err(|e| e.to_string())
+    }
 }
 
 // FIXME: do we need to repeat the entire trait implemenation? Isn't there a nicer way?
@@ -410,6 +539,14 @@ impl BitcoinInterface for sync::Arc<sync::Mutex<dyn BitcoinInterface + 'static>>
         self.lock().unwrap().is_in_chain(tip)
     }
 
+    fn block_in_chain(&self, hash: &bitcoin::BlockHash) -> Option<Block> {
+        self.lock().unwrap().block_in_chain(hash)
+    }
+
+    fn block_at_height(&self, height: i32) -> Option<Block> {
+        self.lock().unwrap().block_at_height(height)
+    }
+
     fn received_coins(
         &self,
         tip: &BlockChainTip,
@@ -442,6 +579,10 @@ impl BitcoinInterface for sync::Arc<sync::Mutex<dyn BitcoinInterface + 'static>>
         self.lock().unwrap().spent_coins(outpoints)
     }
 
+    fn double_spent_coins(&self, outpoints: &[bitcoin::OutPoint]) -> Vec<bitcoin::OutPoint> {
+        self.lock().unwrap().double_spent_coins(outpoints)
+    }
+
     fn common_ancestor(&self, tip: &BlockChainTip) -> Option<BlockChainTip> {
         self.lock().unwrap().common_ancestor(tip)
     }
@@ -450,6 +591,13 @@ impl BitcoinInterface for sync::Arc<sync::Mutex<dyn BitcoinInterface + 'static>>
         self.lock().unwrap().broadcast_tx(tx)
     }
 
+    fn test_mempool_accept(
+        &self,
+        tx: &bitcoin::Transaction,
+    ) -> Result<MempoolAcceptResult, String> {
+        self.lock().unwrap().test_mempool_accept(tx)
+    }
+
     fn start_rescan(
         &self,
         desc: &descriptors::LianaDescriptor,
@@ -458,6 +606,10 @@ impl BitcoinInterface for sync::Arc<sync::Mutex<dyn BitcoinInterface + 'static>>
         self.lock().unwrap().start_rescan(desc, timestamp)
     }
 
+    fn rescan_range(&self, start_height: i32, end_height: i32) -> Result<(), String> {
+        self.lock().unwrap().rescan_range(start_height, end_height)
+    }
+
     fn rescan_progress(&self) -> Option<f64> {
         self.lock().unwrap().rescan_progress()
     }
@@ -484,6 +636,18 @@ impl BitcoinInterface for sync::Arc<sync::Mutex<dyn BitcoinInterface + 'static>>
     fn mempool_entry(&self, txid: &bitcoin::Txid) -> Option<MempoolEntry> {
         self.lock().unwrap().mempool_entry(txid)
     }
+
+    fn backend_info(&self) -> BackendInfo {
+        self.lock().unwrap().backend_info()
+    }
+
+    fn feerate_estimate(&self, conf_target: u16) -> Option<u64> {
+        self.lock().unwrap().feerate_estimate(conf_target)
+    }
+
+    fn reconnect(&mut self) -> Result<(), String> {
+        self.lock().unwrap().reconnect()
+    }
 }
 
 // FIXME: We could avoid this type (and all the conversions entailing allocations) if bitcoind
@@ -1,18 +1,32 @@
 use crate::{
     bitcoin::{BitcoinInterface, BlockChainTip, UTxO},
-    database::{Coin, DatabaseConnection, DatabaseInterface},
+    config::SpentCoinRetention,
+    database::{Coin, DatabaseConnection, DatabaseInterface, FeerateSample},
     descriptors,
 };
 
-use std::{collections::HashSet, sync, time};
+use std::{collections::HashSet, convert::TryFrom, sync, time};
 
 use miniscript::bitcoin::{self, secp256k1};
 
+// The confirmation targets, in blocks, for which to sample the feerate estimates of the backend.
+const FEERATE_SAMPLE_TARGETS: [u16; 3] = [2, 6, 24];
+
+// How often to sample the feerate estimates of the backend, in seconds.
+const FEERATE_SAMPLE_INTERVAL_SECS: u32 = 600;
+
+// For how many blocks to check whether the deposit transaction of a dropped coin was double spent,
+// about two weeks as for the default mempool expiry.
+const DROPPED_COIN_WATCH_BLOCKS: i32 = 2016;
+
 #[derive(Debug, Clone)]
 struct UpdatedCoins {
     pub received: Vec<Coin>,
     pub confirmed: Vec<(bitcoin::OutPoint, i32, u32)>,
     pub expired: Vec<bitcoin::OutPoint>,
+    pub dropped: Vec<Coin>,
+    pub forgotten_dropped: Vec<bitcoin::OutPoint>,
+    pub double_spent: Vec<bitcoin::OutPoint>,
     pub spending: Vec<(bitcoin::OutPoint, bitcoin::Txid)>,
     pub expired_spending: Vec<bitcoin::OutPoint>,
     pub spent: Vec<(bitcoin::OutPoint, bitcoin::Txid, i32, u32)>,
@@ -106,6 +120,48 @@ fn update_coins(
     log::debug!("Newly confirmed coins: {:?}", confirmed);
     log::debug!("Expired coins: {:?}", expired);
 
+    // Keep track of the coins whose deposit transaction was dropped from the mempool, as it may
+    // be because it was double spent. Check whether the deposit transaction of any of those, or
+    // of the ones dropped in a previous poll which didn't come back, now conflicts with a
+    // confirmed transaction. Stop watching those which came back or were dropped too long ago.
+    let dropped: Vec<Coin> = expired
+        .iter()
+        .filter_map(|op| {
+            curr_coins
+                .get(op)
+                .or_else(|| received.iter().find(|c| &c.outpoint == op))
+                .copied()
+        })
+        .collect();
+    let tip_height = db_conn
+        .chain_tip()
+        .expect("Always set at first startup")
+        .height;
+    let (forgotten_dropped, watched_dropped): (Vec<_>, Vec<_>) = db_conn
+        .dropped_coins()
+        .into_iter()
+        .filter(|c| c.invalidated_at.is_none())
+        .partition(|c| {
+            let is_back = curr_coins.contains_key(&c.outpoint)
+                || received.iter().any(|r| r.outpoint == c.outpoint);
+            is_back || tip_height - c.dropped_height >= DROPPED_COIN_WATCH_BLOCKS
+        });
+    let forgotten_dropped: Vec<bitcoin::OutPoint> =
+        forgotten_dropped.into_iter().map(|c| c.outpoint).collect();
+    log::debug!("Dropped coins not watched anymore: {:?}", forgotten_dropped);
+    let to_be_invalidated: Vec<bitcoin::OutPoint> = watched_dropped
+        .into_iter()
+        .map(|c| c.outpoint)
+        .chain(expired.iter().copied())
+        .collect();
+    let double_spent = bit.double_spent_coins(&to_be_invalidated);
+    if !double_spent.is_empty() {
+        log::warn!(
+            "The deposit transaction of coins {:?} was double spent.",
+            double_spent
+        );
+    }
+
     // We need to take the newly received ones into account as well, as they may have been
     // spent within the previous tip and the current one, and we may not poll this chunk of the
     // chain anymore.
@@ -150,6 +206,9 @@ fn update_coins(
         received,
         confirmed,
         expired,
+        dropped,
+        forgotten_dropped,
+        double_spent,
         spending,
         expired_spending,
         spent,
@@ -203,6 +262,23 @@ fn new_tip(bit: &impl BitcoinInterface, current_tip: &BlockChainTip) -> TipUpdat
     }
 }
 
+// Record the updates to our coins in database.
+fn record_updated_coins(db_conn: &mut Box<dyn DatabaseConnection>, updated_coins: &UpdatedCoins) {
+    let tip_height = db_conn
+        .chain_tip()
+        .expect("Always set at first startup")
+        .height;
+    db_conn.new_unspent_coins(&updated_coins.received);
+    db_conn.forget_dropped_coins(&updated_coins.forgotten_dropped);
+    db_conn.record_dropped_coins(&updated_coins.dropped, tip_height);
+    db_conn.remove_coins(&updated_coins.expired);
+    db_conn.invalidate_coins(&updated_coins.double_spent);
+    db_conn.confirm_coins(&updated_coins.confirmed);
+    db_conn.unspend_coins(&updated_coins.expired_spending);
+    db_conn.spend_coins(&updated_coins.spending);
+    db_conn.confirm_spend(&updated_coins.spent);
+}
+
 fn updates(
     db_conn: &mut Box<dyn DatabaseConnection>,
     bit: &impl BitcoinInterface,
@@ -236,12 +312,7 @@ fn updates(
     // The chain tip did not change since we started our updates. Record them and the latest tip.
     // Having the tip in database means that, as far as the chain is concerned, we've got all
     // updates up to this block. But not more.
-    db_conn.new_unspent_coins(&updated_coins.received);
-    db_conn.remove_coins(&updated_coins.expired);
-    db_conn.confirm_coins(&updated_coins.confirmed);
-    db_conn.unspend_coins(&updated_coins.expired_spending);
-    db_conn.spend_coins(&updated_coins.spending);
-    db_conn.confirm_spend(&updated_coins.spent);
+    record_updated_coins(db_conn, &updated_coins);
     if latest_tip != current_tip {
         db_conn.update_tip(&latest_tip);
         log::debug!("New tip: '{}'", latest_tip);
@@ -250,6 +321,38 @@ fn updates(
     log::debug!("Updates done.");
 }
 
+// While a rescan is ongoing, record the coins the backend already found in the blocks it
+// rescanned so they are available before it completes. This doesn't touch our tip: upon
+// completion we still rollback to the block before the rescan timestamp and update from there,
+// which only completes what was already recorded here.
+fn rescan_partial_updates(
+    db_conn: &mut Box<dyn DatabaseConnection>,
+    bit: &impl BitcoinInterface,
+    descs: &[descriptors::SinglePathLianaDesc],
+    secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+    rescan_timestamp: u32,
+) {
+    let rescan_tip = match bit.block_before_date(rescan_timestamp) {
+        Some(block) => block,
+        None => {
+            log::error!(
+                "Could not retrieve block height for timestamp '{}'",
+                rescan_timestamp
+            );
+            return;
+        }
+    };
+    let updated_coins = update_coins(bit, db_conn, &rescan_tip, descs, secp);
+    for coin in &updated_coins.received {
+        log::info!(
+            "Rescan found coin '{}' of {}.",
+            coin.outpoint,
+            coin.amount.display_dynamic()
+        );
+    }
+    record_updated_coins(db_conn, &updated_coins);
+}
+
 // Check if there is any rescan of the backend ongoing or one that just finished.
 fn rescan_check(
     db_conn: &mut Box<dyn DatabaseConnection>,
@@ -266,8 +369,9 @@ fn rescan_check(
     let rescan_timestamp = db_conn.rescan_timestamp();
     if let Some(progress) = bit.rescan_progress() {
         log::info!("Rescan progress: {:.2}%.", progress * 100.0);
-        if rescan_timestamp.is_none() {
-            log::warn!("Backend is rescanning but we didn't ask for it.");
+        match rescan_timestamp {
+            Some(timestamp) => rescan_partial_updates(db_conn, bit, descs, secp, timestamp),
+            None => log::warn!("Backend is rescanning but we didn't ask for it."),
         }
     } else if let Some(timestamp) = rescan_timestamp {
         log::info!("Rescan completed on the backend.");
@@ -298,6 +402,59 @@ fn rescan_check(
     }
 }
 
+// Remove the coins whose spending transaction is buried under at least the configured number of
+// confirmations.
+fn prune_spent_coins(db_conn: &mut Box<dyn DatabaseConnection>, retention: &SpentCoinRetention) {
+    let confirmations = match retention {
+        SpentCoinRetention::KeepAll => return,
+        SpentCoinRetention::Prune { confirmations } => *confirmations,
+    };
+    let tip = db_conn.chain_tip().expect("Always set at first startup");
+    let max_spend_height = tip
+        .height
+        .saturating_sub(i32::try_from(confirmations).unwrap_or(i32::MAX))
+        + 1;
+    let pruned = db_conn.prune_spent_coins(max_spend_height);
+    if pruned > 0 {
+        log::info!(
+            "Pruned {} coins spent at or before height {}.",
+            pruned,
+            max_spend_height
+        );
+    }
+}
+
+// Record the feerate estimates of the Bitcoin backend, unless they were already sampled recently.
+fn sample_feerates(db_conn: &mut Box<dyn DatabaseConnection>, bit: &impl BitcoinInterface) {
+    let now = u32::try_from(
+        time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .expect("System clock went backward the epoch?")
+            .as_secs(),
+    )
+    .expect("Is this the year 2106 yet? Misconfigured system clock.");
+    let since = now.saturating_sub(FEERATE_SAMPLE_INTERVAL_SECS);
+    if !db_conn.feerate_samples(since).is_empty() {
+        return;
+    }
+    let samples: Vec<_> = FEERATE_SAMPLE_TARGETS
+        .iter()
+        .filter_map(|conf_target| {
+            bit.feerate_estimate(*conf_target)
+                .map(|feerate| FeerateSample {
+                    timestamp: now,
+                    conf_target: *conf_target,
+                    feerate,
+                })
+        })
+        .collect();
+    if samples.is_empty() {
+        log::debug!("No feerate estimate available from the Bitcoin backend.");
+    } else {
+        db_conn.store_feerate_samples(&samples);
+    }
+}
+
 /// If the database chain tip is NULL (first startup), initialize it.
 pub fn maybe_initialize_tip(bit: &impl BitcoinInterface, db: &impl DatabaseInterface) {
     let mut db_conn = db.connection();
@@ -324,8 +481,11 @@ pub fn poll(
     db: &sync::Arc<sync::Mutex<dyn DatabaseInterface>>,
     secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
     descs: &[descriptors::SinglePathLianaDesc],
+    spent_coin_retention: &SpentCoinRetention,
 ) {
     let mut db_conn = db.connection();
     updates(&mut db_conn, bit, descs, secp);
     rescan_check(&mut db_conn, bit, descs, secp);
+    prune_spent_coins(&mut db_conn, spent_coin_retention);
+    sample_feerates(&mut db_conn, bit);
 }
@@ -1,6 +1,8 @@
 mod looper;
 
-use crate::{bitcoin::BitcoinInterface, database::DatabaseInterface, descriptors};
+use crate::{
+    bitcoin::BitcoinInterface, config::SpentCoinRetention, database::DatabaseInterface, descriptors,
+};
 
 use std::{
     sync::{self, mpsc},
@@ -9,12 +11,16 @@ use std::{
 
 use miniscript::bitcoin::secp256k1;
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub enum PollerMessage {
     Shutdown,
     /// Ask the Bitcoin poller to poll immediately, get notified through the passed channel once
     /// it's done.
     PollNow(mpsc::SyncSender<()>),
+    /// Ask the Bitcoin poller not to poll, and therefore not to update the database, until the
+    /// other end of the passed receiver is dropped. The poller signals through the passed sender
+    /// once it is paused between two polls.
+    Hold(mpsc::SyncSender<()>, mpsc::Receiver<()>),
 }
 
 /// The Bitcoin poller handler.
@@ -24,6 +30,8 @@ pub struct Poller {
     secp: secp256k1::Secp256k1<secp256k1::VerifyOnly>,
     // The receive and change descriptors (in this order).
     descs: [descriptors::SinglePathLianaDesc; 2],
+    // Whether to remove deeply spent coins from database after each poll.
+    spent_coin_retention: SpentCoinRetention,
 }
 
 impl Poller {
@@ -31,6 +39,7 @@ impl Poller {
         bit: sync::Arc<sync::Mutex<dyn BitcoinInterface>>,
         db: sync::Arc<sync::Mutex<dyn DatabaseInterface>>,
         desc: descriptors::LianaDescriptor,
+        spent_coin_retention: SpentCoinRetention,
     ) -> Poller {
         let secp = secp256k1::Secp256k1::verification_only();
         let descs = [
@@ -46,6 +55,7 @@ impl Poller {
             db,
             secp,
             descs,
+            spent_coin_retention,
         }
     }
 
@@ -91,12 +101,28 @@ impl Poller {
                     // We've been asked to poll, don't wait any further and signal completion to
                     // the caller.
                     last_poll = Some(time::Instant::now());
-                    looper::poll(&self.bit, &self.db, &self.secp, &self.descs);
+                    looper::poll(
+                        &self.bit,
+                        &self.db,
+                        &self.secp,
+                        &self.descs,
+                        &self.spent_coin_retention,
+                    );
                     if let Err(e) = sender.send(()) {
                         log::error!("Error sending immediate poll completion signal: {}.", e);
                     }
                     continue;
                 }
+                Ok(PollerMessage::Hold(sender, receiver)) => {
+                    // We are between two polls. Signal it to the caller and block until we are
+                    // released. Don't reset the time of the last poll, so we poll right away if
+                    // it was due in the meantime.
+                    if let Err(e) = sender.send(()) {
+                        log::error!("Error sending hold acknowledgement signal: {}.", e);
+                    }
+                    while receiver.recv().is_ok() {}
+                    continue;
+                }
                 Err(mpsc::RecvTimeoutError::Timeout) => {
                     // It's been long enough since the last poll.
                 }
@@ -122,7 +148,13 @@ impl Poller {
                 }
             }
 
-            looper::poll(&self.bit, &self.db, &self.secp, &self.descs);
+            looper::poll(
+                &self.bit,
+                &self.db,
+                &self.secp,
+                &self.descs,
+                &self.spent_coin_retention,
+            );
         }
     }
 }
@@ -5,13 +5,18 @@
 mod utils;
 
 use crate::{
-    bitcoin::BitcoinInterface,
-    database::{Coin, DatabaseConnection, DatabaseInterface},
+    bbqr,
+    bitcoin::{BackendInfo, BitcoinInterface},
+    config::{DbEncryptionKey, FeeSource, MIN_PRUNING_DEPTH},
+    database::{Coin, DatabaseConnection, DatabaseInterface, DatabaseStats, StoredAddresses},
     descriptors,
     poller::PollerMessage,
+    random,
     spend::{
-        create_spend, AddrInfo, AncestorInfo, CandidateCoin, CreateSpendRes, SpendCreationError,
-        SpendOutputAddress, SpendTxFees, TxGetter,
+        create_spend, max_input_fee, op_return_data, set_fee_from_change, split_change_output,
+        AddrInfo, AncestorInfo, CandidateCoin, CreateSpendRes, DraftId, Feerate, ForeignInput,
+        InsaneFeeInfo, OutputSeed, SpendCreationError, SpendOutputAddress, SpendTxFees, TxGetter,
+        DUST_OUTPUT_SATS, LONG_TERM_FEERATE_VB, MAX_FEE, MAX_FEERATE,
     },
     DaemonControl, VERSION,
 };
@@ -19,26 +24,79 @@ use crate::{
 pub use crate::database::{CoinStatus, LabelItem};
 
 use utils::{
-    deser_addr_assume_checked, deser_amount_from_sats, deser_fromstr, deser_hex, ser_amount,
-    ser_hex, ser_to_string,
+    deser_addr_assume_checked, deser_amount_from_sats, deser_feerate_vb, deser_fromstr,
+    deser_fromstr_vec, deser_hex, deser_opt_addr_assume_checked, ser_amount, ser_feerate_vb,
+    ser_hex, ser_to_string, ser_to_string_vec,
 };
 
 use std::{
-    collections::{hash_map, HashMap, HashSet},
+    collections::{hash_map, BTreeMap, HashMap, HashSet},
+    convert::TryFrom,
     fmt,
+    str::FromStr,
     sync::{self, mpsc},
+    time,
 };
 
 use miniscript::{
-    bitcoin::{self, address, bip32, psbt::Psbt},
+    bitcoin::{
+        self, absolute, address, bip32,
+        hashes::{sha256, Hash},
+        hex::DisplayHex,
+        psbt::Psbt,
+    },
+    descriptor,
     psbt::PsbtExt,
 };
 use serde::{Deserialize, Serialize};
 
+// The number of labels queried at once from the database when exporting them all.
+const LABELS_EXPORT_CHUNK_SIZE: u64 = 1_000;
+
+// The number of transactions queried at once from the database when iterating over the history.
+const TXS_EXPORT_CHUNK_SIZE: u64 = 100;
+
+// The confirmation targets, in blocks, for which we provide feerate estimates.
+const FEERATE_ESTIMATES_TARGETS: [u16; 3] = [2, 6, 24];
+
+// How many addresses past the next derivation index to import in Bitcoin Core. This is the default
+// size of its keypool, and what we use when importing our descriptors to our own watchonly wallet.
+const CORE_IMPORT_RANGE_MARGIN: u32 = 1_000;
+
+// The confirmation target, in blocks, of the feerate at which we estimate the maximum amount we
+// can spend.
+const MAX_SPENDABLE_CONF_TARGET: u16 = 6;
+
+/// The feerate, in sats/vb, used when none of the configured fee sources could provide an
+/// estimate. This is the default minimum relay feerate so the transaction will at least be
+/// relayed, and it can always be bumped later on.
+pub const FALLBACK_FEERATE: u64 = 1;
+
+/// For how long the result of a `createspend` call is kept to be returned again to a call with the
+/// same idempotency key.
+pub const IDEMPOTENCY_KEY_TTL: time::Duration = time::Duration::from_secs(24 * 60 * 60);
+
+/// How long the coins selected by a Spend transaction draft stay reserved for it, unless it is
+/// broadcast or deleted before.
+pub const COIN_RESERVATION_TTL: time::Duration = time::Duration::from_secs(60 * 60);
+
+/// Past this age, our tip is assumed to be outdated and no anti fee sniping locktime is set on
+/// new transactions. Same as Bitcoin Core.
+pub const MAX_ANTI_FEE_SNIPING_TIP_AGE: time::Duration = time::Duration::from_secs(8 * 60 * 60);
+
+/// The maximum number of outputs the change of a Spend transaction may be split into.
+pub const MAX_CHANGE_OUTPUTS: u32 = 10;
+
+/// The maximum number of characters of a note on a coin.
+pub const MAX_COIN_NOTE_LENGTH: usize = 2_000;
+
+/// The maximum satisfaction weight of a foreign input, that of a standard transaction.
+pub const MAX_FOREIGN_INPUT_SAT_WEIGHT: u32 = 400_000;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CommandError {
     NoOutpointForSelfSend,
-    InvalidFeerate(/* sats/vb */ u64),
+    InvalidFeerate(Feerate),
     UnknownOutpoint(bitcoin::OutPoint),
     AlreadySpent(bitcoin::OutPoint),
     ImmatureCoinbase(bitcoin::OutPoint),
@@ -55,13 +113,101 @@ pub enum CommandError {
     TxBroadcast(String),
     AlreadyRescanning,
     InsaneRescanTimestamp(u32),
+    /// The range of block heights to rescan is empty or goes past the current tip.
+    InsaneRescanRange(u32, u32),
     /// An error that might occur in the racy rescan triggering logic.
     RescanTrigger(String),
+    /// We could not re-establish the connection to the Bitcoin backend.
+    BackendConnection(String),
     RecoveryNotAvailable,
+    /// Our descriptor has no timelocked recovery path.
+    NoRecoveryPath,
     /// Overflowing or unhardened derivation index.
     InvalidDerivationIndex,
     RbfError(RbfErrorInfo),
     EmptyFilterList,
+    /// The given index does not correspond to any spending path of our descriptor.
+    UnknownSpendingPath(usize),
+    /// This coin can't be spent yet through the requested spending path.
+    SpendingPathNotAvailable(/* path index */ usize, bitcoin::OutPoint),
+    /// Error when writing an encrypted copy of the database.
+    DatabaseEncryption(String),
+    /// Error when vacuuming the database.
+    DatabaseVacuum(String),
+    /// Error when gathering randomness, for instance to generate a draft identifier.
+    Randomness(String),
+    /// This block height is outside of the range of blocks scanned by the wallet.
+    HeightNotScanned(i32),
+    /// This block is not part of the best chain of the Bitcoin backend.
+    UnknownBlock(bitcoin::BlockHash),
+    /// Error when analyzing the signatures of a Spend transaction.
+    SpendAnalysis(String),
+    /// Spent coins can't be pruned at such a low depth.
+    PruningTooShallow(/* confirmations */ u32),
+    /// The change can't be split into this number of outputs.
+    InvalidChangeSplit(u32),
+    /// The descriptor to migrate to is invalid.
+    InvalidDescriptor(String),
+    /// None of our coins can be spent to migrate to a new descriptor.
+    NoCoinToMigrate,
+    /// This transaction is not in the mempool of the Bitcoin backend.
+    NotInMempool(bitcoin::Txid),
+    /// This address is one of ours, it can't be part of the address book.
+    NotExternalAddress(bitcoin::Address),
+    /// This coin is reserved by another Spend transaction draft.
+    CoinReserved(bitcoin::OutPoint),
+    /// The note for this coin is longer than [`MAX_COIN_NOTE_LENGTH`].
+    CoinNoteTooLong(bitcoin::OutPoint),
+    /// A reorganization depth must be of at least one block.
+    InvalidReorgDepth,
+    /// The given index does not correspond to any recovery path of our descriptor.
+    UnknownRecoveryPath(usize),
+    /// This transaction is neither one of our wallet transactions nor a stored Spend draft.
+    UnknownTransaction(bitcoin::Txid),
+    /// This address is not one of our receive or change addresses.
+    UnknownAddress(bitcoin::Address),
+    /// Error when encoding or decoding a sequence of BBQr parts.
+    Bbqr(bbqr::BbqrError),
+    /// The decoded data isn't a valid PSBT.
+    InvalidPsbt(String),
+    /// The Bitcoin backend could not provide this transaction, for instance because it was pruned.
+    FundingTxUnavailable(bitcoin::Txid),
+    /// This derivation path is not a single unhardened index within our change descriptor.
+    InvalidChangePath(bip32::DerivationPath),
+    /// Both a change address and a change derivation path were given.
+    ChangeAddressAndPath,
+    /// This foreign input spends one of our coins, is given more than once or its satisfaction
+    /// is too large.
+    InvalidForeignInput(bitcoin::OutPoint),
+    /// A PSBT input spending one of our coins contains a signature from a key which isn't part of
+    /// our descriptor at this coin's derivation index. The fingerprint of the key's origin is
+    /// given if the PSBT provides it.
+    UnexpectedSigner(Option<bip32::Fingerprint>),
+    /// The session is locked and this command isn't available until it is unlocked.
+    Locked,
+    /// The passphrase doesn't match the configured session passphrase.
+    WrongPassphrase,
+    /// No session passphrase is configured, so the wallet can't be locked nor unlocked.
+    NoSessionPassphrase,
+    /// A replacement was drafted and stored for this transaction, which must not be broadcast
+    /// unless the replacement is deleted.
+    SupersededByReplacement(bitcoin::Txid),
+    /// We broadcast a replacement for this transaction.
+    ReplacedBy(bitcoin::Txid),
+    /// This Spend transaction has no output paying to this address.
+    NotADestination(bitcoin::Txid, bitcoin::Address),
+    /// A wallet snapshot to compare doesn't include the coins.
+    SnapshotWithoutCoins,
+    /// This idempotency key was already used for a Spend creation with other parameters.
+    IdempotencyKeyReused(String),
+    /// A Spend transaction is being created with this idempotency key.
+    IdempotencyKeyInFlight(String),
+    /// The funds can't be rotated to this number of outputs.
+    InvalidRotationOutputs(u32),
+    /// None of our coins can be spent to rotate the funds.
+    NoCoinToRotate,
+    /// The same address was given more than once as a destination of a Spend.
+    DuplicateDestination(bitcoin::Address),
 }
 
 impl fmt::Display for CommandError {
@@ -105,22 +251,393 @@ impl fmt::Display for CommandError {
                 "There is already a rescan ongoing. Please wait for it to complete first."
             ),
             Self::InsaneRescanTimestamp(t) => write!(f, "Insane timestamp '{}'.", t),
+            Self::InsaneRescanRange(start, end) => write!(
+                f,
+                "Insane rescan range from height '{}' to height '{}'.",
+                start, end
+            ),
             Self::RescanTrigger(s) => write!(f, "Error while starting rescan: '{}'", s),
+            Self::BackendConnection(s) => {
+                write!(f, "Error connecting to the Bitcoin backend: '{}'.", s)
+            }
             Self::RecoveryNotAvailable => write!(
                 f,
                 "No coin currently spendable through this timelocked recovery path."
             ),
+            Self::NoRecoveryPath => write!(f, "This wallet has no timelocked recovery path."),
             Self::InvalidDerivationIndex => {
                 write!(f, "Unhardened or overflowing BIP32 derivation index.")
             }
             Self::RbfError(e) => write!(f, "RBF error: '{}'.", e),
             Self::EmptyFilterList => write!(f, "Filter list is empty, should supply None instead."),
+            Self::UnknownSpendingPath(i) => write!(f, "Unknown spending path '{}'.", i),
+            Self::SpendingPathNotAvailable(i, op) => write!(
+                f,
+                "Coin at '{}' is not yet spendable through spending path '{}'.",
+                op, i
+            ),
+            Self::DatabaseEncryption(e) => write!(f, "Error encrypting the database: '{}'.", e),
+            Self::DatabaseVacuum(e) => write!(f, "Error vacuuming the database: '{}'.", e),
+            Self::Randomness(e) => write!(f, "Error gathering randomness: '{}'.", e),
+            Self::HeightNotScanned(h) => write!(
+                f,
+                "Block height '{}' is outside of the range of blocks scanned by the wallet.",
+                h
+            ),
+            Self::UnknownBlock(h) => write!(f, "Block '{}' is not part of the best chain.", h),
+            Self::SpendAnalysis(e) => write!(f, "Error analyzing the Spend transaction: '{}'.", e),
+            Self::InvalidChangeSplit(n) => write!(
+                f,
+                "Invalid number of change outputs: {}. Must be between 1 and {}.",
+                n, MAX_CHANGE_OUTPUTS
+            ),
+            Self::PruningTooShallow(confs) => write!(
+                f,
+                "Spent coins can only be pruned after at least {} confirmations, not {}.",
+                MIN_PRUNING_DEPTH, confs
+            ),
+            Self::InvalidDescriptor(e) => write!(f, "Invalid descriptor: {}", e),
+            Self::NoCoinToMigrate => write!(f, "No coin can currently be migrated."),
+            Self::NotInMempool(txid) => write!(f, "Transaction '{}' is not in the mempool.", txid),
+            Self::NotExternalAddress(addr) => write!(
+                f,
+                "Address '{}' is one of ours and can't be part of the address book.",
+                addr
+            ),
+            Self::CoinReserved(op) => write!(
+                f,
+                "Coin at '{}' is reserved by another Spend transaction draft.",
+                op
+            ),
+            Self::CoinNoteTooLong(op) => write!(
+                f,
+                "The note for coin at '{}' is longer than {} characters.",
+                op, MAX_COIN_NOTE_LENGTH
+            ),
+            Self::InvalidReorgDepth => write!(f, "The depth must be of at least one block."),
+            Self::UnknownRecoveryPath(i) => write!(f, "Unknown recovery path '{}'.", i),
+            Self::UnknownTransaction(txid) => write!(f, "Unknown transaction '{}'.", txid),
+            Self::UnknownAddress(addr) => write!(f, "Address '{}' is not one of ours.", addr),
+            Self::Bbqr(e) => write!(f, "BBQr error: {}", e),
+            Self::InvalidPsbt(e) => write!(f, "Invalid PSBT: '{}'.", e),
+            Self::FundingTxUnavailable(txid) => write!(
+                f,
+                "Transaction '{}' could not be retrieved from the Bitcoin backend.",
+                txid
+            ),
+            Self::InvalidChangePath(path) => write!(
+                f,
+                "Invalid change derivation path '{}': it must be a single unhardened index.",
+                path
+            ),
+            Self::ChangeAddressAndPath => write!(
+                f,
+                "Can't specify both a change address and a change derivation path."
+            ),
+            Self::InvalidForeignInput(op) => write!(
+                f,
+                "Invalid foreign input '{}': it must not be one of our coins nor be given more \
+                 than once, and its satisfaction weight must be at most {}.",
+                op, MAX_FOREIGN_INPUT_SAT_WEIGHT
+            ),
+            Self::UnexpectedSigner(fingerprint) => {
+                write!(f, "Signature from a key which isn't part of our descriptor")?;
+                if let Some(fg) = fingerprint {
+                    write!(f, " (origin fingerprint '{}')", fg)?;
+                }
+                write!(f, ".")
+            }
+            Self::Locked => write!(f, "The wallet is locked. Unlock it first."),
+            Self::WrongPassphrase => write!(f, "Wrong session passphrase."),
+            Self::NoSessionPassphrase => write!(f, "No session passphrase is configured."),
+            Self::SupersededByReplacement(replacement_txid) => write!(
+                f,
+                "A replacement was drafted for this transaction: '{}'. Delete the replacement to \
+                 broadcast this transaction instead.",
+                replacement_txid
+            ),
+            Self::ReplacedBy(replacement_txid) => write!(
+                f,
+                "We broadcast a replacement for this transaction: '{}'.",
+                replacement_txid
+            ),
+            Self::NotADestination(txid, addr) => write!(
+                f,
+                "Spend transaction '{}' has no output paying to '{}'.",
+                txid, addr
+            ),
+            Self::SnapshotWithoutCoins => {
+                write!(f, "The snapshots to compare must include the coins.")
+            }
+            Self::IdempotencyKeyReused(key) => write!(
+                f,
+                "Idempotency key '{}' was already used with different parameters.",
+                key
+            ),
+            Self::IdempotencyKeyInFlight(key) => write!(
+                f,
+                "A Spend transaction is already being created with idempotency key '{}'.",
+                key
+            ),
+            Self::InvalidRotationOutputs(n) => write!(
+                f,
+                "Invalid number of outputs to rotate the funds to: {}. Must be between 1 and {}.",
+                n, MAX_CHANGE_OUTPUTS
+            ),
+            Self::NoCoinToRotate => write!(f, "No coin can currently be rotated."),
+            Self::DuplicateDestination(addr) => write!(
+                f,
+                "Address '{}' is given more than once as a destination.",
+                addr
+            ),
         }
     }
 }
 
 impl std::error::Error for CommandError {}
 
+/// The outcome of the broadcast of each of a set of transactions.
+pub type BroadcastResults = Vec<(bitcoin::Txid, Result<(), CommandError>)>;
+
+/// A reference to stored Spend transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpendRef {
+    /// The Spend transaction with this txid.
+    Txid(bitcoin::Txid),
+    /// All the versions of the Spend transaction draft with this identifier.
+    DraftId(DraftId),
+}
+
+/// Possible (mutually exclusive) status of a stored Spend transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpendStatus {
+    /// Doesn't have any signature yet.
+    Draft,
+    /// Has some signatures but not enough to be finalized.
+    PartiallySigned,
+    /// Has enough signatures to be finalized, ready to be broadcast.
+    Signed,
+    /// Was broadcast, as some of our coins are being spent by this transaction.
+    Broadcast,
+}
+
+impl SpendStatus {
+    pub fn from_arg(s: &str) -> Option<SpendStatus> {
+        match s {
+            "draft" => Some(SpendStatus::Draft),
+            "partially_signed" => Some(SpendStatus::PartiallySigned),
+            "signed" => Some(SpendStatus::Signed),
+            "broadcast" => Some(SpendStatus::Broadcast),
+            _ => None,
+        }
+    }
+
+    /// Converts a `SpendStatus` to its equivalent argument name as used in the `listspendtxs`
+    /// RPC command.
+    pub fn to_arg(&self) -> &'static str {
+        match self {
+            SpendStatus::Draft => "draft",
+            SpendStatus::PartiallySigned => "partially_signed",
+            SpendStatus::Signed => "signed",
+            SpendStatus::Broadcast => "broadcast",
+        }
+    }
+
+    /// Get the status of a Spend transaction PSBT which isn't known to be broadcast, from the
+    /// signatures it contains.
+    fn from_psbt(
+        psbt: &Psbt,
+        secp: &bitcoin::secp256k1::Secp256k1<bitcoin::secp256k1::VerifyOnly>,
+    ) -> SpendStatus {
+        if psbt.clone().finalize_mut(secp).is_ok() {
+            SpendStatus::Signed
+        } else if psbt.inputs.iter().any(|psbt_in| {
+            !psbt_in.partial_sigs.is_empty()
+                || psbt_in.tap_key_sig.is_some()
+                || !psbt_in.tap_script_sigs.is_empty()
+        }) {
+            SpendStatus::PartiallySigned
+        } else {
+            SpendStatus::Draft
+        }
+    }
+}
+
+/// How fast a transaction should be confirmed, as an alternative to choosing its feerate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeePriority {
+    /// Within 2 blocks.
+    Fast,
+    /// Within 6 blocks.
+    Normal,
+    /// Within 24 blocks.
+    Slow,
+}
+
+impl FeePriority {
+    pub fn from_arg(s: &str) -> Option<FeePriority> {
+        match s {
+            "fast" => Some(FeePriority::Fast),
+            "normal" => Some(FeePriority::Normal),
+            "slow" => Some(FeePriority::Slow),
+            _ => None,
+        }
+    }
+
+    /// The confirmation target, in blocks, corresponding to this priority.
+    pub fn conf_target(&self) -> u16 {
+        match self {
+            FeePriority::Fast => 2,
+            FeePriority::Normal => 6,
+            FeePriority::Slow => 24,
+        }
+    }
+}
+
+/// How to choose the coins to be spent by a transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoinSelector {
+    /// Spend exactly these coins.
+    Outpoints(Vec<bitcoin::OutPoint>),
+    /// Automatically select among the coins with this label set for either their outpoint or their
+    /// address.
+    Label(String),
+    /// Automatically select among all our spendable coins.
+    All,
+}
+
+/// The optional parameters for the creation of a Spend transaction.
+#[derive(Debug, Clone)]
+pub struct CreateSpendOptions {
+    /// Send the change to this address rather than to our next change address. Can't be combined
+    /// with a `change_path`.
+    pub change_address: Option<bitcoin::Address<bitcoin::address::NetworkUnchecked>>,
+    /// Send the change to the address at this derivation index of our change descriptor rather
+    /// than to the next unused one.
+    pub change_path: Option<bip32::DerivationPath>,
+    /// Spend through this spending path. The primary path is at index 0 and the recovery paths
+    /// follow, by increasing timelock.
+    pub force_path: Option<usize>,
+    /// Avoid combining coins from different addresses, if possible.
+    pub privacy_aware: bool,
+    /// If a Spend transaction was already successfully created with the same key less than
+    /// [`IDEMPOTENCY_KEY_TTL`] ago, the same result is returned instead of creating a new one. The
+    /// key can't be reused with different parameters, nor while its transaction is being created.
+    pub idempotency_key: Option<String>,
+    /// Score the regular coin selection against alternatives, and use the best one.
+    pub selection_weights: Option<SelectionWeights>,
+    /// Select unconfirmed change coins.
+    pub allow_unconfirmed: bool,
+    /// Select unconfirmed coins received from external wallets.
+    pub allow_unconfirmed_external: bool,
+    /// Set this label on the txid of the created transaction.
+    pub label: Option<String>,
+    /// Split the change evenly between up to this number of outputs to our change addresses.
+    pub split_change: Option<u32>,
+    /// Attach this data in an OP_RETURN output.
+    pub op_return: Option<Vec<u8>>,
+    /// Determines the position of the change output(s). A random seed is used if none is given.
+    /// The seed used is returned so the ordering can be verified after the fact.
+    pub output_seed: Option<OutputSeed>,
+    /// Reserve the coins spent by the transaction for its draft once it is stored (see
+    /// [`DaemonControl::update_spend`]), until it is broadcast or deleted, or for
+    /// [`COIN_RESERVATION_TTL`]. Reserved coins are not selected by other Spend transactions.
+    pub reserve_inputs: bool,
+    /// Labels to set on the addresses of the destinations, as if set with
+    /// [`DaemonControl::update_labels`]. Labels for addresses which are not destinations are
+    /// ignored.
+    pub destination_labels: HashMap<bitcoin::Address<bitcoin::address::NetworkUnchecked>, String>,
+    /// Coins not to consider in the automated coin selection. This has no effect if the coins to
+    /// spend are given explicitly.
+    pub exclude_outpoints: Vec<bitcoin::OutPoint>,
+    /// Don't warn about destinations which are addresses of this wallet. These outputs are
+    /// listed in the result in any case.
+    pub allow_self_send: bool,
+    /// The feerate at which the coin selection assumes a change output will be spent in the
+    /// future. Defaults to the configured one, or to [`LONG_TERM_FEERATE_VB`].
+    pub long_term_feerate_vb: Option<u64>,
+    /// Coins which aren't ours to spend as well, for instance for a collaborative transaction.
+    /// They are always included, after our own inputs, and their value and size are accounted
+    /// for in the fee. We don't sign them, and their indexes are listed in the result.
+    pub extra_inputs: Vec<ForeignInput>,
+    /// The maximum number of inputs of the transaction. Defaults to the configured
+    /// `max_spend_inputs`. If the target can't be met within this cap a
+    /// [`SpendCreationError::TooManyInputsRequired`] is returned.
+    pub max_inputs: Option<usize>,
+    /// Don't consider in the automated coin selection the coins worth less than the fee to spend
+    /// them (see [`DaemonControl::uneconomic_coins`]). This has no effect if the coins to spend
+    /// are given explicitly.
+    pub ignore_uneconomic: bool,
+}
+
+impl Default for CreateSpendOptions {
+    fn default() -> Self {
+        Self {
+            change_address: None,
+            change_path: None,
+            force_path: None,
+            privacy_aware: false,
+            idempotency_key: None,
+            selection_weights: None,
+            allow_unconfirmed: true,
+            allow_unconfirmed_external: false,
+            label: None,
+            split_change: None,
+            op_return: None,
+            output_seed: None,
+            reserve_inputs: true,
+            destination_labels: HashMap::new(),
+            exclude_outpoints: Vec::new(),
+            allow_self_send: false,
+            long_term_feerate_vb: None,
+            extra_inputs: Vec::new(),
+            max_inputs: None,
+            ignore_uneconomic: false,
+        }
+    }
+}
+
+/// A Spend transaction creation requested with an idempotency key.
+#[derive(Debug, Clone)]
+pub(crate) struct IdempotentSpend {
+    /// When the creation was requested.
+    pub created_at: time::Instant,
+    /// The hash of the parameters of the request, so the key can't be reused for another one.
+    pub params_hash: sha256::Hash,
+    /// The result of the request, once the transaction was created.
+    pub result: Option<CreateSpendResult>,
+}
+
+// A hash of the parameters of a Spend transaction creation request. The idempotency keys only
+// live in memory, so it's fine for it to rely on their Debug representation.
+fn spend_params_hash(
+    destinations: &HashMap<bitcoin::Address<bitcoin::address::NetworkUnchecked>, u64>,
+    coin_selector: &CoinSelector,
+    feerate: Feerate,
+    options: &CreateSpendOptions,
+) -> sha256::Hash {
+    let destinations: BTreeMap<_, _> = destinations
+        .iter()
+        .map(|(addr, amount)| (format!("{:?}", addr), amount))
+        .collect();
+    let destination_labels: BTreeMap<_, _> = options
+        .destination_labels
+        .iter()
+        .map(|(addr, label)| (format!("{:?}", addr), label))
+        .collect();
+    let options = CreateSpendOptions {
+        destination_labels: HashMap::new(),
+        ..options.clone()
+    };
+    let params = format!(
+        "{:?}{:?}{:?}{:?}{:?}",
+        destinations, coin_selector, feerate, options, destination_labels
+    );
+    sha256::Hash::hash(params.as_bytes())
+}
+
 impl From<SpendCreationError> for CommandError {
     fn from(e: SpendCreationError) -> Self {
         CommandError::SpendCreation(e)
@@ -131,7 +648,8 @@ impl From<SpendCreationError> for CommandError {
 pub enum RbfErrorInfo {
     MissingFeerate,
     SuperfluousFeerate,
-    TooLowFeerate(u64),
+    TooLowFeerate(Feerate),
+    TooLowFee(u64),
     NotSignaling,
 }
 
@@ -145,6 +663,11 @@ impl fmt::Display for RbfErrorInfo {
                 write!(f, "A feerate must not be provided if creating a cancel. We'll always use the smallest one which satisfies the RBF rules.")
             }
             Self::TooLowFeerate(r) => write!(f, "Feerate too low: {}.", r),
+            Self::TooLowFee(min_fee) => write!(
+                f,
+                "Fee too low, the replacement must pay at least {} sats.",
+                min_fee
+            ),
             Self::NotSignaling => write!(f, "Replacement candidate does not signal for RBF."),
         }
     }
@@ -176,6 +699,147 @@ impl<'a> TxGetter for BitcoindTxGetter<'a> {
     }
 }
 
+// Whether this coin would be spendable in the next block through a recovery path with the given
+// timelock.
+fn is_recovery_available(coin: &Coin, current_height: i32, timelock: u16) -> bool {
+    let height_delta: i32 = timelock.into();
+    coin.block_info
+        .map(|b| current_height + 1 >= b.height + height_delta)
+        .unwrap_or(false)
+}
+
+// The number of blocks left before this coin can be spent using the recovery path with the given
+// timelock, or `None` if the coin is unconfirmed.
+fn blocks_before_recovery(coin: &Coin, current_height: i32, timelock: u16) -> Option<u32> {
+    let height_delta: i32 = timelock.into();
+    coin.block_info
+        .map(|b| (b.height + height_delta - (current_height + 1)).max(0) as u32)
+}
+
+// Whether this coin has at least the given number of confirmations at this block height.
+fn has_min_confirmations(coin: &Coin, current_height: i32, min_confirmations: u32) -> bool {
+    min_confirmations == 0
+        || coin
+            .block_info
+            .map(|b| {
+                i64::from(current_height) - i64::from(b.height) >= i64::from(min_confirmations) - 1
+            })
+            .unwrap_or(false)
+}
+
+// The spending paths through which this coin could be spent in the next block, as indexes with
+// the primary path first and then the recovery paths by increasing timelock. Coins which are
+// immature, already being spent or don't have the minimum number of confirmations can't be spent
+// through any path.
+fn spendable_paths(
+    coin: &Coin,
+    current_height: i32,
+    timelocks: &[u16],
+    min_confirmations: u32,
+) -> Vec<usize> {
+    if coin.is_immature
+        || coin.spend_txid.is_some()
+        || !has_min_confirmations(coin, current_height, min_confirmations)
+    {
+        return Vec::new();
+    }
+    std::iter::once(0)
+        .chain(
+            timelocks
+                .iter()
+                .enumerate()
+                .filter(|(_, tl)| is_recovery_available(coin, current_height, **tl))
+                .map(|(i, _)| i + 1),
+        )
+        .collect()
+}
+
+// Compare the addresses stored in database to the ones derived from our descriptor at the same
+// index, and look for the derivation indexes below `end_index` for which no address is stored.
+fn address_consistency(
+    main_descriptor: &descriptors::LianaDescriptor,
+    secp: &bitcoin::secp256k1::Secp256k1<bitcoin::secp256k1::VerifyOnly>,
+    network: bitcoin::Network,
+    stored: &[StoredAddresses],
+    end_index: u32,
+) -> ConsistencyReport {
+    let mut mismatches = Vec::new();
+    for entry in stored {
+        for (is_change, stored_addr) in [
+            (false, &entry.receive_address),
+            (true, &entry.change_address),
+        ] {
+            let desc = if is_change {
+                main_descriptor.change_descriptor()
+            } else {
+                main_descriptor.receive_descriptor()
+            };
+            let expected = desc.derive(entry.derivation_index, secp).address(network);
+            // Compare the address strings, as the network of an unchecked address may differ.
+            if stored_addr.clone().assume_checked().to_string() != expected.to_string() {
+                mismatches.push(AddressMismatch {
+                    derivation_index: entry.derivation_index,
+                    is_change,
+                    stored: stored_addr.clone(),
+                    expected,
+                });
+            }
+        }
+    }
+    let stored_indexes: HashSet<u32> = stored.iter().map(|e| e.derivation_index.into()).collect();
+    let missing = (0..end_index)
+        .filter(|i| !stored_indexes.contains(i))
+        .map(bip32::ChildNumber::from)
+        .collect();
+
+    ConsistencyReport {
+        checked: stored.len(),
+        missing,
+        mismatches,
+    }
+}
+
+// Get the origin of a key from our descriptor.
+fn key_origin(key: &descriptor::DescriptorPublicKey) -> KeyOrigin {
+    match key {
+        descriptor::DescriptorPublicKey::MultiXPub(xpub) => {
+            let (fingerprint, derivation_path) = xpub
+                .origin
+                .clone()
+                .expect("Liana descriptors keys always have an origin");
+            KeyOrigin {
+                fingerprint,
+                derivation_path,
+                xpub: xpub.xkey,
+            }
+        }
+        _ => unreachable!("Liana descriptors keys are always multi xpubs"),
+    }
+}
+
+// Get a feerate estimate for this confirmation target from the first of these sources that can
+// provide one, or fall back to a conservative static feerate.
+fn feerate_estimate(
+    bitcoin: &impl BitcoinInterface,
+    sources: &[FeeSource],
+    conf_target: u16,
+) -> FeerateEstimate {
+    let (feerate, source) = sources
+        .iter()
+        .find_map(|source| match source {
+            FeeSource::Backend => bitcoin
+                .feerate_estimate(conf_target)
+                .map(|feerate| (feerate, FeerateEstimateSource::Backend)),
+            FeeSource::Static { feerate } => Some((*feerate, FeerateEstimateSource::Static)),
+        })
+        .unwrap_or((FALLBACK_FEERATE, FeerateEstimateSource::Fallback));
+    FeerateEstimate {
+        conf_target,
+        feerate,
+        source,
+    }
+}
+
 fn coin_to_candidate(
     coin: &Coin,
     must_select: bool,
@@ -193,6 +857,186 @@ fn coin_to_candidate(
     }
 }
 
+// A warning about this destination if its script is unusual for a payment, as it may be the sign
+// of a mistake when entering the address. Such outputs are either non-standard or use a witness
+// version without any spending rules yet, so coins sent to them could be lost.
+fn destination_warning(addr: &bitcoin::Address) -> Option<String> {
+    match addr.payload() {
+        address::Payload::WitnessProgram(prog) if addr.address_type().is_none() => {
+            let version = prog.version().to_num();
+            Some(if version == 1 {
+                format!(
+                    "Destination {} is a non-standard Taproot output ({} bytes witness program \
+                     instead of 32). Coins sent to it could be lost.",
+                    addr,
+                    prog.program().len()
+                )
+            } else {
+                format!(
+                    "Destination {} uses segwit version {}, which has no spending rules yet. Coins \
+                     sent to it could be lost.",
+                    addr, version
+                )
+            })
+        }
+        _ => None,
+    }
+}
+
+// Group these candidates by the address they pay to, with the clusters of smaller total value
+// first.
+fn address_clusters(candidates: &[CandidateCoin]) -> Vec<Vec<CandidateCoin>> {
+    let mut clusters: BTreeMap<(bool, bip32::ChildNumber), Vec<CandidateCoin>> = BTreeMap::new();
+    for cand in candidates {
+        clusters
+            .entry((cand.is_change, cand.deriv_index))
+            .or_default()
+            .push(*cand);
+    }
+    let mut clusters: Vec<_> = clusters.into_values().collect();
+    clusters.sort_by_key(|cluster| cluster.iter().map(|c| c.amount).sum::<bitcoin::Amount>());
+    clusters
+}
+
+// Whether any of the inputs of this PSBT spends one of our coins which is not confirmed yet.
+fn spends_unconfirmed(db_conn: &mut Box<dyn DatabaseConnection>, psbt: &Psbt) -> bool {
+    let outpoints: Vec<_> = psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .map(|txin| txin.previous_output)
+        .collect();
+    db_conn
+        .coins_by_outpoints(&outpoints)
+        .values()
+        .any(|c| !c.is_confirmed())
+}
+
+// If the transaction of this PSBT isn't labelled yet, set on it the label of the most recently
+// updated stored transaction of the same draft, if any. This way the label set when creating a
+// Spend follows it across its updates and replacements.
+fn inherit_draft_label(db_conn: &mut Box<dyn DatabaseConnection>, psbt: &Psbt) {
+    let draft_id = match DraftId::from_psbt(psbt) {
+        Some(draft_id) => draft_id,
+        None => return,
+    };
+    let txid = psbt.unsigned_tx.txid();
+    let mut draft_txs: Vec<_> = db_conn
+        .list_spend()
+        .into_iter()
+        .filter(|(draft_psbt, _)| DraftId::from_psbt(draft_psbt) == Some(draft_id))
+        .map(|(draft_psbt, updated_at)| (draft_psbt.unsigned_tx.txid(), updated_at))
+        .filter(|(draft_txid, _)| *draft_txid != txid)
+        .collect();
+    draft_txs.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+    let items = draft_txs
+        .iter()
+        .map(|(draft_txid, _)| LabelItem::Txid(*draft_txid))
+        .chain(std::iter::once(LabelItem::Txid(txid)))
+        .collect();
+    let labels = db_conn.labels(&items);
+    if labels.contains_key(&txid.to_string()) {
+        return;
+    }
+    if let Some(label) = draft_txs
+        .iter()
+        .find_map(|(draft_txid, _)| labels.get(&draft_txid.to_string()))
+    {
+        let mut items = HashMap::new();
+        items.insert(LabelItem::Txid(txid), Some(label.clone()));
+        db_conn.update_labels(&items);
+    }
+}
+
+// The result of a Spend creation which lacked `missing` sats to be funded by these candidates.
+fn insufficient_funds(candidates: &[CandidateCoin], missing: u64) -> CreateSpendResult {
+    let available: u64 = candidates.iter().map(|c| c.amount.to_sat()).sum();
+    CreateSpendResult::InsufficientFunds {
+        missing,
+        available,
+        needed: available.saturating_add(missing),
+    }
+}
+
+// A score from 0 to 100 for how much spending these inputs together reveals about the wallet:
+// 100 if they all pay to the same address, decreasing as more distinct addresses are combined.
+fn privacy_score(psbt: &Psbt, candidates: &[CandidateCoin]) -> u8 {
+    let addresses: HashSet<_> = psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .filter_map(|txin| {
+            candidates
+                .iter()
+                .find(|c| c.outpoint == txin.previous_output)
+                .map(|c| (c.is_change, c.deriv_index))
+        })
+        .collect();
+    (100 / addresses.len().max(1)) as u8
+}
+
+// Try to fund the transaction using only the coins paying to a single address, starting with the
+// address whose coins have the smallest total value. Returns `None` if no single address can fund
+// the transaction.
+fn single_address_spend<F>(
+    spend_with: &mut F,
+    candidates: &[CandidateCoin],
+) -> Option<Result<CreateSpendRes, SpendCreationError>>
+where
+    F: FnMut(&[CandidateCoin]) -> Result<CreateSpendRes, SpendCreationError>,
+{
+    address_clusters(candidates)
+        .into_iter()
+        .find_map(|cluster| match spend_with(&cluster) {
+            Err(SpendCreationError::CoinSelection(_))
+            | Err(SpendCreationError::TooManyInputsRequired(_)) => None,
+            res => Some(res),
+        })
+}
+
+// Score each of these transactions on how cheap it is, how much privacy it preserves and how many
+// coins it consolidates, and return the one with the highest score given these weights. On a tie
+// the first transaction is preferred.
+fn best_selection(
+    options: Vec<CreateSpendRes>,
+    candidates: &[CandidateCoin],
+    weights: &SelectionWeights,
+) -> (CreateSpendRes, SelectionScore) {
+    let fee = |res: &CreateSpendRes| {
+        res.psbt
+            .fee()
+            .expect("We created this PSBT, it has all the spent utxos")
+            .to_sat()
+    };
+    let min_fee = options.iter().map(fee).min().expect("At least one option");
+    let max_inputs = options
+        .iter()
+        .map(|res| res.psbt.unsigned_tx.input.len())
+        .max()
+        .expect("At least one option");
+    options
+        .into_iter()
+        .map(|res| {
+            let fee_score = (min_fee * 100 / fee(&res).max(1)) as u8;
+            let privacy = privacy_score(&res.psbt, candidates);
+            let consolidation = (res.psbt.unsigned_tx.input.len() * 100 / max_inputs.max(1)) as u8;
+            let total = weights.fee_weight * u32::from(fee_score)
+                + weights.privacy_weight * u32::from(privacy)
+                + weights.consolidation_weight * u32::from(consolidation);
+            let score = SelectionScore {
+                fee: fee_score,
+                privacy,
+                consolidation,
+                total,
+            };
+            (res, score)
+        })
+        .rev()
+        .max_by_key(|(_, score)| score.total)
+        .expect("At least one option")
+}
+
 impl DaemonControl {
     // Get the derived descriptor for this coin
     fn derived_desc(&self, coin: &Coin) -> descriptors::DerivedSinglePathLianaDesc {
@@ -204,6 +1048,82 @@ impl DaemonControl {
         desc.derive(coin.derivation_index, &self.secp)
     }
 
+    // Check the signatures of the PSBT inputs spending our coins were all made by one of the keys
+    // of our descriptor, as derived for the coin spent. Other inputs are not checked.
+    fn check_psbt_signers(
+        &self,
+        psbt: &Psbt,
+        coins: &HashMap<bitcoin::OutPoint, Coin>,
+    ) -> Result<(), CommandError> {
+        for (txin, psbtin) in psbt.unsigned_tx.input.iter().zip(psbt.inputs.iter()) {
+            let coin = match coins.get(&txin.previous_output) {
+                Some(coin) => coin,
+                None => continue,
+            };
+            let keys = self.derived_desc(coin).keys_origins();
+            for pk in psbtin.partial_sigs.keys() {
+                if !keys.contains_key(&pk.inner) {
+                    let origin = psbtin.bip32_derivation.get(&pk.inner);
+                    return Err(CommandError::UnexpectedSigner(origin.map(|(fg, _)| *fg)));
+                }
+            }
+            let xonly_keys: HashSet<_> = keys.keys().map(|pk| pk.x_only_public_key().0).collect();
+            let tap_signers = psbtin
+                .tap_script_sigs
+                .keys()
+                .map(|(pk, _)| *pk)
+                .chain(psbtin.tap_key_sig.and(psbtin.tap_internal_key));
+            for pk in tap_signers {
+                if !xonly_keys.contains(&pk) {
+                    let origin = psbtin.tap_key_origins.get(&pk);
+                    return Err(CommandError::UnexpectedSigner(
+                        origin.map(|(_, (fg, _))| *fg),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Filter these coins to only keep those whose outpoint or address is labelled with this
+    // label.
+    fn coins_with_label<T>(
+        &self,
+        db_conn: &mut Box<dyn DatabaseConnection>,
+        coins: Vec<(Coin, T)>,
+        label: &str,
+    ) -> Vec<(Coin, T)> {
+        let network = self.config.bitcoin_config.network;
+        let coins_items: Vec<_> = coins
+            .into_iter()
+            .map(|(coin, data)| {
+                let address = self.derived_desc(&coin).address(network);
+                (coin, data, address)
+            })
+            .collect();
+        let items: HashSet<LabelItem> = coins_items
+            .iter()
+            .flat_map(|(coin, _, address)| {
+                [
+                    LabelItem::OutPoint(coin.outpoint),
+                    LabelItem::Address(address.clone()),
+                ]
+            })
+            .collect();
+        let labels = db_conn.labels(&items);
+        let has_label = |item: String| labels.get(&item).map(|l| l == label).unwrap_or(false);
+        coins_items
+            .into_iter()
+            .filter_map(|(coin, data, address)| {
+                if has_label(coin.outpoint.to_string()) || has_label(address.to_string()) {
+                    Some((coin, data))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
     // Check whether this address is valid for the network we are operating on.
     fn validate_address(
         &self,
@@ -215,6 +1135,41 @@ impl DaemonControl {
             .map_err(CommandError::Address)
     }
 
+    // The locktime to set on a new transaction to discourage fee sniping, as Bitcoin Core does: the
+    // current block height or, one time out of ten, a random height up to 99 blocks before it so
+    // that transactions which were delayed don't stand out. No locktime is set if this is
+    // disabled or if our tip is too old, as we may not be synced.
+    fn anti_fee_sniping_locktime(&self) -> Result<absolute::LockTime, CommandError> {
+        if !self.config.anti_fee_sniping {
+            return Ok(absolute::LockTime::ZERO);
+        }
+        let now = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        match self.bitcoin.tip_time() {
+            Some(tip_time)
+                if now.saturating_sub(tip_time.into())
+                    <= MAX_ANTI_FEE_SNIPING_TIP_AGE.as_secs() => {}
+            _ => return Ok(absolute::LockTime::ZERO),
+        }
+        let rand = random::random_bytes().map_err(|e| CommandError::Randomness(e.to_string()))?;
+        let mut height = u32::try_from(self.bitcoin.chain_tip().height).unwrap_or(0);
+        if rand[0] % 10 == 0 {
+            height = height.saturating_sub(u32::from(u16::from_le_bytes([rand[1], rand[2]]) % 100));
+        }
+        Ok(absolute::LockTime::from_height(height).unwrap_or(absolute::LockTime::ZERO))
+    }
+
+    // The feerate at which coin selection assumes a change output will be spent in the future.
+    fn long_term_feerate(&self) -> Feerate {
+        Feerate::from_sat_per_vb(
+            self.config
+                .long_term_feerate_vb
+                .unwrap_or(LONG_TERM_FEERATE_VB),
+        )
+    }
+
     // Get details about this address, if we know about it.
     fn addr_info(
         &self,
@@ -226,6 +1181,74 @@ impl DaemonControl {
             .map(|(index, is_change)| AddrInfo { index, is_change })
     }
 
+    // Get the details of these wallet transactions from the Bitcoin backend. The transactions it
+    // doesn't know about are skipped. A replacement is only reported for unconfirmed transactions,
+    // as a confirmed transaction wasn't replaced whatever we broadcast afterward.
+    fn transactions_info(
+        &self,
+        db_conn: &mut Box<dyn DatabaseConnection>,
+        txids: &[bitcoin::Txid],
+    ) -> Vec<TransactionInfo> {
+        let replacements = db_conn.replaced_by(txids);
+        txids
+            .iter()
+            .filter_map(|txid| {
+                // TODO: batch those calls to the Bitcoin backend
+                // so it can in turn optimize its queries.
+                self.bitcoin
+                    .wallet_transaction(txid)
+                    .map(|(tx, block)| TransactionInfo {
+                        recipient_labels: self.recipient_labels(db_conn, &tx),
+                        tx,
+                        height: block.map(|b| b.height),
+                        time: block.map(|b| b.time),
+                        replaced_by: replacements.get(txid).copied().filter(|_| block.is_none()),
+                    })
+            })
+            .collect()
+    }
+
+    // Get the coins currently reserved by a Spend transaction draft, forgetting about the expired
+    // reservations.
+    fn reserved_coins(&self) -> HashSet<bitcoin::OutPoint> {
+        let mut reservations = self.coin_reservations.lock().unwrap();
+        reservations.retain(|_, (reserved_at, _)| reserved_at.elapsed() < COIN_RESERVATION_TTL);
+        reservations.keys().copied().collect()
+    }
+
+    // Release the coins reserved by this Spend transaction draft, if any.
+    fn release_coins(&self, draft_id: &DraftId) {
+        self.coin_reservations
+            .lock()
+            .unwrap()
+            .retain(|_, (_, id)| id != draft_id);
+    }
+
+    // Get the address book labels of the external addresses paid by this transaction.
+    fn recipient_labels(
+        &self,
+        db_conn: &mut Box<dyn DatabaseConnection>,
+        tx: &bitcoin::Transaction,
+    ) -> HashMap<String, String> {
+        let items: HashSet<LabelItem> = tx
+            .output
+            .iter()
+            .filter_map(|txo| {
+                bitcoin::Address::from_script(
+                    &txo.script_pubkey,
+                    self.config.bitcoin_config.network,
+                )
+                .ok()
+            })
+            .filter(|addr| self.addr_info(db_conn, addr).is_none())
+            .map(LabelItem::Address)
+            .collect();
+        if items.is_empty() {
+            return HashMap::new();
+        }
+        db_conn.labels(&items)
+    }
+
     // Create an address to be used in an output of a spend transaction.
     fn spend_addr(
         &self,
@@ -240,7 +1263,23 @@ impl DaemonControl {
 
     // Get the change address for the next derivation index.
     fn next_change_addr(&self, db_conn: &mut Box<dyn DatabaseConnection>) -> SpendOutputAddress {
-        let index = db_conn.change_index();
+        self.change_addr(db_conn.change_index())
+    }
+
+    // Get the change address at the given derivation path relative to our change descriptor. It
+    // must be a single unhardened index.
+    fn change_addr_at_path(
+        &self,
+        path: &bip32::DerivationPath,
+    ) -> Result<SpendOutputAddress, CommandError> {
+        match path.as_ref() {
+            [index @ bip32::ChildNumber::Normal { .. }] => Ok(self.change_addr(*index)),
+            _ => Err(CommandError::InvalidChangePath(path.clone())),
+        }
+    }
+
+    // Get the change address for the given derivation index.
+    fn change_addr(&self, index: bip32::ChildNumber) -> SpendOutputAddress {
         let desc = self
             .config
             .main_descriptor
@@ -288,6 +1327,19 @@ impl DaemonControl {
         let rescan_progress = db_conn
             .rescan_timestamp()
             .map(|_| self.bitcoin.rescan_progress().unwrap_or(1.0));
+        // Only the shortest timelock matters, as it's the first to expire for any coin.
+        let blocks_until_next_recovery =
+            self.recovery_timelocks()
+                .into_iter()
+                .min()
+                .and_then(|timelock| {
+                    db_conn
+                        .coins(&[CoinStatus::Confirmed], &[])
+                        .values()
+                        .filter(|c| !c.is_immature)
+                        .filter_map(|c| blocks_before_recovery(c, block_height, timelock))
+                        .min()
+                });
         GetInfoResult {
             version: VERSION.to_string(),
             network: self.config.bitcoin_config.network,
@@ -296,28 +1348,468 @@ impl DaemonControl {
             descriptors: GetInfoDescriptors {
                 main: self.config.main_descriptor.clone(),
             },
+            wallet_id: self.config.main_descriptor.wallet_id(),
             rescan_progress,
             timestamp: db_conn.timestamp(),
+            backend: self.bitcoin.backend_info(),
+            max_spendable: self.max_spendable(&mut db_conn),
+            recovery_available: blocks_until_next_recovery == Some(0),
+            blocks_until_next_recovery,
         }
     }
 
-    /// Get a new deposit address. This will always generate a new deposit address, regardless of
-    /// whether it was actually used.
-    pub fn get_new_address(&self) -> GetAddressResult {
-        let mut db_conn = self.db.connection();
-        let index = db_conn.receive_index();
-        let new_index = index
-            .increment()
-            .expect("Can't get into hardened territory");
-        db_conn.set_receive_index(new_index, &self.secp);
-        let address = self
-            .config
-            .main_descriptor
-            .receive_descriptor()
-            .derive(index, &self.secp)
-            .address(self.config.bitcoin_config.network);
-        GetAddressResult::new(address, index)
-    }
+    /// Tear down the connection to the Bitcoin backend and establish a new one, for instance
+    /// after it was restarted, then update our state from it without waiting for the next poll.
+    pub fn reconnect_backend(&self) -> Result<(), CommandError> {
+        self.check_unlocked(false)?;
+        self.bitcoin
+            .lock()
+            .unwrap()
+            .reconnect()
+            .map_err(CommandError::BackendConnection)?;
+
+        let (tx, rx) = mpsc::sync_channel(0);
+        if let Err(e) = self.poller_sender.send(PollerMessage::PollNow(tx)) {
+            log::error!("Error requesting update from poller: {}", e);
+        }
+        if let Err(e) = rx.recv() {
+            log::error!("Error receiving completion signal from poller: {}", e);
+        }
+
+        Ok(())
+    }
+
+    // An estimate of the largest amount we could send to a single recipient using the coins that
+    // automatic coin selection would consider by default, at the feerate estimated for a
+    // confirmation within a few blocks. Coins worth less than the fee to spend them are left out.
+    fn max_spendable(&self, db_conn: &mut Box<dyn DatabaseConnection>) -> bitcoin::Amount {
+        let feerate_vb = feerate_estimate(
+            &self.bitcoin,
+            &self.config.fee_sources,
+            MAX_SPENDABLE_CONF_TARGET,
+        )
+        .feerate;
+        let input_fee = self.config.main_descriptor.spender_input_size() as u64 * feerate_vb;
+        let current_height = self.bitcoin.chain_tip().height;
+        let min_confs = self.config.spendable_min_confirmations;
+        let coins: Vec<_> = db_conn
+            .coins(&[CoinStatus::Unconfirmed, CoinStatus::Confirmed], &[])
+            .into_values()
+            .filter(|c| {
+                let is_spendable =
+                    c.is_confirmed() && has_min_confirmations(c, current_height, min_confs);
+                !c.is_immature && (is_spendable || c.is_change) && c.amount.to_sat() > input_fee
+            })
+            .collect();
+        if coins.is_empty() {
+            return bitcoin::Amount::ZERO;
+        }
+
+        // The recipient's output is assumed to be of the same type as ours.
+        let tx = bitcoin::Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: coins
+                .iter()
+                .map(|c| bitcoin::TxIn {
+                    previous_output: c.outpoint,
+                    ..bitcoin::TxIn::default()
+                })
+                .collect(),
+            output: vec![bitcoin::TxOut {
+                value: bitcoin::Amount::ZERO,
+                script_pubkey: self
+                    .config
+                    .main_descriptor
+                    .receive_descriptor()
+                    .derive(0.into(), &self.secp)
+                    .script_pubkey(),
+            }],
+        };
+        let fee = bitcoin::Amount::from_sat(
+            self.config.main_descriptor.unsigned_tx_max_vbytes(&tx) * feerate_vb,
+        );
+        coins
+            .iter()
+            .map(|c| c.amount)
+            .sum::<bitcoin::Amount>()
+            .checked_sub(fee)
+            .filter(|amount| amount.to_sat() >= DUST_OUTPUT_SATS)
+            .unwrap_or(bitcoin::Amount::ZERO)
+    }
+
+    /// Get a summary of the state of the wallet, as displayed on a dashboard. All the figures are
+    /// computed from the same database connection.
+    pub fn dashboard(&self) -> DashboardSummary {
+        let mut db_conn = self.db.connection();
+
+        let block_height = db_conn.chain_tip().map(|tip| tip.height).unwrap_or(0);
+        let rescan_progress = db_conn
+            .rescan_timestamp()
+            .map(|_| self.bitcoin.rescan_progress().unwrap_or(1.0));
+        let coins = db_conn.coins(
+            &[
+                CoinStatus::Unconfirmed,
+                CoinStatus::Confirmed,
+                CoinStatus::Spending,
+            ],
+            &[],
+        );
+
+        let (mut balance, mut unconfirmed_balance) = (bitcoin::Amount::ZERO, bitcoin::Amount::ZERO);
+        let (mut coin_count, mut pending_count) = (0, 0);
+        for coin in coins.values() {
+            if !coin.is_confirmed() || coin.is_spent() {
+                pending_count += 1;
+            }
+            if coin.is_spent() {
+                continue;
+            }
+            coin_count += 1;
+            if coin.is_confirmed() {
+                balance += coin.amount;
+            } else {
+                unconfirmed_balance += coin.amount;
+            }
+        }
+
+        // The first recovery path to become available is the one with the lowest timelock, on
+        // the oldest unspent coin.
+        let next_recovery_height = self.recovery_timelocks().into_iter().min().and_then(|tl| {
+            coins
+                .values()
+                .filter(|c| !c.is_spent())
+                .filter_map(|c| c.block_info.map(|b| b.height + i32::from(tl)))
+                .min()
+        });
+
+        DashboardSummary {
+            balance,
+            unconfirmed_balance,
+            coin_count,
+            pending_count,
+            next_recovery_height,
+            block_height,
+            sync: self.bitcoin.sync_progress().rounded_up_progress(),
+            rescan_progress,
+        }
+    }
+
+    /// Get the balance of the wallet, considering coins with at least `min_confirmations` as
+    /// confirmed. Coins being spent are not part of it, and coins reserved by a Spend transaction
+    /// draft or immature are accounted for separately.
+    pub fn balance(&self, min_confirmations: u32) -> BalanceBreakdown {
+        let mut db_conn = self.db.connection();
+        let current_height = db_conn.chain_tip().map(|tip| tip.height).unwrap_or(0);
+        let reserved_coins = self.reserved_coins();
+        let coins = db_conn.coins(&[CoinStatus::Unconfirmed, CoinStatus::Confirmed], &[]);
+
+        let mut breakdown = BalanceBreakdown {
+            min_confirmations,
+            confirmed: bitcoin::Amount::ZERO,
+            unconfirmed: bitcoin::Amount::ZERO,
+            immature: bitcoin::Amount::ZERO,
+            reserved: bitcoin::Amount::ZERO,
+        };
+        for (op, coin) in coins {
+            let total = if coin.is_immature {
+                &mut breakdown.immature
+            } else if reserved_coins.contains(&op) {
+                &mut breakdown.reserved
+            } else if has_min_confirmations(&coin, current_height, min_confirmations) {
+                &mut breakdown.confirmed
+            } else {
+                &mut breakdown.unconfirmed
+            };
+            *total += coin.amount;
+        }
+        breakdown
+    }
+
+    /// Get the requested parts of the wallet state at once. The poller is held for the duration
+    /// of the call, so the parts are consistent with each other: they all reflect the same chain
+    /// tip and the same set of coins.
+    pub fn snapshot(&self, parts: &SnapshotParts) -> Result<WalletSnapshot, CommandError> {
+        // The poller is released once `_release` is dropped, at the end of this function. Wait
+        // for it to be paused before reading anything, so we don't read in the middle of a poll.
+        let (_release, hold) = mpsc::channel();
+        let (ack_sender, ack) = mpsc::sync_channel(0);
+        match self
+            .poller_sender
+            .send(PollerMessage::Hold(ack_sender, hold))
+        {
+            Ok(()) => {
+                if let Err(e) = ack.recv() {
+                    log::error!("Error receiving hold acknowledgement from poller: {}", e);
+                }
+            }
+            Err(e) => log::error!("Error holding the poller: {}", e),
+        }
+
+        Ok(WalletSnapshot {
+            info: parts.info.then(|| self.get_info()),
+            coins: parts.coins.then(|| self.list_coins(&[], &[])),
+            feerate_estimates: parts
+                .feerate_estimates
+                .then(|| self.get_feerate_estimates()),
+            spend_txs: parts
+                .spend_txs
+                .then(|| self.list_spend(None, &[]))
+                .transpose()?,
+        })
+    }
+
+    /// Get the changes to our coins between two snapshots taken with [`DaemonControl::snapshot`],
+    /// `a` being the older one. Both must include the coins.
+    /// The coins in `b` which aren't in `a` were added. The coins spent in `b` which weren't in
+    /// `a` were spent, including those added in between. The coins whose deposit or spend in `a`
+    /// was undone in `b` were reorged: they were confirmed in `a` and are unconfirmed or confirmed
+    /// at another height in `b`, their spending transaction in `a` is gone or confirmed at another
+    /// height in `b`, or they were unspent in `a` and aren't in `b` anymore. They are listed as
+    /// they were in `a`.
+    pub fn diff_snapshots(
+        &self,
+        a: &WalletSnapshot,
+        b: &WalletSnapshot,
+    ) -> Result<SnapshotDiff, CommandError> {
+        let (coins_a, coins_b) = match (&a.coins, &b.coins) {
+            (Some(coins_a), Some(coins_b)) => (&coins_a.coins, &coins_b.coins),
+            _ => return Err(CommandError::SnapshotWithoutCoins),
+        };
+        let by_outpoint = |coins: &[ListCoinsEntry]| -> HashMap<bitcoin::OutPoint, ListCoinsEntry> {
+            coins.iter().map(|c| (c.outpoint, c.clone())).collect()
+        };
+        let (prev, next) = (by_outpoint(coins_a), by_outpoint(coins_b));
+
+        let mut diff = SnapshotDiff::default();
+        for coin in coins_b {
+            let prev_coin = prev.get(&coin.outpoint);
+            if prev_coin.is_none() {
+                diff.added.push(coin.clone());
+            }
+            if coin.spend_info.is_some() && prev_coin.map(|c| c.spend_info.is_none()) != Some(false)
+            {
+                diff.spent.push(coin.clone());
+            }
+        }
+        for coin in coins_a {
+            let reorged = match next.get(&coin.outpoint) {
+                // A spent coin may have been pruned.
+                None => coin.spend_info.is_none(),
+                Some(next_coin) => {
+                    let deposit_undone =
+                        coin.block_height.is_some() && coin.block_height != next_coin.block_height;
+                    let spend_undone = match (&coin.spend_info, &next_coin.spend_info) {
+                        (Some(spend), Some(next_spend)) => {
+                            spend.txid != next_spend.txid
+                                || (spend.height.is_some() && spend.height != next_spend.height)
+                        }
+                        (Some(_), None) => true,
+                        (None, _) => false,
+                    };
+                    deposit_undone || spend_undone
+                }
+            };
+            if reorged {
+                diff.reorged.push(coin.clone());
+            }
+        }
+        Ok(diff)
+    }
+
+    /// Get the origin of all the keys in our descriptor, grouped by spending path. The primary
+    /// path comes first, followed by the recovery paths by increasing timelock.
+    pub fn descriptor_keys(&self) -> DescriptorKeysResult {
+        let policy = self.config.main_descriptor.policy();
+        let path_keys = |timelock: Option<u16>, path_info: &descriptors::PathInfo| {
+            let (threshold, keys) = path_info.thresh_keys();
+            PathKeys {
+                timelock,
+                threshold,
+                keys: keys.into_iter().map(key_origin).collect(),
+            }
+        };
+        let paths = std::iter::once(path_keys(None, policy.primary_path()))
+            .chain(
+                policy
+                    .recovery_paths()
+                    .iter()
+                    .map(|(tl, path_info)| path_keys(Some(*tl), path_info)),
+            )
+            .collect();
+        DescriptorKeysResult { paths }
+    }
+
+    /// Describe the spending conditions of our descriptor in plain words, for each spending path
+    /// and as a whole. Keys are designated by the fingerprint of their master extended key.
+    pub fn describe_policy(&self) -> PolicyDescription {
+        let policy = self.config.main_descriptor.policy();
+        let mut paths = vec![PathDescription::new(None, policy.primary_path())];
+        paths.extend(
+            policy
+                .recovery_paths()
+                .iter()
+                .map(|(tl, path_info)| PathDescription::new(Some(*tl), path_info)),
+        );
+        let description = paths
+            .iter()
+            .map(|path| format!("{}.", path.description))
+            .collect::<Vec<_>>()
+            .join(" ");
+        PolicyDescription { paths, description }
+    }
+
+    /// Change the level of the messages logged from now on. This is not persisted: the level from
+    /// the configuration is used again on restart.
+    pub fn set_log_level(&self, level: log::LevelFilter) -> Result<(), CommandError> {
+        self.check_unlocked(false)?;
+        log::set_max_level(level);
+        log::info!("Log level set to '{}'.", level);
+        Ok(())
+    }
+
+    /// Get feerate estimates for a few confirmation targets. The configured fee sources are tried
+    /// in order for each target, and a static fallback feerate is used if none of them succeeds.
+    ///
+    /// If caching is enabled in the configuration, the estimates are reused until they are older
+    /// than the configured duration or explicitly invalidated. Their age is returned.
+    pub fn get_feerate_estimates(&self) -> GetFeerateEstimatesResult {
+        let cache_ttl = self.config.feerate_cache_secs;
+        let mut cache = self.feerate_cache.lock().unwrap();
+        if let Some((computed_at, estimates)) = cache.as_ref() {
+            let age = computed_at.elapsed();
+            if age < cache_ttl {
+                return GetFeerateEstimatesResult {
+                    estimates: estimates.clone(),
+                    age_secs: age.as_secs(),
+                };
+            }
+        }
+
+        let estimates: Vec<_> = FEERATE_ESTIMATES_TARGETS
+            .iter()
+            .map(|target| feerate_estimate(&self.bitcoin, &self.config.fee_sources, *target))
+            .collect();
+        *cache = (!cache_ttl.is_zero()).then(|| (time::Instant::now(), estimates.clone()));
+        GetFeerateEstimatesResult {
+            estimates,
+            age_secs: 0,
+        }
+    }
+
+    /// Drop the cached feerate estimates, so the next call to
+    /// [`DaemonControl::get_feerate_estimates`] queries the fee sources again.
+    pub fn invalidate_feerate_cache(&self) {
+        *self.feerate_cache.lock().unwrap() = None;
+    }
+
+    /// Get the feerate to use for a transaction to be confirmed with this priority, from the
+    /// configured fee sources. See [`DaemonControl::get_feerate_estimates`].
+    pub fn priority_feerate(&self, priority: FeePriority) -> Feerate {
+        let estimate = feerate_estimate(
+            &self.bitcoin,
+            &self.config.fee_sources,
+            priority.conf_target(),
+        );
+        Feerate::from_sat_per_vb(estimate.feerate)
+    }
+
+    /// Get the feerate estimates of the Bitcoin backend sampled by the poller since the given
+    /// timestamp, oldest first.
+    pub fn feerate_history(&self, since: u32) -> FeerateHistoryResult {
+        let mut db_conn = self.db.connection();
+        let samples = db_conn
+            .feerate_samples(since)
+            .into_iter()
+            .map(|sample| FeerateHistoryEntry {
+                timestamp: sample.timestamp,
+                conf_target: sample.conf_target,
+                feerate: sample.feerate,
+            })
+            .collect();
+        FeerateHistoryResult { samples }
+    }
+
+    /// Get a new deposit address. This will always generate a new deposit address, regardless of
+    /// whether it was actually used.
+    pub fn get_new_address(&self) -> Result<GetAddressResult, CommandError> {
+        self.check_unlocked(false)?;
+        let mut db_conn = self.db.connection();
+        let index = db_conn.receive_index();
+        let new_index = index
+            .increment()
+            .expect("Can't get into hardened territory");
+        db_conn.set_receive_index(new_index, &self.secp);
+        let address = self
+            .config
+            .main_descriptor
+            .receive_descriptor()
+            .derive(index, &self.secp)
+            .address(self.config.bitcoin_config.network);
+        Ok(GetAddressResult::new(address, index))
+    }
+
+    /// Get a deposit address, reusing the last one handed out as long as it hasn't received any
+    /// coin. A new address is only generated if there is none yet or the last one was used.
+    pub fn get_unused_address(&self) -> Result<GetAddressResult, CommandError> {
+        self.check_unlocked(false)?;
+        match self.last_address_status() {
+            LastAddressStatus {
+                address: Some(address),
+                derivation_index: Some(index),
+                used: false,
+            } => Ok(GetAddressResult::new(address, index)),
+            _ => self.get_new_address(),
+        }
+    }
+
+    /// Get whether the last receive address we handed out has received any coin yet. There is no
+    /// such address if none was generated yet.
+    pub fn last_address_status(&self) -> LastAddressStatus {
+        let mut db_conn = self.db.connection();
+        let index: u32 = db_conn.receive_index().into();
+        let index = match index.checked_sub(1) {
+            Some(i) => bip32::ChildNumber::from(i),
+            None => {
+                return LastAddressStatus {
+                    address: None,
+                    derivation_index: None,
+                    used: false,
+                }
+            }
+        };
+        let address = self
+            .config
+            .main_descriptor
+            .receive_descriptor()
+            .derive(index, &self.secp)
+            .address(self.config.bitcoin_config.network);
+        // Spent coins count too: the address was used all the same.
+        let used = db_conn
+            .coins(&[], &[])
+            .values()
+            .any(|c| !c.is_change && c.derivation_index == index);
+        LastAddressStatus {
+            address: Some(address),
+            derivation_index: Some(index),
+            used,
+        }
+    }
+
+    /// Re-derive the addresses stored in database and report those which don't match our
+    /// descriptor, as well as the derivation indexes below our next receive or change index which
+    /// have no address stored. This is a diagnostic, the database isn't modified.
+    pub fn verify_address_consistency(&self) -> ConsistencyReport {
+        let mut db_conn = self.db.connection();
+        let end_index = db_conn.receive_index().max(db_conn.change_index()).into();
+        address_consistency(
+            &self.config.main_descriptor,
+            &self.secp,
+            self.config.bitcoin_config.network,
+            &db_conn.stored_addresses(),
+            end_index,
+        )
+    }
 
     /// list addresses
     pub fn list_addresses(
@@ -376,6 +1868,65 @@ impl DaemonControl {
         Ok(ListAddressesResult::new(addresses?))
     }
 
+    // The timelocks of the recovery paths of our descriptor, by increasing value. Empty if the
+    // descriptor has no recovery path.
+    fn recovery_timelocks(&self) -> Vec<u16> {
+        self.config
+            .main_descriptor
+            .policy()
+            .recovery_paths()
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    // Get the entry for this coin in the `listcoins` result, as of the given block height.
+    fn list_coins_entry(
+        &self,
+        coin: Coin,
+        height: i32,
+        timelocks: &[u16],
+        note: Option<String>,
+    ) -> ListCoinsEntry {
+        let spendable_paths = spendable_paths(
+            &coin,
+            height,
+            timelocks,
+            self.config.spendable_min_confirmations,
+        );
+        let address = self
+            .derived_desc(&coin)
+            .address(self.config.bitcoin_config.network);
+        let Coin {
+            amount,
+            outpoint,
+            block_info,
+            spend_txid,
+            spend_block,
+            is_immature,
+            is_change,
+            derivation_index,
+            ..
+        } = coin;
+        let spend_info = spend_txid.map(|txid| LCSpendInfo {
+            txid,
+            height: spend_block.map(|b| b.height),
+        });
+        let block_height = block_info.map(|b| b.height);
+        ListCoinsEntry {
+            address,
+            amount,
+            derivation_index,
+            outpoint,
+            block_height,
+            spend_info,
+            is_immature,
+            is_change,
+            spendable_paths,
+            note,
+        }
+    }
+
     /// Get a list of all known coins, optionally by status and/or outpoint.
     pub fn list_coins(
         &self,
@@ -383,1910 +1934,10062 @@ impl DaemonControl {
         outpoints: &[bitcoin::OutPoint],
     ) -> ListCoinsResult {
         let mut db_conn = self.db.connection();
-        let coins: Vec<ListCoinsEntry> = db_conn
-            .coins(statuses, outpoints)
+        // Use our tip rather than the one of the Bitcoin backend, for the confirmations to be
+        // consistent with the rest of our state (for instance the height reported by `getinfo`).
+        let current_height = db_conn.chain_tip().map(|tip| tip.height).unwrap_or(0);
+        let timelocks = self.recovery_timelocks();
+        let coins = db_conn.coins(statuses, outpoints);
+        let mut notes = db_conn.coin_notes(&coins.keys().copied().collect::<Vec<_>>());
+        let coins: Vec<ListCoinsEntry> = coins
             .into_values()
             .map(|coin| {
-                let Coin {
-                    amount,
-                    outpoint,
-                    block_info,
-                    spend_txid,
-                    spend_block,
-                    is_immature,
-                    is_change,
-                    derivation_index,
-                    ..
-                } = coin;
-                let spend_info = spend_txid.map(|txid| LCSpendInfo {
-                    txid,
-                    height: spend_block.map(|b| b.height),
-                });
-                let block_height = block_info.map(|b| b.height);
-                let address = self
-                    .derived_desc(&coin)
-                    .address(self.config.bitcoin_config.network);
-                ListCoinsEntry {
-                    address,
-                    amount,
-                    derivation_index,
-                    outpoint,
-                    block_height,
-                    spend_info,
-                    is_immature,
-                    is_change,
-                }
+                let note = notes.remove(&coin.outpoint);
+                self.list_coins_entry(coin, current_height, &timelocks, note)
             })
             .collect();
         ListCoinsResult { coins }
     }
 
-    pub fn create_spend(
+    /// Get the address a coin of ours pays to, along with its derivation index.
+    pub fn coin_address(
         &self,
-        destinations: &HashMap<bitcoin::Address<bitcoin::address::NetworkUnchecked>, u64>,
-        coins_outpoints: &[bitcoin::OutPoint],
-        feerate_vb: u64,
-        change_address: Option<bitcoin::Address<bitcoin::address::NetworkUnchecked>>,
-    ) -> Result<CreateSpendResult, CommandError> {
-        let is_self_send = destinations.is_empty();
-        // For self-send, the coins must be specified.
-        if is_self_send && coins_outpoints.is_empty() {
-            return Err(CommandError::NoOutpointForSelfSend);
-        }
-        if feerate_vb < 1 {
-            return Err(CommandError::InvalidFeerate(feerate_vb));
+        outpoint: &bitcoin::OutPoint,
+    ) -> Result<CoinAddressResult, CommandError> {
+        let coin = self
+            .db
+            .connection()
+            .coins_by_outpoints(&[*outpoint])
+            .remove(outpoint)
+            .ok_or(CommandError::UnknownOutpoint(*outpoint))?;
+        let address = self
+            .derived_desc(&coin)
+            .address(self.config.bitcoin_config.network);
+        Ok(CoinAddressResult {
+            address,
+            derivation_index: coin.derivation_index,
+            is_change: coin.is_change,
+        })
+    }
+
+    /// Unlock the session, given the passphrase whose hash is configured as
+    /// `session_passphrase_hash`. Unlocking an already unlocked session is not an error.
+    pub fn unlock(&self, passphrase: &str) -> Result<(), CommandError> {
+        let hash = self
+            .config
+            .session_passphrase_hash
+            .as_ref()
+            .ok_or(CommandError::NoSessionPassphrase)?;
+        if !hash.matches(passphrase) {
+            return Err(CommandError::WrongPassphrase);
         }
-        let mut db_conn = self.db.connection();
-        let mut tx_getter = BitcoindTxGetter::new(&self.bitcoin);
+        self.locked.store(false, sync::atomic::Ordering::Relaxed);
+        Ok(())
+    }
 
-        // Prepare the destination addresses.
-        let mut destinations_checked = Vec::with_capacity(destinations.len());
-        for (address, value_sat) in destinations {
-            let address = self.validate_address(address.clone())?;
-            let amount = bitcoin::Amount::from_sat(*value_sat);
-            let address = self.spend_addr(&mut db_conn, address);
-            destinations_checked.push((address, amount));
+    /// Lock the session. Until it is unlocked, the commands which may modify the wallet are
+    /// rejected, as well as the read-only ones if `lock_read_commands` is set.
+    pub fn lock(&self) -> Result<(), CommandError> {
+        if self.config.session_passphrase_hash.is_none() {
+            return Err(CommandError::NoSessionPassphrase);
         }
+        self.locked.store(true, sync::atomic::Ordering::Relaxed);
+        Ok(())
+    }
 
-        // The change address to be used if a change output needs to be created. It may be
-        // specified by the caller (for instance for the purpose of a sweep, or to avoid us
-        // creating a new change address on every call).
-        let change_address = change_address
-            .map(|addr| {
-                Ok::<_, CommandError>(self.spend_addr(&mut db_conn, self.validate_address(addr)?))
-            })
-            .transpose()?
-            .unwrap_or_else(|| self.next_change_addr(&mut db_conn));
+    /// Whether the session is currently locked.
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(sync::atomic::Ordering::Relaxed)
+    }
 
-        // The candidate coins will be either all optional or all mandatory.
-        // If no coins have been specified, then coins will be selected automatically for
-        // the spend from a set of optional candidates.
-        // Otherwise, only the specified coins will be used, all as mandatory candidates.
-        let candidate_coins: Vec<CandidateCoin> = if coins_outpoints.is_empty() {
-            // From our unconfirmed coins, we only include those that are change outputs
-            // since unconfirmed external deposits are more at risk of being dropped
-            // unexpectedly from the mempool as they are beyond the user's control.
-            db_conn
-                .coins(&[CoinStatus::Unconfirmed, CoinStatus::Confirmed], &[])
-                .into_iter()
-                .filter_map(|(op, c)| {
-                    if c.block_info.is_some() {
-                        Some((c, None)) // confirmed coins have no ancestor info
-                    } else if c.is_change && !c.is_immature {
-                        // In case the mempool_entry is None, the coin will be included without
-                        // any ancestor info.
-                        Some((
-                            c,
-                            self.bitcoin.mempool_entry(&op.txid).map(AncestorInfo::from),
-                        ))
-                    } else {
-                        None
-                    }
-                })
-                .map(|(c, ancestor_info)| {
-                    coin_to_candidate(
-                        &c,
-                        /*must_select=*/ false,
-                        /*sequence=*/ None,
-                        ancestor_info,
-                    )
-                })
-                .collect()
-        } else {
-            // Query from DB and sanity check the provided coins to spend.
-            let coins = db_conn.coins(&[], coins_outpoints);
-            for op in coins_outpoints {
-                let coin = coins.get(op).ok_or(CommandError::UnknownOutpoint(*op))?;
-                if coin.is_spent() {
-                    return Err(CommandError::AlreadySpent(*op));
-                }
-                if coin.is_immature {
-                    return Err(CommandError::ImmatureCoinbase(*op));
-                }
+    /// Check whether a command may be used in the current state of the session. Returns
+    /// [`CommandError::Locked`] if the session is locked, unless the command is read-only and
+    /// read-only commands are allowed while locked.
+    ///
+    /// The methods which may modify the wallet perform this check themselves. The read-only ones
+    /// don't, it's up to the caller to enforce `lock_read_commands`.
+    pub fn check_unlocked(&self, read_only: bool) -> Result<(), CommandError> {
+        if self.is_locked() && (!read_only || self.config.lock_read_commands) {
+            return Err(CommandError::Locked);
+        }
+        Ok(())
+    }
+
+    /// Get the raw transaction which created a coin of ours, along with the height and time of the
+    /// block it was confirmed in, if any. Transactions are cached once fetched from the backend.
+    pub fn coin_funding_tx(&self, outpoint: &bitcoin::OutPoint) -> Result<RawTxInfo, CommandError> {
+        let coin = self
+            .db
+            .connection()
+            .coins_by_outpoints(&[*outpoint])
+            .remove(outpoint)
+            .ok_or(CommandError::UnknownOutpoint(*outpoint))?;
+        let txid = outpoint.txid;
+        let cached_tx = self.funding_txs.lock().unwrap().get(&txid).cloned();
+        let tx = match cached_tx {
+            Some(tx) => tx,
+            None => {
+                let (tx, _) = self
+                    .bitcoin
+                    .wallet_transaction(&txid)
+                    .ok_or(CommandError::FundingTxUnavailable(txid))?;
+                self.funding_txs.lock().unwrap().insert(txid, tx.clone());
+                tx
             }
-            coins
-                .into_iter()
-                .map(|(op, c)| {
-                    let ancestor_info = if c.block_info.is_none() {
-                        // We include any non-change coins here as they have been selected by the caller.
-                        // If the unconfirmed coin's transaction is no longer in the mempool, keep the
-                        // coin as a candidate but without any ancestor info (same as confirmed candidate).
-                        self.bitcoin.mempool_entry(&op.txid).map(AncestorInfo::from)
-                    } else {
-                        None
-                    };
-                    coin_to_candidate(
-                        &c,
-                        /*must_select=*/ true,
-                        /*sequence=*/ None,
-                        ancestor_info,
-                    )
-                })
-                .collect()
         };
+        Ok(RawTxInfo {
+            tx,
+            height: coin.block_info.map(|b| b.height),
+            time: coin.block_info.map(|b| b.time),
+        })
+    }
 
-        // Create the PSBT. If there was no error in doing so make sure to update our next
-        // derivation index in case any address in the transaction outputs was ours and from the
-        // future.
-        let change_info = change_address.info;
-        let CreateSpendRes {
-            psbt,
-            has_change,
-            warnings,
-        } = match create_spend(
-            &self.config.main_descriptor,
-            &self.secp,
-            &mut tx_getter,
-            &destinations_checked,
-            &candidate_coins,
-            SpendTxFees::Regular(feerate_vb),
-            change_address,
-        ) {
-            Ok(res) => res,
-            Err(SpendCreationError::CoinSelection(e)) => {
-                return Ok(CreateSpendResult::InsufficientFunds { missing: e.missing });
-            }
-            Err(e) => {
-                return Err(e.into());
-            }
-        };
-        for (addr, _) in destinations_checked {
-            self.maybe_increase_next_deriv_index(&mut db_conn, &addr.info);
-        }
-        if has_change {
-            self.maybe_increase_next_deriv_index(&mut db_conn, &change_info);
-        }
-
-        Ok(CreateSpendResult::Success {
-            psbt,
-            warnings: warnings.iter().map(|w| w.to_string()).collect(),
-        })
-    }
-
-    pub fn update_spend(&self, mut psbt: Psbt) -> Result<(), CommandError> {
+    /// List the unconfirmed coins we used to have whose deposit transaction was double spent by a
+    /// confirmed transaction, in the order they were dropped. Those are not part of our coins
+    /// anymore.
+    pub fn invalidated_coins(&self) -> InvalidatedCoinsResult {
+        let network = self.config.bitcoin_config.network;
         let mut db_conn = self.db.connection();
-        let tx = &psbt.unsigned_tx;
-
-        // If the transaction already exists in DB, merge the signatures for each input on a best
-        // effort basis.
-        // We work on the newly provided PSBT, in case its content was updated.
-        let txid = tx.txid();
-        if let Some(db_psbt) = db_conn.spend_tx(&txid) {
-            let db_tx = db_psbt.unsigned_tx;
-            for i in 0..db_tx.input.len() {
-                if tx
-                    .input
-                    .get(i)
-                    .map(|tx_in| tx_in.previous_output == db_tx.input[i].previous_output)
-                    != Some(true)
-                {
-                    continue;
-                }
-                let psbtin = match psbt.inputs.get_mut(i) {
-                    Some(psbtin) => psbtin,
-                    None => continue,
-                };
-                let db_psbtin = match db_psbt.inputs.get(i) {
-                    Some(db_psbtin) => db_psbtin,
-                    None => continue,
+        let dropped_coins = db_conn.dropped_coins();
+        // The deposit transaction of a dropped coin may have come back since.
+        let outpoints: Vec<_> = dropped_coins.iter().map(|c| c.outpoint).collect();
+        let current_coins = db_conn.coins(&[], &outpoints);
+        let coins = dropped_coins
+            .into_iter()
+            .filter(|coin| !current_coins.contains_key(&coin.outpoint))
+            .filter_map(|coin| {
+                let invalidated_at = coin.invalidated_at?;
+                let desc = if coin.is_change {
+                    self.config.main_descriptor.change_descriptor()
+                } else {
+                    self.config.main_descriptor.receive_descriptor()
                 };
-                psbtin
-                    .partial_sigs
-                    .extend(db_psbtin.partial_sigs.clone().into_iter());
-                psbtin
-                    .tap_script_sigs
-                    .extend(db_psbtin.tap_script_sigs.clone().into_iter());
-                if psbtin.tap_key_sig.is_none() {
-                    psbtin.tap_key_sig = db_psbtin.tap_key_sig;
-                }
-            }
-        } else {
-            // If the transaction doesn't exist in DB already, sanity check its inputs.
-            // FIXME: should we allow for external inputs?
-            let outpoints: Vec<bitcoin::OutPoint> =
-                tx.input.iter().map(|txin| txin.previous_output).collect();
-            let coins = db_conn.coins_by_outpoints(&outpoints);
-            if coins.len() != outpoints.len() {
-                for op in outpoints {
-                    if coins.get(&op).is_none() {
-                        return Err(CommandError::UnknownOutpoint(op));
-                    }
-                }
-            }
-        }
-
-        // Finally, insert (or update) the PSBT in database.
-        db_conn.store_spend(&psbt);
-
-        Ok(())
+                let address = desc
+                    .derive(coin.derivation_index, &self.secp)
+                    .address(network);
+                Some(InvalidatedCoinEntry {
+                    amount: coin.amount,
+                    outpoint: coin.outpoint,
+                    address,
+                    derivation_index: coin.derivation_index,
+                    is_change: coin.is_change,
+                    invalidated_at,
+                })
+            })
+            .collect();
+        InvalidatedCoinsResult { coins }
     }
 
-    pub fn update_labels(&self, items: &HashMap<LabelItem, Option<String>>) {
+    /// List the coins which were unspent as of the block at the given height. The height must be
+    /// within the range of blocks scanned by the wallet.
+    pub fn list_coins_at_height(&self, height: i32) -> Result<ListCoinsResult, CommandError> {
         let mut db_conn = self.db.connection();
-        db_conn.update_labels(items);
+        // We only know about the coins between the block at which the wallet started scanning the
+        // chain and our current tip.
+        let tip_height = db_conn.chain_tip().map(|tip| tip.height);
+        let scan_start_height = self
+            .bitcoin
+            .block_before_date(db_conn.timestamp())
+            .map(|block| block.height);
+        match (scan_start_height, tip_height) {
+            (Some(start), Some(tip)) if height >= start && height <= tip => {}
+            _ => return Err(CommandError::HeightNotScanned(height)),
+        }
+
+        let timelocks = self.recovery_timelocks();
+        let mut notes = db_conn.coin_notes(&[]);
+        let coins: Vec<ListCoinsEntry> = db_conn
+            .coins(&[], &[])
+            .into_values()
+            .filter(|coin| {
+                let confirmed = coin.block_info.map(|b| b.height <= height).unwrap_or(false);
+                let spent = coin
+                    .spend_block
+                    .map(|b| b.height <= height)
+                    .unwrap_or(false);
+                confirmed && !spent
+            })
+            .map(|mut coin| {
+                // The coin was not spent yet at this height.
+                coin.spend_txid = None;
+                coin.spend_block = None;
+                let note = notes.remove(&coin.outpoint);
+                self.list_coins_entry(coin, height, &timelocks, note)
+            })
+            .collect();
+        Ok(ListCoinsResult { coins })
     }
 
-    pub fn get_labels(&self, items: &HashSet<LabelItem>) -> GetLabelsResult {
-        let mut db_conn = self.db.connection();
-        GetLabelsResult {
-            labels: db_conn.labels(items),
+    /// List our coins with the given statuses (all if empty) grouped by the address they were
+    /// received on, along with the total value of each group. Addresses which received the most
+    /// coins come first, so that address reuse is easy to spot.
+    pub fn list_coins_by_address(&self, statuses: &[CoinStatus]) -> CoinsByAddressResult {
+        let coins = self.list_coins(statuses, &[]).coins;
+        let mut groups: HashMap<bitcoin::Address, Vec<ListCoinsEntry>> = HashMap::new();
+        for coin in coins {
+            groups.entry(coin.address.clone()).or_default().push(coin);
         }
+        let mut addresses: Vec<AddressCoins> = groups
+            .into_iter()
+            .map(|(address, mut coins)| {
+                coins.sort_by_key(|c| c.outpoint);
+                AddressCoins {
+                    derivation_index: coins[0].derivation_index,
+                    is_change: coins[0].is_change,
+                    total: coins.iter().map(|c| c.amount).sum(),
+                    address,
+                    coins,
+                }
+            })
+            .collect();
+        addresses.sort_by(|a, b| {
+            b.coins
+                .len()
+                .cmp(&a.coins.len())
+                .then(b.total.cmp(&a.total))
+                .then(a.address.to_string().cmp(&b.address.to_string()))
+        });
+        CoinsByAddressResult { addresses }
     }
 
-    pub fn list_spend(
+    /// Check whether this address of ours received at least `expected` in total, for instance to
+    /// confirm a payment requested from a customer. The address may have been paid in several
+    /// times: the payment is considered to be as deep as the least confirmed of the coins needed
+    /// to reach the expected amount, using the most confirmed coins first. It is complete once
+    /// this depth is of at least `min_confirmations`. Coins count even if they were spent since.
+    pub fn check_payment(
         &self,
-        txids: Option<Vec<bitcoin::Txid>>,
-    ) -> Result<ListSpendResult, CommandError> {
-        if let Some(ids) = &txids {
-            if ids.is_empty() {
-                return Err(CommandError::EmptyFilterList);
+        address: bitcoin::Address<address::NetworkUnchecked>,
+        expected: bitcoin::Amount,
+        min_confirmations: u32,
+    ) -> Result<PaymentStatus, CommandError> {
+        let address = self.validate_address(address)?;
+        let mut db_conn = self.db.connection();
+        let AddrInfo { index, is_change } = self
+            .addr_info(&mut db_conn, &address)
+            .ok_or_else(|| CommandError::UnknownAddress(address.clone()))?;
+        let tip_height = db_conn.chain_tip().map(|tip| tip.height).unwrap_or(0);
+
+        let mut payments: Vec<Payment> = db_conn
+            .coins(&[], &[])
+            .into_values()
+            .filter(|coin| coin.derivation_index == index && coin.is_change == is_change)
+            .map(|coin| Payment {
+                outpoint: coin.outpoint,
+                amount: coin.amount,
+                confirmations: coin
+                    .block_info
+                    .and_then(|b| u32::try_from(tip_height - b.height + 1).ok())
+                    .unwrap_or(0),
+            })
+            .collect();
+        payments.sort_by(|a, b| {
+            b.confirmations
+                .cmp(&a.confirmations)
+                .then(a.outpoint.cmp(&b.outpoint))
+        });
+
+        let mut sum = bitcoin::Amount::ZERO;
+        let mut confirmations = None;
+        for payment in &payments {
+            sum += payment.amount;
+            if sum >= expected {
+                confirmations = Some(payment.confirmations);
+                break;
             }
         }
+        let received = payments.iter().map(|p| p.amount).sum();
+        Ok(PaymentStatus {
+            address,
+            expected,
+            received,
+            excess: received
+                .checked_sub(expected)
+                .unwrap_or(bitcoin::Amount::ZERO),
+            confirmations,
+            is_paid: confirmations
+                .map(|c| c >= min_confirmations)
+                .unwrap_or(false),
+            payments,
+        })
+    }
 
+    /// List the coins, spent or not, which were confirmed within the last `depth` blocks. Those
+    /// would become unconfirmed (and may even disappear) if these blocks were reorganized out.
+    pub fn coins_within_depth(&self, depth: u32) -> Result<ListCoinsResult, CommandError> {
+        if depth == 0 {
+            return Err(CommandError::InvalidReorgDepth);
+        }
         let mut db_conn = self.db.connection();
-        let spend_psbts = db_conn.list_spend();
-
-        let txids_set: Option<HashSet<_>> = txids.as_ref().map(|list| list.iter().collect());
-        let spend_txs = spend_psbts
+        let tip_height = match db_conn.chain_tip() {
+            Some(tip) => tip.height,
+            None => return Ok(ListCoinsResult { coins: Vec::new() }),
+        };
+        // The lowest block height which would be replaced by a reorg of this depth.
+        let min_height = tip_height
+            .saturating_sub(i32::try_from(depth).unwrap_or(i32::MAX))
+            .saturating_add(1);
+
+        let timelocks = self.recovery_timelocks();
+        let coins: Vec<_> = db_conn
+            .coins(&[], &[])
             .into_iter()
-            .filter_map(|(psbt, updated_at)| {
-                if let Some(set) = &txids_set {
-                    if !set.contains(&psbt.unsigned_tx.txid()) {
-                        return None;
-                    }
-                }
-                Some(ListSpendEntry { psbt, updated_at })
+            .filter(|(_, coin)| {
+                coin.block_info
+                    .map(|b| b.height >= min_height)
+                    .unwrap_or(false)
             })
             .collect();
-        Ok(ListSpendResult { spend_txs })
+        let mut notes = db_conn.coin_notes(&coins.iter().map(|(op, _)| *op).collect::<Vec<_>>());
+        let coins = coins
+            .into_iter()
+            .map(|(op, coin)| {
+                let note = notes.remove(&op);
+                self.list_coins_entry(coin, tip_height, &timelocks, note)
+            })
+            .collect();
+        Ok(ListCoinsResult { coins })
     }
 
-    pub fn delete_spend(&self, txid: &bitcoin::Txid) {
+    /// List our unspent coins which are worth less than the fee to spend them at this feerate
+    /// (in sats/vb). The size of their input assumes the largest satisfaction among our spending
+    /// paths.
+    pub fn uneconomic_coins(&self, feerate_vb: u64) -> Result<ListCoinsResult, CommandError> {
+        if !(1..=MAX_FEERATE).contains(&feerate_vb) {
+            return Err(CommandError::InvalidFeerate(feerate_vb.into()));
+        }
+        let input_fee = max_input_fee(&self.config.main_descriptor, feerate_vb.into());
         let mut db_conn = self.db.connection();
-        db_conn.delete_spend(txid);
+        let current_height = self.bitcoin.chain_tip().height;
+        let timelocks = self.recovery_timelocks();
+        let coins: Vec<_> = db_conn
+            .coins(&[CoinStatus::Unconfirmed, CoinStatus::Confirmed], &[])
+            .into_values()
+            .filter(|coin| coin.amount < input_fee)
+            .collect();
+        let mut notes = db_conn.coin_notes(&coins.iter().map(|c| c.outpoint).collect::<Vec<_>>());
+        let coins = coins
+            .into_iter()
+            .map(|coin| {
+                let note = notes.remove(&coin.outpoint);
+                self.list_coins_entry(coin, current_height, &timelocks, note)
+            })
+            .collect();
+        Ok(ListCoinsResult { coins })
     }
 
-    /// Finalize and broadcast this stored Spend transaction.
-    pub fn broadcast_spend(&self, txid: &bitcoin::Txid) -> Result<(), CommandError> {
-        let mut db_conn = self.db.connection();
-
-        // First, try to finalize the spending transaction with the elements contained
-        // in the PSBT.
-        let mut spend_psbt = db_conn
-            .spend_tx(txid)
-            .ok_or(CommandError::UnknownSpend(*txid))?;
-        spend_psbt.finalize_mut(&self.secp).map_err(|e| {
-            CommandError::SpendFinalization(
-                e.into_iter()
-                    .next()
-                    .map(|e| e.to_string())
-                    .unwrap_or_default(),
-            )
-        })?;
+    /// Create a Spend transaction at a feerate of a whole number of sats/vb. See
+    /// [`DaemonControl::create_spend_with_feerate`].
+    pub fn create_spend(
+        &self,
+        destinations: &HashMap<bitcoin::Address<bitcoin::address::NetworkUnchecked>, u64>,
+        coin_selector: &CoinSelector,
+        feerate_vb: u64,
+        options: CreateSpendOptions,
+    ) -> Result<CreateSpendResult, CommandError> {
+        self.create_spend_with_feerate(
+            destinations,
+            coin_selector,
+            Feerate::from_sat_per_vb(feerate_vb),
+            options,
+        )
+    }
 
-        // Then, broadcast it (or try to, we never know if we are not going to hit an
-        // error at broadcast time).
-        // These checks are already performed at Spend creation time. TODO: a belt-and-suspenders is still worth it though.
-        let final_tx = spend_psbt.extract_tx_unchecked_fee_rate();
-        self.bitcoin
-            .broadcast_tx(&final_tx)
-            .map_err(CommandError::TxBroadcast)?;
+    /// Create a Spend transaction. The feerate may be fractional, in which case the fee is
+    /// rounded up to the next satoshi. See [`CreateSpendOptions`] for the optional parameters.
+    pub fn create_spend_with_feerate(
+        &self,
+        destinations: &HashMap<bitcoin::Address<bitcoin::address::NetworkUnchecked>, u64>,
+        coin_selector: &CoinSelector,
+        feerate: Feerate,
+        options: CreateSpendOptions,
+    ) -> Result<CreateSpendResult, CommandError> {
+        self.check_unlocked(false)?;
+        // The key is reserved before creating the transaction, so concurrent requests with the
+        // same key can't both create one.
+        if let Some(key) = &options.idempotency_key {
+            let params_hash = spend_params_hash(destinations, coin_selector, feerate, &options);
+            let mut spend_keys = self.spend_keys.lock().unwrap();
+            spend_keys.retain(|_, spend| spend.created_at.elapsed() < IDEMPOTENCY_KEY_TTL);
+            match spend_keys.get(key) {
+                Some(spend) if spend.params_hash != params_hash => {
+                    return Err(CommandError::IdempotencyKeyReused(key.clone()));
+                }
+                Some(IdempotentSpend {
+                    result: Some(res), ..
+                }) => return Ok(res.clone()),
+                Some(_) => return Err(CommandError::IdempotencyKeyInFlight(key.clone())),
+                None => {
+                    spend_keys.insert(
+                        key.clone(),
+                        IdempotentSpend {
+                            created_at: time::Instant::now(),
+                            params_hash,
+                            result: None,
+                        },
+                    );
+                }
+            }
+        }
 
-        // Finally, update our state with the changes from this transaction.
-        let (tx, rx) = mpsc::sync_channel(0);
-        if let Err(e) = self.poller_sender.send(PollerMessage::PollNow(tx)) {
-            log::error!("Error requesting update from poller: {}", e);
+        let res = self.build_spend(destinations, coin_selector, feerate, &options);
+        // Only record successfully created transactions, so a retry after a failure (for
+        // instance because of insufficient funds) can succeed.
+        if let Some(key) = &options.idempotency_key {
+            let mut spend_keys = self.spend_keys.lock().unwrap();
+            match (&res, spend_keys.get_mut(key)) {
+                (Ok(res @ CreateSpendResult::Success { .. }), Some(spend)) => {
+                    spend.result = Some(res.clone());
+                }
+                _ => {
+                    spend_keys.remove(key);
+                }
+            }
         }
-        if let Err(e) = rx.recv() {
-            log::error!("Error receiving completion signal from poller: {}", e);
+        let res = res?;
+        // The coins are only reserved once the draft is stored, so creating a transaction just to
+        // look at it doesn't lock them.
+        if let (false, CreateSpendResult::Success { draft_id, .. }) = (options.reserve_inputs, &res)
+        {
+            self.unreserved_drafts
+                .lock()
+                .unwrap()
+                .insert(*draft_id, time::Instant::now());
         }
-
-        Ok(())
+        if let CreateSpendResult::Success { psbt, .. } = &res {
+            // The destinations were validated when creating the transaction.
+            let mut items: HashMap<_, _> = options
+                .destination_labels
+                .into_iter()
+                .filter(|(addr, _)| destinations.contains_key(addr))
+                .map(|(addr, label)| (LabelItem::Address(addr.assume_checked()), Some(label)))
+                .collect();
+            if let Some(label) = options.label {
+                items.insert(LabelItem::Txid(psbt.unsigned_tx.txid()), Some(label));
+            }
+            if !items.is_empty() {
+                self.db.connection().update_labels(&items);
+            }
+        }
+        Ok(res)
     }
 
-    /// Create PSBT to replace the given transaction using RBF.
-    ///
-    /// `txid` must point to a PSBT in our database.
-    ///
-    /// `is_cancel` indicates whether to "cancel" the transaction by including only a single (change)
-    /// output in the replacement or otherwise to keep the same (non-change) outputs and simply
-    /// bump the fee.
-    /// If `true`, the only output of the RBF transaction will be change and the inputs will include
-    /// at least one of the inputs from the previous transaction. If `false`, all inputs from the previous
-    /// transaction will be used in the replacement.
-    /// In both cases:
-    /// - if the previous transaction includes a change output to one of our own change addresses,
-    /// this same address will be used for change in the RBF transaction, if required. If the previous
-    /// transaction pays to more than one of our change addresses, then the one receiving the highest
-    /// value will be used as a change address and the others will be treated as non-change outputs.
-    /// - the RBF transaction may include additional confirmed coins as inputs if required
-    /// in order to pay the higher fee (this applies also when replacing a self-send).
-    ///
-    /// `feerate_vb` is the target feerate for the RBF transaction (in sat/vb). If `None`, it will be set
-    /// to 1 sat/vb larger than the feerate of the previous transaction, which is the minimum value allowed
-    /// when using RBF.
-    pub fn rbf_psbt(
+    // Create a Spend transaction. The options specific to the creation through
+    // `create_spend_with_feerate` (idempotency key, labels and reservation of the inputs) are
+    // ignored.
+    fn build_spend(
         &self,
-        txid: &bitcoin::Txid,
-        is_cancel: bool,
-        feerate_vb: Option<u64>,
+        destinations: &HashMap<bitcoin::Address<bitcoin::address::NetworkUnchecked>, u64>,
+        coin_selector: &CoinSelector,
+        feerate: Feerate,
+        options: &CreateSpendOptions,
     ) -> Result<CreateSpendResult, CommandError> {
+        let &CreateSpendOptions {
+            ref change_address,
+            ref change_path,
+            force_path,
+            privacy_aware,
+            selection_weights,
+            allow_unconfirmed,
+            allow_unconfirmed_external,
+            split_change,
+            ref op_return,
+            output_seed,
+            ref exclude_outpoints,
+            allow_self_send,
+            long_term_feerate_vb,
+            ref extra_inputs,
+            max_inputs,
+            ignore_uneconomic,
+            ..
+        } = options;
+        let is_self_send = destinations.is_empty();
+        // For self-send, the coins must be specified.
+        match coin_selector {
+            CoinSelector::Outpoints(outpoints) if outpoints.is_empty() => {
+                return Err(CommandError::EmptyFilterList);
+            }
+            CoinSelector::Label(_) | CoinSelector::All if is_self_send => {
+                return Err(CommandError::NoOutpointForSelfSend);
+            }
+            _ => {}
+        }
+        if feerate < Feerate::from_sat_per_vb(1) {
+            return Err(CommandError::InvalidFeerate(feerate));
+        }
+        if let Some(n) = split_change.filter(|n| *n < 1 || *n > MAX_CHANGE_OUTPUTS) {
+            return Err(CommandError::InvalidChangeSplit(n));
+        }
+        let max_inputs = max_inputs.or(self.config.max_spend_inputs);
+        let long_term_feerate = match long_term_feerate_vb {
+            Some(f) if !(1..=MAX_FEERATE).contains(&f) => {
+                return Err(CommandError::InvalidFeerate(Feerate::from_sat_per_vb(f)));
+            }
+            Some(f) => Feerate::from_sat_per_vb(f),
+            None => self.long_term_feerate(),
+        };
+        // If the caller wants to spend through a specific spending path, get its timelock. The
+        // primary path is at index 0 and the recovery paths follow, by increasing timelock.
+        let policy = self.config.main_descriptor.policy();
+        let forced_timelock = match force_path {
+            None | Some(0) => None,
+            Some(i) => Some(
+                policy
+                    .recovery_paths()
+                    .keys()
+                    .nth(i - 1)
+                    .copied()
+                    .ok_or(CommandError::UnknownSpendingPath(i))?,
+            ),
+        };
         let mut db_conn = self.db.connection();
         let mut tx_getter = BitcoindTxGetter::new(&self.bitcoin);
-
-        if is_cancel && feerate_vb.is_some() {
-            return Err(CommandError::RbfError(RbfErrorInfo::SuperfluousFeerate));
+        let reserved_coins = self.reserved_coins();
+
+        // The foreign inputs must not be coins of ours, nor be given twice. Their satisfaction
+        // must fit in a standard transaction.
+        let extra_outpoints: Vec<_> = extra_inputs.iter().map(|i| i.outpoint).collect();
+        let our_coins = db_conn.coins_by_outpoints(&extra_outpoints);
+        for (i, input) in extra_inputs.iter().enumerate() {
+            if our_coins.contains_key(&input.outpoint)
+                || extra_outpoints[..i].contains(&input.outpoint)
+                || input.max_sat_weight > MAX_FOREIGN_INPUT_SAT_WEIGHT
+            {
+                return Err(CommandError::InvalidForeignInput(input.outpoint));
+            }
         }
 
-        let prev_psbt = db_conn
-            .spend_tx(txid)
-            .ok_or(CommandError::UnknownSpend(*txid))?;
-        if !prev_psbt.unsigned_tx.is_explicitly_rbf() {
-            return Err(CommandError::RbfError(RbfErrorInfo::NotSignaling));
+        // Prepare the destination addresses.
+        let mut destinations_checked = Vec::with_capacity(destinations.len());
+        for (address, value_sat) in destinations {
+            let address = self.validate_address(address.clone())?;
+            let amount = bitcoin::Amount::from_sat(*value_sat);
+            let address = self.spend_addr(&mut db_conn, address);
+            destinations_checked.push((address, amount));
         }
-        let prev_outpoints: Vec<bitcoin::OutPoint> = prev_psbt
-            .unsigned_tx
-            .input
+        let destination_warnings: Vec<String> = destinations_checked
             .iter()
-            .map(|txin| txin.previous_output)
+            .filter_map(|(addr, _)| destination_warning(&addr.addr))
             .collect();
-        let prev_coins = db_conn.coins_by_outpoints(&prev_outpoints);
-        // Make sure all prev outpoints are coins in our DB.
-        if let Some(op) = prev_outpoints
-            .iter()
-            .find(|op| !prev_coins.contains_key(op))
-        {
-            return Err(CommandError::UnknownOutpoint(*op));
-        }
-        if let Some(op) = prev_coins.iter().find_map(|(_, coin)| {
-            if coin.spend_block.is_some() {
-                Some(coin.outpoint)
-            } else {
-                None
+
+        // The change address to be used if a change output needs to be created. It may be
+        // specified by the caller (for instance for the purpose of a sweep, or to avoid us
+        // creating a new change address on every call), either directly or as a derivation path
+        // within our change descriptor (for instance to segregate change by purpose).
+        let change_address = match (change_address, change_path) {
+            (Some(_), Some(_)) => return Err(CommandError::ChangeAddressAndPath),
+            (Some(addr), None) => {
+                self.spend_addr(&mut db_conn, self.validate_address(addr.clone())?)
             }
-        }) {
-            return Err(CommandError::AlreadySpent(op));
-        }
-        // Compute the minimal feerate and fee the replacement transaction must have to satisfy RBF
-        // rules #3, #4 and #6 (see
-        // https://github.com/bitcoin/bitcoin/blob/master/doc/policy/mempool-replacements.md). By
-        // default (ie if the transaction we are replacing was dropped from the mempool) there is
-        // no minimum absolute fee and the minimum feerate is 1, the minimum relay feerate.
-        let (min_feerate_vb, descendant_fees) = self
-            .bitcoin
-            .mempool_spenders(&prev_outpoints)
-            .into_iter()
-            .fold(
-                (1, bitcoin::Amount::from_sat(0)),
-                |(min_feerate, descendant_fee), entry| {
-                    let entry_feerate = entry
-                        .fees
-                        .base
-                        .checked_div(entry.vsize)
-                        .expect("Can't have a null vsize or tx would be invalid")
-                        .to_sat()
-                        .checked_add(1)
-                        .expect("Can't overflow or tx would be invalid");
-                    (
-                        std::cmp::max(min_feerate, entry_feerate),
-                        descendant_fee + entry.fees.descendant,
-                    )
-                },
-            );
-        // Check replacement transaction's target feerate, if set, is high enough,
-        // and otherwise set it to the min feerate found above.
-        let feerate_vb = if is_cancel {
-            min_feerate_vb
-        } else {
-            feerate_vb.ok_or(CommandError::RbfError(RbfErrorInfo::MissingFeerate))?
+            (None, Some(path)) => self.change_addr_at_path(path)?,
+            (None, None) => self.next_change_addr(&mut db_conn),
         };
-        if feerate_vb < min_feerate_vb {
-            return Err(CommandError::RbfError(RbfErrorInfo::TooLowFeerate(
-                feerate_vb,
-            )));
-        }
-        // Get info about prev outputs to determine replacement outputs.
-        let prev_derivs: Vec<_> = prev_psbt
-            .unsigned_tx
-            .output
-            .iter()
-            .map(|txo| {
-                let address = bitcoin::Address::from_script(
-                    &txo.script_pubkey,
-                    self.config.bitcoin_config.network,
-                )
-                .expect("address already used in finalized transaction");
-                (
-                    address.clone(),
-                    txo.value,
-                    db_conn.derivation_index_by_address(&address),
-                )
-            })
-            .collect();
-        // Set the previous change address to that of the change output with the largest value
-        // and then largest index.
-        let prev_change_address = prev_derivs
-            .iter()
-            .filter_map(|(addr, amt, deriv)| {
-                if let Some((ind, true)) = &deriv {
-                    Some((addr, amt, ind))
+
+        // The candidate coins will be either all optional or all mandatory.
+        // If no coins have been specified, then coins will be selected automatically for
+        // the spend from a set of optional candidates, possibly restricted to those with a given
+        // label.
+        // Otherwise, only the specified coins will be used, all as mandatory candidates.
+        let candidate_coins: Vec<CandidateCoin> = match coin_selector {
+            CoinSelector::All | CoinSelector::Label(_) => {
+                // If spending through a recovery path, only the coins for which its timelock has
+                // expired may be selected.
+                let current_height = self.bitcoin.chain_tip().height;
+                // From our unconfirmed coins, we only include by default those that are change
+                // outputs since unconfirmed external deposits are more at risk of being dropped
+                // unexpectedly from the mempool as they are beyond the user's control. The caller
+                // may opt out of the former or opt in to the latter. Confirmed coins which don't
+                // have the configured minimum number of confirmations are treated the same way.
+                let min_confs = self.config.spendable_min_confirmations;
+                // Coins worth less than the fee to spend them may be left aside.
+                let min_value = if ignore_uneconomic {
+                    max_input_fee(&self.config.main_descriptor, feerate)
                 } else {
-                    None
-                }
-            })
-            .max_by(|(_, amt_1, ind_1), (_, amt_2, ind_2)| amt_1.cmp(amt_2).then(ind_1.cmp(ind_2)))
-            .map(|(addr, _, _)| addr)
-            .cloned();
-        // If not cancel, use all previous outputs as destinations, except for
-        // the output corresponding to the change address we found above.
-        // If cancel, the replacement will not have any destinations, only a change output.
-        let destinations = if !is_cancel {
-            prev_derivs
-                .into_iter()
-                .filter_map(|(addr, amt, _)| {
-                    if prev_change_address.as_ref() != Some(&addr) {
-                        Some((self.spend_addr(&mut db_conn, addr), amt))
-                    } else {
-                        None
+                    bitcoin::Amount::ZERO
+                };
+                let coins: Vec<_> = db_conn
+                    .coins(&[CoinStatus::Unconfirmed, CoinStatus::Confirmed], &[])
+                    .into_iter()
+                    .filter(|(op, c)| {
+                        !reserved_coins.contains(op)
+                            && !exclude_outpoints.contains(op)
+                            && c.amount >= min_value
+                    })
+                    .filter_map(|(op, c)| {
+                        if let Some(timelock) = forced_timelock {
+                            if is_recovery_available(&c, current_height, timelock) {
+                                Some((c, None))
+                            } else {
+                                None
+                            }
+                        } else if c.block_info.is_some()
+                            && has_min_confirmations(&c, current_height, min_confs)
+                        {
+                            Some((c, None)) // confirmed coins have no ancestor info
+                        } else if !c.is_immature
+                            && ((c.is_change && allow_unconfirmed)
+                                || (!c.is_change && allow_unconfirmed_external))
+                        {
+                            // In case the mempool_entry is None, the coin will be included without
+                            // any ancestor info.
+                            let ancestor_info = if c.block_info.is_none() {
+                                self.bitcoin.mempool_entry(&op.txid).map(AncestorInfo::from)
+                            } else {
+                                None
+                            };
+                            Some((c, ancestor_info))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect();
+                let coins = if let CoinSelector::Label(label) = coin_selector {
+                    self.coins_with_label(&mut db_conn, coins, label)
+                } else {
+                    coins
+                };
+                coins
+                    .into_iter()
+                    .map(|(c, ancestor_info)| {
+                        coin_to_candidate(
+                            &c,
+                            /*must_select=*/ false,
+                            /*sequence=*/
+                            forced_timelock.map(bitcoin::Sequence::from_height),
+                            ancestor_info,
+                        )
+                    })
+                    .collect()
+            }
+            CoinSelector::Outpoints(coins_outpoints) => {
+                // Query from DB and sanity check the provided coins to spend.
+                let coins = db_conn.coins(&[], coins_outpoints);
+                for op in coins_outpoints {
+                    let coin = coins.get(op).ok_or(CommandError::UnknownOutpoint(*op))?;
+                    if coin.is_spent() {
+                        return Err(CommandError::AlreadySpent(*op));
                     }
-                })
-                .collect()
-        } else {
-            Vec::new()
+                    if coin.is_immature {
+                        return Err(CommandError::ImmatureCoinbase(*op));
+                    }
+                    if reserved_coins.contains(op) {
+                        return Err(CommandError::CoinReserved(*op));
+                    }
+                    if let Some(timelock) = forced_timelock {
+                        let current_height = self.bitcoin.chain_tip().height;
+                        if !is_recovery_available(coin, current_height, timelock) {
+                            return Err(CommandError::SpendingPathNotAvailable(
+                                force_path.expect("Set if there is a timelock"),
+                                *op,
+                            ));
+                        }
+                    }
+                }
+                coins
+                    .into_iter()
+                    .map(|(op, c)| {
+                        let ancestor_info = if c.block_info.is_none() {
+                            // We include any non-change coins here as they have been selected by the caller.
+                            // If the unconfirmed coin's transaction is no longer in the mempool, keep the
+                            // coin as a candidate but without any ancestor info (same as confirmed candidate).
+                            self.bitcoin.mempool_entry(&op.txid).map(AncestorInfo::from)
+                        } else {
+                            None
+                        };
+                        coin_to_candidate(
+                            &c,
+                            /*must_select=*/ true,
+                            /*sequence=*/
+                            forced_timelock.map(bitcoin::Sequence::from_height),
+                            ancestor_info,
+                        )
+                    })
+                    .collect()
+            }
         };
 
-        // If there was no previous change address, we set the change address for the replacement
-        // to our next change address. This way, we won't increment the change index with each attempt
-        // at creating the replacement PSBT below.
-        let change_address = prev_change_address
-            .map(|addr| self.spend_addr(&mut db_conn, addr))
-            .unwrap_or_else(|| self.next_change_addr(&mut db_conn));
-        // If `!is_cancel`, we take the previous coins as mandatory candidates and add confirmed coins as optional.
-        // Otherwise, we take the previous coins as optional candidates and let coin selection find the
-        // best solution that includes at least one of these. If there are insufficient funds to create the replacement
-        // transaction in this way, then we set candidates in the same way as for the `!is_cancel` case.
-        let mut candidate_coins: Vec<CandidateCoin> = prev_coins
-            .values()
-            .map(|c| {
-                // In case any previous coins are unconfirmed, we don't include their ancestor info
-                // in the candidate as the replacement fee and feerate will be higher and any
-                // additional fee to pay for ancestors should already have been taken into account
-                // when including these coins in the previous transaction.
-                coin_to_candidate(
-                    c, /*must_select=*/ !is_cancel, /*sequence=*/ None,
-                    /*ancestor_info=*/ None,
-                )
-            })
-            .collect();
-        let confirmed_cands: Vec<CandidateCoin> = db_conn
-            .coins(&[CoinStatus::Confirmed], &[])
-            .into_values()
-            .filter_map(|c| {
-                // Make sure we don't have duplicate candidates in case any of the coins are not
-                // currently set as spending in the DB (and are therefore still confirmed).
-                if !prev_coins.contains_key(&c.outpoint) {
-                    Some(coin_to_candidate(
-                        &c, /*must_select=*/ false, /*sequence=*/ None,
-                        /*ancestor_info=*/ None,
-                    ))
-                } else {
-                    None
-                }
-            })
-            .collect();
-        if !is_cancel {
-            candidate_coins.extend(&confirmed_cands);
-        }
-        // The replaced fee is the fee of the transaction being replaced and its descendants. Coin selection
-        // will ensure that the replacement transaction additionally pays for its own weight as per
-        // RBF rule 4.
-        let replaced_fee = descendant_fees.to_sat();
-        // This loop can have up to 2 iterations in the case of cancel and otherwise only 1.
-        loop {
-            match create_spend(
+        // Create the PSBT. If there was no error in doing so make sure to update our next
+        // derivation index in case any address in the transaction outputs was ours and from the
+        // future.
+        let change_info = change_address.info;
+        let mut spend_with = |candidates: &[CandidateCoin]| {
+            create_spend(
                 &self.config.main_descriptor,
                 &self.secp,
                 &mut tx_getter,
-                &destinations,
-                &candidate_coins,
-                SpendTxFees::Rbf(feerate_vb, replaced_fee),
+                &destinations_checked,
+                candidates,
+                extra_inputs,
+                SpendTxFees::Regular(feerate),
+                long_term_feerate,
+                max_inputs,
                 change_address.clone(),
-            ) {
-                Ok(CreateSpendRes {
-                    psbt,
-                    has_change,
-                    warnings,
-                }) => {
-                    // In case of success, make sure to update our next derivation index if any address
-                    // used in the transaction outputs was from the future.
-                    for (addr, _) in destinations {
-                        self.maybe_increase_next_deriv_index(&mut db_conn, &addr.info);
-                    }
-                    if has_change {
-                        self.maybe_increase_next_deriv_index(&mut db_conn, &change_address.info);
-                    }
-
-                    return Ok(CreateSpendResult::Success {
-                        psbt,
-                        warnings: warnings.iter().map(|w| w.to_string()).collect(),
-                    });
-                }
-                Err(SpendCreationError::CoinSelection(e)) => {
-                    // If we get a coin selection error due to insufficient funds and we want to cancel the
-                    // transaction, then set all previous coins as mandatory and add confirmed coins as
-                    // optional, unless we have already done this.
-                    if is_cancel && candidate_coins.iter().all(|c| !c.must_select) {
-                        for cand in candidate_coins.iter_mut() {
-                            cand.must_select = true;
-                        }
-                        candidate_coins.extend(&confirmed_cands);
-                        continue;
-                    } else {
-                        return Ok(CreateSpendResult::InsufficientFunds { missing: e.missing });
+                op_return.as_deref(),
+            )
+        };
+        // If asked to, try to avoid combining coins paying to different addresses by first trying
+        // to fund the transaction with the coins of a single address. If weights were given,
+        // rather choose between the regular selection, the single address one and spending all
+        // the coins depending on how each scores. This only applies to automatic coin selection.
+        let mut extra_warnings = Vec::new();
+        let mut selection_score = None;
+        let is_auto_selection = candidate_coins.iter().all(|c| !c.must_select);
+        let spend_res = match selection_weights {
+            Some(weights) if is_auto_selection => spend_with(&candidate_coins).map(|regular_res| {
+                let mut options = vec![regular_res];
+                options.extend(
+                    single_address_spend(&mut spend_with, &candidate_coins).and_then(Result::ok),
+                );
+                let all_coins: Vec<_> = candidate_coins
+                    .iter()
+                    .map(|c| CandidateCoin {
+                        must_select: true,
+                        ..*c
+                    })
+                    .collect();
+                options.extend(spend_with(&all_coins).ok());
+                let (res, score) = best_selection(options, &candidate_coins, &weights);
+                selection_score = Some(score);
+                res
+            }),
+            _ if privacy_aware && is_auto_selection => {
+                match single_address_spend(&mut spend_with, &candidate_coins) {
+                    Some(res) => res,
+                    None => {
+                        extra_warnings.push(
+                            "Could not fund the transaction with the coins of a single address. \
+                             Coins from different addresses were combined."
+                                .to_string(),
+                        );
+                        spend_with(&candidate_coins)
                     }
                 }
-                Err(e) => {
-                    return Err(e.into());
-                }
-            };
+            }
+            _ => spend_with(&candidate_coins),
+        };
+        let CreateSpendRes {
+            mut psbt,
+            has_change,
+            warnings,
+        } = match spend_res {
+            Ok(res) => res,
+            Err(SpendCreationError::CoinSelection(e)) => {
+                return Ok(insufficient_funds(&candidate_coins, e.missing));
+            }
+            Err(e) => {
+                return Err(e.into());
+            }
+        };
+        // The destinations which are addresses of ours, to be marked as self-send.
+        let self_send_spks: HashSet<bitcoin::ScriptBuf> = destinations_checked
+            .iter()
+            .filter(|(addr, _)| addr.info.is_some())
+            .map(|(addr, _)| addr.addr.script_pubkey())
+            .collect();
+        if !allow_self_send {
+            for (addr, _) in destinations_checked
+                .iter()
+                .filter(|(a, _)| a.info.is_some())
+            {
+                extra_warnings.push(format!(
+                    "Destination {} is an address of this wallet. Set 'allow_self_send' if this \
+                     is intended.",
+                    addr.addr
+                ));
+            }
         }
-    }
-
-    /// Trigger a rescan of the block chain for transactions involving our main descriptor between
-    /// the given date and the current tip.
-    /// The date must be after the genesis block time and before the current tip blocktime.
-    pub fn start_rescan(&self, timestamp: u32) -> Result<(), CommandError> {
-        let mut db_conn = self.db.connection();
-        let genesis_timestamp = self.bitcoin.genesis_block_timestamp();
-
-        let future_timestamp = self
-            .bitcoin
-            .tip_time()
-            .map(|t| timestamp >= t)
-            .unwrap_or(false);
-        if timestamp < genesis_timestamp || future_timestamp {
-            return Err(CommandError::InsaneRescanTimestamp(timestamp));
+        for (addr, _) in destinations_checked {
+            self.maybe_increase_next_deriv_index(&mut db_conn, &addr.info);
         }
-        if db_conn.rescan_timestamp().is_some() || self.bitcoin.rescan_progress().is_some() {
-            return Err(CommandError::AlreadyRescanning);
+        if has_change {
+            self.maybe_increase_next_deriv_index(&mut db_conn, &change_info);
         }
 
-        // TODO: there is a race with the above check for whether the backend is already
-        // rescanning. This could make us crash with the bitcoind backend if someone triggered a
-        // rescan of the wallet just after we checked above and did now.
-        self.bitcoin
-            .start_rescan(&self.config.main_descriptor, timestamp)
-            .map_err(CommandError::RescanTrigger)?;
-        db_conn.set_rescan(timestamp);
+        // If asked to, split the change between several outputs to fresh change addresses of
+        // ours. This is only done if the change goes to one of our change addresses.
+        let mut change_count = if has_change { 1 } else { 0 };
+        if let (
+            Some(split_change),
+            true,
+            Some(AddrInfo {
+                is_change: true, ..
+            }),
+        ) = (split_change.filter(|n| *n > 1), has_change, change_info)
+        {
+            let change_spk = change_address.addr.script_pubkey();
+            let change_index = psbt
+                .unsigned_tx
+                .output
+                .iter()
+                .rposition(|txo| txo.script_pubkey == change_spk)
+                .expect("There is a change output");
+            let mut extra_change_addrs = Vec::with_capacity(split_change as usize - 1);
+            let mut index = db_conn.change_index();
+            for _ in 1..split_change {
+                let desc = self
+                    .config
+                    .main_descriptor
+                    .change_descriptor()
+                    .derive(index, &self.secp);
+                extra_change_addrs.push(SpendOutputAddress {
+                    addr: desc.address(self.config.bitcoin_config.network),
+                    info: Some(AddrInfo {
+                        index,
+                        is_change: true,
+                    }),
+                });
+                index = index
+                    .increment()
+                    .expect("Must not get into hardened territory");
+            }
+            let split_count = split_change_output(
+                &self.config.main_descriptor,
+                &self.secp,
+                &mut psbt,
+                change_index,
+                &extra_change_addrs,
+                feerate,
+            );
+            for addr in &extra_change_addrs[..split_count] {
+                self.maybe_increase_next_deriv_index(&mut db_conn, &addr.info);
+            }
+            change_count += split_count;
+            if split_count + 1 < split_change as usize {
+                extra_warnings.push(format!(
+                    "The change was too small to be split into {} outputs. It was split into {} \
+                     output(s).",
+                    split_change,
+                    split_count + 1
+                ));
+            }
+        }
 
-        Ok(())
+        // Place the change output(s) among the other outputs. The seed is returned for the
+        // ordering to be reproducible.
+        let output_seed = match output_seed {
+            Some(seed) => seed,
+            None => OutputSeed::random().map_err(|e| CommandError::Randomness(e.to_string()))?,
+        };
+        output_seed.place_change_outputs(&mut psbt, change_count);
+        psbt.unsigned_tx.lock_time = self.anti_fee_sniping_locktime()?;
+
+        // If a spending path was forced, only keep the BIP32 derivations of its keys so signing
+        // devices sign for this path.
+        let mut psbt = match forced_timelock {
+            Some(timelock) => {
+                let path_info = policy
+                    .recovery_paths()
+                    .get(&timelock)
+                    .expect("Timelock is from this policy");
+                self.config
+                    .main_descriptor
+                    .prune_bip32_derivs(psbt, path_info)
+            }
+            None if force_path.is_some() => self
+                .config
+                .main_descriptor
+                .prune_bip32_derivs(psbt, policy.primary_path()),
+            None => psbt,
+        };
+        // Identify this draft, so it can be tracked across updates of the PSBT.
+        let draft_id = DraftId::random().map_err(|e| CommandError::Randomness(e.to_string()))?;
+        draft_id.set_in_psbt(&mut psbt);
+        let privacy_score = privacy_score(&psbt, &candidate_coins);
+        let spends_unconfirmed = spends_unconfirmed(&mut db_conn, &psbt);
+        let self_send_outputs = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(_, txo)| self_send_spks.contains(&txo.script_pubkey))
+            .map(|(i, _)| i)
+            .collect();
+        let foreign_inputs = psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .enumerate()
+            .filter(|(_, txin)| extra_outpoints.contains(&txin.previous_output))
+            .map(|(i, _)| i)
+            .collect();
+
+        Ok(CreateSpendResult::Success {
+            draft_id,
+            privacy_score,
+            selection_score,
+            spends_unconfirmed,
+            output_seed: Some(output_seed),
+            feerate,
+            self_send_outputs,
+            foreign_inputs,
+            destination_warnings,
+            locktime: psbt.unsigned_tx.lock_time.to_consensus_u32(),
+            psbt,
+            warnings: warnings
+                .iter()
+                .map(|w| w.to_string())
+                .chain(extra_warnings)
+                .collect(),
+        })
     }
 
-    /// list_confirmed_transactions retrieves a limited list of transactions which occured between two given dates.
-    pub fn list_confirmed_transactions(
+    /// Get the signatures still needed to finalize the stored Spend transaction with this txid.
+    /// They are given for the spending path the transaction is to be satisfied through: the
+    /// recovery path with the largest timelock among those made available by the inputs'
+    /// nSequence, or the primary path if there is none.
+    pub fn signing_requirements(
         &self,
-        start: u32,
-        end: u32,
-        limit: u64,
-    ) -> ListTransactionsResult {
+        txid: &bitcoin::Txid,
+    ) -> Result<SigningRequirements, CommandError> {
+        let psbt = self
+            .db
+            .connection()
+            .spend_tx(txid)
+            .ok_or(CommandError::UnknownSpend(*txid))?;
+        let desc = &self.config.main_descriptor;
+        let spend_info = desc
+            .partial_spend_info(&psbt)
+            .map_err(|e| CommandError::SpendAnalysis(e.to_string()))?;
+        let policy = desc.policy();
+        let (timelock, path_info, path_spend_info) =
+            match spend_info.recovery_paths().iter().next_back() {
+                Some((timelock, path_spend_info)) => (
+                    Some(*timelock),
+                    policy
+                        .recovery_paths()
+                        .get(timelock)
+                        .expect("Timelock is from this policy"),
+                    path_spend_info,
+                ),
+                None => (None, policy.primary_path(), spend_info.primary_path()),
+            };
+
+        // Signatures are accounted per fingerprint, as a signing device may hold more than one key
+        // of a spending path.
+        let (threshold, keys) = path_info.thresh_keys();
+        let mut signed = path_spend_info.signed_pubkeys.clone();
+        let mut remaining_fingerprints = Vec::new();
+        for fingerprint in keys.into_iter().map(|key| key.master_fingerprint()) {
+            match signed.get_mut(&fingerprint) {
+                Some(count) if *count > 0 => *count -= 1,
+                _ if !remaining_fingerprints.contains(&fingerprint) => {
+                    remaining_fingerprints.push(fingerprint)
+                }
+                _ => {}
+            }
+        }
+
+        Ok(SigningRequirements {
+            timelock,
+            threshold,
+            sigs_count: path_spend_info.sigs_count,
+            missing_sigs: threshold.saturating_sub(path_spend_info.sigs_count),
+            remaining_fingerprints,
+        })
+    }
+
+    /// Get the fee the stored Spend transaction with this txid will pay, along with its size and
+    /// feerate once finalized. Contrary to the worst case estimate used when creating it, the size
+    /// is computed for the spending path the transaction is to be satisfied through (see
+    /// [`Self::signing_requirements`]).
+    pub fn final_fee_estimate(
+        &self,
+        txid: &bitcoin::Txid,
+    ) -> Result<FinalFeeEstimate, CommandError> {
         let mut db_conn = self.db.connection();
-        let txids = db_conn.list_txids(start, end, limit);
-        let transactions = txids
-            .iter()
-            .filter_map(|txid| {
-                // TODO: batch those calls to the Bitcoin backend
-                // so it can in turn optimize its queries.
-                self.bitcoin
-                    .wallet_transaction(txid)
-                    .map(|(tx, block)| TransactionInfo {
-                        tx,
-                        height: block.map(|b| b.height),
-                        time: block.map(|b| b.time),
-                    })
-            })
-            .collect();
-        ListTransactionsResult { transactions }
+        let psbt = db_conn
+            .spend_tx(txid)
+            .ok_or(CommandError::UnknownSpend(*txid))?;
+        let desc = &self.config.main_descriptor;
+        let spend_info = desc
+            .partial_spend_info(&psbt)
+            .map_err(|e| CommandError::SpendAnalysis(e.to_string()))?;
+        let timelock = spend_info.recovery_paths().keys().next_back().copied();
+
+        // The value of each input is either set in the PSBT or known from our own coins.
+        let tx = &psbt.unsigned_tx;
+        let outpoints: Vec<bitcoin::OutPoint> =
+            tx.input.iter().map(|txin| txin.previous_output).collect();
+        let coins = db_conn.coins_by_outpoints(&outpoints);
+        let mut total_in = bitcoin::Amount::ZERO;
+        for (op, psbt_in) in outpoints.iter().zip(psbt.inputs.iter()) {
+            total_in += psbt_in
+                .witness_utxo
+                .as_ref()
+                .map(|utxo| utxo.value)
+                .or_else(|| coins.get(op).map(|c| c.amount))
+                .ok_or(CommandError::UnknownOutpoint(*op))?;
+        }
+        let total_out: bitcoin::Amount = tx.output.iter().map(|txo| txo.value).sum();
+        let fee = total_in
+            .checked_sub(total_out)
+            .ok_or(CommandError::SpendCreation(SpendCreationError::InsaneFees(
+                InsaneFeeInfo::NegativeFee,
+            )))?;
+
+        // The finalized transaction has the Segwit marker and flag (2 WU) and each input is
+        // satisfied through the same spending path.
+        let sat_weight = u64::try_from(
+            desc.path_sat_weight(timelock)
+                .expect("Timelock is from this policy"),
+        )
+        .expect("Must fit in a u64");
+        let num_inputs = u64::try_from(tx.input.len()).expect("Must fit in a u64");
+        let weight = bitcoin::Weight::from_wu(
+            tx.weight().to_wu() + 2 + sat_weight.checked_mul(num_inputs).unwrap(),
+        );
+        let vsize = weight.to_vbytes_ceil();
+
+        Ok(FinalFeeEstimate {
+            timelock,
+            fee,
+            vsize,
+            feerate_vb: fee.to_sat().checked_div(vsize).unwrap_or(0),
+        })
     }
 
-    /// list_transactions retrieves the transactions with the given txids.
-    pub fn list_transactions(&self, txids: &[bitcoin::Txid]) -> ListTransactionsResult {
-        let transactions = txids
+    /// Inspect a PSBT before signing it: compute the value of its inputs and outputs, its fee and
+    /// feerate, and list the outputs which don't pay to us. The PSBT doesn't need to be stored.
+    /// Will error if the outputs are worth more than the inputs, or if the fee is insane.
+    pub fn inspect_psbt(&self, psbt: &Psbt) -> Result<PsbtInspection, CommandError> {
+        let mut db_conn = self.db.connection();
+        let tx = &psbt.unsigned_tx;
+
+        // The value of each input is either set in the PSBT or known from our own coins.
+        let outpoints: Vec<bitcoin::OutPoint> =
+            tx.input.iter().map(|txin| txin.previous_output).collect();
+        let coins = db_conn.coins_by_outpoints(&outpoints);
+        let mut total_in = bitcoin::Amount::ZERO;
+        for (op, psbt_in) in outpoints.iter().zip(psbt.inputs.iter()) {
+            total_in += psbt_in
+                .witness_utxo
+                .as_ref()
+                .map(|utxo| utxo.value)
+                .or_else(|| coins.get(op).map(|c| c.amount))
+                .ok_or(CommandError::UnknownOutpoint(*op))?;
+        }
+        let total_out: bitcoin::Amount = tx.output.iter().map(|txo| txo.value).sum();
+
+        // Perform the same sanity checks on the fee as when creating a transaction.
+        let insane_fees = |info| CommandError::SpendCreation(SpendCreationError::InsaneFees(info));
+        let fee = total_in
+            .checked_sub(total_out)
+            .ok_or_else(|| insane_fees(InsaneFeeInfo::NegativeFee))?;
+        if fee > MAX_FEE {
+            return Err(insane_fees(InsaneFeeInfo::TooHighFee(fee.to_sat())));
+        }
+        let feerate_vb = fee.to_sat() / self.config.main_descriptor.unsigned_tx_max_vbytes(tx);
+        if feerate_vb > MAX_FEERATE {
+            return Err(insane_fees(InsaneFeeInfo::TooHighFeerate(feerate_vb)));
+        }
+
+        // The outputs paying to us are either detected from their BIP32 derivations in the PSBT,
+        // or from their address if we know about it.
+        let network = self.config.bitcoin_config.network;
+        let change_indexes: HashSet<usize> = self
+            .config
+            .main_descriptor
+            .change_indexes(psbt, &self.secp)
+            .into_iter()
+            .map(|change| change.index())
+            .collect();
+        // An OP_RETURN output is reported separately, as it isn't paying to a recipient.
+        let op_return = tx
+            .output
             .iter()
-            .filter_map(|txid| {
-                // TODO: batch those calls to the Bitcoin backend
-                // so it can in turn optimize its queries.
-                self.bitcoin
-                    .wallet_transaction(txid)
-                    .map(|(tx, block)| TransactionInfo {
-                        tx,
-                        height: block.map(|b| b.height),
-                        time: block.map(|b| b.time),
-                    })
+            .find_map(|txo| op_return_data(&txo.script_pubkey))
+            .map(|data| data.to_lower_hex_string());
+        let external_outputs = tx
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(_, txo)| !txo.script_pubkey.is_op_return())
+            .filter_map(|(index, txo)| {
+                let address = bitcoin::Address::from_script(&txo.script_pubkey, network).ok();
+                let is_ours = change_indexes.contains(&index)
+                    || address
+                        .as_ref()
+                        .map(|addr| self.addr_info(&mut db_conn, addr).is_some())
+                        .unwrap_or(false);
+                (!is_ours).then(|| PsbtOutputInfo {
+                    index,
+                    address: address.map(|addr| addr.as_unchecked().clone()),
+                    amount: txo.value,
+                })
             })
             .collect();
-        ListTransactionsResult { transactions }
+
+        Ok(PsbtInspection {
+            total_in,
+            total_out,
+            fee,
+            feerate_vb,
+            external_outputs,
+            op_return,
+        })
     }
 
-    /// Create a transaction that sweeps all coins for which a timelocked recovery path is
-    /// currently available to a provided address with the provided feerate.
-    ///
-    /// The `timelock` parameter can be used to specify which recovery path to use. By default,
-    /// we'll use the first recovery path available.
-    ///
-    /// Note that not all coins may be spendable through a single recovery path at the same time.
-    pub fn create_recovery(
-        &self,
-        address: bitcoin::Address<address::NetworkUnchecked>,
-        feerate_vb: u64,
-        timelock: Option<u16>,
-    ) -> Result<CreateRecoveryResult, CommandError> {
-        if feerate_vb < 1 {
-            return Err(CommandError::InvalidFeerate(feerate_vb));
-        }
-        let mut tx_getter = BitcoindTxGetter::new(&self.bitcoin);
+    /// Store this Spend transaction, or update it if it is already stored. The coins it spends
+    /// are reserved for its draft, unless it was created without
+    /// [`CreateSpendOptions::reserve_inputs`].
+    pub fn update_spend(&self, mut psbt: Psbt) -> Result<(), CommandError> {
+        self.check_unlocked(false)?;
         let mut db_conn = self.db.connection();
-        let sweep_addr = self.spend_addr(&mut db_conn, self.validate_address(address)?);
+        let tx = &psbt.unsigned_tx;
+        let outpoints: Vec<bitcoin::OutPoint> =
+            tx.input.iter().map(|txin| txin.previous_output).collect();
+        let coins = db_conn.coins_by_outpoints(&outpoints);
 
-        // Query the coins that we can spend through the specified recovery path (if no recovery
-        // path specified, use the first available one) from the database.
-        let current_height = self.bitcoin.chain_tip().height;
-        let timelock =
-            timelock.unwrap_or_else(|| self.config.main_descriptor.first_timelock_value());
-        let height_delta: i32 = timelock.into();
-        let sweepable_coins: Vec<_> = db_conn
-            .coins(&[CoinStatus::Confirmed], &[])
-            .into_values()
-            .filter_map(|c| {
-                // We are interested in coins available at the *next* block
-                if c.block_info
-                    .map(|b| current_height + 1 >= b.height + height_delta)
-                    .unwrap_or(false)
+        // Don't store signatures which would be of no use for spending our coins.
+        self.check_psbt_signers(&psbt, &coins)?;
+
+        // If the transaction already exists in DB, merge the signatures for each input on a best
+        // effort basis.
+        // We work on the newly provided PSBT, in case its content was updated.
+        let txid = tx.txid();
+        if let Some(db_psbt) = db_conn.spend_tx(&txid) {
+            let db_tx = db_psbt.unsigned_tx;
+            for i in 0..db_tx.input.len() {
+                if tx
+                    .input
+                    .get(i)
+                    .map(|tx_in| tx_in.previous_output == db_tx.input[i].previous_output)
+                    != Some(true)
                 {
-                    Some(coin_to_candidate(
-                        &c,
-                        /*must_select=*/ true,
-                        /*sequence=*/ Some(bitcoin::Sequence::from_height(timelock)),
-                        /*ancestor_info=*/ None,
-                    ))
-                } else {
-                    None
+                    continue;
                 }
-            })
-            .collect();
-        if sweepable_coins.is_empty() {
-            return Err(CommandError::RecoveryNotAvailable);
+                let psbtin = match psbt.inputs.get_mut(i) {
+                    Some(psbtin) => psbtin,
+                    None => continue,
+                };
+                let db_psbtin = match db_psbt.inputs.get(i) {
+                    Some(db_psbtin) => db_psbtin,
+                    None => continue,
+                };
+                psbtin
+                    .partial_sigs
+                    .extend(db_psbtin.partial_sigs.clone().into_iter());
+                psbtin
+                    .tap_script_sigs
+                    .extend(db_psbtin.tap_script_sigs.clone().into_iter());
+                if psbtin.tap_key_sig.is_none() {
+                    psbtin.tap_key_sig = db_psbtin.tap_key_sig;
+                }
+            }
+        } else {
+            // If the transaction doesn't exist in DB already, sanity check its inputs.
+            // FIXME: should we allow for external inputs?
+            if coins.len() != outpoints.len() {
+                for op in outpoints {
+                    if coins.get(&op).is_none() {
+                        return Err(CommandError::UnknownOutpoint(op));
+                    }
+                }
+            }
         }
 
-        let sweep_addr_info = sweep_addr.info;
-        let CreateSpendRes {
-            psbt, has_change, ..
-        } = create_spend(
-            &self.config.main_descriptor,
-            &self.secp,
-            &mut tx_getter,
-            &[], // No destination, only the change address.
-            &sweepable_coins,
-            SpendTxFees::Regular(feerate_vb),
-            sweep_addr,
-        )?;
-        if has_change {
-            self.maybe_increase_next_deriv_index(&mut db_conn, &sweep_addr_info);
+        // Don't lose track of the draft if the identifier was dropped from the updated PSBT.
+        if DraftId::from_psbt(&psbt).is_none() {
+            if let Some(draft_id) = db_conn
+                .spend_tx(&txid)
+                .as_ref()
+                .and_then(DraftId::from_psbt)
+            {
+                draft_id.set_in_psbt(&mut psbt);
+            }
         }
 
-        Ok(CreateRecoveryResult { psbt })
-    }
-}
+        // Finally, insert (or update) the PSBT in database.
+        db_conn.store_spend(&psbt);
+        inherit_draft_label(&mut db_conn, &psbt);
+
+        // And reserve the coins it spends, unless it was asked not to when creating the draft. The
+        // coins already reserved by another draft are left to it.
+        if let Some(draft_id) = DraftId::from_psbt(&psbt) {
+            let mut unreserved_drafts = self.unreserved_drafts.lock().unwrap();
+            unreserved_drafts.retain(|_, created_at| created_at.elapsed() < COIN_RESERVATION_TTL);
+            if !unreserved_drafts.contains_key(&draft_id) {
+                let now = time::Instant::now();
+                let mut reservations = self.coin_reservations.lock().unwrap();
+                for txin in &psbt.unsigned_tx.input {
+                    let reserved_by_other = reservations
+                        .get(&txin.previous_output)
+                        .map(|(reserved_at, id)| {
+                            id != &draft_id && reserved_at.elapsed() < COIN_RESERVATION_TTL
+                        })
+                        .unwrap_or(false);
+                    if !reserved_by_other {
+                        reservations.insert(txin.previous_output, (now, draft_id));
+                    }
+                }
+            }
+        }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GetInfoDescriptors {
-    pub main: descriptors::LianaDescriptor,
-}
+        Ok(())
+    }
 
-/// Information about the daemon
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GetInfoResult {
-    pub version: String,
-    pub network: bitcoin::Network,
-    pub block_height: i32,
-    pub sync: f64,
-    pub descriptors: GetInfoDescriptors,
-    /// The progress as a percentage (between 0 and 1) of an ongoing rescan if there is any
-    pub rescan_progress: Option<f64>,
-    /// Timestamp at wallet creation date
-    pub timestamp: u32,
-}
+    /// Replace the output(s) of the stored Spend transaction `txid` paying to `old` by an output
+    /// paying `new_amount` sats to `new`. The other destinations are preserved and the transaction
+    /// is rebuilt from the same coins at the same feerate, the change absorbing the difference.
+    /// The rebuilt transaction is stored as part of the same draft, in place of the previous one.
+    /// Signatures for the previous transaction aren't valid for the rebuilt one: they are dropped
+    /// and a warning is returned for the Spend to be signed again.
+    pub fn update_spend_destination(
+        &self,
+        txid: &bitcoin::Txid,
+        old: &bitcoin::Address<address::NetworkUnchecked>,
+        new: &bitcoin::Address<address::NetworkUnchecked>,
+        new_amount: u64,
+    ) -> Result<CreateSpendResult, CommandError> {
+        self.check_unlocked(false)?;
+        let mut db_conn = self.db.connection();
+        let prev_psbt = db_conn
+            .spend_tx(txid)
+            .ok_or(CommandError::UnknownSpend(*txid))?;
+        let old = self.validate_address(old.clone())?;
+        let prev_tx = &prev_psbt.unsigned_tx;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GetAddressResult {
-    #[serde(deserialize_with = "deser_addr_assume_checked")]
-    pub address: bitcoin::Address,
-    pub derivation_index: bip32::ChildNumber,
-}
+        // The transaction is rebuilt at the feerate it was created for.
+        let prev_outpoints: Vec<bitcoin::OutPoint> = prev_tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect();
+        let prev_coins = db_conn.coins_by_outpoints(&prev_outpoints);
+        let mut total_in = bitcoin::Amount::ZERO;
+        for op in &prev_outpoints {
+            total_in += prev_coins
+                .get(op)
+                .map(|c| c.amount)
+                .ok_or(CommandError::UnknownOutpoint(*op))?;
+        }
+        let total_out: bitcoin::Amount = prev_tx.output.iter().map(|txo| txo.value).sum();
+        let prev_fee = total_in
+            .checked_sub(total_out)
+            .ok_or(CommandError::SpendCreation(SpendCreationError::InsaneFees(
+                InsaneFeeInfo::NegativeFee,
+            )))?;
+        let vsize = self.config.main_descriptor.unsigned_tx_max_vbytes(prev_tx);
+        let feerate = Feerate::from_sat_per_vb_f64(prev_fee.to_sat() as f64 / vsize as f64)
+            .expect("Fee is a valid amount and vsize is not null");
+
+        // Keep spending through the same path if the previous transaction was a recovery.
+        let force_path = prev_tx
+            .input
+            .iter()
+            .find_map(|txin| match txin.sequence.to_relative_lock_time() {
+                Some(bitcoin::relative::LockTime::Blocks(height)) => Some(height.value()),
+                _ => None,
+            })
+            .and_then(|timelock| {
+                self.config
+                    .main_descriptor
+                    .policy()
+                    .recovery_paths()
+                    .keys()
+                    .position(|t| *t == timelock)
+                    .map(|i| i + 1)
+            });
+
+        // Sort the previous outputs between the destinations and the change, detected from their
+        // BIP32 derivations in the PSBT or from their address. The largest change output is kept
+        // as the change address of the rebuilt transaction. An OP_RETURN output is carried over.
+        let mut destinations = HashMap::new();
+        let mut change: Option<(bitcoin::Address, bitcoin::Amount)> = None;
+        let mut op_return = None;
+        let mut found_old = false;
+        let change_indexes: HashSet<usize> = self
+            .config
+            .main_descriptor
+            .change_indexes(&prev_psbt, &self.secp)
+            .into_iter()
+            .filter_map(|change| match change {
+                descriptors::ChangeOutput::ChangeAddress { index } => Some(index),
+                descriptors::ChangeOutput::DepositAddress { .. } => None,
+            })
+            .collect();
+        for (index, txo) in prev_tx.output.iter().enumerate() {
+            if let Some(data) = op_return_data(&txo.script_pubkey) {
+                op_return = Some(data);
+                continue;
+            }
+            let address = bitcoin::Address::from_script(
+                &txo.script_pubkey,
+                self.config.bitcoin_config.network,
+            )
+            .map_err(|e| CommandError::InvalidPsbt(e.to_string()))?;
+            if address == old {
+                found_old = true;
+            } else if change_indexes.contains(&index)
+                || matches!(
+                    db_conn.derivation_index_by_address(&address),
+                    Some((_, true))
+                )
+            {
+                if change.as_ref().map(|(_, amt)| txo.value > *amt) != Some(false) {
+                    change = Some((address, txo.value));
+                }
+            } else {
+                *destinations
+                    .entry(address.as_unchecked().clone())
+                    .or_insert(0) += txo.value.to_sat();
+            }
+        }
+        if !found_old {
+            return Err(CommandError::NotADestination(*txid, old));
+        }
+        *destinations.entry(new.clone()).or_insert(0) += new_amount;
+
+        // The coins reserved by this draft must be available to rebuild it. The reservations are
+        // restored whatever the outcome, the rebuilt transaction spending the same coins.
+        let draft_id = DraftId::from_psbt(&prev_psbt);
+        let reservations: Vec<_> = self
+            .coin_reservations
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, (_, id))| Some(*id) == draft_id)
+            .map(|(op, reservation)| (*op, *reservation))
+            .collect();
+        if let Some(draft_id) = &draft_id {
+            self.release_coins(draft_id);
+        }
+        let res = self.build_spend(
+            &destinations,
+            &CoinSelector::Outpoints(prev_outpoints.clone()),
+            feerate,
+            &CreateSpendOptions {
+                change_address: change.map(|(addr, _)| addr.as_unchecked().clone()),
+                force_path,
+                allow_unconfirmed: false,
+                op_return,
+                allow_self_send: true,
+                max_inputs: Some(prev_outpoints.len()),
+                ..Default::default()
+            },
+        );
+        self.coin_reservations.lock().unwrap().extend(reservations);
+        let mut res = res?;
 
-impl GetAddressResult {
-    pub fn new(address: bitcoin::Address, derivation_index: bip32::ChildNumber) -> Self {
-        Self {
-            address,
-            derivation_index,
+        if let CreateSpendResult::Success {
+            psbt,
+            warnings,
+            draft_id: new_draft_id,
+            ..
+        } = &mut res
+        {
+            if let Some(draft_id) = draft_id {
+                draft_id.set_in_psbt(psbt);
+                *new_draft_id = draft_id;
+            }
+            let was_signed = prev_psbt.inputs.iter().any(|psbtin| {
+                !psbtin.partial_sigs.is_empty()
+                    || !psbtin.tap_script_sigs.is_empty()
+                    || psbtin.tap_key_sig.is_some()
+            });
+            if was_signed {
+                warnings.push(
+                    "The signatures of the previous transaction were dropped. The updated \
+                     transaction must be signed again."
+                        .to_string(),
+                );
+            }
+            db_conn.store_spend(psbt);
+            inherit_draft_label(&mut db_conn, psbt);
+            if psbt.unsigned_tx.txid() != *txid {
+                db_conn.delete_spend(txid);
+            }
         }
+        Ok(res)
     }
-}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GetLabelsResult {
-    pub labels: HashMap<String, String>,
-}
-
-#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
-pub struct AddressInfo {
-    index: u32,
-    receive: bitcoin::Address,
-    change: bitcoin::Address,
-}
-
-#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
-pub struct ListAddressesResult {
-    addresses: Vec<AddressInfo>,
-}
-
-impl ListAddressesResult {
-    pub fn new(addresses: Vec<AddressInfo>) -> Self {
-        ListAddressesResult { addresses }
+    /// Export the stored Spend transaction with this txid as a sequence of BBQr parts of at most
+    /// `max_part_len` characters each, to be displayed as an animated QR code.
+    pub fn export_spend_psbt_bbqr(
+        &self,
+        txid: &bitcoin::Txid,
+        max_part_len: usize,
+    ) -> Result<Vec<String>, CommandError> {
+        let psbt = self
+            .db
+            .connection()
+            .spend_tx(txid)
+            .ok_or(CommandError::UnknownSpend(*txid))?;
+        bbqr::encode(&psbt.serialize(), bbqr::FILE_TYPE_PSBT, max_part_len)
+            .map_err(CommandError::Bbqr)
     }
-}
-
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-pub struct LCSpendInfo {
-    pub txid: bitcoin::Txid,
-    /// The block height this spending transaction was confirmed at.
-    pub height: Option<i32>,
-}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ListCoinsEntry {
-    #[serde(
-        serialize_with = "ser_amount",
-        deserialize_with = "deser_amount_from_sats"
-    )]
-    pub amount: bitcoin::Amount,
-    pub outpoint: bitcoin::OutPoint,
-    #[serde(
-        serialize_with = "ser_to_string",
-        deserialize_with = "deser_addr_assume_checked"
-    )]
-    pub address: bitcoin::Address,
-    pub block_height: Option<i32>,
-    /// Derivation index used to create the coin deposit address.
-    pub derivation_index: bip32::ChildNumber,
-    /// Information about the transaction spending this coin.
-    pub spend_info: Option<LCSpendInfo>,
-    /// Whether this coin was created by a coinbase transaction that is still immature.
-    pub is_immature: bool,
-    /// Whether the coin deposit address was derived from the change descriptor.
-    pub is_change: bool,
-}
+    /// Reassemble a PSBT from the BBQr parts scanned from an animated QR code, in any order and
+    /// possibly with duplicates, and store it as with [`DaemonControl::update_spend`]. Returns
+    /// the txid of the Spend transaction.
+    pub fn import_spend_psbt_bbqr(&self, parts: &[String]) -> Result<bitcoin::Txid, CommandError> {
+        self.check_unlocked(false)?;
+        let (file_type, data) = bbqr::decode(parts).map_err(CommandError::Bbqr)?;
+        if file_type != bbqr::FILE_TYPE_PSBT {
+            return Err(CommandError::InvalidPsbt(format!(
+                "unexpected BBQr file type '{}'",
+                file_type
+            )));
+        }
+        let psbt =
+            Psbt::deserialize(&data).map_err(|e| CommandError::InvalidPsbt(e.to_string()))?;
+        let txid = psbt.unsigned_tx.txid();
+        self.update_spend(psbt)?;
+        Ok(txid)
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ListCoinsResult {
-    pub coins: Vec<ListCoinsEntry>,
-}
+    /// Set or delete (if the value is `None`) the labels of these items. Returns the items whose
+    /// label was actually created, updated or deleted.
+    pub fn update_labels(
+        &self,
+        items: &HashMap<LabelItem, Option<String>>,
+    ) -> Result<LabelUpdateSummary, CommandError> {
+        self.check_unlocked(false)?;
+        let mut db_conn = self.db.connection();
+        let existing = db_conn.labels(&items.keys().cloned().collect());
+        let mut summary = LabelUpdateSummary::default();
+        for (item, value) in items {
+            match (existing.get(&item.to_string()), value) {
+                (None, Some(_)) => summary.created.push(item.clone()),
+                (Some(prev), Some(value)) if prev != value => summary.updated.push(item.clone()),
+                (Some(_), None) => summary.deleted.push(item.clone()),
+                _ => {}
+            }
+        }
+        db_conn.update_labels(items);
+        Ok(summary)
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(untagged)]
-pub enum CreateSpendResult {
-    Success {
-        #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_fromstr")]
-        psbt: Psbt,
-        warnings: Vec<String>,
-    },
-    InsufficientFunds {
-        missing: u64,
-    },
-}
+    /// Replace all the labels of this wallet with these ones, for instance when restoring them
+    /// from a backup. Either all the existing labels are replaced or none is.
+    pub fn replace_all_labels(
+        &self,
+        labels: &HashMap<LabelItem, String>,
+    ) -> Result<ReplaceLabelsResult, CommandError> {
+        self.check_unlocked(false)?;
+        let deleted = self.db.connection().replace_labels(labels);
+        Ok(ReplaceLabelsResult {
+            deleted,
+            stored: labels.len(),
+        })
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ListSpendEntry {
-    #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_fromstr")]
-    pub psbt: Psbt,
-    pub updated_at: Option<u32>,
-}
+    pub fn get_labels(&self, items: &HashSet<LabelItem>) -> GetLabelsResult {
+        let mut db_conn = self.db.connection();
+        GetLabelsResult {
+            labels: db_conn.labels(items),
+        }
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ListSpendResult {
-    pub spend_txs: Vec<ListSpendEntry>,
-}
+    /// Get the labels related to this transaction at once: the label of the transaction itself,
+    /// of the coins it spends and of the addresses it pays to. The transaction may be one of our
+    /// wallet transactions or a stored Spend transaction draft.
+    pub fn tx_related_labels(&self, txid: &bitcoin::Txid) -> Result<TxLabels, CommandError> {
+        let mut db_conn = self.db.connection();
+        let tx = self
+            .bitcoin
+            .wallet_transaction(txid)
+            .map(|(tx, _)| tx)
+            .or_else(|| db_conn.spend_tx(txid).map(|psbt| psbt.unsigned_tx))
+            .ok_or(CommandError::UnknownTransaction(*txid))?;
+
+        let network = self.config.bitcoin_config.network;
+        let outpoints: Vec<bitcoin::OutPoint> =
+            tx.input.iter().map(|txin| txin.previous_output).collect();
+        let addresses: Vec<bitcoin::Address> = tx
+            .output
+            .iter()
+            .filter_map(|txo| bitcoin::Address::from_script(&txo.script_pubkey, network).ok())
+            .collect();
+        let items: HashSet<LabelItem> = outpoints
+            .iter()
+            .map(|op| LabelItem::OutPoint(*op))
+            .chain(addresses.iter().cloned().map(LabelItem::Address))
+            .chain(std::iter::once(LabelItem::Txid(*txid)))
+            .collect();
+        let mut labels = db_conn.labels(&items);
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ListTransactionsResult {
-    pub transactions: Vec<TransactionInfo>,
-}
+        // Group the labels by the item they are for.
+        let mut take_labels = |keys: Vec<String>| -> HashMap<String, String> {
+            keys.into_iter()
+                .filter_map(|key| labels.remove(&key).map(|label| (key, label)))
+                .collect()
+        };
+        let inputs = take_labels(outpoints.iter().map(|op| op.to_string()).collect());
+        let outputs = take_labels(addresses.iter().map(|addr| addr.to_string()).collect());
+        Ok(TxLabels {
+            transaction: labels.remove(&txid.to_string()),
+            inputs,
+            outputs,
+        })
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TransactionInfo {
-    #[serde(serialize_with = "ser_hex", deserialize_with = "deser_hex")]
-    pub tx: bitcoin::Transaction,
-    pub height: Option<i32>,
-    pub time: Option<u32>,
-}
+    /// Get a page of all the labels stored for this wallet, in insertion order.
+    pub fn export_labels_page(&self, offset: u64, limit: u64) -> ExportLabelsResult {
+        let mut db_conn = self.db.connection();
+        let labels = db_conn
+            .labels_page(offset, limit)
+            .into_iter()
+            .map(|(item, value)| LabelEntry { item, value })
+            .collect();
+        ExportLabelsResult { labels }
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
-pub struct CreateRecoveryResult {
-    #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_fromstr")]
-    pub psbt: Psbt,
-}
+    /// Get all the labels stored for this wallet, in insertion order. They are queried from
+    /// the database by chunks, so the caller doesn't have to know the labelled items up front.
+    pub fn export_all_labels(&self) -> ExportLabelsResult {
+        let mut db_conn = self.db.connection();
+        let mut labels = Vec::new();
+        loop {
+            let page = db_conn.labels_page(labels.len() as u64, LABELS_EXPORT_CHUNK_SIZE);
+            let page_len = page.len() as u64;
+            labels.extend(
+                page.into_iter()
+                    .map(|(item, value)| LabelEntry { item, value }),
+            );
+            if page_len < LABELS_EXPORT_CHUNK_SIZE {
+                break;
+            }
+        }
+        ExportLabelsResult { labels }
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{bitcoin::Block, database::BlockInfo, spend::InsaneFeeInfo, testutils::*};
+    /// The height of the earliest block relevant to this wallet, from which the chain needs to be
+    /// scanned to find all its coins. This is the height of the first block containing one of our
+    /// coins, or the height at wallet creation if lower or if no coin was ever received. Falls
+    /// back to the genesis block if the height at wallet creation can't be determined.
+    pub fn wallet_birthday(&self) -> u32 {
+        let mut db_conn = self.db.connection();
+        let creation_height = self
+            .bitcoin
+            .block_before_date(db_conn.timestamp())
+            .map(|block| block.height);
+        let first_coin_height = db_conn
+            .coins(&[], &[])
+            .into_values()
+            .filter_map(|coin| coin.block_info.map(|b| b.height))
+            .min();
+        let height = creation_height
+            .into_iter()
+            .chain(first_coin_height)
+            .min()
+            .unwrap_or(0);
+        u32::try_from(height).expect("Block heights are never negative")
+    }
 
-    use bitcoin::{
-        bip32::{self, ChildNumber},
-        blockdata::transaction::{TxIn, TxOut, Version as TxVersion},
-        locktime::absolute,
-        Amount, OutPoint, ScriptBuf, Sequence, Transaction, Txid, Witness,
-    };
-    use std::{collections::BTreeMap, str::FromStr};
+    /// Export the state of the wallet which can't be recovered from the chain: the descriptor, the
+    /// derivation indexes, the labels (including the address book) and the stored Spend
+    /// transactions, along with the wallet birthday. It does not contain any private key. See
+    /// [`crate::DaemonHandle::import_wallet`].
+    pub fn export_wallet(&self) -> WalletExport {
+        let mut db_conn = self.db.connection();
+        let labels = self
+            .export_all_labels()
+            .labels
+            .into_iter()
+            .map(|LabelEntry { item, value }| (item.to_string(), value))
+            .collect();
+        let coin_notes = db_conn
+            .coin_notes(&[])
+            .into_iter()
+            .map(|(outpoint, note)| (outpoint.to_string(), note))
+            .collect();
+        let spend_txs = db_conn
+            .list_spend()
+            .into_iter()
+            .map(|(psbt, _)| psbt)
+            .collect();
+        WalletExport {
+            network: self.config.bitcoin_config.network,
+            descriptor: self.config.main_descriptor.clone(),
+            timestamp: db_conn.timestamp(),
+            birthday_height: Some(self.wallet_birthday()),
+            receive_index: db_conn.receive_index().into(),
+            change_index: db_conn.change_index().into(),
+            labels,
+            coin_notes,
+            spend_txs,
+        }
+    }
 
-    #[test]
-    fn getinfo() {
-        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
-        // We can query getinfo
-        ms.control().get_info();
-        ms.shutdown();
+    /// Get the receive and change descriptors in the format of the `importdescriptors` command of
+    /// Bitcoin Core, to watch this wallet there. The addresses up to 1000 indexes past the next
+    /// derivation indexes are imported. Bitcoin Core rescans the chain from the
+    /// wallet birthday (see [`DaemonControl::wallet_birthday`]), given as the wallet creation time
+    /// or the time of the first block containing one of our coins if earlier.
+    pub fn export_core_descriptors(&self) -> Vec<CoreImportDescriptor> {
+        let mut db_conn = self.db.connection();
+        let first_coin_time = db_conn
+            .coins(&[], &[])
+            .into_values()
+            .filter_map(|coin| coin.block_info.map(|b| b.time))
+            .min();
+        let timestamp = first_coin_time
+            .into_iter()
+            .chain(Some(db_conn.timestamp()))
+            .min()
+            .expect("There is always the creation time");
+        let desc = &self.config.main_descriptor;
+        [
+            (desc.receive_descriptor(), db_conn.receive_index(), false),
+            (desc.change_descriptor(), db_conn.change_index(), true),
+        ]
+        .iter()
+        .map(|(desc, next_index, internal)| CoreImportDescriptor {
+            desc: desc.to_string(),
+            range: [
+                0,
+                u32::from(*next_index).saturating_add(CORE_IMPORT_RANGE_MARGIN),
+            ],
+            timestamp,
+            internal: *internal,
+        })
+        .collect()
     }
 
-    #[test]
-    fn getnewaddress() {
-        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+    /// Name this external address in the address book, or remove it from the address book if
+    /// `label` is `None`. The address book is stored as regular address labels.
+    pub fn set_destination_label(
+        &self,
+        address: bitcoin::Address<address::NetworkUnchecked>,
+        label: Option<String>,
+    ) -> Result<(), CommandError> {
+        self.check_unlocked(false)?;
+        let address = self.validate_address(address)?;
+        let mut db_conn = self.db.connection();
+        if self.addr_info(&mut db_conn, &address).is_some() {
+            return Err(CommandError::NotExternalAddress(address));
+        }
+        let mut items = HashMap::new();
+        items.insert(LabelItem::Address(address), label);
+        db_conn.update_labels(&items);
+        Ok(())
+    }
 
-        let control = &ms.control();
-        // We can get an address
-        let addr = control.get_new_address().address;
-        assert_eq!(
-            addr,
-            bitcoin::Address::from_str(
-                "bc1q9ksrc647hx8zp2cewl8p5f487dgux3777yees8rjcx46t4daqzzqt7yga8"
-            )
-            .unwrap()
-            .assume_checked()
-        );
-        // We won't get the same twice.
-        let addr2 = control.get_new_address().address;
-        assert_ne!(addr, addr2);
+    /// Tell, for each of these addresses, whether it is one of our receive or change addresses
+    /// along with its derivation index, or whether it isn't ours.
+    pub fn classify_addresses(
+        &self,
+        addresses: &[bitcoin::Address<address::NetworkUnchecked>],
+    ) -> Result<ClassifyAddressesResult, CommandError> {
+        let mut db_conn = self.db.connection();
+        let addresses = addresses
+            .iter()
+            .map(|addr| {
+                let addr = self.validate_address(addr.clone())?;
+                let kind = match self.addr_info(&mut db_conn, &addr) {
+                    Some(AddrInfo {
+                        index,
+                        is_change: false,
+                    }) => AddressKind::Receive {
+                        derivation_index: index,
+                    },
+                    Some(AddrInfo {
+                        index,
+                        is_change: true,
+                    }) => AddressKind::Change {
+                        derivation_index: index,
+                    },
+                    None => AddressKind::External,
+                };
+                Ok((addr.to_string(), kind))
+            })
+            .collect::<Result<_, CommandError>>()?;
+        Ok(ClassifyAddressesResult { addresses })
+    }
 
-        ms.shutdown();
+    /// Set a freeform note on one of our coins, or remove it if `None`. Unlike a label, a note
+    /// may span multiple lines and be up to [`MAX_COIN_NOTE_LENGTH`] characters long.
+    pub fn set_coin_note(
+        &self,
+        outpoint: &bitcoin::OutPoint,
+        note: Option<String>,
+    ) -> Result<(), CommandError> {
+        self.check_unlocked(false)?;
+        let mut db_conn = self.db.connection();
+        if db_conn.coins_by_outpoints(&[*outpoint]).is_empty() {
+            return Err(CommandError::UnknownOutpoint(*outpoint));
+        }
+        if note
+            .as_ref()
+            .map(|n| n.chars().count() > MAX_COIN_NOTE_LENGTH)
+            .unwrap_or(false)
+        {
+            return Err(CommandError::CoinNoteTooLong(*outpoint));
+        }
+        db_conn.set_coin_note(outpoint, note.as_deref());
+        Ok(())
     }
 
-    #[test]
-    fn listaddresses() {
-        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+    /// Get the address book: all the external addresses which have a label.
+    pub fn list_destination_labels(&self) -> ListDestinationLabelsResult {
+        let mut db_conn = self.db.connection();
+        let destinations = self
+            .export_all_labels()
+            .labels
+            .into_iter()
+            .filter_map(|LabelEntry { item, value }| match item {
+                LabelItem::Address(address) if self.addr_info(&mut db_conn, &address).is_none() => {
+                    Some(DestinationLabel {
+                        address,
+                        label: value,
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        ListDestinationLabelsResult { destinations }
+    }
 
-        let control = &ms.control();
+    /// List the stored Spend transactions along with their status, optionally only those with
+    /// the given txids and among the given statuses. An empty list of statuses doesn't filter.
+    pub fn list_spend(
+        &self,
+        txids: Option<Vec<bitcoin::Txid>>,
+        statuses: &[SpendStatus],
+    ) -> Result<ListSpendResult, CommandError> {
+        if let Some(ids) = &txids {
+            if ids.is_empty() {
+                return Err(CommandError::EmptyFilterList);
+            }
+        }
 
-        let list = control.list_addresses(Some(2), Some(5)).unwrap();
+        let mut db_conn = self.db.connection();
+        let spend_psbts = db_conn.list_spend();
 
-        assert_eq!(list.addresses[0].index, 2);
-        assert_eq!(list.addresses.last().unwrap().index, 6);
+        // A Spend transaction was broadcast if any of the coins it spends is marked as being
+        // spent by it.
+        let outpoints: Vec<bitcoin::OutPoint> = spend_psbts
+            .iter()
+            .flat_map(|(psbt, _)| {
+                psbt.unsigned_tx
+                    .input
+                    .iter()
+                    .map(|txin| txin.previous_output)
+            })
+            .collect();
+        let broadcast_txids: HashSet<bitcoin::Txid> = db_conn
+            .coins_by_outpoints(&outpoints)
+            .into_values()
+            .filter_map(|coin| coin.spend_txid)
+            .collect();
 
-        let addr0 = control.get_new_address().address;
-        let addr1 = control.get_new_address().address;
-        let _addr2 = control.get_new_address().address;
-        let addr3 = control.get_new_address().address;
-        let addr4 = control.get_new_address().address;
+        let txids_set: Option<HashSet<_>> = txids.as_ref().map(|list| list.iter().collect());
+        let spend_txs = spend_psbts
+            .into_iter()
+            .filter_map(|(psbt, updated_at)| {
+                let txid = psbt.unsigned_tx.txid();
+                if let Some(set) = &txids_set {
+                    if !set.contains(&txid) {
+                        return None;
+                    }
+                }
+                let status = if broadcast_txids.contains(&txid) {
+                    SpendStatus::Broadcast
+                } else {
+                    SpendStatus::from_psbt(&psbt, &self.secp)
+                };
+                if !statuses.is_empty() && !statuses.contains(&status) {
+                    return None;
+                }
+                Some(ListSpendEntry {
+                    draft_id: DraftId::from_psbt(&psbt),
+                    psbt,
+                    updated_at,
+                    status,
+                })
+            })
+            .collect();
+        Ok(ListSpendResult { spend_txs })
+    }
 
-        let list = control.list_addresses(Some(0), None).unwrap();
-
-        assert_eq!(list.addresses[0].index, 0);
-        assert_eq!(list.addresses[0].receive, addr0);
-        assert_eq!(list.addresses.last().unwrap().index, 4);
-        assert_eq!(list.addresses.last().unwrap().receive, addr4);
+    /// Delete the referenced Spend transaction(s) from database. The coins reserved by their draft
+    /// are released.
+    pub fn delete_spend(&self, spend_ref: &SpendRef) -> Result<(), CommandError> {
+        self.check_unlocked(false)?;
+        let mut db_conn = self.db.connection();
+        match spend_ref {
+            SpendRef::Txid(txid) => {
+                if let Some(draft_id) = db_conn.spend_tx(txid).and_then(|p| DraftId::from_psbt(&p))
+                {
+                    self.release_coins(&draft_id);
+                }
+                db_conn.delete_spend(txid);
+            }
+            SpendRef::DraftId(draft_id) => {
+                self.release_coins(draft_id);
+                for (psbt, _) in db_conn.list_spend() {
+                    if DraftId::from_psbt(&psbt).as_ref() == Some(draft_id) {
+                        db_conn.delete_spend(&psbt.unsigned_tx.txid());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
 
-        let list = control.list_addresses(None, None).unwrap();
+    // Make sure we didn't broadcast a replacement for this transaction, to avoid broadcasting both
+    // versions of it.
+    fn check_not_replaced(
+        &self,
+        db_conn: &mut Box<dyn DatabaseConnection>,
+        txid: &bitcoin::Txid,
+    ) -> Result<(), CommandError> {
+        match db_conn.replaced_by(&[*txid]).get(txid) {
+            Some(replacement_txid) => Err(CommandError::ReplacedBy(*replacement_txid)),
+            None => Ok(()),
+        }
+    }
 
-        assert_eq!(list.addresses[0].index, 0);
-        assert_eq!(list.addresses[0].receive, addr0);
-        assert_eq!(list.addresses.last().unwrap().index, 4);
-        assert_eq!(list.addresses.last().unwrap().receive, addr4);
+    // Make sure no replacement was drafted and stored for this transaction, to avoid broadcasting
+    // both versions of it.
+    fn check_not_superseded(
+        &self,
+        db_conn: &mut Box<dyn DatabaseConnection>,
+        txid: &bitcoin::Txid,
+    ) -> Result<(), CommandError> {
+        match db_conn
+            .replacement_drafts(txid)
+            .into_iter()
+            .find(|replacement_txid| db_conn.spend_tx(replacement_txid).is_some())
+        {
+            Some(replacement_txid) => Err(CommandError::SupersededByReplacement(replacement_txid)),
+            None => Ok(()),
+        }
+    }
 
-        let list = control.list_addresses(Some(1), Some(3)).unwrap();
+    // Get this stored Spend transaction, finalized with the elements contained in its PSBT.
+    fn finalized_spend(
+        &self,
+        db_conn: &mut Box<dyn DatabaseConnection>,
+        txid: &bitcoin::Txid,
+    ) -> Result<Psbt, CommandError> {
+        let mut spend_psbt = db_conn
+            .spend_tx(txid)
+            .ok_or(CommandError::UnknownSpend(*txid))?;
+        spend_psbt.finalize_mut(&self.secp).map_err(|e| {
+            CommandError::SpendFinalization(
+                e.into_iter()
+                    .next()
+                    .map(|e| e.to_string())
+                    .unwrap_or_default(),
+            )
+        })?;
+        Ok(spend_psbt)
+    }
 
-        assert_eq!(list.addresses[0].index, 1);
-        assert_eq!(list.addresses[0].receive, addr1);
-        assert_eq!(list.addresses.last().unwrap().index, 3);
-        assert_eq!(list.addresses.last().unwrap().receive, addr3);
+    /// Finalize this stored Spend transaction and check whether it would be accepted to the
+    /// mempool of our Bitcoin backend, without broadcasting it.
+    pub fn test_broadcast_spend(
+        &self,
+        txid: &bitcoin::Txid,
+    ) -> Result<TestBroadcastResult, CommandError> {
+        let mut db_conn = self.db.connection();
+        let final_tx = self
+            .finalized_spend(&mut db_conn, txid)?
+            .extract_tx_unchecked_fee_rate();
+        let res = self
+            .bitcoin
+            .test_mempool_accept(&final_tx)
+            .map_err(CommandError::TxBroadcast)?;
+        Ok(TestBroadcastResult {
+            accepted: res.allowed,
+            reject_reason: res.reject_reason,
+            vsize: res.vsize,
+            fee: res.fee.map(|fee| fee.to_sat()),
+        })
+    }
 
-        let addr5 = control.get_new_address().address;
-        let list = control.list_addresses(Some(5), None).unwrap();
+    /// Finalize and broadcast this stored Spend transaction. If `test_first` is set, the
+    /// transaction is only broadcast if our Bitcoin backend would accept it to its mempool (see
+    /// [`Self::test_broadcast_spend`]).
+    ///
+    /// Once a replacement was drafted for this transaction (see [`Self::rbf_psbt`]) and stored,
+    /// it can't be broadcast anymore unless the replacement is deleted.
+    pub fn broadcast_spend(
+        &self,
+        txid: &bitcoin::Txid,
+        test_first: bool,
+    ) -> Result<(), CommandError> {
+        self.check_unlocked(false)?;
+        let mut db_conn = self.db.connection();
+        self.check_not_superseded(&mut db_conn, txid)?;
 
-        assert_eq!(list.addresses[0].index, 5);
-        assert_eq!(list.addresses[0].receive, addr5);
-        assert_eq!(list.addresses.last().unwrap().index, 5);
-        assert_eq!(list.addresses.last().unwrap().receive, addr5);
+        // First, try to finalize the spending transaction with the elements contained
+        // in the PSBT.
+        let spend_psbt = self.finalized_spend(&mut db_conn, txid)?;
 
-        // We can get no address for the last unhardened index.
-        let max_unhardened_index = 2u32.pow(31) - 1;
-        let res = control
-            .list_addresses(Some(max_unhardened_index), Some(0))
-            .unwrap();
-        // This is equivalent to not passing a count.
-        assert_eq!(
-            res,
-            control
-                .list_addresses(Some(max_unhardened_index), None)
-                .unwrap()
-        );
-        // We can also get the one last unhardened index.
-        control
-            .list_addresses(Some(max_unhardened_index), Some(1))
-            .unwrap();
-        // However we can't get into hardened territory.
-        assert_eq!(
-            control
-                .list_addresses(Some(max_unhardened_index), Some(2))
-                .unwrap_err(),
-            CommandError::InvalidDerivationIndex
-        );
+        // Then, broadcast it (or try to, we never know if we are not going to hit an
+        // error at broadcast time).
+        // These checks are already performed at Spend creation time. TODO: a belt-and-suspenders is still worth it though.
+        let draft_id = DraftId::from_psbt(&spend_psbt);
+        let final_tx = spend_psbt.extract_tx_unchecked_fee_rate();
+        let final_txid = final_tx.txid();
+        if test_first {
+            let res = self
+                .bitcoin
+                .test_mempool_accept(&final_tx)
+                .map_err(CommandError::TxBroadcast)?;
+            if !res.allowed {
+                return Err(CommandError::TxBroadcast(format!(
+                    "transaction would be rejected: {}",
+                    res.reject_reason.unwrap_or_default()
+                )));
+            }
+        }
+        // If this transaction spends coins which were already spent by another transaction of
+        // ours, it replaces it. Gather those before the poller updates the coins' spend txid.
+        let prev_outpoints: Vec<bitcoin::OutPoint> = final_tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect();
+        let replaced_txids: Vec<bitcoin::Txid> = db_conn
+            .coins_by_outpoints(&prev_outpoints)
+            .values()
+            .filter_map(|coin| coin.spend_txid)
+            .filter(|spend_txid| *spend_txid != final_txid)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        self.bitcoin
+            .broadcast_tx(&final_tx)
+            .map_err(CommandError::TxBroadcast)?;
+        if let Some(draft_id) = draft_id {
+            self.release_coins(&draft_id);
+        }
+        if !replaced_txids.is_empty() {
+            db_conn.record_replacements(&replaced_txids, &final_txid);
+        }
 
-        // We also can't pass a hardened start index.
-        let first_hardened_index = max_unhardened_index + 1;
-        assert_eq!(
-            control
-                .list_addresses(Some(first_hardened_index), None)
-                .unwrap_err(),
-            CommandError::InvalidDerivationIndex
-        );
-        assert_eq!(
-            control
-                .list_addresses(Some(first_hardened_index), Some(0))
-                .unwrap_err(),
-            CommandError::InvalidDerivationIndex
-        );
-        assert_eq!(
-            control
-                .list_addresses(Some(first_hardened_index), Some(1))
-                .unwrap_err(),
-            CommandError::InvalidDerivationIndex
-        );
+        // Finally, update our state with the changes from this transaction.
+        let (tx, rx) = mpsc::sync_channel(0);
+        if let Err(e) = self.poller_sender.send(PollerMessage::PollNow(tx)) {
+            log::error!("Error requesting update from poller: {}", e);
+        }
+        if let Err(e) = rx.recv() {
+            log::error!("Error receiving completion signal from poller: {}", e);
+        }
 
-        // Much less so overflow.
-        assert_eq!(
-            control.list_addresses(Some(u32::MAX), None).unwrap_err(),
-            CommandError::InvalidDerivationIndex
-        );
-        assert_eq!(
-            control.list_addresses(Some(u32::MAX), Some(0)).unwrap_err(),
-            CommandError::InvalidDerivationIndex
-        );
-        assert_eq!(
-            control.list_addresses(Some(u32::MAX), Some(1)).unwrap_err(),
-            CommandError::InvalidDerivationIndex
-        );
+        Ok(())
+    }
 
-        // We won't crash if we pass a start index larger than the next derivation index without
-        // passing a count. (ie no underflow.)
-        let next_deriv_index = list.addresses.last().unwrap().index + 1;
-        control
-            .list_addresses(Some(next_deriv_index + 1), None)
-            .unwrap();
+    /// Rebroadcast all our transactions which are spending coins and are not confirmed yet, for
+    /// instance to get them back into the mempool of the Bitcoin backend after it was restarted.
+    ///
+    /// Returns the result of the broadcast for each of these transactions. Transactions which got
+    /// confirmed since we last polled are skipped. Transactions for which we broadcast a
+    /// replacement aren't broadcast. Unlike with [`Self::broadcast_spend`], merely drafting and
+    /// storing a replacement doesn't prevent the rebroadcast of the original transaction, as it
+    /// may still be the one in use.
+    pub fn rebroadcast_unconfirmed(&self) -> Result<BroadcastResults, CommandError> {
+        self.check_unlocked(false)?;
+        let mut db_conn = self.db.connection();
+        let txids: HashSet<bitcoin::Txid> = db_conn
+            .list_spending_coins()
+            .values()
+            .filter_map(|coin| coin.spend_txid)
+            .collect();
 
-        ms.shutdown();
+        Ok(txids
+            .into_iter()
+            .filter_map(|txid| {
+                let tx = match self.bitcoin.wallet_transaction(&txid) {
+                    Some((_, Some(_))) => return None,
+                    Some((tx, None)) => tx,
+                    None => return Some((txid, Err(CommandError::UnknownSpend(txid)))),
+                };
+                let res = self.check_not_replaced(&mut db_conn, &txid).and_then(|_| {
+                    self.bitcoin
+                        .broadcast_tx(&tx)
+                        .map_err(CommandError::TxBroadcast)
+                });
+                Some((txid, res))
+            })
+            .collect())
     }
 
-    #[test]
-    fn create_spend() {
-        let dummy_op = bitcoin::OutPoint::from_str(
-            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
-        )
-        .unwrap();
-        let mut dummy_bitcoind = DummyBitcoind::new();
-        dummy_bitcoind.txs.insert(
-            dummy_op.txid,
-            (
-                bitcoin::Transaction {
-                    version: TxVersion::TWO,
-                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
-                    input: vec![],
-                    output: vec![],
-                },
-                None,
-            ),
-        );
-        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
-        let control = &ms.control();
+    /// Get the status of this transaction from the point of view of the Bitcoin backend: whether
+    /// it is confirmed, sitting in the mempool or neither (for instance because it was evicted).
+    pub fn get_mempool_status(&self, txid: &bitcoin::Txid) -> MempoolStatus {
+        if let Some((_, Some(block))) = self.bitcoin.wallet_transaction(txid) {
+            return MempoolStatus::Confirmed {
+                height: block.height,
+            };
+        }
+        match self.bitcoin.mempool_entry(txid) {
+            Some(entry) => MempoolStatus::InMempool {
+                fee: entry.fees.base,
+                vsize: entry.vsize,
+                ancestor_count: entry.ancestor_count,
+                descendant_count: entry.descendant_count,
+            },
+            None => MempoolStatus::NotInMempool,
+        }
+    }
 
-        // Arguments sanity checking
-        let dummy_addr =
-            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
-        let dummy_value = 10_000;
-        let mut destinations = <HashMap<bitcoin::Address<address::NetworkUnchecked>, u64>>::new();
-        assert_eq!(
-            control.create_spend(&destinations, &[], 1, None),
-            Err(CommandError::NoOutpointForSelfSend)
-        );
-        destinations = [(dummy_addr.clone(), dummy_value)]
-            .iter()
-            .cloned()
-            .collect();
-        // Insufficient funds for coin selection.
-        assert!(matches!(
-            control.create_spend(&destinations, &[], 1, None),
-            Ok(CreateSpendResult::InsufficientFunds { .. }),
-        ));
-        assert_eq!(
-            control.create_spend(&destinations, &[dummy_op], 0, None),
-            Err(CommandError::InvalidFeerate(0))
-        );
+    /// Get the fee and size of the package formed by this unconfirmed transaction and its
+    /// unconfirmed ancestors in the mempool of the Bitcoin backend, for instance to size the fee
+    /// of a child transaction bumping it.
+    pub fn package_info(&self, txid: &bitcoin::Txid) -> Result<PackageInfo, CommandError> {
+        let entry = self
+            .bitcoin
+            .mempool_entry(txid)
+            .ok_or(CommandError::NotInMempool(*txid))?;
+        Ok(PackageInfo {
+            ancestor_count: entry.ancestor_count,
+            fee: entry.fees.ancestor,
+            vsize: entry.ancestor_vsize,
+            feerate_vb: entry.fees.ancestor.to_sat() / entry.ancestor_vsize.max(1),
+        })
+    }
 
-        // The coin doesn't exist. If we create a new unspent one at this outpoint with a much
-        // higher value, we'll get a Spend transaction with a change output.
-        assert_eq!(
-            control.create_spend(&destinations, &[dummy_op], 1, None),
-            Err(CommandError::UnknownOutpoint(dummy_op))
-        );
-        let mut db_conn = control.db().lock().unwrap().connection();
-        db_conn.new_unspent_coins(&[Coin {
-            outpoint: dummy_op,
-            is_immature: false,
-            block_info: None,
-            amount: bitcoin::Amount::from_sat(100_000),
-            derivation_index: bip32::ChildNumber::from(13),
-            is_change: false,
-            spend_txid: None,
-            spend_block: None,
-        }]);
-        // If we try to use coin selection, the unconfirmed non-change coin will not be used
-        // as a candidate and so we get a coin selection error due to insufficient funds.
-        assert!(matches!(
-            control.create_spend(&destinations, &[], 1, None),
-            Ok(CreateSpendResult::InsufficientFunds { .. }),
-        ));
-        let (psbt, warnings) = if let CreateSpendResult::Success { psbt, warnings } = control
-            .create_spend(&destinations, &[dummy_op], 1, None)
-            .unwrap()
+    /// List our unconfirmed transactions, either receiving or spending coins, along with the ones
+    /// among them each depends on. A transaction can't confirm before its unconfirmed parents, so
+    /// this shows the chains of transactions which may be bumped using CPFP.
+    ///
+    /// Transactions are sorted so that each comes after its parents.
+    pub fn pending_transactions(&self) -> PendingTransactionsResult {
+        let mut db_conn = self.db.connection();
+        let mut txids = HashSet::new();
+        for coin in db_conn
+            .coins(&[CoinStatus::Unconfirmed, CoinStatus::Spending], &[])
+            .values()
         {
-            (psbt, warnings)
-        } else {
-            panic!("expect successful spend creation")
+            if coin.block_info.is_none() {
+                txids.insert(coin.outpoint.txid);
+            }
+            if let (Some(spend_txid), None) = (coin.spend_txid, coin.spend_block) {
+                txids.insert(spend_txid);
+            }
+        }
+
+        // Transactions which got confirmed since we last polled are skipped.
+        let mut pending: Vec<(bitcoin::Txid, bitcoin::Transaction)> = txids
+            .into_iter()
+            .filter_map(|txid| match self.bitcoin.wallet_transaction(&txid) {
+                Some((tx, None)) => Some((txid, tx)),
+                _ => None,
+            })
+            .collect();
+        pending.sort_by_key(|(txid, _)| *txid);
+        let pending_txids: HashSet<bitcoin::Txid> = pending.iter().map(|(txid, _)| *txid).collect();
+        let parents_of = |tx: &bitcoin::Transaction| -> Vec<bitcoin::Txid> {
+            let mut parents: Vec<_> = tx
+                .input
+                .iter()
+                .map(|txin| txin.previous_output.txid)
+                .filter(|txid| pending_txids.contains(txid))
+                .collect();
+            parents.sort();
+            parents.dedup();
+            parents
         };
-        assert!(psbt.inputs[0].non_witness_utxo.is_some());
-        let tx = psbt.unsigned_tx;
-        assert_eq!(tx.input.len(), 1);
-        assert_eq!(tx.input[0].previous_output, dummy_op);
-        assert_eq!(tx.output.len(), 2);
-        // It has change so no warnings expected.
-        assert!(warnings.is_empty());
-        assert_eq!(
-            tx.output[0].script_pubkey,
-            dummy_addr.payload().script_pubkey()
-        );
-        assert_eq!(tx.output[0].value.to_sat(), dummy_value);
 
-        // NOTE: if you are wondering about the usefulness of these tests asserting arbitrary fixed
-        // values, that's a belt-and-suspenders check to make sure size and fee calculations do not
-        // change unexpectedly. For instance this specific test caught how a change in
-        // rust-bitcoin's serialization of transactions with no input silently affected our fee
-        // calculation.
+        let mut transactions = Vec::with_capacity(pending.len());
+        let mut listed = HashSet::new();
+        while !pending.is_empty() {
+            // List the transactions whose parents were all listed already. There is always at
+            // least one, as transactions can't depend on each other in a cycle.
+            let (ready, rest): (Vec<_>, Vec<_>) = pending
+                .into_iter()
+                .partition(|(_, tx)| parents_of(tx).iter().all(|p| listed.contains(p)));
+            assert!(!ready.is_empty(), "Transactions can't form a cycle.");
+            for (txid, tx) in ready {
+                let feerate_vb = self
+                    .bitcoin
+                    .mempool_entry(&txid)
+                    .map(|entry| entry.fees.base.to_sat() / entry.vsize.max(1));
+                listed.insert(txid);
+                transactions.push(PendingTransaction {
+                    txid,
+                    parents: parents_of(&tx),
+                    feerate_vb,
+                });
+            }
+            pending = rest;
+        }
 
-        // Transaction is 1 in (P2WSH satisfaction), 2 outs. At 1sat/vb, it's 170 sats fees.
-        // At 2sats/vb, it's twice that.
-        assert_eq!(tx.output[1].value.to_sat(), 89_830);
-        let psbt = if let CreateSpendResult::Success { psbt, .. } = control
-            .create_spend(&destinations, &[dummy_op], 2, None)
-            .unwrap()
-        {
-            psbt
-        } else {
-            panic!("expect successful spend creation")
-        };
-        let tx = psbt.unsigned_tx;
-        assert_eq!(tx.output[1].value.to_sat(), 89_660);
+        PendingTransactionsResult { transactions }
+    }
 
-        // A feerate of 555 won't trigger the sanity checks (they were previously not taking the
-        // satisfaction size into account and overestimating the feerate).
-        control
-            .create_spend(&destinations, &[dummy_op], 555, None)
-            .unwrap();
+    /// Write a copy of the database encrypted with this key next to it in the network
+    /// subdirectory of the data directory, as `lianad.encrypted.sqlite3`. In order to use it, stop the daemon, replace the database with
+    /// the encrypted copy and set the key in the configuration.
+    pub fn encrypt_database(&self, encryption_key: &DbEncryptionKey) -> Result<(), CommandError> {
+        self.check_unlocked(false)?;
+        let mut dest_path = self
+            .config
+            .data_dir()
+            .ok_or_else(|| CommandError::DatabaseEncryption("No data directory".to_string()))?;
+        dest_path.push(self.config.bitcoin_config.network.to_string());
+        dest_path.push("lianad.encrypted.sqlite3");
+        self.db
+            .connection()
+            .export_encrypted(&dest_path, encryption_key)
+            .map_err(CommandError::DatabaseEncryption)
+    }
 
-        // If we ask for a too high feerate, or a too large/too small output, it'll fail.
-        assert!(matches!(
-            control.create_spend(&destinations, &[dummy_op], 10_000, None),
-            Ok(CreateSpendResult::InsufficientFunds { .. }),
-        ));
-        *destinations.get_mut(&dummy_addr).unwrap() = 100_001;
-        assert!(matches!(
-            control.create_spend(&destinations, &[dummy_op], 1, None),
-            Ok(CreateSpendResult::InsufficientFunds { .. }),
-        ));
-        *destinations.get_mut(&dummy_addr).unwrap() = 4_500;
-        assert_eq!(
-            control.create_spend(&destinations, &[dummy_op], 1, None),
-            Err(CommandError::SpendCreation(
-                SpendCreationError::InvalidOutputValue(bitcoin::Amount::from_sat(4_500))
-            ))
-        );
+    /// Get the number of rows in each table of the database and its size, to monitor its growth.
+    pub fn get_database_stats(&self) -> DatabaseStatsResult {
+        let DatabaseStats {
+            table_rows,
+            size,
+            free_size,
+        } = self.db.connection().stats();
+        DatabaseStatsResult {
+            table_rows,
+            size,
+            free_size,
+        }
+    }
 
-        // If we ask to create an output for an address from another network, it will fail.
-        let invalid_addr =
-            bitcoin::Address::new(bitcoin::Network::Testnet, dummy_addr.payload().clone());
-        let invalid_destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
-            [(invalid_addr, dummy_value)].iter().cloned().collect();
-        assert!(matches!(
-            control.create_spend(&invalid_destinations, &[dummy_op], 1, None),
-            Err(CommandError::Address(
-                address::Error::NetworkValidation { .. }
-            ))
-        ));
+    /// Rebuild the database to reclaim the space left unused, for instance after pruning spent
+    /// coins. Returns the statistics of the database once vacuumed.
+    pub fn vacuum_database(&self) -> Result<DatabaseStatsResult, CommandError> {
+        self.check_unlocked(false)?;
+        self.db
+            .connection()
+            .vacuum()
+            .map_err(CommandError::DatabaseVacuum)?;
+        Ok(self.get_database_stats())
+    }
 
-        // If we ask for a large, but valid, output we won't get a change output. 95_000 because we
-        // won't create an output lower than 5k sats.
-        *destinations.get_mut(&dummy_addr).unwrap() = 95_000;
-        let (psbt, warnings) = if let CreateSpendResult::Success { psbt, warnings } = control
-            .create_spend(&destinations, &[dummy_op], 1, None)
-            .unwrap()
-        {
-            (psbt, warnings)
-        } else {
-            panic!("expect successful spend creation")
-        };
-        let tx = psbt.unsigned_tx;
-        assert_eq!(tx.input.len(), 1);
-        assert_eq!(tx.input[0].previous_output, dummy_op);
-        assert_eq!(tx.output.len(), 1);
-        assert_eq!(
-            tx.output[0].script_pubkey,
-            dummy_addr.payload().script_pubkey()
-        );
-        assert_eq!(tx.output[0].value.to_sat(), 95_000);
-        // change = 100_000 - 95_000 - /* fee without change */ 127 - /* extra fee for change output */ 43 = 4830
-        assert_eq!(
-            warnings,
-            vec![
-                "Dust UTXO. The minimal change output allowed by Liana is 5000 sats. \
-                Instead of creating a change of 4830 sats, it was added to the \
-                transaction fee. Select a larger input to avoid this from happening."
-            ]
-        );
+    /// Create PSBT to replace the given transaction using RBF.
+    ///
+    /// `txid` must point to a PSBT in our database.
+    ///
+    /// `is_cancel` indicates whether to "cancel" the transaction by including only a single (change)
+    /// output in the replacement or otherwise to keep the same (non-change) outputs and simply
+    /// bump the fee.
+    /// If `true`, the only output of the RBF transaction will be change and the inputs will include
+    /// at least one of the inputs from the previous transaction. If `false`, all inputs from the previous
+    /// transaction will be used in the replacement.
+    /// In both cases:
+    /// - if the previous transaction includes a change output to one of our own change addresses,
+    /// this same address will be used for change in the RBF transaction, if required. If the previous
+    /// transaction pays to more than one of our change addresses, then the one receiving the highest
+    /// value will be used as a change address and the others will be treated as non-change outputs.
+    /// - the RBF transaction may include additional confirmed coins as inputs if required
+    /// in order to pay the higher fee (this applies also when replacing a self-send).
+    ///
+    /// If the previous transaction is a recovery transaction (its inputs are relatively
+    /// timelocked), the replacement keeps the same nSequence for its inputs so it can still be
+    /// satisfied through the recovery path. Additional coins are only used if they are available
+    /// through this same recovery path. If `!is_cancel`, the higher fee is deducted from the sweep
+    /// output rather than keeping its value.
+    ///
+    /// `feerate_vb` is the target feerate for the RBF transaction (in sat/vb). If `None`, it will be set
+    /// to 1 sat/vb larger than the feerate of the previous transaction, which is the minimum value allowed
+    /// when using RBF.
+    ///
+    /// The replacement is recorded as drafted for the previous transaction: once it's stored, the
+    /// previous transaction can't be broadcast anymore unless the replacement is deleted.
+    pub fn rbf_psbt(
+        &self,
+        txid: &bitcoin::Txid,
+        is_cancel: bool,
+        feerate_vb: Option<u64>,
+    ) -> Result<CreateSpendResult, CommandError> {
+        self.rbf_psbt_with_feerate(txid, is_cancel, feerate_vb.map(Feerate::from_sat_per_vb))
+    }
 
-        // Increase the target value by the change amount and the warning will disappear.
-        *destinations.get_mut(&dummy_addr).unwrap() = 95_000 + 4_830;
-        let (psbt, warnings) = if let CreateSpendResult::Success { psbt, warnings } = control
-            .create_spend(&destinations, &[dummy_op], 1, None)
-            .unwrap()
-        {
-            (psbt, warnings)
-        } else {
-            panic!("expect successful spend creation")
-        };
-        let tx = psbt.unsigned_tx;
-        assert_eq!(tx.output.len(), 1);
-        assert!(warnings.is_empty());
+    /// Same as [`DaemonControl::rbf_psbt`], but the target feerate may be fractional.
+    pub fn rbf_psbt_with_feerate(
+        &self,
+        txid: &bitcoin::Txid,
+        is_cancel: bool,
+        feerate: Option<Feerate>,
+    ) -> Result<CreateSpendResult, CommandError> {
+        if is_cancel && feerate.is_some() {
+            return Err(CommandError::RbfError(RbfErrorInfo::SuperfluousFeerate));
+        }
+        self.rbf_psbt_inner(txid, is_cancel, feerate, None, /*fresh_change=*/ false)
+    }
 
-        // Now increase target also by the extra fee that was paying for change and we can still create the spend.
-        *destinations.get_mut(&dummy_addr).unwrap() =
-            95_000 + 4_830 + /* fee for change output */ 43;
-        let (psbt, warnings) = if let CreateSpendResult::Success { psbt, warnings } = control
-            .create_spend(&destinations, &[dummy_op], 1, None)
-            .unwrap()
-        {
-            (psbt, warnings)
-        } else {
-            panic!("expect successful spend creation")
-        };
-        let tx = psbt.unsigned_tx;
-        assert_eq!(tx.output.len(), 1);
-        assert!(warnings.is_empty());
+    /// Same as [`DaemonControl::rbf_psbt`] for bumping the fee of a transaction, but set the
+    /// absolute fee of the replacement instead of its feerate. The fee must satisfy the
+    /// replacement rules: it must exceed the fees of the transactions being replaced by at least
+    /// the replacement's own size at the incremental relay feerate, and its feerate must be higher
+    /// than theirs. If it doesn't, the error gives the minimum acceptable fee.
+    ///
+    /// The fee is set exactly by adjusting the change output. If the replacement has no change
+    /// output, or it can't afford it, the fee may be slightly higher and a warning is returned.
+    pub fn rbf_psbt_with_fee(
+        &self,
+        txid: &bitcoin::Txid,
+        fee: bitcoin::Amount,
+    ) -> Result<CreateSpendResult, CommandError> {
+        self.rbf_psbt_inner(txid, false, None, Some(fee), /*fresh_change=*/ false)
+    }
 
-        // Now increase the target by 1 more sat and we will have insufficient funds.
-        *destinations.get_mut(&dummy_addr).unwrap() =
-            95_000 + 4_830 + /* fee for change output */ 43 + 1;
-        assert_eq!(
-            control.create_spend(&destinations, &[dummy_op], 1, None),
-            Ok(CreateSpendResult::InsufficientFunds { missing: 1 }),
-        );
+    /// Cancel one of our unconfirmed Spend transactions by creating a replacement which sends the
+    /// coins back to a fresh change address of ours. The replacement spends at least one of the
+    /// inputs of the original transaction, plus additional confirmed coins if needed to pay the
+    /// higher fee.
+    ///
+    /// `feerate_vb` is the target feerate of the replacement (in sat/vb). If `None`, it is set to
+    /// the minimum allowed by the replacement rules, that is 1 sat/vb larger than the feerate of
+    /// the transaction being replaced. An error is returned if it is lower than this minimum, or if
+    /// the original transaction isn't replaceable.
+    pub fn cancel_spend(
+        &self,
+        txid: &bitcoin::Txid,
+        feerate_vb: Option<u64>,
+    ) -> Result<CreateSpendResult, CommandError> {
+        self.rbf_psbt_inner(
+            txid,
+            /*is_cancel=*/ true,
+            feerate_vb.map(Feerate::from_sat_per_vb),
+            None,
+            /*fresh_change=*/ true,
+        )
+    }
 
-        // Now decrease the target so that the lost change is just 1 sat.
-        *destinations.get_mut(&dummy_addr).unwrap() =
-            100_000 - /* fee without change */ 127 - /* extra fee for change output */ 43 - 1;
-        let warnings = if let CreateSpendResult::Success { warnings, .. } = control
-            .create_spend(&destinations, &[dummy_op], 1, None)
-            .unwrap()
+    fn rbf_psbt_inner(
+        &self,
+        txid: &bitcoin::Txid,
+        is_cancel: bool,
+        feerate: Option<Feerate>,
+        target_fee: Option<bitcoin::Amount>,
+        fresh_change: bool,
+    ) -> Result<CreateSpendResult, CommandError> {
+        self.check_unlocked(false)?;
+        let mut db_conn = self.db.connection();
+        let mut tx_getter = BitcoindTxGetter::new(&self.bitcoin);
+
+        let prev_psbt = db_conn
+            .spend_tx(txid)
+            .ok_or(CommandError::UnknownSpend(*txid))?;
+        if !prev_psbt.unsigned_tx.is_explicitly_rbf() {
+            return Err(CommandError::RbfError(RbfErrorInfo::NotSignaling));
+        }
+        let prev_outpoints: Vec<bitcoin::OutPoint> = prev_psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect();
+        let prev_coins = db_conn.coins_by_outpoints(&prev_outpoints);
+        // Make sure all prev outpoints are coins in our DB.
+        if let Some(op) = prev_outpoints
+            .iter()
+            .find(|op| !prev_coins.contains_key(op))
         {
-            warnings
+            return Err(CommandError::UnknownOutpoint(*op));
+        }
+        if let Some(op) = prev_coins.iter().find_map(|(_, coin)| {
+            if coin.spend_block.is_some() {
+                Some(coin.outpoint)
+            } else {
+                None
+            }
+        }) {
+            return Err(CommandError::AlreadySpent(op));
+        }
+        // The timelock of the recovery path spent by the previous transaction, if it's a recovery.
+        let recovery_timelock = prev_psbt.unsigned_tx.input.iter().find_map(|txin| {
+            match txin.sequence.to_relative_lock_time() {
+                Some(bitcoin::relative::LockTime::Blocks(height)) => Some(height.value()),
+                _ => None,
+            }
+        });
+        let sequence = recovery_timelock.map(bitcoin::Sequence::from_height);
+        // Compute the minimal feerate and fee the replacement transaction must have to satisfy RBF
+        // rules #3, #4 and #6 (see
+        // https://github.com/bitcoin/bitcoin/blob/master/doc/policy/mempool-replacements.md). By
+        // default (ie if the transaction we are replacing was dropped from the mempool) there is
+        // no minimum absolute fee and the minimum feerate is 1, the minimum relay feerate.
+        let (min_feerate_vb, descendant_fees) = self
+            .bitcoin
+            .mempool_spenders(&prev_outpoints)
+            .into_iter()
+            .fold(
+                (1, bitcoin::Amount::from_sat(0)),
+                |(min_feerate, descendant_fee), entry| {
+                    let entry_feerate = entry
+                        .fees
+                        .base
+                        .checked_div(entry.vsize)
+                        .expect("Can't have a null vsize or tx would be invalid")
+                        .to_sat()
+                        .checked_add(1)
+                        .expect("Can't overflow or tx would be invalid");
+                    (
+                        std::cmp::max(min_feerate, entry_feerate),
+                        descendant_fee + entry.fees.descendant,
+                    )
+                },
+            );
+        // Check replacement transaction's target feerate, if set, is high enough,
+        // and otherwise set it to the min feerate found above.
+        let min_feerate = Feerate::from_sat_per_vb(min_feerate_vb);
+        let feerate = if target_fee.is_some() {
+            min_feerate
+        } else if let Some(feerate) = feerate {
+            feerate
+        } else if is_cancel {
+            min_feerate
         } else {
-            panic!("expect successful spend creation")
+            return Err(CommandError::RbfError(RbfErrorInfo::MissingFeerate));
         };
-        // Message uses "sat" instead of "sats" when value is 1.
-        assert_eq!(
-            warnings,
-            vec![
-                "Dust UTXO. The minimal change output allowed by Liana is 5000 sats. \
-                Instead of creating a change of 1 sat, it was added to the \
-                transaction fee. Select a larger input to avoid this from happening."
-            ]
-        );
-
-        // Now decrease the target value so that we have enough for a change output.
-        *destinations.get_mut(&dummy_addr).unwrap() =
-            95_000 - /* fee without change */ 127 - /* extra fee for change output */ 43;
-        let (psbt, warnings) = if let CreateSpendResult::Success { psbt, warnings } = control
-            .create_spend(&destinations, &[dummy_op], 1, None)
-            .unwrap()
-        {
-            (psbt, warnings)
+        if feerate < min_feerate {
+            return Err(CommandError::RbfError(RbfErrorInfo::TooLowFeerate(feerate)));
+        }
+        // Get info about prev outputs to determine replacement outputs. An OP_RETURN output has no
+        // address, its data is carried over to the replacement unless it's a cancel.
+        let op_return = prev_psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .find_map(|txo| op_return_data(&txo.script_pubkey))
+            .filter(|_| !is_cancel);
+        let prev_derivs: Vec<_> = prev_psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .filter(|txo| !txo.script_pubkey.is_op_return())
+            .map(|txo| {
+                let address = bitcoin::Address::from_script(
+                    &txo.script_pubkey,
+                    self.config.bitcoin_config.network,
+                )
+                .expect("address already used in finalized transaction");
+                (
+                    address.clone(),
+                    txo.value,
+                    db_conn.derivation_index_by_address(&address),
+                )
+            })
+            .collect();
+        // Set the previous change address to that of the change output with the largest value
+        // and then largest index. When bumping the fee of a recovery, the sweep output is used as
+        // change instead so the fee is deducted from it.
+        let prev_change_address = if recovery_timelock.is_some() && !is_cancel {
+            prev_derivs
+                .iter()
+                .max_by_key(|(_, amt, _)| *amt)
+                .map(|(addr, _, _)| addr)
+                .cloned()
         } else {
-            panic!("expect successful spend creation")
+            prev_derivs
+                .iter()
+                .filter_map(|(addr, amt, deriv)| {
+                    if let Some((ind, true)) = &deriv {
+                        Some((addr, amt, ind))
+                    } else {
+                        None
+                    }
+                })
+                .max_by(|(_, amt_1, ind_1), (_, amt_2, ind_2)| {
+                    amt_1.cmp(amt_2).then(ind_1.cmp(ind_2))
+                })
+                .map(|(addr, _, _)| addr)
+                .cloned()
+        };
+        // If not cancel, use all previous outputs as destinations, except for
+        // the output corresponding to the change address we found above.
+        // If cancel, the replacement will not have any destinations, only a change output.
+        let destinations = if !is_cancel {
+            prev_derivs
+                .into_iter()
+                .filter_map(|(addr, amt, _)| {
+                    if prev_change_address.as_ref() != Some(&addr) {
+                        Some((self.spend_addr(&mut db_conn, addr), amt))
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        // If there was no previous change address, or a fresh one was requested, we set the change
+        // address for the replacement to our next change address. This way, we won't increment the
+        // change index with each attempt at creating the replacement PSBT below.
+        let change_address = prev_change_address
+            .filter(|_| !fresh_change)
+            .map(|addr| self.spend_addr(&mut db_conn, addr))
+            .unwrap_or_else(|| self.next_change_addr(&mut db_conn));
+        // If `!is_cancel`, we take the previous coins as mandatory candidates and add confirmed coins as optional.
+        // Otherwise, we take the previous coins as optional candidates and let coin selection find the
+        // best solution that includes at least one of these. If there are insufficient funds to create the replacement
+        // transaction in this way, then we set candidates in the same way as for the `!is_cancel` case.
+        let mut candidate_coins: Vec<CandidateCoin> = prev_coins
+            .values()
+            .map(|c| {
+                // In case any previous coins are unconfirmed, we don't include their ancestor info
+                // in the candidate as the replacement fee and feerate will be higher and any
+                // additional fee to pay for ancestors should already have been taken into account
+                // when including these coins in the previous transaction.
+                coin_to_candidate(
+                    c, /*must_select=*/ !is_cancel, sequence, /*ancestor_info=*/ None,
+                )
+            })
+            .collect();
+        let current_height = self.bitcoin.chain_tip().height;
+        let confirmed_cands: Vec<CandidateCoin> = db_conn
+            .coins(&[CoinStatus::Confirmed], &[])
+            .into_values()
+            .filter_map(|c| {
+                // Make sure we don't have duplicate candidates in case any of the coins are not
+                // currently set as spending in the DB (and are therefore still confirmed). When
+                // replacing a recovery, only the coins available through the same path can be used.
+                let available = recovery_timelock
+                    .map(|timelock| is_recovery_available(&c, current_height, timelock))
+                    .unwrap_or(true);
+                if !prev_coins.contains_key(&c.outpoint) && available {
+                    Some(coin_to_candidate(
+                        &c, /*must_select=*/ false, sequence, /*ancestor_info=*/ None,
+                    ))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        if !is_cancel {
+            candidate_coins.extend(&confirmed_cands);
+        }
+        // The replaced fee is the fee of the transaction being replaced and its descendants. Coin selection
+        // will ensure that the replacement transaction additionally pays for its own weight as per
+        // RBF rule 4.
+        let replaced_fee = descendant_fees.to_sat();
+        // The minimum fee a replacement of this size must pay to satisfy both rules #3 and #4 (at
+        // an incremental relay feerate of 1 sat/vb) and rule #6.
+        let replacement_min_fee = |psbt: &Psbt| {
+            let vsize = self
+                .config
+                .main_descriptor
+                .unsigned_tx_max_vbytes(&psbt.unsigned_tx);
+            std::cmp::max(replaced_fee + vsize, min_feerate.fee_vb(vsize))
+        };
+        let create = |tx_getter: &mut BitcoindTxGetter,
+                      candidate_coins: &[CandidateCoin],
+                      feerate: Feerate| {
+            create_spend(
+                &self.config.main_descriptor,
+                &self.secp,
+                tx_getter,
+                &destinations,
+                candidate_coins,
+                &[],
+                SpendTxFees::Rbf(feerate, replaced_fee),
+                self.long_term_feerate(),
+                self.config.max_spend_inputs,
+                change_address.clone(),
+                op_return.as_deref(),
+            )
+        };
+        // This loop can have up to 2 iterations in the case of cancel and otherwise only 1.
+        loop {
+            match create(&mut tx_getter, &candidate_coins, feerate) {
+                Ok(CreateSpendRes {
+                    mut psbt,
+                    mut has_change,
+                    mut warnings,
+                }) => {
+                    let mut fee_warning = None;
+                    // When targeting an absolute fee, the replacement created at the minimum
+                    // feerate tells us the minimum fee to pay. If the target is higher, create it
+                    // again at the corresponding feerate and set the exact fee through the change.
+                    if let Some(fee) = target_fee {
+                        let min_fee = replacement_min_fee(&psbt);
+                        if fee.to_sat() < min_fee {
+                            return Err(CommandError::RbfError(RbfErrorInfo::TooLowFee(min_fee)));
+                        }
+                        let vsize = self
+                            .config
+                            .main_descriptor
+                            .unsigned_tx_max_vbytes(&psbt.unsigned_tx);
+                        let fee_feerate =
+                            Feerate::from_sat_per_vb_f64(fee.to_sat() as f64 / vsize as f64)
+                                .expect("Fee is a valid amount and vsize is not null");
+                        let res = match create(
+                            &mut tx_getter,
+                            &candidate_coins,
+                            std::cmp::max(fee_feerate, min_feerate),
+                        ) {
+                            Ok(res) => res,
+                            Err(SpendCreationError::CoinSelection(e)) => {
+                                return Ok(insufficient_funds(&candidate_coins, e.missing));
+                            }
+                            Err(e) => return Err(e.into()),
+                        };
+                        psbt = res.psbt;
+                        has_change = res.has_change;
+                        warnings = res.warnings;
+                        let change_spk = change_address.addr.script_pubkey();
+                        let change_index = psbt
+                            .unsigned_tx
+                            .output
+                            .iter()
+                            .position(|txo| txo.script_pubkey == change_spk)
+                            .filter(|_| has_change);
+                        if let Some(index) =
+                            change_index.filter(|_| fee.to_sat() >= replacement_min_fee(&psbt))
+                        {
+                            set_fee_from_change(&mut psbt, index, fee);
+                        }
+                        let actual_fee = psbt.fee().expect("We created this PSBT");
+                        if actual_fee != fee {
+                            fee_warning = Some(format!(
+                                "The fee could not be set to exactly {} sats, the replacement pays \
+                                 {} sats.",
+                                fee.to_sat(),
+                                actual_fee.to_sat()
+                            ));
+                        }
+                    }
+
+                    // In case of success, make sure to update our next derivation index if any address
+                    // used in the transaction outputs was from the future.
+                    for (addr, _) in destinations.iter() {
+                        self.maybe_increase_next_deriv_index(&mut db_conn, &addr.info);
+                    }
+                    if has_change {
+                        self.maybe_increase_next_deriv_index(&mut db_conn, &change_address.info);
+                    }
+
+                    // The replacement is part of the same draft as the transaction it replaces.
+                    let draft_id = match DraftId::from_psbt(&prev_psbt) {
+                        Some(draft_id) => draft_id,
+                        None => DraftId::random()
+                            .map_err(|e| CommandError::Randomness(e.to_string()))?,
+                    };
+                    draft_id.set_in_psbt(&mut psbt);
+                    inherit_draft_label(&mut db_conn, &psbt);
+                    db_conn.record_replacement_draft(txid, &psbt.unsigned_tx.txid());
+                    let privacy_score = privacy_score(&psbt, &candidate_coins);
+                    let spends_unconfirmed = spends_unconfirmed(&mut db_conn, &psbt);
+
+                    return Ok(CreateSpendResult::Success {
+                        draft_id,
+                        privacy_score,
+                        selection_score: None,
+                        spends_unconfirmed,
+                        output_seed: None,
+                        feerate,
+                        self_send_outputs: Vec::new(),
+                        foreign_inputs: Vec::new(),
+                        destination_warnings: Vec::new(),
+                        locktime: psbt.unsigned_tx.lock_time.to_consensus_u32(),
+                        psbt,
+                        warnings: warnings
+                            .iter()
+                            .map(|w| w.to_string())
+                            .chain(fee_warning)
+                            .collect(),
+                    });
+                }
+                Err(SpendCreationError::CoinSelection(e)) => {
+                    // If we get a coin selection error due to insufficient funds and we want to cancel the
+                    // transaction, then set all previous coins as mandatory and add confirmed coins as
+                    // optional, unless we have already done this.
+                    if is_cancel && candidate_coins.iter().all(|c| !c.must_select) {
+                        for cand in candidate_coins.iter_mut() {
+                            cand.must_select = true;
+                        }
+                        candidate_coins.extend(&confirmed_cands);
+                        continue;
+                    } else {
+                        return Ok(insufficient_funds(&candidate_coins, e.missing));
+                    }
+                }
+                Err(e) => {
+                    return Err(e.into());
+                }
+            };
+        }
+    }
+
+    /// Trigger a rescan of the block chain for transactions involving our main descriptor between
+    /// the given date and the current tip.
+    /// The date must be after the genesis block time and before the current tip blocktime.
+    pub fn start_rescan(&self, timestamp: u32) -> Result<(), CommandError> {
+        self.check_unlocked(false)?;
+        let mut db_conn = self.db.connection();
+        let genesis_timestamp = self.bitcoin.genesis_block_timestamp();
+
+        let future_timestamp = self
+            .bitcoin
+            .tip_time()
+            .map(|t| timestamp >= t)
+            .unwrap_or(false);
+        if timestamp < genesis_timestamp || future_timestamp {
+            return Err(CommandError::InsaneRescanTimestamp(timestamp));
+        }
+        if db_conn.rescan_timestamp().is_some() || self.bitcoin.rescan_progress().is_some() {
+            return Err(CommandError::AlreadyRescanning);
+        }
+
+        // TODO: there is a race with the above check for whether the backend is already
+        // rescanning. This could make us crash with the bitcoind backend if someone triggered a
+        // rescan of the wallet just after we checked above and did now.
+        self.bitcoin
+            .start_rescan(&self.config.main_descriptor, timestamp)
+            .map_err(CommandError::RescanTrigger)?;
+        db_conn.set_rescan(timestamp);
+
+        Ok(())
+    }
+
+    /// Trigger a rescan of the block chain for transactions involving our main descriptor from the
+    /// block with the given hash. The block must be part of the best chain.
+    pub fn start_rescan_from_hash(
+        &self,
+        block_hash: &bitcoin::BlockHash,
+    ) -> Result<(), CommandError> {
+        let block = self
+            .bitcoin
+            .block_in_chain(block_hash)
+            .ok_or(CommandError::UnknownBlock(*block_hash))?;
+        self.start_rescan(block.time)
+    }
+
+    /// Trigger a rescan of the block chain for transactions involving our main descriptor only
+    /// between the two given block heights (inclusive). The end height must not be past the
+    /// current tip.
+    ///
+    /// Once the rescan completes, the wallet is synced again from the start of the range so
+    /// any newly found transactions are merged with the existing ones.
+    pub fn rescan_range(&self, start_height: u32, end_height: u32) -> Result<(), CommandError> {
+        self.check_unlocked(false)?;
+        let mut db_conn = self.db.connection();
+
+        let tip = self.bitcoin.chain_tip();
+        let (start, end) = match (i32::try_from(start_height), i32::try_from(end_height)) {
+            (Ok(start), Ok(end)) if start <= end && end <= tip.height => (start, end),
+            _ => return Err(CommandError::InsaneRescanRange(start_height, end_height)),
+        };
+        let start_block = self
+            .bitcoin
+            .block_at_height(start)
+            .ok_or(CommandError::InsaneRescanRange(start_height, end_height))?;
+        if db_conn.rescan_timestamp().is_some() || self.bitcoin.rescan_progress().is_some() {
+            return Err(CommandError::AlreadyRescanning);
+        }
+
+        // TODO: same race as in `start_rescan` with the above check.
+        self.bitcoin
+            .rescan_range(start, end)
+            .map_err(CommandError::RescanTrigger)?;
+        // Recording the rescan with the timestamp of the first block in the range will make the
+        // poller roll back our tip to before it upon completion.
+        db_conn.set_rescan(start_block.time);
+
+        Ok(())
+    }
+
+    /// Remove from database the coins whose spending transaction has at least `older_than_confs`
+    /// confirmations. Those coins, and the transactions that only involve them, won't appear in
+    /// the wallet history anymore.
+    pub fn prune_spent_coins(
+        &self,
+        older_than_confs: u32,
+    ) -> Result<PruneSpentCoinsResult, CommandError> {
+        self.check_unlocked(false)?;
+        if older_than_confs < MIN_PRUNING_DEPTH {
+            return Err(CommandError::PruningTooShallow(older_than_confs));
+        }
+        let mut db_conn = self.db.connection();
+        let tip = db_conn.chain_tip().expect("Always set at first startup");
+        let max_spend_height = tip
+            .height
+            .saturating_sub(i32::try_from(older_than_confs).unwrap_or(i32::MAX))
+            + 1;
+        let pruned_count = db_conn.prune_spent_coins(max_spend_height);
+        Ok(PruneSpentCoinsResult { pruned_count })
+    }
+
+    /// list_confirmed_transactions retrieves a limited list of transactions which occured between two given dates.
+    pub fn list_confirmed_transactions(
+        &self,
+        start: u32,
+        end: u32,
+        limit: u64,
+    ) -> ListTransactionsResult {
+        let mut db_conn = self.db.connection();
+        let txids = db_conn.list_txids(start, end, limit);
+        let transactions = self.transactions_info(&mut db_conn, &txids);
+        ListTransactionsResult { transactions }
+    }
+
+    /// Iterate over all the transactions which occured between two given dates, most recent
+    /// first. They are queried from the database by chunks as the iterator is advanced, so an
+    /// arbitrarily large history can be exported without loading it all in memory at once.
+    pub fn confirmed_transactions_iter(&self, start: u32, end: u32) -> ConfirmedTransactions<'_> {
+        ConfirmedTransactions {
+            control: self,
+            db_conn: self.db.connection(),
+            start,
+            end,
+            offset: 0,
+            chunk: Vec::new().into_iter(),
+            exhausted: false,
+        }
+    }
+
+    /// list_transactions retrieves the transactions with the given txids.
+    pub fn list_transactions(&self, txids: &[bitcoin::Txid]) -> ListTransactionsResult {
+        let mut db_conn = self.db.connection();
+        let transactions = self.transactions_info(&mut db_conn, txids);
+        ListTransactionsResult { transactions }
+    }
+
+    /// Create a transaction that sweeps all coins for which a timelocked recovery path is
+    /// currently available to a provided address with the provided feerate.
+    ///
+    /// The `timelock` parameter can be used to specify which recovery path to use. By default,
+    /// we'll use the first recovery path available.
+    ///
+    /// Note that not all coins may be spendable through a single recovery path at the same time.
+    pub fn create_recovery(
+        &self,
+        address: bitcoin::Address<address::NetworkUnchecked>,
+        feerate_vb: u64,
+        timelock: Option<u16>,
+    ) -> Result<CreateRecoveryResult, CommandError> {
+        self.check_unlocked(false)?;
+        if feerate_vb < 1 {
+            return Err(CommandError::InvalidFeerate(feerate_vb.into()));
+        }
+        // Check this before anything else, as the default timelock below assumes there is one.
+        if self.recovery_timelocks().is_empty() {
+            return Err(CommandError::NoRecoveryPath);
+        }
+        let mut tx_getter = BitcoindTxGetter::new(&self.bitcoin);
+        let mut db_conn = self.db.connection();
+        let sweep_addr = self.spend_addr(&mut db_conn, self.validate_address(address)?);
+
+        // Query the coins that we can spend through the specified recovery path (if no recovery
+        // path specified, use the first available one) from the database.
+        let current_height = self.bitcoin.chain_tip().height;
+        let timelock =
+            timelock.unwrap_or_else(|| self.config.main_descriptor.first_timelock_value());
+        let sweepable_coins: Vec<_> = db_conn
+            .coins(&[CoinStatus::Confirmed], &[])
+            .into_values()
+            .filter_map(|c| {
+                // We are interested in coins available at the *next* block
+                if is_recovery_available(&c, current_height, timelock) {
+                    Some(coin_to_candidate(
+                        &c,
+                        /*must_select=*/ true,
+                        /*sequence=*/ Some(bitcoin::Sequence::from_height(timelock)),
+                        /*ancestor_info=*/ None,
+                    ))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        if sweepable_coins.is_empty() {
+            return Err(CommandError::RecoveryNotAvailable);
+        }
+
+        let sweep_addr_info = sweep_addr.info;
+        let CreateSpendRes {
+            psbt, has_change, ..
+        } = create_spend(
+            &self.config.main_descriptor,
+            &self.secp,
+            &mut tx_getter,
+            &[], // No destination, only the change address.
+            &sweepable_coins,
+            &[],
+            SpendTxFees::Regular(feerate_vb.into()),
+            self.long_term_feerate(),
+            // All the recoverable coins are swept at once.
+            None,
+            sweep_addr,
+            None,
+        )?;
+        if has_change {
+            self.maybe_increase_next_deriv_index(&mut db_conn, &sweep_addr_info);
+        }
+
+        Ok(CreateRecoveryResult { psbt })
+    }
+
+    /// Estimate the size and fee of a transaction sweeping all the coins currently spendable
+    /// through a recovery path, at the given feerate. The recovery paths are indexed by
+    /// increasing timelock, and the first one is used if `recovery_path_index` is `None`. No
+    /// transaction is created: the estimate assumes the coins are swept to a single output of
+    /// the same type as our own addresses, and accounts for the witness of the recovery path.
+    pub fn recovery_cost_estimate(
+        &self,
+        feerate_vb: u64,
+        recovery_path_index: Option<usize>,
+    ) -> Result<RecoveryCostEstimate, CommandError> {
+        if !(1..=MAX_FEERATE).contains(&feerate_vb) {
+            return Err(CommandError::InvalidFeerate(feerate_vb.into()));
+        }
+        let timelocks = self.recovery_timelocks();
+        if timelocks.is_empty() {
+            return Err(CommandError::NoRecoveryPath);
+        }
+        let path_index = recovery_path_index.unwrap_or(0);
+        let timelock = *timelocks
+            .get(path_index)
+            .ok_or(CommandError::UnknownRecoveryPath(path_index))?;
+
+        let current_height = self.bitcoin.chain_tip().height;
+        let sweepable_coins: Vec<Coin> = self
+            .db
+            .connection()
+            .coins(&[CoinStatus::Confirmed], &[])
+            .into_values()
+            .filter(|c| is_recovery_available(c, current_height, timelock))
+            .collect();
+        if sweepable_coins.is_empty() {
+            return Err(CommandError::RecoveryNotAvailable);
+        }
+        let amount: bitcoin::Amount = sweepable_coins.iter().map(|c| c.amount).sum();
+
+        // The unsigned sweep transaction, with a single output to one of our addresses.
+        let desc = &self.config.main_descriptor;
+        let script_pubkey = desc
+            .change_descriptor()
+            .derive(0.into(), &self.secp)
+            .script_pubkey();
+        let tx = bitcoin::Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: sweepable_coins
+                .iter()
+                .map(|c| bitcoin::TxIn {
+                    previous_output: c.outpoint,
+                    sequence: bitcoin::Sequence::from_height(timelock),
+                    ..bitcoin::TxIn::default()
+                })
+                .collect(),
+            output: vec![bitcoin::TxOut {
+                value: amount,
+                script_pubkey,
+            }],
+        };
+
+        // Once finalized the transaction has the Segwit marker and flag (2 WU) and each input is
+        // satisfied through the recovery path.
+        let sat_weight = u64::try_from(
+            desc.path_sat_weight(Some(timelock))
+                .expect("Timelock is from this policy"),
+        )
+        .expect("Must fit in a u64");
+        let num_inputs = u64::try_from(tx.input.len()).expect("Must fit in a u64");
+        let weight = bitcoin::Weight::from_wu(
+            tx.weight().to_wu() + 2 + sat_weight.checked_mul(num_inputs).unwrap(),
+        );
+        let vsize = weight.to_vbytes_ceil();
+
+        Ok(RecoveryCostEstimate {
+            timelock,
+            coins_count: sweepable_coins.len(),
+            amount,
+            vsize,
+            fee: bitcoin::Amount::from_sat(vsize.checked_mul(feerate_vb).unwrap()),
+        })
+    }
+
+    /// Create a transaction sweeping all the coins we can currently spend to the first receive
+    /// address of a new descriptor, for instance to move the funds to a new wallet after a
+    /// recovery key was lost or compromised. The coins which can't be spent yet are left behind,
+    /// in which case a warning is returned.
+    pub fn migrate_to_descriptor(
+        &self,
+        new_descriptor: &str,
+        feerate_vb: u64,
+    ) -> Result<CreateSpendResult, CommandError> {
+        self.check_unlocked(false)?;
+        let network = self.config.bitcoin_config.network;
+        let new_descriptor = descriptors::LianaDescriptor::from_str(new_descriptor)
+            .map_err(|e| CommandError::InvalidDescriptor(e.to_string()))?;
+        if !new_descriptor.all_xpubs_net_is(network) {
+            return Err(CommandError::InvalidDescriptor(format!(
+                "not all keys are for network '{}'.",
+                network
+            )));
+        }
+        if new_descriptor == self.config.main_descriptor {
+            return Err(CommandError::InvalidDescriptor(
+                "this is already the descriptor of this wallet.".to_string(),
+            ));
+        }
+        let address = new_descriptor
+            .receive_descriptor()
+            .derive(0.into(), &self.secp)
+            .address(network);
+
+        let (spendable, left_behind) = self.coins_to_sweep();
+        if spendable.is_empty() {
+            return Err(CommandError::NoCoinToMigrate);
+        }
+
+        let mut res = self.build_spend(
+            &HashMap::new(),
+            &CoinSelector::Outpoints(spendable.iter().map(|c| c.outpoint).collect()),
+            feerate_vb.into(),
+            &CreateSpendOptions {
+                change_address: Some(address.as_unchecked().clone()),
+                ..Default::default()
+            },
+        )?;
+        if let (Some(warning), CreateSpendResult::Success { warnings, .. }) =
+            (left_behind_warning(&left_behind), &mut res)
+        {
+            warnings.push(warning);
+        }
+        Ok(res)
+    }
+
+    /// Create a transaction moving all the coins we can currently spend to `num_outputs` fresh
+    /// receive addresses of this wallet, for instance to periodically break the link between the
+    /// funds and the addresses they were received on. The swept value is split evenly between the
+    /// outputs as the change is with `split_change` in [`DaemonControl::create_spend`]: if it can't
+    /// afford all of them above the dust limit, fewer are created and a warning is returned. The
+    /// coins which can't be spent yet are left behind, as in
+    /// [`DaemonControl::migrate_to_descriptor`].
+    pub fn rotate_funds(
+        &self,
+        feerate_vb: u64,
+        num_outputs: u32,
+    ) -> Result<CreateSpendResult, CommandError> {
+        self.check_unlocked(false)?;
+        if !(1..=MAX_CHANGE_OUTPUTS).contains(&num_outputs) {
+            return Err(CommandError::InvalidRotationOutputs(num_outputs));
+        }
+        let (spendable, left_behind) = self.coins_to_sweep();
+        if spendable.is_empty() {
+            return Err(CommandError::NoCoinToRotate);
+        }
+
+        // The funds are swept to our next receive address, and the output is then split with the
+        // following ones.
+        let mut db_conn = self.db.connection();
+        let network = self.config.bitcoin_config.network;
+        let receive_desc = self.config.main_descriptor.receive_descriptor();
+        let first_index = db_conn.receive_index();
+        let address = receive_desc
+            .derive(first_index, &self.secp)
+            .address(network);
+        let feerate = Feerate::from_sat_per_vb(feerate_vb);
+        let mut res = self.build_spend(
+            &HashMap::new(),
+            &CoinSelector::Outpoints(spendable.iter().map(|c| c.outpoint).collect()),
+            feerate,
+            &CreateSpendOptions {
+                change_address: Some(address.as_unchecked().clone()),
+                ..Default::default()
+            },
+        )?;
+        if let CreateSpendResult::Success { psbt, warnings, .. } = &mut res {
+            let mut extra_addrs = Vec::with_capacity(num_outputs as usize - 1);
+            let mut index = first_index;
+            for _ in 1..num_outputs {
+                index = index
+                    .increment()
+                    .expect("Must not get into hardened territory");
+                extra_addrs.push(SpendOutputAddress {
+                    addr: receive_desc.derive(index, &self.secp).address(network),
+                    info: Some(AddrInfo {
+                        index,
+                        is_change: false,
+                    }),
+                });
+            }
+            let spk = address.script_pubkey();
+            let output_index = psbt
+                .unsigned_tx
+                .output
+                .iter()
+                .position(|txo| txo.script_pubkey == spk)
+                .expect("The funds are swept to this address");
+            let split_count = split_change_output(
+                &self.config.main_descriptor,
+                &self.secp,
+                psbt,
+                output_index,
+                &extra_addrs,
+                feerate,
+            );
+            for addr in &extra_addrs[..split_count] {
+                self.maybe_increase_next_deriv_index(&mut db_conn, &addr.info);
+            }
+            if split_count + 1 < num_outputs as usize {
+                warnings.push(format!(
+                    "The funds were too small to be moved to {} outputs. They were moved to {} \
+                     output(s).",
+                    num_outputs,
+                    split_count + 1
+                ));
+            }
+            warnings.extend(left_behind_warning(&left_behind));
+        }
+        Ok(res)
+    }
+
+    // The coins to spend when sweeping the funds of the wallet, and those left behind. We sweep
+    // the coins which would be selected by default when creating a Spend. The unconfirmed
+    // deposits, the immature coins, those being spent and those reserved by a Spend draft are
+    // left behind.
+    fn coins_to_sweep(&self) -> (Vec<Coin>, Vec<Coin>) {
+        let reserved_coins = self.reserved_coins();
+        self.db
+            .connection()
+            .coins(
+                &[
+                    CoinStatus::Unconfirmed,
+                    CoinStatus::Confirmed,
+                    CoinStatus::Spending,
+                ],
+                &[],
+            )
+            .into_values()
+            .partition(|c| {
+                !c.is_immature
+                    && !c.is_spent()
+                    && (c.is_confirmed() || c.is_change)
+                    && !reserved_coins.contains(&c.outpoint)
+            })
+    }
+}
+
+// The warning about these coins being left behind when sweeping the funds of the wallet, if any.
+fn left_behind_warning(left_behind: &[Coin]) -> Option<String> {
+    if left_behind.is_empty() {
+        return None;
+    }
+    let value: bitcoin::Amount = left_behind.iter().map(|c| c.amount).sum();
+    Some(format!(
+        "{} coin(s) worth {} can't be spent yet and are left behind.",
+        left_behind.len(),
+        value
+    ))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetInfoDescriptors {
+    pub main: descriptors::LianaDescriptor,
+}
+
+/// Information about the daemon
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetInfoResult {
+    pub version: String,
+    pub network: bitcoin::Network,
+    pub block_height: i32,
+    pub sync: f64,
+    pub descriptors: GetInfoDescriptors,
+    /// A stable identifier for this wallet, derived from its descriptor.
+    pub wallet_id: sha256::Hash,
+    /// The progress as a percentage (between 0 and 1) of an ongoing rescan if there is any
+    pub rescan_progress: Option<f64>,
+    /// Timestamp at wallet creation date
+    pub timestamp: u32,
+    /// Information about the Bitcoin backend we are connected to
+    pub backend: BackendInfo,
+    /// Estimate of the largest amount that can currently be sent to a single recipient.
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub max_spendable: bitcoin::Amount,
+    /// Whether one of our spendable coins can currently be spent through a recovery path.
+    pub recovery_available: bool,
+    /// Number of blocks before a recovery path becomes available for one of our spendable coins,
+    /// `0` if one already is. `None` if we have no confirmed spendable coin.
+    pub blocks_until_next_recovery: Option<u32>,
+}
+
+/// A summary of the state of the wallet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DashboardSummary {
+    /// Total value of our confirmed unspent coins.
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub balance: bitcoin::Amount,
+    /// Total value of our unconfirmed unspent coins.
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub unconfirmed_balance: bitcoin::Amount,
+    /// Number of unspent coins, confirmed or not.
+    pub coin_count: usize,
+    /// Number of coins either unconfirmed or being spent by an unconfirmed transaction.
+    pub pending_count: usize,
+    /// Block height at which a recovery path first becomes available for one of our confirmed
+    /// unspent coins, if any.
+    pub next_recovery_height: Option<i32>,
+    pub block_height: i32,
+    pub sync: f64,
+    /// The progress as a percentage (between 0 and 1) of an ongoing rescan if there is any
+    pub rescan_progress: Option<f64>,
+}
+
+/// The balance of the wallet, split by the state of the coins. See [`DaemonControl::balance`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceBreakdown {
+    /// The number of confirmations from which a coin is considered confirmed.
+    pub min_confirmations: u32,
+    /// Total value of our unspent coins with at least `min_confirmations`.
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub confirmed: bitcoin::Amount,
+    /// Total value of our unspent coins with fewer confirmations.
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub unconfirmed: bitcoin::Amount,
+    /// Total value of our immature coinbase coins.
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub immature: bitcoin::Amount,
+    /// Total value of our unspent coins reserved by a Spend transaction draft.
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub reserved: bitcoin::Amount,
+}
+
+/// Which parts of the wallet state to get in a [`WalletSnapshot`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SnapshotParts {
+    /// As returned by [`DaemonControl::get_info`].
+    pub info: bool,
+    /// All our coins, as returned by [`DaemonControl::list_coins`].
+    pub coins: bool,
+    /// As returned by [`DaemonControl::get_feerate_estimates`].
+    pub feerate_estimates: bool,
+    /// All our Spend transactions, as returned by [`DaemonControl::list_spend`].
+    pub spend_txs: bool,
+}
+
+impl SnapshotParts {
+    /// All the parts of the wallet state.
+    pub fn all() -> Self {
+        SnapshotParts {
+            info: true,
+            coins: true,
+            feerate_estimates: true,
+            spend_txs: true,
+        }
+    }
+}
+
+/// A consistent view of the requested parts of the wallet state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletSnapshot {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub info: Option<GetInfoResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coins: Option<ListCoinsResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feerate_estimates: Option<GetFeerateEstimatesResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spend_txs: Option<ListSpendResult>,
+}
+
+/// The changes to our coins between two [`WalletSnapshot`]s.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SnapshotDiff {
+    /// The coins received since the first snapshot.
+    pub added: Vec<ListCoinsEntry>,
+    /// The coins spent since the first snapshot.
+    pub spent: Vec<ListCoinsEntry>,
+    /// The coins whose deposit or spend was undone since the first snapshot, as they were in it.
+    pub reorged: Vec<ListCoinsEntry>,
+}
+
+/// The origin of a key in our descriptor.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyOrigin {
+    /// Fingerprint of the master extended key.
+    pub fingerprint: bip32::Fingerprint,
+    /// Derivation path from the master extended key to the xpub.
+    pub derivation_path: bip32::DerivationPath,
+    pub xpub: bip32::Xpub,
+}
+
+/// The keys of a spending path of our descriptor.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathKeys {
+    /// The timelock of this path, `None` for the primary path.
+    pub timelock: Option<u16>,
+    /// The number of keys required to sign for this path.
+    pub threshold: usize,
+    pub keys: Vec<KeyOrigin>,
+}
+
+/// The spending conditions of a path of our descriptor.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathDescription {
+    /// The timelock of this path, `None` for the primary path.
+    pub timelock: Option<u16>,
+    /// The number of keys required to sign for this path.
+    pub threshold: usize,
+    /// The master fingerprints of the keys that can sign for this path.
+    pub fingerprints: Vec<bip32::Fingerprint>,
+    /// A human-readable description of this path, such as "Primary: 2-of-3 of keys A, B, C".
+    pub description: String,
+}
+
+impl PathDescription {
+    fn new(timelock: Option<u16>, path_info: &descriptors::PathInfo) -> Self {
+        let (threshold, keys) = path_info.thresh_keys();
+        let fingerprints: Vec<_> = keys
+            .into_iter()
+            .map(|k| key_origin(k).fingerprint)
+            .collect();
+        let keys_desc = if fingerprints.len() == 1 {
+            format!("key {}", fingerprints[0])
+        } else {
+            format!(
+                "{}-of-{} of keys {}",
+                threshold,
+                fingerprints.len(),
+                fingerprints
+                    .iter()
+                    .map(|fg| fg.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        };
+        let description = match timelock {
+            None => format!("Primary: {}", keys_desc),
+            Some(tl) => format!("Recovery after {} blocks: {}", tl, keys_desc),
+        };
+        PathDescription {
+            timelock,
+            threshold,
+            fingerprints,
+            description,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyDescription {
+    /// The spending paths: first the primary path, then the recovery paths by increasing timelock.
+    pub paths: Vec<PathDescription>,
+    /// A human-readable description of the whole policy.
+    pub description: String,
+}
+
+/// Whether an address is one of ours, and which kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AddressKind {
+    /// One of our receive addresses.
+    Receive {
+        derivation_index: bip32::ChildNumber,
+    },
+    /// One of our change addresses.
+    Change {
+        derivation_index: bip32::ChildNumber,
+    },
+    /// Not one of our addresses.
+    External,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClassifyAddressesResult {
+    pub addresses: HashMap<String, AddressKind>,
+}
+
+/// The status of a transaction with respect to the mempool of the Bitcoin backend.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum MempoolStatus {
+    /// The transaction is confirmed at this height.
+    Confirmed { height: i32 },
+    /// The transaction is unconfirmed and in the mempool.
+    InMempool {
+        #[serde(
+            serialize_with = "ser_amount",
+            deserialize_with = "deser_amount_from_sats"
+        )]
+        fee: bitcoin::Amount,
+        vsize: u64,
+        /// The number of in-mempool ancestors, including this transaction.
+        ancestor_count: u64,
+        /// The number of in-mempool descendants, including this transaction.
+        descendant_count: u64,
+    },
+    /// The transaction is unconfirmed and not in the mempool. It may need to be rebroadcast.
+    NotInMempool,
+}
+
+/// One of our unconfirmed transactions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingTransaction {
+    pub txid: bitcoin::Txid,
+    /// The unconfirmed transactions of ours this transaction spends outputs of.
+    pub parents: Vec<bitcoin::Txid>,
+    /// The feerate of this transaction alone, in sats/vb rounded down. Only if it is in the
+    /// mempool of the Bitcoin backend.
+    pub feerate_vb: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingTransactionsResult {
+    pub transactions: Vec<PendingTransaction>,
+}
+
+/// An unconfirmed transaction along with its unconfirmed ancestors.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageInfo {
+    /// The number of transactions in the package, including the transaction itself.
+    pub ancestor_count: u64,
+    /// The total fee of the package.
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub fee: bitcoin::Amount,
+    /// The total virtual size of the package.
+    pub vsize: u64,
+    /// The feerate of the package as a whole, in sats/vb rounded down.
+    pub feerate_vb: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DescriptorKeysResult {
+    pub paths: Vec<PathKeys>,
+}
+
+/// Which source a feerate estimate comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeerateEstimateSource {
+    /// The Bitcoin backend.
+    Backend,
+    /// A static feerate set in the configuration.
+    Static,
+    /// None of the configured sources succeeded, this is the default fallback feerate.
+    Fallback,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeerateEstimate {
+    /// The number of blocks within which a transaction should be confirmed.
+    pub conf_target: u16,
+    /// The estimated feerate, in sats/vb.
+    pub feerate: u64,
+    pub source: FeerateEstimateSource,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetFeerateEstimatesResult {
+    pub estimates: Vec<FeerateEstimate>,
+    /// How long ago, in seconds, these estimates were computed. Only non-zero if they were cached.
+    #[serde(default)]
+    pub age_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeerateHistoryEntry {
+    /// When this estimate was sampled, as a UNIX timestamp.
+    pub timestamp: u32,
+    /// The number of blocks within which a transaction should be confirmed.
+    pub conf_target: u16,
+    /// The estimated feerate, in sats/vb.
+    pub feerate: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeerateHistoryResult {
+    pub samples: Vec<FeerateHistoryEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetAddressResult {
+    #[serde(deserialize_with = "deser_addr_assume_checked")]
+    pub address: bitcoin::Address,
+    pub derivation_index: bip32::ChildNumber,
+}
+
+impl GetAddressResult {
+    pub fn new(address: bitcoin::Address, derivation_index: bip32::ChildNumber) -> Self {
+        Self {
+            address,
+            derivation_index,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LastAddressStatus {
+    /// The last receive address handed out, if any.
+    #[serde(deserialize_with = "deser_opt_addr_assume_checked")]
+    pub address: Option<bitcoin::Address>,
+    pub derivation_index: Option<bip32::ChildNumber>,
+    /// Whether this address has received any coin.
+    pub used: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoinAddressResult {
+    #[serde(deserialize_with = "deser_addr_assume_checked")]
+    pub address: bitcoin::Address,
+    pub derivation_index: bip32::ChildNumber,
+    /// Whether the address is a change address.
+    pub is_change: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetLabelsResult {
+    pub labels: HashMap<String, String>,
+}
+
+/// The labels related to a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxLabels {
+    /// The label of the transaction itself.
+    pub transaction: Option<String>,
+    /// The labels of the coins spent by the transaction, by outpoint.
+    pub inputs: HashMap<String, String>,
+    /// The labels of the addresses paid by the transaction, by address.
+    pub outputs: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LabelEntry {
+    #[serde(serialize_with = "ser_to_string")]
+    pub item: LabelItem,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportLabelsResult {
+    pub labels: Vec<LabelEntry>,
+}
+
+/// A portable copy of the wallet state, to be imported on another machine. The coins and
+/// transactions are not part of it, they are retrieved from the chain upon import.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletExport {
+    pub network: bitcoin::Network,
+    #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_fromstr")]
+    pub descriptor: descriptors::LianaDescriptor,
+    /// The wallet creation time, from which to rescan the chain upon import.
+    pub timestamp: u32,
+    /// The height of the earliest block relevant to the wallet (see
+    /// [`DaemonControl::wallet_birthday`]). Upon import the chain is rescanned from this block if
+    /// it is earlier than the wallet creation time. Not present in exports from older versions.
+    #[serde(default)]
+    pub birthday_height: Option<u32>,
+    /// Derivation index of the next receive address.
+    pub receive_index: u32,
+    /// Derivation index of the next change address.
+    pub change_index: u32,
+    /// All the labels, by labelled address, txid or outpoint.
+    pub labels: BTreeMap<String, String>,
+    /// All the coin notes, by outpoint.
+    pub coin_notes: BTreeMap<String, String>,
+    #[serde(
+        serialize_with = "ser_to_string_vec",
+        deserialize_with = "deser_fromstr_vec"
+    )]
+    pub spend_txs: Vec<Psbt>,
+}
+
+/// A descriptor to import in Bitcoin Core using its `importdescriptors` command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoreImportDescriptor {
+    /// The descriptor, along with its checksum.
+    pub desc: String,
+    /// The first and last derivation indexes to import.
+    pub range: [u32; 2],
+    /// The time from which to rescan the chain, as a UNIX timestamp.
+    pub timestamp: u32,
+    /// Whether this descriptor is for change addresses.
+    pub internal: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DestinationLabel {
+    #[serde(serialize_with = "ser_to_string")]
+    pub address: bitcoin::Address,
+    pub label: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ListDestinationLabelsResult {
+    pub destinations: Vec<DestinationLabel>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvalidatedCoinEntry {
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub amount: bitcoin::Amount,
+    pub outpoint: bitcoin::OutPoint,
+    #[serde(
+        serialize_with = "ser_to_string",
+        deserialize_with = "deser_addr_assume_checked"
+    )]
+    pub address: bitcoin::Address,
+    /// Derivation index used to create the coin deposit address.
+    pub derivation_index: bip32::ChildNumber,
+    /// Whether the coin deposit address was derived from the change descriptor.
+    pub is_change: bool,
+    /// Timestamp at which the double spend of the deposit transaction was detected.
+    pub invalidated_at: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvalidatedCoinsResult {
+    pub coins: Vec<InvalidatedCoinEntry>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LabelUpdateSummary {
+    /// The items which didn't have a label before.
+    #[serde(serialize_with = "ser_to_string_vec")]
+    pub created: Vec<LabelItem>,
+    /// The items whose label was changed to a different value.
+    #[serde(serialize_with = "ser_to_string_vec")]
+    pub updated: Vec<LabelItem>,
+    /// The items whose label was removed.
+    #[serde(serialize_with = "ser_to_string_vec")]
+    pub deleted: Vec<LabelItem>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplaceLabelsResult {
+    /// Number of labels removed.
+    pub deleted: usize,
+    /// Number of labels now stored.
+    pub stored: usize,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct AddressInfo {
+    index: u32,
+    receive: bitcoin::Address,
+    change: bitcoin::Address,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct ListAddressesResult {
+    addresses: Vec<AddressInfo>,
+}
+
+impl ListAddressesResult {
+    pub fn new(addresses: Vec<AddressInfo>) -> Self {
+        ListAddressesResult { addresses }
+    }
+}
+
+/// A stored address which is not the one derived from our descriptor at its derivation index.
+#[derive(Debug, Clone, Serialize)]
+pub struct AddressMismatch {
+    pub derivation_index: bip32::ChildNumber,
+    pub is_change: bool,
+    /// The address stored in database.
+    pub stored: bitcoin::Address<bitcoin::address::NetworkUnchecked>,
+    /// The address derived from our descriptor.
+    #[serde(serialize_with = "ser_to_string")]
+    pub expected: bitcoin::Address,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsistencyReport {
+    /// Number of derivation indexes for which addresses are stored.
+    pub checked: usize,
+    /// Derivation indexes in use for which no address is stored.
+    pub missing: Vec<bip32::ChildNumber>,
+    pub mismatches: Vec<AddressMismatch>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LCSpendInfo {
+    pub txid: bitcoin::Txid,
+    /// The block height this spending transaction was confirmed at.
+    pub height: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListCoinsEntry {
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub amount: bitcoin::Amount,
+    pub outpoint: bitcoin::OutPoint,
+    #[serde(
+        serialize_with = "ser_to_string",
+        deserialize_with = "deser_addr_assume_checked"
+    )]
+    pub address: bitcoin::Address,
+    pub block_height: Option<i32>,
+    /// Derivation index used to create the coin deposit address.
+    pub derivation_index: bip32::ChildNumber,
+    /// Information about the transaction spending this coin.
+    pub spend_info: Option<LCSpendInfo>,
+    /// Whether this coin was created by a coinbase transaction that is still immature.
+    pub is_immature: bool,
+    /// Whether the coin deposit address was derived from the change descriptor.
+    pub is_change: bool,
+    /// The spending paths through which this coin can be spent in the next block. The primary path
+    /// is `0` and the recovery paths follow by increasing timelock.
+    pub spendable_paths: Vec<usize>,
+    /// The freeform note set on this coin, if any.
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListCoinsResult {
+    pub coins: Vec<ListCoinsEntry>,
+}
+
+/// The coins received on one of our addresses.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddressCoins {
+    #[serde(
+        serialize_with = "ser_to_string",
+        deserialize_with = "deser_addr_assume_checked"
+    )]
+    pub address: bitcoin::Address,
+    pub derivation_index: bip32::ChildNumber,
+    pub is_change: bool,
+    /// The total value of the coins.
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub total: bitcoin::Amount,
+    pub coins: Vec<ListCoinsEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoinsByAddressResult {
+    pub addresses: Vec<AddressCoins>,
+}
+
+/// A coin received on an address whose payment is being checked.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Payment {
+    pub outpoint: bitcoin::OutPoint,
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub amount: bitcoin::Amount,
+    /// Number of confirmations of the coin, 0 if it is unconfirmed.
+    pub confirmations: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentStatus {
+    #[serde(
+        serialize_with = "ser_to_string",
+        deserialize_with = "deser_addr_assume_checked"
+    )]
+    pub address: bitcoin::Address,
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub expected: bitcoin::Amount,
+    /// The total value received on the address, confirmed or not.
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub received: bitcoin::Amount,
+    /// How much more than expected was received.
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub excess: bitcoin::Amount,
+    /// The confirmation depth of the payment, `None` if not enough was received yet.
+    pub confirmations: Option<u32>,
+    /// Whether enough was received with the requested number of confirmations.
+    pub is_paid: bool,
+    /// The coins received on the address, most confirmed first.
+    pub payments: Vec<Payment>,
+}
+
+/// The relative importance of each criterion when choosing how to select coins for a transaction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SelectionWeights {
+    /// Prefer cheaper transactions.
+    pub fee_weight: u32,
+    /// Prefer transactions which don't combine coins from different addresses.
+    pub privacy_weight: u32,
+    /// Prefer transactions which spend more coins.
+    pub consolidation_weight: u32,
+}
+
+/// How a coin selection scored on each criterion, from 0 to 100, and in total given the weights.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SelectionScore {
+    pub fee: u8,
+    pub privacy: u8,
+    pub consolidation: u8,
+    pub total: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum CreateSpendResult {
+    Success {
+        #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_fromstr")]
+        psbt: Psbt,
+        warnings: Vec<String>,
+        /// The identifier of this Spend transaction draft.
+        draft_id: DraftId,
+        /// How much spending these coins together preserves privacy, from 0 to 100.
+        privacy_score: u8,
+        /// How the coin selection scored, if weights were given to choose it.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        selection_score: Option<SelectionScore>,
+        /// Whether the transaction spends coins which are not confirmed yet, and therefore
+        /// depends on their parent transactions being confirmed.
+        spends_unconfirmed: bool,
+        /// The seed which determined the position of the change output(s), if any.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        output_seed: Option<OutputSeed>,
+        /// The feerate the transaction was created for.
+        #[serde(
+            serialize_with = "ser_feerate_vb",
+            deserialize_with = "deser_feerate_vb"
+        )]
+        feerate: Feerate,
+        /// The indexes of the outputs paying to a destination which is one of our own addresses.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        self_send_outputs: Vec<usize>,
+        /// The indexes of the inputs spending coins which aren't ours, left for their owners to
+        /// sign.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        foreign_inputs: Vec<usize>,
+        /// Warnings about destinations with an unusual script, which could be a mistake.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        destination_warnings: Vec<String>,
+        /// The locktime of the transaction, set to discourage fee sniping.
+        #[serde(default)]
+        locktime: u32,
+    },
+    InsufficientFunds {
+        /// How many more sats are required to fund the transaction.
+        missing: u64,
+        /// The total value, in sats, of the coins which could be selected to fund the
+        /// transaction.
+        available: u64,
+        /// The total value, in sats, of the coins needed to fund the transaction.
+        needed: u64,
+    },
+}
+
+/// The signatures still needed to finalize a Spend transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SigningRequirements {
+    /// Timelock of the recovery path the transaction is to be satisfied through, `None` for the
+    /// primary path.
+    pub timelock: Option<u16>,
+    /// Number of signatures required by this spending path.
+    pub threshold: usize,
+    /// Number of signatures already provided.
+    pub sigs_count: usize,
+    /// Number of signatures still needed.
+    pub missing_sigs: usize,
+    /// Fingerprints of the keys of this spending path which didn't sign yet.
+    pub remaining_fingerprints: Vec<bip32::Fingerprint>,
+}
+
+/// The fee and size of a Spend transaction once finalized.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FinalFeeEstimate {
+    /// Timelock of the recovery path the transaction is to be satisfied through, `None` for the
+    /// primary path.
+    pub timelock: Option<u16>,
+    /// The fee paid by the transaction.
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub fee: bitcoin::Amount,
+    /// The virtual size of the finalized transaction.
+    pub vsize: u64,
+    /// The feerate of the finalized transaction, in sats/vb rounded down.
+    pub feerate_vb: u64,
+}
+
+/// An output of a PSBT which doesn't pay to us.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PsbtOutputInfo {
+    /// Index of this output in the transaction.
+    pub index: usize,
+    /// The address this output pays to, if its script corresponds to a standard address.
+    pub address: Option<bitcoin::Address<bitcoin::address::NetworkUnchecked>>,
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub amount: bitcoin::Amount,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PsbtInspection {
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub total_in: bitcoin::Amount,
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub total_out: bitcoin::Amount,
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub fee: bitcoin::Amount,
+    /// The feerate of the transaction once signed, assuming the largest satisfaction.
+    pub feerate_vb: u64,
+    /// The outputs which don't pay to us, to be checked against the intended recipients.
+    pub external_outputs: Vec<PsbtOutputInfo>,
+    /// The hex-encoded data carried by the OP_RETURN output of the transaction, if any.
+    pub op_return: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListSpendEntry {
+    #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_fromstr")]
+    pub psbt: Psbt,
+    pub updated_at: Option<u32>,
+    /// The identifier of the draft this Spend transaction is part of, if any.
+    pub draft_id: Option<DraftId>,
+    pub status: SpendStatus,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListSpendResult {
+    pub spend_txs: Vec<ListSpendEntry>,
+}
+
+/// Whether a transaction would be accepted to the mempool of our Bitcoin backend.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestBroadcastResult {
+    pub accepted: bool,
+    /// Why the transaction would be rejected, if it would.
+    pub reject_reason: Option<String>,
+    /// The virtual size of the transaction, if it would be accepted.
+    pub vsize: Option<u64>,
+    /// The fee of the transaction in sats, if it would be accepted.
+    pub fee: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListTransactionsResult {
+    pub transactions: Vec<TransactionInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionInfo {
+    #[serde(serialize_with = "ser_hex", deserialize_with = "deser_hex")]
+    pub tx: bitcoin::Transaction,
+    pub height: Option<i32>,
+    pub time: Option<u32>,
+    /// The txid of the transaction we broadcast to replace this one, if it is unconfirmed.
+    pub replaced_by: Option<bitcoin::Txid>,
+    /// The address book labels of the external addresses paid by this transaction.
+    #[serde(default)]
+    pub recipient_labels: HashMap<String, String>,
+}
+
+/// The transaction which created a coin of ours.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RawTxInfo {
+    #[serde(serialize_with = "ser_hex", deserialize_with = "deser_hex")]
+    pub tx: bitcoin::Transaction,
+    pub height: Option<i32>,
+    pub time: Option<u32>,
+}
+
+/// An iterator over the transactions which occured between two dates, most recent first. See
+/// [`DaemonControl::confirmed_transactions_iter`].
+pub struct ConfirmedTransactions<'a> {
+    control: &'a DaemonControl,
+    db_conn: Box<dyn DatabaseConnection>,
+    start: u32,
+    end: u32,
+    // How many txids were already queried from the database.
+    offset: u64,
+    chunk: std::vec::IntoIter<TransactionInfo>,
+    exhausted: bool,
+}
+
+impl<'a> Iterator for ConfirmedTransactions<'a> {
+    type Item = TransactionInfo;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(tx_info) = self.chunk.next() {
+                return Some(tx_info);
+            }
+            if self.exhausted {
+                return None;
+            }
+            let txids = self.db_conn.list_txids_page(
+                self.start,
+                self.end,
+                self.offset,
+                TXS_EXPORT_CHUNK_SIZE,
+            );
+            self.offset += txids.len() as u64;
+            self.exhausted = (txids.len() as u64) < TXS_EXPORT_CHUNK_SIZE;
+            self.chunk = self
+                .control
+                .transactions_info(&mut self.db_conn, &txids)
+                .into_iter();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DatabaseStatsResult {
+    /// The number of rows in each table, by table name.
+    pub table_rows: BTreeMap<String, u64>,
+    /// The size of the database, in bytes.
+    pub size: u64,
+    /// The size of the unused space in the database, which a vacuum would reclaim, in bytes.
+    pub free_size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PruneSpentCoinsResult {
+    /// The number of spent coins removed from database.
+    pub pruned_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CreateRecoveryResult {
+    #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_fromstr")]
+    pub psbt: Psbt,
+}
+
+/// The estimated cost of sweeping the coins currently spendable through a recovery path.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecoveryCostEstimate {
+    /// Timelock of the recovery path.
+    pub timelock: u16,
+    /// The number of coins which would be swept.
+    pub coins_count: usize,
+    /// The total value of the coins which would be swept.
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub amount: bitcoin::Amount,
+    /// The virtual size of the finalized sweep transaction.
+    pub vsize: u64,
+    /// The fee paid by the sweep transaction at the requested feerate.
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub fee: bitcoin::Amount,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bitcoin::{BackendKind, Block, BlockChainTip},
+        config::{PassphraseHash, MIN_PASSPHRASE_ITERATIONS},
+        database::{BlockInfo, FeerateSample},
+        spend::InsaneFeeInfo,
+        testutils::*,
+    };
+
+    use bitcoin::{
+        bip32::{self, ChildNumber},
+        blockdata::transaction::{TxIn, TxOut, Version as TxVersion},
+        locktime::absolute,
+        Amount, OutPoint, ScriptBuf, Sequence, Transaction, Txid, Witness,
+    };
+    use std::{collections::BTreeMap, str::FromStr};
+
+    #[test]
+    fn getinfo() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        // We can query getinfo
+        let info = ms.control().get_info();
+        assert_eq!(info.backend.kind, BackendKind::Bitcoind);
+        assert_eq!(info.backend.network, info.network);
+        assert_eq!(info.max_spendable, bitcoin::Amount::ZERO);
+        assert!(!info.recovery_available);
+        assert_eq!(info.blocks_until_next_recovery, None);
+        ms.shutdown();
+    }
+
+    #[test]
+    fn reconnect_backend() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        ms.control().reconnect_backend().unwrap();
+        assert_eq!(ms.control().get_info().block_height, 100);
+        ms.shutdown();
+
+        // The error is returned if the backend is still unreachable.
+        let mut btc = DummyBitcoind::new();
+        btc.reachable = false;
+        let ms = DummyLiana::new(btc, DummyDatabase::new());
+        assert_eq!(
+            ms.control().reconnect_backend(),
+            Err(CommandError::BackendConnection(
+                "Connection refused".to_string()
+            ))
+        );
+        ms.shutdown();
+    }
+
+    #[test]
+    fn getinfo_recovery() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.update_tip(&BlockChainTip {
+            hash: bitcoin::BlockHash::from_str(
+                "000000007bc154e0fa7ea32218a72fe2c1bb9f86cf8c9ebf9a715ed27fdb229a",
+            )
+            .unwrap(),
+            height: 100,
+        });
+        let txid =
+            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                .unwrap();
+        let coin = |vout, height: Option<i32>, is_immature| Coin {
+            outpoint: OutPoint::new(txid, vout),
+            is_immature,
+            block_info: height.map(|height| BlockInfo { height, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(vout),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        };
+
+        // The recovery path becomes available once the timelock expires in the next block.
+        let confirmed = coin(0, Some(50), false);
+        assert_eq!(blocks_before_recovery(&confirmed, 58, 10), Some(1));
+        assert_eq!(blocks_before_recovery(&confirmed, 59, 10), Some(0));
+        assert_eq!(blocks_before_recovery(&confirmed, 1_000, 10), Some(0));
+        assert_eq!(blocks_before_recovery(&coin(0, None, false), 58, 10), None);
+
+        // Unconfirmed and immature coins aren't considered. The recovery timelock is 10_000
+        // blocks, so the oldest confirmed coin is recoverable in the block at height 10_099.
+        db_conn.new_unspent_coins(&[
+            coin(0, None, false),
+            coin(1, Some(50), true),
+            coin(2, Some(99), false),
+            coin(3, Some(100), false),
+        ]);
+        let info = control.get_info();
+        assert!(!info.recovery_available);
+        assert_eq!(info.blocks_until_next_recovery, Some(9_998));
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn getinfo_max_spendable() {
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.feerate = Some(2);
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let txid =
+            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                .unwrap();
+        let coin = |vout, amount, is_confirmed: bool, is_change, is_immature| Coin {
+            outpoint: OutPoint::new(txid, vout),
+            is_immature,
+            block_info: is_confirmed.then_some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(amount),
+            derivation_index: bip32::ChildNumber::from(vout),
+            is_change,
+            spend_txid: None,
+            spend_block: None,
+        };
+        db_conn.new_unspent_coins(&[
+            coin(0, 100_000, true, false, false),
+            // Unconfirmed change is spendable, but not unconfirmed external deposits.
+            coin(1, 20_000, false, true, false),
+            coin(2, 50_000, false, false, false),
+            // Neither are immature coins nor coins not worth the fee to spend them.
+            coin(3, 50_000, true, false, true),
+            coin(4, 100, true, false, false),
+        ]);
+
+        // The fee to spend the two coins to a single output is deducted from their value.
+        let max_spendable = control.get_info().max_spendable.to_sat();
+        let input_fee = control.config.main_descriptor.spender_input_size() as u64 * 2;
+        assert!(max_spendable < 120_000 - 2 * input_fee);
+        assert!(max_spendable > 120_000 - 3 * input_fee);
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn set_log_level() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let prev_level = log::max_level();
+        ms.control().set_log_level(log::LevelFilter::Warn).unwrap();
+        assert_eq!(log::max_level(), log::LevelFilter::Warn);
+        ms.control().set_log_level(prev_level).unwrap();
+        assert_eq!(log::max_level(), prev_level);
+        ms.shutdown();
+    }
+
+    #[test]
+    fn descriptor_keys() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let res = ms.control().descriptor_keys();
+        assert_eq!(res.paths.len(), 2);
+        let (prim, reco) = (&res.paths[0], &res.paths[1]);
+        assert_eq!((prim.timelock, prim.threshold), (None, 1));
+        assert_eq!((reco.timelock, reco.threshold), (Some(10_000), 1));
+        assert_eq!(prim.keys.len(), 1);
+        assert_eq!(reco.keys.len(), 1);
+        assert_eq!(
+            prim.keys[0].fingerprint,
+            bip32::Fingerprint::from_str("aabbccdd").unwrap()
+        );
+        assert!(prim.keys[0].derivation_path.is_empty());
+        assert_eq!(prim.keys[0].xpub.to_string(), "xpub68JJTXc1MWK8KLW4HGLXZBJknja7kDUJuFHnM424LbziEXsfkh1WQCiEjjHw4zLqSUm4rvhgyGkkuRowE9tCJSgt3TQB5J3SKAbZ2SdcKST");
+        assert_eq!(reco.keys[0].xpub.to_string(), "xpub68JJTXc1MWK8PEQozKsRatrUHXKFNkD1Cb1BuQU9Xr5moCv87anqGyXLyUd4KpnDyZgo3gz4aN1r3NiaoweFW8UutBsBbgKHzaD5HkTkifK");
+        ms.shutdown();
+    }
+
+    #[test]
+    fn classify_addresses() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+
+        let ext_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let res = control
+            .classify_addresses(std::slice::from_ref(&ext_addr))
+            .unwrap()
+            .addresses;
+        assert_eq!(res.len(), 1);
+        assert_eq!(
+            res[&ext_addr.assume_checked().to_string()],
+            AddressKind::External
+        );
+
+        // An address for another network is rejected.
+        let testnet_addr =
+            bitcoin::Address::from_str("tb1qfufcrdyarcg5eph608c6l8vktrc9re6agu4se2").unwrap();
+        assert!(matches!(
+            control.classify_addresses(&[testnet_addr]),
+            Err(CommandError::Address(_))
+        ));
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn describe_policy() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let res = ms.control().describe_policy();
+        assert_eq!(res.paths.len(), 2);
+        assert_eq!(res.paths[0].description, "Primary: key aabbccdd");
+        assert_eq!(res.paths[1].timelock, Some(10_000));
+        assert!(res.paths[1]
+            .description
+            .starts_with("Recovery after 10000 blocks: key "));
+        assert_eq!(
+            res.description,
+            format!(
+                "{}. {}.",
+                res.paths[0].description, res.paths[1].description
+            )
+        );
+        ms.shutdown();
+    }
+
+    #[test]
+    fn feerate_estimates() {
+        let mut bitcoind = DummyBitcoind::new();
+
+        // The backend can't provide an estimate, fall back to the next source.
+        let sources = [FeeSource::Backend, FeeSource::Static { feerate: 5 }];
+        let estimate = feerate_estimate(&bitcoind, &sources, 2);
+        assert_eq!(
+            (estimate.feerate, estimate.source),
+            (5, FeerateEstimateSource::Static)
+        );
+
+        // No source can provide an estimate, use the fallback feerate.
+        let estimate = feerate_estimate(&bitcoind, &sources[..1], 2);
+        assert_eq!(
+            (estimate.feerate, estimate.source),
+            (FALLBACK_FEERATE, FeerateEstimateSource::Fallback)
+        );
+        let estimate = feerate_estimate(&bitcoind, &[], 2);
+        assert_eq!(estimate.source, FeerateEstimateSource::Fallback);
+
+        // The backend is used first if it can provide an estimate.
+        bitcoind.feerate = Some(12);
+        let estimate = feerate_estimate(&bitcoind, &sources, 6);
+        assert_eq!(
+            estimate,
+            FeerateEstimate {
+                conf_target: 6,
+                feerate: 12,
+                source: FeerateEstimateSource::Backend
+            }
+        );
+
+        // The configured sources are used for all targets.
+        let ms = DummyLiana::new(bitcoind, DummyDatabase::new());
+        let res = ms.control().get_feerate_estimates();
+        assert_eq!(res.estimates.len(), FEERATE_ESTIMATES_TARGETS.len());
+        assert!(res
+            .estimates
+            .iter()
+            .all(|est| est.feerate == 12 && est.source == FeerateEstimateSource::Backend));
+
+        // A priority resolves to the estimate for its confirmation target.
+        for priority in [FeePriority::Fast, FeePriority::Normal, FeePriority::Slow].iter() {
+            assert!(FEERATE_ESTIMATES_TARGETS.contains(&priority.conf_target()));
+            assert_eq!(
+                ms.control().priority_feerate(*priority),
+                Feerate::from_sat_per_vb(12)
+            );
+        }
+        ms.shutdown();
+    }
+
+    #[test]
+    fn feerate_estimates_cache() {
+        let mut bitcoind = DummyBitcoind::new();
+        bitcoind.feerate = Some(12);
+        let ms = DummyLiana::new(bitcoind, DummyDatabase::new());
+        let control = ms.control();
+        // The dummy daemon caches the estimates for a minute.
+        assert_eq!(control.config.feerate_cache_secs.as_secs(), 60);
+
+        // Fresh estimates have no age, and they are cached.
+        let res = control.get_feerate_estimates();
+        assert_eq!(res.age_secs, 0);
+        assert!(res.estimates.iter().all(|est| est.feerate == 12));
+        assert!(control.feerate_cache.lock().unwrap().is_some());
+
+        // Within the cache duration, the cached estimates are returned along with their age. Make
+        // the cached ones different to tell them apart from fresh estimates.
+        let cached_at = time::Instant::now()
+            .checked_sub(time::Duration::from_secs(30))
+            .unwrap();
+        let cached_estimates: Vec<_> = res
+            .estimates
+            .iter()
+            .map(|est| FeerateEstimate {
+                feerate: 99,
+                ..est.clone()
+            })
+            .collect();
+        *control.feerate_cache.lock().unwrap() = Some((cached_at, cached_estimates.clone()));
+        let res = control.get_feerate_estimates();
+        assert_eq!(res.estimates, cached_estimates);
+        assert!(res.age_secs >= 30);
+
+        // Once invalidated, fresh estimates are computed.
+        control.invalidate_feerate_cache();
+        assert!(control.feerate_cache.lock().unwrap().is_none());
+        let res = control.get_feerate_estimates();
+        assert_eq!(res.age_secs, 0);
+        assert!(res.estimates.iter().all(|est| est.feerate == 12));
+
+        // So are they once the cached ones expired.
+        let expired_at = time::Instant::now()
+            .checked_sub(time::Duration::from_secs(60))
+            .unwrap();
+        *control.feerate_cache.lock().unwrap() = Some((expired_at, cached_estimates));
+        let res = control.get_feerate_estimates();
+        assert_eq!(res.age_secs, 0);
+        assert!(res.estimates.iter().all(|est| est.feerate == 12));
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn feerate_history() {
+        let mut bitcoind = DummyBitcoind::new();
+        bitcoind.feerate = Some(12);
+        let ms = DummyLiana::new(bitcoind, DummyDatabase::new());
+        let control = ms.control();
+
+        // Some samples recorded a while ago.
+        control
+            .db()
+            .lock()
+            .unwrap()
+            .connection()
+            .store_feerate_samples(&[
+                FeerateSample {
+                    timestamp: 1_000,
+                    conf_target: 2,
+                    feerate: 50,
+                },
+                FeerateSample {
+                    timestamp: 2_000,
+                    conf_target: 2,
+                    feerate: 40,
+                },
+            ]);
+
+        // The poller samples the estimates of the backend for each confirmation target. Polling
+        // again right away doesn't record new samples.
+        for _ in 0..2 {
+            let (tx, rx) = mpsc::sync_channel(0);
+            control
+                .poller_sender
+                .send(PollerMessage::PollNow(tx))
+                .unwrap();
+            rx.recv().unwrap();
+        }
+        let samples = control.feerate_history(0).samples;
+        assert_eq!(samples.len(), 5);
+        assert_eq!(
+            samples[..2]
+                .iter()
+                .map(|s| (s.timestamp, s.feerate))
+                .collect::<Vec<_>>(),
+            vec![(1_000, 50), (2_000, 40)]
+        );
+        assert_eq!(
+            samples[2..]
+                .iter()
+                .map(|s| (s.conf_target, s.feerate))
+                .collect::<Vec<_>>(),
+            vec![(2, 12), (6, 12), (24, 12)]
+        );
+
+        // Older samples can be filtered out.
+        let samples = control.feerate_history(2_000).samples;
+        assert_eq!(samples.len(), 4);
+        assert_eq!(samples[0].timestamp, 2_000);
+        assert!(control.feerate_history(u32::MAX).samples.is_empty());
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn list_coins_spendable_paths() {
+        let mut coin = Coin {
+            outpoint: OutPoint::new(
+                Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                    .unwrap(),
+                0,
+            ),
+            is_immature: false,
+            block_info: Some(BlockInfo {
+                height: 50,
+                time: 1,
+            }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(0),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        };
+
+        // The recovery paths are available once the coin is old enough.
+        assert_eq!(spendable_paths(&coin, 58, &[10, 200], 0), vec![0]);
+        assert_eq!(spendable_paths(&coin, 59, &[10, 200], 0), vec![0, 1]);
+        assert_eq!(spendable_paths(&coin, 248, &[10, 200], 0), vec![0, 1]);
+        assert_eq!(spendable_paths(&coin, 249, &[10, 200], 0), vec![0, 1, 2]);
+        // An unconfirmed coin is only spendable through the primary path.
+        coin.block_info = None;
+        assert_eq!(spendable_paths(&coin, 249, &[10, 200], 0), vec![0]);
+        coin.block_info = Some(BlockInfo {
+            height: 50,
+            time: 1,
+        });
+        // Immature or spending coins aren't spendable at all.
+        coin.is_immature = true;
+        assert!(spendable_paths(&coin, 249, &[10, 200], 0).is_empty());
+        coin.is_immature = false;
+        coin.spend_txid = Some(coin.outpoint.txid);
+        assert!(spendable_paths(&coin, 249, &[10, 200], 0).is_empty());
+        coin.spend_txid = None;
+        // A coin without the required number of confirmations isn't spendable either.
+        assert!(spendable_paths(&coin, 58, &[10, 200], 10).is_empty());
+        assert_eq!(spendable_paths(&coin, 59, &[10, 200], 10), vec![0, 1]);
+        assert_eq!(spendable_paths(&coin, 50, &[10, 200], 1), vec![0]);
+        coin.block_info = None;
+        assert!(spendable_paths(&coin, 249, &[10, 200], 1).is_empty());
+        coin.block_info = Some(BlockInfo {
+            height: 50,
+            time: 1,
+        });
+
+        // Through the command, a coin of our wallet is only spendable through the primary path
+        // as long as the recovery timelock is not expired.
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[coin]);
+        let coins = control.list_coins(&[], &[]).coins;
+        assert_eq!(coins.len(), 1);
+        assert_eq!(coins[0].spendable_paths, vec![0]);
+        ms.shutdown();
+    }
+
+    #[test]
+    fn coin_notes() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let outpoint = OutPoint::from_str(
+            "617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3:0",
+        )
+        .unwrap();
+
+        // The coin must exist.
+        assert_eq!(
+            control.set_coin_note(&outpoint, Some("note".to_string())),
+            Err(CommandError::UnknownOutpoint(outpoint))
+        );
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint,
+            is_immature: false,
+            block_info: None,
+            amount: Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(3),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        assert_eq!(control.list_coins(&[], &[]).coins[0].note, None);
+
+        // A multiline note is returned when listing the coin. It's distinct from the label.
+        let note = "Withdrawal from the exchange.\nKeep for the house down payment.".to_string();
+        control
+            .set_coin_note(&outpoint, Some(note.clone()))
+            .unwrap();
+        assert_eq!(control.list_coins(&[], &[]).coins[0].note, Some(note));
+        assert!(control
+            .get_labels(&HashSet::from([LabelItem::OutPoint(outpoint)]))
+            .labels
+            .is_empty());
+
+        // It can be as long as the maximum length, but not longer.
+        let long_note = "é".repeat(MAX_COIN_NOTE_LENGTH);
+        control
+            .set_coin_note(&outpoint, Some(long_note.clone()))
+            .unwrap();
+        assert_eq!(
+            control.list_coins(&[], &[outpoint]).coins[0].note,
+            Some(long_note.clone())
+        );
+        assert_eq!(
+            control.set_coin_note(&outpoint, Some(long_note + "a")),
+            Err(CommandError::CoinNoteTooLong(outpoint))
+        );
+
+        // It can be removed.
+        control.set_coin_note(&outpoint, None).unwrap();
+        assert_eq!(control.list_coins(&[], &[]).coins[0].note, None);
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn list_coins_at_height() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let txid =
+            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                .unwrap();
+        let coin = |vout, block_height: Option<i32>, spend_height: Option<i32>| Coin {
+            outpoint: OutPoint::new(txid, vout),
+            is_immature: false,
+            block_info: block_height.map(|height| BlockInfo { height, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(vout),
+            is_change: false,
+            spend_txid: spend_height.map(|_| txid),
+            spend_block: spend_height.map(|height| BlockInfo { height, time: 1 }),
+        };
+        db_conn.new_unspent_coins(&[
+            coin(0, Some(10), None),
+            coin(1, Some(20), Some(30)),
+            coin(2, Some(40), None),
+            coin(3, None, None),
+        ]);
+
+        // We haven't scanned any block yet.
+        assert!(matches!(
+            control.list_coins_at_height(10),
+            Err(CommandError::HeightNotScanned(10))
+        ));
+        db_conn.update_tip(&BlockChainTip {
+            hash: bitcoin::BlockHash::from_str(
+                "000000007bc154e0fa7ea32218a72fe2c1bb9f86cf8c9ebf9a715ed27fdb229a",
+            )
+            .unwrap(),
+            height: 100,
+        });
+        assert!(matches!(
+            control.list_coins_at_height(101),
+            Err(CommandError::HeightNotScanned(101))
+        ));
+
+        let unspent_at = |height| {
+            let mut ops: Vec<u32> = control
+                .list_coins_at_height(height)
+                .unwrap()
+                .coins
+                .into_iter()
+                .map(|c| {
+                    assert!(c.spend_info.is_none());
+                    c.outpoint.vout
+                })
+                .collect();
+            ops.sort();
+            ops
+        };
+        assert!(unspent_at(9).is_empty());
+        assert_eq!(unspent_at(10), vec![0]);
+        assert_eq!(unspent_at(25), vec![0, 1]);
+        assert_eq!(unspent_at(30), vec![0]);
+        assert_eq!(unspent_at(100), vec![0, 2]);
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn coins_within_depth() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let txid =
+            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                .unwrap();
+        let coin = |vout, block_height: Option<i32>, spend_height: Option<i32>| Coin {
+            outpoint: OutPoint::new(txid, vout),
+            is_immature: false,
+            block_info: block_height.map(|height| BlockInfo { height, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(vout),
+            is_change: false,
+            spend_txid: spend_height.map(|_| txid),
+            spend_block: spend_height.map(|height| BlockInfo { height, time: 1 }),
+        };
+        db_conn.new_unspent_coins(&[
+            coin(0, Some(10), None),
+            coin(1, Some(95), Some(99)),
+            coin(2, Some(100), None),
+            coin(3, None, None),
+        ]);
+        db_conn.update_tip(&BlockChainTip {
+            hash: bitcoin::BlockHash::from_str(
+                "000000007bc154e0fa7ea32218a72fe2c1bb9f86cf8c9ebf9a715ed27fdb229a",
+            )
+            .unwrap(),
+            height: 100,
+        });
+
+        assert!(matches!(
+            control.coins_within_depth(0),
+            Err(CommandError::InvalidReorgDepth)
+        ));
+        let within = |depth| {
+            let mut ops: Vec<u32> = control
+                .coins_within_depth(depth)
+                .unwrap()
+                .coins
+                .into_iter()
+                .map(|c| c.outpoint.vout)
+                .collect();
+            ops.sort();
+            ops
+        };
+        // Unconfirmed coins are never part of the result, spent coins are.
+        assert_eq!(within(1), vec![2]);
+        assert_eq!(within(5), vec![2]);
+        assert_eq!(within(6), vec![1, 2]);
+        assert_eq!(within(91), vec![0, 1, 2]);
+        assert_eq!(within(u32::MAX), vec![0, 1, 2]);
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn recovery_cost_estimate() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let txid =
+            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                .unwrap();
+        let coin = |vout, height| Coin {
+            outpoint: OutPoint::new(txid, vout),
+            is_immature: false,
+            block_info: Some(BlockInfo { height, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(vout),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        };
+        db_conn.new_unspent_coins(&[coin(0, 90)]);
+
+        assert_eq!(
+            control.recovery_cost_estimate(0, None),
+            Err(CommandError::InvalidFeerate(0.into()))
+        );
+        assert_eq!(
+            control.recovery_cost_estimate(1, Some(1)),
+            Err(CommandError::UnknownRecoveryPath(1))
+        );
+        assert_eq!(
+            control.recovery_cost_estimate(1, None),
+            Err(CommandError::RecoveryNotAvailable)
+        );
+
+        // The dummy chain is shorter than the recovery timelock, pretend the coins were confirmed
+        // long enough ago for the recovery path to be available.
+        db_conn.new_unspent_coins(&[coin(1, 100 - 10_000), coin(2, 101 - 10_000)]);
+        let estimate = control.recovery_cost_estimate(2, Some(0)).unwrap();
+        assert_eq!(estimate, control.recovery_cost_estimate(2, None).unwrap());
+        assert_eq!(estimate.timelock, 10_000);
+        assert_eq!(estimate.coins_count, 2);
+        assert_eq!(estimate.amount, bitcoin::Amount::from_sat(200_000));
+        assert_eq!(estimate.vsize, 224);
+        assert_eq!(estimate.fee.to_sat(), estimate.vsize * 2);
+
+        // The recovery path witness is larger than the primary path's.
+        let desc = &control.config.main_descriptor;
+        assert!(desc.path_sat_weight(Some(10_000)) > desc.path_sat_weight(None));
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn snapshot() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: OutPoint::from_str(
+                "617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3:0",
+            )
+            .unwrap(),
+            is_immature: false,
+            block_info: None,
+            amount: Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(3),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+
+        // Only the requested parts are returned.
+        let snap = control.snapshot(&SnapshotParts::default()).unwrap();
+        assert!(snap.info.is_none() && snap.coins.is_none());
+        assert!(snap.feerate_estimates.is_none() && snap.spend_txs.is_none());
+        let parts = SnapshotParts {
+            coins: true,
+            ..SnapshotParts::default()
+        };
+        let snap = control.snapshot(&parts).unwrap();
+        assert_eq!(snap.coins.unwrap().coins.len(), 1);
+        assert!(snap.info.is_none());
+
+        // Make sure the poller already updated our tip, so it doesn't change between the snapshot
+        // and the comparison.
+        let (sender, receiver) = mpsc::sync_channel(0);
+        control
+            .poller_sender
+            .send(PollerMessage::PollNow(sender))
+            .unwrap();
+        receiver.recv().unwrap();
+        let snap = control.snapshot(&SnapshotParts::all()).unwrap();
+        assert_eq!(
+            snap.info.unwrap().block_height,
+            control.get_info().block_height
+        );
+        assert_eq!(snap.coins.unwrap().coins.len(), 1);
+        assert_eq!(
+            snap.feerate_estimates.unwrap(),
+            control.get_feerate_estimates()
+        );
+        assert!(snap.spend_txs.unwrap().spend_txs.is_empty());
+
+        // The poller was released.
+        control.reconnect_backend().unwrap();
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn diff_snapshots() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let outpoint = |vout| {
+            OutPoint::new(
+                Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                    .unwrap(),
+                vout,
+            )
+        };
+        let coin = |vout, block_info| Coin {
+            outpoint: outpoint(vout),
+            is_immature: false,
+            block_info,
+            amount: Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(vout),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        };
+        let block = Some(BlockInfo {
+            height: 10,
+            time: 1,
+        });
+        db_conn.new_unspent_coins(&[
+            coin(0, block),
+            coin(1, block),
+            coin(2, None),
+            coin(3, block),
+        ]);
+        let parts = SnapshotParts {
+            coins: true,
+            ..SnapshotParts::default()
+        };
+        let snap_a = control.snapshot(&parts).unwrap();
+        let outpoints = |coins: &[ListCoinsEntry]| -> Vec<OutPoint> {
+            let mut outpoints: Vec<_> = coins.iter().map(|c| c.outpoint).collect();
+            outpoints.sort();
+            outpoints
+        };
+
+        // Nothing changed.
+        let diff = control.diff_snapshots(&snap_a, &snap_a).unwrap();
+        assert!(diff.added.is_empty() && diff.spent.is_empty() && diff.reorged.is_empty());
+
+        // A coin is received and spent right away, another one is spent. The deposit of a third
+        // one is reorged, and the unconfirmed one is double spent.
+        let mut snap_b = snap_a.clone();
+        let coins_b = &mut snap_b.coins.as_mut().unwrap().coins;
+        let spend_info = LCSpendInfo {
+            txid: Txid::from_str(
+                "0000000000000000000000000000000000000000000000000000000000000001",
+            )
+            .unwrap(),
+            height: None,
+        };
+        let mut new_coin = coins_b[0].clone();
+        new_coin.outpoint = outpoint(4);
+        new_coin.spend_info = Some(spend_info);
+        coins_b.push(new_coin);
+        coins_b
+            .iter_mut()
+            .find(|c| c.outpoint == outpoint(0))
+            .unwrap()
+            .spend_info = Some(spend_info);
+        coins_b
+            .iter_mut()
+            .find(|c| c.outpoint == outpoint(1))
+            .unwrap()
+            .block_height = None;
+        coins_b.retain(|c| c.outpoint != outpoint(2));
+        let diff = control.diff_snapshots(&snap_a, &snap_b).unwrap();
+        assert_eq!(outpoints(&diff.added), vec![outpoint(4)]);
+        assert_eq!(outpoints(&diff.spent), vec![outpoint(0), outpoint(4)]);
+        assert_eq!(outpoints(&diff.reorged), vec![outpoint(1), outpoint(2)]);
+        // The reorged coins are as they were in the first snapshot.
+        assert!(diff.reorged.iter().any(|c| c.block_height == Some(10)));
+
+        // The spends are then undone, and a spent coin is pruned which isn't a reorg.
+        let mut snap_c = snap_b.clone();
+        let coins_c = &mut snap_c.coins.as_mut().unwrap().coins;
+        coins_c.retain(|c| c.outpoint != outpoint(4));
+        coins_c
+            .iter_mut()
+            .find(|c| c.outpoint == outpoint(0))
+            .unwrap()
+            .spend_info = None;
+        let diff = control.diff_snapshots(&snap_b, &snap_c).unwrap();
+        assert!(diff.added.is_empty() && diff.spent.is_empty());
+        assert_eq!(outpoints(&diff.reorged), vec![outpoint(0)]);
+
+        // Snapshots can be compared once serialized, as they are through the RPC interface.
+        let snap_all = control.snapshot(&SnapshotParts::all()).unwrap();
+        let snap_all: WalletSnapshot =
+            serde_json::from_value(serde_json::json!(&snap_all)).unwrap();
+        let diff = control.diff_snapshots(&snap_all, &snap_b).unwrap();
+        assert_eq!(outpoints(&diff.added), vec![outpoint(4)]);
+
+        // Both snapshots must include the coins.
+        let snap_info = control.snapshot(&SnapshotParts::default()).unwrap();
+        assert_eq!(
+            control.diff_snapshots(&snap_a, &snap_info).unwrap_err(),
+            CommandError::SnapshotWithoutCoins
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn dashboard() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+
+        // An empty wallet.
+        let summary = control.dashboard();
+        assert_eq!(summary.balance, bitcoin::Amount::ZERO);
+        assert_eq!(summary.unconfirmed_balance, bitcoin::Amount::ZERO);
+        assert_eq!((summary.coin_count, summary.pending_count), (0, 0));
+        assert!(summary.next_recovery_height.is_none());
+
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let txid =
+            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                .unwrap();
+        let coin = |vout, block_height: Option<i32>, spend_height: Option<Option<i32>>| Coin {
+            outpoint: OutPoint::new(txid, vout),
+            is_immature: false,
+            block_info: block_height.map(|height| BlockInfo { height, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000 * (u64::from(vout) + 1)),
+            derivation_index: bip32::ChildNumber::from(vout),
+            is_change: false,
+            spend_txid: spend_height.map(|_| txid),
+            spend_block: spend_height
+                .flatten()
+                .map(|height| BlockInfo { height, time: 1 }),
+        };
+        db_conn.new_unspent_coins(&[
+            // Confirmed.
+            coin(0, Some(20), None),
+            coin(1, Some(10), None),
+            // Unconfirmed.
+            coin(2, None, None),
+            // Being spent.
+            coin(3, Some(5), Some(None)),
+            // Spent.
+            coin(4, Some(1), Some(Some(30))),
+        ]);
+
+        let summary = control.dashboard();
+        assert_eq!(summary.balance, bitcoin::Amount::from_sat(300_000));
+        assert_eq!(
+            summary.unconfirmed_balance,
+            bitcoin::Amount::from_sat(300_000)
+        );
+        assert_eq!(summary.coin_count, 3);
+        assert_eq!(summary.pending_count, 2);
+        // The recovery path of the oldest unspent coin is the first to become available.
+        assert_eq!(summary.next_recovery_height, Some(10 + 10_000));
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn session_lock() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+
+        // Without a configured passphrase the session can't be locked, and is never locked.
+        assert!(!control.is_locked());
+        assert_eq!(control.lock(), Err(CommandError::NoSessionPassphrase));
+        assert_eq!(
+            control.unlock("pass"),
+            Err(CommandError::NoSessionPassphrase)
+        );
+        assert_eq!(control.check_unlocked(false), Ok(()));
+
+        // With one it starts locked, and only read-only commands are allowed by default.
+        let mut config = control.config.clone();
+        config.session_passphrase_hash = Some(PassphraseHash::new(
+            "pass",
+            b"sixteen byte salt".to_vec(),
+            MIN_PASSPHRASE_ITERATIONS,
+        ));
+        let locked_control = DaemonControl::new(
+            config.clone(),
+            control.bitcoin.clone(),
+            control.poller_sender.clone(),
+            control.db.clone(),
+            control.secp.clone(),
+        );
+        assert!(locked_control.is_locked());
+        assert_eq!(locked_control.check_unlocked(true), Ok(()));
+        assert_eq!(
+            locked_control.check_unlocked(false),
+            Err(CommandError::Locked)
+        );
+
+        // It must be unlocked with the right passphrase.
+        assert_eq!(
+            locked_control.unlock("wrong"),
+            Err(CommandError::WrongPassphrase)
+        );
+        assert!(locked_control.is_locked());
+        locked_control.unlock("pass").unwrap();
+        assert!(!locked_control.is_locked());
+        assert_eq!(locked_control.check_unlocked(false), Ok(()));
+        locked_control.unlock("pass").unwrap();
+
+        // And it can be locked again.
+        locked_control.lock().unwrap();
+        assert!(locked_control.is_locked());
+        assert_eq!(
+            locked_control.check_unlocked(false),
+            Err(CommandError::Locked)
+        );
+
+        // The commands which may modify the wallet check the session themselves, so they can't
+        // be used while locked even when called directly rather than through the JSONRPC API.
+        let dest: bitcoin::Address<address::NetworkUnchecked> =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        assert_eq!(
+            locked_control.create_spend(
+                &[(dest, 10_000)].iter().cloned().collect(),
+                &CoinSelector::All,
+                1,
+                CreateSpendOptions::default()
+            ),
+            Err(CommandError::Locked)
+        );
+        assert!(matches!(
+            locked_control.get_new_address(),
+            Err(CommandError::Locked)
+        ));
+        assert!(matches!(
+            locked_control.update_labels(&HashMap::new()),
+            Err(CommandError::Locked)
+        ));
+
+        // Read-only commands may be rejected too.
+        config.lock_read_commands = true;
+        let locked_control = DaemonControl::new(
+            config,
+            control.bitcoin.clone(),
+            control.poller_sender.clone(),
+            control.db.clone(),
+            control.secp.clone(),
+        );
+        assert_eq!(
+            locked_control.check_unlocked(true),
+            Err(CommandError::Locked)
+        );
+        locked_control.unlock("pass").unwrap();
+        assert_eq!(locked_control.check_unlocked(true), Ok(()));
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn balance() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+
+        // Make sure our tip is the one of the Bitcoin backend, at height 100.
+        let (tx, rx) = mpsc::sync_channel(0);
+        control
+            .poller_sender
+            .send(PollerMessage::PollNow(tx))
+            .unwrap();
+        rx.recv().unwrap();
+
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let txid =
+            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                .unwrap();
+        let coin = |vout, block_height: Option<i32>, is_immature, is_spending| Coin {
+            outpoint: OutPoint::new(txid, vout),
+            is_immature,
+            block_info: block_height.map(|height| BlockInfo { height, time: 1 }),
+            amount: bitcoin::Amount::from_sat(10_000 * (u64::from(vout) + 1)),
+            derivation_index: bip32::ChildNumber::from(vout),
+            is_change: false,
+            spend_txid: if is_spending { Some(txid) } else { None },
+            spend_block: None,
+        };
+        db_conn.new_unspent_coins(&[
+            // 1 and 10 confirmations.
+            coin(0, Some(100), false, false),
+            coin(1, Some(91), false, false),
+            // Unconfirmed.
+            coin(2, None, false, false),
+            // Immature.
+            coin(3, None, true, false),
+            // Being spent.
+            coin(4, Some(50), false, true),
+            // Reserved by a draft.
+            coin(5, Some(50), false, false),
+        ]);
+        control.coin_reservations.lock().unwrap().insert(
+            OutPoint::new(txid, 5),
+            (time::Instant::now(), DraftId::random().unwrap()),
+        );
+
+        let sats = bitcoin::Amount::from_sat;
+        let balance = control.balance(1);
+        assert_eq!(balance.min_confirmations, 1);
+        assert_eq!(balance.confirmed, sats(30_000));
+        assert_eq!(balance.unconfirmed, sats(30_000));
+        assert_eq!(balance.immature, sats(40_000));
+        assert_eq!(balance.reserved, sats(60_000));
+
+        // The threshold determines which coins are considered confirmed.
+        let balance = control.balance(2);
+        assert_eq!(balance.confirmed, sats(20_000));
+        assert_eq!(balance.unconfirmed, sats(40_000));
+        let balance = control.balance(0);
+        assert_eq!(balance.confirmed, sats(60_000));
+        assert_eq!(balance.unconfirmed, bitcoin::Amount::ZERO);
+        let balance = control.balance(11);
+        assert_eq!(balance.confirmed, bitcoin::Amount::ZERO);
+        assert_eq!(balance.unconfirmed, sats(60_000));
+        assert_eq!(balance.immature, sats(40_000));
+        assert_eq!(balance.reserved, sats(60_000));
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn prune_spent_coins() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+
+        // Make sure our tip is the one of the Bitcoin backend, at height 100.
+        let (tx, rx) = mpsc::sync_channel(0);
+        control
+            .poller_sender
+            .send(PollerMessage::PollNow(tx))
+            .unwrap();
+        rx.recv().unwrap();
+
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let txid =
+            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                .unwrap();
+        let coin = |vout, spend_height: Option<i32>| Coin {
+            outpoint: OutPoint::new(txid, vout),
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(vout),
+            is_change: false,
+            spend_txid: spend_height.map(|_| txid),
+            spend_block: spend_height.map(|height| BlockInfo { height, time: 1 }),
+        };
+        db_conn.new_unspent_coins(&[coin(0, None), coin(1, Some(1)), coin(2, Some(2))]);
+
+        // Pruning too recent spends isn't allowed.
+        assert_eq!(
+            control.prune_spent_coins(MIN_PRUNING_DEPTH - 1),
+            Err(CommandError::PruningTooShallow(MIN_PRUNING_DEPTH - 1))
+        );
+
+        // Only the coin whose spending transaction has 100 confirmations is removed.
+        assert_eq!(
+            control.prune_spent_coins(100).unwrap(),
+            PruneSpentCoinsResult { pruned_count: 1 }
+        );
+        let outpoints: Vec<_> = control
+            .list_coins(&[], &[])
+            .coins
+            .into_iter()
+            .map(|c| c.outpoint.vout)
+            .collect();
+        assert_eq!(outpoints.len(), 2);
+        assert!(!outpoints.contains(&1));
+        assert_eq!(
+            control.prune_spent_coins(100).unwrap(),
+            PruneSpentCoinsResult { pruned_count: 0 }
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn list_coins_by_address() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let txid =
+            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                .unwrap();
+        let coin = |vout, index: u32, amount| Coin {
+            outpoint: OutPoint::new(txid, vout),
+            is_immature: false,
+            block_info: None,
+            amount: bitcoin::Amount::from_sat(amount),
+            derivation_index: bip32::ChildNumber::from(index),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        };
+        assert!(control.list_coins_by_address(&[]).addresses.is_empty());
+        db_conn.new_unspent_coins(&[
+            coin(0, 3, 500_000),
+            coin(1, 7, 100_000),
+            coin(2, 7, 200_000),
+        ]);
+
+        // The reused address comes first, with its coins and their total value.
+        let addresses = control.list_coins_by_address(&[]).addresses;
+        assert_eq!(addresses.len(), 2);
+        assert_eq!(addresses[0].derivation_index, bip32::ChildNumber::from(7));
+        assert_eq!(addresses[0].total, bitcoin::Amount::from_sat(300_000));
+        assert_eq!(
+            addresses[0]
+                .coins
+                .iter()
+                .map(|c| c.outpoint.vout)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert!(addresses[0]
+            .coins
+            .iter()
+            .all(|c| c.address == addresses[0].address));
+        assert_eq!(addresses[1].derivation_index, bip32::ChildNumber::from(3));
+        assert_eq!(addresses[1].total, bitcoin::Amount::from_sat(500_000));
+        assert_eq!(addresses[1].coins.len(), 1);
+        assert_ne!(addresses[0].address, addresses[1].address);
+
+        // The coins may be filtered by status.
+        db_conn.spend_coins(&[(
+            OutPoint::new(txid, 1),
+            Txid::from_str("0c62a990d20d54429e70859292e82374ba6b1b951a3ab60f26bb65fee5724ff7")
+                .unwrap(),
+        )]);
+        let addresses = control
+            .list_coins_by_address(&[CoinStatus::Unconfirmed])
+            .addresses;
+        assert_eq!(addresses.len(), 2);
+        assert_eq!(addresses[0].derivation_index, bip32::ChildNumber::from(3));
+        assert_eq!(addresses[1].total, bitcoin::Amount::from_sat(200_000));
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn coin_address() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let txid =
+            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                .unwrap();
+        let coin = |vout, is_change| Coin {
+            outpoint: OutPoint::new(txid, vout),
+            is_immature: false,
+            block_info: None,
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(42),
+            is_change,
+            spend_txid: None,
+            spend_block: None,
+        };
+        db_conn.new_unspent_coins(&[coin(0, false), coin(1, true)]);
+
+        // The address is the one of the coin, be it a receive or a change one.
+        let listed = control.list_coins(&[], &[]).coins;
+        for vout in 0..2 {
+            let outpoint = OutPoint::new(txid, vout);
+            let res = control.coin_address(&outpoint).unwrap();
+            let entry = listed.iter().find(|c| c.outpoint == outpoint).unwrap();
+            assert_eq!(res.address, entry.address);
+            assert_eq!(res.derivation_index, bip32::ChildNumber::from(42));
+            assert_eq!(res.is_change, vout == 1);
+        }
+        assert_ne!(
+            control
+                .coin_address(&OutPoint::new(txid, 0))
+                .unwrap()
+                .address,
+            control
+                .coin_address(&OutPoint::new(txid, 1))
+                .unwrap()
+                .address
+        );
+
+        let unknown = OutPoint::new(txid, 2);
+        assert_eq!(
+            control.coin_address(&unknown),
+            Err(CommandError::UnknownOutpoint(unknown))
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn coin_funding_tx() {
+        let funding_tx = bitcoin::Transaction {
+            version: TxVersion::TWO,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: vec![],
+            output: vec![bitcoin::TxOut {
+                value: bitcoin::Amount::from_sat(100_000),
+                script_pubkey: bitcoin::ScriptBuf::new(),
+            }],
+        };
+        let txid = funding_tx.txid();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(txid, (funding_tx.clone(), None));
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+
+        // One coin whose funding transaction the backend knows about, one it doesn't.
+        let pruned_txid =
+            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                .unwrap();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let coin = |outpoint| Coin {
+            outpoint,
+            is_immature: false,
+            block_info: Some(BlockInfo {
+                height: 10,
+                time: 1_700_000_000,
+            }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(0),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        };
+        let (outpoint, pruned_outpoint) = (OutPoint::new(txid, 0), OutPoint::new(pruned_txid, 0));
+        db_conn.new_unspent_coins(&[coin(outpoint), coin(pruned_outpoint)]);
+
+        // The transaction is returned along with its confirmation, and cached.
+        let res = control.coin_funding_tx(&outpoint).unwrap();
+        assert_eq!(res.tx, funding_tx);
+        assert_eq!(res.height, Some(10));
+        assert_eq!(res.time, Some(1_700_000_000));
+        assert!(control.funding_txs.lock().unwrap().contains_key(&txid));
+
+        // The cached transaction is used when the backend can't provide it.
+        control
+            .funding_txs
+            .lock()
+            .unwrap()
+            .insert(pruned_txid, funding_tx.clone());
+        assert_eq!(
+            control.coin_funding_tx(&pruned_outpoint).unwrap().tx,
+            funding_tx
+        );
+        control.funding_txs.lock().unwrap().remove(&pruned_txid);
+        assert_eq!(
+            control.coin_funding_tx(&pruned_outpoint).unwrap_err(),
+            CommandError::FundingTxUnavailable(pruned_txid)
+        );
+
+        let unknown = OutPoint::new(txid, 1);
+        assert_eq!(
+            control.coin_funding_tx(&unknown).unwrap_err(),
+            CommandError::UnknownOutpoint(unknown)
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn last_address_status() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+
+        // No address was handed out yet.
+        assert_eq!(
+            control.last_address_status(),
+            LastAddressStatus {
+                address: None,
+                derivation_index: None,
+                used: false,
+            }
+        );
+
+        // Once one is, it's reported as unused until a coin is received on it.
+        let addr = control.get_new_address().unwrap();
+        let status = control.last_address_status();
+        assert_eq!(status.address, Some(addr.address.clone()));
+        assert_eq!(status.derivation_index, Some(addr.derivation_index));
+        assert!(!status.used);
+
+        // A change coin at the same index doesn't count.
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let txid =
+            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                .unwrap();
+        let coin = |vout, is_change| Coin {
+            outpoint: OutPoint::new(txid, vout),
+            is_immature: false,
+            block_info: None,
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: addr.derivation_index,
+            is_change,
+            spend_txid: None,
+            spend_block: None,
+        };
+        db_conn.new_unspent_coins(&[coin(0, true)]);
+        assert!(!control.last_address_status().used);
+        db_conn.new_unspent_coins(&[coin(1, false)]);
+        assert!(control.last_address_status().used);
+
+        // Generating a new address resets the status.
+        let new_addr = control.get_new_address().unwrap();
+        let status = control.last_address_status();
+        assert_eq!(status.address, Some(new_addr.address));
+        assert!(!status.used);
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn get_unused_address() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+
+        // An address is generated if none was yet, and handed out again until it is used.
+        let addr = control.get_unused_address().unwrap();
+        assert_eq!(addr.derivation_index, bip32::ChildNumber::from(0));
+        let same_addr = control.get_unused_address().unwrap();
+        assert_eq!(same_addr.address, addr.address);
+        assert_eq!(same_addr.derivation_index, addr.derivation_index);
+
+        // Once it received a coin, a new one is generated.
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: OutPoint::from_str(
+                "617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3:0",
+            )
+            .unwrap(),
+            is_immature: false,
+            block_info: None,
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: addr.derivation_index,
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        let new_addr = control.get_unused_address().unwrap();
+        assert_eq!(new_addr.derivation_index, bip32::ChildNumber::from(1));
+        assert_ne!(new_addr.address, addr.address);
+        assert_eq!(
+            control.get_unused_address().unwrap().derivation_index,
+            new_addr.derivation_index
+        );
+
+        // It follows the addresses generated by getnewaddress.
+        let newest_addr = control.get_new_address().unwrap();
+        assert_eq!(
+            control.get_unused_address().unwrap().address,
+            newest_addr.address
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn start_rescan_from_hash() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+
+        let unknown_hash = bitcoin::BlockHash::from_str(
+            "00000000000000000000000000000000000000000000000000000000000000aa",
+        )
+        .unwrap();
+        assert_eq!(
+            control.start_rescan_from_hash(&unknown_hash),
+            Err(CommandError::UnknownBlock(unknown_hash))
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn rescan_range() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+
+        // The dummy tip is at height 100.
+        assert_eq!(
+            control.rescan_range(10, 5),
+            Err(CommandError::InsaneRescanRange(10, 5))
+        );
+        assert_eq!(
+            control.rescan_range(0, 101),
+            Err(CommandError::InsaneRescanRange(0, 101))
+        );
+        assert_eq!(
+            control.rescan_range(u32::MAX, u32::MAX),
+            Err(CommandError::InsaneRescanRange(u32::MAX, u32::MAX))
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn getnewaddress() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+
+        let control = &ms.control();
+        // We can get an address
+        let addr = control.get_new_address().unwrap().address;
+        assert_eq!(
+            addr,
+            bitcoin::Address::from_str(
+                "bc1q9ksrc647hx8zp2cewl8p5f487dgux3777yees8rjcx46t4daqzzqt7yga8"
+            )
+            .unwrap()
+            .assume_checked()
+        );
+        // We won't get the same twice.
+        let addr2 = control.get_new_address().unwrap().address;
+        assert_ne!(addr, addr2);
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn listaddresses() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+
+        let control = &ms.control();
+
+        let list = control.list_addresses(Some(2), Some(5)).unwrap();
+
+        assert_eq!(list.addresses[0].index, 2);
+        assert_eq!(list.addresses.last().unwrap().index, 6);
+
+        let addr0 = control.get_new_address().unwrap().address;
+        let addr1 = control.get_new_address().unwrap().address;
+        let _addr2 = control.get_new_address().unwrap().address;
+        let addr3 = control.get_new_address().unwrap().address;
+        let addr4 = control.get_new_address().unwrap().address;
+
+        let list = control.list_addresses(Some(0), None).unwrap();
+
+        assert_eq!(list.addresses[0].index, 0);
+        assert_eq!(list.addresses[0].receive, addr0);
+        assert_eq!(list.addresses.last().unwrap().index, 4);
+        assert_eq!(list.addresses.last().unwrap().receive, addr4);
+
+        let list = control.list_addresses(None, None).unwrap();
+
+        assert_eq!(list.addresses[0].index, 0);
+        assert_eq!(list.addresses[0].receive, addr0);
+        assert_eq!(list.addresses.last().unwrap().index, 4);
+        assert_eq!(list.addresses.last().unwrap().receive, addr4);
+
+        let list = control.list_addresses(Some(1), Some(3)).unwrap();
+
+        assert_eq!(list.addresses[0].index, 1);
+        assert_eq!(list.addresses[0].receive, addr1);
+        assert_eq!(list.addresses.last().unwrap().index, 3);
+        assert_eq!(list.addresses.last().unwrap().receive, addr3);
+
+        let addr5 = control.get_new_address().unwrap().address;
+        let list = control.list_addresses(Some(5), None).unwrap();
+
+        assert_eq!(list.addresses[0].index, 5);
+        assert_eq!(list.addresses[0].receive, addr5);
+        assert_eq!(list.addresses.last().unwrap().index, 5);
+        assert_eq!(list.addresses.last().unwrap().receive, addr5);
+
+        // We can get no address for the last unhardened index.
+        let max_unhardened_index = 2u32.pow(31) - 1;
+        let res = control
+            .list_addresses(Some(max_unhardened_index), Some(0))
+            .unwrap();
+        // This is equivalent to not passing a count.
+        assert_eq!(
+            res,
+            control
+                .list_addresses(Some(max_unhardened_index), None)
+                .unwrap()
+        );
+        // We can also get the one last unhardened index.
+        control
+            .list_addresses(Some(max_unhardened_index), Some(1))
+            .unwrap();
+        // However we can't get into hardened territory.
+        assert_eq!(
+            control
+                .list_addresses(Some(max_unhardened_index), Some(2))
+                .unwrap_err(),
+            CommandError::InvalidDerivationIndex
+        );
+
+        // We also can't pass a hardened start index.
+        let first_hardened_index = max_unhardened_index + 1;
+        assert_eq!(
+            control
+                .list_addresses(Some(first_hardened_index), None)
+                .unwrap_err(),
+            CommandError::InvalidDerivationIndex
+        );
+        assert_eq!(
+            control
+                .list_addresses(Some(first_hardened_index), Some(0))
+                .unwrap_err(),
+            CommandError::InvalidDerivationIndex
+        );
+        assert_eq!(
+            control
+                .list_addresses(Some(first_hardened_index), Some(1))
+                .unwrap_err(),
+            CommandError::InvalidDerivationIndex
+        );
+
+        // Much less so overflow.
+        assert_eq!(
+            control.list_addresses(Some(u32::MAX), None).unwrap_err(),
+            CommandError::InvalidDerivationIndex
+        );
+        assert_eq!(
+            control.list_addresses(Some(u32::MAX), Some(0)).unwrap_err(),
+            CommandError::InvalidDerivationIndex
+        );
+        assert_eq!(
+            control.list_addresses(Some(u32::MAX), Some(1)).unwrap_err(),
+            CommandError::InvalidDerivationIndex
+        );
+
+        // We won't crash if we pass a start index larger than the next derivation index without
+        // passing a count. (ie no underflow.)
+        let next_deriv_index = list.addresses.last().unwrap().index + 1;
+        control
+            .list_addresses(Some(next_deriv_index + 1), None)
+            .unwrap();
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn address_consistency_report() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+        let (desc, network) = (
+            &control.config.main_descriptor,
+            control.config.bitcoin_config.network,
+        );
+        let stored_addrs = |index: u32| StoredAddresses {
+            derivation_index: index.into(),
+            receive_address: desc
+                .receive_descriptor()
+                .derive(index.into(), &control.secp)
+                .address(network)
+                .as_unchecked()
+                .clone(),
+            change_address: desc
+                .change_descriptor()
+                .derive(index.into(), &control.secp)
+                .address(network)
+                .as_unchecked()
+                .clone(),
+        };
+
+        // Nothing stored nor in use, nothing to report.
+        let report = control.verify_address_consistency();
+        assert_eq!(report.checked, 0);
+        assert!(report.missing.is_empty() && report.mismatches.is_empty());
+
+        // All the stored addresses match, but one index in use is missing.
+        let mut stored: Vec<_> = [0, 1, 3, 4].iter().map(|i| stored_addrs(*i)).collect();
+        let report = address_consistency(desc, &control.secp, network, &stored, 4);
+        assert_eq!(report.checked, 4);
+        assert_eq!(report.missing, vec![2.into()]);
+        assert!(report.mismatches.is_empty());
+
+        // The change address stored at index 1 is the one of index 3.
+        stored[1].change_address = stored[2].change_address.clone();
+        let report = address_consistency(desc, &control.secp, network, &stored, 4);
+        assert_eq!(report.mismatches.len(), 1);
+        let mismatch = &report.mismatches[0];
+        assert_eq!(mismatch.derivation_index, 1.into());
+        assert!(mismatch.is_change);
+        assert_eq!(mismatch.stored, stored[2].change_address);
+        assert_eq!(
+            mismatch.expected.as_unchecked(),
+            &stored_addrs(1).change_address
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn update_labels() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+        let txid =
+            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                .unwrap();
+        let (item_a, item_b) = (
+            LabelItem::Txid(txid),
+            LabelItem::OutPoint(OutPoint::new(txid, 0)),
+        );
+
+        let summary = control
+            .update_labels(
+                &[
+                    (item_a.clone(), Some("a".to_string())),
+                    (item_b.clone(), None),
+                ]
+                .iter()
+                .cloned()
+                .collect(),
+            )
+            .unwrap();
+        assert_eq!(summary.created, vec![item_a.clone()]);
+        assert!(summary.updated.is_empty() && summary.deleted.is_empty());
+
+        // Setting the same value again is not an update.
+        let summary = control
+            .update_labels(
+                &[
+                    (item_a.clone(), Some("a".to_string())),
+                    (item_b.clone(), Some("b".to_string())),
+                ]
+                .iter()
+                .cloned()
+                .collect(),
+            )
+            .unwrap();
+        assert_eq!(summary.created, vec![item_b.clone()]);
+        assert!(summary.updated.is_empty() && summary.deleted.is_empty());
+
+        let summary = control
+            .update_labels(
+                &[
+                    (item_a.clone(), Some("aa".to_string())),
+                    (item_b.clone(), None),
+                ]
+                .iter()
+                .cloned()
+                .collect(),
+            )
+            .unwrap();
+        assert!(summary.created.is_empty());
+        assert_eq!(summary.updated, vec![item_a]);
+        assert_eq!(summary.deleted, vec![item_b]);
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn tx_related_labels() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+        let prev_op = OutPoint::from_str(
+            "617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3:1",
+        )
+        .unwrap();
+        let our_addr = control.get_new_address().unwrap().address;
+        let ext_addr = bitcoin::Address::from_str("bc1qvklensptw5lk7d470ds60pcpsr0psdpgyvwepv")
+            .unwrap()
+            .assume_checked();
+        let tx = Transaction {
+            version: TxVersion::TWO,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: vec![TxIn {
+                previous_output: prev_op,
+                ..TxIn::default()
+            }],
+            output: vec![
+                TxOut {
+                    script_pubkey: our_addr.script_pubkey(),
+                    value: Amount::from_sat(10_000),
+                },
+                TxOut {
+                    script_pubkey: ext_addr.script_pubkey(),
+                    value: Amount::from_sat(20_000),
+                },
+            ],
+        };
+        let txid = tx.txid();
+
+        // The transaction must be known.
+        assert_eq!(
+            control.tx_related_labels(&txid),
+            Err(CommandError::UnknownTransaction(txid))
+        );
+        let psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        control.db().connection().store_spend(&psbt);
+        let labels = control.tx_related_labels(&txid).unwrap();
+        assert_eq!(labels.transaction, None);
+        assert!(labels.inputs.is_empty() && labels.outputs.is_empty());
+
+        // Only the labels related to the transaction are returned, grouped by kind of item.
+        let unrelated_op = OutPoint::new(prev_op.txid, 0);
+        control
+            .update_labels(
+                &[
+                    (LabelItem::Txid(txid), Some("rent".to_string())),
+                    (LabelItem::OutPoint(prev_op), Some("salary".to_string())),
+                    (LabelItem::OutPoint(unrelated_op), Some("other".to_string())),
+                    (
+                        LabelItem::Address(our_addr.clone()),
+                        Some("change".to_string()),
+                    ),
+                    (
+                        LabelItem::Address(ext_addr.clone()),
+                        Some("landlord".to_string()),
+                    ),
+                ]
+                .iter()
+                .cloned()
+                .collect(),
+            )
+            .unwrap();
+        let labels = control.tx_related_labels(&txid).unwrap();
+        assert_eq!(labels.transaction, Some("rent".to_string()));
+        assert_eq!(labels.inputs.len(), 1);
+        assert_eq!(labels.inputs.get(&prev_op.to_string()).unwrap(), "salary");
+        assert_eq!(labels.outputs.len(), 2);
+        assert_eq!(labels.outputs.get(&our_addr.to_string()).unwrap(), "change");
+        assert_eq!(
+            labels.outputs.get(&ext_addr.to_string()).unwrap(),
+            "landlord"
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn replace_all_labels() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+        let txid =
+            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                .unwrap();
+        let (item_a, item_b) = (
+            LabelItem::Txid(txid),
+            LabelItem::OutPoint(OutPoint::new(txid, 0)),
+        );
+        control
+            .update_labels(
+                &[
+                    (item_a.clone(), Some("a".to_string())),
+                    (item_b.clone(), Some("b".to_string())),
+                ]
+                .iter()
+                .cloned()
+                .collect(),
+            )
+            .unwrap();
+
+        // The previous labels are all gone, including those of items not in the new set.
+        let res = control
+            .replace_all_labels(
+                &[(item_a.clone(), "aa".to_string())]
+                    .iter()
+                    .cloned()
+                    .collect(),
+            )
+            .unwrap();
+        assert_eq!((res.deleted, res.stored), (2, 1));
+        let labels = control.export_all_labels().labels;
+        assert_eq!(labels.len(), 1);
+        assert_eq!((&labels[0].item, labels[0].value.as_str()), (&item_a, "aa"));
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_spend() {
+        let dummy_op = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(
+            dummy_op.txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        // Use a seed which places the change output last.
+        let seed = OutputSeed::from_str(
+            "0808080808080808080808080808080808080808080808080808080808080808",
+        )
+        .unwrap();
+
+        // Arguments sanity checking
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let dummy_value = 10_000;
+        let mut destinations = <HashMap<bitcoin::Address<address::NetworkUnchecked>, u64>>::new();
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::All,
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Err(CommandError::NoOutpointForSelfSend)
+        );
+        destinations = [(dummy_addr.clone(), dummy_value)]
+            .iter()
+            .cloned()
+            .collect();
+        // Insufficient funds for coin selection.
+        assert!(matches!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::All,
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Ok(CreateSpendResult::InsufficientFunds { .. }),
+        ));
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                0,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Err(CommandError::InvalidFeerate(0.into()))
+        );
+
+        // The coin doesn't exist. If we create a new unspent one at this outpoint with a much
+        // higher value, we'll get a Spend transaction with a change output.
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Err(CommandError::UnknownOutpoint(dummy_op))
+        );
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: dummy_op,
+            is_immature: false,
+            block_info: None,
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        // If we try to use coin selection, the unconfirmed non-change coin will not be used
+        // as a candidate and so we get a coin selection error due to insufficient funds.
+        assert!(matches!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::All,
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Ok(CreateSpendResult::InsufficientFunds { .. }),
+        ));
+        let (psbt, warnings, feerate) = if let CreateSpendResult::Success {
+            psbt,
+            warnings,
+            feerate,
+            ..
+        } = control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            (psbt, warnings, feerate)
+        } else {
+            panic!("expect successful spend creation")
+        };
+        assert_eq!(feerate, Feerate::from_sat_per_vb(1));
+        assert!(psbt.inputs[0].non_witness_utxo.is_some());
+        // The descriptor's xpubs are set in the PSBT for signing devices to recognize change.
+        assert_eq!(psbt.xpub, control.config.main_descriptor.global_xpubs());
+        assert_eq!(psbt.xpub.len(), 2);
+        let tx = psbt.unsigned_tx;
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].previous_output, dummy_op);
+        assert_eq!(tx.output.len(), 2);
+        // It has change so no warnings expected.
+        assert!(warnings.is_empty());
+        assert_eq!(
+            tx.output[0].script_pubkey,
+            dummy_addr.payload().script_pubkey()
+        );
+        assert_eq!(tx.output[0].value.to_sat(), dummy_value);
+
+        // NOTE: if you are wondering about the usefulness of these tests asserting arbitrary fixed
+        // values, that's a belt-and-suspenders check to make sure size and fee calculations do not
+        // change unexpectedly. For instance this specific test caught how a change in
+        // rust-bitcoin's serialization of transactions with no input silently affected our fee
+        // calculation.
+
+        // Transaction is 1 in (P2WSH satisfaction), 2 outs. At 1sat/vb, it's 170 sats fees.
+        // At 2sats/vb, it's twice that.
+        assert_eq!(tx.output[1].value.to_sat(), 89_830);
+        let psbt = if let CreateSpendResult::Success { psbt, .. } = control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                2,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            psbt
+        } else {
+            panic!("expect successful spend creation")
+        };
+        let tx = psbt.unsigned_tx;
+        assert_eq!(tx.output[1].value.to_sat(), 89_660);
+
+        // A feerate of 555 won't trigger the sanity checks (they were previously not taking the
+        // satisfaction size into account and overestimating the feerate).
+        control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                555,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        // If we ask for a too high feerate, or a too large/too small output, it'll fail.
+        assert!(matches!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                10_000,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Ok(CreateSpendResult::InsufficientFunds { .. }),
+        ));
+        *destinations.get_mut(&dummy_addr).unwrap() = 100_001;
+        assert!(matches!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Ok(CreateSpendResult::InsufficientFunds { .. }),
+        ));
+        *destinations.get_mut(&dummy_addr).unwrap() = 4_500;
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Err(CommandError::SpendCreation(
+                SpendCreationError::InvalidOutputValue(bitcoin::Amount::from_sat(4_500))
+            ))
+        );
+
+        // If we ask to create an output for an address from another network, it will fail.
+        let invalid_addr =
+            bitcoin::Address::new(bitcoin::Network::Testnet, dummy_addr.payload().clone());
+        let invalid_destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(invalid_addr, dummy_value)].iter().cloned().collect();
+        assert!(matches!(
+            control.create_spend(
+                &invalid_destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Err(CommandError::Address(
+                address::Error::NetworkValidation { .. }
+            ))
+        ));
+
+        // If we ask for a large, but valid, output we won't get a change output. 95_000 because we
+        // won't create an output lower than 5k sats.
+        *destinations.get_mut(&dummy_addr).unwrap() = 95_000;
+        let (psbt, warnings) = if let CreateSpendResult::Success { psbt, warnings, .. } = control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            (psbt, warnings)
+        } else {
+            panic!("expect successful spend creation")
+        };
+        let tx = psbt.unsigned_tx;
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].previous_output, dummy_op);
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(
+            tx.output[0].script_pubkey,
+            dummy_addr.payload().script_pubkey()
+        );
+        assert_eq!(tx.output[0].value.to_sat(), 95_000);
+        // change = 100_000 - 95_000 - /* fee without change */ 127 - /* extra fee for change output */ 43 = 4830
+        assert_eq!(
+            warnings,
+            vec![
+                "Dust UTXO. The minimal change output allowed by Liana is 5000 sats. \
+                Instead of creating a change of 4830 sats, it was added to the \
+                transaction fee. Select a larger input to avoid this from happening."
+            ]
+        );
+
+        // Increase the target value by the change amount and the warning will disappear.
+        *destinations.get_mut(&dummy_addr).unwrap() = 95_000 + 4_830;
+        let (psbt, warnings) = if let CreateSpendResult::Success { psbt, warnings, .. } = control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            (psbt, warnings)
+        } else {
+            panic!("expect successful spend creation")
+        };
+        let tx = psbt.unsigned_tx;
+        assert_eq!(tx.output.len(), 1);
+        assert!(warnings.is_empty());
+
+        // Now increase target also by the extra fee that was paying for change and we can still create the spend.
+        *destinations.get_mut(&dummy_addr).unwrap() =
+            95_000 + 4_830 + /* fee for change output */ 43;
+        let (psbt, warnings) = if let CreateSpendResult::Success { psbt, warnings, .. } = control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            (psbt, warnings)
+        } else {
+            panic!("expect successful spend creation")
+        };
+        let tx = psbt.unsigned_tx;
+        assert_eq!(tx.output.len(), 1);
+        assert!(warnings.is_empty());
+
+        // Now increase the target by 1 more sat and we will have insufficient funds.
+        *destinations.get_mut(&dummy_addr).unwrap() =
+            95_000 + 4_830 + /* fee for change output */ 43 + 1;
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Ok(CreateSpendResult::InsufficientFunds {
+                missing: 1,
+                available: 100_000,
+                needed: 100_001,
+            }),
+        );
+
+        // Now decrease the target so that the lost change is just 1 sat.
+        *destinations.get_mut(&dummy_addr).unwrap() =
+            100_000 - /* fee without change */ 127 - /* extra fee for change output */ 43 - 1;
+        let warnings = if let CreateSpendResult::Success { warnings, .. } = control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            warnings
+        } else {
+            panic!("expect successful spend creation")
+        };
+        // Message uses "sat" instead of "sats" when value is 1.
+        assert_eq!(
+            warnings,
+            vec![
+                "Dust UTXO. The minimal change output allowed by Liana is 5000 sats. \
+                Instead of creating a change of 1 sat, it was added to the \
+                transaction fee. Select a larger input to avoid this from happening."
+            ]
+        );
+
+        // Now decrease the target value so that we have enough for a change output.
+        *destinations.get_mut(&dummy_addr).unwrap() =
+            95_000 - /* fee without change */ 127 - /* extra fee for change output */ 43;
+        let (psbt, warnings) = if let CreateSpendResult::Success { psbt, warnings, .. } = control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            (psbt, warnings)
+        } else {
+            panic!("expect successful spend creation")
+        };
+        let tx = psbt.unsigned_tx;
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[1].value.to_sat(), 5_000);
+        assert!(warnings.is_empty());
+
+        // Now increase the target by 1 and we'll get a warning again, this time for 1 less than the dust threshold.
+        *destinations.get_mut(&dummy_addr).unwrap() =
+            95_000 - /* fee without change */ 127 - /* extra fee for change output */ 43 + 1;
+        let warnings = if let CreateSpendResult::Success { warnings, .. } = control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            warnings
+        } else {
+            panic!("expect successful spend creation")
+        };
+        assert_eq!(
+            warnings,
+            vec![
+                "Dust UTXO. The minimal change output allowed by Liana is 5000 sats. \
+                Instead of creating a change of 4999 sats, it was added to the \
+                transaction fee. Select a larger input to avoid this from happening."
+            ]
+        );
+
+        // Now if we mark the coin as spent, we won't create another Spend transaction containing
+        // it.
+        db_conn.spend_coins(&[(
+            dummy_op,
+            bitcoin::Txid::from_str(
+                "ef78f79ba747813887747cf8582897a48f1a09f1ca04d2cd3d6fcfdcbb5e0797",
+            )
+            .unwrap(),
+        )]);
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Err(CommandError::AlreadySpent(dummy_op))
+        );
+        // If we try to use coin selection, the spent coin will not be used as a candidate
+        // and so we get a coin selection error due to insufficient funds.
+        assert!(matches!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::All,
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Ok(CreateSpendResult::InsufficientFunds { .. }),
+        ));
+
+        // We'd bail out if they tried to create a transaction with a too high feerate.
+        let dummy_op_dup = bitcoin::OutPoint {
+            txid: dummy_op.txid,
+            vout: dummy_op.vout + 10,
+        };
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: dummy_op_dup,
+            is_immature: false,
+            block_info: None,
+            amount: bitcoin::Amount::from_sat(400_000),
+            derivation_index: bip32::ChildNumber::from(42),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        // Even though 1_000 is the max feerate allowed by our sanity check, we need to
+        // use 1_003 in order to exceed it and fail this test since coin selection is
+        // based on a minimum feerate of `feerate_vb / 4.0` sats/wu, which can result in
+        // the sats/vb feerate being lower than `feerate_vb`.
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op_dup]),
+                1_003,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Err(CommandError::SpendCreation(SpendCreationError::InsaneFees(
+                InsaneFeeInfo::TooHighFeerate(1_001)
+            )))
+        );
+
+        // Add an unconfirmed change coin to be used for coin selection.
+        let confirmed_op_1 = bitcoin::OutPoint {
+            txid: dummy_op.txid,
+            vout: dummy_op.vout + 100,
+        };
+        let unconfirmed_coin = Coin {
+            outpoint: confirmed_op_1,
+            is_immature: false,
+            block_info: None,
+            amount: bitcoin::Amount::from_sat(80_000),
+            derivation_index: bip32::ChildNumber::from(42),
+            is_change: true,
+            spend_txid: None,
+            spend_block: None,
+        };
+        db_conn.new_unspent_coins(&[unconfirmed_coin]);
+        // Coin selection error due to insufficient funds.
+        assert!(matches!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::All,
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Ok(CreateSpendResult::InsufficientFunds { .. }),
+        ));
+        // Set destination amount equal to value of confirmed coins.
+        *destinations.get_mut(&dummy_addr).unwrap() = 80_000;
+        // Coin selection error occurs due to insufficient funds to pay fee.
+        assert!(matches!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::All,
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Ok(CreateSpendResult::InsufficientFunds { .. }),
+        ));
+        let confirmed_op_2 = bitcoin::OutPoint {
+            txid: confirmed_op_1.txid,
+            vout: confirmed_op_1.vout + 10,
+        };
+        // Add new confirmed coin to cover the fee.
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: confirmed_op_2,
+            is_immature: false,
+            block_info: Some(BlockInfo {
+                height: 174500,
+                time: 174500,
+            }),
+            amount: bitcoin::Amount::from_sat(20_000),
+            derivation_index: bip32::ChildNumber::from(43),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        // First, create a transaction using auto coin selection.
+        let psbt = if let CreateSpendResult::Success { psbt, .. } = control
+            .create_spend(
+                &destinations,
+                &CoinSelector::All,
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            psbt
+        } else {
+            panic!("expect successful spend creation")
+        };
+        let tx_auto = psbt.unsigned_tx;
+        let mut tx_prev_outpoints = tx_auto
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect::<Vec<OutPoint>>();
+        tx_prev_outpoints.sort();
+        assert_eq!(tx_auto.input.len(), 2);
+        assert_eq!(tx_prev_outpoints, vec![confirmed_op_1, confirmed_op_2]);
+        // Output includes change.
+        assert_eq!(tx_auto.output.len(), 2);
+        assert_eq!(
+            tx_auto.output[0].script_pubkey,
+            dummy_addr.payload().script_pubkey()
+        );
+        assert_eq!(tx_auto.output[0].value, Amount::from_sat(80_000));
+
+        // Create a second transaction using manual coin selection.
+        let psbt = if let CreateSpendResult::Success { psbt, .. } = control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![confirmed_op_1, confirmed_op_2]),
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            psbt
+        } else {
+            panic!("expect successful spend creation")
+        };
+        let tx_manual = psbt.unsigned_tx;
+        // Check that manual and auto selection give same outputs (except change address).
+        assert_ne!(tx_auto.output, tx_manual.output);
+        assert_eq!(tx_auto.output.len(), tx_manual.output.len());
+        assert_eq!(tx_auto.output[0], tx_manual.output[0]);
+        assert_eq!(tx_auto.output[1].value, tx_manual.output[1].value);
+        assert_ne!(
+            tx_auto.output[1].script_pubkey,
+            tx_manual.output[1].script_pubkey
+        );
+        // Check inputs are also the same. Need to sort as order is not guaranteed by `create_spend`.
+        let mut auto_input = tx_auto.clone().input;
+        let mut manual_input = tx_manual.input;
+        auto_input.sort();
+        manual_input.sort();
+        assert_eq!(auto_input, manual_input);
+
+        // Now check that the spend created above using auto-selection only works when the unconfirmed coin
+        // is change and not immature.
+        // 1. not change and not immature
+        db_conn.remove_coins(&[unconfirmed_coin.outpoint]);
+        let mut unconfirmed_coin_2 = unconfirmed_coin;
+        unconfirmed_coin_2.is_change = false;
+        unconfirmed_coin_2.is_immature = false; // (this is already the case)
+        db_conn.new_unspent_coins(&[unconfirmed_coin_2]);
+        assert!(matches!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::All,
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Ok(CreateSpendResult::InsufficientFunds { .. }),
+        ));
+        // 2. change and immature
+        db_conn.remove_coins(&[unconfirmed_coin_2.outpoint]);
+        unconfirmed_coin_2.is_change = true;
+        unconfirmed_coin_2.is_immature = true;
+        db_conn.new_unspent_coins(&[unconfirmed_coin_2]);
+        assert!(matches!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::All,
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Ok(CreateSpendResult::InsufficientFunds { .. }),
+        ));
+        // 3. not change and immature
+        db_conn.remove_coins(&[unconfirmed_coin_2.outpoint]);
+        unconfirmed_coin_2.is_change = false;
+        unconfirmed_coin_2.is_immature = true;
+        db_conn.new_unspent_coins(&[unconfirmed_coin_2]);
+        assert!(matches!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::All,
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Ok(CreateSpendResult::InsufficientFunds { .. }),
+        ));
+
+        // Re-insert the original unconfirmed coin again.
+        db_conn.remove_coins(&[unconfirmed_coin_2.outpoint]);
+        db_conn.new_unspent_coins(&[unconfirmed_coin]);
+
+        // Now do the same again, but this time specifying the change address to be the same
+        // as for the auto spend.
+        let change_address = bitcoin::Address::from_script(
+            tx_auto.output[1].script_pubkey.as_script(),
+            bitcoin::Network::Bitcoin,
+        )
+        .unwrap();
+        let psbt = if let CreateSpendResult::Success { psbt, .. } = control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![confirmed_op_1, confirmed_op_2]),
+                1,
+                CreateSpendOptions {
+                    change_address: Some(change_address.as_unchecked().clone()),
+                    output_seed: Some(seed),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            psbt
+        } else {
+            panic!("expect successful spend creation")
+        };
+        let tx_manual = psbt.unsigned_tx;
+        // Now the outputs of each transaction are the same.
+        assert_eq!(tx_auto.output, tx_manual.output);
+        // Check again that inputs are still the same.
+        let mut auto_input = tx_auto.input;
+        let mut manual_input = tx_manual.input;
+        auto_input.sort();
+        manual_input.sort();
+        assert_eq!(auto_input, manual_input);
+
+        // Add a confirmed coin with a value near the dust limit and check that
+        // `InsufficientFunds` error is returned if feerate is too high.
+        let confirmed_op_3 = bitcoin::OutPoint {
+            txid: confirmed_op_2.txid,
+            vout: confirmed_op_2.vout + 10,
+        };
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: confirmed_op_3,
+            is_immature: false,
+            block_info: Some(BlockInfo {
+                height: 174500,
+                time: 174500,
+            }),
+            amount: bitcoin::Amount::from_sat(5_250),
+            derivation_index: bip32::ChildNumber::from(56),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        let empty_dest = &HashMap::<bitcoin::Address<address::NetworkUnchecked>, u64>::new();
+        assert!(matches!(
+            control.create_spend(
+                empty_dest,
+                &CoinSelector::Outpoints(vec![confirmed_op_3]),
+                5,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Ok(CreateSpendResult::InsufficientFunds { .. }),
+        ));
+        // If we use a lower fee, the self-send will succeed.
+        let psbt = if let CreateSpendResult::Success { psbt, .. } = control
+            .create_spend(
+                empty_dest,
+                &CoinSelector::Outpoints(vec![confirmed_op_3]),
+                1,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            psbt
+        } else {
+            panic!("expect successful spend creation")
+        };
+        let tx = psbt.unsigned_tx;
+        let tx_prev_outpoints = tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect::<Vec<OutPoint>>();
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx_prev_outpoints, vec![confirmed_op_3]);
+        assert_eq!(tx.output.len(), 1);
+
+        // Can't create a transaction that spends an immature coinbase deposit.
+        let imma_op = bitcoin::OutPoint::from_str(
+            "4753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: imma_op,
+            is_immature: true,
+            block_info: None,
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![imma_op]),
+                1_001,
+                CreateSpendOptions {
+                    output_seed: Some(seed),
+                    ..Default::default()
+                }
+            ),
+            Err(CommandError::ImmatureCoinbase(imma_op))
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn update_spend() {
+        let dummy_op_a = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let dummy_op_b = bitcoin::OutPoint::from_str(
+            "4753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:1",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        let dummy_tx = bitcoin::Transaction {
+            version: TxVersion::TWO,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: vec![],
+            output: vec![],
+        };
+        dummy_bitcoind
+            .txs
+            .insert(dummy_op_a.txid, (dummy_tx.clone(), None));
+        dummy_bitcoind.txs.insert(dummy_op_b.txid, (dummy_tx, None));
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+
+        // Add two (unconfirmed) coins in DB
+        db_conn.new_unspent_coins(&[
+            Coin {
+                outpoint: dummy_op_a,
+                is_immature: false,
+                block_info: None,
+                amount: bitcoin::Amount::from_sat(100_000),
+                derivation_index: bip32::ChildNumber::from(13),
+                is_change: false,
+                spend_txid: None,
+                spend_block: None,
+            },
+            Coin {
+                outpoint: dummy_op_b,
+                is_immature: false,
+                block_info: None,
+                amount: bitcoin::Amount::from_sat(115_680),
+                derivation_index: bip32::ChildNumber::from(34),
+                is_change: false,
+                spend_txid: None,
+                spend_block: None,
+            },
+        ]);
+
+        // Now create three transactions spending those coins differently
+        let dummy_addr_a =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let dummy_addr_b =
+            bitcoin::Address::from_str("bc1q39srgatmkp6k2ne3l52yhkjprdvunvspqydmkx").unwrap();
+        let dummy_value_a = 50_000;
+        let dummy_value_b = 60_000;
+        let destinations_a: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr_a.clone(), dummy_value_a)]
+                .iter()
+                .cloned()
+                .collect();
+        let destinations_b: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr_b.clone(), dummy_value_b)]
+                .iter()
+                .cloned()
+                .collect();
+        let destinations_c: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr_a, dummy_value_a), (dummy_addr_b, dummy_value_b)]
+                .iter()
+                .cloned()
+                .collect();
+        let mut psbt_a = if let CreateSpendResult::Success { psbt, .. } = control
+            .create_spend(
+                &destinations_a,
+                &CoinSelector::Outpoints(vec![dummy_op_a]),
+                1,
+                CreateSpendOptions::default(),
+            )
+            .unwrap()
+        {
+            psbt
+        } else {
+            panic!("expect successful spend creation")
+        };
+        let txid_a = psbt_a.unsigned_tx.txid();
+        let psbt_b = if let CreateSpendResult::Success { psbt, .. } = control
+            .create_spend(
+                &destinations_b,
+                &CoinSelector::Outpoints(vec![dummy_op_b]),
+                10,
+                CreateSpendOptions::default(),
+            )
+            .unwrap()
+        {
+            psbt
+        } else {
+            panic!("expect successful spend creation")
+        };
+        let txid_b = psbt_b.unsigned_tx.txid();
+        let psbt_c = if let CreateSpendResult::Success { psbt, .. } = control
+            .create_spend(
+                &destinations_c,
+                &CoinSelector::Outpoints(vec![dummy_op_a, dummy_op_b]),
+                100,
+                CreateSpendOptions::default(),
+            )
+            .unwrap()
+        {
+            psbt
+        } else {
+            panic!("expect successful spend creation")
+        };
+        let txid_c = psbt_c.unsigned_tx.txid();
+
+        // We can store and query them all
+        control.update_spend(psbt_a.clone()).unwrap();
+        assert_eq!(db_conn.spend_tx(&txid_a).unwrap(), psbt_a);
+        control.update_spend(psbt_b.clone()).unwrap();
+        assert_eq!(db_conn.spend_tx(&txid_b).unwrap(), psbt_b);
+        control.update_spend(psbt_c.clone()).unwrap();
+        assert_eq!(db_conn.spend_tx(&txid_c).unwrap(), psbt_c);
+
+        // As well as update them, with or without new signatures
+        let sig = bitcoin::ecdsa::Signature::from_str("304402204004fcdbb9c0d0cbf585f58cee34dccb012efbd8fc2b0d5e97760045ae35803802201a0bd7ec2383e0b93748abc9946c8e17a8312e314dab85982aeba650e738cbf401").unwrap();
+        let mut unexpected_psbt_a = psbt_a.clone();
+        unexpected_psbt_a.inputs[0].partial_sigs.insert(
+            bitcoin::PublicKey::from_str(
+                "023a664c5617412f0b292665b1fd9d766456a7a3b1614c7e7c5f411200ff1958ef",
+            )
+            .unwrap(),
+            sig,
+        );
+        let our_key = *psbt_a.inputs[0].bip32_derivation.keys().next().unwrap();
+        psbt_a.inputs[0]
+            .partial_sigs
+            .insert(bitcoin::PublicKey::new(our_key), sig);
+        control.update_spend(psbt_a.clone()).unwrap();
+        assert_eq!(db_conn.spend_tx(&txid_a).unwrap(), psbt_a);
+        control.update_spend(psbt_b.clone()).unwrap();
+        assert_eq!(db_conn.spend_tx(&txid_b).unwrap(), psbt_b);
+        control.update_spend(psbt_c.clone()).unwrap();
+        assert_eq!(db_conn.spend_tx(&txid_c).unwrap(), psbt_c);
+
+        // But signatures from keys which aren't ours at the coin's derivation index are refused,
+        // and nothing is stored.
+        assert_eq!(
+            control.update_spend(unexpected_psbt_a.clone()),
+            Err(CommandError::UnexpectedSigner(None))
+        );
+        let other_key = *psbt_b.inputs[0].bip32_derivation.keys().next().unwrap();
+        let (other_fg, other_path) = psbt_b.inputs[0].bip32_derivation[&other_key].clone();
+        unexpected_psbt_a.inputs[0]
+            .bip32_derivation
+            .insert(other_key, (other_fg, other_path));
+        unexpected_psbt_a.inputs[0].partial_sigs = [(bitcoin::PublicKey::new(other_key), sig)]
+            .iter()
+            .cloned()
+            .collect();
+        assert_eq!(
+            control.update_spend(unexpected_psbt_a),
+            Err(CommandError::UnexpectedSigner(Some(other_fg)))
+        );
+        assert_eq!(db_conn.spend_tx(&txid_a).unwrap(), psbt_a);
+
+        // Each draft got a stable identifier, which is kept across updates even if the
+        // updated PSBT doesn't carry it anymore.
+        let draft_id_b = DraftId::from_psbt(&psbt_b).unwrap();
+        assert_ne!(Some(draft_id_b), DraftId::from_psbt(&psbt_c));
+        assert_eq!(
+            DraftId::from_str(&draft_id_b.to_string()).unwrap(),
+            draft_id_b
+        );
+        assert!(DraftId::from_str("not-a-draft-id").is_err());
+        assert!(DraftId::from_str(&txid_b.to_string()).is_err());
+        let mut stripped_b = psbt_b.clone();
+        stripped_b.proprietary.clear();
+        control.update_spend(stripped_b).unwrap();
+        assert_eq!(
+            DraftId::from_psbt(&db_conn.spend_tx(&txid_b).unwrap()),
+            Some(draft_id_b)
+        );
+        assert!(control
+            .list_spend(Some(vec![txid_b]), &[])
+            .unwrap()
+            .spend_txs
+            .iter()
+            .all(|entry| entry.draft_id == Some(draft_id_b)));
+
+        // A modified version of the same draft can be stored along the previous one, and both
+        // can be deleted at once using the draft identifier.
+        let mut psbt_b_bis = psbt_b.clone();
+        psbt_b_bis.unsigned_tx.output[0].value -= bitcoin::Amount::from_sat(1_000);
+        let txid_b_bis = psbt_b_bis.unsigned_tx.txid();
+        control.update_spend(psbt_b_bis).unwrap();
+        assert!(db_conn.spend_tx(&txid_b_bis).is_some());
+        control
+            .delete_spend(&SpendRef::DraftId(draft_id_b))
+            .unwrap();
+        assert!(db_conn.spend_tx(&txid_b).is_none());
+        assert!(db_conn.spend_tx(&txid_b_bis).is_none());
+        assert!(db_conn.spend_tx(&txid_a).is_some());
+        assert!(db_conn.spend_tx(&txid_c).is_some());
+        control.update_spend(psbt_b.clone()).unwrap();
+
+        // The status of each Spend is derived from its signatures and whether it was broadcast,
+        // and they can be filtered by status.
+        let status_of = |txid| {
+            control
+                .list_spend(Some(vec![txid]), &[])
+                .unwrap()
+                .spend_txs
+                .into_iter()
+                .next()
+                .unwrap()
+                .status
+        };
+        assert_eq!(status_of(txid_a), SpendStatus::PartiallySigned);
+        assert_eq!(status_of(txid_b), SpendStatus::Draft);
+        assert_eq!(status_of(txid_c), SpendStatus::Draft);
+        db_conn.spend_coins(&[(psbt_c.unsigned_tx.input[0].previous_output, txid_c)]);
+        assert_eq!(status_of(txid_c), SpendStatus::Broadcast);
+        let drafts = control
+            .list_spend(None, &[SpendStatus::Draft, SpendStatus::Signed])
+            .unwrap()
+            .spend_txs;
+        assert_eq!(drafts.len(), 1);
+        assert_eq!(drafts[0].psbt, psbt_b);
+        for status in [
+            SpendStatus::Draft,
+            SpendStatus::PartiallySigned,
+            SpendStatus::Signed,
+            SpendStatus::Broadcast,
+        ] {
+            assert_eq!(SpendStatus::from_arg(status.to_arg()), Some(status));
+        }
+
+        // We can't store a PSBT spending an external coin
+        let external_op = bitcoin::OutPoint::from_str(
+            "8753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:2",
+        )
+        .unwrap();
+        psbt_a.unsigned_tx.input[0].previous_output = external_op;
+        assert_eq!(
+            control.update_spend(psbt_a),
+            Err(CommandError::UnknownOutpoint(external_op))
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_spend_force_path() {
+        let dummy_op = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(
+            dummy_op.txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: dummy_op,
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr, 10_000)].iter().cloned().collect();
+
+        // The descriptor only has a single recovery path.
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                1,
+                CreateSpendOptions {
+                    force_path: Some(2),
+                    ..Default::default()
+                }
+            ),
+            Err(CommandError::UnknownSpendingPath(2))
+        );
+
+        // The coin was confirmed at height 1 and the tip is at height 100, the recovery path
+        // isn't available yet.
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                1,
+                CreateSpendOptions {
+                    force_path: Some(1),
+                    ..Default::default()
+                }
+            ),
+            Err(CommandError::SpendingPathNotAvailable(1, dummy_op))
+        );
+        // With coin selection, no coin is available through this path.
+        assert!(matches!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::All,
+                1,
+                CreateSpendOptions {
+                    force_path: Some(1),
+                    ..Default::default()
+                }
+            ),
+            Ok(CreateSpendResult::InsufficientFunds { .. }),
+        ));
+
+        // We can force spending through the primary path.
+        let psbt = if let CreateSpendResult::Success { psbt, .. } = control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                1,
+                CreateSpendOptions {
+                    force_path: Some(0),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            psbt
+        } else {
+            panic!("expect successful spend creation")
+        };
+        assert_eq!(
+            psbt.unsigned_tx.input[0].sequence,
+            bitcoin::Sequence::ENABLE_RBF_NO_LOCKTIME
+        );
+        assert!(!psbt.inputs[0].bip32_derivation.is_empty());
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_spend_label_selector() {
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        let ops: Vec<bitcoin::OutPoint> = (0..3)
+            .map(|vout| {
+                bitcoin::OutPoint::from_str(&format!(
+                    "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:{}",
+                    vout
+                ))
+                .unwrap()
+            })
+            .collect();
+        dummy_bitcoind.txs.insert(
+            ops[0].txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let coins: Vec<Coin> = ops
+            .iter()
+            .enumerate()
+            .map(|(i, op)| Coin {
+                outpoint: *op,
+                is_immature: false,
+                block_info: Some(BlockInfo { height: 1, time: 1 }),
+                amount: bitcoin::Amount::from_sat(100_000),
+                derivation_index: bip32::ChildNumber::from(i as u32),
+                is_change: false,
+                spend_txid: None,
+                spend_block: None,
+            })
+            .collect();
+        db_conn.new_unspent_coins(&coins);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let mut destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr.clone(), 150_000)].iter().cloned().collect();
+
+        // Label the first coin directly, and the second one through its address.
+        let second_addr = control
+            .derived_desc(&coins[1])
+            .address(bitcoin::Network::Bitcoin);
+        control
+            .update_labels(
+                &[
+                    (LabelItem::OutPoint(ops[0]), Some("donations".to_string())),
+                    (
+                        LabelItem::Address(second_addr),
+                        Some("donations".to_string()),
+                    ),
+                    (LabelItem::OutPoint(ops[2]), Some("savings".to_string())),
+                ]
+                .iter()
+                .cloned()
+                .collect(),
+            )
+            .unwrap();
+
+        // Both labelled coins are needed to cover the amount, the third one must not be used.
+        let psbt = if let CreateSpendResult::Success { psbt, .. } = control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Label("donations".to_string()),
+                1,
+                CreateSpendOptions::default(),
+            )
+            .unwrap()
+        {
+            psbt
+        } else {
+            panic!("expect successful spend creation")
+        };
+        let spent: HashSet<_> = psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect();
+        assert_eq!(spent, [ops[0], ops[1]].iter().cloned().collect());
+
+        // If the labelled coins aren't enough, we won't fall back to the other coins.
+        *destinations.get_mut(&dummy_addr).unwrap() = 250_000;
+        assert!(matches!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::Label("donations".to_string()),
+                1,
+                CreateSpendOptions::default()
+            ),
+            Ok(CreateSpendResult::InsufficientFunds { .. }),
+        ));
+        assert!(matches!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::All,
+                1,
+                CreateSpendOptions::default()
+            ),
+            Ok(CreateSpendResult::Success { .. }),
+        ));
+        // An unknown label selects no coin.
+        assert!(matches!(
+            control.create_spend(
+                &destinations,
+                &CoinSelector::Label("unknown".to_string()),
+                1,
+                CreateSpendOptions::default()
+            ),
+            Ok(CreateSpendResult::InsufficientFunds { .. }),
+        ));
+        // A label selector can't be used for a self-send.
+        assert_eq!(
+            control.create_spend(
+                &HashMap::new(),
+                &CoinSelector::Label("donations".to_string()),
+                1,
+                CreateSpendOptions::default()
+            ),
+            Err(CommandError::NoOutpointForSelfSend)
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_spend_privacy_aware() {
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        let ops: Vec<bitcoin::OutPoint> = (0..4)
+            .map(|vout| {
+                bitcoin::OutPoint::from_str(&format!(
+                    "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:{}",
+                    vout
+                ))
+                .unwrap()
+            })
+            .collect();
+        dummy_bitcoind.txs.insert(
+            ops[0].txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        // The second and third coins pay to the same address.
+        let coins: Vec<Coin> = [(0, 200_000), (1, 60_000), (1, 60_000), (2, 30_000)]
+            .iter()
+            .zip(ops.iter())
+            .map(|((deriv_index, amount), op)| Coin {
+                outpoint: *op,
+                is_immature: false,
+                block_info: Some(BlockInfo { height: 1, time: 1 }),
+                amount: bitcoin::Amount::from_sat(*amount),
+                derivation_index: bip32::ChildNumber::from(*deriv_index),
+                is_change: false,
+                spend_txid: None,
+                spend_block: None,
+            })
+            .collect();
+        db_conn.new_unspent_coins(&coins);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let mut destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr.clone(), 100_000)].iter().cloned().collect();
+
+        // The smallest address cluster that can fund the transaction is used.
+        let (psbt, warnings, privacy_score) = if let CreateSpendResult::Success {
+            psbt,
+            warnings,
+            privacy_score,
+            ..
+        } = control
+            .create_spend(
+                &destinations,
+                &CoinSelector::All,
+                1,
+                CreateSpendOptions {
+                    privacy_aware: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            (psbt, warnings, privacy_score)
+        } else {
+            panic!("expect successful spend creation")
+        };
+        let spent: HashSet<_> = psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect();
+        assert_eq!(spent, [ops[1], ops[2]].iter().cloned().collect());
+        assert!(warnings.is_empty());
+        assert_eq!(privacy_score, 100);
+
+        // If no single address can fund the transaction, we fall back to combining coins from
+        // different addresses.
+        *destinations.get_mut(&dummy_addr).unwrap() = 300_000;
+        let (psbt, warnings, privacy_score) = if let CreateSpendResult::Success {
+            psbt,
+            warnings,
+            privacy_score,
+            ..
+        } = control
+            .create_spend(
+                &destinations,
+                &CoinSelector::All,
+                1,
+                CreateSpendOptions {
+                    privacy_aware: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            (psbt, warnings, privacy_score)
+        } else {
+            panic!("expect successful spend creation")
+        };
+        assert!(psbt.unsigned_tx.input.len() > 1);
+        assert_eq!(warnings.len(), 1);
+        assert!(privacy_score < 100);
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_spend_selection_weights() {
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        let ops: Vec<bitcoin::OutPoint> = (0..4)
+            .map(|vout| {
+                bitcoin::OutPoint::from_str(&format!(
+                    "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:{}",
+                    vout
+                ))
+                .unwrap()
+            })
+            .collect();
+        dummy_bitcoind.txs.insert(
+            ops[0].txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let coins: Vec<Coin> = [(0, 200_000), (1, 60_000), (1, 60_000), (2, 30_000)]
+            .iter()
+            .zip(ops.iter())
+            .map(|((deriv_index, amount), op)| Coin {
+                outpoint: *op,
+                is_immature: false,
+                block_info: Some(BlockInfo { height: 1, time: 1 }),
+                amount: bitcoin::Amount::from_sat(*amount),
+                derivation_index: bip32::ChildNumber::from(*deriv_index),
+                is_change: false,
+                spend_txid: None,
+                spend_block: None,
+            })
+            .collect();
+        db_conn.new_unspent_coins(&coins);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr, 100_000)].iter().cloned().collect();
+        let create = |weights: Option<SelectionWeights>| {
+            if let CreateSpendResult::Success {
+                psbt,
+                selection_score,
+                ..
+            } = control
+                .create_spend(
+                    &destinations,
+                    &CoinSelector::All,
+                    1,
+                    CreateSpendOptions {
+                        selection_weights: weights,
+                        ..Default::default()
+                    },
+                )
+                .unwrap()
+            {
+                (psbt, selection_score)
+            } else {
+                panic!("expect successful spend creation")
+            }
+        };
+
+        // Without weights, there is no score.
+        let (regular_psbt, score) = create(None);
+        assert!(score.is_none());
+
+        // Only caring about fees gives the same result as the regular coin selection.
+        let (psbt, score) = create(Some(SelectionWeights {
+            fee_weight: 1,
+            ..Default::default()
+        }));
+        let score = score.unwrap();
+        assert_eq!(
+            psbt.unsigned_tx.input.len(),
+            regular_psbt.unsigned_tx.input.len()
+        );
+        assert_eq!(score.fee, 100);
+        assert_eq!(score.total, 100);
+
+        // Favouring consolidation spends all the coins, at the expense of fees and privacy.
+        let (psbt, score) = create(Some(SelectionWeights {
+            fee_weight: 1,
+            privacy_weight: 1,
+            consolidation_weight: 10,
+        }));
+        let score = score.unwrap();
+        assert_eq!(psbt.unsigned_tx.input.len(), 4);
+        assert_eq!(score.consolidation, 100);
+        assert!(score.fee < 100 && score.privacy < 100);
+        assert_eq!(
+            score.total,
+            u32::from(score.fee) + u32::from(score.privacy) + 10 * 100
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_spend_allow_unconfirmed() {
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        let ops: Vec<bitcoin::OutPoint> = (0..3)
+            .map(|vout| {
+                bitcoin::OutPoint::from_str(&format!(
+                    "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:{}",
+                    vout
+                ))
+                .unwrap()
+            })
+            .collect();
+        dummy_bitcoind.txs.insert(
+            ops[0].txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        // A small confirmed coin, an unconfirmed change coin and an unconfirmed external deposit.
+        let coins: Vec<Coin> = [
+            (Some(1), false, 10_000),
+            (None, true, 200_000),
+            (None, false, 300_000),
+        ]
+        .iter()
+        .zip(ops.iter())
+        .map(|((height, is_change, amount), op)| Coin {
+            outpoint: *op,
+            is_immature: false,
+            block_info: height.map(|height| BlockInfo { height, time: 1 }),
+            amount: bitcoin::Amount::from_sat(*amount),
+            derivation_index: bip32::ChildNumber::from(op.vout),
+            is_change: *is_change,
+            spend_txid: None,
+            spend_block: None,
+        })
+        .collect();
+        db_conn.new_unspent_coins(&coins);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let create = |value: u64, allow_unconfirmed: bool, allow_unconfirmed_external: bool| {
+            let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+                [(dummy_addr.clone(), value)].iter().cloned().collect();
+            match control
+                .create_spend(
+                    &destinations,
+                    &CoinSelector::All,
+                    1,
+                    CreateSpendOptions {
+                        allow_unconfirmed,
+                        allow_unconfirmed_external,
+                        ..Default::default()
+                    },
+                )
+                .unwrap()
+            {
+                CreateSpendResult::Success {
+                    psbt,
+                    spends_unconfirmed,
+                    ..
+                } => {
+                    let mut vouts: Vec<u32> = psbt
+                        .unsigned_tx
+                        .input
+                        .iter()
+                        .map(|txin| txin.previous_output.vout)
+                        .collect();
+                    vouts.sort();
+                    Some((vouts, spends_unconfirmed))
+                }
+                CreateSpendResult::InsufficientFunds { .. } => None,
+            }
+        };
+
+        // Only the confirmed coin may be spent.
+        assert_eq!(create(5_000, false, false), Some((vec![0], false)));
+        assert_eq!(create(100_000, false, false), None);
+        // By default, our unconfirmed change may also be spent but not unconfirmed deposits.
+        assert_eq!(create(100_000, true, false), Some((vec![1], true)));
+        assert_eq!(create(400_000, true, false), None);
+        // We can opt in to spending unconfirmed deposits from external wallets.
+        assert_eq!(create(250_000, false, true), Some((vec![2], true)));
+        assert_eq!(create(400_000, true, true), Some((vec![1, 2], true)));
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn inspect_psbt() {
+        let dummy_op = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(
+            dummy_op.txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: dummy_op,
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+
+        // Create a transaction paying to an external address, with a change output.
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr.clone(), 40_000)].iter().cloned().collect();
+        let mut psbt = if let CreateSpendResult::Success { psbt, .. } = control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                2,
+                CreateSpendOptions::default(),
+            )
+            .unwrap()
+        {
+            psbt
+        } else {
+            panic!("expect successful spend creation")
+        };
+        assert_eq!(psbt.unsigned_tx.output.len(), 2);
+        let ext_index = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .position(|txo| txo.value == bitcoin::Amount::from_sat(40_000))
+            .unwrap();
+
+        let inspection = control.inspect_psbt(&psbt).unwrap();
+        assert_eq!(inspection.total_in, bitcoin::Amount::from_sat(100_000));
+        assert_eq!(inspection.total_in - inspection.total_out, inspection.fee);
+        assert!(inspection.feerate_vb >= 1);
+        assert_eq!(
+            inspection.external_outputs,
+            vec![PsbtOutputInfo {
+                index: ext_index,
+                address: Some(dummy_addr.clone()),
+                amount: bitcoin::Amount::from_sat(40_000),
+            }]
+        );
+
+        // Without the value of the input in the PSBT, it's taken from our coin.
+        psbt.inputs[0].witness_utxo = None;
+        assert_eq!(control.inspect_psbt(&psbt).unwrap(), inspection);
+
+        // The outputs can't be worth more than the inputs.
+        psbt.unsigned_tx.output[ext_index].value = bitcoin::Amount::from_sat(100_000);
+        assert_eq!(
+            control.inspect_psbt(&psbt),
+            Err(CommandError::SpendCreation(SpendCreationError::InsaneFees(
+                InsaneFeeInfo::NegativeFee
+            )))
+        );
+
+        // The value of an input which isn't ours must be set in the PSBT.
+        psbt.unsigned_tx.input[0].previous_output.vout = 1;
+        assert_eq!(
+            control.inspect_psbt(&psbt),
+            Err(CommandError::UnknownOutpoint(
+                psbt.unsigned_tx.input[0].previous_output
+            ))
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn signing_requirements() {
+        let dummy_op = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(
+            dummy_op.txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: dummy_op,
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr, 40_000)].iter().cloned().collect();
+        let psbt = if let CreateSpendResult::Success { psbt, .. } = control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                2,
+                CreateSpendOptions::default(),
+            )
+            .unwrap()
+        {
+            psbt
+        } else {
+            panic!("expect successful spend creation")
+        };
+        let txid = psbt.unsigned_tx.txid();
+
+        // The Spend must be stored.
+        assert_eq!(
+            control.signing_requirements(&txid),
+            Err(CommandError::UnknownSpend(txid))
+        );
+
+        // The transaction isn't signed yet: the signature of the single primary key is missing.
+        control.update_spend(psbt).unwrap();
+        assert_eq!(
+            control.signing_requirements(&txid).unwrap(),
+            SigningRequirements {
+                timelock: None,
+                threshold: 1,
+                sigs_count: 0,
+                missing_sigs: 1,
+                remaining_fingerprints: vec![bip32::Fingerprint::from_str("aabbccdd").unwrap()],
+            }
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn final_fee_estimate() {
+        let dummy_op = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(
+            dummy_op.txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: dummy_op,
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr, 40_000)].iter().cloned().collect();
+        let psbt = if let CreateSpendResult::Success { psbt, .. } = control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                10,
+                CreateSpendOptions::default(),
+            )
+            .unwrap()
+        {
+            psbt
+        } else {
+            panic!("expect successful spend creation")
+        };
+        let txid = psbt.unsigned_tx.txid();
+
+        // The Spend must be stored.
+        assert_eq!(
+            control.final_fee_estimate(&txid),
+            Err(CommandError::UnknownSpend(txid))
+        );
+
+        // Through the primary path, the finalized transaction is smaller than the worst case
+        // estimated at creation. So the actual feerate is higher than the requested one.
+        control.update_spend(psbt.clone()).unwrap();
+        let desc = &control.config.main_descriptor;
+        let max_vsize = desc.unsigned_tx_max_vbytes(&psbt.unsigned_tx);
+        let fee = bitcoin::Amount::from_sat(100_000)
+            - psbt
+                .unsigned_tx
+                .output
+                .iter()
+                .map(|txo| txo.value)
+                .sum::<bitcoin::Amount>();
+        let primary_estimate = control.final_fee_estimate(&txid).unwrap();
+        assert_eq!(primary_estimate.timelock, None);
+        assert_eq!(primary_estimate.fee, fee);
+        assert!(primary_estimate.vsize < max_vsize);
+        assert!(primary_estimate.feerate_vb >= 10);
+        assert_eq!(
+            primary_estimate.feerate_vb,
+            fee.to_sat() / primary_estimate.vsize
+        );
+
+        // Through the recovery path the witness is larger.
+        let mut reco_psbt = psbt;
+        reco_psbt.unsigned_tx.input[0].sequence = bitcoin::Sequence::from_height(10_000);
+        let reco_txid = reco_psbt.unsigned_tx.txid();
+        control.update_spend(reco_psbt).unwrap();
+        let reco_estimate = control.final_fee_estimate(&reco_txid).unwrap();
+        assert_eq!(reco_estimate.timelock, Some(10_000));
+        assert_eq!(reco_estimate.fee, fee);
+        assert!(reco_estimate.vsize > primary_estimate.vsize);
+        assert!(reco_estimate.vsize <= max_vsize);
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_spend_label() {
+        let dummy_op = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(
+            dummy_op.txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: dummy_op,
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr.clone(), 40_000)].iter().cloned().collect();
+        // A label for an address which isn't a destination is ignored.
+        let other_addr =
+            bitcoin::Address::from_str("bc1q39srgatmkp6k2ne3l52yhkjprdvunvspqydmkx").unwrap();
+        let destination_labels = [
+            (dummy_addr.clone(), "landlord".to_string()),
+            (other_addr.clone(), "unrelated".to_string()),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        let psbt = if let CreateSpendResult::Success { psbt, .. } = control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                2,
+                CreateSpendOptions {
+                    label: Some("rent".to_string()),
+                    destination_labels,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            psbt
+        } else {
+            panic!("expect successful spend creation")
+        };
+
+        // The label is set on the txid right away.
+        let txid = psbt.unsigned_tx.txid();
+        let txid_item = LabelItem::Txid(txid);
+        let labels = control
+            .get_labels(&[txid_item].iter().cloned().collect())
+            .labels;
+        assert_eq!(labels.get(&txid.to_string()), Some(&"rent".to_string()));
+        control.update_spend(psbt.clone()).unwrap();
+
+        // So is the label of the destination.
+        let dummy_addr = dummy_addr.assume_checked();
+        let other_addr = other_addr.assume_checked();
+        let labels = control
+            .get_labels(
+                &[
+                    LabelItem::Address(dummy_addr.clone()),
+                    LabelItem::Address(other_addr),
+                ]
+                .iter()
+                .cloned()
+                .collect(),
+            )
+            .labels;
+        assert_eq!(labels.len(), 1);
+        assert_eq!(
+            labels.get(&dummy_addr.to_string()),
+            Some(&"landlord".to_string())
+        );
+
+        // If the transaction of this draft is modified, the label follows it.
+        let mut edited_psbt = psbt;
+        edited_psbt.unsigned_tx.output[0].value -= bitcoin::Amount::from_sat(1_000);
+        let edited_txid = edited_psbt.unsigned_tx.txid();
+        control.update_spend(edited_psbt).unwrap();
+        let labels = control
+            .get_labels(&[LabelItem::Txid(edited_txid)].iter().cloned().collect())
+            .labels;
+        assert_eq!(
+            labels.get(&edited_txid.to_string()),
+            Some(&"rent".to_string())
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_spend_split_change() {
+        let dummy_op = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(
+            dummy_op.txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: dummy_op,
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let create = |amount: u64, split_change| {
+            let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+                [(dummy_addr.clone(), amount)].iter().cloned().collect();
+            control.create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                2,
+                CreateSpendOptions {
+                    split_change,
+                    ..Default::default()
+                },
+            )
+        };
+
+        // The number of change outputs must be sane.
+        for n in [0, MAX_CHANGE_OUTPUTS + 1] {
+            assert_eq!(
+                create(40_000, Some(n)),
+                Err(CommandError::InvalidChangeSplit(n))
+            );
+        }
+
+        // Without splitting there is a single change output.
+        let single_change_psbt = match create(40_000, None).unwrap() {
+            CreateSpendResult::Success { psbt, .. } => psbt,
+            _ => panic!("expect successful spend creation"),
+        };
+        assert_eq!(single_change_psbt.unsigned_tx.output.len(), 2);
+
+        // The change is split evenly between three outputs to distinct addresses of ours, at the
+        // same feerate.
+        let change_index = db_conn.change_index();
+        let (psbt, warnings) = match create(40_000, Some(3)).unwrap() {
+            CreateSpendResult::Success { psbt, warnings, .. } => (psbt, warnings),
+            _ => panic!("expect successful spend creation"),
+        };
+        assert!(warnings.is_empty());
+        let tx = &psbt.unsigned_tx;
+        assert_eq!(tx.output.len(), 4);
+        let change_outputs: Vec<_> = tx
+            .output
+            .iter()
+            .zip(psbt.outputs.iter())
+            .filter(|(_, psbt_out)| !psbt_out.bip32_derivation.is_empty())
+            .map(|(txo, _)| txo)
+            .collect();
+        assert_eq!(change_outputs.len(), 3);
+        let spks: HashSet<_> = change_outputs
+            .iter()
+            .map(|txo| &txo.script_pubkey)
+            .collect();
+        assert_eq!(spks.len(), 3);
+        let values: Vec<_> = change_outputs
+            .iter()
+            .map(|txo| txo.value.to_sat())
+            .collect();
+        assert!(values.iter().max().unwrap() - values.iter().min().unwrap() < 3);
+        let fee = |tx: &bitcoin::Transaction| {
+            100_000 - tx.output.iter().map(|o| o.value.to_sat()).sum::<u64>()
+        };
+        let (single_tx, tx_vsize) = (&single_change_psbt.unsigned_tx, tx.vsize() as u64);
+        assert_eq!(
+            fee(tx) - fee(single_tx),
+            (tx_vsize - single_tx.vsize() as u64) * 2
+        );
+        // The next change derivation index was increased past the additional change addresses.
+        assert!(db_conn.change_index() > change_index);
+
+        // If the change can't afford as many outputs, it's split in as many as possible.
+        let (psbt, warnings) = match create(80_000, Some(10)).unwrap() {
+            CreateSpendResult::Success { psbt, warnings, .. } => (psbt, warnings),
+            _ => panic!("expect successful spend creation"),
+        };
+        assert_eq!(psbt.unsigned_tx.output.len(), 4);
+        assert!(psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .all(|txo| txo.value.to_sat() >= DUST_OUTPUT_SATS));
+        assert_eq!(warnings.len(), 1);
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn feerate_fractional() {
+        let feerate = |sats_vb| Feerate::from_sat_per_vb_f64(sats_vb).unwrap();
+        assert_eq!(feerate(2.0), Feerate::from_sat_per_vb(2));
+        assert_eq!(feerate(1.5).to_sat_per_kvb(), 1_500);
+        // The precision is a thousandth of a sat/vb.
+        assert_eq!(feerate(1.2345).to_sat_per_kvb(), 1_235);
+        assert_eq!(feerate(1.2344).to_sat_per_kvb(), 1_234);
+        for invalid in [-1.0, f64::NAN, f64::INFINITY, 1e20] {
+            assert!(Feerate::from_sat_per_vb_f64(invalid).is_none());
+        }
+        assert_eq!(feerate(1.5).to_string(), "1.5");
+        assert_eq!(feerate(0.001).to_string(), "0.001");
+        assert_eq!(feerate(12.0).to_string(), "12");
+        assert_eq!(feerate(1.001).to_sat_per_vb_ceil(), 2);
+        assert_eq!(feerate(1.0).to_sat_per_vb_ceil(), 1);
+
+        // Absolute fees are rounded up to the next satoshi.
+        assert_eq!(feerate(1.5).fee_vb(141), 212);
+        assert_eq!(feerate(1.5).fee_vb(140), 210);
+        assert_eq!(feerate(1.001).fee_vb(999), 1_000);
+        assert_eq!(Feerate::from_sat_per_vb(3).fee_vb(141), 423);
+    }
+
+    #[test]
+    fn create_spend_fractional_feerate() {
+        let dummy_op = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(
+            dummy_op.txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: dummy_op,
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr, 40_000)].iter().cloned().collect();
+        let create = |feerate| {
+            control.create_spend_with_feerate(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                feerate,
+                CreateSpendOptions::default(),
+            )
+        };
+        let fee = |feerate| match create(feerate) {
+            Ok(CreateSpendResult::Success { psbt, .. }) => {
+                let tx = psbt.unsigned_tx;
+                let vbytes = control.config.main_descriptor.unsigned_tx_max_vbytes(&tx);
+                let value_out: u64 = tx.output.iter().map(|o| o.value.to_sat()).sum();
+                (100_000 - value_out, vbytes)
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        };
+
+        // A feerate below 1 sat/vb is still invalid.
+        let half = Feerate::from_sat_per_vb_f64(0.5).unwrap();
+        assert_eq!(create(half), Err(CommandError::InvalidFeerate(half)));
+
+        // A fractional feerate is not rounded up to the next integer: the fee paid is the one at
+        // this feerate for the size of the transaction, rounded up to the next satoshi.
+        let (fee_1, vbytes) = fee(Feerate::from_sat_per_vb(1));
+        let (fee_2, _) = fee(Feerate::from_sat_per_vb(2));
+        let (fee_1_5, vbytes_1_5) = fee(Feerate::from_sat_per_vb_f64(1.5).unwrap());
+        assert_eq!(vbytes, vbytes_1_5);
+        assert!(fee_1 < fee_1_5 && fee_1_5 < fee_2);
+        assert_eq!(
+            fee_1_5,
+            Feerate::from_sat_per_vb_f64(1.5).unwrap().fee_vb(vbytes)
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_spend_op_return() {
+        let dummy_op = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(
+            dummy_op.txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: dummy_op,
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr.clone(), 40_000)].iter().cloned().collect();
+        let create = |op_return: Option<Vec<u8>>| {
+            control.create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                2,
+                CreateSpendOptions {
+                    op_return,
+                    ..Default::default()
+                },
+            )
+        };
+
+        // The data must fit in a standard OP_RETURN output.
+        assert_eq!(
+            create(Some(vec![0x42; 81])),
+            Err(CommandError::SpendCreation(
+                SpendCreationError::OpReturnTooLarge(81)
+            ))
+        );
+
+        // A zero-value OP_RETURN output is added along with the recipient and the change.
+        let data = b"liana notarization".to_vec();
+        let psbt = match create(Some(data.clone())).unwrap() {
+            CreateSpendResult::Success { psbt, .. } => psbt,
+            _ => panic!("expect successful spend creation"),
+        };
+        let tx = &psbt.unsigned_tx;
+        assert_eq!(tx.output.len(), 3);
+        let op_returns: Vec<_> = tx
+            .output
+            .iter()
+            .filter(|txo| txo.script_pubkey.is_op_return())
+            .collect();
+        assert_eq!(op_returns.len(), 1);
+        assert_eq!(op_returns[0].value, bitcoin::Amount::ZERO);
+        assert_eq!(op_return_data(&op_returns[0].script_pubkey), Some(data));
+
+        // It is reported separately from the recipients when inspecting the PSBT.
+        let inspection = control.inspect_psbt(&psbt).unwrap();
+        assert_eq!(inspection.external_outputs.len(), 1);
+        assert_eq!(
+            inspection.external_outputs[0].address,
+            Some(dummy_addr.clone())
+        );
+        assert_eq!(
+            inspection.op_return.as_deref(),
+            Some("6c69616e61206e6f746172697a6174696f6e")
+        );
+        assert!(inspection.feerate_vb >= 2);
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_spend_self_send() {
+        let dummy_op = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        // The first receive address of the dummy descriptor.
+        let own_addr = bitcoin::Address::from_str(
+            "bc1q9ksrc647hx8zp2cewl8p5f487dgux3777yees8rjcx46t4daqzzqt7yga8",
+        )
+        .unwrap()
+        .assume_checked();
+        let mut db = DummyDatabase::new();
+        db.insert_address(own_addr.clone(), 0.into(), false);
+        let ms = DummyLiana::new(DummyBitcoind::new(), db);
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: dummy_op,
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        let ext_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> = [
+            (own_addr.as_unchecked().clone(), 20_000),
+            (ext_addr.clone(), 30_000),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        let create = |allow_self_send: bool| match control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                2,
+                CreateSpendOptions {
+                    allow_self_send,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            CreateSpendResult::Success {
+                psbt,
+                warnings,
+                self_send_outputs,
+                ..
+            } => (psbt, warnings, self_send_outputs),
+            _ => panic!("expect successful spend creation"),
+        };
+
+        // Paying to one of our own addresses is not an error, but we get a warning.
+        let (psbt, warnings, self_send_outputs) = create(false);
+        assert_eq!(
+            warnings,
+            vec![format!(
+                "Destination {} is an address of this wallet. Set 'allow_self_send' if this is \
+                 intended.",
+                own_addr
+            )]
+        );
+        // The output paying to our address is reported, but not the change output.
+        assert_eq!(psbt.unsigned_tx.output.len(), 3);
+        assert_eq!(self_send_outputs.len(), 1);
+        assert_eq!(
+            psbt.unsigned_tx.output[self_send_outputs[0]].script_pubkey,
+            own_addr.script_pubkey()
+        );
+
+        // The warning can be silenced, the output is still reported.
+        let (psbt, warnings, self_send_outputs) = create(true);
+        assert!(warnings.is_empty());
+        assert_eq!(self_send_outputs.len(), 1);
+        assert_eq!(
+            psbt.unsigned_tx.output[self_send_outputs[0]].script_pubkey,
+            own_addr.script_pubkey()
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_spend_destination_warnings() {
+        let dummy_op = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: dummy_op,
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        let create = |addr: &str| match control
+            .create_spend(
+                &[(bitcoin::Address::from_str(addr).unwrap(), 20_000)]
+                    .iter()
+                    .cloned()
+                    .collect(),
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                2,
+                CreateSpendOptions::default(),
+            )
+            .unwrap()
+        {
+            CreateSpendResult::Success {
+                destination_warnings,
+                ..
+            } => destination_warnings,
+            _ => panic!("expect successful spend creation"),
+        };
+
+        // No warning for the usual address types.
+        assert!(create("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").is_empty());
+        assert!(
+            create("bc1p5d7rjq7g6rdk2yhzks9smlaqtedr4dekq08ge8ztwac72sfr9rusxg3297").is_empty()
+        );
+
+        // An address using a witness version without spending rules is flagged.
+        assert_eq!(
+            create("bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcs"),
+            vec![
+                "Destination bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcs uses segwit version 2, which has \
+                 no spending rules yet. Coins sent to it could be lost."
+                    .to_string()
+            ]
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_spend_output_seed() {
+        let dummy_op = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(
+            dummy_op.txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: dummy_op,
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr.clone(), 40_000)].iter().cloned().collect();
+        let create = |output_seed: Option<OutputSeed>| match control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                2,
+                CreateSpendOptions {
+                    output_seed,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            CreateSpendResult::Success {
+                psbt, output_seed, ..
+            } => {
+                let dest_position = psbt
+                    .unsigned_tx
+                    .output
+                    .iter()
+                    .position(|txo| txo.script_pubkey == dummy_addr.payload().script_pubkey())
+                    .unwrap();
+                (dest_position, output_seed)
+            }
+            _ => panic!("expect successful spend creation"),
+        };
+
+        // The change position is determined by the seed, which is returned.
+        let seed_last = OutputSeed::from_str(
+            "0808080808080808080808080808080808080808080808080808080808080808",
+        )
+        .unwrap();
+        let seed_first = OutputSeed::from_str(
+            "0505050505050505050505050505050505050505050505050505050505050505",
+        )
+        .unwrap();
+        assert_eq!(create(Some(seed_last)), (0, Some(seed_last)));
+        assert_eq!(create(Some(seed_first)), (1, Some(seed_first)));
+
+        // The same seed always gives the same ordering.
+        for _ in 0..5 {
+            assert_eq!(create(Some(seed_first)), (1, Some(seed_first)));
+        }
+
+        // If no seed is given, a random one is used and returned.
+        let (_, first_seed) = create(None);
+        let (_, second_seed) = create(None);
+        assert!(first_seed.is_some());
+        assert_ne!(first_seed, second_seed);
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_spend_reserve_inputs() {
+        let dummy_op_a = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let dummy_op_b = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:1",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(
+            dummy_op_a.txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[
+            Coin {
+                outpoint: dummy_op_a,
+                is_immature: false,
+                block_info: Some(BlockInfo { height: 1, time: 1 }),
+                amount: bitcoin::Amount::from_sat(100_000),
+                derivation_index: bip32::ChildNumber::from(13),
+                is_change: false,
+                spend_txid: None,
+                spend_block: None,
+            },
+            Coin {
+                outpoint: dummy_op_b,
+                is_immature: false,
+                block_info: Some(BlockInfo { height: 1, time: 1 }),
+                amount: bitcoin::Amount::from_sat(100_000),
+                derivation_index: bip32::ChildNumber::from(14),
+                is_change: false,
+                spend_txid: None,
+                spend_block: None,
+            },
+        ]);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr, 40_000)].iter().cloned().collect();
+        let create = |coin_selector: CoinSelector, reserve_inputs: bool| {
+            control
+                .create_spend(
+                    &destinations,
+                    &coin_selector,
+                    2,
+                    CreateSpendOptions {
+                        reserve_inputs,
+                        ..Default::default()
+                    },
+                )
+                .map(|res| match res {
+                    CreateSpendResult::Success { psbt, draft_id, .. } => (psbt, draft_id),
+                    _ => panic!("expect successful spend creation"),
+                })
+        };
+        let inputs = |psbt: &Psbt| -> Vec<bitcoin::OutPoint> {
+            psbt.unsigned_tx
+                .input
+                .iter()
+                .map(|txin| txin.previous_output)
+                .collect()
+        };
+
+        // Until a draft is stored, its coins may be selected by other Spend transactions.
+        let (psbt, draft_id) = create(CoinSelector::Outpoints(vec![dummy_op_a]), true).unwrap();
+        let (other_psbt, _) =
+            create(CoinSelector::Outpoints(vec![dummy_op_a, dummy_op_b]), true).unwrap();
+
+        // Once stored, they can't be selected again, neither manually nor automatically.
+        control.update_spend(psbt).unwrap();
+        assert_eq!(
+            create(CoinSelector::Outpoints(vec![dummy_op_a]), true),
+            Err(CommandError::CoinReserved(dummy_op_a))
+        );
+        for _ in 0..5 {
+            let (psbt, _) = create(CoinSelector::All, true).unwrap();
+            assert_eq!(inputs(&psbt), vec![dummy_op_b]);
+        }
+
+        // Storing another draft spending the same coin doesn't take over the reservation.
+        control.update_spend(other_psbt.clone()).unwrap();
+        control
+            .delete_spend(&SpendRef::Txid(other_psbt.unsigned_tx.txid()))
+            .unwrap();
+        assert_eq!(
+            create(CoinSelector::Outpoints(vec![dummy_op_a]), true),
+            Err(CommandError::CoinReserved(dummy_op_a))
+        );
+
+        // Deleting the draft releases the reservation.
+        control.delete_spend(&SpendRef::DraftId(draft_id)).unwrap();
+        let (psbt, _) = create(CoinSelector::Outpoints(vec![dummy_op_a]), false).unwrap();
+        assert_eq!(inputs(&psbt), vec![dummy_op_a]);
+
+        // Without reservation, the coin stays available once the draft is stored.
+        control.update_spend(psbt.clone()).unwrap();
+        create(CoinSelector::Outpoints(vec![dummy_op_a]), true).unwrap();
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_spend_exclude_outpoints() {
+        let dummy_op_a = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let dummy_op_b = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:1",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(
+            dummy_op_a.txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[
+            Coin {
+                outpoint: dummy_op_a,
+                is_immature: false,
+                block_info: Some(BlockInfo { height: 1, time: 1 }),
+                amount: bitcoin::Amount::from_sat(100_000),
+                derivation_index: bip32::ChildNumber::from(13),
+                is_change: false,
+                spend_txid: None,
+                spend_block: None,
+            },
+            Coin {
+                outpoint: dummy_op_b,
+                is_immature: false,
+                block_info: Some(BlockInfo { height: 1, time: 1 }),
+                amount: bitcoin::Amount::from_sat(100_000),
+                derivation_index: bip32::ChildNumber::from(14),
+                is_change: false,
+                spend_txid: None,
+                spend_block: None,
+            },
+        ]);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr, 40_000)].iter().cloned().collect();
+        let create = |exclude_outpoints: &[bitcoin::OutPoint]| match control
+            .create_spend(
+                &destinations,
+                &CoinSelector::All,
+                2,
+                CreateSpendOptions {
+                    exclude_outpoints: exclude_outpoints.to_vec(),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            CreateSpendResult::Success { psbt, .. } => psbt
+                .unsigned_tx
+                .input
+                .iter()
+                .map(|txin| txin.previous_output)
+                .collect::<Vec<_>>(),
+            CreateSpendResult::InsufficientFunds { .. } => vec![],
+        };
+
+        // An excluded coin is never selected, but it isn't excluded for the next transactions.
+        for _ in 0..5 {
+            assert_eq!(create(&[dummy_op_a]), vec![dummy_op_b]);
+            assert_eq!(create(&[dummy_op_b]), vec![dummy_op_a]);
+        }
+        assert!(create(&[dummy_op_a, dummy_op_b]).is_empty());
+        assert!(!create(&[]).is_empty());
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn migrate_to_descriptor() {
+        let dummy_op = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(
+            dummy_op.txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+
+        // The same keys, with a longer timelock.
+        let our_desc = control.config.main_descriptor.to_string();
+        let new_desc = our_desc
+            .split('#')
+            .next()
+            .unwrap()
+            .replace("older(10000)", "older(20000)");
+        assert!(matches!(
+            control.migrate_to_descriptor("wsh(pk(invalid))", 2),
+            Err(CommandError::InvalidDescriptor(..))
+        ));
+        assert!(matches!(
+            control.migrate_to_descriptor(&our_desc, 2),
+            Err(CommandError::InvalidDescriptor(..))
+        ));
+        assert_eq!(
+            control.migrate_to_descriptor(&new_desc, 2),
+            Err(CommandError::NoCoinToMigrate)
+        );
+
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let coin = |outpoint, is_confirmed: bool| Coin {
+            outpoint,
+            is_immature: false,
+            block_info: is_confirmed.then_some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        };
+        // The unconfirmed deposit is left behind.
+        let unconfirmed_op = OutPoint::new(dummy_op.txid, 1);
+        db_conn.new_unspent_coins(&[coin(dummy_op, true), coin(unconfirmed_op, false)]);
+        let (psbt, warnings) = match control.migrate_to_descriptor(&new_desc, 2).unwrap() {
+            CreateSpendResult::Success { psbt, warnings, .. } => (psbt, warnings),
+            _ => panic!("expect successful spend creation"),
+        };
+        let tx = &psbt.unsigned_tx;
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].previous_output, dummy_op);
+        assert_eq!(tx.output.len(), 1);
+        let new_desc = descriptors::LianaDescriptor::from_str(&new_desc).unwrap();
+        assert_eq!(
+            tx.output[0].script_pubkey,
+            new_desc
+                .receive_descriptor()
+                .derive(0.into(), &control.secp)
+                .script_pubkey()
+        );
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("1 coin(s)"));
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn rotate_funds() {
+        let dummy_op = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(
+            dummy_op.txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+
+        assert_eq!(
+            control.rotate_funds(2, 0),
+            Err(CommandError::InvalidRotationOutputs(0))
+        );
+        assert_eq!(
+            control.rotate_funds(2, MAX_CHANGE_OUTPUTS + 1),
+            Err(CommandError::InvalidRotationOutputs(MAX_CHANGE_OUTPUTS + 1))
+        );
+        assert_eq!(
+            control.rotate_funds(2, 3),
+            Err(CommandError::NoCoinToRotate)
+        );
+
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let coin = |outpoint, is_confirmed: bool| Coin {
+            outpoint,
+            is_immature: false,
+            block_info: is_confirmed.then_some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        };
+        // The unconfirmed deposit is left behind.
+        let unconfirmed_op = OutPoint::new(dummy_op.txid, 1);
+        db_conn.new_unspent_coins(&[coin(dummy_op, true), coin(unconfirmed_op, false)]);
+        let first_index = db_conn.receive_index();
+        let (psbt, warnings) = match control.rotate_funds(2, 3).unwrap() {
+            CreateSpendResult::Success { psbt, warnings, .. } => (psbt, warnings),
+            _ => panic!("expect successful spend creation"),
+        };
+        let tx = &psbt.unsigned_tx;
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].previous_output, dummy_op);
+
+        // The funds were moved to the next three receive addresses, which are now used.
+        assert_eq!(tx.output.len(), 3);
+        let receive_desc = control.config.main_descriptor.receive_descriptor();
+        let mut index = first_index;
+        for _ in 0..3 {
+            let spk = receive_desc.derive(index, &control.secp).script_pubkey();
+            assert!(tx.output.iter().any(|txo| txo.script_pubkey == spk));
+            index = index.increment().unwrap();
+        }
+        assert_eq!(db_conn.receive_index(), index);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("1 coin(s)"));
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_spend_idempotency_key() {
+        let dummy_op = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(
+            dummy_op.txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: dummy_op,
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr, 10_000)].iter().cloned().collect();
+        let try_create = |feerate, key: Option<&str>| {
+            control.create_spend(
+                &destinations,
+                &CoinSelector::All,
+                feerate,
+                CreateSpendOptions {
+                    idempotency_key: key.map(|k| k.to_string()),
+                    ..Default::default()
+                },
+            )
+        };
+        let create = |feerate, key| try_create(feerate, key).unwrap();
+
+        // Without an idempotency key, a new draft is created on each call.
+        assert_ne!(create(1, None), create(1, None));
+
+        // With the same key and parameters the first result is returned again.
+        let res = create(1, Some("request-1"));
+        assert!(matches!(res, CreateSpendResult::Success { .. }));
+        assert_eq!(create(1, Some("request-1")), res);
+        assert_ne!(create(1, Some("request-2")), res);
+
+        // The key can't be reused with different parameters.
+        assert_eq!(
+            try_create(2, Some("request-1")),
+            Err(CommandError::IdempotencyKeyReused("request-1".to_string()))
+        );
+
+        // A failure is not recorded, so the request can be retried.
+        assert!(matches!(
+            create(10_000, Some("request-3")),
+            CreateSpendResult::InsufficientFunds { .. }
+        ));
+        assert!(matches!(
+            create(1, Some("request-3")),
+            CreateSpendResult::Success { .. }
+        ));
+
+        // While a Spend is being created with a key, another request with the same key and
+        // parameters is refused instead of creating a second transaction.
+        let options = CreateSpendOptions {
+            idempotency_key: Some("request-4".to_string()),
+            ..Default::default()
+        };
+        control.spend_keys.lock().unwrap().insert(
+            "request-4".to_string(),
+            IdempotentSpend {
+                created_at: time::Instant::now(),
+                params_hash: spend_params_hash(
+                    &destinations,
+                    &CoinSelector::All,
+                    Feerate::from_sat_per_vb(1),
+                    &options,
+                ),
+                result: None,
+            },
+        );
+        assert_eq!(
+            try_create(1, Some("request-4")),
+            Err(CommandError::IdempotencyKeyInFlight(
+                "request-4".to_string()
+            ))
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn pending_transactions() {
+        // A transaction spending the given outpoints, with a single output.
+        let tx = |prevouts: &[bitcoin::OutPoint]| bitcoin::Transaction {
+            version: TxVersion::TWO,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: prevouts
+                .iter()
+                .map(|prevout| bitcoin::TxIn {
+                    previous_output: *prevout,
+                    ..bitcoin::TxIn::default()
+                })
+                .collect(),
+            output: vec![TxOut {
+                script_pubkey: ScriptBuf::new(),
+                value: Amount::from_sat(10_000),
+            }],
+        };
+        let ext_outpoint = |vout| {
+            bitcoin::OutPoint::from_str(&format!(
+                "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:{}",
+                vout
+            ))
+            .unwrap()
+        };
+        let block = Block {
+            hash: bitcoin::BlockHash::from_str(
+                "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
+            )
+            .unwrap(),
+            time: 1,
+            height: 1,
+        };
+
+        // An unconfirmed deposit A, whose coin is spent by an unconfirmed transaction B, itself
+        // creating a change coin. A confirmed deposit C whose coin is spent by an unconfirmed
+        // transaction D. A deposit E which got confirmed since we last polled.
+        let tx_a = tx(&[ext_outpoint(0)]);
+        let tx_b = tx(&[bitcoin::OutPoint::new(tx_a.txid(), 0)]);
+        let tx_c = tx(&[ext_outpoint(1)]);
+        let tx_d = tx(&[bitcoin::OutPoint::new(tx_c.txid(), 0), ext_outpoint(2)]);
+        let tx_e = tx(&[ext_outpoint(3)]);
+        let mut bitcoind = DummyBitcoind::new();
+        for tx in [&tx_a, &tx_b, &tx_d].iter() {
+            bitcoind.txs.insert(tx.txid(), ((*tx).clone(), None));
+        }
+        for tx in [&tx_c, &tx_e].iter() {
+            bitcoind.txs.insert(tx.txid(), ((*tx).clone(), Some(block)));
+        }
+        let ms = DummyLiana::new(bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let coin = |outpoint, confirmed: bool, spend_txid| Coin {
+            outpoint,
+            is_immature: false,
+            block_info: confirmed.then_some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(10_000),
+            derivation_index: bip32::ChildNumber::from(0),
+            is_change: false,
+            spend_txid,
+            spend_block: None,
+        };
+        db_conn.new_unspent_coins(&[
+            coin(
+                bitcoin::OutPoint::new(tx_a.txid(), 0),
+                false,
+                Some(tx_b.txid()),
+            ),
+            coin(bitcoin::OutPoint::new(tx_b.txid(), 0), false, None),
+            coin(
+                bitcoin::OutPoint::new(tx_c.txid(), 0),
+                true,
+                Some(tx_d.txid()),
+            ),
+            coin(bitcoin::OutPoint::new(tx_e.txid(), 0), false, None),
+        ]);
+
+        // B depends on A and is listed after it. None of them is in the mempool of the dummy
+        // backend.
+        let res = control.pending_transactions();
+        assert_eq!(res.transactions.len(), 3);
+        let position = |txid| {
+            res.transactions
+                .iter()
+                .position(|tx| tx.txid == txid)
+                .unwrap()
+        };
+        assert!(position(tx_a.txid()) < position(tx_b.txid()));
+        assert_eq!(
+            res.transactions[position(tx_b.txid())].parents,
+            vec![tx_a.txid()]
+        );
+        assert!(res.transactions[position(tx_a.txid())].parents.is_empty());
+        assert!(res.transactions[position(tx_d.txid())].parents.is_empty());
+        assert!(res.transactions.iter().all(|tx| tx.feerate_vb.is_none()));
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn test_broadcast_spend() {
+        let dummy_op = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: dummy_op,
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr, 40_000)].iter().cloned().collect();
+        let psbt = match control
+            .create_spend(
+                &destinations,
+                &CoinSelector::Outpoints(vec![dummy_op]),
+                2,
+                CreateSpendOptions::default(),
+            )
+            .unwrap()
+        {
+            CreateSpendResult::Success { psbt, .. } => psbt,
+            _ => panic!("expect successful spend creation"),
+        };
+        let txid = psbt.unsigned_tx.txid();
+
+        // The Spend must be stored and finalizable to be tested, as to be broadcast.
+        assert_eq!(
+            control.test_broadcast_spend(&txid),
+            Err(CommandError::UnknownSpend(txid))
+        );
+        db_conn.store_spend(&psbt);
+        assert!(matches!(
+            control.test_broadcast_spend(&txid),
+            Err(CommandError::SpendFinalization(_))
+        ));
+        assert!(matches!(
+            control.broadcast_spend(&txid, true),
+            Err(CommandError::SpendFinalization(_))
+        ));
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn rbf_psbt() {
+        let dummy_op_a = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        // Transaction spends outpoint a.
+        let dummy_tx_a = bitcoin::Transaction {
+            version: TxVersion::TWO,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: vec![bitcoin::TxIn {
+                previous_output: dummy_op_a,
+                sequence: bitcoin::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                ..bitcoin::TxIn::default()
+            }],
+            output: vec![],
+        };
+        // PSBT corresponding to the above transaction.
+        let dummy_psbt_a = Psbt {
+            unsigned_tx: dummy_tx_a.clone(),
+            version: 0,
+            xpub: BTreeMap::new(),
+            proprietary: BTreeMap::new(),
+            unknown: BTreeMap::new(),
+            inputs: vec![],
+            outputs: vec![],
+        };
+        let dummy_txid_a = dummy_psbt_a.unsigned_tx.txid();
+        dummy_bitcoind.txs.insert(dummy_txid_a, (dummy_tx_a, None));
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        // The spend needs to be in DB before using RBF.
+        assert_eq!(
+            control.rbf_psbt(&dummy_txid_a, true, None),
+            Err(CommandError::UnknownSpend(dummy_txid_a))
+        );
+        // Store the spend.
+        db_conn.store_spend(&dummy_psbt_a);
+        // Now add the coin to DB, but as spent.
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: dummy_op_a,
+            is_immature: false,
+            block_info: Some(BlockInfo {
+                height: 174500,
+                time: 174500,
+            }),
+            amount: bitcoin::Amount::from_sat(300_000),
+            derivation_index: bip32::ChildNumber::from(11),
+            is_change: false,
+            spend_txid: Some(dummy_txid_a),
+            spend_block: Some(BlockInfo {
+                height: 184500,
+                time: 184500,
+            }),
+        }]);
+        // The coin is spent so we cannot RBF.
+        assert_eq!(
+            control.rbf_psbt(&dummy_txid_a, true, None),
+            Err(CommandError::AlreadySpent(dummy_op_a))
+        );
+        db_conn.unspend_coins(&[dummy_op_a]);
+        // Now remove the coin.
+        db_conn.remove_coins(&[dummy_op_a]);
+        assert_eq!(
+            control.rbf_psbt(&dummy_txid_a, true, None),
+            Err(CommandError::UnknownOutpoint(dummy_op_a))
+        );
+        // A target feerate not higher than the previous should return an error. This is tested in
+        // the functional tests.
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn rbf_psbt_recovery() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+
+        // A coin spent by a recovery transaction sweeping it to an external address, through the
+        // path with a relative timelock of 10_000 blocks.
+        let coin_outpoint = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let sweep_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let sequence = bitcoin::Sequence::from_height(10_000);
+        let reco_tx = bitcoin::Transaction {
+            version: TxVersion::TWO,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: vec![bitcoin::TxIn {
+                previous_output: coin_outpoint,
+                sequence,
+                ..bitcoin::TxIn::default()
+            }],
+            output: vec![TxOut {
+                script_pubkey: sweep_addr.payload().script_pubkey(),
+                value: Amount::from_sat(99_000),
+            }],
+        };
+        let reco_psbt = Psbt::from_unsigned_tx(reco_tx).unwrap();
+        let reco_txid = reco_psbt.unsigned_tx.txid();
+        db_conn.store_spend(&reco_psbt);
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: coin_outpoint,
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(0),
+            is_change: false,
+            spend_txid: Some(reco_txid),
+            spend_block: None,
+        }]);
+
+        // The replacement keeps spending the coin through the recovery path and pays the
+        // higher fee out of the sweep output.
+        let rbf_psbt = match control.rbf_psbt(&reco_txid, false, Some(10)).unwrap() {
+            CreateSpendResult::Success { psbt, .. } => psbt,
+            res => panic!("Unexpected result: {:?}", res),
+        };
+        assert_eq!(rbf_psbt.unsigned_tx.input.len(), 1);
+        assert_eq!(rbf_psbt.unsigned_tx.input[0].previous_output, coin_outpoint);
+        assert_eq!(rbf_psbt.unsigned_tx.input[0].sequence, sequence);
+        assert_eq!(rbf_psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(
+            rbf_psbt.unsigned_tx.output[0].script_pubkey,
+            sweep_addr.payload().script_pubkey()
+        );
+        assert!(rbf_psbt.unsigned_tx.output[0].value < Amount::from_sat(99_000));
+
+        // The absolute fee of the replacement can be set instead. It must at least pay for the
+        // replacement's own size, as the replaced transaction isn't in mempool.
+        let min_fee = match control.rbf_psbt_with_fee(&reco_txid, Amount::from_sat(100)) {
+            Err(CommandError::RbfError(RbfErrorInfo::TooLowFee(min_fee))) => min_fee,
+            res => panic!("Unexpected result: {:?}", res),
+        };
+        assert_eq!(
+            min_fee,
+            control
+                .config
+                .main_descriptor
+                .unsigned_tx_max_vbytes(&rbf_psbt.unsigned_tx)
+        );
+        let (rbf_psbt, warnings) = match control
+            .rbf_psbt_with_fee(&reco_txid, Amount::from_sat(3_000))
+            .unwrap()
+        {
+            CreateSpendResult::Success { psbt, warnings, .. } => (psbt, warnings),
+            res => panic!("Unexpected result: {:?}", res),
+        };
+        assert!(warnings.is_empty());
+        assert_eq!(rbf_psbt.fee().unwrap(), Amount::from_sat(3_000));
+        assert_eq!(rbf_psbt.unsigned_tx.input[0].sequence, sequence);
+        assert_eq!(rbf_psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(
+            rbf_psbt.unsigned_tx.output[0].value,
+            Amount::from_sat(97_000)
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn cancel_spend() {
+        // The change address at index 5 of the dummy descriptor.
+        let prev_change_addr = bitcoin::Address::from_str(
+            "bc1q7er25q5p3geg4s8rrcn8mr65pjvdaqr5xaqwcfp53cr6jxz73p5srj5ny9",
+        )
+        .unwrap()
+        .assume_checked();
+        let mut db = DummyDatabase::new();
+        db.insert_address(prev_change_addr.clone(), 5.into(), true);
+        let ms = DummyLiana::new(DummyBitcoind::new(), db);
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+
+        // A Spend paying to an external address, with change to one of our change addresses.
+        let coin_outpoint = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let ext_addr = bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv")
+            .unwrap()
+            .assume_checked();
+        let tx = bitcoin::Transaction {
+            version: TxVersion::TWO,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: vec![bitcoin::TxIn {
+                previous_output: coin_outpoint,
+                sequence: bitcoin::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                ..bitcoin::TxIn::default()
+            }],
+            output: vec![
+                TxOut {
+                    script_pubkey: ext_addr.script_pubkey(),
+                    value: Amount::from_sat(50_000),
+                },
+                TxOut {
+                    script_pubkey: prev_change_addr.script_pubkey(),
+                    value: Amount::from_sat(49_000),
+                },
+            ],
+        };
+        let psbt = Psbt::from_unsigned_tx(tx.clone()).unwrap();
+        let txid = psbt.unsigned_tx.txid();
+        db_conn.store_spend(&psbt);
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: coin_outpoint,
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(0),
+            is_change: false,
+            spend_txid: Some(txid),
+            spend_block: None,
+        }]);
+
+        // The cancel sends the coin back to a fresh change address, instead of the previous one.
+        let fresh_change_addr = control
+            .config
+            .main_descriptor
+            .change_descriptor()
+            .derive(db_conn.change_index(), &control.secp)
+            .address(control.config.bitcoin_config.network);
+        assert_ne!(fresh_change_addr, prev_change_addr);
+        let cancel_psbt = match control.cancel_spend(&txid, None).unwrap() {
+            CreateSpendResult::Success { psbt, .. } => psbt,
+            res => panic!("Unexpected result: {:?}", res),
+        };
+        assert_eq!(cancel_psbt.unsigned_tx.input.len(), 1);
+        assert_eq!(
+            cancel_psbt.unsigned_tx.input[0].previous_output,
+            coin_outpoint
+        );
+        assert_eq!(cancel_psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(
+            cancel_psbt.unsigned_tx.output[0].script_pubkey,
+            fresh_change_addr.script_pubkey()
+        );
+
+        // A target feerate may be given for the cancel.
+        let min_fee = cancel_psbt.fee().unwrap();
+        let cancel_psbt = match control.cancel_spend(&txid, Some(10)).unwrap() {
+            CreateSpendResult::Success { psbt, .. } => psbt,
+            res => panic!("Unexpected result: {:?}", res),
+        };
+        assert_eq!(cancel_psbt.unsigned_tx.output.len(), 1);
+        assert!(cancel_psbt.fee().unwrap() > min_fee * 9);
+
+        // A transaction which doesn't signal for RBF can't be cancelled.
+        let mut final_tx = tx;
+        final_tx.input[0].sequence = bitcoin::Sequence::MAX;
+        let final_psbt = Psbt::from_unsigned_tx(final_tx).unwrap();
+        db_conn.store_spend(&final_psbt);
+        assert_eq!(
+            control.cancel_spend(&final_psbt.unsigned_tx.txid(), None),
+            Err(CommandError::RbfError(RbfErrorInfo::NotSignaling))
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn broadcast_superseded_spend() {
+        let coin_outpoint = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let ext_addr = bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv")
+            .unwrap()
+            .assume_checked();
+        let tx = bitcoin::Transaction {
+            version: TxVersion::TWO,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: vec![bitcoin::TxIn {
+                previous_output: coin_outpoint,
+                sequence: bitcoin::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                ..bitcoin::TxIn::default()
+            }],
+            output: vec![TxOut {
+                script_pubkey: ext_addr.script_pubkey(),
+                value: Amount::from_sat(90_000),
+            }],
+        };
+        let psbt = Psbt::from_unsigned_tx(tx.clone()).unwrap();
+        let txid = psbt.unsigned_tx.txid();
+        let mut bitcoind = DummyBitcoind::new();
+        bitcoind.txs.insert(txid, (tx, None));
+        let ms = DummyLiana::new(bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.store_spend(&psbt);
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: coin_outpoint,
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(0),
+            is_change: false,
+            spend_txid: Some(txid),
+            spend_block: None,
+        }]);
+
+        // The Spend can't be finalized, but it isn't superseded by a replacement either.
+        let not_superseded = |res: Result<(), CommandError>| {
+            !matches!(res, Err(CommandError::SupersededByReplacement(..)))
+        };
+        assert!(not_superseded(control.broadcast_spend(&txid, false)));
+
+        // Drafting a replacement isn't enough to prevent the broadcast of the original, it must
+        // also be stored.
+        let replacement = match control.rbf_psbt(&txid, false, Some(10)).unwrap() {
+            CreateSpendResult::Success { psbt, .. } => psbt,
+            res => panic!("Unexpected result: {:?}", res),
+        };
+        let replacement_txid = replacement.unsigned_tx.txid();
+        assert!(not_superseded(control.broadcast_spend(&txid, false)));
+        db_conn.store_spend(&replacement);
+        assert_eq!(
+            control.broadcast_spend(&txid, false),
+            Err(CommandError::SupersededByReplacement(replacement_txid))
+        );
+
+        // Until the replacement is broadcast, the original is still rebroadcast as it may be the
+        // one in the mempool.
+        assert_eq!(
+            control.rebroadcast_unconfirmed().unwrap(),
+            vec![(txid, Ok(()))]
+        );
+        db_conn.record_replacements(&[txid], &replacement_txid);
+        assert_eq!(
+            control.rebroadcast_unconfirmed().unwrap(),
+            vec![(txid, Err(CommandError::ReplacedBy(replacement_txid)))]
+        );
+
+        // The replacement itself may be broadcast.
+        assert!(not_superseded(
+            control.broadcast_spend(&replacement_txid, false)
+        ));
+
+        // Once the replacement is deleted, the original may be broadcast again.
+        control
+            .delete_spend(&SpendRef::Txid(replacement_txid))
+            .unwrap();
+        assert!(not_superseded(control.broadcast_spend(&txid, false)));
+        assert!(db_conn.replacement_drafts(&txid).is_empty());
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn update_spend_destination() {
+        let coin_outpoint = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(
+            coin_outpoint.txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: coin_outpoint,
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        let old_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let new_addr =
+            bitcoin::Address::from_str("bc1q39srgatmkp6k2ne3l52yhkjprdvunvspqydmkx").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(old_addr.clone(), 40_000)].iter().cloned().collect();
+        let coin_selector = CoinSelector::Outpoints(vec![coin_outpoint]);
+        let create = || {
+            control.create_spend(
+                &destinations,
+                &coin_selector,
+                2,
+                CreateSpendOptions {
+                    label: Some("rent".to_string()),
+                    ..Default::default()
+                },
+            )
         };
-        let tx = psbt.unsigned_tx;
-        assert_eq!(tx.output.len(), 2);
-        assert_eq!(tx.output[1].value.to_sat(), 5_000);
-        assert!(warnings.is_empty());
+        let (mut psbt, draft_id) = match create().unwrap() {
+            CreateSpendResult::Success { psbt, draft_id, .. } => (psbt, draft_id),
+            res => panic!("Unexpected result: {:?}", res),
+        };
+        let txid = psbt.unsigned_tx.txid();
+        let prev_fee = psbt.fee().unwrap();
+        // Store the draft so its coin is reserved, then add a (dummy) signature to it.
+        control.update_spend(psbt.clone()).unwrap();
+        let sig = bitcoin::ecdsa::Signature::from_str("304402204004fcdbb9c0d0cbf585f58cee34dccb012efbd8fc2b0d5e97760045ae35803802201a0bd7ec2383e0b93748abc9946c8e17a8312e314dab85982aeba650e738cbf401").unwrap();
+        psbt.inputs[0].partial_sigs.insert(
+            bitcoin::PublicKey::from_str(
+                "023a664c5617412f0b292665b1fd9d766456a7a3b1614c7e7c5f411200ff1958ef",
+            )
+            .unwrap(),
+            sig,
+        );
+        db_conn.store_spend(&psbt);
 
-        // Now increase the target by 1 and we'll get a warning again, this time for 1 less than the dust threshold.
-        *destinations.get_mut(&dummy_addr).unwrap() =
-            95_000 - /* fee without change */ 127 - /* extra fee for change output */ 43 + 1;
-        let warnings = if let CreateSpendResult::Success { warnings, .. } = control
-            .create_spend(&destinations, &[dummy_op], 1, None)
+        // The Spend must be stored and pay to the address to replace.
+        let unknown_txid = bitcoin::Txid::from_str(
+            "0000000000000000000000000000000000000000000000000000000000000000",
+        )
+        .unwrap();
+        assert_eq!(
+            control
+                .update_spend_destination(&unknown_txid, &old_addr, &new_addr, 50_000)
+                .unwrap_err(),
+            CommandError::UnknownSpend(unknown_txid)
+        );
+        assert_eq!(
+            control
+                .update_spend_destination(&txid, &new_addr, &old_addr, 50_000)
+                .unwrap_err(),
+            CommandError::NotADestination(txid, new_addr.clone().assume_checked())
+        );
+
+        // The coins can't fund a larger amount. Nothing is stored then.
+        assert!(matches!(
+            control.update_spend_destination(&txid, &old_addr, &new_addr, 200_000),
+            Ok(CreateSpendResult::InsufficientFunds { .. })
+        ));
+        assert_eq!(db_conn.spend_tx(&txid), Some(psbt.clone()));
+
+        // Replace the destination. The change pays for the difference, at the same feerate.
+        let (new_psbt, new_draft_id, warnings) = match control
+            .update_spend_destination(&txid, &old_addr, &new_addr, 50_000)
             .unwrap()
         {
-            warnings
-        } else {
-            panic!("expect successful spend creation")
+            CreateSpendResult::Success {
+                psbt,
+                draft_id,
+                warnings,
+                ..
+            } => (psbt, draft_id, warnings),
+            res => panic!("Unexpected result: {:?}", res),
         };
+        let new_txid = new_psbt.unsigned_tx.txid();
+        assert_ne!(new_txid, txid);
+        assert_eq!(new_draft_id, draft_id);
+        assert_eq!(DraftId::from_psbt(&new_psbt), Some(draft_id));
+        let outputs = &new_psbt.unsigned_tx.output;
+        assert_eq!(outputs.len(), 2);
+        assert!(!outputs
+            .iter()
+            .any(|txo| txo.script_pubkey == old_addr.clone().assume_checked().script_pubkey()));
+        assert!(outputs.iter().any(|txo| txo.script_pubkey
+            == new_addr.clone().assume_checked().script_pubkey()
+            && txo.value == Amount::from_sat(50_000)));
+        assert_eq!(new_psbt.fee().unwrap(), prev_fee);
+
+        // The signatures were dropped, and the caller is told so.
+        assert!(new_psbt.inputs.iter().all(|i| i.partial_sigs.is_empty()));
+        assert!(warnings.iter().any(|w| w.contains("signed again")));
+
+        // The rebuilt transaction replaced the previous one in database, with its label. The coin
+        // is still reserved by the draft.
+        assert_eq!(db_conn.spend_tx(&txid), None);
+        assert_eq!(db_conn.spend_tx(&new_txid), Some(new_psbt));
+        let labels = db_conn.labels(&[LabelItem::Txid(new_txid)].iter().cloned().collect());
+        assert_eq!(labels.get(&new_txid.to_string()), Some(&"rent".to_string()));
         assert_eq!(
-            warnings,
-            vec![
-                "Dust UTXO. The minimal change output allowed by Liana is 5000 sats. \
-                Instead of creating a change of 4999 sats, it was added to the \
-                transaction fee. Select a larger input to avoid this from happening."
-            ]
+            create().unwrap_err(),
+            CommandError::CoinReserved(coin_outpoint)
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn list_confirmed_transactions() {
+        let outpoint = OutPoint::new(
+            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                .unwrap(),
+            0,
+        );
+
+        let deposit1: Transaction = Transaction {
+            version: TxVersion::ONE,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::from_consensus(1).unwrap()),
+            input: vec![TxIn {
+                witness: Witness::new(),
+                previous_output: outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence(0),
+            }],
+            output: vec![TxOut {
+                script_pubkey: ScriptBuf::new(),
+                value: Amount::from_sat(100_000_000),
+            }],
+        };
+
+        let deposit2: Transaction = Transaction {
+            version: TxVersion::ONE,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::from_consensus(1).unwrap()),
+            input: vec![TxIn {
+                witness: Witness::new(),
+                previous_output: outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence(0),
+            }],
+            output: vec![TxOut {
+                script_pubkey: ScriptBuf::new(),
+                value: Amount::from_sat(2000),
+            }],
+        };
+
+        let deposit3: Transaction = Transaction {
+            version: TxVersion::ONE,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::from_consensus(1).unwrap()),
+            input: vec![TxIn {
+                witness: Witness::new(),
+                previous_output: outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence(0),
+            }],
+            output: vec![TxOut {
+                script_pubkey: ScriptBuf::new(),
+                value: Amount::from_sat(3000),
+            }],
+        };
+
+        let spend_tx: Transaction = Transaction {
+            version: TxVersion::ONE,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::from_consensus(1).unwrap()),
+            input: vec![TxIn {
+                witness: Witness::new(),
+                previous_output: OutPoint {
+                    txid: deposit1.txid(),
+                    vout: 0,
+                },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence(0),
+            }],
+            output: vec![
+                TxOut {
+                    script_pubkey: ScriptBuf::new(),
+                    value: Amount::from_sat(4000),
+                },
+                TxOut {
+                    script_pubkey: ScriptBuf::new(),
+                    value: Amount::from_sat(100_000_000 - 4000 - 1000),
+                },
+            ],
+        };
+
+        let mut db = DummyDatabase::new();
+        db.insert_coins(vec![
+            // Deposit 1
+            Coin {
+                is_change: false,
+                is_immature: false,
+                outpoint: OutPoint {
+                    txid: deposit1.txid(),
+                    vout: 0,
+                },
+                block_info: Some(BlockInfo { height: 1, time: 1 }),
+                spend_block: Some(BlockInfo { height: 3, time: 3 }),
+                derivation_index: ChildNumber::from(0),
+                amount: bitcoin::Amount::from_sat(100_000_000),
+                spend_txid: Some(spend_tx.txid()),
+            },
+            // Deposit 2
+            Coin {
+                is_change: false,
+                is_immature: false,
+                outpoint: OutPoint {
+                    txid: deposit2.txid(),
+                    vout: 0,
+                },
+                block_info: Some(BlockInfo { height: 2, time: 2 }),
+                spend_block: None,
+                derivation_index: ChildNumber::from(1),
+                amount: bitcoin::Amount::from_sat(2000),
+                spend_txid: None,
+            },
+            // This coin is a change output.
+            Coin {
+                is_change: true,
+                is_immature: false,
+                outpoint: OutPoint::new(spend_tx.txid(), 1),
+                block_info: Some(BlockInfo { height: 3, time: 3 }),
+                spend_block: None,
+                derivation_index: ChildNumber::from(2),
+                amount: bitcoin::Amount::from_sat(100_000_000 - 4000 - 1000),
+                spend_txid: None,
+            },
+            // Deposit 3
+            Coin {
+                is_change: false,
+                is_immature: false,
+                outpoint: OutPoint {
+                    txid: deposit3.txid(),
+                    vout: 0,
+                },
+                block_info: Some(BlockInfo { height: 4, time: 4 }),
+                spend_block: None,
+                derivation_index: ChildNumber::from(3),
+                amount: bitcoin::Amount::from_sat(3000),
+                spend_txid: None,
+            },
+        ]);
+
+        let mut btc = DummyBitcoind::new();
+        btc.txs.insert(
+            deposit1.txid(),
+            (
+                deposit1.clone(),
+                Some(Block {
+                    hash: bitcoin::BlockHash::from_str(
+                        "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
+                    )
+                    .unwrap(),
+                    time: 1,
+                    height: 1,
+                }),
+            ),
+        );
+        btc.txs.insert(
+            deposit2.txid(),
+            (
+                deposit2.clone(),
+                Some(Block {
+                    hash: bitcoin::BlockHash::from_str(
+                        "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
+                    )
+                    .unwrap(),
+                    time: 2,
+                    height: 2,
+                }),
+            ),
+        );
+        btc.txs.insert(
+            spend_tx.txid(),
+            (
+                spend_tx.clone(),
+                Some(Block {
+                    hash: bitcoin::BlockHash::from_str(
+                        "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
+                    )
+                    .unwrap(),
+                    time: 3,
+                    height: 3,
+                }),
+            ),
+        );
+        btc.txs.insert(
+            deposit3.txid(),
+            (
+                deposit3.clone(),
+                Some(Block {
+                    hash: bitcoin::BlockHash::from_str(
+                        "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
+                    )
+                    .unwrap(),
+                    time: 4,
+                    height: 4,
+                }),
+            ),
         );
 
-        // Now if we mark the coin as spent, we won't create another Spend transaction containing
-        // it.
-        db_conn.spend_coins(&[(
-            dummy_op,
-            bitcoin::Txid::from_str(
-                "ef78f79ba747813887747cf8582897a48f1a09f1ca04d2cd3d6fcfdcbb5e0797",
-            )
-            .unwrap(),
-        )]);
-        assert_eq!(
-            control.create_spend(&destinations, &[dummy_op], 1, None),
-            Err(CommandError::AlreadySpent(dummy_op))
-        );
-        // If we try to use coin selection, the spent coin will not be used as a candidate
-        // and so we get a coin selection error due to insufficient funds.
-        assert!(matches!(
-            control.create_spend(&destinations, &[], 1, None),
-            Ok(CreateSpendResult::InsufficientFunds { .. }),
-        ));
+        let ms = DummyLiana::new(btc, db);
 
-        // We'd bail out if they tried to create a transaction with a too high feerate.
-        let dummy_op_dup = bitcoin::OutPoint {
-            txid: dummy_op.txid,
-            vout: dummy_op.vout + 10,
-        };
-        db_conn.new_unspent_coins(&[Coin {
-            outpoint: dummy_op_dup,
-            is_immature: false,
-            block_info: None,
-            amount: bitcoin::Amount::from_sat(400_000),
-            derivation_index: bip32::ChildNumber::from(42),
-            is_change: false,
-            spend_txid: None,
-            spend_block: None,
-        }]);
-        // Even though 1_000 is the max feerate allowed by our sanity check, we need to
-        // use 1_003 in order to exceed it and fail this test since coin selection is
-        // based on a minimum feerate of `feerate_vb / 4.0` sats/wu, which can result in
-        // the sats/vb feerate being lower than `feerate_vb`.
-        assert_eq!(
-            control.create_spend(&destinations, &[dummy_op_dup], 1_003, None),
-            Err(CommandError::SpendCreation(SpendCreationError::InsaneFees(
-                InsaneFeeInfo::TooHighFeerate(1_001)
-            )))
+        let control = &ms.control();
+
+        let transactions = control.list_confirmed_transactions(0, 4, 10).transactions;
+        assert_eq!(transactions.len(), 4);
+
+        assert_eq!(transactions[0].time, Some(4));
+        assert_eq!(transactions[0].tx, deposit3);
+
+        assert_eq!(transactions[1].time, Some(3));
+        assert_eq!(transactions[1].tx, spend_tx);
+
+        assert_eq!(transactions[2].time, Some(2));
+        assert_eq!(transactions[2].tx, deposit2);
+
+        assert_eq!(transactions[3].time, Some(1));
+        assert_eq!(transactions[3].tx, deposit1);
+
+        let transactions = control.list_confirmed_transactions(2, 3, 10).transactions;
+        assert_eq!(transactions.len(), 2);
+
+        assert_eq!(transactions[0].time, Some(3));
+        assert_eq!(transactions[1].time, Some(2));
+        assert_eq!(transactions[1].tx, deposit2);
+
+        let transactions = control.list_confirmed_transactions(2, 3, 1).transactions;
+        assert_eq!(transactions.len(), 1);
+
+        assert_eq!(transactions[0].time, Some(3));
+        assert_eq!(transactions[0].tx, spend_tx);
+
+        // The whole history can be iterated over, in the same order.
+        let txs: Vec<_> = control
+            .confirmed_transactions_iter(0, 4)
+            .map(|tx_info| tx_info.tx)
+            .collect();
+        assert_eq!(txs, vec![deposit3, spend_tx, deposit2.clone(), deposit1]);
+        let txs: Vec<_> = control
+            .confirmed_transactions_iter(2, 2)
+            .map(|tx_info| tx_info.tx)
+            .collect();
+        assert_eq!(txs, vec![deposit2]);
+        assert_eq!(control.confirmed_transactions_iter(5, 10).count(), 0);
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn list_transactions() {
+        let outpoint = OutPoint::new(
+            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                .unwrap(),
+            0,
         );
 
-        // Add an unconfirmed change coin to be used for coin selection.
-        let confirmed_op_1 = bitcoin::OutPoint {
-            txid: dummy_op.txid,
-            vout: dummy_op.vout + 100,
-        };
-        let unconfirmed_coin = Coin {
-            outpoint: confirmed_op_1,
-            is_immature: false,
-            block_info: None,
-            amount: bitcoin::Amount::from_sat(80_000),
-            derivation_index: bip32::ChildNumber::from(42),
-            is_change: true,
-            spend_txid: None,
-            spend_block: None,
-        };
-        db_conn.new_unspent_coins(&[unconfirmed_coin]);
-        // Coin selection error due to insufficient funds.
-        assert!(matches!(
-            control.create_spend(&destinations, &[], 1, None),
-            Ok(CreateSpendResult::InsufficientFunds { .. }),
-        ));
-        // Set destination amount equal to value of confirmed coins.
-        *destinations.get_mut(&dummy_addr).unwrap() = 80_000;
-        // Coin selection error occurs due to insufficient funds to pay fee.
-        assert!(matches!(
-            control.create_spend(&destinations, &[], 1, None),
-            Ok(CreateSpendResult::InsufficientFunds { .. }),
-        ));
-        let confirmed_op_2 = bitcoin::OutPoint {
-            txid: confirmed_op_1.txid,
-            vout: confirmed_op_1.vout + 10,
+        let tx1: Transaction = Transaction {
+            version: TxVersion::ONE,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::from_consensus(1).unwrap()),
+            input: vec![TxIn {
+                witness: Witness::new(),
+                previous_output: outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence(0),
+            }],
+            output: vec![TxOut {
+                script_pubkey: ScriptBuf::new(),
+                value: Amount::from_sat(100_000_000),
+            }],
         };
-        // Add new confirmed coin to cover the fee.
-        db_conn.new_unspent_coins(&[Coin {
-            outpoint: confirmed_op_2,
-            is_immature: false,
-            block_info: Some(BlockInfo {
-                height: 174500,
-                time: 174500,
-            }),
-            amount: bitcoin::Amount::from_sat(20_000),
-            derivation_index: bip32::ChildNumber::from(43),
-            is_change: false,
-            spend_txid: None,
-            spend_block: None,
-        }]);
-        // First, create a transaction using auto coin selection.
-        let psbt = if let CreateSpendResult::Success { psbt, .. } =
-            control.create_spend(&destinations, &[], 1, None).unwrap()
-        {
-            psbt
-        } else {
-            panic!("expect successful spend creation")
+
+        let tx2: Transaction = Transaction {
+            version: TxVersion::ONE,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::from_consensus(1).unwrap()),
+            input: vec![TxIn {
+                witness: Witness::new(),
+                previous_output: outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence(0),
+            }],
+            output: vec![TxOut {
+                script_pubkey: ScriptBuf::new(),
+                value: Amount::from_sat(2000),
+            }],
         };
-        let tx_auto = psbt.unsigned_tx;
-        let mut tx_prev_outpoints = tx_auto
-            .input
-            .iter()
-            .map(|txin| txin.previous_output)
-            .collect::<Vec<OutPoint>>();
-        tx_prev_outpoints.sort();
-        assert_eq!(tx_auto.input.len(), 2);
-        assert_eq!(tx_prev_outpoints, vec![confirmed_op_1, confirmed_op_2]);
-        // Output includes change.
-        assert_eq!(tx_auto.output.len(), 2);
-        assert_eq!(
-            tx_auto.output[0].script_pubkey,
-            dummy_addr.payload().script_pubkey()
-        );
-        assert_eq!(tx_auto.output[0].value, Amount::from_sat(80_000));
 
-        // Create a second transaction using manual coin selection.
-        let psbt = if let CreateSpendResult::Success { psbt, .. } = control
-            .create_spend(&destinations, &[confirmed_op_1, confirmed_op_2], 1, None)
-            .unwrap()
-        {
-            psbt
-        } else {
-            panic!("expect successful spend creation")
+        let tx3: Transaction = Transaction {
+            version: TxVersion::ONE,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::from_consensus(1).unwrap()),
+            input: vec![TxIn {
+                witness: Witness::new(),
+                previous_output: outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence(0),
+            }],
+            output: vec![TxOut {
+                script_pubkey: ScriptBuf::new(),
+                value: Amount::from_sat(3000),
+            }],
         };
-        let tx_manual = psbt.unsigned_tx;
-        // Check that manual and auto selection give same outputs (except change address).
-        assert_ne!(tx_auto.output, tx_manual.output);
-        assert_eq!(tx_auto.output.len(), tx_manual.output.len());
-        assert_eq!(tx_auto.output[0], tx_manual.output[0]);
-        assert_eq!(tx_auto.output[1].value, tx_manual.output[1].value);
-        assert_ne!(
-            tx_auto.output[1].script_pubkey,
-            tx_manual.output[1].script_pubkey
+
+        let mut btc = DummyBitcoind::new();
+        btc.txs.insert(
+            tx1.txid(),
+            (
+                tx1.clone(),
+                Some(Block {
+                    hash: bitcoin::BlockHash::from_str(
+                        "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
+                    )
+                    .unwrap(),
+                    time: 1,
+                    height: 1,
+                }),
+            ),
+        );
+        btc.txs.insert(
+            tx2.txid(),
+            (
+                tx2.clone(),
+                Some(Block {
+                    hash: bitcoin::BlockHash::from_str(
+                        "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
+                    )
+                    .unwrap(),
+                    time: 2,
+                    height: 2,
+                }),
+            ),
+        );
+        btc.txs.insert(
+            tx3.txid(),
+            (
+                tx3.clone(),
+                Some(Block {
+                    hash: bitcoin::BlockHash::from_str(
+                        "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
+                    )
+                    .unwrap(),
+                    time: 4,
+                    height: 4,
+                }),
+            ),
         );
-        // Check inputs are also the same. Need to sort as order is not guaranteed by `create_spend`.
-        let mut auto_input = tx_auto.clone().input;
-        let mut manual_input = tx_manual.input;
-        auto_input.sort();
-        manual_input.sort();
-        assert_eq!(auto_input, manual_input);
 
-        // Now check that the spend created above using auto-selection only works when the unconfirmed coin
-        // is change and not immature.
-        // 1. not change and not immature
-        db_conn.remove_coins(&[unconfirmed_coin.outpoint]);
-        let mut unconfirmed_coin_2 = unconfirmed_coin;
-        unconfirmed_coin_2.is_change = false;
-        unconfirmed_coin_2.is_immature = false; // (this is already the case)
-        db_conn.new_unspent_coins(&[unconfirmed_coin_2]);
-        assert!(matches!(
-            control.create_spend(&destinations, &[], 1, None),
-            Ok(CreateSpendResult::InsufficientFunds { .. }),
-        ));
-        // 2. change and immature
-        db_conn.remove_coins(&[unconfirmed_coin_2.outpoint]);
-        unconfirmed_coin_2.is_change = true;
-        unconfirmed_coin_2.is_immature = true;
-        db_conn.new_unspent_coins(&[unconfirmed_coin_2]);
-        assert!(matches!(
-            control.create_spend(&destinations, &[], 1, None),
-            Ok(CreateSpendResult::InsufficientFunds { .. }),
-        ));
-        // 3. not change and immature
-        db_conn.remove_coins(&[unconfirmed_coin_2.outpoint]);
-        unconfirmed_coin_2.is_change = false;
-        unconfirmed_coin_2.is_immature = true;
-        db_conn.new_unspent_coins(&[unconfirmed_coin_2]);
-        assert!(matches!(
-            control.create_spend(&destinations, &[], 1, None),
-            Ok(CreateSpendResult::InsufficientFunds { .. }),
-        ));
+        let ms = DummyLiana::new(btc, DummyDatabase::new());
 
-        // Re-insert the original unconfirmed coin again.
-        db_conn.remove_coins(&[unconfirmed_coin_2.outpoint]);
-        db_conn.new_unspent_coins(&[unconfirmed_coin]);
+        let control = &ms.control();
 
-        // Now do the same again, but this time specifying the change address to be the same
-        // as for the auto spend.
-        let change_address = bitcoin::Address::from_script(
-            tx_auto.output[1].script_pubkey.as_script(),
-            bitcoin::Network::Bitcoin,
-        )
-        .unwrap();
-        let psbt = if let CreateSpendResult::Success { psbt, .. } = control
-            .create_spend(
-                &destinations,
-                &[confirmed_op_1, confirmed_op_2],
-                1,
-                Some(change_address.as_unchecked().clone()),
-            )
-            .unwrap()
-        {
-            psbt
-        } else {
-            panic!("expect successful spend creation")
-        };
-        let tx_manual = psbt.unsigned_tx;
-        // Now the outputs of each transaction are the same.
-        assert_eq!(tx_auto.output, tx_manual.output);
-        // Check again that inputs are still the same.
-        let mut auto_input = tx_auto.input;
-        let mut manual_input = tx_manual.input;
-        auto_input.sort();
-        manual_input.sort();
-        assert_eq!(auto_input, manual_input);
+        let transactions = control.list_transactions(&[tx1.txid()]).transactions;
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].tx, tx1);
 
-        // Add a confirmed coin with a value near the dust limit and check that
-        // `InsufficientFunds` error is returned if feerate is too high.
-        let confirmed_op_3 = bitcoin::OutPoint {
-            txid: confirmed_op_2.txid,
-            vout: confirmed_op_2.vout + 10,
-        };
-        db_conn.new_unspent_coins(&[Coin {
-            outpoint: confirmed_op_3,
-            is_immature: false,
-            block_info: Some(BlockInfo {
-                height: 174500,
-                time: 174500,
-            }),
-            amount: bitcoin::Amount::from_sat(5_250),
-            derivation_index: bip32::ChildNumber::from(56),
-            is_change: false,
-            spend_txid: None,
-            spend_block: None,
-        }]);
-        let empty_dest = &HashMap::<bitcoin::Address<address::NetworkUnchecked>, u64>::new();
-        assert!(matches!(
-            control.create_spend(empty_dest, &[confirmed_op_3], 5, None),
-            Ok(CreateSpendResult::InsufficientFunds { .. }),
-        ));
-        // If we use a lower fee, the self-send will succeed.
-        let psbt = if let CreateSpendResult::Success { psbt, .. } = control
-            .create_spend(empty_dest, &[confirmed_op_3], 1, None)
-            .unwrap()
-        {
-            psbt
-        } else {
-            panic!("expect successful spend creation")
-        };
-        let tx = psbt.unsigned_tx;
-        let tx_prev_outpoints = tx
-            .input
+        let transactions = control
+            .list_transactions(&[tx1.txid(), tx2.txid(), tx3.txid()])
+            .transactions;
+        assert_eq!(transactions.len(), 3);
+
+        let txs: Vec<Transaction> = transactions
             .iter()
-            .map(|txin| txin.previous_output)
-            .collect::<Vec<OutPoint>>();
-        assert_eq!(tx.input.len(), 1);
-        assert_eq!(tx_prev_outpoints, vec![confirmed_op_3]);
-        assert_eq!(tx.output.len(), 1);
+            .map(|transaction| transaction.tx.clone())
+            .collect();
 
-        // Can't create a transaction that spends an immature coinbase deposit.
-        let imma_op = bitcoin::OutPoint::from_str(
-            "4753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
-        )
-        .unwrap();
-        db_conn.new_unspent_coins(&[Coin {
-            outpoint: imma_op,
-            is_immature: true,
-            block_info: None,
-            amount: bitcoin::Amount::from_sat(100_000),
-            derivation_index: bip32::ChildNumber::from(13),
-            is_change: false,
-            spend_txid: None,
-            spend_block: None,
-        }]);
-        assert_eq!(
-            control.create_spend(&destinations, &[imma_op], 1_001, None),
-            Err(CommandError::ImmatureCoinbase(imma_op))
-        );
+        assert!(txs.contains(&tx1));
+        assert!(txs.contains(&tx2));
+        assert!(txs.contains(&tx3));
 
         ms.shutdown();
     }
 
     #[test]
-    fn update_spend() {
-        let dummy_op_a = bitcoin::OutPoint::from_str(
-            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
-        )
-        .unwrap();
-        let dummy_op_b = bitcoin::OutPoint::from_str(
-            "4753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:1",
-        )
-        .unwrap();
-        let mut dummy_bitcoind = DummyBitcoind::new();
-        let dummy_tx = bitcoin::Transaction {
+    fn replaced_transactions() {
+        // Two conflicting transactions spending the same coin.
+        let tx = |value| Transaction {
             version: TxVersion::TWO,
             lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
-            input: vec![],
-            output: vec![],
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                script_pubkey: ScriptBuf::new(),
+                value: Amount::from_sat(value),
+            }],
         };
-        dummy_bitcoind
-            .txs
-            .insert(dummy_op_a.txid, (dummy_tx.clone(), None));
-        dummy_bitcoind.txs.insert(dummy_op_b.txid, (dummy_tx, None));
-        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let (original, replacement) = (tx(10_000), tx(9_000));
+        let block = Block {
+            hash: bitcoin::BlockHash::from_str(
+                "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
+            )
+            .unwrap(),
+            time: 1,
+            height: 1,
+        };
+
+        // While the original is unconfirmed, it is reported as replaced.
+        let mut btc = DummyBitcoind::new();
+        btc.txs.insert(original.txid(), (original.clone(), None));
+        let ms = DummyLiana::new(btc, DummyDatabase::new());
         let control = &ms.control();
-        let mut db_conn = control.db().lock().unwrap().connection();
+        control
+            .db()
+            .lock()
+            .unwrap()
+            .connection()
+            .record_replacements(&[original.txid()], &replacement.txid());
+        let transactions = control.list_transactions(&[original.txid()]).transactions;
+        assert_eq!(transactions[0].replaced_by, Some(replacement.txid()));
+        ms.shutdown();
 
-        // Add two (unconfirmed) coins in DB
-        db_conn.new_unspent_coins(&[
-            Coin {
-                outpoint: dummy_op_a,
-                is_immature: false,
-                block_info: None,
-                amount: bitcoin::Amount::from_sat(100_000),
-                derivation_index: bip32::ChildNumber::from(13),
-                is_change: false,
-                spend_txid: None,
-                spend_block: None,
-            },
-            Coin {
-                outpoint: dummy_op_b,
-                is_immature: false,
-                block_info: None,
-                amount: bitcoin::Amount::from_sat(115_680),
-                derivation_index: bip32::ChildNumber::from(34),
-                is_change: false,
-                spend_txid: None,
-                spend_block: None,
-            },
-        ]);
+        // Once the original confirmed instead of the replacement, it isn't anymore.
+        let mut btc = DummyBitcoind::new();
+        btc.txs
+            .insert(original.txid(), (original.clone(), Some(block)));
+        let ms = DummyLiana::new(btc, DummyDatabase::new());
+        let control = &ms.control();
+        control
+            .db()
+            .lock()
+            .unwrap()
+            .connection()
+            .record_replacements(&[original.txid()], &replacement.txid());
+        let transactions = control.list_transactions(&[original.txid()]).transactions;
+        assert_eq!(transactions[0].replaced_by, None);
+        ms.shutdown();
+    }
 
-        // Now create three transactions spending those coins differently
-        let dummy_addr_a =
+    #[test]
+    fn destination_labels() {
+        let alice_addr =
             bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
-        let dummy_addr_b =
+        let bob_addr =
             bitcoin::Address::from_str("bc1q39srgatmkp6k2ne3l52yhkjprdvunvspqydmkx").unwrap();
-        let dummy_value_a = 50_000;
-        let dummy_value_b = 60_000;
-        let destinations_a: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
-            [(dummy_addr_a.clone(), dummy_value_a)]
-                .iter()
-                .cloned()
-                .collect();
-        let destinations_b: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
-            [(dummy_addr_b.clone(), dummy_value_b)]
-                .iter()
-                .cloned()
-                .collect();
-        let destinations_c: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
-            [(dummy_addr_a, dummy_value_a), (dummy_addr_b, dummy_value_b)]
-                .iter()
-                .cloned()
-                .collect();
-        let mut psbt_a = if let CreateSpendResult::Success { psbt, .. } = control
-            .create_spend(&destinations_a, &[dummy_op_a], 1, None)
-            .unwrap()
-        {
-            psbt
-        } else {
-            panic!("expect successful spend creation")
-        };
-        let txid_a = psbt_a.unsigned_tx.txid();
-        let psbt_b = if let CreateSpendResult::Success { psbt, .. } = control
-            .create_spend(&destinations_b, &[dummy_op_b], 10, None)
-            .unwrap()
-        {
-            psbt
-        } else {
-            panic!("expect successful spend creation")
-        };
-        let txid_b = psbt_b.unsigned_tx.txid();
-        let psbt_c = if let CreateSpendResult::Success { psbt, .. } = control
-            .create_spend(&destinations_c, &[dummy_op_a, dummy_op_b], 100, None)
-            .unwrap()
-        {
-            psbt
-        } else {
-            panic!("expect successful spend creation")
+        let tx = Transaction {
+            version: TxVersion::TWO,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: vec![TxIn::default()],
+            output: vec![
+                TxOut {
+                    script_pubkey: alice_addr.payload().script_pubkey(),
+                    value: Amount::from_sat(10_000),
+                },
+                TxOut {
+                    script_pubkey: bob_addr.payload().script_pubkey(),
+                    value: Amount::from_sat(20_000),
+                },
+            ],
         };
-        let txid_c = psbt_c.unsigned_tx.txid();
-
-        // We can store and query them all
-        control.update_spend(psbt_a.clone()).unwrap();
-        assert_eq!(db_conn.spend_tx(&txid_a).unwrap(), psbt_a);
-        control.update_spend(psbt_b.clone()).unwrap();
-        assert_eq!(db_conn.spend_tx(&txid_b).unwrap(), psbt_b);
-        control.update_spend(psbt_c.clone()).unwrap();
-        assert_eq!(db_conn.spend_tx(&txid_c).unwrap(), psbt_c);
+        let mut btc = DummyBitcoind::new();
+        btc.txs.insert(tx.txid(), (tx.clone(), None));
+        let ms = DummyLiana::new(btc, DummyDatabase::new());
+        let control = &ms.control();
 
-        // As well as update them, with or without new signatures
-        let sig = bitcoin::ecdsa::Signature::from_str("304402204004fcdbb9c0d0cbf585f58cee34dccb012efbd8fc2b0d5e97760045ae35803802201a0bd7ec2383e0b93748abc9946c8e17a8312e314dab85982aeba650e738cbf401").unwrap();
-        psbt_a.inputs[0].partial_sigs.insert(
-            bitcoin::PublicKey::from_str(
-                "023a664c5617412f0b292665b1fd9d766456a7a3b1614c7e7c5f411200ff1958ef",
-            )
-            .unwrap(),
-            sig,
+        // The address book is empty at first, and no recipient of the transaction is known.
+        assert!(control.list_destination_labels().destinations.is_empty());
+        let transactions = control.list_transactions(&[tx.txid()]).transactions;
+        assert!(transactions[0].recipient_labels.is_empty());
+
+        // Name Alice's address. Labels on other items are not part of the address book.
+        control
+            .set_destination_label(alice_addr.clone(), Some("Alice".to_string()))
+            .unwrap();
+        let mut items = HashMap::new();
+        items.insert(LabelItem::Txid(tx.txid()), Some("Rent".to_string()));
+        control.update_labels(&items).unwrap();
+        assert_eq!(
+            control.list_destination_labels().destinations,
+            vec![DestinationLabel {
+                address: alice_addr.clone().assume_checked(),
+                label: "Alice".to_string(),
+            }]
         );
-        control.update_spend(psbt_a.clone()).unwrap();
-        assert_eq!(db_conn.spend_tx(&txid_a).unwrap(), psbt_a);
-        control.update_spend(psbt_b.clone()).unwrap();
-        assert_eq!(db_conn.spend_tx(&txid_b).unwrap(), psbt_b);
-        control.update_spend(psbt_c.clone()).unwrap();
-        assert_eq!(db_conn.spend_tx(&txid_c).unwrap(), psbt_c);
 
-        // We can't store a PSBT spending an external coin
-        let external_op = bitcoin::OutPoint::from_str(
-            "8753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:2",
-        )
-        .unwrap();
-        psbt_a.unsigned_tx.input[0].previous_output = external_op;
+        // The known recipient of the transaction is labelled.
+        let transactions = control.list_transactions(&[tx.txid()]).transactions;
+        assert_eq!(transactions[0].recipient_labels.len(), 1);
         assert_eq!(
-            control.update_spend(psbt_a),
-            Err(CommandError::UnknownOutpoint(external_op))
+            transactions[0]
+                .recipient_labels
+                .get(&alice_addr.clone().assume_checked().to_string()),
+            Some(&"Alice".to_string())
         );
 
+        // An address for another network is rejected.
+        let testnet_addr = bitcoin::Address::from_str(
+            "tb1qed7lyessqcecav5uxultf6zc8nefd9kalgaa7dwrglcc6ld5vd3qe20spe",
+        )
+        .unwrap();
+        assert!(matches!(
+            control.set_destination_label(testnet_addr, Some("Carol".to_string())),
+            Err(CommandError::Address(_))
+        ));
+
+        // Alice can be removed from the address book.
+        control.set_destination_label(alice_addr, None).unwrap();
+        assert!(control.list_destination_labels().destinations.is_empty());
+        let transactions = control.list_transactions(&[tx.txid()]).transactions;
+        assert!(transactions[0].recipient_labels.is_empty());
+
         ms.shutdown();
     }
 
     #[test]
-    fn rbf_psbt() {
-        let dummy_op_a = bitcoin::OutPoint::from_str(
-            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
-        )
-        .unwrap();
-        let mut dummy_bitcoind = DummyBitcoind::new();
-        // Transaction spends outpoint a.
-        let dummy_tx_a = bitcoin::Transaction {
-            version: TxVersion::TWO,
-            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
-            input: vec![bitcoin::TxIn {
-                previous_output: dummy_op_a,
-                sequence: bitcoin::Sequence::ENABLE_RBF_NO_LOCKTIME,
-                ..bitcoin::TxIn::default()
-            }],
-            output: vec![],
-        };
-        // PSBT corresponding to the above transaction.
-        let dummy_psbt_a = Psbt {
-            unsigned_tx: dummy_tx_a.clone(),
-            version: 0,
-            xpub: BTreeMap::new(),
-            proprietary: BTreeMap::new(),
-            unknown: BTreeMap::new(),
-            inputs: vec![],
-            outputs: vec![],
-        };
-        let dummy_txid_a = dummy_psbt_a.unsigned_tx.txid();
-        dummy_bitcoind.txs.insert(dummy_txid_a, (dummy_tx_a, None));
-        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+    fn wallet_birthday() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
         let control = &ms.control();
+
+        // The dummy backend reports the genesis block as the last block before any date, so
+        // that's the height at wallet creation.
+        assert_eq!(control.wallet_birthday(), 0);
+        // A coin confirmed later doesn't change the birthday.
         let mut db_conn = control.db().lock().unwrap().connection();
-        // The spend needs to be in DB before using RBF.
-        assert_eq!(
-            control.rbf_psbt(&dummy_txid_a, true, None),
-            Err(CommandError::UnknownSpend(dummy_txid_a))
-        );
-        // Store the spend.
-        db_conn.store_spend(&dummy_psbt_a);
-        // Now add the coin to DB, but as spent.
         db_conn.new_unspent_coins(&[Coin {
-            outpoint: dummy_op_a,
+            outpoint: OutPoint::from_str(
+                "617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3:0",
+            )
+            .unwrap(),
             is_immature: false,
             block_info: Some(BlockInfo {
-                height: 174500,
-                time: 174500,
+                height: 90,
+                time: 1,
             }),
-            amount: bitcoin::Amount::from_sat(300_000),
-            derivation_index: bip32::ChildNumber::from(11),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(0),
             is_change: false,
-            spend_txid: Some(dummy_txid_a),
-            spend_block: Some(BlockInfo {
-                height: 184500,
-                time: 184500,
-            }),
+            spend_txid: None,
+            spend_block: None,
         }]);
-        // The coin is spent so we cannot RBF.
-        assert_eq!(
-            control.rbf_psbt(&dummy_txid_a, true, None),
-            Err(CommandError::AlreadySpent(dummy_op_a))
-        );
-        db_conn.unspend_coins(&[dummy_op_a]);
-        // Now remove the coin.
-        db_conn.remove_coins(&[dummy_op_a]);
-        assert_eq!(
-            control.rbf_psbt(&dummy_txid_a, true, None),
-            Err(CommandError::UnknownOutpoint(dummy_op_a))
-        );
-        // A target feerate not higher than the previous should return an error. This is tested in
-        // the functional tests.
+        assert_eq!(control.wallet_birthday(), 0);
 
         ms.shutdown();
     }
 
     #[test]
-    fn list_confirmed_transactions() {
-        let outpoint = OutPoint::new(
-            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
-                .unwrap(),
-            0,
-        );
+    fn export_wallet() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
 
-        let deposit1: Transaction = Transaction {
-            version: TxVersion::ONE,
-            lock_time: absolute::LockTime::Blocks(absolute::Height::from_consensus(1).unwrap()),
-            input: vec![TxIn {
-                witness: Witness::new(),
-                previous_output: outpoint,
-                script_sig: ScriptBuf::new(),
-                sequence: Sequence(0),
-            }],
+        // Record some state which can't be retrieved from the chain.
+        let addr = control.get_new_address().unwrap().address;
+        let txid =
+            Txid::from_str("f7e9f1d4b3a5c6e8d7f9a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f6")
+                .unwrap();
+        let mut items = HashMap::new();
+        items.insert(
+            LabelItem::Address(addr.clone()),
+            Some("Savings".to_string()),
+        );
+        items.insert(LabelItem::Txid(txid), Some("Rent".to_string()));
+        control.update_labels(&items).unwrap();
+        let tx = Transaction {
+            version: TxVersion::TWO,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: vec![TxIn::default()],
             output: vec![TxOut {
-                script_pubkey: ScriptBuf::new(),
-                value: Amount::from_sat(100_000_000),
+                script_pubkey: addr.script_pubkey(),
+                value: Amount::from_sat(10_000),
             }],
         };
+        let psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        control.db().connection().store_spend(&psbt);
+
+        let export = control.export_wallet();
+        assert_eq!(export.network, bitcoin::Network::Bitcoin);
+        assert_eq!(export.descriptor, control.config.main_descriptor);
+        assert_eq!(export.receive_index, 1);
+        assert_eq!(export.change_index, 0);
+        assert_eq!(export.labels.len(), 2);
+        assert_eq!(export.labels.get(&addr.to_string()).unwrap(), "Savings");
+        assert_eq!(export.labels.get(&txid.to_string()).unwrap(), "Rent");
+        assert_eq!(export.spend_txs, vec![psbt]);
+        assert_eq!(export.birthday_height, Some(control.wallet_birthday()));
+
+        // It can be serialized and parsed back.
+        let ser = serde_json::to_string(&export).unwrap();
+        let deser: WalletExport = serde_json::from_str(&ser).unwrap();
+        assert_eq!(deser, export);
+        // The birthday is missing from exports of older versions.
+        let mut old_export = serde_json::to_value(&export).unwrap();
+        old_export
+            .as_object_mut()
+            .unwrap()
+            .remove("birthday_height");
+        let deser: WalletExport = serde_json::from_value(old_export).unwrap();
+        assert_eq!(deser.birthday_height, None);
 
-        let deposit2: Transaction = Transaction {
-            version: TxVersion::ONE,
-            lock_time: absolute::LockTime::Blocks(absolute::Height::from_consensus(1).unwrap()),
-            input: vec![TxIn {
-                witness: Witness::new(),
-                previous_output: outpoint,
-                script_sig: ScriptBuf::new(),
-                sequence: Sequence(0),
-            }],
-            output: vec![TxOut {
-                script_pubkey: ScriptBuf::new(),
-                value: Amount::from_sat(2000),
-            }],
-        };
+        ms.shutdown();
+    }
 
-        let deposit3: Transaction = Transaction {
-            version: TxVersion::ONE,
-            lock_time: absolute::LockTime::Blocks(absolute::Height::from_consensus(1).unwrap()),
-            input: vec![TxIn {
-                witness: Witness::new(),
-                previous_output: outpoint,
-                script_sig: ScriptBuf::new(),
-                sequence: Sequence(0),
-            }],
+    #[test]
+    fn export_core_descriptors() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        control.get_new_address().unwrap();
+
+        // Both the receive and change descriptors are exported, with a checksum, and Bitcoin Core
+        // rescans from the wallet creation time.
+        let creation_time = db_conn.timestamp();
+        let descs = control.export_core_descriptors();
+        assert_eq!(descs.len(), 2);
+        let desc = &control.config.main_descriptor;
+        assert_eq!(descs[0].desc, desc.receive_descriptor().to_string());
+        assert_eq!(descs[1].desc, desc.change_descriptor().to_string());
+        assert!(descs.iter().all(|d| d.desc.contains('#')));
+        assert_eq!((descs[0].internal, descs[1].internal), (false, true));
+        assert_eq!(descs[0].range, [0, 1_001]);
+        assert_eq!(descs[1].range, [0, 1_000]);
+        assert!(descs.iter().all(|d| d.timestamp == creation_time));
+
+        // If one of our coins was confirmed earlier, it rescans from there instead.
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: OutPoint::from_str(
+                "617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3:0",
+            )
+            .unwrap(),
+            is_immature: false,
+            block_info: Some(BlockInfo {
+                height: 90,
+                time: 1_000,
+            }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(0),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        let descs = control.export_core_descriptors();
+        assert!(descs.iter().all(|d| d.timestamp == 1_000));
+
+        // The fields are named as Bitcoin Core expects them.
+        let json = serde_json::to_value(&descs[1]).unwrap();
+        assert_eq!(json["internal"], true);
+        assert_eq!(json["range"], serde_json::json!([0, 1_000]));
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn rebroadcast_unconfirmed() {
+        let dummy_tx = |value: u64| Transaction {
+            version: TxVersion::TWO,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: vec![TxIn::default()],
             output: vec![TxOut {
                 script_pubkey: ScriptBuf::new(),
-                value: Amount::from_sat(3000),
-            }],
-        };
-
-        let spend_tx: Transaction = Transaction {
-            version: TxVersion::ONE,
-            lock_time: absolute::LockTime::Blocks(absolute::Height::from_consensus(1).unwrap()),
-            input: vec![TxIn {
-                witness: Witness::new(),
-                previous_output: OutPoint {
-                    txid: deposit1.txid(),
-                    vout: 0,
-                },
-                script_sig: ScriptBuf::new(),
-                sequence: Sequence(0),
+                value: Amount::from_sat(value),
             }],
-            output: vec![
-                TxOut {
-                    script_pubkey: ScriptBuf::new(),
-                    value: Amount::from_sat(4000),
-                },
-                TxOut {
-                    script_pubkey: ScriptBuf::new(),
-                    value: Amount::from_sat(100_000_000 - 4000 - 1000),
-                },
-            ],
         };
-
-        let mut db = DummyDatabase::new();
-        db.insert_coins(vec![
-            // Deposit 1
-            Coin {
-                is_change: false,
-                is_immature: false,
-                outpoint: OutPoint {
-                    txid: deposit1.txid(),
-                    vout: 0,
-                },
-                block_info: Some(BlockInfo { height: 1, time: 1 }),
-                spend_block: Some(BlockInfo { height: 3, time: 3 }),
-                derivation_index: ChildNumber::from(0),
-                amount: bitcoin::Amount::from_sat(100_000_000),
-                spend_txid: Some(spend_tx.txid()),
-            },
-            // Deposit 2
-            Coin {
-                is_change: false,
-                is_immature: false,
-                outpoint: OutPoint {
-                    txid: deposit2.txid(),
-                    vout: 0,
-                },
-                block_info: Some(BlockInfo { height: 2, time: 2 }),
-                spend_block: None,
-                derivation_index: ChildNumber::from(1),
-                amount: bitcoin::Amount::from_sat(2000),
-                spend_txid: None,
-            },
-            // This coin is a change output.
-            Coin {
-                is_change: true,
-                is_immature: false,
-                outpoint: OutPoint::new(spend_tx.txid(), 1),
-                block_info: Some(BlockInfo { height: 3, time: 3 }),
-                spend_block: None,
-                derivation_index: ChildNumber::from(2),
-                amount: bitcoin::Amount::from_sat(100_000_000 - 4000 - 1000),
-                spend_txid: None,
-            },
-            // Deposit 3
-            Coin {
-                is_change: false,
-                is_immature: false,
-                outpoint: OutPoint {
-                    txid: deposit3.txid(),
-                    vout: 0,
-                },
-                block_info: Some(BlockInfo { height: 4, time: 4 }),
-                spend_block: None,
-                derivation_index: ChildNumber::from(3),
-                amount: bitcoin::Amount::from_sat(3000),
-                spend_txid: None,
-            },
-        ]);
+        let (unconfirmed_tx, confirmed_tx, unknown_tx) =
+            (dummy_tx(1_000), dummy_tx(2_000), dummy_tx(3_000));
 
         let mut btc = DummyBitcoind::new();
+        btc.txs
+            .insert(unconfirmed_tx.txid(), (unconfirmed_tx.clone(), None));
         btc.txs.insert(
-            deposit1.txid(),
+            confirmed_tx.txid(),
             (
-                deposit1.clone(),
+                confirmed_tx.clone(),
                 Some(Block {
                     hash: bitcoin::BlockHash::from_str(
                         "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
@@ -2297,203 +12000,898 @@ mod tests {
                 }),
             ),
         );
-        btc.txs.insert(
-            deposit2.txid(),
-            (
-                deposit2.clone(),
-                Some(Block {
-                    hash: bitcoin::BlockHash::from_str(
-                        "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
+        let ms = DummyLiana::new(btc, DummyDatabase::new());
+        let control = &ms.control();
+
+        // Without any coin being spent there is nothing to rebroadcast.
+        assert!(control.rebroadcast_unconfirmed().unwrap().is_empty());
+
+        // Mark a coin as spent by each of the transactions. One is still unconfirmed, the other
+        // got confirmed since we last polled and the last one is unknown to the Bitcoin backend.
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let txs = [&unconfirmed_tx, &confirmed_tx, &unknown_tx];
+        let coins: Vec<Coin> = (0..txs.len())
+            .map(|i| Coin {
+                outpoint: OutPoint::new(
+                    Txid::from_str(
+                        "617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3",
                     )
                     .unwrap(),
-                    time: 2,
-                    height: 2,
-                }),
-            ),
+                    i as u32,
+                ),
+                is_immature: false,
+                block_info: Some(BlockInfo { height: 1, time: 1 }),
+                amount: bitcoin::Amount::from_sat(100_000),
+                derivation_index: bip32::ChildNumber::from(i as u32),
+                is_change: false,
+                spend_txid: None,
+                spend_block: None,
+            })
+            .collect();
+        db_conn.new_unspent_coins(&coins);
+        let spends: Vec<_> = coins
+            .iter()
+            .zip(txs.iter())
+            .map(|(coin, tx)| (coin.outpoint, tx.txid()))
+            .collect();
+        db_conn.spend_coins(&spends);
+
+        let mut res = control.rebroadcast_unconfirmed().unwrap();
+        res.sort_by_key(|(txid, _)| *txid == unknown_tx.txid());
+        assert_eq!(
+            res,
+            vec![
+                (unconfirmed_tx.txid(), Ok(())),
+                (
+                    unknown_tx.txid(),
+                    Err(CommandError::UnknownSpend(unknown_tx.txid()))
+                ),
+            ]
         );
+
+        ms.shutdown();
+    }
+    #[test]
+    fn mempool_status() {
+        let confirmed_tx = Transaction {
+            version: TxVersion::TWO,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+        let mut btc = DummyBitcoind::new();
         btc.txs.insert(
-            spend_tx.txid(),
+            confirmed_tx.txid(),
             (
-                spend_tx.clone(),
+                confirmed_tx.clone(),
                 Some(Block {
                     hash: bitcoin::BlockHash::from_str(
                         "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
                     )
                     .unwrap(),
-                    time: 3,
-                    height: 3,
+                    time: 1,
+                    height: 12,
                 }),
             ),
         );
-        btc.txs.insert(
-            deposit3.txid(),
-            (
-                deposit3.clone(),
-                Some(Block {
-                    hash: bitcoin::BlockHash::from_str(
-                        "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
-                    )
-                    .unwrap(),
-                    time: 4,
-                    height: 4,
-                }),
-            ),
+        let ms = DummyLiana::new(btc, DummyDatabase::new());
+        let control = &ms.control();
+
+        assert_eq!(
+            control.get_mempool_status(&confirmed_tx.txid()),
+            MempoolStatus::Confirmed { height: 12 }
+        );
+        // The dummy backend's mempool is always empty.
+        let unknown_txid =
+            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                .unwrap();
+        assert_eq!(
+            control.get_mempool_status(&unknown_txid),
+            MempoolStatus::NotInMempool
+        );
+        // Neither is there package information for a transaction not in the mempool.
+        assert_eq!(
+            control.package_info(&unknown_txid),
+            Err(CommandError::NotInMempool(unknown_txid))
+        );
+        assert_eq!(
+            control.package_info(&confirmed_tx.txid()),
+            Err(CommandError::NotInMempool(confirmed_tx.txid()))
         );
 
-        let ms = DummyLiana::new(btc, db);
+        ms.shutdown();
+    }
 
+    #[test]
+    fn invalidated_coins() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
         let control = &ms.control();
+        let mut db_conn = control.db.connection();
+        let coin = |op: &str, is_change| Coin {
+            outpoint: bitcoin::OutPoint::from_str(op).unwrap(),
+            is_immature: false,
+            block_info: None,
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(4),
+            is_change,
+            spend_txid: None,
+            spend_block: None,
+        };
+        let coin_a = coin(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+            false,
+        );
+        let coin_b = coin(
+            "4753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:1",
+            true,
+        );
 
-        let transactions = control.list_confirmed_transactions(0, 4, 10).transactions;
-        assert_eq!(transactions.len(), 4);
+        // Dropped coins are only reported once their deposit was double spent.
+        db_conn.record_dropped_coins(&[coin_a, coin_b], 100);
+        assert!(control.invalidated_coins().coins.is_empty());
+        db_conn.invalidate_coins(&[coin_b.outpoint]);
+        let coins = control.invalidated_coins().coins;
+        assert_eq!(coins.len(), 1);
+        assert_eq!(coins[0].outpoint, coin_b.outpoint);
+        assert_eq!(coins[0].amount, coin_b.amount);
+        assert!(coins[0].is_change);
+        assert_eq!(
+            coins[0].address,
+            control
+                .config
+                .main_descriptor
+                .change_descriptor()
+                .derive(4.into(), &control.secp)
+                .address(bitcoin::Network::Bitcoin)
+        );
 
-        assert_eq!(transactions[0].time, Some(4));
-        assert_eq!(transactions[0].tx, deposit3);
+        // If the deposit comes back, the coin isn't reported anymore.
+        db_conn.invalidate_coins(&[coin_a.outpoint]);
+        assert_eq!(control.invalidated_coins().coins.len(), 2);
+        db_conn.new_unspent_coins(&[coin_a]);
+        let coins = control.invalidated_coins().coins;
+        assert_eq!(coins.len(), 1);
+        assert_eq!(coins[0].outpoint, coin_b.outpoint);
+        // And if it's dropped again, it isn't invalidated anymore.
+        db_conn.remove_coins(&[coin_a.outpoint]);
+        db_conn.record_dropped_coins(&[coin_a], 100);
+        assert_eq!(control.invalidated_coins().coins.len(), 1);
 
-        assert_eq!(transactions[1].time, Some(3));
-        assert_eq!(transactions[1].tx, spend_tx);
+        ms.shutdown();
+    }
 
-        assert_eq!(transactions[2].time, Some(2));
-        assert_eq!(transactions[2].tx, deposit2);
+    #[test]
+    fn spend_psbt_bbqr() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
 
-        assert_eq!(transactions[3].time, Some(1));
-        assert_eq!(transactions[3].tx, deposit1);
+        let coin_outpoint = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let tx = bitcoin::Transaction {
+            version: TxVersion::TWO,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: vec![bitcoin::TxIn {
+                previous_output: coin_outpoint,
+                sequence: bitcoin::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                ..bitcoin::TxIn::default()
+            }],
+            output: vec![TxOut {
+                script_pubkey: bitcoin::Address::from_str(
+                    "bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv",
+                )
+                .unwrap()
+                .assume_checked()
+                .script_pubkey(),
+                value: Amount::from_sat(90_000),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        let txid = psbt.unsigned_tx.txid();
+        db_conn.store_spend(&psbt);
 
-        let transactions = control.list_confirmed_transactions(2, 3, 10).transactions;
-        assert_eq!(transactions.len(), 2);
+        // The stored PSBT is split into parts of the requested size.
+        let parts = control.export_spend_psbt_bbqr(&txid, 60).unwrap();
+        assert!(parts.len() > 1);
+        assert!(parts.iter().all(|p| p.len() <= 60 && p.starts_with("B$2P")));
+        assert_eq!(
+            bbqr::decode(&parts).unwrap(),
+            (bbqr::FILE_TYPE_PSBT, psbt.serialize())
+        );
+        let unknown_txid = bitcoin::Txid::from_str(
+            "0000000000000000000000000000000000000000000000000000000000000000",
+        )
+        .unwrap();
+        assert_eq!(
+            control.export_spend_psbt_bbqr(&unknown_txid, 60),
+            Err(CommandError::UnknownSpend(unknown_txid))
+        );
+        assert_eq!(
+            control.export_spend_psbt_bbqr(&txid, 15),
+            Err(CommandError::Bbqr(bbqr::BbqrError::PartTooShort(15)))
+        );
 
-        assert_eq!(transactions[0].time, Some(3));
-        assert_eq!(transactions[1].time, Some(2));
-        assert_eq!(transactions[1].tx, deposit2);
+        // A signed version is scanned back, out of order and with duplicates. The signature is
+        // merged into the stored PSBT.
+        let sig = bitcoin::ecdsa::Signature::from_str("304402204004fcdbb9c0d0cbf585f58cee34dccb012efbd8fc2b0d5e97760045ae35803802201a0bd7ec2383e0b93748abc9946c8e17a8312e314dab85982aeba650e738cbf401").unwrap();
+        psbt.inputs[0].partial_sigs.insert(
+            bitcoin::PublicKey::from_str(
+                "023a664c5617412f0b292665b1fd9d766456a7a3b1614c7e7c5f411200ff1958ef",
+            )
+            .unwrap(),
+            sig,
+        );
+        let mut signed_parts = bbqr::encode(&psbt.serialize(), bbqr::FILE_TYPE_PSBT, 60).unwrap();
+        signed_parts.reverse();
+        signed_parts.push(signed_parts[0].clone());
+        assert_eq!(control.import_spend_psbt_bbqr(&signed_parts), Ok(txid));
+        assert_eq!(
+            db_conn.spend_tx(&txid).unwrap().inputs[0].partial_sigs,
+            psbt.inputs[0].partial_sigs
+        );
+
+        // All the parts are needed, and they must contain a PSBT.
+        assert_eq!(
+            control.import_spend_psbt_bbqr(&signed_parts[1..signed_parts.len() - 1]),
+            Err(CommandError::Bbqr(bbqr::BbqrError::MissingParts(vec![
+                signed_parts.len() - 2
+            ])))
+        );
+        let not_psbt = bbqr::encode(b"not a psbt", bbqr::FILE_TYPE_PSBT, 60).unwrap();
+        assert!(matches!(
+            control.import_spend_psbt_bbqr(&not_psbt),
+            Err(CommandError::InvalidPsbt(_))
+        ));
+        let not_psbt = bbqr::encode(&psbt.serialize(), 'T', 60).unwrap();
+        assert!(matches!(
+            control.import_spend_psbt_bbqr(&not_psbt),
+            Err(CommandError::InvalidPsbt(_))
+        ));
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn check_payment() {
+        // The first receive address of the dummy descriptor.
+        let addr = bitcoin::Address::from_str(
+            "bc1q9ksrc647hx8zp2cewl8p5f487dgux3777yees8rjcx46t4daqzzqt7yga8",
+        )
+        .unwrap();
+        let mut db = DummyDatabase::new();
+        db.insert_address(addr.clone().assume_checked(), 0.into(), false);
+        let ms = DummyLiana::new(DummyBitcoind::new(), db);
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.update_tip(&BlockChainTip {
+            hash: bitcoin::BlockHash::from_str(
+                "000000007bc154e0fa7ea32218a72fe2c1bb9f86cf8c9ebf9a715ed27fdb229a",
+            )
+            .unwrap(),
+            height: 100,
+        });
+        let txid =
+            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
+                .unwrap();
+        let coin = |vout, index: u32, amount, block_height: Option<i32>| Coin {
+            outpoint: OutPoint::new(txid, vout),
+            is_immature: false,
+            block_info: block_height.map(|height| BlockInfo { height, time: 1 }),
+            amount: bitcoin::Amount::from_sat(amount),
+            derivation_index: bip32::ChildNumber::from(index),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        };
+        let check = |sats, min_confs| {
+            control
+                .check_payment(addr.clone(), bitcoin::Amount::from_sat(sats), min_confs)
+                .unwrap()
+        };
+
+        // Nothing was received yet.
+        let status = check(50_000, 1);
+        assert_eq!(status.received, bitcoin::Amount::ZERO);
+        assert_eq!(status.confirmations, None);
+        assert!(!status.is_paid && status.payments.is_empty());
+
+        // A first partial payment, unconfirmed. A coin on another address doesn't count.
+        db_conn.new_unspent_coins(&[coin(0, 0, 30_000, None), coin(1, 1, 100_000, Some(90))]);
+        let status = check(50_000, 0);
+        assert_eq!(status.received.to_sat(), 30_000);
+        assert_eq!(status.confirmations, None);
+        assert!(!status.is_paid);
+
+        // The rest is paid in a second, confirmed, transaction. The payment is only as deep as
+        // the least confirmed coin needed to reach the expected amount.
+        db_conn.new_unspent_coins(&[coin(2, 0, 25_000, Some(98))]);
+        let status = check(50_000, 1);
+        assert_eq!(status.received.to_sat(), 55_000);
+        assert_eq!(status.excess.to_sat(), 5_000);
+        assert_eq!(status.confirmations, Some(0));
+        assert!(!status.is_paid);
+        assert!(check(50_000, 0).is_paid);
+        assert_eq!(
+            status
+                .payments
+                .iter()
+                .map(|p| (p.outpoint.vout, p.confirmations))
+                .collect::<Vec<_>>(),
+            vec![(2, 3), (0, 0)]
+        );
+        // The confirmed coin alone is enough for a smaller amount.
+        let status = check(20_000, 3);
+        assert_eq!(status.confirmations, Some(3));
+        assert!(status.is_paid);
+        assert!(!check(20_000, 4).is_paid);
+
+        // Once the first payment confirms, the whole amount is.
+        db_conn.confirm_coins(&[(OutPoint::new(txid, 0), 99, 1)]);
+        let status = check(50_000, 2);
+        assert_eq!(status.confirmations, Some(2));
+        assert!(status.is_paid);
+
+        // We can only check the payments to our own addresses.
+        let ext_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        assert_eq!(
+            control.check_payment(ext_addr.clone(), bitcoin::Amount::from_sat(1), 1),
+            Err(CommandError::UnknownAddress(ext_addr.assume_checked()))
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_spend_long_term_feerate() {
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        let ops: Vec<bitcoin::OutPoint> = (0..3)
+            .map(|vout| {
+                bitcoin::OutPoint::from_str(&format!(
+                    "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:{}",
+                    vout
+                ))
+                .unwrap()
+            })
+            .collect();
+        dummy_bitcoind.txs.insert(
+            ops[0].txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let coins: Vec<Coin> = [100_000, 20_000, 12_000]
+            .iter()
+            .zip(ops.iter())
+            .map(|(amount, op)| Coin {
+                outpoint: *op,
+                is_immature: false,
+                block_info: Some(BlockInfo { height: 1, time: 1 }),
+                amount: bitcoin::Amount::from_sat(*amount),
+                derivation_index: bip32::ChildNumber::from(op.vout),
+                is_change: false,
+                spend_txid: None,
+                spend_block: None,
+            })
+            .collect();
+        db_conn.new_unspent_coins(&coins);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr, 105_000)].iter().cloned().collect();
+        let create = |long_term_feerate_vb: Option<u64>| {
+            control.create_spend(
+                &destinations,
+                &CoinSelector::All,
+                2,
+                CreateSpendOptions {
+                    long_term_feerate_vb,
+                    ..Default::default()
+                },
+            )
+        };
+        // Get the indexes of the coins spent and the number of outputs.
+        let selection = |long_term_feerate_vb: Option<u64>| {
+            if let CreateSpendResult::Success { psbt, .. } = create(long_term_feerate_vb).unwrap() {
+                let mut spent: Vec<u32> = psbt
+                    .unsigned_tx
+                    .input
+                    .iter()
+                    .map(|txin| txin.previous_output.vout)
+                    .collect();
+                spent.sort_unstable();
+                (spent, psbt.unsigned_tx.output.len())
+            } else {
+                panic!("expect successful spend creation")
+            }
+        };
+
+        // With a long-term feerate close to the current one, creating change is cheap. By default
+        // the long-term feerate is of 10 sats/vb.
+        assert_eq!(selection(Some(1)), (vec![0, 1], 2));
+        assert_eq!(selection(None), (vec![0, 1], 2));
+
+        // If change is expected to be expensive to spend in the future, we rather select coins
+        // which avoid creating change altogether and leave the excess to fees.
+        assert_eq!(selection(Some(100)), (vec![0, 2], 1));
+
+        // The long-term feerate must be sane.
+        assert_eq!(
+            create(Some(0)),
+            Err(CommandError::InvalidFeerate(Feerate::from_sat_per_vb(0)))
+        );
+        assert_eq!(
+            create(Some(MAX_FEERATE + 1)),
+            Err(CommandError::InvalidFeerate(Feerate::from_sat_per_vb(
+                MAX_FEERATE + 1
+            )))
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_spend_change_path() {
+        let outpoint = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(
+            outpoint.txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint,
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(0),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr, 10_000)].iter().cloned().collect();
+        let create = |change_address: Option<bitcoin::Address<address::NetworkUnchecked>>,
+                      change_path: &str| {
+            control.create_spend(
+                &destinations,
+                &CoinSelector::All,
+                1,
+                CreateSpendOptions {
+                    change_address,
+                    change_path: Some(bip32::DerivationPath::from_str(change_path).unwrap()),
+                    ..Default::default()
+                },
+            )
+        };
 
-        let transactions = control.list_confirmed_transactions(2, 3, 1).transactions;
-        assert_eq!(transactions.len(), 1);
+        // The change is sent to the address at the requested index of our change descriptor.
+        let change_index = db_conn.change_index();
+        let psbt = match create(None, "m/42").unwrap() {
+            CreateSpendResult::Success { psbt, .. } => psbt,
+            _ => panic!("expect successful spend creation"),
+        };
+        let change_addr = control
+            .config
+            .main_descriptor
+            .change_descriptor()
+            .derive(42.into(), &control.secp)
+            .address(control.config.bitcoin_config.network);
+        assert_eq!(psbt.unsigned_tx.output.len(), 2);
+        assert!(psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .any(|txo| txo.script_pubkey == change_addr.script_pubkey()));
+        // As for any change address of ours from the future, our next change index is updated.
+        assert!(change_index < 43.into());
+        assert_eq!(db_conn.change_index(), 43.into());
+
+        // The path must be a single unhardened index within the change descriptor.
+        for path in ["m", "m/1/42", "m/42'"].iter() {
+            assert_eq!(
+                create(None, path),
+                Err(CommandError::InvalidChangePath(
+                    bip32::DerivationPath::from_str(path).unwrap()
+                ))
+            );
+        }
 
-        assert_eq!(transactions[0].time, Some(3));
-        assert_eq!(transactions[0].tx, spend_tx);
+        // It can't be combined with a change address.
+        let change_address = control
+            .get_new_address()
+            .unwrap()
+            .address
+            .as_unchecked()
+            .clone();
+        assert_eq!(
+            create(Some(change_address), "m/42"),
+            Err(CommandError::ChangeAddressAndPath)
+        );
 
         ms.shutdown();
     }
 
     #[test]
-    fn list_transactions() {
-        let outpoint = OutPoint::new(
-            Txid::from_str("617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3")
-                .unwrap(),
-            0,
-        );
-
-        let tx1: Transaction = Transaction {
-            version: TxVersion::ONE,
-            lock_time: absolute::LockTime::Blocks(absolute::Height::from_consensus(1).unwrap()),
-            input: vec![TxIn {
-                witness: Witness::new(),
-                previous_output: outpoint,
-                script_sig: ScriptBuf::new(),
-                sequence: Sequence(0),
-            }],
-            output: vec![TxOut {
-                script_pubkey: ScriptBuf::new(),
-                value: Amount::from_sat(100_000_000),
-            }],
+    fn uneconomic_coins() {
+        let txid =
+            Txid::from_str("3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810")
+                .unwrap();
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let coin = |vout, amount| Coin {
+            outpoint: OutPoint::new(txid, vout),
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(amount),
+            derivation_index: bip32::ChildNumber::from(vout),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
         };
-
-        let tx2: Transaction = Transaction {
-            version: TxVersion::ONE,
-            lock_time: absolute::LockTime::Blocks(absolute::Height::from_consensus(1).unwrap()),
-            input: vec![TxIn {
-                witness: Witness::new(),
-                previous_output: outpoint,
-                script_sig: ScriptBuf::new(),
-                sequence: Sequence(0),
-            }],
-            output: vec![TxOut {
-                script_pubkey: ScriptBuf::new(),
-                value: Amount::from_sat(2000),
-            }],
+        // At 50 sats/vb, the first coin is worth less than the fee to spend it and the second one
+        // a bit more. At 100 sats/vb, both are.
+        let input_fee = max_input_fee(&control.config.main_descriptor, 50.into()).to_sat();
+        db_conn.new_unspent_coins(&[
+            coin(0, input_fee - 1),
+            coin(1, input_fee + 1),
+            coin(2, 1_000_000),
+        ]);
+        let uneconomic = |feerate_vb| {
+            let mut outpoints: Vec<_> = control
+                .uneconomic_coins(feerate_vb)
+                .unwrap()
+                .coins
+                .into_iter()
+                .map(|c| c.outpoint.vout)
+                .collect();
+            outpoints.sort();
+            outpoints
         };
+        assert_eq!(uneconomic(50), vec![0]);
+        assert_eq!(uneconomic(100), vec![0, 1]);
+        assert!(uneconomic(1).is_empty());
+        assert_eq!(
+            control.uneconomic_coins(0).unwrap_err(),
+            CommandError::InvalidFeerate(0.into())
+        );
+        assert_eq!(
+            control.uneconomic_coins(MAX_FEERATE + 1).unwrap_err(),
+            CommandError::InvalidFeerate((MAX_FEERATE + 1).into())
+        );
 
-        let tx3: Transaction = Transaction {
-            version: TxVersion::ONE,
-            lock_time: absolute::LockTime::Blocks(absolute::Height::from_consensus(1).unwrap()),
-            input: vec![TxIn {
-                witness: Witness::new(),
-                previous_output: outpoint,
-                script_sig: ScriptBuf::new(),
-                sequence: Sequence(0),
-            }],
-            output: vec![TxOut {
-                script_pubkey: ScriptBuf::new(),
-                value: Amount::from_sat(3000),
-            }],
+        // The automated coin selection may leave them aside.
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr, 2_000_000)].iter().cloned().collect();
+        let available = |ignore_uneconomic| match control
+            .create_spend(
+                &destinations,
+                &CoinSelector::All,
+                50,
+                CreateSpendOptions {
+                    ignore_uneconomic,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        {
+            CreateSpendResult::InsufficientFunds { available, .. } => available,
+            res => panic!("Unexpected result: {:?}", res),
         };
+        assert_eq!(available(false), 1_000_000 + 2 * input_fee);
+        assert_eq!(available(true), 1_000_000 + input_fee + 1);
 
-        let mut btc = DummyBitcoind::new();
-        btc.txs.insert(
-            tx1.txid(),
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_spend_max_inputs() {
+        let txid =
+            Txid::from_str("3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810")
+                .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(
+            txid,
             (
-                tx1.clone(),
-                Some(Block {
-                    hash: bitcoin::BlockHash::from_str(
-                        "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
-                    )
-                    .unwrap(),
-                    time: 1,
-                    height: 1,
-                }),
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
             ),
         );
-        btc.txs.insert(
-            tx2.txid(),
-            (
-                tx2.clone(),
-                Some(Block {
-                    hash: bitcoin::BlockHash::from_str(
-                        "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
-                    )
-                    .unwrap(),
-                    time: 2,
-                    height: 2,
-                }),
-            ),
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let coin = |vout, amount| Coin {
+            outpoint: OutPoint::new(txid, vout),
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(amount),
+            derivation_index: bip32::ChildNumber::from(vout),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        };
+        db_conn.new_unspent_coins(&[
+            coin(0, 20_000),
+            coin(1, 20_000),
+            coin(2, 20_000),
+            coin(3, 20_000),
+        ]);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr, 50_000)].iter().cloned().collect();
+        let create = |coin_selector: &CoinSelector, max_inputs: Option<usize>| {
+            control.create_spend(
+                &destinations,
+                coin_selector,
+                1,
+                CreateSpendOptions {
+                    max_inputs,
+                    ..Default::default()
+                },
+            )
+        };
+        let n_inputs = |res: Result<CreateSpendResult, CommandError>| match res.unwrap() {
+            CreateSpendResult::Success { psbt, .. } => psbt.unsigned_tx.input.len(),
+            _ => panic!("expect successful spend creation"),
+        };
+
+        // Three coins are needed to fund the transaction.
+        assert_eq!(n_inputs(create(&CoinSelector::All, Some(3))), 3);
+        assert_eq!(
+            create(&CoinSelector::All, Some(2)),
+            Err(CommandError::SpendCreation(
+                SpendCreationError::TooManyInputsRequired(3)
+            ))
         );
-        btc.txs.insert(
-            tx3.txid(),
-            (
-                tx3.clone(),
-                Some(Block {
-                    hash: bitcoin::BlockHash::from_str(
-                        "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
-                    )
-                    .unwrap(),
-                    time: 4,
-                    height: 4,
-                }),
-            ),
+
+        // With a larger coin available, it is preferred to stay within the cap.
+        db_conn.new_unspent_coins(&[coin(4, 70_000)]);
+        assert_eq!(n_inputs(create(&CoinSelector::All, Some(1))), 1);
+
+        // The coins given explicitly count too.
+        let outpoints = (0..3).map(|vout| OutPoint::new(txid, vout)).collect();
+        assert_eq!(
+            create(&CoinSelector::Outpoints(outpoints), Some(2)),
+            Err(CommandError::SpendCreation(
+                SpendCreationError::TooManyInputsRequired(3)
+            ))
         );
 
-        let ms = DummyLiana::new(btc, DummyDatabase::new());
+        ms.shutdown();
+    }
 
+    #[test]
+    fn create_spend_extra_inputs() {
+        let outpoint = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(
+            outpoint.txid,
+            (
+                bitcoin::Transaction {
+                    version: TxVersion::TWO,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![],
+                    output: vec![],
+                },
+                None,
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
         let control = &ms.control();
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint,
+            is_immature: false,
+            block_info: Some(BlockInfo { height: 1, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(0),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr.clone(), 120_000)].iter().cloned().collect();
+        // A P2WPKH coin of someone else.
+        let foreign = ForeignInput {
+            outpoint: bitcoin::OutPoint::from_str(
+                "617eab1fc0b03ee7f82ba70166725291783461f1a0e7975eaf8b5f8f674234f3:1",
+            )
+            .unwrap(),
+            witness_utxo: bitcoin::TxOut {
+                value: bitcoin::Amount::from_sat(50_000),
+                script_pubkey: dummy_addr.assume_checked().script_pubkey(),
+            },
+            max_sat_weight: 109,
+        };
+        let create = |extra_inputs: &[ForeignInput]| {
+            control.create_spend(
+                &destinations,
+                &CoinSelector::All,
+                2,
+                CreateSpendOptions {
+                    extra_inputs: extra_inputs.to_vec(),
+                    ..Default::default()
+                },
+            )
+        };
 
-        let transactions = control.list_transactions(&[tx1.txid()]).transactions;
-        assert_eq!(transactions.len(), 1);
-        assert_eq!(transactions[0].tx, tx1);
+        // Our coin alone can't fund the transaction.
+        assert!(matches!(
+            create(&[]).unwrap(),
+            CreateSpendResult::InsufficientFunds { .. }
+        ));
 
-        let transactions = control
-            .list_transactions(&[tx1.txid(), tx2.txid(), tx3.txid()])
-            .transactions;
-        assert_eq!(transactions.len(), 3);
+        // With the foreign input it can. It comes after ours, and is marked as foreign.
+        let (psbt, foreign_inputs) = match create(std::slice::from_ref(&foreign)).unwrap() {
+            CreateSpendResult::Success {
+                psbt,
+                foreign_inputs,
+                ..
+            } => (psbt, foreign_inputs),
+            _ => panic!("expect successful spend creation"),
+        };
+        assert_eq!(foreign_inputs, vec![1]);
+        assert_eq!(psbt.unsigned_tx.input[0].previous_output, outpoint);
+        assert_eq!(psbt.unsigned_tx.input[1].previous_output, foreign.outpoint);
+        assert_eq!(
+            psbt.inputs[1].witness_utxo,
+            Some(foreign.witness_utxo.clone())
+        );
+        assert!(psbt.inputs[1].bip32_derivation.is_empty());
+        assert!(!psbt.inputs[0].bip32_derivation.is_empty());
+
+        // The fee accounts for the size of the foreign input.
+        let max_sat_weight = control.config.main_descriptor.max_sat_weight() as u64;
+        let max_vbytes = (psbt.unsigned_tx.weight().to_wu() + max_sat_weight + 109).div_ceil(4);
+        let fee = psbt.fee().unwrap().to_sat();
+        assert!(fee >= 2 * max_vbytes, "{} < 2 * {}", fee, max_vbytes);
+        assert!(fee < 2 * max_vbytes + 10);
+
+        // Foreign inputs can't be ours, be given twice or have an insane satisfaction weight.
+        let ours = ForeignInput {
+            outpoint,
+            ..foreign.clone()
+        };
+        assert_eq!(
+            create(&[ours]),
+            Err(CommandError::InvalidForeignInput(outpoint))
+        );
+        assert_eq!(
+            create(&[foreign.clone(), foreign.clone()]),
+            Err(CommandError::InvalidForeignInput(foreign.outpoint))
+        );
+        let heavy = ForeignInput {
+            max_sat_weight: MAX_FOREIGN_INPUT_SAT_WEIGHT + 1,
+            ..foreign.clone()
+        };
+        assert_eq!(
+            create(&[heavy]),
+            Err(CommandError::InvalidForeignInput(foreign.outpoint))
+        );
 
-        let txs: Vec<Transaction> = transactions
-            .iter()
-            .map(|transaction| transaction.tx.clone())
-            .collect();
+        ms.shutdown();
+    }
 
-        assert!(txs.contains(&tx1));
-        assert!(txs.contains(&tx2));
-        assert!(txs.contains(&tx3));
+    #[test]
+    fn create_spend_anti_fee_sniping() {
+        let outpoint = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr, 50_000)].iter().cloned().collect();
+        // Create a Spend against a backend whose tip has this timestamp, and get its locktime.
+        let locktimes = |tip_time: Option<u32>| {
+            let mut dummy_bitcoind = DummyBitcoind::new();
+            dummy_bitcoind.tip_time = tip_time;
+            dummy_bitcoind.txs.insert(
+                outpoint.txid,
+                (
+                    bitcoin::Transaction {
+                        version: TxVersion::TWO,
+                        lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                        input: vec![],
+                        output: vec![],
+                    },
+                    None,
+                ),
+            );
+            let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+            let control = &ms.control();
+            control
+                .db()
+                .lock()
+                .unwrap()
+                .connection()
+                .new_unspent_coins(&[Coin {
+                    outpoint,
+                    is_immature: false,
+                    block_info: Some(BlockInfo { height: 1, time: 1 }),
+                    amount: bitcoin::Amount::from_sat(100_000),
+                    derivation_index: bip32::ChildNumber::from(0),
+                    is_change: false,
+                    spend_txid: None,
+                    spend_block: None,
+                }]);
+            let res = (0..20)
+                .map(|_| {
+                    match control
+                        .create_spend(
+                            &destinations,
+                            &CoinSelector::All,
+                            1,
+                            CreateSpendOptions::default(),
+                        )
+                        .unwrap()
+                    {
+                        CreateSpendResult::Success { psbt, locktime, .. } => {
+                            assert_eq!(psbt.unsigned_tx.lock_time.to_consensus_u32(), locktime);
+                            locktime
+                        }
+                        CreateSpendResult::InsufficientFunds { .. } => {
+                            panic!("expect successful spend creation")
+                        }
+                    }
+                })
+                .collect::<Vec<_>>();
+            ms.shutdown();
+            res
+        };
 
-        ms.shutdown();
+        // The locktime is set to the current height of 100, or to a height a bit before it.
+        let now = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32;
+        let res = locktimes(Some(now - 60));
+        assert!(res.iter().all(|l| (1..=100).contains(l)));
+        assert!(res.iter().filter(|l| **l == 100).count() > 1);
+
+        // Unless the tip is too old for us to be confident we are synced.
+        let too_old = now - MAX_ANTI_FEE_SNIPING_TIP_AGE.as_secs() as u32 - 60;
+        assert!(locktimes(Some(too_old)).iter().all(|l| *l == 0));
+        assert!(locktimes(None).iter().all(|l| *l == 0));
     }
 }
@@ -1,5 +1,7 @@
 use std::str::FromStr;
 
+use crate::spend::Feerate;
+
 use miniscript::bitcoin::{self, consensus, hashes::hex::FromHex};
 use serde::{de, Deserialize, Deserializer, Serializer};
 
@@ -20,6 +22,28 @@ pub fn ser_to_string<T: std::fmt::Display, S: Serializer>(
     s.serialize_str(&field.to_string())
 }
 
+/// Serialize a list of items as a list of strings.
+pub fn ser_to_string_vec<T: std::fmt::Display, S: Serializer>(
+    items: &[T],
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    s.collect_seq(items.iter().map(|item| item.to_string()))
+}
+
+/// Deserialize a list of items from a list of strings.
+pub fn deser_fromstr_vec<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    <T as FromStr>::Err: std::fmt::Display,
+{
+    let strings = Vec::<String>::deserialize(deserializer)?;
+    strings
+        .iter()
+        .map(|string| T::from_str(string).map_err(de::Error::custom))
+        .collect()
+}
+
 /// Deserialize an address from string, assuming the network was checked.
 pub fn deser_addr_assume_checked<'de, D>(deserializer: D) -> Result<bitcoin::Address, D::Error>
 where
@@ -31,6 +55,22 @@ where
         .map_err(de::Error::custom)
 }
 
+/// Deserialize an optional address from string, assuming the network was checked.
+pub fn deser_opt_addr_assume_checked<'de, D>(
+    deserializer: D,
+) -> Result<Option<bitcoin::Address>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|string| {
+            bitcoin::Address::from_str(&string)
+                .map(|addr| addr.assume_checked())
+                .map_err(de::Error::custom)
+        })
+        .transpose()
+}
+
 /// Serialize an amount as sats
 pub fn ser_amount<S: Serializer>(amount: &bitcoin::Amount, s: S) -> Result<S::Ok, S::Error> {
     s.serialize_u64(amount.to_sat())
@@ -45,6 +85,20 @@ where
     Ok(bitcoin::Amount::from_sat(a))
 }
 
+/// Serialize a feerate as a (possibly fractional) number of sats/vb.
+pub fn ser_feerate_vb<S: Serializer>(feerate: &Feerate, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_f64(feerate.to_sat_per_kvb() as f64 / 1_000.0)
+}
+
+/// Deserialize a feerate from a (possibly fractional) number of sats/vb.
+pub fn deser_feerate_vb<'de, D>(deserializer: D) -> Result<Feerate, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let f = f64::deserialize(deserializer)?;
+    Feerate::from_sat_per_vb_f64(f).ok_or_else(|| de::Error::custom("Invalid feerate"))
+}
+
 pub fn ser_hex<S, T>(t: T, s: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
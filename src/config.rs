@@ -2,7 +2,11 @@ use crate::descriptors::LianaDescriptor;
 
 use std::{fmt, net::SocketAddr, path::PathBuf, str::FromStr, time::Duration};
 
-use miniscript::bitcoin::Network;
+use miniscript::bitcoin::{
+    hashes::{hmac, sha256, Hash, HashEngine},
+    hex::{DisplayHex, FromHex},
+    Network,
+};
 
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
@@ -85,6 +89,64 @@ fn default_daemon() -> bool {
     false
 }
 
+fn default_anti_fee_sniping() -> bool {
+    true
+}
+
+fn default_fee_sources() -> Vec<FeeSource> {
+    vec![FeeSource::Backend]
+}
+
+fn is_default_fee_sources(sources: &[FeeSource]) -> bool {
+    sources == default_fee_sources().as_slice()
+}
+
+/// A source of feerate estimates.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FeeSource {
+    /// Ask the Bitcoin backend for an estimate (`estimatesmartfee` for bitcoind).
+    Backend,
+    /// Always use this feerate, in sats/vb.
+    Static { feerate: u64 },
+}
+
+/// The minimum depth, in number of confirmations, of the spending transaction of a coin for it to
+/// be pruned. This makes sure a pruned coin can't get unspent by a block chain reorganization.
+pub const MIN_PRUNING_DEPTH: u32 = 100;
+
+/// The highest index at which the automatic derivation of receive addresses may start. bitcoind
+/// only watches the first 1000 addresses of our descriptors until one of them is used, and we look
+/// 200 addresses ahead of the derivation index.
+pub const MAX_DERIVATION_START_INDEX: u32 = 800;
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+fn is_true(b: &bool) -> bool {
+    *b
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+fn is_default_spent_coin_retention(retention: &SpentCoinRetention) -> bool {
+    retention == &SpentCoinRetention::default()
+}
+
+/// How long to keep the coins of ours which were spent in database.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SpentCoinRetention {
+    /// Never remove spent coins from database.
+    #[default]
+    KeepAll,
+    /// Remove coins whose spending transaction has at least this number of confirmations.
+    Prune { confirmations: u32 },
+}
+
 /// RPC authentication options.
 #[derive(Clone, PartialEq, Serialize)]
 pub enum BitcoindRpcAuth {
@@ -105,6 +167,137 @@ impl fmt::Debug for BitcoindRpcAuth {
     }
 }
 
+/// The key used to encrypt the database at rest.
+#[derive(Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct DbEncryptionKey(pub String);
+
+impl fmt::Debug for DbEncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "REDACTED DATABASE ENCRYPTION KEY")
+    }
+}
+
+/// The minimum number of PBKDF2 iterations for hashing the session passphrase.
+pub const MIN_PASSPHRASE_ITERATIONS: u32 = 10_000;
+
+/// The minimum length of the salt for hashing the session passphrase, in bytes.
+pub const MIN_PASSPHRASE_SALT_LEN: usize = 16;
+
+/// A passphrase hashed using PBKDF2-HMAC-SHA256. It is written as
+/// `pbkdf2-sha256$<iterations>$<hex salt>$<hex hash>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassphraseHash {
+    pub iterations: u32,
+    pub salt: Vec<u8>,
+    pub hash: [u8; 32],
+}
+
+impl PassphraseHash {
+    /// Hash this passphrase with the given salt and number of iterations.
+    pub fn new(passphrase: &str, salt: Vec<u8>, iterations: u32) -> Self {
+        let hash = pbkdf2_sha256(passphrase.as_bytes(), &salt, iterations);
+        Self {
+            iterations,
+            salt,
+            hash,
+        }
+    }
+
+    /// Whether this is the hash of the given passphrase. The hashes are compared in constant time.
+    pub fn matches(&self, passphrase: &str) -> bool {
+        let hash = pbkdf2_sha256(passphrase.as_bytes(), &self.salt, self.iterations);
+        hash.iter()
+            .zip(self.hash.iter())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+    }
+}
+
+// PBKDF2 as per RFC 8018, for a single block of output.
+fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let keyed_engine = hmac::HmacEngine::<sha256::Hash>::new(password);
+    let mut engine = keyed_engine.clone();
+    engine.input(salt);
+    engine.input(&1u32.to_be_bytes());
+    let mut u = hmac::Hmac::from_engine(engine).to_byte_array();
+    let mut res = u;
+    for _ in 1..iterations {
+        let mut engine = keyed_engine.clone();
+        engine.input(&u);
+        u = hmac::Hmac::from_engine(engine).to_byte_array();
+        for (r, b) in res.iter_mut().zip(u.iter()) {
+            *r ^= b;
+        }
+    }
+    res
+}
+
+impl fmt::Display for PassphraseHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pbkdf2-sha256${}${}${}",
+            self.iterations,
+            self.salt.as_hex(),
+            self.hash.as_hex()
+        )
+    }
+}
+
+impl FromStr for PassphraseHash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('$');
+        if parts.next() != Some("pbkdf2-sha256") {
+            return Err("must be of the form 'pbkdf2-sha256$<iterations>$<salt>$<hash>'".into());
+        }
+        let (iterations, salt, hash) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(iterations), Some(salt), Some(hash)) if parts.next().is_none() => {
+                (iterations, salt, hash)
+            }
+            _ => {
+                return Err("must be of the form 'pbkdf2-sha256$<iterations>$<salt>$<hash>'".into())
+            }
+        };
+        let iterations: u32 = iterations
+            .parse()
+            .map_err(|e| format!("invalid number of iterations: {}", e))?;
+        if iterations < MIN_PASSPHRASE_ITERATIONS {
+            return Err(format!(
+                "the number of iterations must be at least {}",
+                MIN_PASSPHRASE_ITERATIONS
+            ));
+        }
+        let salt = Vec::from_hex(salt).map_err(|e| format!("invalid salt: {}", e))?;
+        if salt.len() < MIN_PASSPHRASE_SALT_LEN {
+            return Err(format!(
+                "the salt must be at least {} bytes long",
+                MIN_PASSPHRASE_SALT_LEN
+            ));
+        }
+        let hash = <[u8; 32]>::from_hex(hash).map_err(|e| format!("invalid hash: {}", e))?;
+        Ok(Self {
+            iterations,
+            salt,
+            hash,
+        })
+    }
+}
+
+impl Serialize for PassphraseHash {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        serialize_to_string(self, s)
+    }
+}
+
+impl<'de> Deserialize<'de> for PassphraseHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_fromstr(deserializer)
+    }
+}
+
 /// Everything we need to know for talking to bitcoind serenely
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct BitcoindConfig {
@@ -144,16 +337,74 @@ pub struct Config {
         default = "default_loglevel"
     )]
     pub log_level: log::LevelFilter,
+    /// An optional file to write the logs to, instead of the standard output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_file: Option<PathBuf>,
     /// The descriptor to use for sending/receiving coins
     #[serde(
         deserialize_with = "deserialize_fromstr",
         serialize_with = "serialize_to_string"
     )]
     pub main_descriptor: LianaDescriptor,
+    /// The number of confirmations a coin must have to be considered spendable. Coins with fewer
+    /// confirmations are only selected automatically if spending unconfirmed coins is allowed.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub spendable_min_confirmations: u32,
+    /// Whether to expose, next to the JSONRPC socket, a second socket on which only the commands
+    /// which don't modify the wallet's state are accepted.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub observer_socket: bool,
+    /// For how long to reuse the feerate estimates before querying the fee sources again. Zero
+    /// disables the caching.
+    #[serde(
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration",
+        default,
+        skip_serializing_if = "Duration::is_zero"
+    )]
+    pub feerate_cache_secs: Duration,
+    /// The feerate, in sats/vb, at which coin selection assumes a change output will be spent in
+    /// the future. Defaults to 10 sats/vb. The higher it is, the less change outputs are created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub long_term_feerate_vb: Option<u64>,
+    /// Whether to set the locktime of new transactions to the current block height, as Bitcoin
+    /// Core does to discourage fee sniping. This also makes our transactions look like theirs.
+    #[serde(default = "default_anti_fee_sniping", skip_serializing_if = "is_true")]
+    pub anti_fee_sniping: bool,
+    /// The maximum number of inputs of the Spend transactions we create, for instance to stay
+    /// within the limits of a signing device. Can be overridden for a single transaction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_spend_inputs: Option<usize>,
+    /// The derivation index at which the automatic generation of receive addresses starts. The
+    /// addresses at lower indexes are left for manual use. Coins received on them are still
+    /// detected.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub derivation_start_index: u32,
+    /// The PBKDF2 hash of a passphrase. If set, the wallet starts locked and the commands which
+    /// modify its state are rejected until it is unlocked with this passphrase.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_passphrase_hash: Option<PassphraseHash>,
+    /// Whether to also reject the commands which don't modify the wallet's state while it is
+    /// locked.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub lock_read_commands: bool,
     /// Settings for the Bitcoin interface
     pub bitcoin_config: BitcoinConfig,
     /// Settings specific to bitcoind as the Bitcoin interface
     pub bitcoind_config: Option<BitcoindConfig>,
+    /// The sources to query for feerate estimates, in order of preference
+    #[serde(
+        default = "default_fee_sources",
+        skip_serializing_if = "is_default_fee_sources"
+    )]
+    pub fee_sources: Vec<FeeSource>,
+    /// An optional key to encrypt the database with. Requires lianad to be built with the
+    /// `sqlcipher` feature.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub db_encryption_key: Option<DbEncryptionKey>,
+    /// Whether to remove the spent coins from database once they are buried deep enough.
+    #[serde(default, skip_serializing_if = "is_default_spent_coin_retention")]
+    pub spent_coin_retention: SpentCoinRetention,
 }
 
 impl Config {
@@ -267,6 +518,51 @@ impl Config {
             )));
         }
 
+        if self
+            .fee_sources
+            .iter()
+            .any(|source| matches!(source, FeeSource::Static { feerate: 0 }))
+        {
+            return Err(ConfigError::Unexpected(
+                "A static fee source must have a feerate of at least 1 sat/vb".to_string(),
+            ));
+        }
+
+        if self.long_term_feerate_vb == Some(0) {
+            return Err(ConfigError::Unexpected(
+                "The long-term feerate must be of at least 1 sat/vb".to_string(),
+            ));
+        }
+
+        if self.max_spend_inputs == Some(0) {
+            return Err(ConfigError::Unexpected(
+                "The maximum number of inputs of a Spend must be at least 1".to_string(),
+            ));
+        }
+
+        if self.derivation_start_index > MAX_DERIVATION_START_INDEX {
+            return Err(ConfigError::Unexpected(format!(
+                "The derivation start index must be at most {}",
+                MAX_DERIVATION_START_INDEX
+            )));
+        }
+
+        if self.db_encryption_key.is_some() && !cfg!(feature = "sqlcipher") {
+            return Err(ConfigError::Unexpected(
+                "A database encryption key is set but lianad was not built with the 'sqlcipher' feature"
+                    .to_string(),
+            ));
+        }
+
+        if let SpentCoinRetention::Prune { confirmations } = self.spent_coin_retention {
+            if confirmations < MIN_PRUNING_DEPTH {
+                return Err(ConfigError::Unexpected(format!(
+                    "Spent coins can only be pruned after at least {} confirmations",
+                    MIN_PRUNING_DEPTH
+                )));
+            }
+        }
+
         // TODO: check the semantics of the main descriptor
 
         Ok(())
@@ -277,7 +573,51 @@ impl Config {
 mod tests {
     use std::path::PathBuf;
 
-    use super::{config_file_path, BitcoindConfig, BitcoindRpcAuth, Config};
+    use std::str::FromStr;
+
+    use miniscript::bitcoin::hex::FromHex;
+
+    use super::{
+        config_file_path, pbkdf2_sha256, BitcoindConfig, BitcoindRpcAuth, Config, FeeSource,
+        PassphraseHash, SpentCoinRetention,
+    };
+
+    #[test]
+    fn passphrase_hash() {
+        // Test vectors from RFC 7914.
+        assert_eq!(
+            pbkdf2_sha256(b"password", b"salt", 2),
+            <[u8; 32]>::from_hex(
+                "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            pbkdf2_sha256(b"password", b"salt", 4096),
+            <[u8; 32]>::from_hex(
+                "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a"
+            )
+            .unwrap()
+        );
+
+        let hash = PassphraseHash::new("test", vec![0x42; 16], 10_000);
+        assert!(hash.matches("test"));
+        assert!(!hash.matches("test "));
+        assert_eq!(PassphraseHash::from_str(&hash.to_string()), Ok(hash));
+
+        // Too few iterations, too short a salt, or not a PBKDF2 hash at all.
+        let salt = "00112233445566778899aabbccddeeff";
+        let digest = "688ab710ba72f6bed5659d83d6865f4ea11c7d524c0051cb01d92e5e3e1c355f";
+        assert!(
+            PassphraseHash::from_str(&format!("pbkdf2-sha256$10000${}${}", salt, digest)).is_ok()
+        );
+        assert!(
+            PassphraseHash::from_str(&format!("pbkdf2-sha256$9999${}${}", salt, digest)).is_err()
+        );
+        assert!(PassphraseHash::from_str(&format!("pbkdf2-sha256$10000$0011${}", digest)).is_err());
+        assert!(PassphraseHash::from_str(&format!("pbkdf2-sha256$10000${}", salt)).is_err());
+        assert!(PassphraseHash::from_str(digest).is_err());
+    }
 
     // Test the format of the configuration file
     #[test]
@@ -359,6 +699,72 @@ mod tests {
         #[cfg(unix)] // On non-UNIX there is no 'daemon' member.
         assert_eq!(toml_str, serialized);
 
+        // A valid, round-tripping, config with custom fee sources
+        let toml_str = r#"
+            data_dir = '/home/wizardsardine/custom/folder/'
+            daemon = false
+            log_level = 'TRACE'
+            log_file = '/home/wizardsardine/lianad.log'
+            main_descriptor = 'wsh(andor(pk([aabbccdd]tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr/<0;1>/*),older(10000),pk([aabbccdd]tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/<0;1>/*)))#dw4ulnrs'
+            spendable_min_confirmations = 6
+            observer_socket = true
+            feerate_cache_secs = 30
+            long_term_feerate_vb = 25
+            anti_fee_sniping = false
+            max_spend_inputs = 50
+            derivation_start_index = 100
+            session_passphrase_hash = 'pbkdf2-sha256$10000$00112233445566778899aabbccddeeff$688ab710ba72f6bed5659d83d6865f4ea11c7d524c0051cb01d92e5e3e1c355f'
+            lock_read_commands = true
+
+            [bitcoin_config]
+            network = 'bitcoin'
+            poll_interval_secs = 18
+
+            [bitcoind_config]
+            cookie_path = '/home/user/.bitcoin/.cookie'
+            addr = '127.0.0.1:8332'
+
+            [[fee_sources]]
+            kind = 'backend'
+
+            [[fee_sources]]
+            kind = 'static'
+            feerate = 5
+
+            [spent_coin_retention]
+            kind = 'prune'
+            confirmations = 1000
+            "#.trim_start().replace("            ", "");
+        let parsed = toml::from_str::<Config>(&toml_str).expect("Deserializing toml_str");
+        assert_eq!(
+            parsed.fee_sources,
+            vec![FeeSource::Backend, FeeSource::Static { feerate: 5 }]
+        );
+        assert_eq!(
+            parsed.log_file,
+            Some(PathBuf::from("/home/wizardsardine/lianad.log"))
+        );
+        assert_eq!(
+            parsed.spent_coin_retention,
+            SpentCoinRetention::Prune {
+                confirmations: 1000
+            }
+        );
+        assert_eq!(parsed.spendable_min_confirmations, 6);
+        assert!(parsed.observer_socket);
+        assert_eq!(parsed.feerate_cache_secs.as_secs(), 30);
+        assert_eq!(parsed.long_term_feerate_vb, Some(25));
+        assert!(!parsed.anti_fee_sniping);
+        assert_eq!(parsed.max_spend_inputs, Some(50));
+        assert_eq!(parsed.derivation_start_index, 100);
+        let passphrase_hash = parsed.session_passphrase_hash.as_ref().unwrap();
+        assert!(passphrase_hash.matches("test"));
+        assert!(!passphrase_hash.matches("tset"));
+        assert!(parsed.lock_read_commands);
+        let serialized = toml::to_string_pretty(&parsed).expect("Serializing to toml");
+        #[cfg(unix)] // On non-UNIX there is no 'daemon' member.
+        assert_eq!(toml_str, serialized);
+
         // Invalid desc checksum
         let toml_str = r#"
             daemon = false
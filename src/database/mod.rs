@@ -6,6 +6,7 @@ pub mod sqlite;
 
 use crate::{
     bitcoin::BlockChainTip,
+    config::DbEncryptionKey,
     database::sqlite::{
         schema::{DbBlockInfo, DbCoin, DbTip},
         SqliteConn, SqliteDb,
@@ -13,14 +14,15 @@ use crate::{
 };
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fmt::Display,
     iter::FromIterator,
+    path,
     str::FromStr,
     sync,
 };
 
-use miniscript::bitcoin::{self, bip32, psbt::Psbt, secp256k1};
+use miniscript::bitcoin::{self, address, bip32, psbt::Psbt, secp256k1};
 
 pub trait DatabaseInterface: Send {
     fn connection(&self) -> Box<dyn DatabaseConnection>;
@@ -118,6 +120,10 @@ pub trait DatabaseConnection {
     /// Mark a set of coins as spent by a specified txid at a specified block time.
     fn confirm_spend(&mut self, outpoints: &[(bitcoin::OutPoint, bitcoin::Txid, i32, u32)]);
 
+    /// Remove the coins whose spending transaction was confirmed at or below this height. Returns
+    /// the number of coins removed.
+    fn prune_spent_coins(&mut self, max_spend_height: i32) -> usize;
+
     /// Get specific coins from the database.
     fn coins_by_outpoints(
         &mut self,
@@ -141,11 +147,86 @@ pub trait DatabaseConnection {
 
     fn labels(&mut self, labels: &HashSet<LabelItem>) -> HashMap<String, String>;
 
+    /// Retrieve a page of all the labels stored in database, ordered by insertion.
+    fn labels_page(&mut self, offset: u64, limit: u64) -> Vec<(LabelItem, String)>;
+
+    /// Atomically delete all the labels and store these ones instead. Returns the number of
+    /// labels deleted.
+    fn replace_labels(&mut self, labels: &HashMap<LabelItem, String>) -> usize;
+
     /// Mark the given tip as the new best seen block. Update stored data accordingly.
     fn rollback_tip(&mut self, new_tip: &BlockChainTip);
 
     /// Retrieve a limited list of txids that where deposited or spent between the start and end timestamps (inclusive bounds)
     fn list_txids(&mut self, start: u32, end: u32, limit: u64) -> Vec<bitcoin::Txid>;
+
+    /// Retrieve a page of the txids that where deposited or spent between the start and end
+    /// timestamps (inclusive bounds), most recent first.
+    fn list_txids_page(
+        &mut self,
+        start: u32,
+        end: u32,
+        offset: u64,
+        limit: u64,
+    ) -> Vec<bitcoin::Txid>;
+
+    /// Record that these transactions were replaced by the transaction with txid `replaced_by`.
+    fn record_replacements(&mut self, txids: &[bitcoin::Txid], replaced_by: &bitcoin::Txid);
+
+    /// Get the txid of the transaction that replaced each of these transactions, if any.
+    fn replaced_by(&mut self, txids: &[bitcoin::Txid]) -> HashMap<bitcoin::Txid, bitcoin::Txid>;
+
+    /// Record that a replacement with txid `replacement_txid` was drafted for this transaction.
+    fn record_replacement_draft(&mut self, txid: &bitcoin::Txid, replacement_txid: &bitcoin::Txid);
+
+    /// Get the txids of the replacements drafted for this transaction whose Spend wasn't deleted.
+    fn replacement_drafts(&mut self, txid: &bitcoin::Txid) -> Vec<bitcoin::Txid>;
+
+    /// Record these unconfirmed coins whose deposit transaction was dropped from the mempool at
+    /// this height. This does not remove them from our coins. If a coin was already recorded, it
+    /// is marked as not invalidated anymore and its drop height is updated.
+    fn record_dropped_coins(&mut self, coins: &[Coin], height: i32);
+
+    /// Forget about these dropped coins, whose deposit transaction doesn't need to be watched
+    /// anymore.
+    fn forget_dropped_coins(&mut self, outpoints: &[bitcoin::OutPoint]);
+
+    /// Mark these dropped coins as invalidated: their deposit transaction was double spent by a
+    /// confirmed transaction.
+    fn invalidate_coins(&mut self, outpoints: &[bitcoin::OutPoint]);
+
+    /// Get all the dropped coins, invalidated or not, in the order they were dropped.
+    fn dropped_coins(&mut self) -> Vec<DroppedCoin>;
+
+    /// Get all the addresses stored in the derivation index cache, by increasing derivation index.
+    fn stored_addresses(&mut self) -> Vec<StoredAddresses>;
+
+    /// Set the note on the coin at this outpoint, or remove it if `None`.
+    fn set_coin_note(&mut self, outpoint: &bitcoin::OutPoint, note: Option<&str>);
+
+    /// Get the notes of the coins at these outpoints. If no outpoint is given, get all the notes.
+    fn coin_notes(&mut self, outpoints: &[bitcoin::OutPoint])
+        -> HashMap<bitcoin::OutPoint, String>;
+
+    /// Store these feerate samples.
+    fn store_feerate_samples(&mut self, samples: &[FeerateSample]);
+
+    /// Get the feerate samples taken at or after this timestamp, by increasing timestamp and
+    /// confirmation target.
+    fn feerate_samples(&mut self, since: u32) -> Vec<FeerateSample>;
+
+    /// Write a copy of the database encrypted with this key at the given path.
+    fn export_encrypted(
+        &mut self,
+        dest_path: &path::Path,
+        encryption_key: &DbEncryptionKey,
+    ) -> Result<(), String>;
+
+    /// Get the number of rows in each table and the size of the database.
+    fn stats(&mut self) -> DatabaseStats;
+
+    /// Rebuild the database to reclaim the unused space.
+    fn vacuum(&mut self) -> Result<(), String>;
 }
 
 impl DatabaseConnection for SqliteConn {
@@ -250,6 +331,10 @@ impl DatabaseConnection for SqliteConn {
         self.confirm_spend(outpoints)
     }
 
+    fn prune_spent_coins(&mut self, max_spend_height: i32) -> usize {
+        self.prune_spent_coins(max_spend_height)
+    }
+
     fn derivation_index_by_address(
         &mut self,
         address: &bitcoin::Address,
@@ -303,6 +388,22 @@ impl DatabaseConnection for SqliteConn {
         HashMap::from_iter(labels.into_iter().map(|label| (label.item, label.value)))
     }
 
+    fn replace_labels(&mut self, labels: &HashMap<LabelItem, String>) -> usize {
+        self.replace_labels(labels)
+    }
+
+    fn labels_page(&mut self, offset: u64, limit: u64) -> Vec<(LabelItem, String)> {
+        let network = self.db_tip().network;
+        self.db_labels_page(offset, limit)
+            .into_iter()
+            .map(|label| {
+                let item = LabelItem::from_str(&label.item, network)
+                    .expect("We only store valid label items");
+                (item, label.value)
+            })
+            .collect()
+    }
+
     fn rollback_tip(&mut self, new_tip: &BlockChainTip) {
         self.rollback_tip(new_tip)
     }
@@ -310,6 +411,95 @@ impl DatabaseConnection for SqliteConn {
     fn list_txids(&mut self, start: u32, end: u32, limit: u64) -> Vec<bitcoin::Txid> {
         self.db_list_txids(start, end, limit)
     }
+
+    fn list_txids_page(
+        &mut self,
+        start: u32,
+        end: u32,
+        offset: u64,
+        limit: u64,
+    ) -> Vec<bitcoin::Txid> {
+        self.db_list_txids_page(start, end, offset, limit)
+    }
+
+    fn record_replacements(&mut self, txids: &[bitcoin::Txid], replaced_by: &bitcoin::Txid) {
+        self.record_replacements(txids, replaced_by)
+    }
+
+    fn replaced_by(&mut self, txids: &[bitcoin::Txid]) -> HashMap<bitcoin::Txid, bitcoin::Txid> {
+        self.db_replaced_by(txids)
+    }
+
+    fn record_replacement_draft(&mut self, txid: &bitcoin::Txid, replacement_txid: &bitcoin::Txid) {
+        self.record_replacement_draft(txid, replacement_txid)
+    }
+
+    fn replacement_drafts(&mut self, txid: &bitcoin::Txid) -> Vec<bitcoin::Txid> {
+        self.db_replacement_drafts(txid)
+    }
+
+    fn record_dropped_coins(&mut self, coins: &[Coin], height: i32) {
+        self.record_dropped_coins(coins, height)
+    }
+
+    fn forget_dropped_coins(&mut self, outpoints: &[bitcoin::OutPoint]) {
+        self.forget_dropped_coins(outpoints)
+    }
+
+    fn invalidate_coins(&mut self, outpoints: &[bitcoin::OutPoint]) {
+        self.invalidate_coins(outpoints)
+    }
+
+    fn dropped_coins(&mut self) -> Vec<DroppedCoin> {
+        self.db_dropped_coins()
+    }
+
+    fn stored_addresses(&mut self) -> Vec<StoredAddresses> {
+        self.db_addresses()
+            .into_iter()
+            .map(|db_addr| StoredAddresses {
+                derivation_index: db_addr.derivation_index,
+                receive_address: db_addr.receive_address,
+                change_address: db_addr.change_address,
+            })
+            .collect()
+    }
+
+    fn set_coin_note(&mut self, outpoint: &bitcoin::OutPoint, note: Option<&str>) {
+        self.set_coin_note(outpoint, note)
+    }
+
+    fn coin_notes(
+        &mut self,
+        outpoints: &[bitcoin::OutPoint],
+    ) -> HashMap<bitcoin::OutPoint, String> {
+        self.db_coin_notes(outpoints)
+    }
+
+    fn store_feerate_samples(&mut self, samples: &[FeerateSample]) {
+        self.store_feerate_samples(samples)
+    }
+
+    fn feerate_samples(&mut self, since: u32) -> Vec<FeerateSample> {
+        self.db_feerate_samples(since)
+    }
+
+    fn export_encrypted(
+        &mut self,
+        dest_path: &path::Path,
+        encryption_key: &DbEncryptionKey,
+    ) -> Result<(), String> {
+        self.export_encrypted(dest_path, encryption_key)
+            .map_err(|e| e.to_string())
+    }
+
+    fn stats(&mut self) -> DatabaseStats {
+        self.db_stats()
+    }
+
+    fn vacuum(&mut self) -> Result<(), String> {
+        self.vacuum().map_err(|e| e.to_string())
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -375,6 +565,50 @@ impl Coin {
     }
 }
 
+/// An unconfirmed coin whose deposit transaction was dropped from the mempool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DroppedCoin {
+    pub outpoint: bitcoin::OutPoint,
+    pub amount: bitcoin::Amount,
+    pub derivation_index: bip32::ChildNumber,
+    pub is_change: bool,
+    /// Height of our tip when the deposit transaction was dropped.
+    pub dropped_height: i32,
+    /// Timestamp at which we detected the deposit transaction was double spent by a confirmed
+    /// transaction, if it was.
+    pub invalidated_at: Option<u32>,
+}
+
+/// A feerate estimate of the Bitcoin backend, as sampled by the poller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeerateSample {
+    /// Timestamp at which the estimate was obtained.
+    pub timestamp: u32,
+    /// The confirmation target of the estimate, in blocks.
+    pub conf_target: u16,
+    /// The estimated feerate, in sats/vb.
+    pub feerate: u64,
+}
+
+/// The receive and change addresses stored for a derivation index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredAddresses {
+    pub derivation_index: bip32::ChildNumber,
+    pub receive_address: bitcoin::Address<address::NetworkUnchecked>,
+    pub change_address: bitcoin::Address<address::NetworkUnchecked>,
+}
+
+/// The size of the database and of its tables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatabaseStats {
+    /// Number of rows in each table, by table name.
+    pub table_rows: BTreeMap<String, u64>,
+    /// Size of the database, in bytes.
+    pub size: u64,
+    /// Size of the unused pages of the database, which a vacuum would reclaim, in bytes.
+    pub free_size: u64,
+}
+
 /// Possible (mutually exclusive) status of a coin.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum CoinStatus {
@@ -12,6 +12,7 @@ mod utils;
 
 use crate::{
     bitcoin::BlockChainTip,
+    config::DbEncryptionKey,
     database::{
         sqlite::{
             schema::{
@@ -20,10 +21,10 @@ use crate::{
             },
             utils::{
                 create_fresh_db, curr_timestamp, db_exec, db_query, db_tx_query, db_version,
-                maybe_apply_migration, LOOK_AHEAD_LIMIT,
+                maybe_apply_migration, open_connection, LOOK_AHEAD_LIMIT,
             },
         },
-        Coin, CoinStatus, LabelItem,
+        Coin, CoinStatus, DatabaseStats, DroppedCoin, FeerateSample, LabelItem,
     },
     descriptors::LianaDescriptor,
 };
@@ -43,7 +44,7 @@ use miniscript::bitcoin::{
     secp256k1,
 };
 
-const DB_VERSION: i64 = 4;
+const DB_VERSION: i64 = 10;
 
 #[derive(Debug)]
 pub enum SqliteDbError {
@@ -52,6 +53,8 @@ pub enum SqliteDbError {
     UnsupportedVersion(i64),
     InvalidNetwork(bitcoin::Network),
     DescriptorMismatch(Box<LianaDescriptor>),
+    Decryption,
+    Encryption(String),
     Rusqlite(rusqlite::Error),
 }
 
@@ -73,6 +76,11 @@ impl std::fmt::Display for SqliteDbError {
             SqliteDbError::DescriptorMismatch(desc) => {
                 write!(f, "Database descriptor mismatch: '{}'.", desc)
             }
+            SqliteDbError::Decryption => write!(
+                f,
+                "Could not read the database. Is the database encryption key correct?"
+            ),
+            SqliteDbError::Encryption(e) => write!(f, "Error encrypting the database: '{}'", e),
             SqliteDbError::Rusqlite(e) => write!(f, "SQLite error: '{}'", e),
         }
     }
@@ -116,6 +124,7 @@ pub struct FreshDbOptions {
     pub(self) main_descriptor: LianaDescriptor,
     pub(self) schema: &'static str,
     pub(self) version: i64,
+    pub(self) timestamp: Option<u32>,
 }
 
 impl FreshDbOptions {
@@ -128,26 +137,37 @@ impl FreshDbOptions {
             main_descriptor,
             schema: SCHEMA,
             version: DB_VERSION,
+            timestamp: None,
         }
     }
+
+    /// Set the wallet creation time to this timestamp instead of the current time. This is useful
+    /// when restoring an existing wallet.
+    pub fn with_timestamp(mut self, timestamp: u32) -> FreshDbOptions {
+        self.timestamp = Some(timestamp);
+        self
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct SqliteDb {
     db_path: path::PathBuf,
+    encryption_key: Option<DbEncryptionKey>,
 }
 
 impl SqliteDb {
     /// Instanciate an SQLite database either from an existing database file or by creating a fresh
-    /// one.
+    /// one. If an encryption key is given the database is encrypted with it, which requires to be
+    /// built with the `sqlcipher` feature.
     pub fn new(
         db_path: path::PathBuf,
         fresh_options: Option<FreshDbOptions>,
         secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+        encryption_key: Option<DbEncryptionKey>,
     ) -> Result<SqliteDb, SqliteDbError> {
         // Create the database if needed, and make sure the db file exists.
         if let Some(options) = fresh_options {
-            create_fresh_db(&db_path, options, secp)?;
+            create_fresh_db(&db_path, options, secp, encryption_key.as_ref())?;
             log::info!("Created a fresh database at {}.", db_path.display());
         }
         if !db_path.exists() {
@@ -155,14 +175,17 @@ impl SqliteDb {
         }
 
         log::info!("Checking if the database needs upgrading.");
-        maybe_apply_migration(&db_path)?;
+        maybe_apply_migration(&db_path, encryption_key.as_ref())?;
 
-        Ok(SqliteDb { db_path })
+        Ok(SqliteDb {
+            db_path,
+            encryption_key,
+        })
     }
 
     /// Get a new connection to the database.
     pub fn connection(&self) -> Result<SqliteConn, SqliteDbError> {
-        let conn = rusqlite::Connection::open(&self.db_path)?;
+        let conn = open_connection(&self.db_path, self.encryption_key.as_ref())?;
         conn.busy_timeout(std::time::Duration::from_secs(60))?;
         Ok(SqliteConn { conn })
     }
@@ -197,6 +220,38 @@ impl SqliteDb {
 
         Ok(())
     }
+
+    /// Perform the startup sanity checks against an existing database without modifying it. No
+    /// migration is applied: if the database needs to be upgraded its content is not checked.
+    pub fn check_existing(
+        db_path: path::PathBuf,
+        encryption_key: Option<DbEncryptionKey>,
+        bitcoind_network: bitcoin::Network,
+        main_descriptor: &LianaDescriptor,
+    ) -> Result<(), SqliteDbError> {
+        if !db_path.exists() {
+            return Err(SqliteDbError::FileNotFound(db_path));
+        }
+
+        let mut conn = open_connection(&db_path, encryption_key.as_ref())?;
+        let db_version = db_version(&mut conn)?;
+        if db_version > DB_VERSION {
+            return Err(SqliteDbError::UnsupportedVersion(db_version));
+        }
+        if db_version < DB_VERSION {
+            log::info!(
+                "Database at version {} would be upgraded at startup. Not checking its content.",
+                db_version
+            );
+            return Ok(());
+        }
+
+        SqliteDb {
+            db_path,
+            encryption_key,
+        }
+        .sanity_check(bitcoind_network, main_descriptor)
+    }
 }
 
 // We only support single wallet. The id of the wallet row is always 1.
@@ -207,6 +262,81 @@ pub struct SqliteConn {
 }
 
 impl SqliteConn {
+    /// Write an encrypted copy of this database at the given path. Requires to be built with the
+    /// `sqlcipher` feature.
+    pub fn export_encrypted(
+        &mut self,
+        dest_path: &path::Path,
+        encryption_key: &DbEncryptionKey,
+    ) -> Result<(), SqliteDbError> {
+        if !cfg!(feature = "sqlcipher") {
+            return Err(SqliteDbError::Encryption(
+                "lianad was not built with the 'sqlcipher' feature".to_string(),
+            ));
+        }
+        if dest_path.exists() {
+            return Err(SqliteDbError::Encryption(format!(
+                "'{}' already exists",
+                dest_path.display()
+            )));
+        }
+        let dest_str = dest_path
+            .to_str()
+            .ok_or_else(|| SqliteDbError::Encryption("Invalid destination path".to_string()))?;
+
+        self.conn.execute(
+            "ATTACH DATABASE ?1 AS encrypted KEY ?2",
+            rusqlite::params![dest_str, encryption_key.0],
+        )?;
+        let res = self
+            .conn
+            .query_row("SELECT sqlcipher_export('encrypted')", [], |_| Ok(()))
+            .map_err(SqliteDbError::from);
+        self.conn.execute("DETACH DATABASE encrypted", [])?;
+        res
+    }
+
+    /// Get the number of rows in each table and the size of the database.
+    pub fn db_stats(&mut self) -> DatabaseStats {
+        let tables: Vec<String> = db_query(
+            &mut self.conn,
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+            rusqlite::params![],
+            |row| row.get(0),
+        )
+        .expect("Db must not fail");
+        let table_rows = tables
+            .into_iter()
+            .map(|table| {
+                // The table names come from the schema, not from the user.
+                let count: i64 = self
+                    .conn
+                    .query_row(&format!("SELECT COUNT(*) FROM \"{}\"", table), [], |row| {
+                        row.get(0)
+                    })
+                    .expect("Db must not fail");
+                (table, count as u64)
+            })
+            .collect();
+        let pragma = |conn: &rusqlite::Connection, name: &str| -> u64 {
+            conn.query_row(&format!("PRAGMA {}", name), [], |row| row.get::<_, i64>(0))
+                .expect("Db must not fail") as u64
+        };
+        let page_size = pragma(&self.conn, "page_size");
+        DatabaseStats {
+            table_rows,
+            size: pragma(&self.conn, "page_count") * page_size,
+            free_size: pragma(&self.conn, "freelist_count") * page_size,
+        }
+    }
+
+    /// Rebuild the database file to reclaim the unused space. This can't happen within a
+    /// transaction and may fail if another connection is writing to the database.
+    pub fn vacuum(&mut self) -> Result<(), SqliteDbError> {
+        self.conn.execute_batch("VACUUM")?;
+        Ok(())
+    }
+
     pub fn db_version(&mut self) -> i64 {
         db_version(&mut self.conn).expect("db must not fail")
     }
@@ -555,6 +685,21 @@ impl SqliteConn {
         .expect("Database must be available")
     }
 
+    /// Remove the coins whose spending transaction was confirmed at or below this height. Returns
+    /// the number of coins removed.
+    pub fn prune_spent_coins(&mut self, max_spend_height: i32) -> usize {
+        let mut pruned = 0;
+        db_exec(&mut self.conn, |db_tx| {
+            pruned = db_tx.execute(
+                "DELETE FROM coins WHERE spend_block_height IS NOT NULL AND spend_block_height <= ?1",
+                rusqlite::params![max_spend_height],
+            )?;
+            Ok(())
+        })
+        .expect("Database must be available");
+        pruned
+    }
+
     pub fn db_address(&mut self, address: &bitcoin::Address) -> Option<DbAddress> {
         db_query(
             &mut self.conn,
@@ -566,6 +711,17 @@ impl SqliteConn {
         .pop()
     }
 
+    /// Get all the entries of the addresses cache, by increasing derivation index.
+    pub fn db_addresses(&mut self) -> Vec<DbAddress> {
+        db_query(
+            &mut self.conn,
+            "SELECT * FROM addresses ORDER BY derivation_index",
+            rusqlite::params![],
+            |row| row.try_into(),
+        )
+        .expect("Db must not fail")
+    }
+
     pub fn db_coins(&mut self, outpoints: &[bitcoin::OutPoint]) -> Vec<DbCoin> {
         self.coins(&[], outpoints)
     }
@@ -635,6 +791,30 @@ impl SqliteConn {
         .expect("Db must not fail")
     }
 
+    /// Delete all the labels of this wallet and insert the given ones instead, within a single
+    /// transaction. Returns the number of labels deleted.
+    pub fn replace_labels(&mut self, labels: &HashMap<LabelItem, String>) -> usize {
+        let mut deleted = 0;
+        db_exec(&mut self.conn, |db_tx| {
+            deleted =
+                db_tx.execute("DELETE FROM labels WHERE wallet_id = ?1", rusqlite::params![WALLET_ID])?;
+            for (item, value) in labels {
+                let kind = match item {
+                    LabelItem::Address(_) => DbLabelledKind::Address,
+                    LabelItem::Txid(_) => DbLabelledKind::Txid,
+                    LabelItem::OutPoint(_) => DbLabelledKind::OutPoint,
+                };
+                db_tx.execute(
+                    "INSERT INTO labels (wallet_id, item, item_kind, value) VALUES (?1, ?2, ?3, ?4)",
+                    rusqlite::params![WALLET_ID, item.to_string(), kind as i64, value],
+                )?;
+            }
+            Ok(())
+        })
+        .expect("Db must not fail");
+        deleted
+    }
+
     pub fn db_labels(&mut self, items: &HashSet<LabelItem>) -> Vec<DbLabel> {
         let query = format!(
             "SELECT * FROM labels where item in ({})",
@@ -650,28 +830,121 @@ impl SqliteConn {
         .expect("Db must not fail")
     }
 
+    /// Retrieves a page of all the labels for this wallet, ordered by insertion.
+    pub fn db_labels_page(&mut self, offset: u64, limit: u64) -> Vec<DbLabel> {
+        db_query(
+            &mut self.conn,
+            "SELECT * FROM labels WHERE wallet_id = ?1 ORDER BY id LIMIT ?2 OFFSET ?3",
+            rusqlite::params![WALLET_ID, limit, offset],
+            |row| row.try_into(),
+        )
+        .expect("Db must not fail")
+    }
+
     /// Retrieves a limited and ordered list of transactions ids that happened during the given
     /// range.
     pub fn db_list_txids(&mut self, start: u32, end: u32, limit: u64) -> Vec<bitcoin::Txid> {
+        self.db_list_txids_page(start, end, 0, limit)
+    }
+
+    /// Retrieves a page of the ordered list of transactions ids that happened during the given
+    /// range. Transactions are sorted by decreasing block time, then by txid.
+    pub fn db_list_txids_page(
+        &mut self,
+        start: u32,
+        end: u32,
+        offset: u64,
+        limit: u64,
+    ) -> Vec<bitcoin::Txid> {
         db_query(
             &mut self.conn,
-            "SELECT DISTINCT(txid) FROM ( \
-                SELECT * from ( \
-                    SELECT txid, blocktime AS date FROM coins \
-                    WHERE blocktime >= (?1) \
-                    AND blocktime <= (?2) \
-                    ORDER BY blocktime \
-                ) \
+            "SELECT txid FROM ( \
+                SELECT txid, blocktime AS date FROM coins \
+                WHERE blocktime >= (?1) \
+                AND blocktime <= (?2) \
                 UNION \
-                SELECT * FROM (
-                    SELECT spend_txid AS txid, spend_block_time AS date FROM coins \
-                    WHERE spend_block_time >= (?1) \
-                    AND spend_block_time <= (?2) \
-                    ORDER BY spend_block_time \
-                ) \
-                ORDER BY date DESC LIMIT (?3) \
-            )",
-            rusqlite::params![start, end, limit],
+                SELECT spend_txid AS txid, spend_block_time AS date FROM coins \
+                WHERE spend_block_time >= (?1) \
+                AND spend_block_time <= (?2) \
+            ) \
+            ORDER BY date DESC, txid LIMIT (?3) OFFSET (?4)",
+            rusqlite::params![start, end, limit, offset],
+            |row| {
+                let txid: Vec<u8> = row.get(0)?;
+                let txid: bitcoin::Txid =
+                    encode::deserialize(&txid).expect("We only store valid txids");
+                Ok(txid)
+            },
+        )
+        .expect("Db must not fail")
+    }
+
+    /// Record that the transactions with the given txids were replaced by the transaction with
+    /// txid `replaced_by`. Overwrites any previously recorded replacement.
+    pub fn record_replacements(&mut self, txids: &[bitcoin::Txid], replaced_by: &bitcoin::Txid) {
+        db_exec(&mut self.conn, |db_tx| {
+            for txid in txids {
+                db_tx.execute(
+                    "INSERT INTO replaced_transactions (txid, replaced_by) VALUES (?1, ?2) \
+                    ON CONFLICT DO UPDATE SET replaced_by=excluded.replaced_by",
+                    rusqlite::params![txid[..].to_vec(), replaced_by[..].to_vec()],
+                )?;
+            }
+            Ok(())
+        })
+        .expect("Db must not fail");
+    }
+
+    /// Get the txid of the transaction replacing each of the given transactions, if any.
+    pub fn db_replaced_by(
+        &mut self,
+        txids: &[bitcoin::Txid],
+    ) -> HashMap<bitcoin::Txid, bitcoin::Txid> {
+        let mut replacements = HashMap::with_capacity(txids.len());
+        for txid in txids {
+            let replaced_by = db_query(
+                &mut self.conn,
+                "SELECT replaced_by FROM replaced_transactions WHERE txid = ?1",
+                rusqlite::params![txid[..].to_vec()],
+                |row| {
+                    let txid: Vec<u8> = row.get(0)?;
+                    let txid: bitcoin::Txid =
+                        encode::deserialize(&txid).expect("We only store valid txids");
+                    Ok(txid)
+                },
+            )
+            .expect("Db must not fail")
+            .pop();
+            if let Some(replaced_by) = replaced_by {
+                replacements.insert(*txid, replaced_by);
+            }
+        }
+        replacements
+    }
+
+    /// Record that a replacement was drafted for this transaction.
+    pub fn record_replacement_draft(
+        &mut self,
+        txid: &bitcoin::Txid,
+        replacement_txid: &bitcoin::Txid,
+    ) {
+        db_exec(&mut self.conn, |db_tx| {
+            db_tx.execute(
+                "INSERT INTO replacement_drafts (txid, replacement_txid) VALUES (?1, ?2) \
+                ON CONFLICT DO NOTHING",
+                rusqlite::params![txid[..].to_vec(), replacement_txid[..].to_vec()],
+            )?;
+            Ok(())
+        })
+        .expect("Db must not fail");
+    }
+
+    /// Get the txids of the replacements drafted for this transaction.
+    pub fn db_replacement_drafts(&mut self, txid: &bitcoin::Txid) -> Vec<bitcoin::Txid> {
+        db_query(
+            &mut self.conn,
+            "SELECT replacement_txid FROM replacement_drafts WHERE txid = ?1",
+            rusqlite::params![txid[..].to_vec()],
             |row| {
                 let txid: Vec<u8> = row.get(0)?;
                 let txid: bitcoin::Txid =
@@ -682,12 +955,176 @@ impl SqliteConn {
         .expect("Db must not fail")
     }
 
+    /// Record these coins whose deposit transaction was dropped from the mempool at this height.
+    /// Coins already recorded are marked as not invalidated anymore.
+    pub fn record_dropped_coins(&mut self, coins: &[Coin], height: i32) {
+        db_exec(&mut self.conn, |db_tx| {
+            for coin in coins {
+                let deriv_index: u32 = coin.derivation_index.into();
+                db_tx.execute(
+                    "INSERT INTO dropped_coins (txid, vout, amount_sat, derivation_index, is_change, dropped_height) \
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6) \
+                    ON CONFLICT DO UPDATE SET invalidated_at = NULL, dropped_height = excluded.dropped_height",
+                    rusqlite::params![
+                        coin.outpoint.txid[..].to_vec(),
+                        coin.outpoint.vout,
+                        coin.amount.to_sat(),
+                        deriv_index,
+                        coin.is_change,
+                        height,
+                    ],
+                )?;
+            }
+            Ok(())
+        })
+        .expect("Db must not fail");
+    }
+
+    /// Remove these coins from the dropped ones.
+    pub fn forget_dropped_coins(&mut self, outpoints: &[bitcoin::OutPoint]) {
+        db_exec(&mut self.conn, |db_tx| {
+            for outpoint in outpoints {
+                db_tx.execute(
+                    "DELETE FROM dropped_coins WHERE txid = ?1 AND vout = ?2",
+                    rusqlite::params![outpoint.txid[..].to_vec(), outpoint.vout],
+                )?;
+            }
+            Ok(())
+        })
+        .expect("Db must not fail");
+    }
+
+    /// Mark these dropped coins as invalidated at the current time, if they weren't already.
+    pub fn invalidate_coins(&mut self, outpoints: &[bitcoin::OutPoint]) {
+        db_exec(&mut self.conn, |db_tx| {
+            for outpoint in outpoints {
+                db_tx.execute(
+                    "UPDATE dropped_coins SET invalidated_at = ?1 \
+                    WHERE txid = ?2 AND vout = ?3 AND invalidated_at IS NULL",
+                    rusqlite::params![curr_timestamp(), outpoint.txid[..].to_vec(), outpoint.vout],
+                )?;
+            }
+            Ok(())
+        })
+        .expect("Db must not fail");
+    }
+
+    /// Get all the dropped coins, in the order they were dropped.
+    pub fn db_dropped_coins(&mut self) -> Vec<DroppedCoin> {
+        db_query(
+            &mut self.conn,
+            "SELECT txid, vout, amount_sat, derivation_index, is_change, dropped_height, invalidated_at \
+            FROM dropped_coins ORDER BY id",
+            rusqlite::params![],
+            |row| {
+                let txid: Vec<u8> = row.get(0)?;
+                let txid: bitcoin::Txid =
+                    encode::deserialize(&txid).expect("We only store valid txids");
+                let vout = row.get(1)?;
+                let amount: i64 = row.get(2)?;
+                let amount = bitcoin::Amount::from_sat(amount.try_into().expect("Insane db"));
+                let der_idx: u32 = row.get(3)?;
+                Ok(DroppedCoin {
+                    outpoint: bitcoin::OutPoint { txid, vout },
+                    amount,
+                    derivation_index: bip32::ChildNumber::from(der_idx),
+                    is_change: row.get(4)?,
+                    dropped_height: row.get(5)?,
+                    invalidated_at: row.get(6)?,
+                })
+            },
+        )
+        .expect("Db must not fail")
+    }
+
+    /// Set the note on the coin at this outpoint, or remove it if `None`.
+    pub fn set_coin_note(&mut self, outpoint: &bitcoin::OutPoint, note: Option<&str>) {
+        db_exec(&mut self.conn, |db_tx| {
+            if let Some(note) = note {
+                db_tx.execute(
+                    "INSERT INTO coin_notes (txid, vout, note) VALUES (?1, ?2, ?3) \
+                    ON CONFLICT DO UPDATE SET note=excluded.note",
+                    rusqlite::params![outpoint.txid[..].to_vec(), outpoint.vout, note],
+                )?;
+            } else {
+                db_tx.execute(
+                    "DELETE FROM coin_notes WHERE txid = ?1 AND vout = ?2",
+                    rusqlite::params![outpoint.txid[..].to_vec(), outpoint.vout],
+                )?;
+            }
+            Ok(())
+        })
+        .expect("Db must not fail");
+    }
+
+    /// Get the notes of the coins at these outpoints, or all the notes if none is given.
+    pub fn db_coin_notes(
+        &mut self,
+        outpoints: &[bitcoin::OutPoint],
+    ) -> HashMap<bitcoin::OutPoint, String> {
+        db_query(
+            &mut self.conn,
+            "SELECT txid, vout, note FROM coin_notes",
+            rusqlite::params![],
+            |row| {
+                let txid: Vec<u8> = row.get(0)?;
+                let txid: bitcoin::Txid =
+                    encode::deserialize(&txid).expect("We only store valid txids");
+                let vout = row.get(1)?;
+                let note: String = row.get(2)?;
+                Ok((bitcoin::OutPoint { txid, vout }, note))
+            },
+        )
+        .expect("Db must not fail")
+        .into_iter()
+        .filter(|(outpoint, _)| outpoints.is_empty() || outpoints.contains(outpoint))
+        .collect()
+    }
+
+    /// Store these feerate samples.
+    pub fn store_feerate_samples(&mut self, samples: &[FeerateSample]) {
+        db_exec(&mut self.conn, |db_tx| {
+            for sample in samples {
+                db_tx.execute(
+                    "INSERT INTO feerate_samples (timestamp, conf_target, feerate) \
+                    VALUES (?1, ?2, ?3)",
+                    rusqlite::params![sample.timestamp, sample.conf_target, sample.feerate],
+                )?;
+            }
+            Ok(())
+        })
+        .expect("Db must not fail");
+    }
+
+    /// Get the feerate samples taken at or after this timestamp, by increasing timestamp and
+    /// confirmation target.
+    pub fn db_feerate_samples(&mut self, since: u32) -> Vec<FeerateSample> {
+        db_query(
+            &mut self.conn,
+            "SELECT timestamp, conf_target, feerate FROM feerate_samples WHERE timestamp >= ?1 \
+            ORDER BY timestamp, conf_target",
+            rusqlite::params![since],
+            |row| {
+                Ok(FeerateSample {
+                    timestamp: row.get(0)?,
+                    conf_target: row.get(1)?,
+                    feerate: row.get(2)?,
+                })
+            },
+        )
+        .expect("Db must not fail")
+    }
+
     pub fn delete_spend(&mut self, txid: &bitcoin::Txid) {
         db_exec(&mut self.conn, |db_tx| {
             db_tx.execute(
                 "DELETE FROM spend_transactions WHERE txid = ?1",
                 rusqlite::params![txid[..].to_vec()],
             )?;
+            db_tx.execute(
+                "DELETE FROM replacement_drafts WHERE txid = ?1 OR replacement_txid = ?1",
+                rusqlite::params![txid[..].to_vec()],
+            )?;
             Ok(())
         })
         .expect("Db must not fail");
@@ -727,7 +1164,7 @@ impl SqliteConn {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::database::{BlockInfo, DbBlockInfo};
+    use crate::database::{BlockInfo, DatabaseConnection, DbBlockInfo};
     use crate::testutils::*;
     use std::{
         collections::{HashMap, HashSet},
@@ -919,7 +1356,7 @@ CREATE TABLE labels (
             .iter()
             .collect();
         let options = dummy_options();
-        let db = SqliteDb::new(db_path, Some(options.clone()), &secp).unwrap();
+        let db = SqliteDb::new(db_path, Some(options.clone()), &secp, None).unwrap();
 
         (tmp_dir, options, secp, db)
     }
@@ -933,14 +1370,14 @@ CREATE TABLE labels (
         let db_path: path::PathBuf = [tmp_dir.as_path(), path::Path::new("lianad.sqlite3")]
             .iter()
             .collect();
-        assert!(SqliteDb::new(db_path.clone(), None, &secp)
+        assert!(SqliteDb::new(db_path.clone(), None, &secp, None)
             .unwrap_err()
             .to_string()
             .contains("database file not found"));
 
         let options = dummy_options();
 
-        let db = SqliteDb::new(db_path.clone(), Some(options.clone()), &secp).unwrap();
+        let db = SqliteDb::new(db_path.clone(), Some(options.clone()), &secp, None).unwrap();
         db.sanity_check(bitcoin::Network::Testnet, &options.main_descriptor)
             .unwrap_err()
             .to_string()
@@ -948,7 +1385,7 @@ CREATE TABLE labels (
         fs::remove_file(&db_path).unwrap();
         let other_desc_str = "wsh(andor(pk([aabbccdd]tpubDExU4YLJkyQ9RRbVScQq2brFxWWha7WmAUByPWyaWYwmcTv3Shx8aHp6mVwuE5n4TeM4z5DTWGf2YhNPmXtfvyr8cUDVvA3txdrFnFgNdF7/<0;1>/*),older(10000),pk([aabbccdd]tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/<0;1>/*)))";
         let other_desc = LianaDescriptor::from_str(other_desc_str).unwrap();
-        let db = SqliteDb::new(db_path.clone(), Some(options.clone()), &secp).unwrap();
+        let db = SqliteDb::new(db_path.clone(), Some(options.clone()), &secp, None).unwrap();
         db.sanity_check(bitcoin::Network::Bitcoin, &other_desc)
             .unwrap_err()
             .to_string()
@@ -956,12 +1393,86 @@ CREATE TABLE labels (
         fs::remove_file(&db_path).unwrap();
         // TODO: version check
 
-        let db = SqliteDb::new(db_path.clone(), Some(options.clone()), &secp).unwrap();
+        let db = SqliteDb::new(db_path.clone(), Some(options.clone()), &secp, None).unwrap();
         db.sanity_check(bitcoin::Network::Bitcoin, &options.main_descriptor)
             .unwrap();
-        let db = SqliteDb::new(db_path, None, &secp).unwrap();
+        let db = SqliteDb::new(db_path.clone(), None, &secp, None).unwrap();
         db.sanity_check(bitcoin::Network::Bitcoin, &options.main_descriptor)
             .unwrap();
+        fs::remove_file(&db_path).unwrap();
+
+        // The wallet creation time may be set when creating the database, when restoring a wallet.
+        let db = SqliteDb::new(
+            db_path,
+            Some(options.with_timestamp(1_600_000_000)),
+            &secp,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            db.connection().unwrap().db_wallet().timestamp,
+            1_600_000_000
+        );
+
+        fs::remove_dir_all(tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn db_encryption() {
+        let (tmp_dir, options, secp, db) = dummy_db();
+        let key = DbEncryptionKey("correct horse battery staple".to_string());
+        let encrypted_path = tmp_dir.join("lianad.encrypted.sqlite3");
+
+        // Without SQLCipher we can't encrypt the database.
+        if !cfg!(feature = "sqlcipher") {
+            db.connection()
+                .unwrap()
+                .export_encrypted(&encrypted_path, &key)
+                .unwrap_err();
+            assert!(!encrypted_path.exists());
+            fs::remove_dir_all(tmp_dir).unwrap();
+            return;
+        }
+
+        // We can make an encrypted copy of a plaintext database. It can only be opened with the
+        // right key.
+        db.connection()
+            .unwrap()
+            .export_encrypted(&encrypted_path, &key)
+            .unwrap();
+        let wrong_key = DbEncryptionKey("wrong key".to_string());
+        for wrong_key in [None, Some(wrong_key)].iter().cloned() {
+            assert!(matches!(
+                SqliteDb::new(encrypted_path.clone(), None, &secp, wrong_key),
+                Err(SqliteDbError::Decryption)
+            ));
+        }
+        let enc_db = SqliteDb::new(encrypted_path.clone(), None, &secp, Some(key.clone())).unwrap();
+        enc_db
+            .sanity_check(bitcoin::Network::Bitcoin, &options.main_descriptor)
+            .unwrap();
+        // We won't overwrite an existing file.
+        db.connection()
+            .unwrap()
+            .export_encrypted(&encrypted_path, &key)
+            .unwrap_err();
+
+        // A fresh database can be encrypted from the start.
+        let fresh_path = tmp_dir.join("fresh.sqlite3");
+        let fresh_db = SqliteDb::new(
+            fresh_path.clone(),
+            Some(options.clone()),
+            &secp,
+            Some(key.clone()),
+        )
+        .unwrap();
+        fresh_db
+            .sanity_check(bitcoin::Network::Bitcoin, &options.main_descriptor)
+            .unwrap();
+        assert!(matches!(
+            SqliteDb::new(fresh_path, None, &secp, None),
+            Err(SqliteDbError::Decryption)
+        ));
 
         fs::remove_dir_all(tmp_dir).unwrap();
     }
@@ -1032,6 +1543,355 @@ CREATE TABLE labels (
         fs::remove_dir_all(tmp_dir).unwrap();
     }
 
+    #[test]
+    fn db_replacements() {
+        let (tmp_dir, _, _, db) = dummy_db();
+
+        {
+            let mut conn = db.connection().unwrap();
+            let txid_a = bitcoin::Txid::from_slice(&[0; 32][..]).unwrap();
+            let txid_b = bitcoin::Txid::from_slice(&[1; 32][..]).unwrap();
+            let txid_c = bitcoin::Txid::from_slice(&[2; 32][..]).unwrap();
+            assert!(conn.db_replaced_by(&[txid_a, txid_b, txid_c]).is_empty());
+
+            conn.record_replacements(&[txid_a], &txid_b);
+            let replacements = conn.db_replaced_by(&[txid_a, txid_b, txid_c]);
+            assert_eq!(replacements.len(), 1);
+            assert_eq!(replacements[&txid_a], txid_b);
+
+            // Recording a new replacement for the same transaction overwrites the previous one.
+            conn.record_replacements(&[txid_a, txid_b], &txid_c);
+            let replacements = conn.db_replaced_by(&[txid_a, txid_b, txid_c]);
+            assert_eq!(replacements.len(), 2);
+            assert_eq!(replacements[&txid_a], txid_c);
+            assert_eq!(replacements[&txid_b], txid_c);
+        }
+
+        fs::remove_dir_all(tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn db_replacement_drafts() {
+        let (tmp_dir, _, _, db) = dummy_db();
+
+        {
+            let mut conn = db.connection().unwrap();
+            let txid_a = bitcoin::Txid::from_slice(&[0; 32][..]).unwrap();
+            let txid_b = bitcoin::Txid::from_slice(&[1; 32][..]).unwrap();
+            let txid_c = bitcoin::Txid::from_slice(&[2; 32][..]).unwrap();
+            assert!(conn.db_replacement_drafts(&txid_a).is_empty());
+
+            // Several replacements may be drafted for the same transaction, recording one twice
+            // is a no-op.
+            conn.record_replacement_draft(&txid_a, &txid_b);
+            conn.record_replacement_draft(&txid_a, &txid_c);
+            conn.record_replacement_draft(&txid_a, &txid_c);
+            let mut drafts = conn.db_replacement_drafts(&txid_a);
+            drafts.sort();
+            let mut expected = vec![txid_b, txid_c];
+            expected.sort();
+            assert_eq!(drafts, expected);
+            assert!(conn.db_replacement_drafts(&txid_b).is_empty());
+
+            // Deleting the Spend of a replacement forgets about it.
+            conn.delete_spend(&txid_b);
+            assert_eq!(conn.db_replacement_drafts(&txid_a), vec![txid_c]);
+            // So does deleting the Spend it replaces.
+            conn.delete_spend(&txid_a);
+            assert!(conn.db_replacement_drafts(&txid_a).is_empty());
+        }
+
+        fs::remove_dir_all(tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn db_coin_notes() {
+        let (tmp_dir, _, _, db) = dummy_db();
+
+        {
+            let mut conn = db.connection().unwrap();
+            let op_a = bitcoin::OutPoint::from_str(
+                "6f0dc85a369f44a72b4b5b9e8e9e7d5b4fb5b3f7e5b2c0a4d8d3a0b1e2f3a4b5:1",
+            )
+            .unwrap();
+            let op_b = bitcoin::OutPoint::from_str(
+                "6f0dc85a369f44a72b4b5b9e8e9e7d5b4fb5b3f7e5b2c0a4d8d3a0b1e2f3a4b5:2",
+            )
+            .unwrap();
+            assert!(conn.db_coin_notes(&[]).is_empty());
+
+            // Set notes on two coins, then update one of them.
+            conn.set_coin_note(&op_a, Some("first\nnote"));
+            conn.set_coin_note(&op_b, Some("second note"));
+            conn.set_coin_note(&op_b, Some("updated note"));
+            let notes = conn.db_coin_notes(&[]);
+            assert_eq!(notes.len(), 2);
+            assert_eq!(notes[&op_a], "first\nnote");
+            assert_eq!(notes[&op_b], "updated note");
+            let notes = conn.db_coin_notes(&[op_b]);
+            assert_eq!(notes.len(), 1);
+            assert_eq!(notes[&op_b], "updated note");
+
+            // A note can be removed.
+            conn.set_coin_note(&op_a, None);
+            assert!(conn.db_coin_notes(&[op_a]).is_empty());
+            assert_eq!(conn.db_coin_notes(&[]).len(), 1);
+        }
+
+        fs::remove_dir_all(tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn db_feerate_samples() {
+        let (tmp_dir, _, _, db) = dummy_db();
+
+        {
+            let mut conn = db.connection().unwrap();
+            assert!(conn.db_feerate_samples(0).is_empty());
+
+            let sample = |timestamp, conf_target, feerate| FeerateSample {
+                timestamp,
+                conf_target,
+                feerate,
+            };
+            conn.store_feerate_samples(&[sample(2_000, 6, 10), sample(2_000, 2, 20)]);
+            conn.store_feerate_samples(&[sample(1_000, 2, 30)]);
+
+            // They are returned by increasing timestamp and confirmation target.
+            assert_eq!(
+                conn.db_feerate_samples(0),
+                vec![
+                    sample(1_000, 2, 30),
+                    sample(2_000, 2, 20),
+                    sample(2_000, 6, 10)
+                ]
+            );
+            assert_eq!(
+                conn.db_feerate_samples(1_001),
+                vec![sample(2_000, 2, 20), sample(2_000, 6, 10)]
+            );
+            assert!(conn.db_feerate_samples(2_001).is_empty());
+        }
+
+        fs::remove_dir_all(tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn db_dropped_coins() {
+        let (tmp_dir, _, _, db) = dummy_db();
+
+        {
+            let mut conn = db.connection().unwrap();
+            let coin = Coin {
+                outpoint: bitcoin::OutPoint::from_str(
+                    "6f0dc85a369f44a72b4b5b9e8e9e7d5b4fb5b3f7e5b2c0a4d8d3a0b1e2f3a4b5:1",
+                )
+                .unwrap(),
+                is_immature: false,
+                block_info: None,
+                amount: bitcoin::Amount::from_sat(42_000),
+                derivation_index: bip32::ChildNumber::from_normal_idx(3).unwrap(),
+                is_change: true,
+                spend_txid: None,
+                spend_block: None,
+            };
+            assert!(conn.db_dropped_coins().is_empty());
+
+            // Recording a dropped coin twice doesn't duplicate it.
+            conn.record_dropped_coins(&[coin], 100);
+            conn.record_dropped_coins(&[coin], 101);
+            let dropped = conn.db_dropped_coins();
+            assert_eq!(dropped.len(), 1);
+            assert_eq!(dropped[0].outpoint, coin.outpoint);
+            assert_eq!(dropped[0].amount, coin.amount);
+            assert_eq!(dropped[0].derivation_index, coin.derivation_index);
+            assert!(dropped[0].is_change);
+            assert_eq!(dropped[0].dropped_height, 101);
+            assert!(dropped[0].invalidated_at.is_none());
+
+            // Once invalidated, the timestamp isn't updated anymore.
+            conn.invalidate_coins(&[coin.outpoint]);
+            let invalidated_at = conn.db_dropped_coins()[0].invalidated_at;
+            assert!(invalidated_at.is_some());
+            std::thread::sleep(std::time::Duration::from_secs(1));
+            conn.invalidate_coins(&[coin.outpoint]);
+            assert_eq!(conn.db_dropped_coins()[0].invalidated_at, invalidated_at);
+
+            // If it is dropped again, it's not invalidated anymore.
+            conn.record_dropped_coins(&[coin], 102);
+            let dropped = conn.db_dropped_coins();
+            assert_eq!(dropped.len(), 1);
+            assert_eq!(dropped[0].dropped_height, 102);
+            assert!(dropped[0].invalidated_at.is_none());
+
+            // It can be forgotten.
+            conn.forget_dropped_coins(&[coin.outpoint]);
+            assert!(conn.db_dropped_coins().is_empty());
+        }
+
+        fs::remove_dir_all(tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn db_prune_spent_coins() {
+        let (tmp_dir, _, _, db) = dummy_db();
+
+        {
+            let mut conn = db.connection().unwrap();
+            let coin = |outpoint: &str, derivation_index: u32| Coin {
+                outpoint: bitcoin::OutPoint::from_str(outpoint).unwrap(),
+                is_immature: false,
+                block_info: None,
+                amount: bitcoin::Amount::from_sat(98765),
+                derivation_index: bip32::ChildNumber::from_normal_idx(derivation_index).unwrap(),
+                is_change: false,
+                spend_txid: None,
+                spend_block: None,
+            };
+            let coin_a = coin(
+                "6f0dc85a369b44458eba3a1f0ea5b5935d563afb6994f70f5b0094e05be1676c:1",
+                10,
+            );
+            let coin_b = coin(
+                "61db3e276b095e5b05f1849dd6bfffb4e7e5ec1c4a4210099b98fce01571936f:12",
+                11,
+            );
+            let coin_c = coin(
+                "5b0f1849dd6bfffb4e7e5ec1c4a4210099b98fce01571936f61db3e276b095e0:3",
+                12,
+            );
+            conn.new_unspent_coins(&[coin_a, coin_b, coin_c]);
+            conn.confirm_coins(&[
+                (coin_a.outpoint, 100, 100),
+                (coin_b.outpoint, 100, 100),
+                (coin_c.outpoint, 100, 100),
+            ]);
+
+            // Coin A was spent at height 150, coin B is being spent and coin C is unspent.
+            let spend_txid = bitcoin::Txid::from_slice(&[0; 32][..]).unwrap();
+            conn.spend_coins(&[(coin_a.outpoint, spend_txid), (coin_b.outpoint, spend_txid)]);
+            conn.confirm_spend(&[(coin_a.outpoint, spend_txid, 150, 150)]);
+
+            // Only coins whose spend confirmed at or below the given height are pruned.
+            assert_eq!(conn.prune_spent_coins(149), 0);
+            assert_eq!(conn.coins(&[], &[]).len(), 3);
+            assert_eq!(conn.prune_spent_coins(150), 1);
+            let outpoints: HashSet<bitcoin::OutPoint> = conn
+                .coins(&[], &[])
+                .into_iter()
+                .map(|c| c.outpoint)
+                .collect();
+            assert_eq!(
+                outpoints,
+                [coin_b.outpoint, coin_c.outpoint].iter().cloned().collect()
+            );
+            assert_eq!(conn.prune_spent_coins(1_000), 0);
+        }
+
+        fs::remove_dir_all(tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn db_stats_vacuum() {
+        let (tmp_dir, _, _, db) = dummy_db();
+
+        {
+            let mut conn = db.connection().unwrap();
+            let stats = conn.db_stats();
+            assert_eq!(stats.table_rows.get("coins"), Some(&0));
+            assert!(stats.table_rows.contains_key("spend_transactions"));
+            assert!(stats.size > 0);
+
+            // Store many coins, the rows are accounted for and the database grows.
+            let coins: Vec<Coin> = (0..1_000u32)
+                .map(|i| Coin {
+                    outpoint: bitcoin::OutPoint::new(
+                        bitcoin::Txid::from_slice(&[1; 32][..]).unwrap(),
+                        i,
+                    ),
+                    is_immature: false,
+                    block_info: None,
+                    amount: bitcoin::Amount::from_sat(98765),
+                    derivation_index: bip32::ChildNumber::from_normal_idx(i).unwrap(),
+                    is_change: false,
+                    spend_txid: None,
+                    spend_block: None,
+                })
+                .collect();
+            conn.new_unspent_coins(&coins);
+            let full_stats = conn.db_stats();
+            assert_eq!(full_stats.table_rows.get("coins"), Some(&1_000));
+            assert!(full_stats.size > stats.size);
+
+            // Once they are removed, their space is unused until the database is vacuumed.
+            let outpoints: Vec<bitcoin::OutPoint> = coins.iter().map(|c| c.outpoint).collect();
+            conn.remove_coins(&outpoints);
+            let removed_stats = conn.db_stats();
+            assert_eq!(removed_stats.table_rows.get("coins"), Some(&0));
+            assert_eq!(removed_stats.size, full_stats.size);
+            assert!(removed_stats.free_size > 0);
+            conn.vacuum().unwrap();
+            let vacuumed_stats = conn.db_stats();
+            assert_eq!(vacuumed_stats.free_size, 0);
+            assert!(vacuumed_stats.size < removed_stats.size);
+        }
+
+        fs::remove_dir_all(tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn db_labels_page() {
+        let (tmp_dir, _, _, db) = dummy_db();
+
+        {
+            let mut conn = db.connection().unwrap();
+            assert!(conn.db_labels_page(0, 10).is_empty());
+
+            let txids = [
+                "0c62a990d20d54429e70859292e82374ba6b1b951a3ab60f26bb65fee5724ff7",
+                "f7e8a5e7db9c7ac18e2ab4b4e08e5bad0bd7cd4c3b466cf37e8b0c46b7a09ba5",
+                "29b4d0d2c2b4b42b8a7d6e9ce6e8c1f1d5a8b4d2c7c1dc1e4e2c2a0b9d5a3f7e",
+            ];
+            for (i, txid) in txids.iter().enumerate() {
+                let item = LabelItem::from_str(txid, bitcoin::Network::Bitcoin).unwrap();
+                let mut labels = HashMap::new();
+                labels.insert(item, Some(format!("label {}", i)));
+                conn.update_labels(&labels);
+            }
+
+            // Labels are returned in insertion order, and paginated.
+            let page = conn.db_labels_page(0, 2);
+            assert_eq!(page.len(), 2);
+            assert_eq!(page[0].item, txids[0]);
+            assert_eq!(page[1].item, txids[1]);
+            let page = conn.db_labels_page(2, 2);
+            assert_eq!(page.len(), 1);
+            assert_eq!(page[0].item, txids[2]);
+            assert_eq!(page[0].value, "label 2");
+            assert!(conn.db_labels_page(3, 2).is_empty());
+
+            // The same through the database interface, which parses back the items.
+            let all = conn.labels_page(0, 10);
+            assert_eq!(all.len(), 3);
+            assert_eq!(all[1].0.to_string(), txids[1]);
+            assert_eq!(all[1].1, "label 1");
+
+            // All the labels can be replaced at once.
+            let item = LabelItem::from_str(txids[2], bitcoin::Network::Bitcoin).unwrap();
+            let mut labels = HashMap::new();
+            labels.insert(item, "new label".to_string());
+            assert_eq!(conn.replace_labels(&labels), 3);
+            let page = conn.db_labels_page(0, 10);
+            assert_eq!(page.len(), 1);
+            assert_eq!(page[0].item, txids[2]);
+            assert_eq!(page[0].value, "new label");
+            assert_eq!(conn.replace_labels(&HashMap::new()), 1);
+            assert!(conn.db_labels_page(0, 10).is_empty());
+        }
+
+        fs::remove_dir_all(tmp_dir).unwrap();
+    }
+
     #[test]
     fn db_coins() {
         let (tmp_dir, _, _, db) = dummy_db();
@@ -1608,6 +2468,12 @@ CREATE TABLE labels (
                 .derive(200.into(), &secp)
                 .address(options.bitcoind_network);
             assert!(conn.db_address(&addr).is_none());
+            let all_addrs = conn.db_addresses();
+            assert_eq!(all_addrs.len(), 200);
+            assert!(all_addrs
+                .iter()
+                .enumerate()
+                .all(|(i, a)| a.derivation_index == (i as u32).into()));
 
             // But if we increment the deposit derivation index, the 200th one will be there.
             conn.set_derivation_index(1.into(), false, &secp);
@@ -2066,6 +2932,25 @@ CREATE TABLE labels (
                     .unwrap(),
                 ]
             );
+
+            // The list can be paginated.
+            let db_txids = conn.db_list_txids_page(1_123_000, 1_127_000, 2, 10);
+            assert_eq!(
+                &db_txids[..],
+                &[
+                    bitcoin::Txid::from_str(
+                        "19f56e65069f0a7a3bfb00c6a7085cc0669e03e91befeca1ee9891c9e737b2fb"
+                    )
+                    .unwrap(),
+                    bitcoin::Txid::from_str(
+                        "0c62a990d20d54429e70859292e82374ba6b1b951a3ab60f26bb65fee5724ff7"
+                    )
+                    .unwrap()
+                ]
+            );
+            assert!(conn
+                .db_list_txids_page(1_123_000, 1_127_000, 4, 10)
+                .is_empty());
         }
 
         fs::remove_dir_all(tmp_dir).unwrap();
@@ -2085,7 +2970,7 @@ CREATE TABLE labels (
         let mut options = dummy_options();
         options.schema = V0_SCHEMA;
         options.version = 0;
-        create_fresh_db(&db_path, options, &secp).unwrap();
+        create_fresh_db(&db_path, options, &secp, None).unwrap();
 
         // Two PSBTs we'll insert in the DB before and after the migration. Note they are random
         // PSBTs taken from the descriptor unit tests, it doesn't matter.
@@ -2168,9 +3053,9 @@ CREATE TABLE labels (
         }
 
         // Migrate the DB.
-        maybe_apply_migration(&db_path).unwrap();
-        maybe_apply_migration(&db_path).unwrap(); // Migrating twice will be a no-op.
-        let db = SqliteDb::new(db_path, None, &secp).unwrap();
+        maybe_apply_migration(&db_path, None).unwrap();
+        maybe_apply_migration(&db_path, None).unwrap(); // Migrating twice will be a no-op.
+        let db = SqliteDb::new(db_path, None, &secp, None).unwrap();
 
         // We should now be able to insert another PSBT, to query both, and the first PSBT must
         // have no associated timestamp.
@@ -2228,12 +3113,13 @@ CREATE TABLE labels (
         let mut options = dummy_options();
         options.schema = V3_SCHEMA;
         options.version = 3;
-        create_fresh_db(&db_path, options, &secp).unwrap();
+        create_fresh_db(&db_path, options, &secp, None).unwrap();
 
         {
             // Don't use SqliteDb::new() in order not to apply migration.
             let db = SqliteDb {
                 db_path: db_path.clone(),
+                encryption_key: None,
             };
             let mut conn = db.connection().unwrap();
             assert!(conn.db_version() == 3);
@@ -2395,10 +3281,10 @@ CREATE TABLE labels (
             );
 
             // Migrate the DB.
-            maybe_apply_migration(&db_path).unwrap();
-            assert!(conn.db_version() == 4);
-            maybe_apply_migration(&db_path).unwrap(); // Migrating twice will be a no-op.
-            assert!(conn.db_version() == 4);
+            maybe_apply_migration(&db_path, None).unwrap();
+            assert!(conn.db_version() == 10);
+            maybe_apply_migration(&db_path, None).unwrap(); // Migrating twice will be a no-op.
+            assert!(conn.db_version() == 10);
             let coins_post = conn.coins(&[], &[]);
             assert_eq!(coins_pre, coins_post);
         }
@@ -2419,15 +3305,15 @@ CREATE TABLE labels (
         let mut options = dummy_options();
         options.schema = V0_SCHEMA;
         options.version = 0;
-        create_fresh_db(&db_path, options, &secp).unwrap();
+        create_fresh_db(&db_path, options, &secp, None).unwrap();
 
         // SqliteDb new is doing the migration.
-        let db = SqliteDb::new(db_path, None, &secp).unwrap();
+        let db = SqliteDb::new(db_path, None, &secp, None).unwrap();
 
         {
             let mut conn = db.connection().unwrap();
             let version = conn.db_version();
-            assert_eq!(version, 4);
+            assert_eq!(version, 10);
 
             let txid_str = "0c62a990d20d54429e70859292e82374ba6b1b951a3ab60f26bb65fee5724ff7";
             let txid = LabelItem::from_str(txid_str, bitcoin::Network::Bitcoin).unwrap();
@@ -87,6 +87,58 @@ CREATE TABLE labels (
     item TEXT UNIQUE NOT NULL,
     value TEXT NOT NULL
 );
+
+/* Transactions of ours that were replaced by another one we broadcast (for instance when
+ * bumping its fee), along with the txid of the replacement transaction. */
+CREATE TABLE replaced_transactions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    txid BLOB UNIQUE NOT NULL,
+    replaced_by BLOB NOT NULL
+);
+
+/* Unconfirmed coins of ours whose deposit transaction was dropped from the mempool. They are not
+ * part of the coins table anymore. The 'dropped_height' field is the height of our tip when the
+ * deposit transaction was dropped. The 'invalidated_at' field is the timestamp at which we
+ * detected that the deposit transaction was double spent by a confirmed transaction, if it was. */
+CREATE TABLE dropped_coins (
+    id INTEGER PRIMARY KEY NOT NULL,
+    txid BLOB NOT NULL,
+    vout INTEGER NOT NULL,
+    amount_sat INTEGER NOT NULL,
+    derivation_index INTEGER NOT NULL,
+    is_change BOOLEAN NOT NULL CHECK (is_change IN (0,1)),
+    invalidated_at INTEGER,
+    dropped_height INTEGER NOT NULL DEFAULT 0,
+    UNIQUE (txid, vout)
+);
+
+/* Freeform notes on our coins. Unlike labels they may be longer and span multiple lines. A note
+ * isn't removed along with its coin, so it's kept if the coin is found again after a reorg. */
+CREATE TABLE coin_notes (
+    id INTEGER PRIMARY KEY NOT NULL,
+    txid BLOB NOT NULL,
+    vout INTEGER NOT NULL,
+    note TEXT NOT NULL,
+    UNIQUE (txid, vout)
+);
+
+/* The feerate estimates of the Bitcoin backend, sampled periodically for a few confirmation
+ * targets. The feerate is in sats/vb. */
+CREATE TABLE feerate_samples (
+    id INTEGER PRIMARY KEY NOT NULL,
+    timestamp INTEGER NOT NULL,
+    conf_target INTEGER NOT NULL,
+    feerate INTEGER NOT NULL
+);
+
+/* Replacements drafted for our transactions, for instance to bump their fee. The replacement may
+ * not have been stored, let alone broadcast. */
+CREATE TABLE replacement_drafts (
+    id INTEGER PRIMARY KEY NOT NULL,
+    txid BLOB NOT NULL,
+    replacement_txid BLOB NOT NULL,
+    UNIQUE (txid, replacement_txid)
+);
 ";
 
 /// A row in the "tip" table.
@@ -1,4 +1,7 @@
-use crate::database::sqlite::{FreshDbOptions, SqliteDbError, DB_VERSION};
+use crate::{
+    config::DbEncryptionKey,
+    database::sqlite::{FreshDbOptions, SqliteDbError, DB_VERSION},
+};
 
 use std::{convert::TryInto, fs, path, time};
 
@@ -82,15 +85,40 @@ pub fn create_db_file(db_path: &path::Path) -> Result<(), std::io::Error> {
     };
 }
 
+/// Open a connection to the database at this path, using this key to decrypt it if any.
+///
+/// Returns `SqliteDbError::Decryption` if the database can't be read with this key, for instance
+/// if it's encrypted with another key or if it's not encrypted but a key was given.
+pub fn open_connection(
+    db_path: &path::Path,
+    encryption_key: Option<&DbEncryptionKey>,
+) -> Result<rusqlite::Connection, SqliteDbError> {
+    let conn = rusqlite::Connection::open(db_path)?;
+    if let Some(key) = encryption_key {
+        conn.pragma_update(None, "key", &key.0)?;
+    }
+
+    // The key is only checked when the database is first read.
+    match conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(())) {
+        Err(rusqlite::Error::SqliteFailure(e, _))
+            if e.code == rusqlite::ErrorCode::NotADatabase =>
+        {
+            Err(SqliteDbError::Decryption)
+        }
+        res => Ok(res.map(|_| conn)?),
+    }
+}
+
 /// Create a fresh Liana database with the given schema.
 pub fn create_fresh_db(
     db_path: &path::Path,
     options: FreshDbOptions,
     secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+    encryption_key: Option<&DbEncryptionKey>,
 ) -> Result<(), SqliteDbError> {
     create_db_file(db_path)?;
 
-    let timestamp = curr_timestamp();
+    let timestamp = options.timestamp.unwrap_or_else(curr_timestamp);
 
     // Fill the initial addresses. On a fresh database, the deposit_derivation_index is
     // necessarily 0.
@@ -112,7 +140,7 @@ pub fn create_fresh_db(
         );
     }
 
-    let mut conn = rusqlite::Connection::open(db_path)?;
+    let mut conn = open_connection(db_path, encryption_key)?;
     db_exec(&mut conn, |tx| {
         tx.execute_batch(options.schema)?;
         tx.execute(
@@ -229,10 +257,101 @@ fn migrate_v3_to_v4(conn: &mut rusqlite::Connection) -> Result<(), SqliteDbError
     Ok(())
 }
 
+// Record the transactions replaced by the ones we broadcast.
+fn migrate_v4_to_v5(conn: &mut rusqlite::Connection) -> Result<(), SqliteDbError> {
+    db_exec(conn, |tx| {
+        tx.execute(
+            "CREATE TABLE replaced_transactions (id INTEGER PRIMARY KEY NOT NULL, txid BLOB UNIQUE NOT NULL, replaced_by BLOB NOT NULL)",
+            rusqlite::params![],
+        )?;
+        tx.execute("UPDATE version SET version = 5", rusqlite::params![])?;
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+// Record the unconfirmed coins whose deposit transaction was dropped, to detect double spends.
+fn migrate_v5_to_v6(conn: &mut rusqlite::Connection) -> Result<(), SqliteDbError> {
+    db_exec(conn, |tx| {
+        tx.execute(
+            "CREATE TABLE dropped_coins (id INTEGER PRIMARY KEY NOT NULL, txid BLOB NOT NULL, vout INTEGER NOT NULL, amount_sat INTEGER NOT NULL, derivation_index INTEGER NOT NULL, is_change BOOLEAN NOT NULL CHECK (is_change IN (0,1)), invalidated_at INTEGER, UNIQUE (txid, vout))",
+            rusqlite::params![],
+        )?;
+        tx.execute("UPDATE version SET version = 6", rusqlite::params![])?;
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+// Store freeform notes on coins, separately from their label.
+fn migrate_v6_to_v7(conn: &mut rusqlite::Connection) -> Result<(), SqliteDbError> {
+    db_exec(conn, |tx| {
+        tx.execute(
+            "CREATE TABLE coin_notes (id INTEGER PRIMARY KEY NOT NULL, txid BLOB NOT NULL, vout INTEGER NOT NULL, note TEXT NOT NULL, UNIQUE (txid, vout))",
+            rusqlite::params![],
+        )?;
+        tx.execute("UPDATE version SET version = 7", rusqlite::params![])?;
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+// Record the feerate estimates of the Bitcoin backend over time.
+fn migrate_v7_to_v8(conn: &mut rusqlite::Connection) -> Result<(), SqliteDbError> {
+    db_exec(conn, |tx| {
+        tx.execute(
+            "CREATE TABLE feerate_samples (id INTEGER PRIMARY KEY NOT NULL, timestamp INTEGER NOT NULL, conf_target INTEGER NOT NULL, feerate INTEGER NOT NULL)",
+            rusqlite::params![],
+        )?;
+        tx.execute("UPDATE version SET version = 8", rusqlite::params![])?;
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+fn migrate_v8_to_v9(conn: &mut rusqlite::Connection) -> Result<(), SqliteDbError> {
+    db_exec(conn, |tx| {
+        tx.execute(
+            "CREATE TABLE replacement_drafts (id INTEGER PRIMARY KEY NOT NULL, txid BLOB NOT NULL, replacement_txid BLOB NOT NULL, UNIQUE (txid, replacement_txid))",
+            rusqlite::params![],
+        )?;
+        tx.execute("UPDATE version SET version = 9", rusqlite::params![])?;
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+// Record the height at which coins were dropped, to stop watching them after a while.
+fn migrate_v9_to_v10(conn: &mut rusqlite::Connection) -> Result<(), SqliteDbError> {
+    db_exec(conn, |tx| {
+        tx.execute(
+            "ALTER TABLE dropped_coins ADD COLUMN dropped_height INTEGER NOT NULL DEFAULT 0",
+            rusqlite::params![],
+        )?;
+        // We don't know when the coins already recorded were dropped. Watch them from now on.
+        tx.execute(
+            "UPDATE dropped_coins SET dropped_height = COALESCE((SELECT blockheight FROM tip), 0)",
+            rusqlite::params![],
+        )?;
+        tx.execute("UPDATE version SET version = 10", rusqlite::params![])?;
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
 /// Check the database version and if necessary apply the migrations to upgrade it to the current
 /// one.
-pub fn maybe_apply_migration(db_path: &path::Path) -> Result<(), SqliteDbError> {
-    let mut conn = rusqlite::Connection::open(db_path)?;
+pub fn maybe_apply_migration(
+    db_path: &path::Path,
+    encryption_key: Option<&DbEncryptionKey>,
+) -> Result<(), SqliteDbError> {
+    let mut conn = open_connection(db_path, encryption_key)?;
 
     // Iteratively apply the database migrations necessary.
     loop {
@@ -262,6 +381,36 @@ pub fn maybe_apply_migration(db_path: &path::Path) -> Result<(), SqliteDbError>
                 migrate_v3_to_v4(&mut conn)?;
                 log::warn!("Migration from database version 3 to version 4 successful.");
             }
+            4 => {
+                log::warn!("Upgrading database from version 4 to version 5.");
+                migrate_v4_to_v5(&mut conn)?;
+                log::warn!("Migration from database version 4 to version 5 successful.");
+            }
+            5 => {
+                log::warn!("Upgrading database from version 5 to version 6.");
+                migrate_v5_to_v6(&mut conn)?;
+                log::warn!("Migration from database version 5 to version 6 successful.");
+            }
+            6 => {
+                log::warn!("Upgrading database from version 6 to version 7.");
+                migrate_v6_to_v7(&mut conn)?;
+                log::warn!("Migration from database version 6 to version 7 successful.");
+            }
+            7 => {
+                log::warn!("Upgrading database from version 7 to version 8.");
+                migrate_v7_to_v8(&mut conn)?;
+                log::warn!("Migration from database version 7 to version 8 successful.");
+            }
+            8 => {
+                log::warn!("Upgrading database from version 8 to version 9.");
+                migrate_v8_to_v9(&mut conn)?;
+                log::warn!("Migration from database version 8 to version 9 successful.");
+            }
+            9 => {
+                log::warn!("Upgrading database from version 9 to version 10.");
+                migrate_v9_to_v10(&mut conn)?;
+                log::warn!("Migration from database version 9 to version 10 successful.");
+            }
             _ => return Err(SqliteDbError::UnsupportedVersion(version)),
         }
     }
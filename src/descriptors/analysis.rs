@@ -258,6 +258,15 @@ impl PathInfo {
         }
     }
 
+    /// Get the required number of keys for spending through this path, and the keys that can be
+    /// used to provide a signature for this path.
+    pub fn thresh_keys(&self) -> (usize, Vec<&descriptor::DescriptorPublicKey>) {
+        match self {
+            PathInfo::Single(key) => (1, vec![key]),
+            PathInfo::Multi(k, keys) => (*k, keys.iter().collect()),
+        }
+    }
+
     /// Add another available key to this `PathInfo`. Note this doesn't change the threshold.
     pub fn with_added_key(mut self, key: descriptor::DescriptorPublicKey) -> Self {
         match self {
@@ -2,10 +2,11 @@ use miniscript::{
     bitcoin::{
         self, bip32,
         constants::WITNESS_SCALE_FACTOR,
+        hashes::{sha256, Hash, HashEngine},
         psbt::{Input as PsbtIn, Output as PsbtOut, Psbt},
         secp256k1,
     },
-    descriptor,
+    descriptor, plan,
     psbt::{PsbtInputExt, PsbtOutputExt},
     translate_hash_clone, ForEachKey, TranslatePk, Translator,
 };
@@ -79,6 +80,41 @@ fn key_is_for_path(
     false
 }
 
+// The assets available to satisfy a spending path: signatures for its keys and its relative
+// timelock, if any.
+struct PathAssets {
+    keys: HashSet<descriptor::DefiniteDescriptorKey>,
+    timelock: Option<bitcoin::Sequence>,
+}
+
+impl plan::AssetProvider<descriptor::DefiniteDescriptorKey> for PathAssets {
+    fn provider_lookup_ecdsa_sig(&self, pk: &descriptor::DefiniteDescriptorKey) -> bool {
+        self.keys.contains(pk)
+    }
+
+    fn provider_lookup_tap_key_spend_sig(
+        &self,
+        pk: &descriptor::DefiniteDescriptorKey,
+    ) -> Option<usize> {
+        // We always sign with SIGHASH_DEFAULT.
+        self.keys.contains(pk).then_some(64)
+    }
+
+    fn provider_lookup_tap_leaf_script_sig(
+        &self,
+        pk: &descriptor::DefiniteDescriptorKey,
+        _: &bitcoin::TapLeafHash,
+    ) -> Option<usize> {
+        self.keys.contains(pk).then_some(64)
+    }
+
+    fn check_older(&self, seq: bitcoin::Sequence) -> bool {
+        self.timelock
+            .map(|tl| seq.is_height_locked() && seq <= tl)
+            .unwrap_or(false)
+    }
+}
+
 /// An [SinglePathLianaDesc] that contains multipath keys for (and only for) the receive keychain
 /// and the change keychain.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -211,6 +247,31 @@ impl LianaDescriptor {
         &self.change_desc
     }
 
+    /// A stable identifier for the wallet using this descriptor: the SHA256 of the receive and
+    /// change descriptors.
+    pub fn wallet_id(&self) -> sha256::Hash {
+        let mut engine = sha256::Hash::engine();
+        engine.input(self.receive_desc.to_string().as_bytes());
+        engine.input(self.change_desc.to_string().as_bytes());
+        sha256::Hash::from_engine(engine)
+    }
+
+    /// The xpubs contained in this descriptor along with their origin, as expected in the PSBT
+    /// global xpub field. Signing devices use it to recognize outputs paying back to the wallet.
+    /// Keys without an origin (such as the unspendable Taproot internal key) are not included.
+    pub fn global_xpubs(&self) -> BTreeMap<bip32::Xpub, bip32::KeySource> {
+        let mut xpubs = BTreeMap::new();
+        self.multi_desc.for_each_key(|key| {
+            if let descriptor::DescriptorPublicKey::MultiXPub(xpub) = key {
+                if let Some(ref origin) = xpub.origin {
+                    xpubs.insert(xpub.xkey, origin.clone());
+                }
+            }
+            true
+        });
+        xpubs
+    }
+
     /// Get the spending policy of this descriptor.
     pub fn policy(&self) -> LianaPolicy {
         LianaPolicy::from_multipath_descriptor(&self.multi_desc)
@@ -260,6 +321,54 @@ impl LianaDescriptor {
             .unwrap()
     }
 
+    /// Get the size difference of a transaction input spending a Script derived from this
+    /// descriptor before and after satisfaction through the given spending path: the recovery path
+    /// with this timelock, or the primary path if `None`. The returned value is in weight units.
+    /// Contrary to [`Self::max_sat_weight`] this isn't the worst case among all spending paths.
+    /// This takes into account the size of the witness stack length varint.
+    ///
+    /// Returns `None` if there is no recovery path with this timelock.
+    pub fn path_sat_weight(&self, timelock: Option<u16>) -> Option<usize> {
+        let policy = self.policy();
+        let path_info = match timelock {
+            Some(tl) => policy.recovery_paths().get(&tl)?,
+            None => policy.primary_path(),
+        };
+        // The size of the satisfaction doesn't depend on the derivation index. Keys are matched
+        // exactly rather than by origin, as a signer may be used in more than one spending path.
+        let (_, keys) = path_info.thresh_keys();
+        let assets = PathAssets {
+            keys: keys
+                .into_iter()
+                .map(|key| {
+                    key.clone()
+                        .into_single_keys()
+                        .swap_remove(0)
+                        .at_derivation_index(0)
+                        .expect("Unhardened index")
+                })
+                .collect(),
+            timelock: timelock.map(bitcoin::Sequence::from_height),
+        };
+        let desc = self
+            .receive_desc
+            .0
+            .at_derivation_index(0)
+            .expect("Unhardened index");
+        // Under P2WSH the witness script isn't part of the plan's witness, contrary to the leaf
+        // script and control block under Taproot.
+        let witness_script_size = if self.is_taproot() {
+            0
+        } else {
+            let script_len = desc.explicit_script().expect("Not Taproot").len();
+            bitcoin::VarInt(script_len as u64).size() + script_len
+        };
+        let plan = desc
+            .plan(&assets)
+            .expect("Each spending path is satisfiable with its own keys.");
+        Some(plan.witness_size() + witness_script_size)
+    }
+
     /// Get the maximum size in virtual bytes of the whole input in a transaction spending
     /// a coin with this Script.
     pub fn spender_input_size(&self) -> usize {
@@ -611,6 +720,17 @@ impl DerivedSinglePathLianaDesc {
             .collect()
     }
 
+    /// The public keys of this derived descriptor along with their origin, for both wsh() and
+    /// tr() descriptors.
+    pub fn keys_origins(&self) -> Bip32Deriv {
+        let mut keys = Bip32Deriv::new();
+        self.0.for_each_key(|k| {
+            keys.insert(k.key.inner, k.origin.clone());
+            true
+        });
+        keys
+    }
+
     // FIXME: update_with_descriptor() needs a Descriptor<DefiniteKey>. This is a temporary hack to
     // avoid having to duplicate the cumbersome logic here. Could use translate_pk() instead in the
     // future.
@@ -1037,6 +1157,66 @@ mod tests {
         assert!(!psbt_in.bip32_derivation.is_empty());
     }
 
+    #[test]
+    fn multipath_descriptor_derivation() {
+        let secp = secp256k1::Secp256k1::verification_only();
+        let desc_str = "wsh(andor(pk([abcdef01]tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr/<0;1>/*),older(10000),pk([abcdef01]tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/<0;1>/*)))#2qj59a9y";
+        let desc = LianaDescriptor::from_str(desc_str).unwrap();
+        // The multipath descriptor is rendered as it was parsed.
+        assert_eq!(desc.to_string(), desc_str);
+
+        // The receive and change descriptors are the first and second paths of the multipath
+        // descriptor, respectively.
+        let desc_no_checksum = desc_str.split('#').next().unwrap();
+        let receive_desc = descriptor::Descriptor::<descriptor::DescriptorPublicKey>::from_str(
+            &desc_no_checksum.replace("<0;1>", "0"),
+        )
+        .unwrap();
+        let change_desc = descriptor::Descriptor::<descriptor::DescriptorPublicKey>::from_str(
+            &desc_no_checksum.replace("<0;1>", "1"),
+        )
+        .unwrap();
+        assert_eq!(desc.receive_descriptor(), &receive_desc);
+        assert_eq!(desc.change_descriptor(), &change_desc);
+        for index in [0, 1, 11, 1_000] {
+            assert_eq!(
+                desc.receive_descriptor()
+                    .derive(index.into(), &secp)
+                    .script_pubkey(),
+                receive_desc
+                    .at_derivation_index(index)
+                    .unwrap()
+                    .script_pubkey()
+            );
+            assert_eq!(
+                desc.change_descriptor()
+                    .derive(index.into(), &secp)
+                    .script_pubkey(),
+                change_desc
+                    .at_derivation_index(index)
+                    .unwrap()
+                    .script_pubkey()
+            );
+        }
+
+        // The same holds for paths other than 0 and 1, the receive one being the first.
+        let desc = LianaDescriptor::from_str(&desc_no_checksum.replace("<0;1>", "<2;3>")).unwrap();
+        assert_eq!(
+            desc.receive_descriptor(),
+            &descriptor::Descriptor::<descriptor::DescriptorPublicKey>::from_str(
+                &desc_no_checksum.replace("<0;1>", "2")
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            desc.change_descriptor(),
+            &descriptor::Descriptor::<descriptor::DescriptorPublicKey>::from_str(
+                &desc_no_checksum.replace("<0;1>", "3")
+            )
+            .unwrap()
+        );
+    }
+
     #[test]
     fn inheritance_descriptor_tl_value() {
         // Must always contain at least one timelocked path.
@@ -1076,6 +1256,20 @@ mod tests {
             desc.spender_input_size(),
             32 + 4 + 1 + 4 + wu_to_vb(witness_size),
         );
+
+        // Through the recovery path this is the maximum satisfaction size. Through the primary
+        // path it's only the witness stack size, the signature and the script.
+        assert_eq!(desc.path_sat_weight(Some(2)), Some(witness_size));
+        assert_eq!(desc.path_sat_weight(Some(2)), Some(desc.max_sat_weight()));
+        assert_eq!(desc.path_sat_weight(None), Some(1 + 73 + 66));
+        assert_eq!(desc.path_sat_weight(Some(3)), None);
+
+        // Under Taproot a single primary key is used through the key path: the witness is only
+        // made of the stack size and a signature.
+        let desc = LianaDescriptor::from_str("tr([f5acc2fd]tpubD6NzVbkrYhZ4YgUx2ZLNt2rLYAMTdYysCRzKoLu2BeSHKvzqPaBDvf17GeBPnExUVPkuBpx4kniP964e2MxyzzazcXLptxLXModSVCVEV1T/<0;1>/*,and_v(v:pkh([8a64f2a9]tpubD6NzVbkrYhZ4WmzFjvQrp7sDa4ECUxTi9oby8K4FZkd3XCBtEdKwUiQyYJaxiJo5y42gyDWEczrFpozEjeLxMPxjf2WtkfcbpUdfvNnozWF/<0;1>/*),older(10)))").unwrap();
+        assert_eq!(desc.path_sat_weight(None), Some(1 + 1 + 64));
+        let recov_weight = desc.path_sat_weight(Some(10)).unwrap();
+        assert!(recov_weight > 1 + 1 + 64 && recov_weight <= desc.max_sat_weight());
     }
 
     #[test]
@@ -1209,6 +1403,53 @@ mod tests {
         roundtrip("tr(xpub661MyMwAqRbcGqmqNapgQ9kqrLcDeZLHPktzsBcZXTtNx7aEay8NKQPizKcpu2fUejNbZzhZQaZLeDWL3nt8zg9QbFLBUTRQu4qqcSzeEmF/<0;1>/*,{{and_v(v:multi_a(1,[b4e32970]xpub661MyMwAqRbcEbs6ohRoUqTckEfLeT3vB2EsuWuckrEuDSKqdFXV6so8xJb4kvA4ZxT6hCydyFKsKwJrDm2LgSfTCphVqZgQbLzF49KwaXc/<0;1>/*,[c318e87f]xpub661MyMwAqRbcG2qnrFJ2MhKFSHehbVkK38gFfG7zXwasN51dKrL4kffj1HRd2zFhAZeQsjYKS8YaiN4sC4gVPHR28qXdQf7pf7nbYoefg6T/<0;1>/*),older(1678)),{and_v(v:pk([6c0d38a3]xpub661MyMwAqRbcF87hAvenL8GHW7qxhtn8Y9zHVkQbuTsd6RVtWkhBY5gh6m4Rua9ENmYDx7jTb8kbiyVB9iaLAbyRudxPFVTFoGPp6rTqoZn/<0;1>/*),older(42)),and_v(v:multi_a(2,[2e1370a6]xpub661MyMwAqRbcGRzCgSNLW7VFUFdwvC1dFXmKgWbZwQERj2QfNQuy5diCQSHNXuQYSS9FwXykLeWKtnZ5yRJ4ZHZzYqWf13FUY4PbDpBhipr/<0;1>/*,[fae2633e]xpub661MyMwAqRbcG9qKwZ7F363Mx3Ai3H2aMXAWTjvYCZrH4wqDEDLnsVghWFrwTKwpDGGzsSDCL7vPTiaiY7DhhdV2bY6RdPNGd7bF9om1MFz/<0;1>/*,[2ae87e33]xpub661MyMwAqRbcGw8ZvGfdLEjhCk4YC9hZrrUceKipiH32ANDMQccYFqq91kH8RpcwGiPnCbUWFo1S6ZGY2GxbVdJFsMYXqzpL1byJ1D3G2Mh/<0;1>/*),older(43))}},multi_a(2,[40f48611]xpub661MyMwAqRbcGUkDb45NBcMYwaaSE3fhsMNwvdf2psYhrqhFmRJY9n8irJuEB3juhK5LQPBiiqdr2gixMmC7Nmtg3Mwu4C5wbeagaAzbb9W/<0;1>/*,[a2bdfbe5]xpub661MyMwAqRbcH228eUBaJvc7Va1y7cGyEH9DZ5vPneKgZDX8eMsSd8PHS3uRYCFySyHPy3VfGfS8vKb5FzcS2MbNorNVv2c3Hn7AvVJJZ73/<0;1>/*,[028ece7a]xpub661MyMwAqRbcG9W1pZzs7rvWVtHeW1anzABj8iQRBbnz8yLf7vgUmYkVsydLf1hLffibgfzUjTBcrNCDKaBNnuqLtsp1xyiLSZJyLDtEjkF/<0;1>/*)})#xgzxdvrv");
     }
 
+    #[test]
+    fn wallet_id() {
+        let desc_str = "wsh(or_d(pk([aabbccdd]xpub6Eze7yAT3Y1wGrnzedCNVYDXUqa9NmHVWck5emBaTbXtURbe1NWZbK9bsz1TiVE7Cz341PMTfYgFw1KdLWdzcM1UMFTcdQfCYhhXZ2HJvTW/<0;1>/*),and_v(v:pkh([aabbccdd]xpub688Hn4wScQAAiYJLPg9yH27hUpfZAUnmJejRQBCiwfP5PEDzjWMNW1wChcninxr5gyavFqbbDjdV1aK5USJz8NDVjUy7FRQaaqqXHh5SbXe/<0;1>/*),older(52560))))";
+        let desc = LianaDescriptor::from_str(desc_str).unwrap();
+        assert_eq!(
+            desc.wallet_id().to_string(),
+            "c860fd31737d2ea8b482cba735f5f18cf50f07d43348109394805c1a88af4a69"
+        );
+        // It doesn't depend on the presence of the checksum.
+        let desc_checksum = LianaDescriptor::from_str(&desc.to_string()).unwrap();
+        assert_eq!(desc.wallet_id(), desc_checksum.wallet_id());
+        // But it depends on the policy.
+        let other_desc =
+            LianaDescriptor::from_str(&desc_str.replace("older(52560)", "older(52561)")).unwrap();
+        assert_ne!(desc.wallet_id(), other_desc.wallet_id());
+    }
+
+    #[test]
+    fn global_xpubs() {
+        // A multisig under P2WSH, each xpub is used for both the primary and the recovery path.
+        let desc = LianaDescriptor::from_str("wsh(or_d(multi(3,[aabb0011/48'/0'/0'/2']xpub6Eze7yAT3Y1wGrnzedCNVYDXUqa9NmHVWck5emBaTbXtURbe1NWZbK9bsz1TiVE7Cz341PMTfYgFw1KdLWdzcM1UMFTcdQfCYhhXZ2HJvTW/0/<0;1>/*,[aabb0012/48'/0'/0'/2']xpub6Bw79HbNSeS2xXw1sngPE3ehnk1U3iSPCgLYzC9LpN8m9nDuaKLZvkg8QXxL5pDmEmQtYscmUD8B9MkAAZbh6vxPzNXMaLfGQ9Sb3z85qhR/0/<0;1>/*,[aabb0013/48'/0'/0'/2']xpub67zuTXF9Ln4731avKTBSawoVVNRuMfmRvkL7kLUaLBRqma9ZqdHBJg9qx8cPUm3oNQMiXT4TmGovXNoQPuwg17RFcVJ8YrnbcooN7pxVJqC/0/<0;1>/*),and_v(v:thresh(2,pkh([aabb0011/48'/0'/0'/2']xpub6Eze7yAT3Y1wGrnzedCNVYDXUqa9NmHVWck5emBaTbXtURbe1NWZbK9bsz1TiVE7Cz341PMTfYgFw1KdLWdzcM1UMFTcdQfCYhhXZ2HJvTW/1/<0;1>/*),a:pkh([aabb0012/48'/0'/0'/2']xpub6Bw79HbNSeS2xXw1sngPE3ehnk1U3iSPCgLYzC9LpN8m9nDuaKLZvkg8QXxL5pDmEmQtYscmUD8B9MkAAZbh6vxPzNXMaLfGQ9Sb3z85qhR/1/<0;1>/*),a:pkh([aabb0013/48'/0'/0'/2']xpub67zuTXF9Ln4731avKTBSawoVVNRuMfmRvkL7kLUaLBRqma9ZqdHBJg9qx8cPUm3oNQMiXT4TmGovXNoQPuwg17RFcVJ8YrnbcooN7pxVJqC/1/<0;1>/*)),older(26352))))").unwrap();
+        let origin_path = bip32::DerivationPath::from_str("m/48'/0'/0'/2'").unwrap();
+        let expected: BTreeMap<_, _> = [
+            ("xpub6Eze7yAT3Y1wGrnzedCNVYDXUqa9NmHVWck5emBaTbXtURbe1NWZbK9bsz1TiVE7Cz341PMTfYgFw1KdLWdzcM1UMFTcdQfCYhhXZ2HJvTW", "aabb0011"),
+            ("xpub6Bw79HbNSeS2xXw1sngPE3ehnk1U3iSPCgLYzC9LpN8m9nDuaKLZvkg8QXxL5pDmEmQtYscmUD8B9MkAAZbh6vxPzNXMaLfGQ9Sb3z85qhR", "aabb0012"),
+            ("xpub67zuTXF9Ln4731avKTBSawoVVNRuMfmRvkL7kLUaLBRqma9ZqdHBJg9qx8cPUm3oNQMiXT4TmGovXNoQPuwg17RFcVJ8YrnbcooN7pxVJqC", "aabb0013"),
+        ]
+        .iter()
+        .map(|(xpub, fg)| {
+            (
+                bip32::Xpub::from_str(xpub).unwrap(),
+                (bip32::Fingerprint::from_str(fg).unwrap(), origin_path.clone()),
+            )
+        })
+        .collect();
+        assert_eq!(desc.global_xpubs(), expected);
+
+        // Under Taproot the unspendable internal key has no origin and isn't included.
+        let desc = LianaDescriptor::from_str("tr(tpubD6NzVbkrYhZ4YdBUPkUhDYj6Sd1QK8vgiCf5RwHnAnSNK5ozemAZzPTYZbgQq4diod7oxFJJYGa8FNRHzRo7URkixzQTuudh38xRRdSc4Hu/<0;1>/*,{and_v(v:multi_a(1,[ffd63c8d/48'/1'/0'/2']tpubDExA3EC3iAsPxPhFn4j6gMiVup6V2eH3qKyk69RcTc9TTNRfFYVPad8bJD5FCHVQxyBT4izKsvr7Btd2R4xmQ1hZkvsqGBaeE82J71uTK4N/<2;3>/*,[da2ee873/48'/1'/0'/2']tpubDEbXY6RbN9mxAvQW797WxReGGkrdyRfdYcehVVaQQcQ3kyfhxSMcnU9qGpUVRHXXALvBtc99jcuxx5tkzcLaJbAukSNpP9h2ti4XFRosv1g/<2;3>/*),older(2)),multi_a(2,[ffd63c8d/48'/1'/0'/2']tpubDExA3EC3iAsPxPhFn4j6gMiVup6V2eH3qKyk69RcTc9TTNRfFYVPad8bJD5FCHVQxyBT4izKsvr7Btd2R4xmQ1hZkvsqGBaeE82J71uTK4N/<0;1>/*,[da2ee873/48'/1'/0'/2']tpubDEbXY6RbN9mxAvQW797WxReGGkrdyRfdYcehVVaQQcQ3kyfhxSMcnU9qGpUVRHXXALvBtc99jcuxx5tkzcLaJbAukSNpP9h2ti4XFRosv1g/<0;1>/*)})").unwrap();
+        let global_xpubs = desc.global_xpubs();
+        assert_eq!(global_xpubs.len(), 2);
+        let origin_path = bip32::DerivationPath::from_str("m/48'/1'/0'/2'").unwrap();
+        assert!(global_xpubs
+            .values()
+            .all(|(_, der_path)| *der_path == origin_path));
+    }
+
     fn psbt_from_str(psbt_str: &str) -> Psbt {
         Psbt::from_str(psbt_str).unwrap()
     }
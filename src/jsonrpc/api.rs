@@ -1,32 +1,97 @@
 use crate::{
-    commands::{CoinStatus, LabelItem},
+    commands::{
+        CoinSelector, CoinStatus, CommandError, CreateSpendOptions, FeePriority, LabelItem,
+        SelectionWeights, SnapshotParts, SpendRef, SpendStatus, WalletSnapshot,
+    },
+    config::DbEncryptionKey,
     jsonrpc::{Error, Params, Request, Response},
+    spend::{DraftId, Feerate, ForeignInput, OutputSeed},
     DaemonControl,
 };
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{hash_map, HashMap, HashSet},
     convert::TryInto,
     str::FromStr,
 };
 
-use miniscript::bitcoin::{self, psbt::Psbt, Txid};
+use miniscript::bitcoin::{self, bip32, hashes::hex::FromHex, psbt::Psbt, Txid};
+
+// Parse a foreign input given as an object with its outpoint, the amount and script of the coin
+// it spends, and its maximum satisfaction weight.
+fn foreign_input(input: &serde_json::Value) -> Option<ForeignInput> {
+    let outpoint = input
+        .get("outpoint")?
+        .as_str()
+        .and_then(|op| bitcoin::OutPoint::from_str(op).ok())?;
+    let value = bitcoin::Amount::from_sat(input.get("amount")?.as_u64()?);
+    let script_pubkey = input
+        .get("script_pubkey")?
+        .as_str()
+        .and_then(|spk| bitcoin::ScriptBuf::from_hex(spk).ok())?;
+    let max_sat_weight = input
+        .get("max_sat_weight")?
+        .as_u64()
+        .and_then(|w| w.try_into().ok())?;
+    Some(ForeignInput {
+        outpoint,
+        witness_utxo: bitcoin::TxOut {
+            value,
+            script_pubkey,
+        },
+        max_sat_weight,
+    })
+}
 
 fn create_spend(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
-    let destinations = params
+    // The value of each destination is either an amount or an object with an amount and a label.
+    // Different spellings of an address (such as upper and lower case Bech32) parse to the same
+    // destination: they are merged below. They may only carry a label if it's the same one.
+    let mut destination_labels = HashMap::new();
+    let mut conflicting_label = None;
+    let destinations_list = params
         .get(0, "destinations")
         .ok_or_else(|| Error::invalid_params("Missing 'destinations' parameter."))?
         .as_object()
         .and_then(|obj| {
             obj.into_iter()
                 .map(|(k, v)| {
-                    let addr = bitcoin::Address::from_str(k).ok()?;
-                    let amount: u64 = v.as_i64()?.try_into().ok()?;
+                    let addr: bitcoin::Address<bitcoin::address::NetworkUnchecked> =
+                        bitcoin::Address::from_str(k).ok()?;
+                    let amount = match v {
+                        serde_json::Value::Object(dest) => {
+                            match dest.get("label") {
+                                Some(serde_json::Value::String(l)) if l.len() <= 100 => {
+                                    match destination_labels.entry(addr.clone()) {
+                                        hash_map::Entry::Vacant(entry) => {
+                                            entry.insert(l.to_string());
+                                        }
+                                        hash_map::Entry::Occupied(entry) if entry.get() != l => {
+                                            conflicting_label = Some(addr.clone());
+                                            return None;
+                                        }
+                                        hash_map::Entry::Occupied(_) => {}
+                                    }
+                                }
+                                None => {}
+                                _ => return None,
+                            }
+                            dest.get("amount")?
+                        }
+                        v => v,
+                    };
+                    let amount: u64 = amount.as_i64()?.try_into().ok()?;
                     Some((addr, amount))
                 })
-                .collect::<Option<HashMap<bitcoin::Address<bitcoin::address::NetworkUnchecked>, u64>>>()
+                .collect::<Option<Vec<(bitcoin::Address<bitcoin::address::NetworkUnchecked>, u64)>>>()
         })
-        .ok_or_else(|| Error::invalid_params("Invalid 'destinations' parameter."))?;
+        .ok_or_else(|| match conflicting_label {
+            Some(addr) => Error::invalid_params(format!(
+                "Conflicting labels for destination '{}'.",
+                addr.assume_checked()
+            )),
+            None => Error::invalid_params("Invalid 'destinations' parameter."),
+        })?;
     let outpoints = params
         .get(1, "outpoints")
         .ok_or_else(|| Error::invalid_params("Missing 'outpoints' parameter."))?
@@ -41,11 +106,23 @@ fn create_spend(control: &DaemonControl, params: Params) -> Result<serde_json::V
                 .collect::<Option<Vec<bitcoin::OutPoint>>>()
         })
         .ok_or_else(|| Error::invalid_params("Invalid 'outpoints' parameter."))?;
-    let feerate: u64 = params
+    // The feerate is either a number of sats/vb or a priority to be resolved to a feerate.
+    let feerate = params
         .get(2, "feerate")
-        .ok_or_else(|| Error::invalid_params("Missing 'feerate' parameter."))?
-        .as_u64()
-        .ok_or_else(|| Error::invalid_params("Invalid 'feerate' parameter."))?;
+        .ok_or_else(|| Error::invalid_params("Missing 'feerate' parameter."))
+        .and_then(|f| match f {
+            serde_json::Value::String(p) => FeePriority::from_arg(p)
+                .map(|p| control.priority_feerate(p))
+                .ok_or_else(|| {
+                    Error::invalid_params(
+                        "Invalid 'feerate' parameter: priority must be one of 'fast', 'normal' or 'slow'.",
+                    )
+                }),
+            _ => f
+                .as_f64()
+                .and_then(Feerate::from_sat_per_vb_f64)
+                .ok_or_else(|| Error::invalid_params("Invalid 'feerate' parameter.")),
+        })?;
     let change_address: Option<bitcoin::Address<bitcoin::address::NetworkUnchecked>> = params
         .get(3, "change_address")
         .map(|addr| {
@@ -58,7 +135,288 @@ fn create_spend(control: &DaemonControl, params: Params) -> Result<serde_json::V
         })
         .transpose()?;
 
-    let res = control.create_spend(&destinations, &outpoints, feerate, change_address)?;
+    // The options which were added after the above parameters may only be given by name.
+    if let Params::Array(params) = &params {
+        if params.len() > 4 {
+            return Err(Error::invalid_params(
+                "The optional parameters of 'createspend' besides 'change_address' must be given by name.",
+            ));
+        }
+    }
+    let option = |name: &str| match &params {
+        Params::Map(map) => map.get(name),
+        Params::Array(_) => None,
+    };
+    let force_path: Option<usize> = option("force_path")
+        .map(|p| {
+            p.as_u64()
+                .and_then(|p| p.try_into().ok())
+                .ok_or_else(|| Error::invalid_params("Invalid 'force_path' parameter."))
+        })
+        .transpose()?;
+    let coins_label = option("coins_label")
+        .map(|label| {
+            label.as_str().map(|l| l.to_string()).ok_or_else(|| {
+                Error::invalid_params("Invalid 'coins_label' parameter: must be a string.")
+            })
+        })
+        .transpose()?;
+    let privacy_aware = option("privacy_aware")
+        .map(|p| {
+            p.as_bool()
+                .ok_or_else(|| Error::invalid_params("Invalid 'privacy_aware' parameter."))
+        })
+        .transpose()?
+        .unwrap_or(false);
+    let idempotency_key = option("idempotency_key")
+        .map(|p| {
+            p.as_str()
+                .map(|s| s.to_string())
+                .ok_or_else(|| Error::invalid_params("Invalid 'idempotency_key' parameter."))
+        })
+        .transpose()?;
+    let selection_weights: Option<SelectionWeights> = option("selection_weights")
+        .map(|w| {
+            serde_json::from_value(w.clone()).map_err(|e| {
+                Error::invalid_params(format!("Invalid 'selection_weights' parameter: {}.", e))
+            })
+        })
+        .transpose()?;
+    let allow_unconfirmed = option("allow_unconfirmed")
+        .map(|p| {
+            p.as_bool()
+                .ok_or_else(|| Error::invalid_params("Invalid 'allow_unconfirmed' parameter."))
+        })
+        .transpose()?
+        .unwrap_or(true);
+    let allow_unconfirmed_external = option("allow_unconfirmed_external")
+        .map(|p| {
+            p.as_bool().ok_or_else(|| {
+                Error::invalid_params("Invalid 'allow_unconfirmed_external' parameter.")
+            })
+        })
+        .transpose()?
+        .unwrap_or(false);
+    let label = option("label")
+        .map(|l| match l.as_str() {
+            Some(l) if l.len() <= 100 => Ok(l.to_string()),
+            _ => Err(Error::invalid_params(
+                "Invalid 'label' parameter: must be a string of at most 100 characters.",
+            )),
+        })
+        .transpose()?;
+    let split_change = option("split_change")
+        .map(|n| {
+            n.as_u64()
+                .and_then(|n| n.try_into().ok())
+                .ok_or_else(|| Error::invalid_params("Invalid 'split_change' parameter."))
+        })
+        .transpose()?;
+    let op_return = option("op_return")
+        .map(|d| match d {
+            serde_json::Value::Array(_) => Err(Error::invalid_params(
+                "Invalid 'op_return' parameter: only a single OP_RETURN output is supported.",
+            )),
+            _ => d
+                .as_str()
+                .and_then(|d| Vec::from_hex(d).ok())
+                .ok_or_else(|| {
+                    Error::invalid_params("Invalid 'op_return' parameter: must be a hex string.")
+                }),
+        })
+        .transpose()?;
+    let output_seed = option("output_seed")
+        .map(|seed| {
+            seed.as_str()
+                .and_then(|s| OutputSeed::from_str(s).ok())
+                .ok_or_else(|| {
+                    Error::invalid_params(
+                        "Invalid 'output_seed' parameter: must be a 32 bytes hex string.",
+                    )
+                })
+        })
+        .transpose()?;
+    let reserve_inputs = option("reserve_inputs")
+        .map(|p| {
+            p.as_bool()
+                .ok_or_else(|| Error::invalid_params("Invalid 'reserve_inputs' parameter."))
+        })
+        .transpose()?
+        .unwrap_or(true);
+    let exclude_outpoints = option("exclude_outpoints")
+        .map(|ops| {
+            ops.as_array()
+                .and_then(|arr| {
+                    arr.iter()
+                        .map(|entry| {
+                            entry
+                                .as_str()
+                                .and_then(|e| bitcoin::OutPoint::from_str(e).ok())
+                        })
+                        .collect::<Option<Vec<bitcoin::OutPoint>>>()
+                })
+                .ok_or_else(|| Error::invalid_params("Invalid 'exclude_outpoints' parameter."))
+        })
+        .transpose()?
+        .unwrap_or_default();
+    let allow_self_send = option("allow_self_send")
+        .map(|p| {
+            p.as_bool()
+                .ok_or_else(|| Error::invalid_params("Invalid 'allow_self_send' parameter."))
+        })
+        .transpose()?
+        .unwrap_or(false);
+    let long_term_feerate_vb = option("long_term_feerate")
+        .map(|f| {
+            f.as_u64()
+                .ok_or_else(|| Error::invalid_params("Invalid 'long_term_feerate' parameter."))
+        })
+        .transpose()?;
+    let change_path = option("change_path")
+        .map(|p| {
+            p.as_str()
+                .and_then(|p| bip32::DerivationPath::from_str(p).ok())
+                .ok_or_else(|| Error::invalid_params("Invalid 'change_path' parameter."))
+        })
+        .transpose()?;
+    let extra_inputs = option("extra_inputs")
+        .map(|inputs| {
+            inputs
+                .as_array()
+                .and_then(|arr| arr.iter().map(foreign_input).collect::<Option<Vec<_>>>())
+                .ok_or_else(|| Error::invalid_params("Invalid 'extra_inputs' parameter."))
+        })
+        .transpose()?
+        .unwrap_or_default();
+    let max_inputs = option("max_inputs")
+        .map(|n| {
+            n.as_u64()
+                .filter(|n| *n > 0)
+                .and_then(|n| n.try_into().ok())
+                .ok_or_else(|| Error::invalid_params("Invalid 'max_inputs' parameter."))
+        })
+        .transpose()?;
+    let ignore_uneconomic = option("ignore_uneconomic")
+        .map(|p| {
+            p.as_bool()
+                .ok_or_else(|| Error::invalid_params("Invalid 'ignore_uneconomic' parameter."))
+        })
+        .transpose()?
+        .unwrap_or(false);
+    let allow_duplicate_destinations = option("allow_duplicate_destinations")
+        .map(|p| {
+            p.as_bool().ok_or_else(|| {
+                Error::invalid_params("Invalid 'allow_duplicate_destinations' parameter.")
+            })
+        })
+        .transpose()?
+        .unwrap_or(false);
+    let mut destinations = HashMap::with_capacity(destinations_list.len());
+    for (addr, amount) in destinations_list {
+        match destinations.entry(addr) {
+            hash_map::Entry::Vacant(entry) => {
+                entry.insert(amount);
+            }
+            hash_map::Entry::Occupied(entry) if !allow_duplicate_destinations => {
+                return Err(CommandError::DuplicateDestination(
+                    entry.key().clone().assume_checked(),
+                )
+                .into());
+            }
+            hash_map::Entry::Occupied(mut entry) => {
+                let total = entry
+                    .get()
+                    .checked_add(amount)
+                    .ok_or_else(|| Error::invalid_params("Invalid 'destinations' parameter."))?;
+                entry.insert(total);
+            }
+        }
+    }
+    let coin_selector = match (outpoints.is_empty(), coins_label) {
+        (true, None) => CoinSelector::All,
+        (true, Some(label)) => CoinSelector::Label(label),
+        (false, None) => CoinSelector::Outpoints(outpoints),
+        (false, Some(_)) => {
+            return Err(Error::invalid_params(
+                "Can't specify both 'outpoints' and 'coins_label'.",
+            ))
+        }
+    };
+
+    let res = control.create_spend_with_feerate(
+        &destinations,
+        &coin_selector,
+        feerate,
+        CreateSpendOptions {
+            change_address,
+            change_path,
+            force_path,
+            privacy_aware,
+            idempotency_key,
+            selection_weights,
+            allow_unconfirmed,
+            allow_unconfirmed_external,
+            label,
+            split_change,
+            op_return,
+            output_seed,
+            reserve_inputs,
+            destination_labels,
+            exclude_outpoints,
+            allow_self_send,
+            long_term_feerate_vb,
+            extra_inputs,
+            max_inputs,
+            ignore_uneconomic,
+        },
+    )?;
+    Ok(serde_json::json!(&res))
+}
+
+fn signing_requirements(
+    control: &DaemonControl,
+    params: Params,
+) -> Result<serde_json::Value, Error> {
+    let txid = params
+        .get(0, "txid")
+        .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?
+        .as_str()
+        .and_then(|s| bitcoin::Txid::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'txid' parameter."))?;
+    let res = control.signing_requirements(&txid)?;
+    Ok(serde_json::json!(&res))
+}
+
+fn final_fee_estimate(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let txid = params
+        .get(0, "txid")
+        .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?
+        .as_str()
+        .and_then(|s| bitcoin::Txid::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'txid' parameter."))?;
+    let res = control.final_fee_estimate(&txid)?;
+    Ok(serde_json::json!(&res))
+}
+
+fn get_tx_labels(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let txid = params
+        .get(0, "txid")
+        .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?
+        .as_str()
+        .and_then(|s| bitcoin::Txid::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'txid' parameter."))?;
+    let res = control.tx_related_labels(&txid)?;
+    Ok(serde_json::json!(&res))
+}
+
+fn inspect_psbt(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let psbt: Psbt = params
+        .get(0, "psbt")
+        .ok_or_else(|| Error::invalid_params("Missing 'psbt' parameter."))?
+        .as_str()
+        .and_then(|s| Psbt::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'psbt' parameter."))?;
+    let res = control.inspect_psbt(&psbt)?;
     Ok(serde_json::json!(&res))
 }
 
@@ -74,15 +432,108 @@ fn update_spend(control: &DaemonControl, params: Params) -> Result<serde_json::V
     Ok(serde_json::json!({}))
 }
 
+fn update_spend_destination(
+    control: &DaemonControl,
+    params: Params,
+) -> Result<serde_json::Value, Error> {
+    let txid = params
+        .get(0, "txid")
+        .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?
+        .as_str()
+        .and_then(|s| bitcoin::Txid::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'txid' parameter."))?;
+    let old_address = params
+        .get(1, "old_address")
+        .ok_or_else(|| Error::invalid_params("Missing 'old_address' parameter."))?
+        .as_str()
+        .and_then(|s| bitcoin::Address::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'old_address' parameter."))?;
+    let new_address = params
+        .get(2, "new_address")
+        .ok_or_else(|| Error::invalid_params("Missing 'new_address' parameter."))?
+        .as_str()
+        .and_then(|s| bitcoin::Address::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'new_address' parameter."))?;
+    let amount = params
+        .get(3, "amount")
+        .ok_or_else(|| Error::invalid_params("Missing 'amount' parameter."))?
+        .as_u64()
+        .ok_or_else(|| Error::invalid_params("Invalid 'amount' parameter."))?;
+    let res = control.update_spend_destination(&txid, &old_address, &new_address, amount)?;
+    Ok(serde_json::json!(&res))
+}
+
+fn export_spend_bbqr(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let txid = params
+        .get(0, "txid")
+        .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?
+        .as_str()
+        .and_then(|s| bitcoin::Txid::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'txid' parameter."))?;
+    let max_part_len: usize = params
+        .get(1, "max_part_len")
+        .ok_or_else(|| Error::invalid_params("Missing 'max_part_len' parameter."))?
+        .as_u64()
+        .and_then(|l| l.try_into().ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'max_part_len' parameter."))?;
+    let parts = control.export_spend_psbt_bbqr(&txid, max_part_len)?;
+    Ok(serde_json::json!({ "parts": parts }))
+}
+
+fn import_spend_bbqr(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let parts: Vec<String> = params
+        .get(0, "parts")
+        .ok_or_else(|| Error::invalid_params("Missing 'parts' parameter."))?
+        .as_array()
+        .and_then(|arr| {
+            arr.iter()
+                .map(|entry| entry.as_str().map(|e| e.to_string()))
+                .collect()
+        })
+        .ok_or_else(|| Error::invalid_params("Invalid 'parts' parameter."))?;
+    let txid = control.import_spend_psbt_bbqr(&parts)?;
+    Ok(serde_json::json!({ "txid": txid }))
+}
+
 fn delete_spend(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    // Either the txid of a Spend or the identifier of a Spend draft.
+    let spend_ref = params
+        .get(0, "txid")
+        .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?
+        .as_str()
+        .and_then(|s| {
+            bitcoin::Txid::from_str(s)
+                .map(SpendRef::Txid)
+                .or_else(|_| DraftId::from_str(s).map(SpendRef::DraftId))
+                .ok()
+        })
+        .ok_or_else(|| Error::invalid_params("Invalid 'txid' parameter."))?;
+    control.delete_spend(&spend_ref)?;
+
+    Ok(serde_json::json!({}))
+}
+
+fn test_broadcast_spend(
+    control: &DaemonControl,
+    params: Params,
+) -> Result<serde_json::Value, Error> {
     let txid = params
         .get(0, "txid")
         .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?
         .as_str()
         .and_then(|s| bitcoin::Txid::from_str(s).ok())
         .ok_or_else(|| Error::invalid_params("Invalid 'txid' parameter."))?;
-    control.delete_spend(&txid);
+    let res = control.test_broadcast_spend(&txid)?;
+    Ok(serde_json::json!(&res))
+}
 
+fn unlock(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let passphrase = params
+        .get(0, "passphrase")
+        .ok_or_else(|| Error::invalid_params("Missing 'passphrase' parameter."))?
+        .as_str()
+        .ok_or_else(|| Error::invalid_params("Invalid 'passphrase' parameter."))?;
+    control.unlock(passphrase)?;
     Ok(serde_json::json!({}))
 }
 
@@ -93,11 +544,107 @@ fn broadcast_spend(control: &DaemonControl, params: Params) -> Result<serde_json
         .as_str()
         .and_then(|s| bitcoin::Txid::from_str(s).ok())
         .ok_or_else(|| Error::invalid_params("Invalid 'txid' parameter."))?;
-    control.broadcast_spend(&txid)?;
+    let test_first = params
+        .get(1, "test_first")
+        .map(|p| {
+            p.as_bool()
+                .ok_or_else(|| Error::invalid_params("Invalid 'test_first' parameter."))
+        })
+        .transpose()?
+        .unwrap_or(false);
+    control.broadcast_spend(&txid, test_first)?;
+
+    Ok(serde_json::json!({}))
+}
+
+fn encrypt_database(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let key = params
+        .get(0, "key")
+        .ok_or_else(|| Error::invalid_params("Missing 'key' parameter."))?
+        .as_str()
+        .filter(|key| !key.is_empty())
+        .ok_or_else(|| Error::invalid_params("Invalid 'key' parameter."))?;
+    control.encrypt_database(&DbEncryptionKey(key.to_string()))?;
+
+    Ok(serde_json::json!({}))
+}
+
+fn rebroadcast_unconfirmed(control: &DaemonControl) -> Result<serde_json::Value, Error> {
+    let transactions: Vec<serde_json::Value> = control
+        .rebroadcast_unconfirmed()?
+        .into_iter()
+        .map(|(txid, res)| {
+            serde_json::json!({
+                "txid": txid.to_string(),
+                "error": res.err().map(|e| e.to_string()),
+            })
+        })
+        .collect();
+    Ok(serde_json::json!({ "transactions": transactions }))
+}
+
+fn get_mempool_status(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let txid = params
+        .get(0, "txid")
+        .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?
+        .as_str()
+        .and_then(|s| bitcoin::Txid::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'txid' parameter."))?;
+
+    Ok(serde_json::json!(&control.get_mempool_status(&txid)))
+}
+
+fn get_package_info(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let txid = params
+        .get(0, "txid")
+        .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?
+        .as_str()
+        .and_then(|s| bitcoin::Txid::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'txid' parameter."))?;
+    let res = control.package_info(&txid)?;
+
+    Ok(serde_json::json!(&res))
+}
+
+fn set_log_level(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let level = params
+        .get(0, "level")
+        .ok_or_else(|| Error::invalid_params("Missing 'level' parameter."))?
+        .as_str()
+        .and_then(|s| log::LevelFilter::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'level' parameter."))?;
+    control.set_log_level(level)?;
 
     Ok(serde_json::json!({}))
 }
 
+fn get_coin_address(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let outpoint = params
+        .get(0, "outpoint")
+        .ok_or_else(|| Error::invalid_params("Missing 'outpoint' parameter."))?
+        .as_str()
+        .and_then(|s| bitcoin::OutPoint::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'outpoint' parameter."))?;
+    let res = control.coin_address(&outpoint)?;
+
+    Ok(serde_json::json!(&res))
+}
+
+fn get_coin_funding_tx(
+    control: &DaemonControl,
+    params: Params,
+) -> Result<serde_json::Value, Error> {
+    let outpoint = params
+        .get(0, "outpoint")
+        .ok_or_else(|| Error::invalid_params("Missing 'outpoint' parameter."))?
+        .as_str()
+        .and_then(|s| bitcoin::OutPoint::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'outpoint' parameter."))?;
+    let res = control.coin_funding_tx(&outpoint)?;
+
+    Ok(serde_json::json!(&res))
+}
+
 fn rbf_psbt(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
     let txid = params
         .get(0, "txid")
@@ -110,25 +657,66 @@ fn rbf_psbt(control: &DaemonControl, params: Params) -> Result<serde_json::Value
         .ok_or_else(|| Error::invalid_params("Missing 'is_cancel' parameter."))?
         .as_bool()
         .ok_or_else(|| Error::invalid_params("Invalid 'is_cancel' parameter."))?;
-    let feerate_vb: Option<u64> = if let Some(feerate) = params.get(2, "feerate") {
+    let feerate: Option<Feerate> = if let Some(feerate) = params.get(2, "feerate") {
         Some(
             feerate
-                .as_u64()
+                .as_f64()
+                .and_then(Feerate::from_sat_per_vb_f64)
                 .ok_or_else(|| Error::invalid_params("Invalid 'feerate' parameter."))?,
         )
     } else {
         None
     };
-    let res = control.rbf_psbt(&txid, is_cancel, feerate_vb)?;
+    let fee = params
+        .get(3, "fee")
+        .map(|fee| {
+            fee.as_u64()
+                .map(bitcoin::Amount::from_sat)
+                .ok_or_else(|| Error::invalid_params("Invalid 'fee' parameter."))
+        })
+        .transpose()?;
+    let res = match fee {
+        Some(_) if is_cancel => {
+            return Err(Error::invalid_params(
+                "A fee must not be provided if creating a cancel.",
+            ))
+        }
+        Some(_) if feerate.is_some() => {
+            return Err(Error::invalid_params(
+                "Only one of 'feerate' and 'fee' may be provided.",
+            ))
+        }
+        Some(fee) => control.rbf_psbt_with_fee(&txid, fee)?,
+        None => control.rbf_psbt_with_feerate(&txid, is_cancel, feerate)?,
+    };
     Ok(serde_json::json!(&res))
 }
 
-fn list_coins(control: &DaemonControl, params: Option<Params>) -> Result<serde_json::Value, Error> {
+fn cancel_spend(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let txid = params
+        .get(0, "txid")
+        .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?
+        .as_str()
+        .and_then(|s| bitcoin::Txid::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'txid' parameter."))?;
+    let feerate_vb = params
+        .get(1, "feerate")
+        .map(|feerate| {
+            feerate
+                .as_u64()
+                .ok_or_else(|| Error::invalid_params("Invalid 'feerate' parameter."))
+        })
+        .transpose()?;
+    let res = control.cancel_spend(&txid, feerate_vb)?;
+    Ok(serde_json::json!(&res))
+}
+
+// Parse the optional 'statuses' parameter of the coins listing commands.
+fn coin_statuses(params: Option<&Params>) -> Result<Vec<CoinStatus>, Error> {
     let statuses_arg = params
-        .as_ref()
         .and_then(|p| p.get(0, "statuses"))
         .and_then(|statuses| statuses.as_array());
-    let statuses: Vec<CoinStatus> = if let Some(statuses_arg) = statuses_arg {
+    if let Some(statuses_arg) = statuses_arg {
         statuses_arg
             .iter()
             .map(|status_arg| {
@@ -142,10 +730,14 @@ fn list_coins(control: &DaemonControl, params: Option<Params>) -> Result<serde_j
                         ))
                     })
             })
-            .collect::<Result<Vec<CoinStatus>, Error>>()?
+            .collect()
     } else {
-        Vec::new()
-    };
+        Ok(Vec::new())
+    }
+}
+
+fn list_coins(control: &DaemonControl, params: Option<Params>) -> Result<serde_json::Value, Error> {
+    let statuses = coin_statuses(params.as_ref())?;
     let outpoints_arg = params
         .as_ref()
         .and_then(|p| p.get(1, "outpoints"))
@@ -230,7 +822,7 @@ fn list_spendtxs(
     control: &DaemonControl,
     params: Option<Params>,
 ) -> Result<serde_json::Value, Error> {
-    let txids: Option<Vec<bitcoin::Txid>> = if let Some(p) = params {
+    let txids: Option<Vec<bitcoin::Txid>> = if let Some(p) = params.as_ref() {
         let tx_ids = p.get(0, "txids");
         if let Some(ids) = tx_ids {
             let ids: Vec<Txid> = ids
@@ -249,7 +841,30 @@ fn list_spendtxs(
         None
     };
 
-    Ok(serde_json::json!(&control.list_spend(txids)?))
+    let statuses: Vec<SpendStatus> = if let Some(statuses_arg) = params
+        .as_ref()
+        .and_then(|p| p.get(1, "statuses"))
+        .and_then(|statuses| statuses.as_array())
+    {
+        statuses_arg
+            .iter()
+            .map(|status_arg| {
+                status_arg
+                    .as_str()
+                    .and_then(SpendStatus::from_arg)
+                    .ok_or_else(|| {
+                        Error::invalid_params(format!(
+                            "Invalid value {} in 'statuses' parameter.",
+                            status_arg
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<SpendStatus>, Error>>()?
+    } else {
+        Vec::new()
+    };
+
+    Ok(serde_json::json!(&control.list_spend(txids, &statuses)?))
 }
 
 fn list_transactions(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
@@ -278,17 +893,196 @@ fn start_rescan(control: &DaemonControl, params: Params) -> Result<serde_json::V
     Ok(serde_json::json!({}))
 }
 
-fn create_recovery(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
-    let address = params
-        .get(0, "address")
-        .ok_or_else(|| Error::invalid_params("Missing 'address' parameter."))?
+fn start_rescan_from_hash(
+    control: &DaemonControl,
+    params: Params,
+) -> Result<serde_json::Value, Error> {
+    let block_hash = params
+        .get(0, "block_hash")
+        .ok_or_else(|| Error::invalid_params("Missing 'block_hash' parameter."))?
         .as_str()
-        .and_then(|s| bitcoin::Address::from_str(s).ok())
-        .ok_or_else(|| Error::invalid_params("Invalid 'address' parameter."))?;
-    let feerate: u64 = params
-        .get(1, "feerate")
-        .ok_or_else(|| Error::invalid_params("Missing 'feerate' parameter."))?
-        .as_u64()
+        .and_then(|h| bitcoin::BlockHash::from_str(h).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'block_hash' parameter."))?;
+    control.start_rescan_from_hash(&block_hash)?;
+
+    Ok(serde_json::json!({}))
+}
+
+fn rescan_range(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let start_height: u32 = params
+        .get(0, "start_height")
+        .ok_or_else(|| Error::invalid_params("Missing 'start_height' parameter."))?
+        .as_u64()
+        .and_then(|h| h.try_into().ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'start_height' parameter."))?;
+    let end_height: u32 = params
+        .get(1, "end_height")
+        .ok_or_else(|| Error::invalid_params("Missing 'end_height' parameter."))?
+        .as_u64()
+        .and_then(|h| h.try_into().ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'end_height' parameter."))?;
+    control.rescan_range(start_height, end_height)?;
+
+    Ok(serde_json::json!({}))
+}
+
+fn prune_spent_coins(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let older_than_confs: u32 = params
+        .get(0, "older_than_confs")
+        .ok_or_else(|| Error::invalid_params("Missing 'older_than_confs' parameter."))?
+        .as_u64()
+        .and_then(|c| c.try_into().ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'older_than_confs' parameter."))?;
+    let res = control.prune_spent_coins(older_than_confs)?;
+
+    Ok(serde_json::json!(&res))
+}
+
+fn list_coins_by_address(
+    control: &DaemonControl,
+    params: Option<Params>,
+) -> Result<serde_json::Value, Error> {
+    let statuses = coin_statuses(params.as_ref())?;
+    let res = control.list_coins_by_address(&statuses);
+    Ok(serde_json::json!(&res))
+}
+
+fn list_coins_at_height(
+    control: &DaemonControl,
+    params: Params,
+) -> Result<serde_json::Value, Error> {
+    let height: i32 = params
+        .get(0, "height")
+        .ok_or_else(|| Error::invalid_params("Missing 'height' parameter."))?
+        .as_u64()
+        .and_then(|h| h.try_into().ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'height' parameter."))?;
+    let res = control.list_coins_at_height(height)?;
+
+    Ok(serde_json::json!(&res))
+}
+
+fn list_coins_within_depth(
+    control: &DaemonControl,
+    params: Params,
+) -> Result<serde_json::Value, Error> {
+    let depth: u32 = params
+        .get(0, "depth")
+        .ok_or_else(|| Error::invalid_params("Missing 'depth' parameter."))?
+        .as_u64()
+        .and_then(|d| d.try_into().ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'depth' parameter."))?;
+    let res = control.coins_within_depth(depth)?;
+
+    Ok(serde_json::json!(&res))
+}
+
+fn list_uneconomic_coins(
+    control: &DaemonControl,
+    params: Params,
+) -> Result<serde_json::Value, Error> {
+    let feerate = params
+        .get(0, "feerate")
+        .ok_or_else(|| Error::invalid_params("Missing 'feerate' parameter."))?
+        .as_u64()
+        .ok_or_else(|| Error::invalid_params("Invalid 'feerate' parameter."))?;
+    let res = control.uneconomic_coins(feerate)?;
+
+    Ok(serde_json::json!(&res))
+}
+
+fn get_balance(
+    control: &DaemonControl,
+    params: Option<Params>,
+) -> Result<serde_json::Value, Error> {
+    let min_confirmations = params
+        .as_ref()
+        .and_then(|p| p.get(0, "min_confirmations"))
+        .map(|confs| {
+            confs
+                .as_u64()
+                .and_then(|c| c.try_into().ok())
+                .ok_or_else(|| Error::invalid_params("Invalid 'min_confirmations' parameter."))
+        })
+        .transpose()?
+        .unwrap_or(1);
+    Ok(serde_json::json!(&control.balance(min_confirmations)))
+}
+
+fn get_feerate_history(
+    control: &DaemonControl,
+    params: Option<Params>,
+) -> Result<serde_json::Value, Error> {
+    let since = params
+        .as_ref()
+        .and_then(|p| p.get(0, "since"))
+        .map(|since| {
+            since
+                .as_u64()
+                .and_then(|s| s.try_into().ok())
+                .ok_or_else(|| Error::invalid_params("Invalid 'since' parameter."))
+        })
+        .transpose()?
+        .unwrap_or(0);
+    Ok(serde_json::json!(&control.feerate_history(since)))
+}
+
+fn get_snapshot(
+    control: &DaemonControl,
+    params: Option<Params>,
+) -> Result<serde_json::Value, Error> {
+    let parts = match params.as_ref().and_then(|p| p.get(0, "parts")) {
+        None => SnapshotParts::all(),
+        Some(parts) => {
+            let parts = parts
+                .as_array()
+                .ok_or_else(|| Error::invalid_params("Invalid 'parts' parameter."))?;
+            let mut res = SnapshotParts::default();
+            for part in parts {
+                match part.as_str() {
+                    Some("info") => res.info = true,
+                    Some("coins") => res.coins = true,
+                    Some("feerate_estimates") => res.feerate_estimates = true,
+                    Some("spend_txs") => res.spend_txs = true,
+                    _ => {
+                        return Err(Error::invalid_params(format!(
+                            "Invalid value {} in 'parts' parameter.",
+                            part
+                        )))
+                    }
+                }
+            }
+            res
+        }
+    };
+    let res = control.snapshot(&parts)?;
+
+    Ok(serde_json::json!(&res))
+}
+
+fn diff_snapshots(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let snapshot = |index, name: &str| -> Result<WalletSnapshot, Error> {
+        let snapshot = params
+            .get(index, name)
+            .ok_or_else(|| Error::invalid_params(format!("Missing '{}' parameter.", name)))?;
+        serde_json::from_value(snapshot.clone())
+            .map_err(|e| Error::invalid_params(format!("Invalid '{}' parameter: {}.", name, e)))
+    };
+    let res = control.diff_snapshots(&snapshot(0, "a")?, &snapshot(1, "b")?)?;
+    Ok(serde_json::json!(&res))
+}
+
+fn create_recovery(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let address = params
+        .get(0, "address")
+        .ok_or_else(|| Error::invalid_params("Missing 'address' parameter."))?
+        .as_str()
+        .and_then(|s| bitcoin::Address::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'address' parameter."))?;
+    let feerate: u64 = params
+        .get(1, "feerate")
+        .ok_or_else(|| Error::invalid_params("Missing 'feerate' parameter."))?
+        .as_u64()
         .ok_or_else(|| Error::invalid_params("Invalid 'feerate' parameter."))?;
     let timelock: Option<u16> = params
         .get(2, "timelock")
@@ -303,6 +1097,86 @@ fn create_recovery(control: &DaemonControl, params: Params) -> Result<serde_json
     Ok(serde_json::json!(&res))
 }
 
+fn get_recovery_cost(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let feerate: u64 = params
+        .get(0, "feerate")
+        .ok_or_else(|| Error::invalid_params("Missing 'feerate' parameter."))?
+        .as_u64()
+        .ok_or_else(|| Error::invalid_params("Invalid 'feerate' parameter."))?;
+    let recovery_path: Option<usize> = params
+        .get(1, "recovery_path")
+        .map(|i| {
+            i.as_u64()
+                .and_then(|i| i.try_into().ok())
+                .ok_or_else(|| Error::invalid_params("Invalid 'recovery_path' parameter."))
+        })
+        .transpose()?;
+
+    let res = control.recovery_cost_estimate(feerate, recovery_path)?;
+    Ok(serde_json::json!(&res))
+}
+
+fn migrate_to_descriptor(
+    control: &DaemonControl,
+    params: Params,
+) -> Result<serde_json::Value, Error> {
+    let descriptor = params
+        .get(0, "descriptor")
+        .ok_or_else(|| Error::invalid_params("Missing 'descriptor' parameter."))?
+        .as_str()
+        .ok_or_else(|| Error::invalid_params("Invalid 'descriptor' parameter."))?;
+    let feerate: u64 = params
+        .get(1, "feerate")
+        .ok_or_else(|| Error::invalid_params("Missing 'feerate' parameter."))?
+        .as_u64()
+        .ok_or_else(|| Error::invalid_params("Invalid 'feerate' parameter."))?;
+
+    let res = control.migrate_to_descriptor(descriptor, feerate)?;
+    Ok(serde_json::json!(&res))
+}
+
+fn rotate_funds(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let feerate: u64 = params
+        .get(0, "feerate")
+        .ok_or_else(|| Error::invalid_params("Missing 'feerate' parameter."))?
+        .as_u64()
+        .ok_or_else(|| Error::invalid_params("Invalid 'feerate' parameter."))?;
+    let num_outputs: u32 = params
+        .get(1, "num_outputs")
+        .ok_or_else(|| Error::invalid_params("Missing 'num_outputs' parameter."))?
+        .as_u64()
+        .and_then(|n| n.try_into().ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'num_outputs' parameter."))?;
+
+    let res = control.rotate_funds(feerate, num_outputs)?;
+    Ok(serde_json::json!(&res))
+}
+
+// Parse an entry of the 'labels' mapping parameter into a label item and its value, if any.
+fn label_entry(
+    control: &DaemonControl,
+    item: &str,
+    value: &serde_json::Value,
+) -> Result<(LabelItem, Option<String>), Error> {
+    let value = value.as_str().map(|s| s.to_string());
+    if let Some(value) = &value {
+        if value.len() > 100 {
+            return Err(Error::invalid_params(format!(
+                "Invalid 'labels.{}' value length: must be less or equal than 100 characters",
+                item
+            )));
+        }
+    }
+    let item =
+        LabelItem::from_str(item, control.config.bitcoin_config.network).ok_or_else(|| {
+            Error::invalid_params(format!(
+                "Invalid 'labels.{}' parameter: must be an address, a txid or an outpoint",
+                item
+            ))
+        })?;
+    Ok((item, value))
+}
+
 fn update_labels(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
     let mut items = HashMap::new();
     for (item, value) in params
@@ -312,27 +1186,32 @@ fn update_labels(control: &DaemonControl, params: Params) -> Result<serde_json::
         .ok_or_else(|| Error::invalid_params("Invalid 'labels' parameter."))?
         .iter()
     {
-        let value = value.as_str().map(|s| s.to_string());
-        if let Some(value) = &value {
-            if value.len() > 100 {
-                return Err(Error::invalid_params(format!(
-                    "Invalid 'labels.{}' value length: must be less or equal than 100 characters",
-                    item
-                )));
-            }
-        }
-        let item =
-            LabelItem::from_str(item, control.config.bitcoin_config.network).ok_or_else(|| {
-                Error::invalid_params(format!(
-                    "Invalid 'labels.{}' parameter: must be an address, a txid or an outpoint",
-                    item
-                ))
-            })?;
+        let (item, value) = label_entry(control, item, value)?;
         items.insert(item, value);
     }
 
-    control.update_labels(&items);
-    Ok(serde_json::json!({}))
+    let res = control.update_labels(&items)?;
+    Ok(serde_json::json!(&res))
+}
+
+fn replace_labels(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let mut labels = HashMap::new();
+    for (item, value) in params
+        .get(0, "labels")
+        .ok_or_else(|| Error::invalid_params("Missing 'labels' parameter."))?
+        .as_object()
+        .ok_or_else(|| Error::invalid_params("Invalid 'labels' parameter."))?
+        .iter()
+    {
+        let (label_item, value) = label_entry(control, item, value)?;
+        let value = value.ok_or_else(|| {
+            Error::invalid_params(format!("Invalid 'labels.{}' value: must be a string", item))
+        })?;
+        labels.insert(label_item, value);
+    }
+
+    let res = control.replace_all_labels(&labels)?;
+    Ok(serde_json::json!(&res))
 }
 
 fn get_labels(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
@@ -364,8 +1243,181 @@ fn get_labels(control: &DaemonControl, params: Params) -> Result<serde_json::Val
     Ok(serde_json::json!(control.get_labels(&items)))
 }
 
+fn export_labels(
+    control: &DaemonControl,
+    params: Option<Params>,
+) -> Result<serde_json::Value, Error> {
+    let offset = get_opt_u32(&params, 0, "offset")?;
+    let limit = get_opt_u32(&params, 1, "limit")?;
+
+    let res = match (offset, limit) {
+        (None, None) => control.export_all_labels(),
+        (offset, Some(limit)) => {
+            control.export_labels_page(offset.unwrap_or(0).into(), limit.into())
+        }
+        (Some(_), None) => {
+            return Err(Error::invalid_params(
+                "The 'offset' parameter requires the 'limit' parameter.",
+            ))
+        }
+    };
+    Ok(serde_json::json!(&res))
+}
+
+fn classify_addresses(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let addresses = params
+        .get(0, "addresses")
+        .ok_or_else(|| Error::invalid_params("Missing 'addresses' parameter."))?
+        .as_array()
+        .and_then(|arr| {
+            arr.iter()
+                .map(|entry| {
+                    entry
+                        .as_str()
+                        .and_then(|e| bitcoin::Address::from_str(e).ok())
+                })
+                .collect::<Option<Vec<_>>>()
+        })
+        .ok_or_else(|| Error::invalid_params("Invalid 'addresses' parameter."))?;
+    let res = control.classify_addresses(&addresses)?;
+    Ok(serde_json::json!(&res))
+}
+
+fn check_payment(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let address = params
+        .get(0, "address")
+        .ok_or_else(|| Error::invalid_params("Missing 'address' parameter."))?
+        .as_str()
+        .and_then(|s| bitcoin::Address::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'address' parameter."))?;
+    let expected = params
+        .get(1, "amount")
+        .ok_or_else(|| Error::invalid_params("Missing 'amount' parameter."))?
+        .as_u64()
+        .map(bitcoin::Amount::from_sat)
+        .ok_or_else(|| Error::invalid_params("Invalid 'amount' parameter."))?;
+    let min_confirmations = params
+        .get(2, "min_confirmations")
+        .map(|confs| {
+            confs
+                .as_u64()
+                .and_then(|c| c.try_into().ok())
+                .ok_or_else(|| Error::invalid_params("Invalid 'min_confirmations' parameter."))
+        })
+        .transpose()?
+        .unwrap_or(1);
+    let res = control.check_payment(address, expected, min_confirmations)?;
+    Ok(serde_json::json!(&res))
+}
+
+fn set_destination_label(
+    control: &DaemonControl,
+    params: Params,
+) -> Result<serde_json::Value, Error> {
+    let address = params
+        .get(0, "address")
+        .ok_or_else(|| Error::invalid_params("Missing 'address' parameter."))?
+        .as_str()
+        .and_then(|s| bitcoin::Address::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'address' parameter."))?;
+    let label = params
+        .get(1, "label")
+        .ok_or_else(|| Error::invalid_params("Missing 'label' parameter."))?;
+    let label =
+        match label {
+            serde_json::Value::Null => None,
+            serde_json::Value::String(l) if l.len() <= 100 => Some(l.to_string()),
+            _ => return Err(Error::invalid_params(
+                "Invalid 'label' parameter: must be null or a string of at most 100 characters.",
+            )),
+        };
+    control.set_destination_label(address, label)?;
+    Ok(serde_json::json!({}))
+}
+
+fn set_coin_note(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let outpoint = params
+        .get(0, "outpoint")
+        .ok_or_else(|| Error::invalid_params("Missing 'outpoint' parameter."))?
+        .as_str()
+        .and_then(|s| bitcoin::OutPoint::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'outpoint' parameter."))?;
+    let note = match params
+        .get(1, "note")
+        .ok_or_else(|| Error::invalid_params("Missing 'note' parameter."))?
+    {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(n) => Some(n.to_string()),
+        _ => {
+            return Err(Error::invalid_params(
+                "Invalid 'note' parameter: must be null or a string.",
+            ))
+        }
+    };
+    control.set_coin_note(&outpoint, note)?;
+    Ok(serde_json::json!({}))
+}
+
+/// The commands which don't modify the state of the wallet, and may therefore be accepted on an
+/// observer connection.
+const READ_ONLY_METHODS: &[&str] = &[
+    "checkpayment",
+    "classifyaddresses",
+    "describepolicy",
+    "diffsnapshots",
+    "exportcoredescriptors",
+    "exportlabels",
+    "exportspendbbqr",
+    "exportwallet",
+    "getbalance",
+    "getcoinaddress",
+    "getcoinfundingtx",
+    "getdashboard",
+    "getdatabasestats",
+    "getdescriptorkeys",
+    "getfeerateestimates",
+    "getfeeratehistory",
+    "getfinalfeeestimate",
+    "getinfo",
+    "getlabels",
+    "getlastaddressstatus",
+    "getmempoolstatus",
+    "getpackageinfo",
+    "getrecoverycost",
+    "getsigningrequirements",
+    "getsnapshot",
+    "gettxlabels",
+    "getwalletbirthday",
+    "inspectpsbt",
+    "invalidatefeeratecache",
+    "listaddresses",
+    "listcoins",
+    "listcoinsatheight",
+    "listcoinsbyaddress",
+    "listcoinswithindepth",
+    "listconfirmed",
+    "listdestinationlabels",
+    "listinvalidatedcoins",
+    "listpendingtransactions",
+    "listspendtxs",
+    "listtransactions",
+    "listuneconomiccoins",
+    "testbroadcastspend",
+    "verifyaddresses",
+];
+
+/// Whether this command may be handled on an observer connection.
+pub fn is_read_only(method: &str) -> bool {
+    READ_ONLY_METHODS.contains(&method)
+}
+
 /// Handle an incoming JSONRPC2 request.
 pub fn handle_request(control: &DaemonControl, req: Request) -> Result<Response, Error> {
+    // The commands which may modify the wallet check the session themselves.
+    if is_read_only(&req.method) {
+        control.check_unlocked(true)?;
+    }
+
     let result = match req.method.as_str() {
         "broadcastspend" => {
             let params = req
@@ -373,6 +1425,24 @@ pub fn handle_request(control: &DaemonControl, req: Request) -> Result<Response,
                 .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?;
             broadcast_spend(control, params)?
         }
+        "cancelspend" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?;
+            cancel_spend(control, params)?
+        }
+        "checkpayment" => {
+            let params = req.params.ok_or_else(|| {
+                Error::invalid_params("Missing 'address' and 'amount' parameters.")
+            })?;
+            check_payment(control, params)?
+        }
+        "classifyaddresses" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'addresses' parameter."))?;
+            classify_addresses(control, params)?
+        }
         "createrecovery" => {
             let params = req.params.ok_or_else(|| {
                 Error::invalid_params("Missing 'address' and 'feerate' parameters.")
@@ -399,12 +1469,143 @@ pub fn handle_request(control: &DaemonControl, req: Request) -> Result<Response,
             })?;
             rbf_psbt(control, params)?
         }
+        "rebroadcastunconfirmed" => rebroadcast_unconfirmed(control)?,
+        "reconnectbackend" => {
+            control.reconnect_backend()?;
+            serde_json::json!({})
+        }
+        "encryptdatabase" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'key' parameter."))?;
+            encrypt_database(control, params)?
+        }
+        "exportlabels" => {
+            let params = req.params;
+            export_labels(control, params)?
+        }
+        "exportspendbbqr" => {
+            let params = req.params.ok_or_else(|| {
+                Error::invalid_params("Missing 'txid' and 'max_part_len' parameters.")
+            })?;
+            export_spend_bbqr(control, params)?
+        }
+        "exportwallet" => serde_json::json!(&control.export_wallet()),
+        "exportcoredescriptors" => {
+            serde_json::json!({ "descriptors": control.export_core_descriptors() })
+        }
+        "importspendbbqr" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'parts' parameter."))?;
+            import_spend_bbqr(control, params)?
+        }
+        "getcoinaddress" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'outpoint' parameter."))?;
+            get_coin_address(control, params)?
+        }
+        "getcoinfundingtx" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'outpoint' parameter."))?;
+            get_coin_funding_tx(control, params)?
+        }
+        "describepolicy" => serde_json::json!(&control.describe_policy()),
+        "getdescriptorkeys" => serde_json::json!(&control.descriptor_keys()),
+        "getfeerateestimates" => serde_json::json!(&control.get_feerate_estimates()),
+        "getfeeratehistory" => get_feerate_history(control, req.params)?,
+        "getdashboard" => serde_json::json!(&control.dashboard()),
+        "getbalance" => get_balance(control, req.params)?,
+        "invalidatefeeratecache" => {
+            control.invalidate_feerate_cache();
+            serde_json::json!({})
+        }
+        "getdatabasestats" => serde_json::json!(&control.get_database_stats()),
         "getinfo" => serde_json::json!(&control.get_info()),
-        "getnewaddress" => serde_json::json!(&control.get_new_address()),
+        "getsnapshot" => get_snapshot(control, req.params)?,
+        "diffsnapshots" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'a' and 'b' parameters."))?;
+            diff_snapshots(control, params)?
+        }
+        "getsigningrequirements" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?;
+            signing_requirements(control, params)?
+        }
+        "getfinalfeeestimate" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?;
+            final_fee_estimate(control, params)?
+        }
+        "getnewaddress" => serde_json::json!(&control.get_new_address()?),
+        "getunusedaddress" => serde_json::json!(&control.get_unused_address()?),
+        "gettxlabels" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?;
+            get_tx_labels(control, params)?
+        }
+        "getwalletbirthday" => {
+            serde_json::json!({ "height": control.wallet_birthday() })
+        }
+        "getlastaddressstatus" => serde_json::json!(&control.last_address_status()),
+        "getmempoolstatus" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?;
+            get_mempool_status(control, params)?
+        }
+        "getpackageinfo" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?;
+            get_package_info(control, params)?
+        }
+        "getrecoverycost" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'feerate' parameter."))?;
+            get_recovery_cost(control, params)?
+        }
+        "inspectpsbt" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'psbt' parameter."))?;
+            inspect_psbt(control, params)?
+        }
         "listcoins" => {
             let params = req.params;
             list_coins(control, params)?
         }
+        "listcoinsatheight" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'height' parameter."))?;
+            list_coins_at_height(control, params)?
+        }
+        "listcoinsbyaddress" => {
+            let params = req.params;
+            list_coins_by_address(control, params)?
+        }
+        "listcoinswithindepth" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'depth' parameter."))?;
+            list_coins_within_depth(control, params)?
+        }
+        "listuneconomiccoins" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'feerate' parameter."))?;
+            list_uneconomic_coins(control, params)?
+        }
+        "listinvalidatedcoins" => serde_json::json!(&control.invalidated_coins()),
         "listaddresses" => {
             let params = req.params;
             list_addresses(control, params)?
@@ -417,7 +1618,32 @@ pub fn handle_request(control: &DaemonControl, req: Request) -> Result<Response,
             })?;
             list_confirmed(control, params)?
         }
+        "listdestinationlabels" => serde_json::json!(&control.list_destination_labels()),
+        "listpendingtransactions" => serde_json::json!(&control.pending_transactions()),
         "listspendtxs" => list_spendtxs(control, req.params)?,
+        "lock" => {
+            control.lock()?;
+            serde_json::json!({})
+        }
+        "migratetodescriptor" => {
+            let params = req.params.ok_or_else(|| {
+                Error::invalid_params("Missing 'descriptor' and 'feerate' parameters.")
+            })?;
+            migrate_to_descriptor(control, params)?
+        }
+        "rotatefunds" => {
+            let params = req.params.ok_or_else(|| {
+                Error::invalid_params("Missing 'feerate' and 'num_outputs' parameters.")
+            })?;
+            rotate_funds(control, params)?
+        }
+        "vacuumdatabase" => serde_json::json!(&control.vacuum_database()?),
+        "prunespentcoins" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'older_than_confs' parameter."))?;
+            prune_spent_coins(control, params)?
+        }
         "listtransactions" => {
             let params = req.params.ok_or_else(|| {
                 Error::invalid_params(
@@ -432,13 +1658,70 @@ pub fn handle_request(control: &DaemonControl, req: Request) -> Result<Response,
                 .ok_or_else(|| Error::invalid_params("Missing 'timestamp' parameter."))?;
             start_rescan(control, params)?
         }
+        "startrescanfromhash" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'block_hash' parameter."))?;
+            start_rescan_from_hash(control, params)?
+        }
+        "rescanrange" => {
+            let params = req.params.ok_or_else(|| {
+                Error::invalid_params("Missing 'start_height' and 'end_height' parameters.")
+            })?;
+            rescan_range(control, params)?
+        }
+        "setdestinationlabel" => {
+            let params = req.params.ok_or_else(|| {
+                Error::invalid_params("Missing 'address' and 'label' parameters.")
+            })?;
+            set_destination_label(control, params)?
+        }
+        "setcoinnote" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'outpoint' parameter."))?;
+            set_coin_note(control, params)?
+        }
+        "setloglevel" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'level' parameter."))?;
+            set_log_level(control, params)?
+        }
         "stop" => serde_json::json!({}),
+        "testbroadcastspend" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?;
+            test_broadcast_spend(control, params)?
+        }
+        "unlock" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'passphrase' parameter."))?;
+            unlock(control, params)?
+        }
+        "verifyaddresses" => serde_json::json!(&control.verify_address_consistency()),
         "updatespend" => {
             let params = req
                 .params
                 .ok_or_else(|| Error::invalid_params("Missing 'psbt' parameter."))?;
             update_spend(control, params)?
         }
+        "updatespenddestination" => {
+            let params = req.params.ok_or_else(|| {
+                Error::invalid_params(
+                    "Missing 'txid', 'old_address', 'new_address' and 'amount' parameters.",
+                )
+            })?;
+            update_spend_destination(control, params)?
+        }
+        "replacelabels" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'labels' parameter."))?;
+            replace_labels(control, params)?
+        }
         "updatelabels" => {
             let params = req
                 .params
@@ -53,6 +53,10 @@ pub struct Request {
 
 /// A failure to broadcast a transaction to the P2P network.
 const BROADCAST_ERROR: i64 = 1_000;
+/// A command which may modify the wallet was sent on an observer connection.
+const READ_ONLY_ERROR: i64 = 1_001;
+/// The session is locked and the command isn't available until it is unlocked.
+const LOCKED_ERROR: i64 = 1_002;
 
 /// JSONRPC2 error codes. See https://www.jsonrpc.org/specification#error_object.
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -131,6 +135,17 @@ impl Error {
         Error::new(ErrorCode::MethodNotFound, "Method not found")
     }
 
+    /// This command can't be used on an observer connection, as it may modify the wallet.
+    pub fn read_only(method: &str) -> Error {
+        Error::new(
+            ErrorCode::ServerError(READ_ONLY_ERROR),
+            format!(
+                "Command '{}' is not available on a read-only connection",
+                method
+            ),
+        )
+    }
+
     pub fn invalid_params(message: impl Into<String>) -> Error {
         Error::new(
             ErrorCode::InvalidParams,
@@ -162,19 +177,63 @@ impl From<commands::CommandError> for Error {
             | commands::CommandError::UnknownSpend(..)
             | commands::CommandError::SpendFinalization(..)
             | commands::CommandError::InsaneRescanTimestamp(..)
+            | commands::CommandError::InsaneRescanRange(..)
             | commands::CommandError::AlreadyRescanning
             | commands::CommandError::InvalidDerivationIndex
             | commands::CommandError::RbfError(..)
             | commands::CommandError::EmptyFilterList
-            | commands::CommandError::RecoveryNotAvailable => {
+            | commands::CommandError::RecoveryNotAvailable
+            | commands::CommandError::NoRecoveryPath
+            | commands::CommandError::UnknownSpendingPath(..)
+            | commands::CommandError::SpendingPathNotAvailable(..)
+            | commands::CommandError::HeightNotScanned(..)
+            | commands::CommandError::UnknownBlock(..)
+            | commands::CommandError::SpendAnalysis(..)
+            | commands::CommandError::PruningTooShallow(..)
+            | commands::CommandError::InvalidChangeSplit(..)
+            | commands::CommandError::InvalidDescriptor(..)
+            | commands::CommandError::NoCoinToMigrate
+            | commands::CommandError::NotInMempool(..)
+            | commands::CommandError::NotExternalAddress(..)
+            | commands::CommandError::CoinReserved(..)
+            | commands::CommandError::CoinNoteTooLong(..)
+            | commands::CommandError::InvalidReorgDepth
+            | commands::CommandError::UnknownRecoveryPath(..)
+            | commands::CommandError::UnknownTransaction(..)
+            | commands::CommandError::UnknownAddress(..)
+            | commands::CommandError::Bbqr(..)
+            | commands::CommandError::InvalidPsbt(..)
+            | commands::CommandError::InvalidChangePath(..)
+            | commands::CommandError::ChangeAddressAndPath
+            | commands::CommandError::InvalidForeignInput(..)
+            | commands::CommandError::UnexpectedSigner(..)
+            | commands::CommandError::WrongPassphrase
+            | commands::CommandError::NoSessionPassphrase
+            | commands::CommandError::SupersededByReplacement(..)
+            | commands::CommandError::ReplacedBy(..)
+            | commands::CommandError::NotADestination(..)
+            | commands::CommandError::SnapshotWithoutCoins
+            | commands::CommandError::IdempotencyKeyReused(..)
+            | commands::CommandError::IdempotencyKeyInFlight(..)
+            | commands::CommandError::InvalidRotationOutputs(..)
+            | commands::CommandError::NoCoinToRotate
+            | commands::CommandError::DuplicateDestination(..) => {
                 Error::new(ErrorCode::InvalidParams, e.to_string())
             }
-            commands::CommandError::RescanTrigger(..) => {
+            commands::CommandError::RescanTrigger(..)
+            | commands::CommandError::BackendConnection(..)
+            | commands::CommandError::DatabaseEncryption(..)
+            | commands::CommandError::DatabaseVacuum(..)
+            | commands::CommandError::Randomness(..)
+            | commands::CommandError::FundingTxUnavailable(..) => {
                 Error::new(ErrorCode::InternalError, e.to_string())
             }
             commands::CommandError::TxBroadcast(_) => {
                 Error::new(ErrorCode::ServerError(BROADCAST_ERROR), e.to_string())
             }
+            commands::CommandError::Locked => {
+                Error::new(ErrorCode::ServerError(LOCKED_ERROR), e.to_string())
+            }
         }
     }
 }
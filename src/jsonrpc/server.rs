@@ -4,7 +4,7 @@
 //! JSONRPC2 requests on a Unix Domain Socket.
 
 use crate::{
-    jsonrpc::{api, Request, Response},
+    jsonrpc::{api, Error, Request, Response},
     DaemonControl,
 };
 
@@ -19,6 +19,24 @@ use std::{
 // Maximum number of concurrent RPC connections we may accept.
 const MAX_CONNECTIONS: u32 = 16;
 
+// The commands whose parameters are secrets, and must therefore never be logged.
+const SECRET_PARAMS_METHODS: &[&str] = &["encryptdatabase", "unlock"];
+
+// The request as it may be logged: the parameters of the commands taking a secret are redacted.
+fn loggable_request(req: &Request) -> String {
+    if SECRET_PARAMS_METHODS.contains(&req.method.as_str()) {
+        let redacted = Request {
+            params: None,
+            ..req.clone()
+        };
+        let mut value = serde_json::json!(redacted);
+        value["params"] = serde_json::json!("REDACTED");
+        value.to_string()
+    } else {
+        serde_json::json!(req).to_string()
+    }
+}
+
 // Read a command from the stream.
 //
 // In order to both treat commands separately (respond as soon as we read one), and support
@@ -79,11 +97,13 @@ fn read_command(
     }
 }
 
-// Handle all messages from this connection.
+// Handle all messages from this connection. On an observer connection only the commands which
+// don't modify the wallet are handled.
 fn connection_handler(
     control: DaemonControl,
     mut stream: net::UnixStream,
     shutdown: sync::Arc<atomic::AtomicBool>,
+    observer: bool,
 ) -> Result<(), io::Error> {
     let mut buf = vec![0; 2048];
     let mut end = 0;
@@ -99,14 +119,16 @@ fn connection_handler(
         };
 
         let req_id = req.id.clone();
-        if &req.method == "stop" {
-            shutdown.store(true, atomic::Ordering::Relaxed);
-            log::info!("Stopping the liana daemon.");
-        }
-
-        log::trace!("JSONRPC request: {:?}", serde_json::to_string(&req));
-        let response =
-            api::handle_request(&control, req).unwrap_or_else(|e| Response::error(req_id, e));
+        log::trace!("JSONRPC request: {}", loggable_request(&req));
+        let response = if observer && !api::is_read_only(&req.method) {
+            Response::error(req_id, Error::read_only(&req.method))
+        } else {
+            if &req.method == "stop" {
+                shutdown.store(true, atomic::Ordering::Relaxed);
+                log::info!("Stopping the liana daemon.");
+            }
+            api::handle_request(&control, req).unwrap_or_else(|e| Response::error(req_id, e))
+        };
         log::trace!("JSONRPC response: {:?}", serde_json::to_string(&response));
         if let Err(e) = serde_json::to_writer(&stream, &response) {
             log::error!("Error writing response: '{}'", e);
@@ -118,9 +140,11 @@ fn connection_handler(
 }
 
 // FIXME: have a decent way to share the DaemonControl between connections. Maybe make it Clone?
-/// The main event loop. Wait for connections, and treat requests sent through them.
+/// The main event loop. Wait for connections, and treat requests sent through them. Connections
+/// made through the `observer_listener`, if any, may only use read-only commands.
 pub fn rpcserver_loop(
     listener: net::UnixListener,
+    observer_listener: Option<net::UnixListener>,
     daemon_control: DaemonControl,
     shutdown: sync::Arc<atomic::AtomicBool>,
 ) -> Result<(), io::Error> {
@@ -129,13 +153,19 @@ pub fn rpcserver_loop(
     let connections_counter = sync::Arc::from(atomic::AtomicU32::new(0));
 
     listener.set_nonblocking(true)?;
+    if let Some(observer_listener) = &observer_listener {
+        observer_listener.set_nonblocking(true)?;
+    }
     while !shutdown.load(atomic::Ordering::Relaxed) {
-        let (connection, _) = match listener.accept() {
-            Ok(c) => c,
-            Err(_) => {
-                thread::sleep(time::Duration::from_millis(100));
-                continue;
-            }
+        let (connection, observer) = match listener.accept() {
+            Ok((c, _)) => (c, false),
+            Err(_) => match observer_listener.as_ref().map(|l| l.accept()) {
+                Some(Ok((c, _))) => (c, true),
+                _ => {
+                    thread::sleep(time::Duration::from_millis(100));
+                    continue;
+                }
+            },
         };
         log::trace!("New JSONRPC connection");
 
@@ -153,7 +183,7 @@ pub fn rpcserver_loop(
                 let shutdown = shutdown.clone();
 
                 move || {
-                    if let Err(e) = connection_handler(control, connection, shutdown) {
+                    if let Err(e) = connection_handler(control, connection, shutdown, observer) {
                         log::error!("Error while handling connection {}: '{}'", handler_id, e);
                     } else {
                         log::trace!("Connection {} terminated without error.", handler_id);
@@ -257,6 +287,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn secret_params_redacted() {
+        let req: Request = serde_json::from_str(
+            r#"{"jsonrpc": "2.0", "id": 0, "method": "unlock", "params": ["hunter2"]}"#,
+        )
+        .unwrap();
+        let logged = loggable_request(&req);
+        assert!(!logged.contains("hunter2"));
+        assert!(logged.contains("REDACTED"));
+        let req: Request = serde_json::from_str(
+            r#"{"jsonrpc": "2.0", "id": 0, "method": "encryptdatabase", "params": {"key": "hunter2"}}"#,
+        )
+        .unwrap();
+        assert!(!loggable_request(&req).contains("hunter2"));
+
+        // The parameters of the other commands are logged as is.
+        let req: Request = serde_json::from_str(
+            r#"{"jsonrpc": "2.0", "id": 0, "method": "getlabels", "params": [["hunter2"]]}"#,
+        )
+        .unwrap();
+        assert!(loggable_request(&req).contains("hunter2"));
+    }
+
     #[test]
     fn command_read_single() {
         let socket_path = env::temp_dir().join(format!(
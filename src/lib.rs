@@ -1,3 +1,4 @@
+pub mod bbqr;
 mod bitcoin;
 pub mod commands;
 pub mod config;
@@ -21,20 +22,26 @@ pub use crate::bitcoin::d::{BitcoinD, BitcoindError, WalletError};
 use crate::jsonrpc::server::{rpcserver_loop, rpcserver_setup};
 use crate::{
     bitcoin::{poller, BitcoinInterface},
-    config::Config,
+    commands::{FeerateEstimate, IdempotentSpend, WalletExport},
+    config::{Config, ConfigError},
     database::{
         sqlite::{FreshDbOptions, SqliteDb, SqliteDbError},
-        DatabaseInterface,
+        DatabaseConnection, DatabaseInterface, LabelItem,
     },
+    spend::DraftId,
 };
 
 use std::{
+    cmp,
+    collections::HashMap,
+    convert::TryInto,
     error, fmt, fs, io, path,
+    str::FromStr,
     sync::{self, mpsc},
-    thread,
+    thread, time,
 };
 
-use miniscript::bitcoin::secp256k1;
+use miniscript::bitcoin::{bip32, secp256k1, OutPoint, Transaction, Txid};
 
 #[cfg(not(test))]
 use std::panic;
@@ -89,21 +96,29 @@ pub const VERSION: Version = Version {
 #[derive(Debug)]
 pub enum StartupError {
     Io(io::Error),
+    Config(ConfigError),
     DefaultDataDirNotFound,
     DatadirCreation(path::PathBuf, io::Error),
+    NotWritable(path::PathBuf, io::Error),
     MissingBitcoindConfig,
     Database(SqliteDbError),
+    DatabaseDecryption,
     Bitcoind(BitcoindError),
     #[cfg(unix)]
     Daemonization(&'static str),
     #[cfg(windows)]
     NoWatchonlyInDatadir,
+    WalletImport(String),
+    /// Receive addresses were already generated or used up to this index, below the configured
+    /// derivation start index.
+    DerivationStartIndex(/* start index */ u32, /* receive index */ u32),
 }
 
 impl fmt::Display for StartupError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Self::Io(e) => write!(f, "{}", e),
+            Self::Config(e) => write!(f, "{}", e),
             Self::DefaultDataDirNotFound => write!(
                 f,
                 "Not data directory was specified and a default path could not be determined for this platform."
@@ -112,11 +127,19 @@ impl fmt::Display for StartupError {
                 f,
                 "Could not create data directory at '{}': '{}'", dir_path.display(), e
             ),
+            Self::NotWritable(dir_path, e) => write!(
+                f,
+                "Directory at '{}' is not writable: '{}'", dir_path.display(), e
+            ),
             Self::MissingBitcoindConfig => write!(
                 f,
                 "Our Bitcoin interface is bitcoind but we have no 'bitcoind_config' entry in the configuration."
             ),
             Self::Database(e) => write!(f, "Error initializing database: '{}'.", e),
+            Self::DatabaseDecryption => write!(
+                f,
+                "Could not decrypt the database. Is the 'db_encryption_key' in the configuration correct?"
+            ),
             Self::Bitcoind(e) => write!(f, "Error setting up bitcoind interface: '{}'.", e),
             #[cfg(unix)]
             Self::Daemonization(e) => write!(f, "Error when daemonizing: '{}'.", e),
@@ -131,6 +154,13 @@ impl fmt::Display for StartupError {
                      first run Liana v4 before running more recent Liana versions."
                 )
             }
+            Self::WalletImport(e) => write!(f, "Error importing wallet: '{}'.", e),
+            Self::DerivationStartIndex(start, index) => write!(
+                f,
+                "Receive addresses up to derivation index {} were already generated or used, they \
+                 would collide with those reserved below the derivation start index {}.",
+                index, start
+            ),
         }
     }
 }
@@ -143,9 +173,18 @@ impl From<io::Error> for StartupError {
     }
 }
 
+impl From<ConfigError> for StartupError {
+    fn from(e: ConfigError) -> Self {
+        Self::Config(e)
+    }
+}
+
 impl From<SqliteDbError> for StartupError {
     fn from(e: SqliteDbError) -> Self {
-        Self::Database(e)
+        match e {
+            SqliteDbError::Decryption => Self::DatabaseDecryption,
+            e => Self::Database(e),
+        }
     }
 }
 
@@ -177,6 +216,21 @@ fn create_datadir(datadir_path: &path::Path) -> Result<(), StartupError> {
     };
 }
 
+// Make sure we could write to this directory, or to the closest existing parent directory
+// should it not exist yet. This does not create the directory.
+fn check_writable(dir_path: &path::Path) -> Result<(), StartupError> {
+    let existing_dir = dir_path
+        .ancestors()
+        .find(|p| p.exists())
+        .unwrap_or(dir_path);
+    let not_writable = |e| StartupError::NotWritable(existing_dir.to_path_buf(), e);
+    let probe_path = existing_dir.join(".lianad_write_probe");
+    fs::File::create(&probe_path).map_err(not_writable)?;
+    fs::remove_file(&probe_path).map_err(not_writable)?;
+
+    Ok(())
+}
+
 // Connect to the SQLite database. Create it if starting fresh, and do some sanity checks.
 // If all went well, returns the interface to the SQLite database.
 fn setup_sqlite(
@@ -196,13 +250,47 @@ fn setup_sqlite(
     } else {
         None
     };
-    let sqlite = SqliteDb::new(db_path, options, secp)?;
+    let sqlite = SqliteDb::new(db_path, options, secp, config.db_encryption_key.clone())?;
     sqlite.sanity_check(config.bitcoin_config.network, &config.main_descriptor)?;
     log::info!("Database initialized and checked.");
 
     Ok(sqlite)
 }
 
+// Make the automatic generation of receive addresses start at the configured derivation index,
+// unless it's already past it. If some receive addresses were already generated or used below it,
+// they may collide with the addresses reserved for manual use so we refuse to start.
+fn setup_derivation_start_index(
+    config: &Config,
+    db: &sync::Arc<sync::Mutex<dyn DatabaseInterface>>,
+    secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+) -> Result<(), StartupError> {
+    let start_index = config.derivation_start_index;
+    let mut db_conn = db.connection();
+    let receive_index: u32 = db_conn.receive_index().into();
+    if receive_index >= start_index {
+        return Ok(());
+    }
+    if receive_index > 0 {
+        return Err(StartupError::DerivationStartIndex(
+            start_index,
+            receive_index,
+        ));
+    }
+    let index = bip32::ChildNumber::from_normal_idx(start_index).map_err(|_| {
+        StartupError::Config(ConfigError::Unexpected(format!(
+            "invalid derivation start index '{}'",
+            start_index
+        )))
+    })?;
+    db_conn.set_receive_index(index, secp);
+    log::info!(
+        "Receive addresses will be generated from derivation index {}.",
+        start_index
+    );
+    Ok(())
+}
+
 // Connect to bitcoind. Setup the watchonly wallet, and do some sanity checks.
 // If all went well, returns the interface to bitcoind.
 fn setup_bitcoind(
@@ -253,6 +341,9 @@ fn setup_bitcoind(
     Ok(bitcoind)
 }
 
+// Feerate estimates along with the time they were computed at.
+type CachedFeerateEstimates = (time::Instant, Vec<FeerateEstimate>);
+
 #[derive(Clone)]
 pub struct DaemonControl {
     config: Config,
@@ -261,6 +352,19 @@ pub struct DaemonControl {
     // FIXME: Should we require Sync on DatabaseInterface rather than using a Mutex?
     db: sync::Arc<sync::Mutex<dyn DatabaseInterface>>,
     secp: secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+    // The Spend transactions created, or being created, with an idempotency key.
+    spend_keys: sync::Arc<sync::Mutex<HashMap<String, IdempotentSpend>>>,
+    // The coins reserved by a Spend transaction draft, along with the time of the reservation.
+    coin_reservations: sync::Arc<sync::Mutex<HashMap<OutPoint, (time::Instant, DraftId)>>>,
+    // The Spend transaction drafts whose coins must not be reserved once stored, along with their
+    // creation time.
+    unreserved_drafts: sync::Arc<sync::Mutex<HashMap<DraftId, time::Instant>>>,
+    // The last feerate estimates computed, if they are to be reused.
+    feerate_cache: sync::Arc<sync::Mutex<Option<CachedFeerateEstimates>>>,
+    // The transactions which created our coins, as fetched from the Bitcoin backend.
+    funding_txs: sync::Arc<sync::Mutex<HashMap<Txid, Transaction>>>,
+    // Whether the session is locked. Always false if no session passphrase is configured.
+    locked: sync::Arc<sync::atomic::AtomicBool>,
 }
 
 impl DaemonControl {
@@ -271,12 +375,19 @@ impl DaemonControl {
         db: sync::Arc<sync::Mutex<dyn DatabaseInterface>>,
         secp: secp256k1::Secp256k1<secp256k1::VerifyOnly>,
     ) -> DaemonControl {
+        let locked = config.session_passphrase_hash.is_some();
         DaemonControl {
             config,
             bitcoin,
             poller_sender,
             db,
             secp,
+            spend_keys: sync::Arc::new(sync::Mutex::new(HashMap::new())),
+            coin_reservations: sync::Arc::new(sync::Mutex::new(HashMap::new())),
+            unreserved_drafts: sync::Arc::new(sync::Mutex::new(HashMap::new())),
+            feerate_cache: sync::Arc::new(sync::Mutex::new(None)),
+            funding_txs: sync::Arc::new(sync::Mutex::new(HashMap::new())),
+            locked: sync::Arc::new(sync::atomic::AtomicBool::new(locked)),
         }
     }
 
@@ -347,6 +458,7 @@ impl DaemonHandle {
                 &secp,
             )?)) as sync::Arc<sync::Mutex<dyn DatabaseInterface>>,
         };
+        setup_derivation_start_index(&config, &db, &secp)?;
 
         // Now, set up the Bitcoin interface.
         let bit = match bitcoin {
@@ -374,8 +486,12 @@ impl DaemonHandle {
 
         // Start the poller thread. Keep the thread handle to be able to check if it crashed. Store
         // an atomic to be able to stop it.
-        let bitcoin_poller =
-            poller::Poller::new(bit.clone(), db.clone(), config.main_descriptor.clone());
+        let bitcoin_poller = poller::Poller::new(
+            bit.clone(),
+            db.clone(),
+            config.main_descriptor.clone(),
+            config.spent_coin_retention.clone(),
+        );
         let (poller_sender, poller_receiver) = mpsc::sync_channel(0);
         let poller_handle = thread::Builder::new()
             .name("Bitcoin Network poller".to_string())
@@ -389,6 +505,8 @@ impl DaemonHandle {
             })
             .expect("Spawning the poller thread must never fail.");
 
+        #[cfg(feature = "daemon")]
+        let observer_socket = config.observer_socket;
         // Create the API the external world will use to talk to us, either directly through the Rust
         // structure or through the JSONRPC server we may setup below.
         let control = DaemonControl::new(config, bit, poller_sender.clone(), db, secp);
@@ -401,12 +519,15 @@ impl DaemonHandle {
                 .spawn({
                     let shutdown = rpcserver_shutdown.clone();
                     move || {
-                        let mut rpc_socket = data_dir;
-                        rpc_socket.push("lianad_rpc");
-                        let listener = rpcserver_setup(&rpc_socket)?;
+                        let listener = rpcserver_setup(&data_dir.join("lianad_rpc"))?;
+                        let observer_listener = if observer_socket {
+                            Some(rpcserver_setup(&data_dir.join("lianad_rpc_observer"))?)
+                        } else {
+                            None
+                        };
                         log::info!("JSONRPC server started.");
 
-                        rpcserver_loop(listener, control, shutdown)?;
+                        rpcserver_loop(listener, observer_listener, control, shutdown)?;
                         log::info!("JSONRPC server stopped.");
                         Ok(())
                     }
@@ -428,6 +549,155 @@ impl DaemonHandle {
         })
     }
 
+    /// Perform all the startup checks which do not involve the Bitcoin backend, without creating
+    /// or modifying anything. This gives feedback on an invalid configuration (for instance a
+    /// descriptor which does not match the existing database) without starting the daemon.
+    ///
+    /// This assumes the default Bitcoin and database interfaces (`bitcoind` RPC and SQLite).
+    pub fn validate(config: &Config) -> Result<(), StartupError> {
+        config.check()?;
+
+        let mut data_dir = config
+            .data_dir()
+            .ok_or(StartupError::DefaultDataDirNotFound)?;
+        data_dir.push(config.bitcoin_config.network.to_string());
+        check_writable(&data_dir)?;
+        if data_dir.as_path().exists() {
+            let db_path = data_dir.as_path().join("lianad.sqlite3");
+            SqliteDb::check_existing(
+                db_path,
+                config.db_encryption_key.clone(),
+                config.bitcoin_config.network,
+                &config.main_descriptor,
+            )?;
+            #[cfg(windows)]
+            if !data_dir.as_path().join("lianad_watchonly_wallet").exists() {
+                return Err(StartupError::NoWatchonlyInDatadir);
+            }
+        }
+
+        if let Some(log_dir) = config
+            .log_file
+            .as_ref()
+            .and_then(|p| p.parent())
+            .filter(|p| !p.as_os_str().is_empty())
+        {
+            check_writable(log_dir)?;
+        }
+
+        config
+            .bitcoind_config
+            .as_ref()
+            .ok_or(StartupError::MissingBitcoindConfig)?;
+        log::info!("Configuration validated.");
+
+        Ok(())
+    }
+
+    /// Restore a wallet exported with [`DaemonControl::export_wallet`] into a new data directory.
+    ///
+    /// The database is created from the exported descriptor, derivation indexes, labels and Spend
+    /// transactions and a rescan is started on `bitcoind` from the wallet birthday. The
+    /// coins and transactions are re-derived from the chain by this rescan, they are never taken
+    /// from the export. The daemon may then be started with this configuration as usual.
+    ///
+    /// This assumes the default Bitcoin and database interfaces (`bitcoind` RPC and SQLite).
+    pub fn import_wallet(config: &Config, export: &WalletExport) -> Result<(), StartupError> {
+        config.check()?;
+        let network = config.bitcoin_config.network;
+        if export.network != network {
+            return Err(StartupError::WalletImport(format!(
+                "the export is for network '{}' but the configuration is for network '{}'",
+                export.network, network
+            )));
+        }
+        if export.descriptor != config.main_descriptor {
+            return Err(StartupError::WalletImport(
+                "the exported descriptor does not match the configured one".to_string(),
+            ));
+        }
+        let labels = export
+            .labels
+            .iter()
+            .map(|(item, value)| {
+                LabelItem::from_str(item, network)
+                    .map(|item| (item, Some(value.clone())))
+                    .ok_or_else(|| {
+                        StartupError::WalletImport(format!("invalid labelled item '{}'", item))
+                    })
+            })
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        let coin_notes = export
+            .coin_notes
+            .iter()
+            .map(|(outpoint, note)| {
+                OutPoint::from_str(outpoint)
+                    .map(|outpoint| (outpoint, note))
+                    .map_err(|_| {
+                        StartupError::WalletImport(format!("invalid outpoint '{}'", outpoint))
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let index = |i: u32| {
+            bip32::ChildNumber::from_normal_idx(i).map_err(|_| {
+                StartupError::WalletImport(format!("invalid derivation index '{}'", i))
+            })
+        };
+        let (receive_index, change_index) =
+            (index(export.receive_index)?, index(export.change_index)?);
+
+        let mut data_dir = config
+            .data_dir()
+            .ok_or(StartupError::DefaultDataDirNotFound)?;
+        data_dir.push(network.to_string());
+        if data_dir.as_path().exists() {
+            return Err(StartupError::WalletImport(format!(
+                "a data directory already exists at '{}'",
+                data_dir.display()
+            )));
+        }
+        create_datadir(&data_dir)?;
+
+        let secp = secp256k1::Secp256k1::verification_only();
+        let db_path = data_dir.as_path().join("lianad.sqlite3");
+        let options = FreshDbOptions::new(network, config.main_descriptor.clone())
+            .with_timestamp(export.timestamp);
+        let sqlite = SqliteDb::new(
+            db_path,
+            Some(options),
+            &secp,
+            config.db_encryption_key.clone(),
+        )?;
+        sqlite.sanity_check(network, &config.main_descriptor)?;
+        let mut conn = sqlite.connection()?;
+        conn.set_receive_index(receive_index, &secp);
+        conn.set_change_index(change_index, &secp);
+        conn.update_labels(&labels);
+        for (outpoint, note) in coin_notes {
+            conn.set_coin_note(&outpoint, Some(note.as_str()));
+        }
+        for psbt in &export.spend_txs {
+            conn.store_spend(psbt);
+        }
+
+        // Rescan from the wallet birthday if it's known, it may be before the wallet creation time
+        // if the exported wallet was itself restored from an older one.
+        let bitcoind = setup_bitcoind(config, &data_dir, true)?;
+        let rescan_timestamp = export
+            .birthday_height
+            .and_then(|height| bitcoind.get_block_hash(height.try_into().ok()?))
+            .and_then(|hash| bitcoind.get_block_stats(hash))
+            .map(|stats| cmp::min(stats.time, export.timestamp))
+            .unwrap_or(export.timestamp);
+        conn.set_rescan(rescan_timestamp);
+        log::info!("Database restored from the wallet export.");
+
+        bitcoind.start_rescan(&config.main_descriptor, rescan_timestamp)?;
+        log::info!("Rescan of the chain started from the wallet birthday.");
+
+        Ok(())
+    }
+
     /// Start the Liana daemon with the default Bitcoin and database interfaces (`bitcoind` RPC
     /// and SQLite).
     pub fn start_default(
@@ -499,7 +769,7 @@ impl DaemonHandle {
 mod tests {
     use super::*;
     use crate::{
-        config::{BitcoinConfig, BitcoindConfig, BitcoindRpcAuth},
+        config::{BitcoinConfig, BitcoindConfig, BitcoindRpcAuth, FeeSource, SpentCoinRetention},
         descriptors::LianaDescriptor,
         testutils::*,
     };
@@ -728,7 +998,20 @@ mod tests {
             #[cfg(unix)]
             daemon: false,
             log_level: log::LevelFilter::Debug,
+            log_file: None,
             main_descriptor: desc,
+            fee_sources: vec![FeeSource::Backend],
+            db_encryption_key: None,
+            spent_coin_retention: SpentCoinRetention::KeepAll,
+            feerate_cache_secs: time::Duration::ZERO,
+            long_term_feerate_vb: None,
+            anti_fee_sniping: true,
+            max_spend_inputs: None,
+            derivation_start_index: 0,
+            session_passphrase_hash: None,
+            lock_read_commands: false,
+            spendable_min_confirmations: 0,
+            observer_socket: false,
         };
 
         // Start the daemon in a new thread so the current one acts as the bitcoind server.
@@ -781,4 +1064,122 @@ mod tests {
 
         fs::remove_dir_all(&tmp_dir).unwrap();
     }
+
+    #[test]
+    fn validate_config() {
+        let tmp_dir = tmp_dir();
+        fs::create_dir_all(&tmp_dir).unwrap();
+        let data_dir: path::PathBuf = [tmp_dir.as_path(), path::Path::new("datadir")]
+            .iter()
+            .collect();
+        let network_dir = data_dir.as_path().join("bitcoin");
+
+        let desc_str = "wsh(andor(pk([aabbccdd]xpub68JJTXc1MWK8KLW4HGLXZBJknja7kDUJuFHnM424LbziEXsfkh1WQCiEjjHw4zLqSUm4rvhgyGkkuRowE9tCJSgt3TQB5J3SKAbZ2SdcKST/<0;1>/*),older(10000),pk([aabbccdd]xpub68JJTXc1MWK8PEQozKsRatrUHXKFNkD1Cb1BuQU9Xr5moCv87anqGyXLyUd4KpnDyZgo3gz4aN1r3NiaoweFW8UutBsBbgKHzaD5HkTkifK/<0;1>/*)))#3xh8xmhn";
+        let desc = LianaDescriptor::from_str(desc_str).unwrap();
+        let mut config = Config {
+            bitcoin_config: BitcoinConfig {
+                network: bitcoin::Network::Bitcoin,
+                poll_interval_secs: time::Duration::from_secs(2),
+            },
+            bitcoind_config: Some(BitcoindConfig {
+                addr: net::SocketAddrV4::new(net::Ipv4Addr::new(127, 0, 0, 1), 8332).into(),
+                rpc_auth: BitcoindRpcAuth::CookieFile(tmp_dir.as_path().join("cookie")),
+            }),
+            data_dir: Some(data_dir),
+            #[cfg(unix)]
+            daemon: false,
+            log_level: log::LevelFilter::Debug,
+            log_file: None,
+            main_descriptor: desc.clone(),
+            fee_sources: vec![FeeSource::Backend],
+            db_encryption_key: None,
+            spent_coin_retention: SpentCoinRetention::KeepAll,
+            feerate_cache_secs: time::Duration::ZERO,
+            long_term_feerate_vb: None,
+            anti_fee_sniping: true,
+            max_spend_inputs: None,
+            derivation_start_index: 0,
+            session_passphrase_hash: None,
+            lock_read_commands: false,
+            spendable_min_confirmations: 0,
+            observer_socket: false,
+        };
+
+        // A fresh configuration is valid, and validating it doesn't create the data directory.
+        DaemonHandle::validate(&config).unwrap();
+        assert!(!network_dir.exists());
+
+        // An invalid configuration is rejected.
+        config.fee_sources = vec![FeeSource::Static { feerate: 0 }];
+        assert!(matches!(
+            DaemonHandle::validate(&config),
+            Err(StartupError::Config(_))
+        ));
+        config.fee_sources = vec![FeeSource::Backend];
+        config.bitcoind_config = None;
+        assert!(matches!(
+            DaemonHandle::validate(&config),
+            Err(StartupError::MissingBitcoindConfig)
+        ));
+        config.bitcoind_config = Some(BitcoindConfig {
+            addr: net::SocketAddrV4::new(net::Ipv4Addr::new(127, 0, 0, 1), 8332).into(),
+            rpc_auth: BitcoindRpcAuth::CookieFile(tmp_dir.as_path().join("cookie")),
+        });
+
+        // A data directory without a database is rejected.
+        fs::create_dir_all(&network_dir).unwrap();
+        assert!(matches!(
+            DaemonHandle::validate(&config),
+            Err(StartupError::Database(SqliteDbError::FileNotFound(_)))
+        ));
+
+        // Once the database is created, it is checked against the configuration.
+        let secp = secp256k1::Secp256k1::verification_only();
+        SqliteDb::new(
+            network_dir.as_path().join("lianad.sqlite3"),
+            Some(FreshDbOptions::new(bitcoin::Network::Bitcoin, desc)),
+            &secp,
+            None,
+        )
+        .unwrap();
+        DaemonHandle::validate(&config).unwrap();
+        let other_desc_str = "wsh(or_d(pk([aabbccdd]xpub68JJTXc1MWK8KLW4HGLXZBJknja7kDUJuFHnM424LbziEXsfkh1WQCiEjjHw4zLqSUm4rvhgyGkkuRowE9tCJSgt3TQB5J3SKAbZ2SdcKST/<0;1>/*),and_v(v:pkh([aabbccdd]xpub68JJTXc1MWK8PEQozKsRatrUHXKFNkD1Cb1BuQU9Xr5moCv87anqGyXLyUd4KpnDyZgo3gz4aN1r3NiaoweFW8UutBsBbgKHzaD5HkTkifK/<0;1>/*),older(100))))";
+        config.main_descriptor = LianaDescriptor::from_str(other_desc_str).unwrap();
+        assert!(matches!(
+            DaemonHandle::validate(&config),
+            Err(StartupError::Database(SqliteDbError::DescriptorMismatch(_)))
+        ));
+
+        fs::remove_dir_all(&tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn derivation_start_index() {
+        let secp = secp256k1::Secp256k1::verification_only();
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = ms.control();
+        let mut config = control.config.clone();
+        let next_index = || u32::from(control.get_new_address().unwrap().derivation_index);
+
+        // The receive addresses are generated from the start index.
+        config.derivation_start_index = 100;
+        setup_derivation_start_index(&config, &control.db(), &secp).unwrap();
+        assert_eq!(next_index(), 100);
+        assert_eq!(next_index(), 101);
+
+        // Lowering the start index doesn't go back to the lower addresses.
+        config.derivation_start_index = 50;
+        setup_derivation_start_index(&config, &control.db(), &secp).unwrap();
+        assert_eq!(next_index(), 102);
+
+        // The start index can't be raised above the addresses already generated.
+        config.derivation_start_index = 200;
+        assert!(matches!(
+            setup_derivation_start_index(&config, &control.db(), &secp),
+            Err(StartupError::DerivationStartIndex(200, 103))
+        ));
+        assert_eq!(next_index(), 103);
+
+        ms.shutdown();
+    }
 }
@@ -1,9 +1,10 @@
-use crate::{bitcoin::MempoolEntry, descriptors};
+use crate::{bitcoin::MempoolEntry, descriptors, random};
 
 use std::{
     collections::{BTreeMap, HashMap},
-    convert::TryInto,
+    convert::{TryFrom, TryInto},
     fmt,
+    str::FromStr,
 };
 
 pub use bdk_coin_select::InsufficientFunds;
@@ -16,7 +17,11 @@ use miniscript::bitcoin::{
     absolute::{Height, LockTime},
     bip32,
     constants::WITNESS_SCALE_FACTOR,
-    psbt::{Input as PsbtIn, Output as PsbtOut, Psbt},
+    hashes::{sha256, Hash, HashEngine},
+    hex::{DisplayHex, FromHex},
+    opcodes::all::OP_RETURN,
+    psbt::{self, Input as PsbtIn, Output as PsbtOut, Psbt},
+    script::{Instruction, PushBytesBuf},
     secp256k1,
 };
 use serde::{Deserialize, Serialize};
@@ -25,8 +30,8 @@ use serde::{Deserialize, Serialize};
 /// That's 1$ at 20_000$ per BTC.
 pub const DUST_OUTPUT_SATS: u64 = 5_000;
 
-/// Long-term feerate (sats/vb) used for coin selection considerations.
-pub const LONG_TERM_FEERATE_VB: f32 = 10.0;
+/// Default long-term feerate (sats/vb) used for coin selection considerations.
+pub const LONG_TERM_FEERATE_VB: u64 = 10;
 
 /// Assume that paying more than 1BTC in fee is a bug.
 pub const MAX_FEE: bitcoin::Amount = bitcoin::Amount::ONE_BTC;
@@ -34,6 +39,78 @@ pub const MAX_FEE: bitcoin::Amount = bitcoin::Amount::ONE_BTC;
 /// Assume that paying more than 1000sat/vb in feerate is a bug.
 pub const MAX_FEERATE: u64 = 1_000;
 
+/// The maximum size of the data carried by an OP_RETURN output for it to be standard.
+pub const MAX_OP_RETURN_DATA_SIZE: usize = 80;
+
+/// A feerate in sats/vb, with a precision of a thousandth of a sat/vb. This allows to target a
+/// fractional feerate without overpaying by rounding it up to the next integer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Feerate(/* sats/kvb */ u64);
+
+impl Feerate {
+    pub fn from_sat_per_vb(sats_vb: u64) -> Feerate {
+        Feerate(sats_vb.saturating_mul(1_000))
+    }
+
+    /// Get a feerate from a fractional number of sats/vb, rounded to the nearest thousandth.
+    /// Returns `None` if it is negative, not a number or too large to be represented.
+    pub fn from_sat_per_vb_f64(sats_vb: f64) -> Option<Feerate> {
+        if !(0.0..=(u64::MAX / 1_000) as f64).contains(&sats_vb) {
+            return None;
+        }
+        Some(Feerate((sats_vb * 1_000.0).round() as u64))
+    }
+
+    pub fn to_sat_per_kvb(self) -> u64 {
+        self.0
+    }
+
+    /// The feerate in sats/vb, rounded up.
+    pub fn to_sat_per_vb_ceil(self) -> u64 {
+        self.0.saturating_add(999) / 1_000
+    }
+
+    /// The fee in sats for a transaction of this virtual size at this feerate, rounded up.
+    pub fn fee_vb(self, vbytes: u64) -> u64 {
+        self.0.saturating_mul(vbytes).saturating_add(999) / 1_000
+    }
+}
+
+/// The fee to pay at this feerate for an input spending a coin derived from this descriptor,
+/// assuming the largest satisfaction among its spending paths. A coin worth less than this costs
+/// more to spend than it's worth.
+pub fn max_input_fee(desc: &descriptors::LianaDescriptor, feerate: Feerate) -> bitcoin::Amount {
+    let weight: u64 = (TXIN_BASE_WEIGHT as usize)
+        .checked_add(desc.max_sat_weight())
+        .unwrap()
+        .try_into()
+        .expect("Input weight must fit in u64");
+    let vbytes = weight
+        .checked_add(WITNESS_SCALE_FACTOR as u64 - 1)
+        .unwrap()
+        .checked_div(WITNESS_SCALE_FACTOR as u64)
+        .unwrap();
+    bitcoin::Amount::from_sat(feerate.fee_vb(vbytes))
+}
+
+impl From<u64> for Feerate {
+    fn from(sats_vb: u64) -> Feerate {
+        Feerate::from_sat_per_vb(sats_vb)
+    }
+}
+
+impl fmt::Display for Feerate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (int, frac) = (self.0 / 1_000, self.0 % 1_000);
+        if frac == 0 {
+            write!(f, "{}", int)
+        } else {
+            let frac = format!("{:03}", frac);
+            write!(f, "{}.{}", int, frac.trim_end_matches('0'))
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InsaneFeeInfo {
     NegativeFee,
@@ -44,12 +121,16 @@ pub enum InsaneFeeInfo {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SpendCreationError {
-    InvalidFeerate(/* sats/vb */ u64),
+    InvalidFeerate(Feerate),
     InvalidOutputValue(bitcoin::Amount),
     InsaneFees(InsaneFeeInfo),
     SanityCheckFailure(Psbt),
     FetchingTransaction(bitcoin::OutPoint),
     CoinSelection(InsufficientFunds),
+    /// Funding the transaction requires at least this number of inputs, more than allowed.
+    TooManyInputsRequired(usize),
+    /// The data to be carried by an OP_RETURN output is too large, of the given size in bytes.
+    OpReturnTooLarge(usize),
 }
 
 impl fmt::Display for SpendCreationError {
@@ -74,6 +155,16 @@ impl fmt::Display for SpendCreationError {
                 write!(f, "Could not fetch transaction for coin {}", op)
             }
             Self::CoinSelection(e) => write!(f, "Coin selection error: '{}'", e),
+            Self::TooManyInputsRequired(needed) => write!(
+                f,
+                "Funding the transaction requires at least {} inputs, more than allowed.",
+                needed
+            ),
+            Self::OpReturnTooLarge(size) => write!(
+                f,
+                "OP_RETURN data of {} bytes exceeds the maximum of {} bytes.",
+                size, MAX_OP_RETURN_DATA_SIZE
+            ),
             Self::SanityCheckFailure(psbt) => write!(
                 f,
                 "BUG! Please report this. Failed sanity checks for PSBT '{}'.",
@@ -94,11 +185,28 @@ fn check_output_value(value: bitcoin::Amount) -> Result<(), SpendCreationError>
     }
 }
 
+/// Get the data carried by this script if it is a standard OP_RETURN output's one.
+pub fn op_return_data(script: &bitcoin::Script) -> Option<Vec<u8>> {
+    let mut instructions = script.instructions();
+    match (
+        instructions.next(),
+        instructions.next(),
+        instructions.next(),
+    ) {
+        (Some(Ok(Instruction::Op(OP_RETURN))), Some(Ok(Instruction::PushBytes(data))), None) => {
+            Some(data.as_bytes().to_vec())
+        }
+        (Some(Ok(Instruction::Op(OP_RETURN))), None, None) => Some(Vec::new()),
+        _ => None,
+    }
+}
+
 // Apply some sanity checks on a created transaction's PSBT.
 // TODO: add more sanity checks from revault_tx
 fn sanity_check_psbt(
     spent_desc: &descriptors::LianaDescriptor,
     psbt: &Psbt,
+    foreign_inputs: &[ForeignInput],
 ) -> Result<(), SpendCreationError> {
     let tx = &psbt.unsigned_tx;
 
@@ -110,11 +218,14 @@ fn sanity_check_psbt(
         return Err(SpendCreationError::SanityCheckFailure(psbt.clone()));
     }
 
-    // Compute the transaction input value, checking all PSBT inputs have the derivation
-    // index set for signing devices to recognize them as ours.
+    // Compute the transaction input value, checking all PSBT inputs but the foreign ones have
+    // the derivation index set for signing devices to recognize them as ours.
     let mut value_in = 0;
-    for psbtin in psbt.inputs.iter() {
-        if psbtin.bip32_derivation.is_empty() && psbtin.tap_key_origins.is_empty() {
+    for (txin, psbtin) in tx.input.iter().zip(psbt.inputs.iter()) {
+        let is_foreign = foreign_inputs
+            .iter()
+            .any(|i| i.outpoint == txin.previous_output);
+        if !is_foreign && psbtin.bip32_derivation.is_empty() && psbtin.tap_key_origins.is_empty() {
             return Err(SpendCreationError::SanityCheckFailure(psbt.clone()));
         }
         value_in += psbtin
@@ -137,7 +248,7 @@ fn sanity_check_psbt(
     }
 
     // Check the feerate isn't insane.
-    let tx_vb = spent_desc.unsigned_tx_max_vbytes(tx);
+    let tx_vb = unsigned_tx_max_vbytes(spent_desc, tx, foreign_inputs);
     let feerate_sats_vb = abs_fee
         .checked_div(tx_vb)
         .ok_or(SpendCreationError::InsaneFees(
@@ -159,6 +270,53 @@ fn sanity_check_psbt(
     Ok(())
 }
 
+// The maximum size of this transaction once satisfied, in virtual bytes. Same as
+// [`descriptors::LianaDescriptor::unsigned_tx_max_vbytes`] but accounting for the satisfaction
+// weight of the foreign inputs.
+fn unsigned_tx_max_vbytes(
+    desc: &descriptors::LianaDescriptor,
+    tx: &bitcoin::Transaction,
+    foreign_inputs: &[ForeignInput],
+) -> u64 {
+    if foreign_inputs.is_empty() {
+        return desc.unsigned_tx_max_vbytes(tx);
+    }
+    let max_sat_weight: u64 = desc.max_sat_weight().try_into().unwrap();
+    let sat_weight: u64 = tx
+        .input
+        .iter()
+        .map(|txin| {
+            foreign_inputs
+                .iter()
+                .find(|i| i.outpoint == txin.previous_output)
+                .map(|i| u64::from(i.max_sat_weight))
+                .unwrap_or(max_sat_weight)
+        })
+        .sum();
+    let witness_factor: u64 = WITNESS_SCALE_FACTOR.try_into().unwrap();
+    tx.weight()
+        .to_wu()
+        .checked_add(sat_weight)
+        .unwrap()
+        .checked_add(witness_factor.checked_sub(1).unwrap())
+        .unwrap()
+        .checked_div(witness_factor)
+        .unwrap()
+}
+
+/// An input spending a coin which isn't ours, for instance to create a transaction together with
+/// other participants. It is left for its owner to sign.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ForeignInput {
+    /// The coin spent by this input.
+    pub outpoint: bitcoin::OutPoint,
+    /// The output spent by this input, which carries its amount. Set in the PSBT input.
+    pub witness_utxo: bitcoin::TxOut,
+    /// The maximum difference in weight of this input before and after satisfaction, accounting
+    /// for the witness stack size. Used to pay the fee for this input.
+    pub max_sat_weight: u32,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct AncestorInfo {
     pub vsize: u32,
@@ -255,6 +413,9 @@ impl bdk_coin_select::BnbMetric for LowestFeeChangeCondition {
 ///
 /// `candidate_coins` are the coins to consider for selection.
 ///
+/// `foreign_inputs` are inputs which are always part of the transaction but aren't ours. They
+/// contribute their value and weight to the selection, but aren't part of the returned selection.
+///
 /// `base_tx` is the transaction to select coins for. It should be without any inputs
 /// and without a change output, but with all non-change outputs added.
 ///
@@ -269,20 +430,30 @@ impl bdk_coin_select::BnbMetric for LowestFeeChangeCondition {
 /// descendants, which will ensure that RBF rule 4 is satisfied.
 /// Otherwise, it should be `None`.
 ///
+/// `long_term_feerate_vb` is the feerate (in sats/vb) at which we expect to spend the change
+/// output in the future. The higher it is compared to `feerate_vb`, the more the selection favours
+/// spending more coins now and avoiding to create change.
+///
 /// `max_sat_weight` is the maximum weight difference of an input in the
 /// transaction before and after satisfaction.
 ///
 /// `must_have_change` indicates whether the transaction must have a change output.
 /// If `true`, the returned change amount will be positive.
+///
+/// `max_inputs` is the maximum number of inputs of the transaction, foreign inputs included.
+#[allow(clippy::too_many_arguments)]
 fn select_coins_for_spend(
     candidate_coins: &[CandidateCoin],
+    foreign_inputs: &[ForeignInput],
     base_tx: bitcoin::Transaction,
     change_txo: bitcoin::TxOut,
     feerate_vb: f32,
+    long_term_feerate_vb: f32,
     replaced_fee: Option<u64>,
     max_sat_weight: u32,
     must_have_change: bool,
-) -> Result<CoinSelectionRes, InsufficientFunds> {
+    max_inputs: Option<usize>,
+) -> Result<CoinSelectionRes, SpendCreationError> {
     let out_value_nochange = base_tx.output.iter().map(|o| o.value.to_sat()).sum();
     let out_weight_nochange: u32 = {
         let mut total: u32 = 0;
@@ -301,8 +472,7 @@ fn select_coins_for_spend(
     let n_outputs_nochange = base_tx.output.len();
     let max_input_weight = TXIN_BASE_WEIGHT + max_sat_weight;
     // Get feerate as u32 for calculation relating to ancestor below.
-    // We expect `feerate_vb` to be a positive integer, but take ceil()
-    // just in case to be sure we pay enough for ancestors.
+    // The feerate may be fractional, so take ceil() to be sure we pay enough for ancestors.
     let feerate_vb_u32 = feerate_vb.ceil() as u32;
     let witness_factor: u32 = WITNESS_SCALE_FACTOR
         .try_into()
@@ -344,21 +514,39 @@ fn select_coins_for_spend(
             },
             is_segwit: true, // We only support receiving on Segwit scripts.
         })
+        .chain(foreign_inputs.iter().map(|input| {
+            Candidate {
+                input_count: 1,
+                value: input.witness_utxo.value.to_sat(),
+                weight: TXIN_BASE_WEIGHT
+                    .checked_add(input.max_sat_weight)
+                    .expect("effective weight must fit in u32"),
+                is_segwit: true, // A witness UTXO is given for it.
+            }
+        }))
         .collect();
-    let mut selector = CoinSelector::new(&candidates);
-    for (i, cand) in candidate_coins.iter().enumerate() {
-        if cand.must_select {
-            // It's fine because the index passed to `select` refers to the original candidates ordering
-            // (and in any case the ordering of candidates is still the same in the coin selector).
+    let new_selector = || {
+        let mut selector = CoinSelector::new(&candidates);
+        for (i, cand) in candidate_coins.iter().enumerate() {
+            if cand.must_select {
+                // It's fine because the index passed to `select` refers to the original candidates ordering
+                // (and in any case the ordering of candidates is still the same in the coin selector).
+                selector.select(i);
+            }
+        }
+        // The foreign inputs come after our candidates, and are always part of the transaction.
+        for i in candidate_coins.len()..candidates.len() {
             selector.select(i);
         }
-    }
+        selector
+    };
+    let mut selector = new_selector();
 
     // Now set the change policy. We use a policy which ensures no change output is created with a
     // lower value than our custom dust limit. NOTE: the change output weight must not account for
     // a potential difference in the size of the outputs count varint.
     let feerate = FeeRate::from_sat_per_vb(feerate_vb);
-    let long_term_feerate = FeeRate::from_sat_per_vb(LONG_TERM_FEERATE_VB);
+    let long_term_feerate = FeeRate::from_sat_per_vb(long_term_feerate_vb);
     let change_output_weight: u32 = change_txo
         .weight()
         .to_wu()
@@ -438,10 +626,47 @@ fn select_coins_for_spend(
                     drain
                 };
                 let missing = selector.excess(target, drain).unsigned_abs();
-                return Err(InsufficientFunds { missing });
+                return Err(SpendCreationError::CoinSelection(InsufficientFunds {
+                    missing,
+                }));
             }
         }
     }
+    // If too many inputs were selected, select again from the coins with the largest value, as it
+    // minimizes the number of inputs. There is no need to check the cap again if it still isn't
+    // enough, since this is the smallest number of inputs we can get.
+    if let Some(max_inputs) = max_inputs {
+        if selector.selected_indices().len() > max_inputs {
+            log::debug!(
+                "Coin selection spends {} inputs, more than the maximum of {}. Selecting coins \
+                 by descending value...",
+                selector.selected_indices().len(),
+                max_inputs
+            );
+            let mut greedy_selector = new_selector();
+            greedy_selector.sort_candidates_by_key(|(_, cand)| std::cmp::Reverse(cand.value));
+            loop {
+                let drain = greedy_selector.drain(target, change_policy);
+                if greedy_selector.is_target_met_with_drain(target, drain)
+                    && (drain.is_some() || !must_have_change)
+                {
+                    break;
+                }
+                if !greedy_selector.select_next() {
+                    // The target could be met by the first selection, so this is unlikely. But
+                    // ancestors may make the largest coins less efficient to spend.
+                    break;
+                }
+            }
+            let needed = greedy_selector.selected_indices().len();
+            if needed > max_inputs {
+                return Err(SpendCreationError::TooManyInputsRequired(
+                    needed.min(selector.selected_indices().len()),
+                ));
+            }
+            selector = greedy_selector;
+        }
+    }
     // By now, selection is complete and we can check how much change to give according to our policy.
     let drain = selector.drain(target, change_policy);
     let change_amount = bitcoin::Amount::from_sat(drain.value);
@@ -461,6 +686,7 @@ fn select_coins_for_spend(
     let selected = selector
         .selected_indices()
         .iter()
+        .filter(|i| **i < candidate_coins.len())
         .map(|i| candidate_coins[*i])
         .inspect(|cand| {
             total_added_weight = total_added_weight
@@ -520,11 +746,11 @@ pub trait TxGetter {
 /// https://github.com/bitcoin/bitcoin/blob/master/doc/policy/mempool-replacements.md for more
 /// information about how it should be set.
 pub enum SpendTxFees {
-    /// The target feerate in sats/vb for this transaction.
-    Regular(u64),
-    /// The (target feerate in sats/vb, total fees in sats of transaction(s) to be replaced
-    /// including descendants) for this transaction.
-    Rbf(u64, u64),
+    /// The target feerate for this transaction.
+    Regular(Feerate),
+    /// The (target feerate, total fees in sats of transaction(s) to be replaced including
+    /// descendants) for this transaction.
+    Rbf(Feerate, u64),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -557,6 +783,158 @@ impl fmt::Display for CreateSpendWarning {
     }
 }
 
+// The prefix of the proprietary PSBT global fields we set.
+const PSBT_PROPRIETARY_PREFIX: &[u8] = b"liana";
+
+// The subtype of the proprietary PSBT global field containing the draft identifier.
+const PSBT_PROPRIETARY_DRAFT_ID: u8 = 0x00;
+
+fn draft_id_key() -> psbt::raw::ProprietaryKey {
+    psbt::raw::ProprietaryKey {
+        prefix: PSBT_PROPRIETARY_PREFIX.to_vec(),
+        subtype: PSBT_PROPRIETARY_DRAFT_ID,
+        key: Vec::new(),
+    }
+}
+
+/// A stable identifier for a Spend transaction draft. Contrary to the txid, it doesn't change
+/// when the transaction is modified (for instance to bump its fee).
+///
+/// This is a random (version 4) UUID, stored in a proprietary global field of the PSBT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DraftId([u8; 16]);
+
+impl DraftId {
+    /// Generate a new random draft identifier.
+    pub fn random() -> Result<DraftId, random::RandomnessError> {
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&random::random_bytes()?[..16]);
+        // Set the UUID version (4) and variant (RFC 4122).
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        Ok(DraftId(bytes))
+    }
+
+    /// Get the draft identifier stored in this PSBT, if any.
+    pub fn from_psbt(psbt: &Psbt) -> Option<DraftId> {
+        psbt.proprietary
+            .get(&draft_id_key())
+            .and_then(|value| value.as_slice().try_into().ok())
+            .map(DraftId)
+    }
+
+    /// Store this draft identifier in the PSBT, replacing any existing one.
+    pub fn set_in_psbt(&self, psbt: &mut Psbt) {
+        psbt.proprietary.insert(draft_id_key(), self.0.to_vec());
+    }
+}
+
+impl fmt::Display for DraftId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if [4, 6, 8, 10].contains(&i) {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for DraftId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DraftId, Self::Err> {
+        let groups: Vec<&str> = s.split('-').collect();
+        let groups_len: Vec<usize> = groups.iter().map(|g| g.len()).collect();
+        let hex = groups.concat();
+        if groups_len != [8, 4, 4, 4, 12] || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("Invalid draft id '{}'.", s));
+        }
+        let mut bytes = [0; 16];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).expect("Checked above");
+        }
+        Ok(DraftId(bytes))
+    }
+}
+
+impl Serialize for DraftId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for DraftId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        DraftId::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// A seed determining the position of the change output(s) among the outputs of a Spend
+/// transaction. The same seed always gives the same ordering for the same outputs, which allows
+/// to verify after the fact that the placement of the change was not manipulated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputSeed([u8; 32]);
+
+impl OutputSeed {
+    /// Generate a new random seed.
+    pub fn random() -> Result<OutputSeed, random::RandomnessError> {
+        random::random_bytes().map(OutputSeed)
+    }
+
+    /// Move the last `change_count` outputs of this PSBT, its change outputs, each to a position
+    /// among the other outputs determined by this seed. The relative order of the other outputs
+    /// is preserved.
+    pub fn place_change_outputs(&self, psbt: &mut Psbt, change_count: usize) {
+        let first_change = psbt.unsigned_tx.output.len() - change_count;
+        let change_txos = psbt.unsigned_tx.output.split_off(first_change);
+        let change_psbt_outs = psbt.outputs.split_off(first_change);
+        for (i, (txo, psbt_out)) in change_txos.into_iter().zip(change_psbt_outs).enumerate() {
+            let mut engine = sha256::HashEngine::default();
+            engine.input(&self.0);
+            engine.input(&(i as u32).to_le_bytes());
+            let hash = sha256::Hash::from_engine(engine).to_byte_array();
+            let mut rand_bytes = [0; 8];
+            rand_bytes.copy_from_slice(&hash[..8]);
+            let position =
+                u64::from_le_bytes(rand_bytes) % (psbt.unsigned_tx.output.len() as u64 + 1);
+            psbt.unsigned_tx.output.insert(position as usize, txo);
+            psbt.outputs.insert(position as usize, psbt_out);
+        }
+    }
+}
+
+impl fmt::Display for OutputSeed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.as_hex())
+    }
+}
+
+impl FromStr for OutputSeed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OutputSeed, Self::Err> {
+        <[u8; 32]>::from_hex(s)
+            .map(OutputSeed)
+            .map_err(|_| format!("Invalid output seed '{}'.", s))
+    }
+}
+
+impl Serialize for OutputSeed {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for OutputSeed {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        OutputSeed::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
 pub struct CreateSpendRes {
     /// The created PSBT.
     pub psbt: Psbt,
@@ -585,24 +963,39 @@ pub struct CreateSpendRes {
 /// `destinations` is empty, they will all be included as inputs of the transaction. Otherwise, a
 /// coin selection algorithm will be run to spend the most efficient subset of them to meet the
 /// `destinations` requirements.
+/// * `foreign_inputs`: inputs which aren't ours to always include in the transaction, after our
+/// own. Their value and size is accounted for, but they are left for their owners to sign.
 /// * `fees`: the target feerate (in sats/vb) and, if necessary, minimum absolute fee for this tx.
+/// * `long_term_feerate`: the feerate at which we expect to spend a change output in the future,
+/// used by the coin selection to weigh creating change against spending more coins now.
+/// * `max_inputs`: the maximum number of inputs of the transaction, foreign inputs included. If
+/// the coin selection needs more, the coins with the largest value are selected instead. If it is
+/// still not enough a [`SpendCreationError::TooManyInputsRequired`] is returned.
 /// * `change_addr`: the address to use for a change output if we need to create one. Can be set to
 /// an external address (if combined with an empty list of `destinations` it's useful to sweep some
 /// or all coins of a wallet to an external address).
+/// * `op_return`: some data, of at most `MAX_OP_RETURN_DATA_SIZE` bytes, to be carried by an
+/// additional zero-value OP_RETURN output.
+#[allow(clippy::too_many_arguments)]
 pub fn create_spend(
     main_descriptor: &descriptors::LianaDescriptor,
     secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
     tx_getter: &mut impl TxGetter,
     destinations: &[(SpendOutputAddress, bitcoin::Amount)],
     candidate_coins: &[CandidateCoin],
+    foreign_inputs: &[ForeignInput],
     fees: SpendTxFees,
+    long_term_feerate: Feerate,
+    max_inputs: Option<usize>,
     change_addr: SpendOutputAddress,
+    op_return: Option<&[u8]>,
 ) -> Result<CreateSpendRes, SpendCreationError> {
     // This method does quite a few things. In addition, we support different modes (coin control
     // vs automated coin selection, self-spend, sweep, etc..) which make the logic a bit more
     // intricate. Here is a brief overview of what we're doing here:
     // 1. Create a transaction with all the target outputs (if this is a self-send, none are added
-    //    at this step the only output will be added as a change output).
+    //    at this step the only output will be added as a change output), along with the OP_RETURN
+    //    output if any.
     // 2. Automatically select the coins if necessary and determine whether a change output will be
     //    necessary for this transaction from the set of (automatically or manually) selected
     //    coins. The output for a self-send is added there.  The change output is also (ab)used to
@@ -618,14 +1011,15 @@ pub fn create_spend(
         SpendTxFees::Rbf(feerate, fee) => (feerate, Some(fee)),
     };
     let is_self_send = destinations.is_empty();
-    if feerate_vb < 1 {
+    if feerate_vb < Feerate::from_sat_per_vb(1) {
         return Err(SpendCreationError::InvalidFeerate(feerate_vb));
     }
 
     // Create transaction with no inputs and no outputs.
     let mut tx = bitcoin::Transaction {
         version: bitcoin::transaction::Version::TWO,
-        lock_time: LockTime::Blocks(Height::ZERO), // TODO: randomized anti fee sniping
+        // The caller may set an anti fee sniping locktime once the transaction is created.
+        lock_time: LockTime::Blocks(Height::ZERO),
         input: Vec::with_capacity(candidate_coins.iter().filter(|c| c.must_select).count()),
         output: Vec::with_capacity(destinations.len()),
     };
@@ -655,6 +1049,21 @@ pub fn create_spend(
     }
     assert_eq!(tx.output.is_empty(), is_self_send);
 
+    // If requested, also attach some data to the transaction through a zero-value OP_RETURN
+    // output. It is accounted for in the size of the transaction during coin selection.
+    if let Some(data) = op_return {
+        if data.len() > MAX_OP_RETURN_DATA_SIZE {
+            return Err(SpendCreationError::OpReturnTooLarge(data.len()));
+        }
+        let data = PushBytesBuf::try_from(data.to_vec()).expect("Checked the size above");
+        tx.output.push(bitcoin::TxOut {
+            value: bitcoin::Amount::ZERO,
+            script_pubkey: bitcoin::ScriptBuf::new_op_return(&data),
+        });
+        psbt_outs.push(PsbtOut::default());
+    }
+    let n_outputs = tx.output.len();
+
     // Now compute whether we'll need a change output while automatically selecting coins to be
     // used as input if necessary.
     // We need to get the size of a potential change output to select coins / determine whether
@@ -674,29 +1083,30 @@ pub fn create_spend(
         // At this point the transaction still has no input and no change output, as expected
         // by the coins selection helper function.
         assert!(tx.input.is_empty());
-        assert_eq!(tx.output.len(), destinations.len());
-        // TODO: Introduce general conversion error type.
-        let feerate_vb: f32 = {
-            let fr: u16 = feerate_vb.try_into().map_err(|_| {
-                SpendCreationError::InsaneFees(InsaneFeeInfo::TooHighFeerate(feerate_vb))
-            })?;
-            fr
+        assert_eq!(tx.output.len(), n_outputs);
+        if feerate_vb > Feerate::from_sat_per_vb(u16::MAX.into()) {
+            return Err(SpendCreationError::InsaneFees(
+                InsaneFeeInfo::TooHighFeerate(feerate_vb.to_sat_per_vb_ceil()),
+            ));
         }
-        .into();
+        let feerate_vb = feerate_vb.to_sat_per_kvb() as f32 / 1_000.0;
+        let long_term_feerate_vb = long_term_feerate.to_sat_per_kvb() as f32 / 1_000.0;
         let max_sat_wu = main_descriptor
             .max_sat_weight()
             .try_into()
             .expect("Weight must fit in a u32");
         select_coins_for_spend(
             candidate_coins,
+            foreign_inputs,
             tx.clone(),
             change_txo.clone(),
             feerate_vb,
+            long_term_feerate_vb,
             replaced_fee,
             max_sat_wu,
             is_self_send,
-        )
-        .map_err(SpendCreationError::CoinSelection)?
+            max_inputs,
+        )?
     };
     // If necessary, add a change output.
     // For a self-send, coin selection will only find solutions with change and will otherwise
@@ -761,17 +1171,31 @@ pub fn create_spend(
         psbt_ins.push(psbt_in);
     }
 
+    // Add the foreign inputs after ours. Only the spent output is set in their PSBT input, their
+    // owners will provide what is needed to sign them.
+    for input in foreign_inputs {
+        tx.input.push(bitcoin::TxIn {
+            previous_output: input.outpoint,
+            sequence: bitcoin::Sequence::ENABLE_RBF_NO_LOCKTIME,
+            ..bitcoin::TxIn::default()
+        });
+        psbt_ins.push(PsbtIn {
+            witness_utxo: Some(input.witness_utxo.clone()),
+            ..PsbtIn::default()
+        });
+    }
+
     // Finally, create the PSBT with all inputs and outputs, sanity check it and return it.
     let psbt = Psbt {
         unsigned_tx: tx,
         version: 0,
-        xpub: BTreeMap::new(),
+        xpub: main_descriptor.global_xpubs(),
         proprietary: BTreeMap::new(),
         unknown: BTreeMap::new(),
         inputs: psbt_ins,
         outputs: psbt_outs,
     };
-    sanity_check_psbt(main_descriptor, &psbt)?;
+    sanity_check_psbt(main_descriptor, &psbt, foreign_inputs)?;
     // TODO: maybe check for common standardness rules (max size, ..)?
 
     Ok(CreateSpendRes {
@@ -780,3 +1204,80 @@ pub fn create_spend(
         warnings,
     })
 }
+
+/// Set the absolute fee of this PSBT by adjusting the value of its change output at index
+/// `change_index`. Returns `false` and leaves the PSBT untouched if the change output can't
+/// afford it while staying above `DUST_OUTPUT_SATS`.
+pub fn set_fee_from_change(psbt: &mut Psbt, change_index: usize, fee: bitcoin::Amount) -> bool {
+    let current_fee = match psbt.fee() {
+        Ok(fee) => fee,
+        Err(_) => return false,
+    };
+    let change_value = psbt.unsigned_tx.output[change_index].value;
+    let new_value = match (change_value + current_fee).checked_sub(fee) {
+        Some(value) if value.to_sat() >= DUST_OUTPUT_SATS => value,
+        _ => return false,
+    };
+    psbt.unsigned_tx.output[change_index].value = new_value;
+    true
+}
+
+/// Split the change output at index `change_index` of this PSBT into itself and one additional
+/// output to each of the `extra_change_addrs`, for privacy. The fee for the additional outputs at
+/// `feerate` is deducted from the change and the rest is split evenly between the change
+/// outputs. If the change can't afford all of them above `DUST_OUTPUT_SATS`, only as many as it
+/// can afford are created.
+///
+/// Returns the number of additional change outputs created.
+pub fn split_change_output(
+    main_descriptor: &descriptors::LianaDescriptor,
+    secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+    psbt: &mut Psbt,
+    change_index: usize,
+    extra_change_addrs: &[SpendOutputAddress],
+    feerate: Feerate,
+) -> usize {
+    let change_value = psbt.unsigned_tx.output[change_index].value.to_sat();
+    let base_vsize = psbt.unsigned_tx.vsize() as u64;
+
+    for count in (1..=extra_change_addrs.len()).rev() {
+        let addrs = &extra_change_addrs[..count];
+        let mut tx = psbt.unsigned_tx.clone();
+        tx.output.extend(addrs.iter().map(|addr| bitcoin::TxOut {
+            value: bitcoin::Amount::ZERO,
+            script_pubkey: addr.addr.script_pubkey(),
+        }));
+        let extra_fee = feerate.fee_vb(tx.vsize() as u64 - base_vsize);
+        let value = match change_value.checked_sub(extra_fee) {
+            Some(value) => value / (count as u64 + 1),
+            None => continue,
+        };
+        if value < DUST_OUTPUT_SATS {
+            continue;
+        }
+
+        // The original change output gets the remainder of the division.
+        let first_value = change_value - extra_fee - value * count as u64;
+        tx.output[change_index].value = bitcoin::Amount::from_sat(first_value);
+        for txo in tx.output.iter_mut().rev().take(count) {
+            txo.value = bitcoin::Amount::from_sat(value);
+        }
+        psbt.unsigned_tx = tx;
+        for addr in addrs {
+            let mut psbt_out = PsbtOut::default();
+            if let Some(AddrInfo { index, is_change }) = addr.info {
+                let desc = if is_change {
+                    main_descriptor.change_descriptor()
+                } else {
+                    main_descriptor.receive_descriptor()
+                };
+                desc.derive(index, secp)
+                    .update_change_psbt_out(&mut psbt_out);
+            }
+            psbt.outputs.push(psbt_out);
+        }
+        return count;
+    }
+
+    0
+}
@@ -1,7 +1,13 @@
 use crate::{
-    bitcoin::{BitcoinInterface, Block, BlockChainTip, MempoolEntry, SyncProgress, UTxO},
-    config::{BitcoinConfig, Config},
-    database::{BlockInfo, Coin, CoinStatus, DatabaseConnection, DatabaseInterface, LabelItem},
+    bitcoin::{
+        BackendInfo, BackendKind, BitcoinInterface, Block, BlockChainTip, MempoolAcceptResult,
+        MempoolEntry, SyncProgress, UTxO,
+    },
+    config::{BitcoinConfig, Config, DbEncryptionKey, FeeSource, SpentCoinRetention},
+    database::{
+        BlockInfo, Coin, CoinStatus, DatabaseConnection, DatabaseInterface, DatabaseStats,
+        DroppedCoin, FeerateSample, LabelItem, StoredAddresses,
+    },
     descriptors, DaemonControl, DaemonHandle,
 };
 
@@ -21,6 +27,11 @@ use miniscript::{
 
 pub struct DummyBitcoind {
     pub txs: HashMap<Txid, (Transaction, Option<Block>)>,
+    pub feerate: Option<u64>,
+    /// Whether reconnecting to this backend succeeds.
+    pub reachable: bool,
+    /// The timestamp in the header of the tip block.
+    pub tip_time: Option<u32>,
 }
 
 impl DummyBitcoind {}
@@ -29,6 +40,12 @@ impl DummyBitcoind {
     pub fn new() -> Self {
         Self {
             txs: HashMap::new(),
+            feerate: None,
+            reachable: true,
+            tip_time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|d| d.as_secs() as u32),
         }
     }
 }
@@ -64,6 +81,24 @@ impl BitcoinInterface for DummyBitcoind {
         true
     }
 
+    fn block_in_chain(&self, hash: &bitcoin::BlockHash) -> Option<Block> {
+        let genesis = self.genesis_block();
+        (*hash == genesis.hash).then(|| Block {
+            hash: genesis.hash,
+            height: genesis.height,
+            time: self.genesis_block_timestamp(),
+        })
+    }
+
+    fn block_at_height(&self, height: i32) -> Option<Block> {
+        let genesis = self.genesis_block();
+        (height == genesis.height).then(|| Block {
+            hash: genesis.hash,
+            height: genesis.height,
+            time: self.genesis_block_timestamp(),
+        })
+    }
+
     fn received_coins(
         &self,
         _: &BlockChainTip,
@@ -93,28 +128,51 @@ impl BitcoinInterface for DummyBitcoind {
         (Vec::new(), Vec::new())
     }
 
+    fn double_spent_coins(&self, _: &[bitcoin::OutPoint]) -> Vec<bitcoin::OutPoint> {
+        Vec::new()
+    }
+
     fn common_ancestor(&self, _: &BlockChainTip) -> Option<BlockChainTip> {
         todo!()
     }
 
     fn broadcast_tx(&self, _: &bitcoin::Transaction) -> Result<(), String> {
-        todo!()
+        Ok(())
+    }
+
+    fn test_mempool_accept(
+        &self,
+        tx: &bitcoin::Transaction,
+    ) -> Result<MempoolAcceptResult, String> {
+        Ok(MempoolAcceptResult {
+            allowed: true,
+            reject_reason: None,
+            vsize: Some(tx.vsize() as u64),
+            fee: None,
+        })
     }
 
     fn start_rescan(&self, _: &descriptors::LianaDescriptor, _: u32) -> Result<(), String> {
         todo!()
     }
 
+    fn rescan_range(&self, _: i32, _: i32) -> Result<(), String> {
+        todo!()
+    }
+
     fn rescan_progress(&self) -> Option<f64> {
         None
     }
 
-    fn block_before_date(&self, _: u32) -> Option<BlockChainTip> {
-        todo!()
+    fn block_before_date(&self, timestamp: u32) -> Option<BlockChainTip> {
+        if timestamp < self.genesis_block_timestamp() {
+            return None;
+        }
+        Some(self.genesis_block())
     }
 
     fn tip_time(&self) -> Option<u32> {
-        todo!()
+        self.tip_time
     }
 
     fn wallet_transaction(
@@ -131,6 +189,27 @@ impl BitcoinInterface for DummyBitcoind {
     fn mempool_entry(&self, _: &bitcoin::Txid) -> Option<MempoolEntry> {
         None
     }
+
+    fn backend_info(&self) -> BackendInfo {
+        BackendInfo {
+            kind: BackendKind::Bitcoind,
+            version: "/Satoshi:26.0.0/".to_string(),
+            network: bitcoin::Network::Bitcoin,
+            chain_tip_hash: self.chain_tip().hash,
+        }
+    }
+
+    fn feerate_estimate(&self, _: u16) -> Option<u64> {
+        self.feerate
+    }
+
+    fn reconnect(&mut self) -> Result<(), String> {
+        if self.reachable {
+            Ok(())
+        } else {
+            Err("Connection refused".to_string())
+        }
+    }
 }
 
 struct DummyDbState {
@@ -139,6 +218,13 @@ struct DummyDbState {
     curr_tip: Option<BlockChainTip>,
     coins: HashMap<bitcoin::OutPoint, Coin>,
     spend_txs: HashMap<bitcoin::Txid, (Psbt, Option<u32>)>,
+    labels: Vec<(LabelItem, String)>,
+    replacements: HashMap<bitcoin::Txid, bitcoin::Txid>,
+    replacement_drafts: HashSet<(bitcoin::Txid, bitcoin::Txid)>,
+    dropped_coins: Vec<DroppedCoin>,
+    coin_notes: HashMap<bitcoin::OutPoint, String>,
+    feerate_samples: Vec<FeerateSample>,
+    addresses: HashMap<bitcoin::Address, (bip32::ChildNumber, bool)>,
     timestamp: u32,
 }
 
@@ -170,6 +256,13 @@ impl DummyDatabase {
                 curr_tip: None,
                 coins: HashMap::new(),
                 spend_txs: HashMap::new(),
+                labels: Vec::new(),
+                replacements: HashMap::new(),
+                replacement_drafts: HashSet::new(),
+                dropped_coins: Vec::new(),
+                coin_notes: HashMap::new(),
+                feerate_samples: Vec::new(),
+                addresses: HashMap::new(),
                 timestamp: now,
             })),
         }
@@ -180,6 +273,20 @@ impl DummyDatabase {
             self.db.write().unwrap().coins.insert(coin.outpoint, coin);
         }
     }
+
+    /// Record an address of the wallet, to be found by `derivation_index_by_address`.
+    pub fn insert_address(
+        &mut self,
+        addr: bitcoin::Address,
+        index: bip32::ChildNumber,
+        is_change: bool,
+    ) {
+        self.db
+            .write()
+            .unwrap()
+            .addresses
+            .insert(addr, (index, is_change));
+    }
 }
 
 impl DatabaseConnection for DummyDatabase {
@@ -265,7 +372,7 @@ impl DatabaseConnection for DummyDatabase {
     fn list_spending_coins(&mut self) -> HashMap<bitcoin::OutPoint, Coin> {
         let mut result = HashMap::new();
         for (k, v) in self.db.read().unwrap().coins.iter() {
-            if v.spend_txid.is_some() {
+            if v.spend_txid.is_some() && v.spend_block.is_none() {
                 result.insert(*k, *v);
             }
         }
@@ -330,11 +437,22 @@ impl DatabaseConnection for DummyDatabase {
         }
     }
 
+    fn prune_spent_coins(&mut self, max_spend_height: i32) -> usize {
+        let coins = &mut self.db.write().unwrap().coins;
+        let prev_count = coins.len();
+        coins.retain(|_, coin| {
+            coin.spend_block
+                .map(|block| block.height > max_spend_height)
+                .unwrap_or(true)
+        });
+        prev_count - coins.len()
+    }
+
     fn derivation_index_by_address(
         &mut self,
-        _: &bitcoin::Address,
+        addr: &bitcoin::Address,
     ) -> Option<(bip32::ChildNumber, bool)> {
-        None
+        self.db.read().unwrap().addresses.get(addr).copied()
     }
 
     fn coins_by_outpoints(
@@ -382,7 +500,10 @@ impl DatabaseConnection for DummyDatabase {
     }
 
     fn delete_spend(&mut self, txid: &bitcoin::Txid) {
-        self.db.write().unwrap().spend_txs.remove(txid);
+        let mut db = self.db.write().unwrap();
+        db.spend_txs.remove(txid);
+        db.replacement_drafts
+            .retain(|(tx, replacement)| tx != txid && replacement != txid);
     }
 
     fn rollback_tip(&mut self, _: &BlockChainTip) {
@@ -401,15 +522,63 @@ impl DatabaseConnection for DummyDatabase {
         todo!()
     }
 
-    fn update_labels(&mut self, _items: &HashMap<LabelItem, Option<String>>) {
-        todo!()
+    fn update_labels(&mut self, items: &HashMap<LabelItem, Option<String>>) {
+        let labels = &mut self.db.write().unwrap().labels;
+        for (item, value) in items {
+            if let Some(value) = value {
+                if let Some(label) = labels.iter_mut().find(|(i, _)| i == item) {
+                    label.1 = value.clone();
+                } else {
+                    labels.push((item.clone(), value.clone()));
+                }
+            } else {
+                labels.retain(|(i, _)| i != item);
+            }
+        }
     }
 
-    fn labels(&mut self, _items: &HashSet<LabelItem>) -> HashMap<String, String> {
-        todo!()
+    fn labels(&mut self, items: &HashSet<LabelItem>) -> HashMap<String, String> {
+        self.db
+            .read()
+            .unwrap()
+            .labels
+            .iter()
+            .filter(|(item, _)| items.contains(item))
+            .map(|(item, value)| (item.to_string(), value.clone()))
+            .collect()
+    }
+
+    fn labels_page(&mut self, offset: u64, limit: u64) -> Vec<(LabelItem, String)> {
+        self.db
+            .read()
+            .unwrap()
+            .labels
+            .iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect()
+    }
+
+    fn replace_labels(&mut self, labels: &HashMap<LabelItem, String>) -> usize {
+        let new_labels = labels
+            .iter()
+            .map(|(item, value)| (item.clone(), value.clone()))
+            .collect();
+        std::mem::replace(&mut self.db.write().unwrap().labels, new_labels).len()
     }
 
     fn list_txids(&mut self, start: u32, end: u32, limit: u64) -> Vec<bitcoin::Txid> {
+        self.list_txids_page(start, end, 0, limit)
+    }
+
+    fn list_txids_page(
+        &mut self,
+        start: u32,
+        end: u32,
+        offset: u64,
+        limit: u64,
+    ) -> Vec<bitcoin::Txid> {
         let mut txids_and_time = Vec::new();
         let coins = &self.db.read().unwrap().coins;
         // Get txid and block time of every transactions that happened between start and end
@@ -432,10 +601,169 @@ impl DatabaseConnection for DummyDatabase {
                 }
             }
         }
-        // Apply order and limit
-        txids_and_time.sort_by(|(_, t1), (_, t2)| t2.cmp(t1));
-        txids_and_time.truncate(limit as usize);
-        txids_and_time.into_iter().map(|(txid, _)| txid).collect()
+        // Apply order, offset and limit
+        txids_and_time.sort_by(|(txid1, t1), (txid2, t2)| t2.cmp(t1).then(txid1.cmp(txid2)));
+        txids_and_time
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .map(|(txid, _)| txid)
+            .collect()
+    }
+
+    fn record_replacements(&mut self, txids: &[bitcoin::Txid], replaced_by: &bitcoin::Txid) {
+        let replacements = &mut self.db.write().unwrap().replacements;
+        for txid in txids {
+            replacements.insert(*txid, *replaced_by);
+        }
+    }
+
+    fn replaced_by(&mut self, txids: &[bitcoin::Txid]) -> HashMap<bitcoin::Txid, bitcoin::Txid> {
+        let replacements = &self.db.read().unwrap().replacements;
+        txids
+            .iter()
+            .filter_map(|txid| replacements.get(txid).map(|r| (*txid, *r)))
+            .collect()
+    }
+
+    fn record_replacement_draft(&mut self, txid: &bitcoin::Txid, replacement_txid: &bitcoin::Txid) {
+        self.db
+            .write()
+            .unwrap()
+            .replacement_drafts
+            .insert((*txid, *replacement_txid));
+    }
+
+    fn replacement_drafts(&mut self, txid: &bitcoin::Txid) -> Vec<bitcoin::Txid> {
+        self.db
+            .read()
+            .unwrap()
+            .replacement_drafts
+            .iter()
+            .filter(|(tx, _)| tx == txid)
+            .map(|(_, replacement)| *replacement)
+            .collect()
+    }
+
+    fn record_dropped_coins(&mut self, coins: &[Coin], height: i32) {
+        let dropped_coins = &mut self.db.write().unwrap().dropped_coins;
+        for coin in coins {
+            if let Some(dropped) = dropped_coins
+                .iter_mut()
+                .find(|c| c.outpoint == coin.outpoint)
+            {
+                dropped.invalidated_at = None;
+                dropped.dropped_height = height;
+            } else {
+                dropped_coins.push(DroppedCoin {
+                    outpoint: coin.outpoint,
+                    amount: coin.amount,
+                    derivation_index: coin.derivation_index,
+                    is_change: coin.is_change,
+                    dropped_height: height,
+                    invalidated_at: None,
+                });
+            }
+        }
+    }
+
+    fn forget_dropped_coins(&mut self, outpoints: &[bitcoin::OutPoint]) {
+        self.db
+            .write()
+            .unwrap()
+            .dropped_coins
+            .retain(|c| !outpoints.contains(&c.outpoint));
+    }
+
+    fn invalidate_coins(&mut self, outpoints: &[bitcoin::OutPoint]) {
+        let now: u32 = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            .try_into()
+            .unwrap();
+        let dropped_coins = &mut self.db.write().unwrap().dropped_coins;
+        for coin in dropped_coins.iter_mut() {
+            if outpoints.contains(&coin.outpoint) && coin.invalidated_at.is_none() {
+                coin.invalidated_at = Some(now);
+            }
+        }
+    }
+
+    fn dropped_coins(&mut self) -> Vec<DroppedCoin> {
+        self.db.read().unwrap().dropped_coins.clone()
+    }
+
+    fn stored_addresses(&mut self) -> Vec<StoredAddresses> {
+        Vec::new()
+    }
+
+    fn set_coin_note(&mut self, outpoint: &bitcoin::OutPoint, note: Option<&str>) {
+        let coin_notes = &mut self.db.write().unwrap().coin_notes;
+        if let Some(note) = note {
+            coin_notes.insert(*outpoint, note.to_string());
+        } else {
+            coin_notes.remove(outpoint);
+        }
+    }
+
+    fn coin_notes(
+        &mut self,
+        outpoints: &[bitcoin::OutPoint],
+    ) -> HashMap<bitcoin::OutPoint, String> {
+        self.db
+            .read()
+            .unwrap()
+            .coin_notes
+            .iter()
+            .filter(|(outpoint, _)| outpoints.is_empty() || outpoints.contains(outpoint))
+            .map(|(outpoint, note)| (*outpoint, note.clone()))
+            .collect()
+    }
+
+    fn store_feerate_samples(&mut self, samples: &[FeerateSample]) {
+        self.db
+            .write()
+            .unwrap()
+            .feerate_samples
+            .extend_from_slice(samples);
+    }
+
+    fn feerate_samples(&mut self, since: u32) -> Vec<FeerateSample> {
+        let mut samples: Vec<_> = self
+            .db
+            .read()
+            .unwrap()
+            .feerate_samples
+            .iter()
+            .filter(|s| s.timestamp >= since)
+            .copied()
+            .collect();
+        samples.sort_by_key(|s| (s.timestamp, s.conf_target));
+        samples
+    }
+
+    fn export_encrypted(&mut self, _: &path::Path, _: &DbEncryptionKey) -> Result<(), String> {
+        Err("The dummy database can't be encrypted".to_string())
+    }
+
+    fn stats(&mut self) -> DatabaseStats {
+        let db = self.db.read().unwrap();
+        DatabaseStats {
+            table_rows: [
+                ("coins".to_string(), db.coins.len() as u64),
+                ("spend_transactions".to_string(), db.spend_txs.len() as u64),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+            size: 0,
+            free_size: 0,
+        }
+    }
+
+    fn vacuum(&mut self) -> Result<(), String> {
+        Ok(())
     }
 }
 
@@ -495,7 +823,20 @@ impl DummyLiana {
             #[cfg(unix)]
             daemon: false,
             log_level: log::LevelFilter::Debug,
+            log_file: None,
             main_descriptor: desc,
+            fee_sources: vec![FeeSource::Backend],
+            db_encryption_key: None,
+            spent_coin_retention: SpentCoinRetention::KeepAll,
+            feerate_cache_secs: time::Duration::from_secs(60),
+            long_term_feerate_vb: None,
+            anti_fee_sniping: true,
+            max_spend_inputs: None,
+            derivation_start_index: 0,
+            session_passphrase_hash: None,
+            lock_read_commands: false,
+            spendable_min_confirmations: 0,
+            observer_socket: false,
         };
 
         let handle = DaemonHandle::start(
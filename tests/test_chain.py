@@ -311,6 +311,15 @@ def test_deposit_replacement(lianad, bitcoind):
 
     # We must forget about the deposit.
     wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == 0)
+    assert len(lianad.rpc.listinvalidatedcoins()["coins"]) == 0
+
+    # Once the conflicting transaction confirms, the coin is reported as invalidated.
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listinvalidatedcoins()["coins"]) == 1)
+    invalidated_coin = lianad.rpc.listinvalidatedcoins()["coins"][0]
+    assert invalidated_coin["outpoint"].startswith(deposit_tx["txid"])
+    assert invalidated_coin["address"] == addr
+    assert invalidated_coin["amount"] == COIN
 
     # Send a new one, it'll be detected.
     addr = lianad.rpc.getnewaddress()["address"]
@@ -355,6 +364,46 @@ def test_rescan_and_recovery(lianad, bitcoind):
     sign_and_broadcast(lianad, bitcoind, reco_psbt, recovery=True)
 
 
+def test_rescan_range(lianad, bitcoind):
+    """Test we can retrieve a coin by rescanning only the blocks around its confirmation."""
+    initial_tip = bitcoind.rpc.getblockheader(bitcoind.rpc.getbestblockhash())
+
+    # Get a coin confirmed in a known block.
+    destination = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(destination, 0.5)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    deposit_height = bitcoind.rpc.getblockcount()
+    wait_for(lambda: lianad.rpc.getinfo()["block_height"] == deposit_height)
+    assert len(lianad.rpc.listcoins()["coins"]) == 1
+
+    # Advance the blocktime by >2h in median-time past so the coin isn't found at import.
+    bitcoind.rpc.setmocktime(initial_tip["time"] + 60 * 60 * 3)
+    bitcoind.generate_block(12)
+    lianad.restart_fresh(bitcoind)
+    assert len(lianad.rpc.listcoins()["coins"]) == 0
+
+    # The range must be sane.
+    tip_height = bitcoind.rpc.getblockcount()
+    with pytest.raises(RpcError, match="Insane rescan range.*"):
+        lianad.rpc.rescanrange(deposit_height, deposit_height - 1)
+    with pytest.raises(RpcError, match="Insane rescan range.*"):
+        lianad.rpc.rescanrange(deposit_height, tip_height + 1)
+    assert lianad.rpc.getinfo()["rescan_progress"] is None
+
+    # Rescanning a range which doesn't contain the deposit won't find it.
+    lianad.rpc.rescanrange(deposit_height + 1, tip_height)
+    wait_for(lambda: lianad.rpc.getinfo()["rescan_progress"] is None)
+    assert len(lianad.rpc.listcoins()["coins"]) == 0
+
+    # Rescanning only the block of the deposit finds it.
+    lianad.rpc.rescanrange(deposit_height, deposit_height)
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == 1)
+    wait_for(lambda: lianad.rpc.getinfo()["rescan_progress"] is None)
+    coin = lianad.rpc.listcoins()["coins"][0]
+    assert coin["outpoint"].startswith(txid)
+    assert coin["block_height"] == deposit_height
+
+
 @pytest.mark.skipif(
     USE_TAPROOT, reason="Needs a finalizer implemented in the Python test framework."
 )
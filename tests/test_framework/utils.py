@@ -30,6 +30,9 @@ USE_TAPROOT = bool(
 
 
 COIN = 10**8
+# An output seed for 'createspend' which places the change output last, for transactions with up
+# to 3 other outputs.
+CHANGE_LAST_SEED = "08" * 32
 
 
 def wait_for(success, timeout=TIMEOUT, debug_fn=None):
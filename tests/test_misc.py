@@ -1,4 +1,6 @@
+import hashlib
 import logging
+import os
 import pytest
 import shutil
 import time
@@ -9,6 +11,7 @@ from test_framework.serializations import PSBT
 from test_framework.utils import (
     wait_for,
     RpcError,
+    UnixDomainSocketRpc,
     OLD_LIANAD_PATH,
     LIANAD_PATH,
     COIN,
@@ -78,6 +81,31 @@ def receive_and_send(lianad, bitcoind):
     bitcoind.generate_block(1, wait_for_mempool=txid)
 
 
+def test_multisig_global_xpubs(lianad_multisig, bitcoind):
+    """The PSBTs we create contain the descriptor's xpubs in their global xpub field."""
+    addr = lianad_multisig.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad_multisig.rpc.listcoins()["coins"]) == 1)
+
+    destinations = {bitcoind.rpc.getnewaddress(): 200_000}
+    res = lianad_multisig.rpc.createspend(destinations, [], 2)
+    psbt = PSBT.from_base64(res["psbt"])
+    PSBT_GLOBAL_XPUB = 0x01
+    global_xpubs = psbt.g.map[PSBT_GLOBAL_XPUB]
+
+    # All the keys from the primary and recovery paths are present, with their origin. The
+    # serialized xpub ends with the public key and the origin starts with the fingerprint.
+    signer = lianad_multisig.signer
+    hds = signer.prim_hds + signer.recov_hds[10]
+    expected = {hd.pubkey: bytes.fromhex(xpub_fingerprint(hd)) for hd in hds}
+    found = {xpub[-33:]: origin[:4] for xpub, origin in global_xpubs.items()}
+    for pubkey, fingerprint in expected.items():
+        assert found[pubkey] == fingerprint
+    # Under Taproot the internal key is also part of the descriptor.
+    assert len(found) == len(expected) + (1 if USE_TAPROOT else 0)
+
+
 def test_multisig(lianad_multisig, bitcoind):
     """Test using lianad with a descriptor that contains multiple keys for both
     the primary and recovery paths."""
@@ -300,6 +328,66 @@ def test_bitcoind_submit_block(bitcoind):
     wait_for(lambda: bitcoind.rpc.getblockcount() == block_count + 1)
 
 
+def test_reconnect_backend(lianad, bitcoind):
+    """Reconnecting to bitcoind updates our state right away."""
+    lianad.rpc.reconnectbackend()
+    bitcoind.generate_block(1)
+    lianad.rpc.reconnectbackend()
+    assert lianad.rpc.getinfo()["block_height"] == bitcoind.rpc.getblockcount()
+
+
+def test_observer_socket(lianad, bitcoind):
+    """The observer socket only accepts commands which don't modify the wallet."""
+    lianad.stop()
+    with open(lianad.conf_file, "r") as f:
+        conf = f.read()
+    with open(lianad.conf_file, "w") as f:
+        f.write("observer_socket = true\n" + conf)
+    lianad.start()
+
+    socket_path = os.path.join(lianad.datadir, "regtest", "lianad_rpc_observer")
+    observer = UnixDomainSocketRpc(socket_path)
+    info = lianad.rpc.getinfo()
+    assert observer.getinfo()["descriptors"] == info["descriptors"]
+    assert observer.listcoins() == lianad.rpc.listcoins()
+    assert observer.getlabels([]) == {"labels": {}}
+    for method in ("getnewaddress", "stop"):
+        with pytest.raises(RpcError, match="not available on a read-only connection"):
+            observer.call(method)
+    # The daemon wasn't stopped, and the regular socket can still be used to modify the wallet.
+    lianad.rpc.getnewaddress()
+
+
+def test_session_lock(lianad, bitcoind):
+    """A session passphrase gates the commands which modify the wallet."""
+    passphrase = "correct horse battery staple"
+    lianad.stop()
+    with open(lianad.conf_file, "r") as f:
+        conf = f.read()
+    with open(lianad.conf_file, "w") as f:
+        salt = os.urandom(16)
+        digest = hashlib.pbkdf2_hmac("sha256", passphrase.encode(), salt, 100_000)
+        passphrase_hash = f"pbkdf2-sha256$100000${salt.hex()}${digest.hex()}"
+        f.write(f"session_passphrase_hash = '{passphrase_hash}'\n" + conf)
+    lianad.start()
+
+    # The daemon starts locked. Read-only commands are still available.
+    lianad.rpc.getinfo()
+    with pytest.raises(RpcError, match="The wallet is locked"):
+        lianad.rpc.getnewaddress()
+    with pytest.raises(RpcError, match="Wrong session passphrase"):
+        lianad.rpc.unlock("wrong")
+    with pytest.raises(RpcError, match="The wallet is locked"):
+        lianad.rpc.getnewaddress()
+
+    # Once unlocked, all commands are available until it is locked again.
+    lianad.rpc.unlock(passphrase)
+    lianad.rpc.getnewaddress()
+    lianad.rpc.lock()
+    with pytest.raises(RpcError, match="The wallet is locked"):
+        lianad.rpc.getnewaddress()
+
+
 def bitcoind_wait_new_block(bitcoind):
     """Call 'waitfornewblock', retry on 503."""
     while True:
@@ -1,9 +1,12 @@
+import base64
 import pytest
 import random
 import re
+import sqlite3
 import time
 
 from fixtures import *
+from test_framework.bitcoind import BitcoindRpcInterface
 from test_framework.serializations import (
     PSBT,
     PSBT_IN_PARTIAL_SIG,
@@ -18,6 +21,7 @@ from test_framework.utils import (
     sign_and_broadcast,
     sign_and_broadcast_psbt,
     USE_TAPROOT,
+    CHANGE_LAST_SEED,
 )
 
 
@@ -30,7 +34,194 @@ def test_getinfo(lianad):
     res = lianad.rpc.getinfo()
     assert res["sync"] == 1.0
     assert "main" in res["descriptors"]
+    assert len(res["wallet_id"]) == 64
     assert res["rescan_progress"] is None
+    assert res["backend"]["kind"] == "bitcoind"
+    assert res["backend"]["network"] == "regtest"
+    assert res["backend"]["version"].startswith("/Satoshi:")
+    assert len(res["backend"]["chain_tip_hash"]) == 64
+    assert res["max_spendable"] == 0
+    assert res["recovery_available"] is False
+    assert res["blocks_until_next_recovery"] is None
+
+
+def test_setloglevel(lianad):
+    with pytest.raises(RpcError, match="Invalid 'level' parameter"):
+        lianad.rpc.setloglevel("verbose")
+    lianad.rpc.setloglevel("info")
+    lianad.wait_for_log("Log level set to 'INFO'")
+    # Once set to warn, the info message about the level change itself isn't logged anymore.
+    lianad.rpc.setloglevel("warn")
+    lianad.rpc.setloglevel("debug")
+    lianad.wait_for_log("Log level set to 'DEBUG'")
+    assert not lianad.is_in_log("Log level set to 'WARN'")
+
+
+def test_getdashboard(lianad, bitcoind):
+    res = lianad.rpc.getdashboard()
+    assert res["balance"] == res["unconfirmed_balance"] == 0
+    assert res["coin_count"] == res["pending_count"] == 0
+    assert res["next_recovery_height"] is None
+
+    # Receive a coin, it's first pending then confirmed.
+    addr = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.01)
+    wait_for(lambda: lianad.rpc.getdashboard()["coin_count"] == 1)
+    res = lianad.rpc.getdashboard()
+    assert res["balance"] == 0
+    assert res["unconfirmed_balance"] == 1_000_000
+    assert res["pending_count"] == 1
+    # An unconfirmed deposit isn't spendable by default.
+    assert lianad.rpc.getinfo()["max_spendable"] == 0
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: lianad.rpc.getdashboard()["pending_count"] == 0)
+    res = lianad.rpc.getdashboard()
+    assert res["balance"] == 1_000_000
+    assert res["unconfirmed_balance"] == 0
+    block_height = lianad.rpc.listcoins()["coins"][0]["block_height"]
+    assert res["next_recovery_height"] == block_height + 10
+    # Once confirmed, all of it but the fee can be spent.
+    assert 990_000 < lianad.rpc.getinfo()["max_spendable"] < 1_000_000
+    # The recovery path becomes available in the block at the next recovery height.
+    res = lianad.rpc.getinfo()
+    assert res["recovery_available"] is False
+    assert res["blocks_until_next_recovery"] == block_height + 10 - (res["block_height"] + 1)
+    bitcoind.generate_block(res["blocks_until_next_recovery"])
+    wait_for(
+        lambda: lianad.rpc.getinfo()["block_height"] == bitcoind.rpc.getblockcount()
+    )
+    res = lianad.rpc.getinfo()
+    assert res["recovery_available"] is True
+    assert res["blocks_until_next_recovery"] == 0
+
+
+def test_getbalance(lianad, bitcoind):
+    assert lianad.rpc.getbalance() == {
+        "min_confirmations": 1,
+        "confirmed": 0,
+        "unconfirmed": 0,
+        "immature": 0,
+        "reserved": 0,
+    }
+
+    # Receive two coins, confirm one of them.
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.02)
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == 2)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    res = lianad.rpc.getbalance()
+    assert res["confirmed"] == 1_000_000
+    assert res["unconfirmed"] == 2_000_000
+    res = lianad.rpc.getbalance(0)
+    assert res["confirmed"] == 3_000_000
+    assert res["unconfirmed"] == 0
+    res = lianad.rpc.getbalance(min_confirmations=2)
+    assert res["confirmed"] == 0
+    assert res["unconfirmed"] == 3_000_000
+
+    # The coins reserved by a Spend draft are accounted for separately.
+    outpoint = lianad.rpc.listcoins(["confirmed"])["coins"][0]["outpoint"]
+    lianad.rpc.createspend({bitcoind.rpc.getnewaddress(): 100_000}, [outpoint], 2)
+    res = lianad.rpc.getbalance()
+    assert res["confirmed"] == 0
+    assert res["reserved"] == 1_000_000
+
+
+def test_getsnapshot(lianad, bitcoind):
+    # By default all the parts are returned.
+    res = lianad.rpc.getsnapshot()
+    for part in ("info", "coins", "feerate_estimates", "spend_txs"):
+        assert part in res
+    assert res["coins"]["coins"] == []
+
+    # Receive a coin, it's reported consistently across the parts.
+    addr = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.getsnapshot(["coins"])["coins"]["coins"]) == 1)
+    res = lianad.rpc.getsnapshot()
+    assert res["coins"]["coins"][0]["block_height"] <= res["info"]["block_height"]
+
+    # Only the requested parts are returned.
+    res = lianad.rpc.getsnapshot(["coins"])
+    assert list(res.keys()) == ["coins"]
+
+    with pytest.raises(RpcError, match="Invalid value"):
+        lianad.rpc.getsnapshot(["balance"])
+
+
+def test_diffsnapshots(lianad, bitcoind):
+    """The changes to our coins between two snapshots are reported."""
+    addr = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    snap_a = lianad.rpc.getsnapshot()
+    diff = lianad.rpc.diffsnapshots(snap_a, snap_a)
+    assert diff == {"added": [], "spent": [], "reorged": []}
+
+    # Spend the coin, and receive a new one.
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    destinations = {bitcoind.rpc.getnewaddress(): 100_000}
+    res = lianad.rpc.createspend(destinations, outpoints, 2)
+    spend_txid = sign_and_broadcast_psbt(lianad, PSBT.from_base64(res["psbt"]))
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == 2)
+    snap_b = lianad.rpc.getsnapshot()
+    diff = lianad.rpc.diffsnapshots(snap_a, snap_b)
+    assert [c["outpoint"] for c in diff["spent"]] == outpoints
+    assert diff["spent"][0]["spend_info"]["txid"] == spend_txid
+    assert [c["outpoint"].split(":")[0] for c in diff["added"]] == [spend_txid]
+    assert diff["reorged"] == []
+
+    with pytest.raises(RpcError, match="must include the coins"):
+        lianad.rpc.diffsnapshots(snap_a, lianad.rpc.getsnapshot(["info"]))
+
+
+def test_getfeerateestimates(lianad):
+    res = lianad.rpc.getfeerateestimates()
+    assert [est["conf_target"] for est in res["estimates"]] == [2, 6, 24]
+    for est in res["estimates"]:
+        # On a fresh regtest chain bitcoind does not have enough data to estimate fees.
+        assert est["source"] in ("backend", "fallback")
+        assert est["feerate"] >= 1
+
+    # Estimates aren't cached by default, so they are always fresh.
+    assert res["age_secs"] == 0
+    assert lianad.rpc.invalidatefeeratecache() == {}
+    assert lianad.rpc.getfeerateestimates()["age_secs"] == 0
+
+
+def test_getfeeratehistory(lianad):
+    # On a fresh regtest chain bitcoind can't estimate fees, so there may be no sample at all.
+    samples = lianad.rpc.getfeeratehistory()["samples"]
+    for sample in samples:
+        assert sample["conf_target"] in (2, 6, 24)
+        assert sample["feerate"] >= 1
+    assert [s["timestamp"] for s in samples] == sorted(s["timestamp"] for s in samples)
+    assert lianad.rpc.getfeeratehistory(int(time.time()) + 3600)["samples"] == []
+
+    with pytest.raises(RpcError, match="Invalid 'since' parameter."):
+        lianad.rpc.getfeeratehistory(-1)
+
+
+def test_createspend_fee_priority(lianad, bitcoind):
+    """A Spend may be created for a confirmation target instead of a feerate."""
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    destinations = {bitcoind.rpc.getnewaddress(): 100_000}
+
+    estimates = lianad.rpc.getfeerateestimates()["estimates"]
+    for priority, est in zip(["fast", "normal", "slow"], estimates):
+        res = lianad.rpc.createspend(destinations, outpoints, priority)
+        assert res["feerate"] == est["feerate"]
+    res = lianad.rpc.createspend(destinations, outpoints, 2.5)
+    assert res["feerate"] == 2.5
+
+    with pytest.raises(RpcError, match="priority must be one of"):
+        lianad.rpc.createspend(destinations, outpoints, "asap")
 
 
 def test_getaddress(lianad):
@@ -42,6 +233,35 @@ def test_getaddress(lianad):
     assert lianad.rpc.getnewaddress()["derivation_index"] == res["derivation_index"] + 2
 
 
+def test_getlastaddressstatus(lianad, bitcoind):
+    assert lianad.rpc.getlastaddressstatus() == {
+        "address": None,
+        "derivation_index": None,
+        "used": False,
+    }
+    addr = lianad.rpc.getnewaddress()
+    assert lianad.rpc.getlastaddressstatus() == {**addr, "used": False}
+
+    # Once it receives a coin it's reported as used, until a new address is generated.
+    bitcoind.rpc.sendtoaddress(addr["address"], 0.01)
+    wait_for(lambda: lianad.rpc.getlastaddressstatus()["used"])
+    new_addr = lianad.rpc.getnewaddress()
+    assert lianad.rpc.getlastaddressstatus() == {**new_addr, "used": False}
+
+
+def test_getunusedaddress(lianad, bitcoind):
+    addr = lianad.rpc.getunusedaddress()
+    assert addr["derivation_index"] == 0
+    assert lianad.rpc.getunusedaddress() == addr
+
+    # Once it receives a coin a new address is handed out.
+    bitcoind.rpc.sendtoaddress(addr["address"], 0.01)
+    wait_for(lambda: lianad.rpc.getlastaddressstatus()["used"])
+    new_addr = lianad.rpc.getunusedaddress()
+    assert new_addr["derivation_index"] == 1
+    assert lianad.rpc.getunusedaddress() == new_addr
+
+
 def test_listaddresses(lianad):
     list = lianad.rpc.listaddresses(2, 5)
     list2 = lianad.rpc.listaddresses(start_index=2, count=5)
@@ -76,6 +296,51 @@ def test_listaddresses(lianad):
         lianad.rpc.listaddresses(0, "blb")
 
 
+def test_classifyaddresses(lianad, bitcoind):
+    receive = lianad.rpc.getnewaddress()
+    change = lianad.rpc.listaddresses(3, 1)["addresses"][0]["change"]
+    external = bitcoind.rpc.getnewaddress()
+    res = lianad.rpc.classifyaddresses([receive["address"], change, external])
+    assert res["addresses"] == {
+        receive["address"]: {
+            "kind": "receive",
+            "derivation_index": receive["derivation_index"],
+        },
+        change: {"kind": "change", "derivation_index": 3},
+        external: {"kind": "external"},
+    }
+
+    with pytest.raises(RpcError, match="Invalid 'addresses' parameter"):
+        lianad.rpc.classifyaddresses(["not an address"])
+
+
+def test_verifyaddresses(lianad):
+    lianad.rpc.getnewaddress()
+    lianad.rpc.getnewaddress()
+    # The addresses are stored up to the look-ahead limit past the next derivation index.
+    res = lianad.rpc.verifyaddresses()
+    assert res == {"checked": 202, "missing": [], "mismatches": []}
+
+    # Corrupt the addresses cache: remove an index in use and store a wrong address for another.
+    lianad.stop()
+    db_path = os.path.join(lianad.datadir, "regtest", "lianad.sqlite3")
+    with sqlite3.connect(db_path) as conn:
+        conn.execute("DELETE FROM addresses WHERE derivation_index = 1")
+        conn.execute(
+            "UPDATE addresses SET receive_address = change_address WHERE derivation_index = 5"
+        )
+    conn.close()
+    lianad.start()
+    res = lianad.rpc.verifyaddresses()
+    assert res["checked"] == 201
+    assert res["missing"] == [1]
+    assert len(res["mismatches"]) == 1
+    mismatch = res["mismatches"][0]
+    assert mismatch["derivation_index"] == 5 and mismatch["is_change"] is False
+    assert mismatch["stored"] == lianad.rpc.listaddresses(5, 1)["addresses"][0]["change"]
+    assert mismatch["expected"] == lianad.rpc.listaddresses(5, 1)["addresses"][0]["receive"]
+
+
 def test_listcoins(lianad, bitcoind):
     # Initially empty
     res = lianad.rpc.listcoins()
@@ -95,6 +360,29 @@ def test_listcoins(lianad, bitcoind):
     assert res[0]["is_change"] == False
     assert res[0]["block_height"] is None
     assert res[0]["spend_info"] is None
+    assert res[0]["spendable_paths"] == [0]
+
+    # We can get the address of the coin from its outpoint.
+    assert lianad.rpc.getcoinaddress(outpoint_a) == {
+        "address": addr_a["address"],
+        "derivation_index": addr_a["derivation_index"],
+        "is_change": False,
+    }
+    with pytest.raises(RpcError, match="Unknown outpoint"):
+        lianad.rpc.getcoinaddress(f"{txid_a}:42")
+
+    # We can set a multiline note on the coin, separately from its label.
+    assert res[0]["note"] is None
+    note = "Withdrawal from the exchange.\nKeep for the house down payment."
+    lianad.rpc.setcoinnote(outpoint_a, note)
+    assert lianad.rpc.listcoins([], [outpoint_a])["coins"][0]["note"] == note
+    assert outpoint_a not in lianad.rpc.getlabels([outpoint_a])["labels"]
+    with pytest.raises(RpcError, match="is longer than 2000 characters"):
+        lianad.rpc.setcoinnote(outpoint_a, "a" * 2001)
+    with pytest.raises(RpcError, match="Unknown outpoint"):
+        lianad.rpc.setcoinnote(f"{txid_a}:42", note)
+    lianad.rpc.setcoinnote(outpoint_a, None)
+    assert lianad.rpc.listcoins([], [outpoint_a])["coins"][0]["note"] is None
 
     assert len(lianad.rpc.listcoins(["confirmed", "spent", "spending"])["coins"]) == 0
     assert (
@@ -133,6 +421,7 @@ def test_listcoins(lianad, bitcoind):
     spend_info = lianad.rpc.listcoins()["coins"][0]["spend_info"]
     assert spend_info["txid"] == spend_txid
     assert spend_info["height"] is None
+    assert lianad.rpc.listcoins()["coins"][0]["spendable_paths"] == []
     assert len(lianad.rpc.listcoins(["spent"])["coins"]) == 0
     assert len(lianad.rpc.listcoins(["spending"])["coins"]) == 1
 
@@ -359,6 +648,135 @@ def test_listcoins(lianad, bitcoind):
             lianad.rpc.listcoins(statuses, outpoints)
 
 
+def test_listcoinsatheight(lianad, bitcoind):
+    # Receive a coin and get it confirmed.
+    addr = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 1)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    height = bitcoind.rpc.getblockcount()
+    wait_for(lambda: lianad.rpc.getinfo()["block_height"] == height)
+    outpoint = lianad.rpc.listcoins()["coins"][0]["outpoint"]
+
+    # It wasn't there before its confirmation height.
+    assert len(lianad.rpc.listcoinsatheight(height - 1)["coins"]) == 0
+    assert lianad.rpc.listcoinsatheight(height)["coins"][0]["outpoint"] == outpoint
+
+    # Spend it. It is still listed as of the heights before the spend confirmed.
+    spend_tx = spend_coins(lianad, bitcoind, [lianad.rpc.listcoins()["coins"][0]])
+    spend_txid = get_txid(spend_tx)
+    bitcoind.generate_block(1, wait_for_mempool=spend_txid)
+    wait_for(lambda: lianad.rpc.getinfo()["block_height"] == height + 1)
+    coins = lianad.rpc.listcoinsatheight(height)["coins"]
+    assert len(coins) == 1 and coins[0]["spend_info"] is None
+    assert outpoint not in [
+        c["outpoint"] for c in lianad.rpc.listcoinsatheight(height + 1)["coins"]
+    ]
+
+    # We can't list coins as of a block we haven't scanned yet.
+    with pytest.raises(RpcError, match="outside of the range of blocks scanned"):
+        lianad.rpc.listcoinsatheight(height + 2)
+
+
+def test_listcoinsbyaddress(lianad, bitcoind):
+    # Receive two coins on the same address and one on another.
+    reused_addr = lianad.rpc.getnewaddress()["address"]
+    other_addr = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendmany("", {reused_addr: 0.01, other_addr: 0.05})
+    txid_b = bitcoind.rpc.sendtoaddress(reused_addr, 0.02)
+    bitcoind.generate_block(1, wait_for_mempool=[txid, txid_b])
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 3)
+
+    # The reused address comes first.
+    addresses = lianad.rpc.listcoinsbyaddress()["addresses"]
+    assert [a["address"] for a in addresses] == [reused_addr, other_addr]
+    assert addresses[0]["total"] == 3_000_000
+    assert len(addresses[0]["coins"]) == 2
+    assert all(c["address"] == reused_addr for c in addresses[0]["coins"])
+    assert addresses[1]["total"] == 5_000_000
+    assert not addresses[0]["is_change"]
+
+    # The coins can be filtered by status.
+    assert lianad.rpc.listcoinsbyaddress(["unconfirmed"])["addresses"] == []
+    with pytest.raises(RpcError, match="Invalid value"):
+        lianad.rpc.listcoinsbyaddress(["foo"])
+
+
+def test_checkpayment(lianad, bitcoind):
+    addr = lianad.rpc.getnewaddress()["address"]
+    res = lianad.rpc.checkpayment(addr, 3_000_000)
+    assert res["received"] == 0 and res["confirmations"] is None
+    assert not res["is_paid"]
+
+    # A first partial payment.
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: lianad.rpc.checkpayment(addr, 3_000_000)["received"] == 1_000_000)
+    assert not lianad.rpc.checkpayment(addr, 3_000_000)["is_paid"]
+
+    # The rest, and more, is paid but is unconfirmed.
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.025)
+    wait_for(lambda: lianad.rpc.checkpayment(addr, 3_000_000)["received"] == 3_500_000)
+    res = lianad.rpc.checkpayment(addr, 3_000_000)
+    assert res["excess"] == 500_000
+    assert res["confirmations"] == 0
+    assert not res["is_paid"]
+    assert [p["confirmations"] for p in res["payments"]] == [1, 0]
+    assert lianad.rpc.checkpayment(addr, 3_000_000, 0)["is_paid"]
+
+    # Once confirmed the payment is complete.
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: lianad.rpc.checkpayment(addr, 3_000_000)["is_paid"])
+    assert not lianad.rpc.checkpayment(addr, 3_000_000, 2)["is_paid"]
+    assert lianad.rpc.checkpayment(addr, 1_000_000, 2)["is_paid"]
+
+    # We can't check a payment to an external address.
+    with pytest.raises(RpcError, match="is not one of ours"):
+        lianad.rpc.checkpayment(bitcoind.rpc.getnewaddress(), 1_000)
+
+
+def test_getcoinfundingtx(lianad, bitcoind):
+    addr = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.01)
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == 1)
+    outpoint = lianad.rpc.listcoins()["coins"][0]["outpoint"]
+
+    # The raw funding transaction is returned, without confirmation info until it confirms.
+    raw_tx = bitcoind.rpc.gettransaction(txid)["hex"]
+    res = lianad.rpc.getcoinfundingtx(outpoint)
+    assert res["tx"] == raw_tx
+    assert res["height"] is None and res["time"] is None
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: lianad.rpc.listcoins()["coins"][0]["block_height"] is not None)
+    res = lianad.rpc.getcoinfundingtx(outpoint)
+    assert res["tx"] == raw_tx
+    assert res["height"] == bitcoind.rpc.getblockcount()
+
+    with pytest.raises(RpcError, match="Unknown outpoint"):
+        lianad.rpc.getcoinfundingtx(f"{txid}:42")
+
+
+def test_listcoinswithindepth(lianad, bitcoind):
+    # Receive a coin and get it confirmed.
+    addr = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 1)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    height = bitcoind.rpc.getblockcount()
+    wait_for(lambda: lianad.rpc.getinfo()["block_height"] == height)
+    outpoint = lianad.rpc.listcoins()["coins"][0]["outpoint"]
+
+    # It is affected by a reorg of the tip block, until more blocks are mined on top of it.
+    coins = lianad.rpc.listcoinswithindepth(1)["coins"]
+    assert [c["outpoint"] for c in coins] == [outpoint]
+    bitcoind.generate_block(2)
+    wait_for(lambda: lianad.rpc.getinfo()["block_height"] == height + 2)
+    assert len(lianad.rpc.listcoinswithindepth(2)["coins"]) == 0
+    coins = lianad.rpc.listcoinswithindepth(3)["coins"]
+    assert [c["outpoint"] for c in coins] == [outpoint]
+
+    with pytest.raises(RpcError, match="at least one block"):
+        lianad.rpc.listcoinswithindepth(0)
+
+
 def test_jsonrpc_server(lianad, bitcoind):
     """Test passing parameters as a list or a mapping."""
     addr = lianad.rpc.getnewaddress()["address"]
@@ -398,6 +816,9 @@ def test_create_spend(lianad, bitcoind):
         bitcoind.rpc.getnewaddress(): 400_000,
         bitcoind.rpc.getnewaddress(): 1_000_000,
     }
+    # The options besides the change address may only be given by name.
+    with pytest.raises(RpcError, match="must be given by name"):
+        lianad.rpc.createspend(destinations, outpoints, 18, None, 0)
     res = lianad.rpc.createspend(destinations, outpoints, 18)
     assert "psbt" in res
 
@@ -504,6 +925,10 @@ def test_list_spend(lianad, bitcoind):
     assert time_before_update <= first_psbt["updated_at"] <= int(time.time())
     second_psbt = next(entry for entry in list_res if entry["psbt"] == res_b["psbt"])
     assert time_before_update <= second_psbt["updated_at"] <= int(time.time())
+    # Each has its own draft identifier, as returned at creation.
+    assert first_psbt["draft_id"] == res["draft_id"]
+    assert second_psbt["draft_id"] == res_b["draft_id"]
+    assert res["draft_id"] != res_b["draft_id"]
 
     # If we delete the first one, we'll get only the second one.
     first_psbt = PSBT.from_base64(res["psbt"])
@@ -512,13 +937,51 @@ def test_list_spend(lianad, bitcoind):
     assert len(list_res) == 1
     assert list_res[0]["psbt"] == res_b["psbt"]
 
-    # If we delete the second one, result will be empty.
-    second_psbt = PSBT.from_base64(res_b["psbt"])
-    lianad.rpc.delspendtx(second_psbt.tx.txid().hex())
+    # If we delete the second one using its draft identifier, result will be empty.
+    lianad.rpc.delspendtx(res_b["draft_id"])
     list_res = lianad.rpc.listspendtxs()["spend_txs"]
     assert len(list_res) == 0
 
 
+def test_list_spend_statuses(lianad, bitcoind):
+    """Test the status of stored Spend transactions, and filtering them by status."""
+    addr = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.1)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    destinations = {
+        bitcoind.rpc.getnewaddress(): 200_000,
+    }
+    res = lianad.rpc.createspend(destinations, outpoints, 2)
+    spend_psbt = PSBT.from_base64(res["psbt"])
+    spend_txid = spend_psbt.tx.txid().hex()
+
+    # A freshly stored Spend is a draft.
+    lianad.rpc.updatespend(res["psbt"])
+    assert lianad.rpc.listspendtxs()["spend_txs"][0]["status"] == "draft"
+    assert len(lianad.rpc.listspendtxs(statuses=["draft"])["spend_txs"]) == 1
+    assert len(lianad.rpc.listspendtxs(statuses=["signed"])["spend_txs"]) == 0
+    with pytest.raises(RpcError, match="Invalid value.*in 'statuses' parameter."):
+        lianad.rpc.listspendtxs(statuses=["unsigned"])
+
+    # Once signed it's ready to be broadcast.
+    signed_psbt = lianad.signer.sign_psbt(spend_psbt)
+    lianad.rpc.updatespend(signed_psbt.to_base64())
+    signed = lianad.rpc.listspendtxs(statuses=["signed", "broadcast"])["spend_txs"]
+    assert len(signed) == 1
+    assert signed[0]["status"] == "signed"
+    assert len(lianad.rpc.listspendtxs(statuses=["draft"])["spend_txs"]) == 0
+
+    # And once broadcast, it's reported as such.
+    lianad.rpc.broadcastspend(spend_txid)
+    wait_for(
+        lambda: lianad.rpc.listspendtxs([spend_txid])["spend_txs"][0]["status"]
+        == "broadcast"
+    )
+    assert len(lianad.rpc.listspendtxs(statuses=["signed"])["spend_txs"]) == 0
+
+
 def test_update_spend(lianad, bitcoind):
     # Start by creating a Spend PSBT
     addr = lianad.rpc.getnewaddress()["address"]
@@ -576,6 +1039,57 @@ def test_update_spend(lianad, bitcoind):
     assert psbt_merged.i[0].map[PSBT_IN_PARTIAL_SIG][dummy_pk_b] == dummy_sig_b
 
 
+def test_spend_bbqr(lianad, bitcoind):
+    def base36(n):
+        digits = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ"
+        return digits[n // 36] + digits[n % 36]
+
+    addr = lianad.rpc.getnewaddress()["address"]
+    bitcoind.rpc.sendtoaddress(addr, 0.2567)
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) > 0)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    destinations = {
+        bitcoind.rpc.getnewaddress(): 200_000,
+    }
+    res = lianad.rpc.createspend(destinations, outpoints, 6)
+    psbt = PSBT.from_base64(res["psbt"])
+    txid = psbt.tx.txid().hex()
+
+    # We can't export an unknown Spend
+    with pytest.raises(RpcError, match="Unknown spend transaction.*"):
+        lianad.rpc.exportspendbbqr(txid, 200)
+    lianad.rpc.updatespend(res["psbt"])
+
+    # The PSBT is exported as Base32 encoded parts of the requested size.
+    parts = lianad.rpc.exportspendbbqr(txid, 200)["parts"]
+    assert len(parts) > 1
+    for i, part in enumerate(parts):
+        assert len(part) <= 200
+        assert part.startswith(f"B$2P{base36(len(parts))}{base36(i)}")
+    data = "".join(p[8:] for p in parts)
+    data += "=" * (-len(data) % 8)
+    stored_psbt = lianad.rpc.listspendtxs()["spend_txs"][0]["psbt"]
+    assert base64.b32decode(data) == base64.b64decode(stored_psbt)
+    with pytest.raises(RpcError, match="Parts must be at least 16 characters long.*"):
+        lianad.rpc.exportspendbbqr(txid, 15)
+
+    # A signed PSBT can be imported from parts scanned in any order, with duplicates.
+    signed_psbt = lianad.signer.sign_psbt(PSBT.from_base64(res["psbt"]))
+    signed_data = base64.b32encode(base64.b64decode(signed_psbt.to_base64()))
+    signed_data = signed_data.decode().rstrip("=")
+    chunks = [signed_data[i : i + 80] for i in range(0, len(signed_data), 80)]
+    signed_parts = [
+        f"B$2P{base36(len(chunks))}{base36(i)}{c}" for i, c in enumerate(chunks)
+    ]
+    scanned = list(reversed(signed_parts)) + signed_parts[:1]
+    with pytest.raises(RpcError, match="Missing parts at indexes.*"):
+        lianad.rpc.importspendbbqr(signed_parts[1:])
+    assert lianad.rpc.importspendbbqr(scanned)["txid"] == txid
+    stored_psbt = PSBT.from_base64(lianad.rpc.listspendtxs()["spend_txs"][0]["psbt"])
+    assert len(stored_psbt.i[0].map[PSBT_IN_PARTIAL_SIG]) > 0
+    lianad.rpc.broadcastspend(txid)
+
+
 def test_broadcast_spend(lianad, bitcoind):
     # Create a new coin and a spending tx for it.
     addr = lianad.rpc.getnewaddress()["address"]
@@ -605,6 +1119,152 @@ def test_broadcast_spend(lianad, bitcoind):
     lianad.rpc.broadcastspend(txid)
 
 
+def test_testbroadcastspend(lianad, bitcoind):
+    # Create a new coin and a signed spending tx for it.
+    addr = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    res = lianad.rpc.createspend({bitcoind.rpc.getnewaddress(): 200_000}, outpoints, 2)
+    psbt = PSBT.from_base64(res["psbt"])
+    txid = psbt.tx.txid().hex()
+    with pytest.raises(RpcError, match="Unknown spend transaction.*"):
+        lianad.rpc.testbroadcastspend(txid)
+    lianad.rpc.updatespend(res["psbt"])
+    with pytest.raises(RpcError, match="Failed to finalize the spend transaction.*"):
+        lianad.rpc.testbroadcastspend(txid)
+    signed_psbt = lianad.signer.sign_psbt(psbt)
+    lianad.rpc.updatespend(signed_psbt.to_base64())
+
+    # It would be accepted, but it isn't broadcast.
+    res = lianad.rpc.testbroadcastspend(txid)
+    assert res["accepted"]
+    assert res["reject_reason"] is None
+    assert res["vsize"] > 0 and res["fee"] > 0
+    assert txid not in bitcoind.rpc.getrawmempool()
+
+    # Once broadcast, it would be rejected as it's already in the mempool. Broadcasting it again
+    # after checking it fails.
+    lianad.rpc.broadcastspend(txid, True)
+    bitcoind.rpc.getmempoolentry(txid)
+    res = lianad.rpc.testbroadcastspend(txid)
+    assert not res["accepted"]
+    assert res["reject_reason"] is not None
+    with pytest.raises(RpcError, match="would be rejected"):
+        lianad.rpc.broadcastspend(txid=txid, test_first=True)
+
+
+def test_reserve_inputs(lianad, bitcoind):
+    """The coins of a stored Spend draft can't be used by another one until it's deleted."""
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    destinations = {bitcoind.rpc.getnewaddress(): 100_000}
+
+    # Creating a draft doesn't reserve the coin, nor does storing one created without reservation.
+    res = lianad.rpc.createspend(
+        destinations=destinations, outpoints=outpoints, feerate=2, reserve_inputs=False
+    )
+    lianad.rpc.updatespend(res["psbt"])
+    res = lianad.rpc.createspend(destinations, outpoints, 2)
+
+    # Once stored, the coin is reserved for the draft.
+    lianad.rpc.updatespend(res["psbt"])
+    with pytest.raises(RpcError, match="is reserved by another Spend transaction draft"):
+        lianad.rpc.createspend(destinations, outpoints, 2)
+    # It isn't available to coin selection either.
+    assert lianad.rpc.createspend(destinations, [], 2)["available"] == 0
+
+    # Deleting the draft releases it.
+    lianad.rpc.delspendtx(res["draft_id"])
+    assert "psbt" in lianad.rpc.createspend(destinations, outpoints, 2)
+
+
+def test_exclude_outpoints(lianad, bitcoind):
+    """Coins may be excluded from automatic coin selection for a single Spend."""
+    txids = [
+        bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+        for _ in range(2)
+    ]
+    bitcoind.generate_block(1, wait_for_mempool=txids)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 2)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    destinations = {bitcoind.rpc.getnewaddress(): 100_000}
+
+    # The excluded coin is never selected.
+    for excluded, other in [outpoints, outpoints[::-1]]:
+        res = lianad.rpc.createspend(
+            destinations=destinations,
+            outpoints=[],
+            feerate=2,
+            exclude_outpoints=[excluded],
+        )
+        psbt = PSBT.from_base64(res["psbt"])
+        assert [f"{i.prevout.hash:064x}:{i.prevout.n}" for i in psbt.tx.vin] == [other]
+
+    # Excluding all coins leaves nothing to select.
+    res = lianad.rpc.createspend(
+        destinations=destinations,
+        outpoints=[],
+        feerate=2,
+        exclude_outpoints=outpoints,
+    )
+    assert res["available"] == 0
+
+
+def test_prunespentcoins(lianad, bitcoind):
+    # Receive two coins and spend one of them.
+    txids = [
+        bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+        for _ in range(2)
+    ]
+    bitcoind.generate_block(1, wait_for_mempool=txids)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 2)
+    coin = lianad.rpc.listcoins()["coins"][0]
+    spend_txid = get_txid(spend_coins(lianad, bitcoind, [coin]))
+    bitcoind.generate_block(1, wait_for_mempool=spend_txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["spent"])["coins"]) == 1)
+
+    # Spent coins can't be pruned too early.
+    with pytest.raises(RpcError, match="Spent coins can only be pruned after at least"):
+        lianad.rpc.prunespentcoins(10)
+
+    # The spent coin is only pruned once its spending transaction is deep enough.
+    assert lianad.rpc.prunespentcoins(100)["pruned_count"] == 0
+    bitcoind.generate_block(99)
+    wait_for(
+        lambda: lianad.rpc.getinfo()["block_height"] == bitcoind.rpc.getblockcount()
+    )
+    assert lianad.rpc.prunespentcoins(100)["pruned_count"] == 1
+    coins = lianad.rpc.listcoins()["coins"]
+    assert len(coins) == 1
+    assert coins[0]["outpoint"] != coin["outpoint"]
+
+    # The history of the remaining coin is still available.
+    deposit_txid = coins[0]["outpoint"][:64]
+    res = lianad.rpc.listtransactions([deposit_txid])
+    assert len(res["transactions"]) == 1
+
+
+def test_databasestats(lianad, bitcoind):
+    """The size of the database can be monitored and its unused space reclaimed."""
+    stats = lianad.rpc.getdatabasestats()
+    assert stats["table_rows"]["coins"] == 0
+    assert stats["size"] > 0
+
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    assert lianad.rpc.getdatabasestats()["table_rows"]["coins"] == 1
+
+    # Once vacuumed, the database has no unused space left.
+    stats = lianad.rpc.vacuumdatabase()
+    assert stats["table_rows"]["coins"] == 1
+    assert stats["free_size"] == 0
+
+
 def test_start_rescan(lianad, bitcoind):
     """Test we successfully retrieve all our transactions after losing state by rescanning."""
     initial_timestamp = int(time.time())
@@ -646,6 +1306,11 @@ def test_start_rescan(lianad, bitcoind):
     # we can rescan from genesis block
     lianad.rpc.startrescan(genesis_timestamp)
     wait_for(lambda: lianad.rpc.getinfo()["rescan_progress"] is None)
+    # We can also rescan from a block given its hash, if the block is in the best chain.
+    with pytest.raises(RpcError, match=".*is not part of the best chain."):
+        lianad.rpc.startrescanfromhash("00" * 32)
+    lianad.rpc.startrescanfromhash(block_hash)
+    wait_for(lambda: lianad.rpc.getinfo()["rescan_progress"] is None)
 
     # First, get some coins
     for _ in range(10):
@@ -918,15 +1583,66 @@ def test_create_recovery(lianad, bitcoind):
     sign_and_broadcast(lianad, bitcoind, reco_psbt, recovery=True)
 
 
+def test_getrecoverycost(lianad, bitcoind):
+    """Test the estimate of the cost of sweeping the coins available through the timelocked path."""
+    destinations = {
+        lianad.rpc.getnewaddress()["address"]: 0.1,
+        lianad.rpc.getnewaddress()["address"]: 0.2,
+    }
+    txid = bitcoind.rpc.sendmany("", destinations)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(
+        lambda: lianad.rpc.getinfo()["block_height"] == bitcoind.rpc.getblockcount()
+    )
+
+    # There's nothing to sweep yet.
+    with pytest.raises(
+        RpcError,
+        match="No coin currently spendable through this timelocked recovery path",
+    ):
+        lianad.rpc.getrecoverycost(2)
+    # There is a single recovery path in the fixture.
+    with pytest.raises(RpcError, match="Unknown recovery path '1'"):
+        lianad.rpc.getrecoverycost(2, 1)
+
+    # Once the timelock matured, we get an estimate for both coins.
+    bitcoind.generate_block(9)
+    wait_for(
+        lambda: lianad.rpc.getinfo()["block_height"] == bitcoind.rpc.getblockcount()
+    )
+    estimate = lianad.rpc.getrecoverycost(2)
+    assert estimate == lianad.rpc.getrecoverycost(2, 0)
+    assert estimate["timelock"] == 10
+    assert estimate["coins_count"] == 2
+    assert estimate["amount"] == int(0.3 * COIN)
+    assert estimate["fee"] == estimate["vsize"] * 2
+    # It doesn't depend on the feerate besides the fee.
+    assert lianad.rpc.getrecoverycost(10)["fee"] == estimate["vsize"] * 10
+
+    # It matches the actual recovery transaction sweeping the coins to one of our addresses,
+    # up to the size of the signatures.
+    res = lianad.rpc.createrecovery(lianad.rpc.getnewaddress()["address"], 2)
+    reco_psbt = PSBT.from_base64(res["psbt"])
+    assert len(reco_psbt.tx.vin) == 2
+    txid = sign_and_broadcast(lianad, bitcoind, reco_psbt, recovery=True)
+    vsize = bitcoind.rpc.getmempoolentry(txid)["vsize"]
+    assert vsize <= estimate["vsize"] <= vsize + 2
+
+
 def test_labels(lianad, bitcoind):
     """Test the creation and updating of labels."""
     # We can set a label for an address.
     addr = lianad.rpc.getnewaddress()["address"]
-    lianad.rpc.updatelabels({addr: "first-addr"})
+    res = lianad.rpc.updatelabels({addr: "first-addr"})
+    assert res == {"created": [addr], "updated": [], "deleted": []}
     assert lianad.rpc.getlabels([addr])["labels"] == {addr: "first-addr"}
     # And also update it.
-    lianad.rpc.updatelabels({addr: "first-addr-1"})
+    res = lianad.rpc.updatelabels({addr: "first-addr-1"})
+    assert res == {"created": [], "updated": [addr], "deleted": []}
     assert lianad.rpc.getlabels([addr])["labels"] == {addr: "first-addr-1"}
+    # Setting the same label again doesn't change anything.
+    res = lianad.rpc.updatelabels({addr: "first-addr-1"})
+    assert res == {"created": [], "updated": [], "deleted": []}
     # But we can't set a label larger than 100 characters
     with pytest.raises(RpcError, match=".*must be less or equal than 100 characters"):
         lianad.rpc.updatelabels({addr: "".join("a" for _ in range(101))})
@@ -1044,6 +1760,169 @@ def test_labels(lianad, bitcoind):
     assert sec_addr not in res
     assert res[random_address] == "this address is random"
 
+    # We can export all the labels at once, or page through them.
+    all_labels = lianad.rpc.exportlabels()["labels"]
+    assert len(all_labels) == 8
+    assert {l["item"]: l["value"] for l in all_labels}[random_address] == (
+        "this address is random"
+    )
+    assert addr not in (l["item"] for l in all_labels)
+    first_page = lianad.rpc.exportlabels(0, 5)["labels"]
+    second_page = lianad.rpc.exportlabels(5, 5)["labels"]
+    assert len(first_page) == 5 and len(second_page) == 3
+    assert first_page + second_page == all_labels
+    with pytest.raises(RpcError, match=".*'offset' parameter requires the 'limit'"):
+        lianad.rpc.exportlabels(offset=2)
+
+    # The exported labels can be restored, replacing all the current ones at once.
+    with pytest.raises(RpcError, match="must be a string"):
+        lianad.rpc.replacelabels({random_address: None})
+    assert len(lianad.rpc.exportlabels()["labels"]) == 8
+    res = lianad.rpc.replacelabels({random_address: "restored"})
+    assert res == {"deleted": 8, "stored": 1}
+    assert lianad.rpc.exportlabels()["labels"] == [
+        {"item": random_address, "value": "restored"}
+    ]
+    res = lianad.rpc.replacelabels({l["item"]: l["value"] for l in all_labels})
+    assert res == {"deleted": 1, "stored": 8}
+    assert sorted(lianad.rpc.exportlabels()["labels"], key=lambda l: l["item"]) == sorted(
+        all_labels, key=lambda l: l["item"]
+    )
+
+    # All the labels are part of the wallet export.
+    export = lianad.rpc.exportwallet()
+    assert export["labels"] == {l["item"]: l["value"] for l in all_labels}
+    assert export["descriptor"] == lianad.rpc.getinfo()["descriptors"]["main"]
+    assert export["network"] == "regtest"
+
+
+def test_getwalletbirthday(lianad, bitcoind):
+    """The wallet birthday is the height of the chain at wallet creation."""
+    birthday = lianad.rpc.getwalletbirthday()["height"]
+    assert 0 < birthday <= lianad.rpc.getinfo()["block_height"]
+
+    # Receiving a coin after the wallet creation doesn't change it.
+    addr = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    assert lianad.rpc.getwalletbirthday()["height"] == birthday
+
+    # It's part of the wallet export.
+    assert lianad.rpc.exportwallet()["birthday_height"] == birthday
+
+
+def test_exportcoredescriptors(lianad, bitcoind):
+    """The wallet can be watched from a Bitcoin Core watchonly wallet."""
+    addr = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+
+    descs = lianad.rpc.exportcoredescriptors()["descriptors"]
+    assert [d["internal"] for d in descs] == [False, True]
+    assert all(d["range"][0] == 0 and d["range"][1] >= 1_000 for d in descs)
+
+    # Import them into a fresh watchonly wallet, it finds our coin and addresses.
+    bitcoind.node_rpc.createwallet("core_watchonly", True, True, "", False, True)
+    watchonly = BitcoindRpcInterface(
+        bitcoind.bitcoin_dir, "regtest", bitcoind.rpcport, wallet="core_watchonly"
+    )
+    res = watchonly.importdescriptors(descs)
+    assert all(r["success"] for r in res), res
+    assert watchonly.getaddressinfo(addr)["ismine"]
+    change_addr = lianad.rpc.listaddresses(0, 1)["addresses"][0]["change"]
+    assert watchonly.getaddressinfo(change_addr)["ischange"]
+    assert [u["txid"] for u in watchonly.listunspent()] == [txid]
+
+
+def test_gettxlabels(lianad, bitcoind):
+    """Get the labels related to a transaction in a single call."""
+    with pytest.raises(RpcError, match="Unknown transaction"):
+        lianad.rpc.gettxlabels("00" * 32)
+
+    # Receive a coin and label the deposit address and transaction.
+    addr = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoint = lianad.rpc.listcoins()["coins"][0]["outpoint"]
+    lianad.rpc.updatelabels({addr: "deposit", txid: "salary", outpoint: "salary coin"})
+    res = lianad.rpc.gettxlabels(txid)
+    assert res["transaction"] == "salary"
+    assert res["inputs"] == {}
+    assert res["outputs"] == {addr: "deposit"}
+
+    # A Spend draft spending this coin gets the label of the coin as input.
+    dest_addr = bitcoind.rpc.getnewaddress()
+    lianad.rpc.setdestinationlabel(dest_addr, "Alice")
+    res = lianad.rpc.createspend({dest_addr: 100_000}, [outpoint], 2)
+    lianad.rpc.updatespend(res["psbt"])
+    spend_txid = PSBT.from_base64(res["psbt"]).tx.txid().hex()
+    res = lianad.rpc.gettxlabels(spend_txid)
+    assert res["transaction"] is None
+    assert res["inputs"] == {outpoint: "salary coin"}
+    assert res["outputs"] == {dest_addr: "Alice"}
+
+
+def test_destination_labels(lianad, bitcoind):
+    """Name the external addresses we pay to, and get their names when listing transactions."""
+    assert lianad.rpc.listdestinationlabels()["destinations"] == []
+    # Our own addresses can't be part of the address book.
+    our_addr = lianad.rpc.getnewaddress()["address"]
+    with pytest.raises(RpcError, match="is one of ours"):
+        lianad.rpc.setdestinationlabel(our_addr, "Me")
+
+    alice_addr = bitcoind.rpc.getnewaddress()
+    lianad.rpc.setdestinationlabel(alice_addr, "Alice")
+    lianad.rpc.updatelabels({our_addr: "Mine"})
+    assert lianad.rpc.listdestinationlabels()["destinations"] == [
+        {"address": alice_addr, "label": "Alice"}
+    ]
+
+    # Pay Alice, the transaction lists her as a recipient.
+    txid = bitcoind.rpc.sendtoaddress(our_addr, 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    res = lianad.rpc.createspend({alice_addr: 100_000}, outpoints, 2)
+    spend_txid = sign_and_broadcast_psbt(lianad, PSBT.from_base64(res["psbt"]))
+    bitcoind.generate_block(1, wait_for_mempool=spend_txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["spent"])["coins"]) == 1)
+    tx = lianad.rpc.listtransactions([spend_txid])["transactions"][0]
+    assert tx["recipient_labels"] == {alice_addr: "Alice"}
+
+    # Alice can be removed from the address book.
+    lianad.rpc.setdestinationlabel(alice_addr, None)
+    assert lianad.rpc.listdestinationlabels()["destinations"] == []
+    tx = lianad.rpc.listtransactions([spend_txid])["transactions"][0]
+    assert tx["recipient_labels"] == {}
+
+
+def test_createspend_destination_labels(lianad, bitcoind):
+    """Destinations may be labelled when creating the Spend."""
+    addr = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+
+    dest_a = bitcoind.rpc.getnewaddress()
+    dest_b = bitcoind.rpc.getnewaddress()
+    destinations = {
+        dest_a: {"amount": 100_000, "label": "Landlord"},
+        dest_b: 200_000,
+    }
+    with pytest.raises(RpcError, match="Invalid 'destinations' parameter"):
+        lianad.rpc.createspend(
+            {dest_a: {"amount": 100_000, "label": "a" * 101}}, outpoints, 2
+        )
+    res = lianad.rpc.createspend(destinations, outpoints, 2)
+    assert "psbt" in res
+    # The label was only set for the destination which had one.
+    labels = lianad.rpc.getlabels([dest_a, dest_b])["labels"]
+    assert labels == {dest_a: "Landlord"}
+
 
 def test_rbfpsbt_bump_fee(lianad, bitcoind):
     """Test the use of RBF to bump the fee of a transaction."""
@@ -1064,11 +1943,19 @@ def test_rbfpsbt_bump_fee(lianad, bitcoind):
     destinations = {
         bitcoind.rpc.getnewaddress(): 650_000,
     }
-    first_res = lianad.rpc.createspend(destinations, first_outpoints, 1)
+    first_res = lianad.rpc.createspend(
+        destinations=destinations,
+        outpoints=first_outpoints,
+        feerate=1,
+        label="Rent",
+        output_seed=CHANGE_LAST_SEED,
+    )
     first_psbt = PSBT.from_base64(first_res["psbt"])
     # The transaction has a change output.
     assert len(first_psbt.o) == len(first_psbt.tx.vout) == 2
     first_txid = first_psbt.tx.txid().hex()
+    # The label was set on the transaction when creating it.
+    assert lianad.rpc.getlabels([first_txid])["labels"][first_txid] == "Rent"
     # We must provide a valid feerate.
     for bad_feerate in [-1, "foo", 18_446_744_073_709_551_616]:
         with pytest.raises(RpcError, match=f"Invalid 'feerate' parameter."):
@@ -1100,6 +1987,9 @@ def test_rbfpsbt_bump_fee(lianad, bitcoind):
     # Let's use an even higher feerate.
     rbf_1_res = lianad.rpc.rbfpsbt(first_txid, False, 10)
     rbf_1_psbt = PSBT.from_base64(rbf_1_res["psbt"])
+    # The replacement inherits the label of the transaction it replaces.
+    rbf_1_txid = rbf_1_psbt.tx.txid().hex()
+    assert lianad.rpc.getlabels([rbf_1_txid])["labels"][rbf_1_txid] == "Rent"
     # The inputs are the same in both (no new inputs needed in the replacement).
     assert sorted(i.prevout.serialize() for i in first_psbt.tx.vin) == sorted(
         i.prevout.serialize() for i in rbf_1_psbt.tx.vin
@@ -1194,6 +2084,133 @@ def test_rbfpsbt_bump_fee(lianad, bitcoind):
             for c in lianad.rpc.listcoins([], first_outpoints)["coins"]
         )
     )
+    # The daemon recorded which of our transactions replaced which.
+    txids = [first_txid, rbf_1_txid, rbf_2_txid]
+    txs = lianad.rpc.listtransactions(txids)["transactions"]
+    assert [tx["replaced_by"] for tx in txs] == [rbf_1_txid, rbf_2_txid, None]
+
+
+def test_rbfpsbt_absolute_fee(lianad, bitcoind):
+    """The fee of a transaction can be bumped to an absolute fee."""
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins(["confirmed"])["coins"]]
+    res = lianad.rpc.createspend({bitcoind.rpc.getnewaddress(): 200_000}, outpoints, 1)
+    first_psbt = PSBT.from_base64(res["psbt"])
+    first_txid = sign_and_broadcast_psbt(lianad, first_psbt)
+    first_fee = int(bitcoind.rpc.getmempoolentry(first_txid)["fees"]["base"] * COIN)
+    wait_for(
+        lambda: all(
+            c["spend_info"] is not None and c["spend_info"]["txid"] == first_txid
+            for c in lianad.rpc.listcoins([], outpoints)["coins"]
+        )
+    )
+
+    # The fee can't be set along with a feerate, nor for a cancel.
+    with pytest.raises(RpcError, match="Only one of 'feerate' and 'fee' may be provided."):
+        lianad.rpc.rbfpsbt(txid=first_txid, is_cancel=False, feerate=2, fee=10_000)
+    with pytest.raises(RpcError, match="A fee must not be provided if creating a cancel."):
+        lianad.rpc.rbfpsbt(txid=first_txid, is_cancel=True, fee=10_000)
+
+    # Paying the same fee doesn't satisfy the replacement rules. We are told the minimum.
+    with pytest.raises(RpcError, match="Fee too low") as err:
+        lianad.rpc.rbfpsbt(txid=first_txid, is_cancel=False, fee=first_fee)
+    min_fee = int(re.search(r"at least (\d+) sats", str(err.value)).group(1))
+    assert min_fee > first_fee
+
+    # The replacement pays exactly the requested fee and is accepted by bitcoind.
+    res = lianad.rpc.rbfpsbt(txid=first_txid, is_cancel=False, fee=min_fee)
+    assert len(res["warnings"]) == 0
+    rbf_psbt = PSBT.from_base64(res["psbt"])
+    rbf_txid = sign_and_broadcast_psbt(lianad, rbf_psbt)
+    entry = bitcoind.rpc.getmempoolentry(rbf_txid)
+    assert int(entry["fees"]["base"] * COIN) == min_fee
+
+
+def test_cancelspend(lianad, bitcoind):
+    """An unconfirmed Spend can be cancelled by sending its coins to a fresh change address."""
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins(["confirmed"])["coins"]]
+    res = lianad.rpc.createspend({bitcoind.rpc.getnewaddress(): 200_000}, outpoints, 2)
+    first_psbt = PSBT.from_base64(res["psbt"])
+    first_txid = sign_and_broadcast_psbt(lianad, first_psbt)
+    wait_for(
+        lambda: all(
+            c["spend_info"] is not None and c["spend_info"]["txid"] == first_txid
+            for c in lianad.rpc.listcoins([], outpoints)["coins"]
+        )
+    )
+
+    # The feerate must be higher than the one of the transaction being replaced.
+    with pytest.raises(RpcError, match="Feerate too low"):
+        lianad.rpc.cancelspend(first_txid, 2)
+
+    # The cancel has a single output, to neither the recipient nor the previous change address.
+    res = lianad.rpc.cancelspend(first_txid, 10)
+    cancel_psbt = PSBT.from_base64(res["psbt"])
+    assert len(cancel_psbt.tx.vout) == 1
+    prev_spks = [txo.scriptPubKey for txo in first_psbt.tx.vout]
+    assert cancel_psbt.tx.vout[0].scriptPubKey not in prev_spks
+    cancel_txid = sign_and_broadcast_psbt(lianad, cancel_psbt)
+    wait_for(lambda: cancel_txid in bitcoind.rpc.getrawmempool())
+    assert first_txid not in bitcoind.rpc.getrawmempool()
+
+
+def test_rbfpsbt_recovery(lianad, bitcoind):
+    """Bumping the fee of a recovery transaction keeps spending through the timelocked path."""
+    # Get two coins and make their timelock mature (we use a csv of 10 in the fixture).
+    destinations = {
+        lianad.rpc.getnewaddress()["address"]: 0.1,
+        lianad.rpc.getnewaddress()["address"]: 0.2,
+    }
+    txid = bitcoind.rpc.sendmany("", destinations)
+    bitcoind.generate_block(10, wait_for_mempool=txid)
+    wait_for(
+        lambda: lianad.rpc.getinfo()["block_height"] == bitcoind.rpc.getblockcount()
+    )
+
+    # Sweep them through the recovery path at a low feerate.
+    sweep_addr = bitcoind.rpc.getnewaddress()
+    res = lianad.rpc.createrecovery(sweep_addr, 2)
+    reco_psbt = PSBT.from_base64(res["psbt"])
+    reco_txid = reco_psbt.tx.txid().hex()
+    assert all(txin.nSequence == 10 for txin in reco_psbt.tx.vin)
+    reco_psbt = lianad.signer.sign_psbt(reco_psbt, recovery=True)
+    lianad.rpc.updatespend(reco_psbt.to_base64())
+    lianad.rpc.broadcastspend(reco_txid)
+    outpoints = [txin.prevout.serialize() for txin in reco_psbt.tx.vin]
+    wait_for(lambda: reco_txid in bitcoind.rpc.getrawmempool())
+
+    # Bump its fee. The replacement spends the same coins with the same nSequence and the
+    # higher fee is deducted from the sweep output.
+    res = lianad.rpc.rbfpsbt(reco_txid, False, 10)
+    rbf_psbt = PSBT.from_base64(res["psbt"])
+    assert sorted(txin.prevout.serialize() for txin in rbf_psbt.tx.vin) == sorted(outpoints)
+    assert all(txin.nSequence == 10 for txin in rbf_psbt.tx.vin)
+    assert len(rbf_psbt.tx.vout) == 1
+    assert rbf_psbt.tx.vout[0].scriptPubKey == reco_psbt.tx.vout[0].scriptPubKey
+    assert rbf_psbt.tx.vout[0].nValue < reco_psbt.tx.vout[0].nValue
+
+    # The replacement can be signed for the recovery path and replaces the original.
+    rbf_txid = rbf_psbt.tx.txid().hex()
+    rbf_psbt = lianad.signer.sign_psbt(rbf_psbt, recovery=True)
+    lianad.rpc.updatespend(rbf_psbt.to_base64())
+    lianad.rpc.broadcastspend(rbf_txid)
+    mempool = bitcoind.rpc.getrawmempool()
+    assert rbf_txid in mempool and reco_txid not in mempool
+    bitcoind.generate_block(1, wait_for_mempool=rbf_txid)
+    wait_for(
+        lambda: all(
+            c["spend_info"] is not None
+            and c["spend_info"]["txid"] == rbf_txid
+            and c["spend_info"]["height"] is not None
+            for c in lianad.rpc.listcoins(["spent"])["coins"]
+        )
+        and len(lianad.rpc.listcoins(["spent"])["coins"]) == 2
+    )
 
 
 def test_rbfpsbt_insufficient_funds(lianad, bitcoind):
@@ -1259,7 +2276,12 @@ def test_rbfpsbt_cancel(lianad, bitcoind):
     destinations = {
         bitcoind.rpc.getnewaddress(): 650_000,
     }
-    first_res = lianad.rpc.createspend(destinations, first_outpoints, 1)
+    first_res = lianad.rpc.createspend(
+        destinations=destinations,
+        outpoints=first_outpoints,
+        feerate=1,
+        output_seed=CHANGE_LAST_SEED,
+    )
     first_psbt = PSBT.from_base64(first_res["psbt"])
     # The transaction has a change output.
     assert len(first_psbt.o) == len(first_psbt.tx.vout) == 2
@@ -1386,3 +2408,11 @@ def test_rbfpsbt_cancel(lianad, bitcoind):
             for c in lianad.rpc.listcoins([], [rbf_1_outpoint])["coins"]
         )
     )
+
+
+def test_encryptdatabase(lianad):
+    # The functional tests are run against a lianad built without SQLCipher.
+    with pytest.raises(RpcError, match="sqlcipher"):
+        lianad.rpc.encryptdatabase("my secret key")
+    with pytest.raises(RpcError, match="Invalid 'key' parameter"):
+        lianad.rpc.encryptdatabase("")
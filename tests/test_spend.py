@@ -1,11 +1,17 @@
 from fixtures import *
-from test_framework.serializations import PSBT, uint256_from_str
+from test_framework.serializations import (
+    PSBT,
+    PSBT_IN_PARTIAL_SIG,
+    PSBT_IN_TAP_SCRIPT_SIG,
+    uint256_from_str,
+)
 from test_framework.utils import (
     sign_and_broadcast_psbt,
     wait_for,
     COIN,
     RpcError,
     USE_TAPROOT,
+    CHANGE_LAST_SEED,
 )
 
 
@@ -27,25 +33,60 @@ def test_spend_change(lianad, bitcoind):
     # Create a transaction that will spend this coin to 1) one of our receive
     # addresses 2) an external address 3) one of our change addresses.
     outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    ext_addr = bitcoind.rpc.getnewaddress()
     destinations = {
-        bitcoind.rpc.getnewaddress(): 100_000,
+        ext_addr: 100_000,
         lianad.rpc.getnewaddress()["address"]: 100_000,
     }
-    res = lianad.rpc.createspend(destinations, outpoints, 2)
+    res = lianad.rpc.createspend(
+        destinations=destinations,
+        outpoints=outpoints,
+        feerate=2,
+        allow_self_send=True,
+    )
     assert "psbt" in res
 
+    # Only the output to the external address is reported when inspecting the PSBT.
+    inspection = lianad.rpc.inspectpsbt(res["psbt"])
+    assert inspection["total_in"] == 1_000_000
+    assert inspection["total_in"] - inspection["total_out"] == inspection["fee"]
+    assert inspection["feerate_vb"] >= 2
+    assert len(inspection["external_outputs"]) == 1
+    assert inspection["external_outputs"][0]["address"] == ext_addr
+    assert inspection["external_outputs"][0]["amount"] == 100_000
+
     # The transaction must contain a change output.
     spend_psbt = PSBT.from_base64(res["psbt"])
     assert len(spend_psbt.o) == 3
     assert len(spend_psbt.tx.vout) == 3
-    # Since the transaction contains a change output there is no warning.
+    # Since the transaction contains a change output and paying to ourselves is allowed there is
+    # no warning. The output paying to our receive address is reported as self-send.
     assert len(res["warnings"]) == 0
+    assert len(res["self_send_outputs"]) == 1
+
+    # Before signing, all the signatures of the primary path are missing.
+    lianad.rpc.updatespend(res["psbt"])
+    spend_txid = spend_psbt.tx.txid().hex()
+    reqs = lianad.rpc.getsigningrequirements(spend_txid)
+    assert reqs["timelock"] is None
+    assert reqs["sigs_count"] == 0
+    assert reqs["missing_sigs"] == reqs["threshold"]
+    assert len(reqs["remaining_fingerprints"]) >= reqs["threshold"]
 
     # Sign and broadcast this first Spend transaction.
     signed_psbt = lianad.signer.sign_psbt(spend_psbt)
     lianad.rpc.updatespend(signed_psbt.to_base64())
-    spend_txid = signed_psbt.tx.txid().hex()
+    reqs = lianad.rpc.getsigningrequirements(spend_txid)
+    assert reqs["missing_sigs"] == 0
+    estimate = lianad.rpc.getfinalfeeestimate(spend_txid)
+    assert estimate["timelock"] is None
+    assert estimate["fee"] == inspection["fee"]
+    assert estimate["feerate_vb"] >= inspection["feerate_vb"]
     lianad.rpc.broadcastspend(spend_txid)
+    # The estimated size is the one of the broadcast transaction, up to the size of the ECDSA
+    # signatures.
+    mempool_vsize = bitcoind.rpc.getmempoolentry(spend_txid)["vsize"]
+    assert 0 <= estimate["vsize"] - mempool_vsize <= len(spend_psbt.i)
     bitcoind.generate_block(1, wait_for_mempool=spend_txid)
     wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == 3)
 
@@ -72,6 +113,187 @@ def test_spend_change(lianad, bitcoind):
     bitcoind.generate_block(1, wait_for_mempool=spend_txid)
 
 
+def test_rebroadcast_unconfirmed(lianad, bitcoind):
+    """We can rebroadcast our unconfirmed Spend transactions."""
+    addr = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == 1)
+
+    # Nothing to rebroadcast yet.
+    assert lianad.rpc.rebroadcastunconfirmed()["transactions"] == []
+
+    # Broadcast a Spend transaction, it gets rebroadcast until it's confirmed.
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    destinations = {bitcoind.rpc.getnewaddress(): 100_000}
+    res = lianad.rpc.createspend(destinations, outpoints, 2)
+    signed_psbt = lianad.signer.sign_psbt(PSBT.from_base64(res["psbt"]))
+    lianad.rpc.updatespend(signed_psbt.to_base64())
+    spend_txid = signed_psbt.tx.txid().hex()
+    lianad.rpc.broadcastspend(spend_txid)
+    wait_for(
+        lambda: lianad.rpc.listcoins()["coins"][0]["spend_info"] is not None
+    )
+    assert lianad.rpc.rebroadcastunconfirmed()["transactions"] == [
+        {"txid": spend_txid, "error": None}
+    ]
+    bitcoind.generate_block(1, wait_for_mempool=spend_txid)
+    wait_for(
+        lambda: lianad.rpc.listcoins()["coins"][0]["spend_info"]["height"] is not None
+    )
+    assert lianad.rpc.rebroadcastunconfirmed()["transactions"] == []
+
+
+def test_broadcast_superseded_spend(lianad, bitcoind):
+    """A Spend can't be broadcast once a replacement for it was drafted and stored."""
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    destinations = {bitcoind.rpc.getnewaddress(): 100_000}
+    res = lianad.rpc.createspend(destinations, outpoints, 2)
+    spend_txid = sign_and_broadcast_psbt(lianad, PSBT.from_base64(res["psbt"]))
+
+    # Once a replacement is stored, the original can't be broadcast anymore.
+    rbf_psbt = PSBT.from_base64(lianad.rpc.rbfpsbt(spend_txid, False, 5)["psbt"])
+    signed_rbf_psbt = lianad.signer.sign_psbt(rbf_psbt)
+    lianad.rpc.updatespend(signed_rbf_psbt.to_base64())
+    rbf_txid = signed_rbf_psbt.tx.txid().hex()
+    with pytest.raises(RpcError, match=f"replacement was drafted .*: '{rbf_txid}'"):
+        lianad.rpc.broadcastspend(spend_txid)
+    # It is still rebroadcast though, as the replacement wasn't broadcast.
+    assert lianad.rpc.rebroadcastunconfirmed()["transactions"] == [
+        {"txid": spend_txid, "error": None}
+    ]
+
+    # After deleting the replacement, it can be.
+    lianad.rpc.delspendtx(rbf_txid)
+    lianad.rpc.broadcastspend(spend_txid)
+    assert lianad.rpc.rebroadcastunconfirmed()["transactions"] == [
+        {"txid": spend_txid, "error": None}
+    ]
+
+
+def test_updatespenddestination(lianad, bitcoind):
+    """A destination of a stored Spend can be replaced, dropping its signatures."""
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    old_addr, new_addr = bitcoind.rpc.getnewaddress(), bitcoind.rpc.getnewaddress()
+    res = lianad.rpc.createspend({old_addr: 100_000}, outpoints, 2)
+    signed_psbt = lianad.signer.sign_psbt(PSBT.from_base64(res["psbt"]))
+    lianad.rpc.updatespend(signed_psbt.to_base64())
+    spend_txid = signed_psbt.tx.txid().hex()
+
+    with pytest.raises(RpcError, match="has no output paying to"):
+        lianad.rpc.updatespenddestination(spend_txid, new_addr, old_addr, 200_000)
+    res = lianad.rpc.updatespenddestination(spend_txid, old_addr, new_addr, 200_000)
+    assert any("signed again" in w for w in res["warnings"])
+    psbt = PSBT.from_base64(res["psbt"])
+    sig_fields = (PSBT_IN_PARTIAL_SIG, PSBT_IN_TAP_SCRIPT_SIG)
+    assert all(f not in psbt_in.map for psbt_in in psbt.i for f in sig_fields)
+    assert 200_000 in [o.nValue for o in psbt.tx.vout]
+    stored = [s["psbt"] for s in lianad.rpc.listspendtxs()["spend_txs"]]
+    assert stored == [res["psbt"]]
+
+    # The updated Spend can be signed and broadcast.
+    new_txid = sign_and_broadcast_psbt(lianad, psbt)
+    bitcoind.generate_block(1, wait_for_mempool=new_txid)
+
+
+def test_mempool_status(lianad, bitcoind):
+    """We can query whether a transaction is in the mempool of the backend."""
+    addr = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.01)
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == 1)
+
+    res = lianad.rpc.getmempoolstatus(txid)
+    entry = bitcoind.rpc.getmempoolentry(txid)
+    assert res == {
+        "status": "in_mempool",
+        "fee": int(entry["fees"]["base"] * COIN),
+        "vsize": entry["vsize"],
+        "ancestor_count": 1,
+        "descendant_count": 1,
+    }
+
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    height = bitcoind.rpc.getblockcount()
+    wait_for(lambda: lianad.rpc.getinfo()["block_height"] == height)
+    assert lianad.rpc.getmempoolstatus(txid) == {
+        "status": "confirmed",
+        "height": height,
+    }
+
+    # A transaction we created but never broadcast isn't in the mempool.
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    destinations = {bitcoind.rpc.getnewaddress(): 100_000}
+    res = lianad.rpc.createspend(destinations, outpoints, 2)
+    spend_txid = PSBT.from_base64(res["psbt"]).tx.txid().hex()
+    assert lianad.rpc.getmempoolstatus(spend_txid) == {"status": "not_in_mempool"}
+
+
+def test_getpackageinfo(lianad, bitcoind):
+    """The package info accounts for the unconfirmed ancestors of a transaction."""
+    addr = lianad.rpc.getnewaddress()["address"]
+    parent_txid = bitcoind.rpc.sendtoaddress(addr, 0.01)
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == 1)
+    with pytest.raises(RpcError, match="is not in the mempool"):
+        lianad.rpc.getpackageinfo("00" * 32)
+
+    parent_entry = bitcoind.rpc.getmempoolentry(parent_txid)
+    parent_fee = int(parent_entry["fees"]["base"] * COIN)
+    assert lianad.rpc.getpackageinfo(parent_txid) == {
+        "ancestor_count": 1,
+        "fee": parent_fee,
+        "vsize": parent_entry["vsize"],
+        "feerate_vb": parent_fee // parent_entry["vsize"],
+    }
+
+    # Spend the unconfirmed coin, the package of the child includes its parent.
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    destinations = {bitcoind.rpc.getnewaddress(): 100_000}
+    res = lianad.rpc.createspend(destinations, outpoints, 10)
+    child_txid = sign_and_broadcast_psbt(lianad, PSBT.from_base64(res["psbt"]))
+    child_entry = bitcoind.rpc.getmempoolentry(child_txid)
+    package = lianad.rpc.getpackageinfo(child_txid)
+    assert package["ancestor_count"] == 2
+    assert package["fee"] == parent_fee + int(child_entry["fees"]["base"] * COIN)
+    assert package["vsize"] == parent_entry["vsize"] + child_entry["vsize"]
+    assert package["feerate_vb"] == package["fee"] // package["vsize"]
+
+
+def test_listpendingtransactions(lianad, bitcoind):
+    """Our unconfirmed transactions are listed along with the ones they depend on."""
+    assert lianad.rpc.listpendingtransactions()["transactions"] == []
+    parent_txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    wait_for(lambda: len(lianad.rpc.listpendingtransactions()["transactions"]) == 1)
+    parent_entry = bitcoind.rpc.getmempoolentry(parent_txid)
+    parent_fee = int(parent_entry["fees"]["base"] * COIN)
+    assert lianad.rpc.listpendingtransactions()["transactions"] == [
+        {
+            "txid": parent_txid,
+            "parents": [],
+            "feerate_vb": parent_fee // parent_entry["vsize"],
+        }
+    ]
+
+    # Spend the unconfirmed coin, the child depends on its parent and is listed after it.
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    destinations = {bitcoind.rpc.getnewaddress(): 100_000}
+    res = lianad.rpc.createspend(destinations, outpoints, 10)
+    child_txid = sign_and_broadcast_psbt(lianad, PSBT.from_base64(res["psbt"]))
+    wait_for(lambda: len(lianad.rpc.listpendingtransactions()["transactions"]) == 2)
+    txs = lianad.rpc.listpendingtransactions()["transactions"]
+    assert [tx["txid"] for tx in txs] == [parent_txid, child_txid]
+    assert txs[1]["parents"] == [parent_txid]
+
+    # Once confirmed, they aren't pending anymore.
+    bitcoind.generate_block(1, wait_for_mempool=[parent_txid, child_txid])
+    wait_for(lambda: len(lianad.rpc.listpendingtransactions()["transactions"]) == 0)
+
+
 def test_coin_marked_spent(lianad, bitcoind):
     """Test a spent coin is marked as such under various conditions."""
     # Receive a coin in a single transaction
@@ -150,7 +372,12 @@ def test_coin_marked_spent(lianad, bitcoind):
         lianad.rpc.getnewaddress()["address"]: int(0.03 * COIN) - 1_000,
     }
     outpoint_3 = [c["outpoint"] for c in coins_c if c["amount"] == 0.03 * COIN][0]
-    res = lianad.rpc.createspend(destinations, [outpoint_3], 1)
+    res = lianad.rpc.createspend(
+        destinations=destinations,
+        outpoints=[outpoint_3],
+        feerate=1,
+        allow_self_send=True,
+    )
     psbt = PSBT.from_base64(res["psbt"])
     sign_and_broadcast(psbt)
     change_amount = 828 if USE_TAPROOT else 818
@@ -168,7 +395,12 @@ def test_coin_marked_spent(lianad, bitcoind):
     destinations = {
         lianad.rpc.getnewaddress()["address"]: int(0.04 * COIN / 2),
     }
-    res = lianad.rpc.createspend(destinations, [outpoint_4], 18)
+    res = lianad.rpc.createspend(
+        destinations=destinations,
+        outpoints=[outpoint_4],
+        feerate=18,
+        allow_self_send=True,
+    )
     psbt = PSBT.from_base64(res["psbt"])
     sign_and_broadcast(psbt)
     assert len(psbt.o) == 2
@@ -186,7 +418,12 @@ def test_coin_marked_spent(lianad, bitcoind):
         lianad.rpc.getnewaddress()["address"]: int(0.01 * COIN),
         bitcoind.rpc.getnewaddress(): int(0.01 * COIN),
     }
-    res = lianad.rpc.createspend(destinations, [outpoint_5, outpoint_6], 2)
+    res = lianad.rpc.createspend(
+        destinations=destinations,
+        outpoints=[outpoint_5, outpoint_6],
+        feerate=2,
+        allow_self_send=True,
+    )
     psbt = PSBT.from_base64(res["psbt"])
     sign_and_broadcast(psbt)
     assert len(psbt.o) == 4
@@ -286,13 +523,256 @@ def test_send_to_self(lianad, bitcoind):
     # Therefore, the DB receive index is now 4.
 
 
+def test_createspend_self_send(lianad, bitcoind):
+    """Paying to one of our own addresses is flagged unless explicitly allowed."""
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+
+    own_addr = lianad.rpc.getnewaddress()["address"]
+    destinations = {
+        own_addr: 200_000,
+        bitcoind.rpc.getnewaddress(): 300_000,
+    }
+    res = lianad.rpc.createspend(destinations, outpoints, 2)
+    assert res["warnings"] == [
+        f"Destination {own_addr} is an address of this wallet. Set 'allow_self_send' if this "
+        "is intended."
+    ]
+    psbt = PSBT.from_base64(res["psbt"])
+    assert len(psbt.tx.vout) == 3
+    assert len(res["self_send_outputs"]) == 1
+    assert psbt.tx.vout[res["self_send_outputs"][0]].nValue == 200_000
+
+    # Once allowed, there is no warning but the output is still reported.
+    res = lianad.rpc.createspend(
+        destinations=destinations,
+        outpoints=outpoints,
+        feerate=2,
+        allow_self_send=True,
+    )
+    assert len(res["warnings"]) == 0
+    psbt = PSBT.from_base64(res["psbt"])
+    assert psbt.tx.vout[res["self_send_outputs"][0]].nValue == 200_000
+
+    # No output is reported when paying to external addresses only.
+    res = lianad.rpc.createspend({bitcoind.rpc.getnewaddress(): 300_000}, outpoints, 2)
+    assert len(res["warnings"]) == 0
+    assert "self_send_outputs" not in res
+
+
+def test_createspend_duplicate_destination(lianad, bitcoind):
+    """Paying the same address twice is refused unless explicitly allowed."""
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+
+    # The same Bech32 address, spelled in lower and upper case.
+    dest_addr = bitcoind.rpc.getnewaddress(address_type="bech32")
+    destinations = {
+        dest_addr: 200_000,
+        dest_addr.upper(): 300_000,
+    }
+    with pytest.raises(RpcError, match="is given more than once as a destination"):
+        lianad.rpc.createspend(destinations, outpoints, 2)
+
+    # Once allowed, the amounts are summed into a single output.
+    res = lianad.rpc.createspend(
+        destinations=destinations,
+        outpoints=outpoints,
+        feerate=2,
+        allow_duplicate_destinations=True,
+    )
+    psbt = PSBT.from_base64(res["psbt"])
+    assert len(psbt.tx.vout) == 2
+    assert any(o.nValue == 500_000 for o in psbt.tx.vout)
+
+    # The merged destinations may not carry different labels.
+    destinations = {
+        dest_addr: {"amount": 200_000, "label": "rent"},
+        dest_addr.upper(): {"amount": 300_000, "label": "groceries"},
+    }
+    with pytest.raises(RpcError, match="Conflicting labels for destination"):
+        lianad.rpc.createspend(
+            destinations=destinations,
+            outpoints=outpoints,
+            feerate=2,
+            allow_duplicate_destinations=True,
+        )
+    destinations[dest_addr.upper()]["label"] = "rent"
+    lianad.rpc.createspend(
+        destinations=destinations,
+        outpoints=outpoints,
+        feerate=2,
+        allow_duplicate_destinations=True,
+    )
+
+
+def test_createspend_destination_warnings(lianad, bitcoind):
+    """Destinations with an unusual script are flagged."""
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+
+    # No warning when paying to a usual address.
+    res = lianad.rpc.createspend({bitcoind.rpc.getnewaddress(): 200_000}, outpoints, 2)
+    assert "destination_warnings" not in res
+
+    # A segwit v2 address has no spending rules yet.
+    v2_addr = "bcrt1zw508d6qejxtdg4y5r3zarvaryv2wuatf"
+    res = lianad.rpc.createspend({v2_addr: 200_000}, outpoints, 2)
+    assert res["destination_warnings"] == [
+        f"Destination {v2_addr} uses segwit version 2, which has no spending rules yet. Coins "
+        "sent to it could be lost."
+    ]
+
+
+
+def test_createspend_anti_fee_sniping(lianad, bitcoind):
+    """The locktime is set to discourage fee sniping, as Bitcoin Core does."""
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+
+    tip_height = bitcoind.rpc.getblockcount()
+    wait_for(lambda: lianad.rpc.getinfo()["block_height"] == tip_height)
+    res = lianad.rpc.createspend({bitcoind.rpc.getnewaddress(): 200_000}, outpoints, 2)
+    psbt = PSBT.from_base64(res["psbt"])
+    assert psbt.tx.nLockTime == res["locktime"]
+    assert tip_height - 99 <= res["locktime"] <= tip_height
+
+    # The transaction can be broadcast right away.
+    signed_psbt = lianad.signer.sign_psbt(psbt)
+    lianad.rpc.updatespend(signed_psbt.to_base64())
+    lianad.rpc.broadcastspend(signed_psbt.tx.txid().hex())
+
+
+def test_createspend_change_path(lianad, bitcoind):
+    """The change can be sent to a given index of our change descriptor."""
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    destinations = {bitcoind.rpc.getnewaddress(): 200_000}
+
+    res = lianad.rpc.createspend(destinations, outpoints, 2, change_path="m/42")
+    change_addr = lianad.rpc.listaddresses(42, 1)["addresses"][0]["change"]
+    vout = bitcoind.rpc.decodepsbt(res["psbt"])["tx"]["vout"]
+    assert change_addr in [o["scriptPubKey"]["address"] for o in vout]
+    lianad.rpc.delspendtx(res["draft_id"])
+
+    with pytest.raises(RpcError, match="it must be a single unhardened index"):
+        lianad.rpc.createspend(destinations, outpoints, 2, change_path="m/1/42")
+    with pytest.raises(RpcError, match="Can't specify both a change address"):
+        lianad.rpc.createspend(
+            destinations,
+            outpoints,
+            2,
+            change_address=lianad.rpc.getnewaddress()["address"],
+            change_path="m/42",
+        )
+
+
+def test_createspend_extra_inputs(lianad, bitcoind):
+    """Coins of another wallet may be added as inputs, for this wallet to sign them."""
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.001)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+
+    # A P2WPKH coin of the bitcoind wallet.
+    foreign_addr = bitcoind.rpc.getnewaddress("", "bech32")
+    txid = bitcoind.rpc.sendtoaddress(foreign_addr, 0.002)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    utxo = next(
+        u for u in bitcoind.rpc.listunspent(1, 9999999, [foreign_addr]) if u["txid"] == txid
+    )
+    foreign_input = {
+        "outpoint": f"{utxo['txid']}:{utxo['vout']}",
+        "amount": 200_000,
+        "script_pubkey": utxo["scriptPubKey"],
+        "max_sat_weight": 109,
+    }
+
+    # Our coin alone isn't enough, but along with the foreign input it is.
+    destinations = {bitcoind.rpc.getnewaddress(): 250_000}
+    res = lianad.rpc.createspend(destinations, outpoints, 2)
+    assert "missing" in res
+    res = lianad.rpc.createspend(
+        destinations, outpoints, 2, extra_inputs=[foreign_input]
+    )
+    assert res["foreign_inputs"] == [1]
+    psbt = PSBT.from_base64(res["psbt"])
+    assert len(psbt.tx.vin) == 2
+
+    # The other wallet can sign its input.
+    processed = bitcoind.rpc.walletprocesspsbt(res["psbt"])
+    decoded = bitcoind.rpc.decodepsbt(processed["psbt"])
+    assert "final_scriptwitness" in decoded["inputs"][1]
+
+    with pytest.raises(RpcError, match="Invalid foreign input"):
+        lianad.rpc.createspend(
+            destinations,
+            outpoints,
+            2,
+            extra_inputs=[{**foreign_input, "outpoint": outpoints[0]}],
+        )
+
+
+def test_createspend_max_inputs(lianad, bitcoind):
+    """The number of inputs of a Spend may be capped."""
+    for _ in range(3):
+        addr = lianad.rpc.getnewaddress()["address"]
+        txid = bitcoind.rpc.sendtoaddress(addr, 0.001)
+        bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 3)
+
+    # Funding this transaction needs all three coins.
+    destinations = {bitcoind.rpc.getnewaddress(): 250_000}
+    res = lianad.rpc.createspend(destinations, [], 2, max_inputs=3)
+    assert len(PSBT.from_base64(res["psbt"]).tx.vin) == 3
+    with pytest.raises(RpcError, match="requires at least 3 inputs"):
+        lianad.rpc.createspend(destinations, [], 2, max_inputs=2)
+    with pytest.raises(RpcError, match="Invalid 'max_inputs' parameter"):
+        lianad.rpc.createspend(destinations, [], 2, max_inputs=0)
+
+
+def test_uneconomic_coins(lianad, bitcoind):
+    """Coins worth less than the fee to spend them may be left aside."""
+    for amount in (0.00005, 0.01):
+        addr = lianad.rpc.getnewaddress()["address"]
+        txid = bitcoind.rpc.sendtoaddress(addr, amount)
+        bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 2)
+
+    # The small coin is only uneconomic at a high feerate.
+    assert lianad.rpc.listuneconomiccoins(1)["coins"] == []
+    coins = lianad.rpc.listuneconomiccoins(100)["coins"]
+    assert [c["amount"] for c in coins] == [5_000]
+    with pytest.raises(RpcError, match="Invalid feerate"):
+        lianad.rpc.listuneconomiccoins(0)
+
+    # It is left aside by the coin selection if requested.
+    destinations = {bitcoind.rpc.getnewaddress(): 2_000_000}
+    res = lianad.rpc.createspend(destinations, [], 100)
+    assert res["available"] == 1_005_000
+    res = lianad.rpc.createspend(destinations, [], 100, ignore_uneconomic=True)
+    assert res["available"] == 1_000_000
+
+
 def test_coin_selection(lianad, bitcoind):
     """We can create a spend using coin selection."""
     # Send to an (external) address.
     dest_addr_1 = bitcoind.rpc.getnewaddress()
     # Coin selection is not possible if we have no coins.
     assert len(lianad.rpc.listcoins()["coins"]) == 0
-    assert "missing" in lianad.rpc.createspend({dest_addr_1: 100_000}, [], 2)
+    res = lianad.rpc.createspend({dest_addr_1: 100_000}, [], 2)
+    assert res["available"] == 0
+    assert res["needed"] == res["missing"] > 100_000
 
     # Receive a coin in an unconfirmed deposit transaction.
     recv_addr_1 = lianad.rpc.getnewaddress()["address"]
@@ -303,7 +783,18 @@ def test_coin_selection(lianad, bitcoind):
     assert len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 0
     assert len(lianad.rpc.listcoins(["unconfirmed"])["coins"]) == 1
     assert lianad.rpc.listcoins(["unconfirmed"])["coins"][0]["is_change"] is False
-    assert "missing" in lianad.rpc.createspend({dest_addr_1: 100_000}, [], 2)
+    res = lianad.rpc.createspend({dest_addr_1: 100_000}, [], 2)
+    assert "missing" in res
+    # The unconfirmed deposit isn't counted as available.
+    assert res["available"] == 0
+    # Unless we opt in to spending unconfirmed coins from external wallets.
+    res = lianad.rpc.createspend(
+        destinations={dest_addr_1: 100_000},
+        outpoints=[],
+        feerate=2,
+        allow_unconfirmed_external=True,
+    )
+    assert res["spends_unconfirmed"] is True
 
     # Confirm coin.
     bitcoind.generate_block(1, wait_for_mempool=deposit_1)
@@ -311,10 +802,13 @@ def test_coin_selection(lianad, bitcoind):
     # Coin selection now succeeds.
     spend_res_1 = lianad.rpc.createspend({dest_addr_1: 100_000}, [], 2)
     assert "psbt" in spend_res_1
+    assert spend_res_1["spends_unconfirmed"] is False
     assert len(spend_res_1["warnings"]) == 0
     # Increase spend amount and we have insufficient funds again even though we
     # now have confirmed coins.
-    assert "missing" in lianad.rpc.createspend({dest_addr_1: 200_000}, [], 2)
+    res = lianad.rpc.createspend({dest_addr_1: 200_000}, [], 2)
+    assert res["available"] == 120_000
+    assert res["needed"] == res["available"] + res["missing"]
 
     # The transaction contains a change output.
     spend_psbt_1 = PSBT.from_base64(spend_res_1["psbt"])
@@ -450,9 +944,16 @@ def test_coin_selection(lianad, bitcoind):
 
     # Now create the same spend with auto and manual selection:
     dest_addr_4 = bitcoind.rpc.getnewaddress()
-    spend_res_4 = lianad.rpc.createspend({dest_addr_4: 15_000}, [], 2)
+    spend_res_4 = lianad.rpc.createspend(
+        destinations={dest_addr_4: 15_000},
+        outpoints=[],
+        feerate=2,
+        output_seed=CHANGE_LAST_SEED,
+    )
     assert "psbt" in spend_res_4
     assert len(spend_res_4["warnings"]) == 0
+    # The seed used to place the change output is returned.
+    assert spend_res_4["output_seed"] == CHANGE_LAST_SEED
 
     # The transaction contains a change output.
     spend_psbt_4 = PSBT.from_base64(spend_res_4["psbt"])
@@ -465,7 +966,12 @@ def test_coin_selection(lianad, bitcoind):
         f"{txin.prevout.hash:064x}:{txin.prevout.n}" for txin in spend_psbt_4.tx.vin
     ]
     assert len(outpoints) > 0
-    res_manual = lianad.rpc.createspend({dest_addr_4: 15_000}, outpoints, 2)
+    res_manual = lianad.rpc.createspend(
+        destinations={dest_addr_4: 15_000},
+        outpoints=outpoints,
+        feerate=2,
+        output_seed=CHANGE_LAST_SEED,
+    )
     assert len(res_manual["warnings"]) == 0
     psbt_manual = PSBT.from_base64(res_manual["psbt"])
 
@@ -557,3 +1063,180 @@ def test_sweep(lianad, bitcoind):
         c["amount"] for c in lianad.rpc.listcoins(["unconfirmed", "confirmed"])["coins"]
     )
     assert balance == int((0.2 + 0.1 + 0.3) * COIN)
+
+
+def test_split_change(lianad, bitcoind):
+    """The change of a Spend can be split between several outputs to our change addresses."""
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+
+    res = lianad.rpc.createspend(
+        destinations={bitcoind.rpc.getnewaddress(): 100_000},
+        outpoints=outpoints,
+        feerate=2,
+        split_change=3,
+    )
+    assert len(res["warnings"]) == 0
+    psbt = PSBT.from_base64(res["psbt"])
+    assert len(psbt.tx.vout) == 4
+    change_values = [o.nValue for o in psbt.tx.vout if o.nValue != 100_000]
+    assert max(change_values) - min(change_values) < 3
+
+    # Once broadcast and confirmed, all the change outputs are detected as our coins.
+    txid = sign_and_broadcast_psbt(lianad, psbt)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 3)
+    assert all(c["is_change"] for c in lianad.rpc.listcoins(["confirmed"])["coins"])
+
+
+def test_op_return(lianad, bitcoind):
+    """Some data can be attached to a Spend through an OP_RETURN output."""
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    destinations = {bitcoind.rpc.getnewaddress(): 100_000}
+
+    # The data must fit in a standard OP_RETURN output, and there can only be one.
+    with pytest.raises(RpcError, match="exceeds the maximum of 80 bytes"):
+        lianad.rpc.createspend(
+            destinations=destinations, outpoints=outpoints, feerate=2, op_return="00" * 81
+        )
+    with pytest.raises(RpcError, match="only a single OP_RETURN output"):
+        lianad.rpc.createspend(
+            destinations=destinations,
+            outpoints=outpoints,
+            feerate=2,
+            op_return=["00", "01"],
+        )
+
+    data = b"liana notarization".hex()
+    res = lianad.rpc.createspend(
+        destinations=destinations, outpoints=outpoints, feerate=2, op_return=data
+    )
+    psbt = PSBT.from_base64(res["psbt"])
+    assert len(psbt.tx.vout) == 3
+    inspection = lianad.rpc.inspectpsbt(res["psbt"])
+    assert inspection["op_return"] == data
+    assert len(inspection["external_outputs"]) == 1
+
+    # It is relayed and mined as any other transaction.
+    txid = sign_and_broadcast_psbt(lianad, psbt)
+    tx = bitcoind.rpc.getrawtransaction(txid, True)
+    nulldata = [o for o in tx["vout"] if o["scriptPubKey"]["type"] == "nulldata"]
+    assert len(nulldata) == 1 and nulldata[0]["value"] == 0
+
+    # It's kept when bumping the fee of the transaction, but not when cancelling it.
+    rbf_psbt = lianad.rpc.rbfpsbt(txid, False, 5)["psbt"]
+    assert lianad.rpc.inspectpsbt(rbf_psbt)["op_return"] == data
+    cancel_psbt = lianad.rpc.rbfpsbt(txid, True, 5)["psbt"]
+    assert lianad.rpc.inspectpsbt(cancel_psbt)["op_return"] is None
+
+
+def test_migrate_to_descriptor(lianad, bitcoind):
+    """All our spendable coins can be swept to a new descriptor."""
+    # The same keys, with a longer timelock.
+    our_desc = lianad.rpc.getinfo()["descriptors"]["main"]
+    new_desc = our_desc.split("#")[0].replace("older(10)", "older(20)")
+    with pytest.raises(RpcError, match="already the descriptor of this wallet"):
+        lianad.rpc.migratetodescriptor(our_desc, 2)
+    with pytest.raises(RpcError, match="No coin can currently be migrated"):
+        lianad.rpc.migratetodescriptor(new_desc, 2)
+
+    # A confirmed coin is swept, an unconfirmed deposit is left behind.
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.02)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed", "unconfirmed"])["coins"]) == 2)
+    res = lianad.rpc.migratetodescriptor(new_desc, 2)
+    assert len(res["warnings"]) == 1
+    assert "left behind" in res["warnings"][0]
+    psbt = PSBT.from_base64(res["psbt"])
+    assert len(psbt.tx.vin) == 1 and len(psbt.tx.vout) == 1
+
+    # Once broadcast, the coin is spent and the new wallet received the funds.
+    txid = sign_and_broadcast_psbt(lianad, psbt)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["spent"])["coins"]) == 1)
+    assert len(lianad.rpc.listcoins(["confirmed", "unconfirmed"])["coins"]) == 1
+
+
+def test_rotate_funds(lianad, bitcoind):
+    """All our spendable coins can be swept to fresh receive addresses of the wallet."""
+    with pytest.raises(RpcError, match="Must be between 1 and 10"):
+        lianad.rpc.rotatefunds(2, 0)
+    with pytest.raises(RpcError, match="No coin can currently be rotated"):
+        lianad.rpc.rotatefunds(2, 3)
+
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    receive_index = lianad.rpc.exportwallet()["receive_index"]
+    res = lianad.rpc.rotatefunds(2, 3)
+    assert res["warnings"] == []
+    psbt = PSBT.from_base64(res["psbt"])
+    assert len(psbt.tx.vin) == 1 and len(psbt.tx.vout) == 3
+    # The receive addresses used are not handed out anymore.
+    assert lianad.rpc.exportwallet()["receive_index"] == receive_index + 3
+
+    # Once broadcast, the funds are received on three new coins.
+    txid = sign_and_broadcast_psbt(lianad, psbt)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 3)
+    assert all(not c["is_change"] for c in lianad.rpc.listcoins(["confirmed"])["coins"])
+
+
+def test_spendable_min_confirmations(lianad, bitcoind):
+    """Coins without the configured number of confirmations aren't selected automatically."""
+    lianad.stop()
+    with open(lianad.conf_file, "r") as f:
+        conf = f.read()
+    with open(lianad.conf_file, "w") as f:
+        f.write("spendable_min_confirmations = 3\n" + conf)
+    lianad.start()
+
+    # A coin with a single confirmation is listed but not spendable.
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    assert lianad.rpc.listcoins()["coins"][0]["spendable_paths"] == []
+    dest_addr = bitcoind.rpc.getnewaddress()
+    res = lianad.rpc.createspend({dest_addr: 100_000}, [], 2)
+    assert res["available"] == 0
+    # It may still be selected as an unconfirmed coin received from an external wallet.
+    res = lianad.rpc.createspend(
+        destinations={dest_addr: 100_000},
+        outpoints=[],
+        feerate=2,
+        allow_unconfirmed_external=True,
+    )
+    assert "psbt" in res
+
+    # Once it has enough confirmations it is selected by default.
+    bitcoind.generate_block(2)
+    wait_for(
+        lambda: lianad.rpc.getinfo()["block_height"] == bitcoind.rpc.getblockcount()
+    )
+    assert lianad.rpc.listcoins()["coins"][0]["spendable_paths"] == [0]
+    res = lianad.rpc.createspend({dest_addr: 100_000}, [], 2)
+    assert "psbt" in res
+
+
+def test_fractional_feerate(lianad, bitcoind):
+    """A spend can target a fractional feerate without paying the next integer one."""
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins(["confirmed"])["coins"]) == 1)
+    outpoint = lianad.rpc.listcoins()["coins"][0]["outpoint"]
+    dest_addr = bitcoind.rpc.getnewaddress()
+
+    def fee(feerate):
+        res = lianad.rpc.createspend({dest_addr: 100_000}, [outpoint], feerate)
+        psbt = PSBT.from_base64(res["psbt"])
+        return 1_000_000 - sum(o.nValue for o in psbt.tx.vout)
+
+    assert fee(1) < fee(1.5) < fee(2)
+    with pytest.raises(RpcError, match="Invalid feerate: 0.5 sats/vb."):
+        fee(0.5)